@@ -1,3 +1,4 @@
+#[cfg(target_os = "windows")]
 extern crate winres;
 
 use std::{env, error::Error, process::Command};
@@ -6,7 +7,7 @@ use time::OffsetDateTime;
 fn main() -> Result<(), Box<dyn Error>> {
     // build date
     let time = OffsetDateTime::now_utc();
-    println!("cargo:rustc-env=BUILD_DATE={}/{}/{}", time.year(), time.month(), time.day());
+    println!("cargo:rustc-env=BUILD_DATE={}/{}/{}", time.year(), u8::from(time.month()), time.day());
 
     // git hash
     let output = Command::new("git").args(&["rev-parse", "--short", "HEAD"]).output()?;
@@ -306,8 +306,8 @@ impl DeobfState {
     }
 
     pub fn simplify(&mut self, expr: &ast::Expr, assets: &GameAssets) -> Option<f64> {
-        match expr {
-            ast::Expr::LiteralIdentifier(ident) => {
+        match &expr.kind {
+            ast::ExprKind::LiteralIdentifier(ident) => {
                 if let Some(index) = self.get_asset_index(ident, assets) {
                     Some(index as f64)
                 } else if ident == b"pi" {
@@ -317,8 +317,8 @@ impl DeobfState {
                     self.constants.get(ident).copied()
                 }
             },
-            ast::Expr::LiteralReal(real) => Some(*real),
-            ast::Expr::Unary(unary) => {
+            ast::ExprKind::LiteralReal(real) => Some(*real),
+            ast::ExprKind::Unary(unary) => {
                 let child = self.simplify(&unary.child, assets)?;
                 match unary.op {
                     Operator::Add => Some(child),
@@ -326,7 +326,7 @@ impl DeobfState {
                     _ => None, // technically there's others. none used by obf
                 }
             },
-            ast::Expr::Binary(binary) => {
+            ast::ExprKind::Binary(binary) => {
                 let left = self.simplify(&binary.left, assets)?;
                 let right = self.simplify(&binary.right, assets)?;
                 match binary.op {
@@ -362,7 +362,7 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
     pub fn process_expr(&mut self, ex: &'_ ast::Expr) {
         macro_rules! push_str {
             ($lit: literal) => {
-                self.output.extend_from_slice(($lit).as_bytes());
+                self.output.extend_from_slice(($lit).as_bytes())
             };
         }
 
@@ -372,9 +372,9 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
             writer.output.push(b')');
         }
 
-        match ex {
-            ast::Expr::LiteralIdentifier(expr) => {
-                if let Some(simple) = self.deobf.simplify(&ast::Expr::LiteralIdentifier(expr), self.assets) {
+        match &ex.kind {
+            ast::ExprKind::LiteralIdentifier(expr) => {
+                if let Some(simple) = self.deobf.simplify(&ast::ExprKind::LiteralIdentifier(expr).into(), self.assets) {
                     let _ = write!(self.output, "{}", simple);
                 } else if self.deobf.vars.get(expr).is_some() || expr == b"pi" {
                     self.output.extend_from_slice(expr);
@@ -382,25 +382,25 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                     self.write_field(expr);
                 }
             },
-            ast::Expr::LiteralReal(real) => {
+            ast::ExprKind::LiteralReal(real) => {
                 let _ = write!(self.output, "{}", real);
             },
-            ast::Expr::LiteralString(string) => {
+            ast::ExprKind::LiteralString(string) => {
                 let quote = if string.iter().any(|&x| x == b'"') { b'\'' } else { b'"' };
                 self.output.push(quote);
                 self.output.extend_from_slice(string);
                 self.output.push(quote);
             },
-            ast::Expr::Unary(expr) => {
+            ast::ExprKind::Unary(expr) => {
                 let op = op_to_str(expr.op);
                 self.output.extend_from_slice(op);
                 let prev_state = self.is_gml_expr;
                 self.is_gml_expr = true;
                 if let Some(simple) = self.deobf.simplify(&expr.child, self.assets) {
-                    self.process_expr(&ast::Expr::LiteralReal(simple));
+                    self.process_expr(&ast::ExprKind::LiteralReal(simple).into());
                 } else {
-                    match &expr.child {
-                        ast::Expr::Binary(b) if !matches!(b.op, Operator::Deref | Operator::Index) => {
+                    match &expr.child.kind {
+                        ast::ExprKind::Binary(b) if !matches!(b.op, Operator::Deref | Operator::Index) => {
                             write_wrapped(self, &expr.child)
                         },
                         _ => self.process_expr(&expr.child),
@@ -408,22 +408,22 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                 }
                 self.is_gml_expr = prev_state;
             },
-            ast::Expr::Binary(expr) => {
+            ast::ExprKind::Binary(expr) => {
                 let prev_state = self.is_gml_expr;
                 self.is_gml_expr = true;
                 if let Some(simple) = self.deobf.simplify(ex, self.assets) {
-                    self.process_expr(&ast::Expr::LiteralReal(simple));
+                    self.process_expr(&ast::ExprKind::LiteralReal(simple).into());
                 } else if expr.op == Operator::Index {
                     // array indexing
                     self.process_expr(&expr.left);
                     self.output.push(b'[');
-                    if let ast::Expr::Group(group) = &expr.right {
+                    if let ast::ExprKind::Group(group) = &expr.right.kind {
                         for (i, expr) in group.iter().enumerate() {
                             if i != 0 {
                                 push_str!(", ");
                             }
                             if let Some(simple) = self.deobf.simplify(expr, &self.assets) {
-                                self.process_expr(&ast::Expr::LiteralReal(simple));
+                                self.process_expr(&ast::ExprKind::LiteralReal(simple).into());
                             } else {
                                 self.process_expr(expr);
                             }
@@ -461,11 +461,11 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                         }
                     } else {
                         // Write the LHS expression normally, wrapping it only if necessary
-                        match &expr.left {
-                            ast::Expr::LiteralIdentifier(_) => {
+                        match &expr.left.kind {
+                            ast::ExprKind::LiteralIdentifier(_) => {
                                 self.process_expr(&expr.left);
                             },
-                            ast::Expr::Binary(b) if matches!(b.op, Operator::Index | Operator::Deref) => {
+                            ast::ExprKind::Binary(b) if matches!(b.op, Operator::Index | Operator::Deref) => {
                                 self.process_expr(&expr.left);
                             },
                             _ => {
@@ -481,17 +481,17 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                     // Helper fn: write one side of the expr, deciding whether to paren-wrap it or not
                     fn write_side(writer: &mut ExprWriter, expr: &ast::Expr, can_wrap: bool) {
                         if let Some(simple) = writer.deobf.simplify(expr, writer.assets) {
-                            writer.process_expr(&ast::Expr::LiteralReal(simple));
+                            writer.process_expr(&ast::ExprKind::LiteralReal(simple).into());
                         } else if can_wrap {
-                            match expr {
-                                ast::Expr::LiteralIdentifier(_)
-                                | ast::Expr::LiteralReal(_)
-                                | ast::Expr::LiteralString(_)
-                                | ast::Expr::Unary(_)
-                                | ast::Expr::Function(_) => {
+                            match &expr.kind {
+                                ast::ExprKind::LiteralIdentifier(_)
+                                | ast::ExprKind::LiteralReal(_)
+                                | ast::ExprKind::LiteralString(_)
+                                | ast::ExprKind::Unary(_)
+                                | ast::ExprKind::Function(_) => {
                                     writer.process_expr(expr);
                                 },
-                                ast::Expr::Binary(b) if matches!(b.op, Operator::Index | Operator::Deref) => {
+                                ast::ExprKind::Binary(b) if matches!(b.op, Operator::Index | Operator::Deref) => {
                                     writer.process_expr(expr);
                                 },
                                 _ => {
@@ -525,7 +525,7 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                     push_str!(";\r\n");
                 }
             },
-            ast::Expr::DoUntil(expr) => {
+            ast::ExprKind::DoUntil(expr) => {
                 push_str!("do ");
                 self.write_expr_grouped(&expr.body, false);
                 push_str!("until (");
@@ -534,7 +534,7 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                 self.is_gml_expr = false;
                 push_str!(");\r\n");
             },
-            ast::Expr::For(expr) => {
+            ast::ExprKind::For(expr) => {
                 fn remove_truncate(x: &mut Vec<u8>, pat: &[u8]) {
                     if x.ends_with(pat) {
                         x.truncate(x.len() - pat.len());
@@ -555,7 +555,7 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                 self.is_gml_expr = false;
                 self.write_expr_grouped(&expr.body, true);
             },
-            ast::Expr::Function(expr) => {
+            ast::ExprKind::Function(expr) => {
                 if let Some(idx) = self
                     .assets
                     .scripts
@@ -585,14 +585,14 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                     push_str!(");\r\n");
                 }
             },
-            ast::Expr::Group(exprs) => {
+            ast::ExprKind::Group(exprs) => {
                 let skip_newline = self.group_skip_newline;
                 self.group_skip_newline = false;
                 push_str!("{\r\n");
                 self.indent += 1;
                 let mut is_case = false;
                 for expr in exprs {
-                    if matches!(expr, ast::Expr::Case(_) | ast::Expr::Default) {
+                    if matches!(expr.kind, ast::ExprKind::Case(_) | ast::ExprKind::Default) {
                         if is_case {
                             self.indent -= 1;
                         } else {
@@ -617,7 +617,7 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                     push_str!("}\r\n");
                 }
             },
-            ast::Expr::If(expr) => {
+            ast::ExprKind::If(expr) => {
                 push_str!("if (");
                 self.is_gml_expr = true;
                 self.process_expr(&expr.cond);
@@ -628,7 +628,7 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
 
                 if let Some(expr_else) = &expr.else_body {
                     push_str!(" else ");
-                    if matches!(expr_else, ast::Expr::If(_)) {
+                    if matches!(expr_else.kind, ast::ExprKind::If(_)) {
                         self.process_expr(expr_else);
                     } else {
                         self.write_expr_grouped(expr_else, true);
@@ -637,7 +637,7 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                     push_str!("\r\n");
                 }
             },
-            ast::Expr::Repeat(expr) => {
+            ast::ExprKind::Repeat(expr) => {
                 push_str!("repeat (");
                 self.is_gml_expr = true;
                 self.process_expr(&expr.count);
@@ -645,7 +645,7 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                 push_str!(") ");
                 self.write_expr_grouped(&expr.body, true);
             },
-            ast::Expr::Switch(expr) => {
+            ast::ExprKind::Switch(expr) => {
                 push_str!("switch (");
                 self.is_gml_expr = true;
                 self.process_expr(&expr.input);
@@ -653,7 +653,7 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                 push_str!(") ");
                 self.write_expr_grouped(&expr.body, true);
             },
-            ast::Expr::Var(expr) => {
+            ast::ExprKind::Var(expr) => {
                 if !expr.vars.is_empty() {
                     push_str!("var ");
                     for (i, name) in expr.vars.iter().enumerate() {
@@ -665,7 +665,7 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                     push_str!(";\r\n");
                 }
             },
-            ast::Expr::GlobalVar(expr) => {
+            ast::ExprKind::GlobalVar(expr) => {
                 if !expr.vars.is_empty() {
                     push_str!("globalvar ");
                     for (i, name) in expr.vars.iter().enumerate() {
@@ -677,7 +677,7 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                     push_str!(";\r\n");
                 }
             },
-            ast::Expr::With(expr) => {
+            ast::ExprKind::With(expr) => {
                 push_str!("with (");
                 self.is_gml_expr = true;
                 if let Some(simple) = self.deobf.simplify(&expr.target, self.assets) {
@@ -699,7 +699,7 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                 push_str!(") ");
                 self.write_expr_grouped(&expr.body, true);
             },
-            ast::Expr::While(expr) => {
+            ast::ExprKind::While(expr) => {
                 push_str!("while (");
                 self.is_gml_expr = true;
                 self.process_expr(&expr.cond);
@@ -707,18 +707,18 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
                 push_str!(") ");
                 self.write_expr_grouped(&expr.body, true);
             },
-            ast::Expr::Case(expr) => {
+            ast::ExprKind::Case(expr) => {
                 push_str!("case ");
                 self.is_gml_expr = true;
                 self.process_expr(expr);
                 self.is_gml_expr = false;
                 push_str!(":\r\n");
             },
-            ast::Expr::Default => push_str!("default:\r\n"),
-            ast::Expr::Continue => push_str!("continue;\r\n"),
-            ast::Expr::Break => push_str!("break;\r\n"),
-            ast::Expr::Exit => push_str!("exit;\r\n"),
-            ast::Expr::Return(expr) => {
+            ast::ExprKind::Default => push_str!("default:\r\n"),
+            ast::ExprKind::Continue => push_str!("continue;\r\n"),
+            ast::ExprKind::Break => push_str!("break;\r\n"),
+            ast::ExprKind::Exit => push_str!("exit;\r\n"),
+            ast::ExprKind::Return(expr) => {
                 push_str!("return ");
                 self.is_gml_expr = true;
                 self.process_expr(expr);
@@ -729,7 +729,7 @@ impl<'a, 'b, 'c> ExprWriter<'a, 'b, 'c> {
     }
 
     pub fn write_expr_grouped(&mut self, expr: &ast::Expr, newline: bool) {
-        if matches!(expr, ast::Expr::Group(_)) {
+        if matches!(expr.kind, ast::ExprKind::Group(_)) {
             if !newline {
                 self.group_skip_newline = true;
             }
@@ -1,3 +1,4 @@
+#[cfg(target_os = "windows")]
 extern crate winres;
 
 use gl_generator::{Api, Fallbacks, GlobalGenerator, Profile, Registry};
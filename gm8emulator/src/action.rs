@@ -2,9 +2,11 @@ use crate::{
     game::Game,
     gml::{
         self,
+        bytecode::Program,
         compiler::Compiler,
         mappings,
-        runtime::{Instruction, Node},
+        runtime::Node,
+        trace::Frame,
         Context, Value,
     },
 };
@@ -52,14 +54,19 @@ pub struct Action {
 /// Abstraction for a tree of Actions
 /// Note that Vec is necessary here due to functions such as object_event_add and object_event_clear
 #[derive(Debug, Default, Serialize, Deserialize)]
-pub struct Tree(Vec<Action>);
+pub struct Tree {
+    actions: Vec<Action>,
+
+    /// The actions as they came from the game, until they first run, which is when GM8 compiles them
+    uncompiled: Option<Box<[CodeAction]>>,
+}
 
 /// Body of an action, depending on the action kind.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Body {
     Normal {
         /// The arguments to be passed to the function or code body
-        args: Box<[Node]>,
+        args: Box<[Program]>,
 
         /// The body of this action to be executed
         body: GmlBody,
@@ -69,7 +76,7 @@ pub enum Body {
     },
     Repeat {
         /// The expression giving the number of times to repeat.
-        count: Node,
+        count: Program,
 
         /// The tree of actions to repeat.
         body: Box<[Action]>,
@@ -80,7 +87,7 @@ pub enum Body {
 #[derive(Serialize, Deserialize)]
 pub enum GmlBody {
     Function(usize),
-    Code(Rc<[Instruction]>),
+    Code(Rc<Program>),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -104,7 +111,26 @@ impl Tree {
         let mut iter = list.iter().enumerate().peekable();
         let mut output = Vec::new();
         Self::from_iter(&mut iter, compiler, false, &mut output)?;
-        Ok(Self(output))
+        Ok(Self { actions: output, uncompiled: None })
+    }
+
+    /// Keep a list of gm8exe CodeActions to be turned into an Action tree once it's needed.
+    pub fn from_list_lazy(list: Vec<CodeAction>) -> Self {
+        Self { actions: Vec::new(), uncompiled: Some(list.into_boxed_slice()) }
+    }
+
+    /// Wrap some actions which have already been compiled, such as ones from an old savestate.
+    pub fn from_actions(actions: Vec<Action>) -> Self {
+        Self { actions, uncompiled: None }
+    }
+
+    /// Compile the actions if that hasn't happened yet. If it fails they stay uncompiled, so it fails again next time.
+    pub fn compile(&mut self, compiler: &mut Compiler) -> Result<(), String> {
+        if let Some(list) = &self.uncompiled {
+            self.actions = Self::from_list(list, compiler)?.actions;
+            self.uncompiled = None;
+        }
+        Ok(())
     }
 
     fn from_iter<'a, T>(
@@ -187,7 +213,9 @@ impl Tree {
                                         &action.param_types,
                                         action.param_count,
                                     )?,
-                                    body: GmlBody::Code(compiler.compile(&action.fn_code.0).map_err(|e| e.message)?),
+                                    body: GmlBody::Code(
+                                        compiler.compile(&action.fn_code.0).map_err(|e| e.to_string())?,
+                                    ),
                                     if_else,
                                 },
                             });
@@ -218,7 +246,9 @@ impl Tree {
                         relative: action.is_relative,
                         invert_condition: action.invert_condition,
                         body: Body::Repeat {
-                            count: compiler.compile_expression(&action.param_strings[0].0).map_err(|e| e.message)?,
+                            count: compiler
+                                .compile_expression(&action.param_strings[0].0)
+                                .map_err(|e| e.to_string())?,
                             body: body.into_boxed_slice(),
                         },
                     });
@@ -235,7 +265,7 @@ impl Tree {
                         invert_condition: action.invert_condition,
                         body: Body::Normal {
                             args: Box::new([]),
-                            body: GmlBody::Code(compiler.compile(&code).map_err(|e| e.message)?),
+                            body: GmlBody::Code(compiler.compile(&code).map_err(|e| e.to_string())?),
                             if_else: None,
                         },
                     });
@@ -249,7 +279,9 @@ impl Tree {
                         invert_condition: action.invert_condition,
                         body: Body::Normal {
                             args: Box::new([]),
-                            body: GmlBody::Code(compiler.compile(&action.param_strings[0].0).map_err(|e| e.message)?),
+                            body: GmlBody::Code(
+                                compiler.compile(&action.param_strings[0].0).map_err(|e| e.to_string())?,
+                            ),
                             if_else: None,
                         },
                     });
@@ -272,29 +304,30 @@ impl Tree {
         params: &[gm8exe::asset::PascalString],
         types: &[u32],
         count: usize,
-    ) -> Result<Box<[Node]>, String> {
+    ) -> Result<Box<[Program]>, String> {
         Ok(params
             .iter()
             .zip(types.iter())
             .take(count)
             .map(|(param, t)| match *t {
-                1 | 2 => Ok(Node::Literal { value: Value::Str(param.0.as_ref().into()) }),
+                1 | 2 => Ok(Program::from_node(&Node::Literal { value: Value::Str(param.0.as_ref().into()) })),
                 _ => compiler.compile_expression(&param.0),
             })
             .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| e.message)?
+            .map_err(|e| e.to_string())?
             .into_boxed_slice())
     }
 
-    pub fn new_from_code(code: Rc<[Instruction]>) -> Rc<RefCell<Self>> {
-        let mut tree = Self(Vec::new());
+    pub fn new_from_code(code: Rc<Program>) -> Rc<RefCell<Self>> {
+        let mut tree = Self::default();
         tree.push_code(code);
         Rc::new(RefCell::new(tree))
     }
 
-    pub fn push_code(&mut self, code: Rc<[Instruction]>) {
-        self.0.push(Action {
-            index: self.0.len(),
+    /// Add a code action to the end. The tree has to have been compiled already.
+    pub fn push_code(&mut self, code: Rc<Program>) {
+        self.actions.push(Action {
+            index: self.actions.len(),
             target: None,
             relative: false,
             invert_condition: false,
@@ -314,7 +347,16 @@ impl Game {
         event_number: usize,
         as_object: i32,
     ) -> gml::Result<()> {
-        self.exec_slice(&tree.borrow().0, this, other, event_type, event_number, as_object)?;
+        // only borrowed mutably when there's something to compile, since the tree can already be running further up
+        // the stack, such as when an event performs itself
+        if tree.borrow().uncompiled.is_some() {
+            let compiled = tree.borrow_mut().compile(&mut self.compiler);
+            if let Err(e) = compiled {
+                let error = self.locate_error(gml::Error::CompileError(e), None);
+                return self.catch_error(error)
+            }
+        }
+        self.exec_slice(&tree.borrow().actions, this, other, event_type, event_number, as_object)?;
         Ok(())
     }
 
@@ -352,58 +394,61 @@ impl Game {
                     }
                     */
 
-                    let mut returned_value = Default::default();
-                    match action.target {
-                        None | Some(gml::SELF) | Some(gml::OTHER) => {
-                            if action.target == Some(gml::OTHER) {
-                                context.this = other;
-                                context.other = this;
-                            }
+                    let returned_value = self.with_frame_caught(Frame::Action(action.index), |game| {
+                        let mut returned_value = Default::default();
+                        match action.target {
+                            None | Some(gml::SELF) | Some(gml::OTHER) => {
+                                if action.target == Some(gml::OTHER) {
+                                    context.this = other;
+                                    context.other = this;
+                                }
 
-                            let mut arg_values: [Value; 16] = Default::default();
-                            for (dest, src) in arg_values.iter_mut().zip(args.iter()) {
-                                *dest = self.eval(src, &mut context)?;
-                            }
+                                let mut arg_values: [Value; 16] = Default::default();
+                                for (dest, src) in arg_values.iter_mut().zip(args.iter()) {
+                                    *dest = game.eval(src, &mut context)?;
+                                }
 
-                            returned_value = match gml_body {
-                                GmlBody::Function(f) => self.invoke(*f, &mut context, &arg_values[..args.len()])?,
-                                GmlBody::Code(code) => {
-                                    context.arguments = arg_values;
-                                    context.argument_count = args.len();
-                                    self.execute(code, &mut context)?;
-                                    context.return_value
-                                },
-                            };
-                        },
-                        Some(i) if i < 0 => (),
-                        Some(i) => {
-                            if let Some(Some(object)) = self.assets.objects.get(i as usize) {
-                                context.other = this;
-                                let ids = object.children.clone();
-                                let mut iter = self.room.instance_list.iter_by_identity(ids);
-                                while let Some(instance) = iter.next(&self.room.instance_list) {
-                                    context.this = instance;
-
-                                    let mut arg_values: [Value; 16] = Default::default();
-                                    for (dest, src) in arg_values.iter_mut().zip(args.iter()) {
-                                        *dest = self.eval(src, &mut context)?;
+                                returned_value = match gml_body {
+                                    GmlBody::Function(f) => game.invoke(*f, &mut context, &arg_values[..args.len()])?,
+                                    GmlBody::Code(code) => {
+                                        context.arguments = arg_values;
+                                        context.argument_count = args.len();
+                                        game.execute(code, &mut context)?;
+                                        context.return_value
+                                    },
+                                };
+                            },
+                            Some(i) if i < 0 => (),
+                            Some(i) => {
+                                if let Some(Some(object)) = game.assets.objects.get(i as usize) {
+                                    context.other = this;
+                                    let ids = object.children.clone();
+                                    let mut iter = game.room.instance_list.iter_by_identity(ids);
+                                    while let Some(instance) = iter.next(&game.room.instance_list) {
+                                        context.this = instance;
+
+                                        let mut arg_values: [Value; 16] = Default::default();
+                                        for (dest, src) in arg_values.iter_mut().zip(args.iter()) {
+                                            *dest = game.eval(src, &mut context)?;
+                                        }
+
+                                        returned_value = match gml_body {
+                                            GmlBody::Function(f) => {
+                                                game.invoke(*f, &mut context, &arg_values[..args.len()])?
+                                            },
+                                            GmlBody::Code(code) => {
+                                                context.arguments = arg_values;
+                                                context.argument_count = args.len();
+                                                game.execute(code, &mut context)?;
+                                                context.return_value.clone()
+                                            },
+                                        };
                                     }
-
-                                    returned_value = match gml_body {
-                                        GmlBody::Function(f) => {
-                                            self.invoke(*f, &mut context, &arg_values[..args.len()])?
-                                        },
-                                        GmlBody::Code(code) => {
-                                            context.arguments = arg_values;
-                                            context.argument_count = args.len();
-                                            self.execute(code, &mut context)?;
-                                            context.return_value.clone()
-                                        },
-                                    };
                                 }
-                            }
-                        },
-                    }
+                            },
+                        }
+                        Ok(returned_value)
+                    })?;
 
                     if let Some((if_body, else_body)) = if_else {
                         let target =
@@ -425,7 +470,8 @@ impl Game {
                         event_object: as_object,
                         ..Default::default()
                     };
-                    let mut count = i32::from(self.eval(count, &mut context)?);
+                    let frame = Frame::Action(action.index);
+                    let mut count = i32::from(self.with_frame_caught(frame, |game| game.eval(count, &mut context))?);
                     while count > 0 {
                         match self.exec_slice(body, this, other, event_type, event_number, as_object)? {
                             ReturnType::Continue => (),
@@ -441,3 +487,135 @@ impl Game {
         Ok(ReturnType::Continue)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game::{draw::Halign, replay, GetAsset, PlayType},
+        instance::Instance,
+        testing::{action, code, run, test_game},
+    };
+
+    #[test]
+    fn colours_and_fonts() {
+        let mut game = test_game();
+        run(&mut game, 0.0, 0.0, &[action("action_fill_color", &["c_red"])]).unwrap();
+        assert_eq!(u32::from(game.draw_colour), 0x0000FF);
+        run(&mut game, 0.0, 0.0, &[action("action_line_color", &["c_blue"])]).unwrap();
+        assert_eq!(u32::from(game.draw_colour), 0xFF0000);
+
+        game.draw_font_id = 0;
+        run(&mut game, 0.0, 0.0, &[action("action_draw_font", &["3"])]).unwrap();
+        assert_eq!(game.draw_font_id, -1);
+        game.draw_font_id = 0;
+        let old_font = action("action_draw_font_old", &["\"Arial\"", "12", "c_lime", "1", "0", "1"]);
+        run(&mut game, 0.0, 0.0, &[old_font]).unwrap();
+        assert_eq!((game.draw_font_id, u32::from(game.draw_colour)), (-1, 0x00FF00));
+        assert!(matches!(game.draw_halign, Halign::Middle));
+    }
+
+    #[test]
+    fn sound_conditions() {
+        let mut game = test_game();
+        // nothing's ever playing, so only the NOT version of the question runs what's under it
+        let mut if_sound = action("action_if_sound", &["0"]);
+        let actions = [action("action_sound", &["0", "1"]), if_sound, action("action_fill_color", &["c_red"])];
+        run(&mut game, 0.0, 0.0, &actions).unwrap();
+        assert_eq!(u32::from(game.draw_colour), 0);
+        if_sound = action("action_if_sound", &["0"]);
+        if_sound.invert_condition = true;
+        run(&mut game, 0.0, 0.0, &[if_sound, action("action_fill_color", &["c_red"])]).unwrap();
+        assert_eq!(u32::from(game.draw_colour), 0x0000FF);
+        run(&mut game, 0.0, 0.0, &[action("action_replace_sound", &["0", "\"music.wav\""])]).unwrap();
+    }
+
+    #[test]
+    fn replacing_assets() {
+        let mut game = test_game();
+        game.fatal_errors = true;
+        let mut replace = action("action_replace_sprite", &["0", "x.png", "1"]);
+        replace.param_types[1] = 1; // file name, so it's not an expression
+        match run(&mut game, 0.0, 0.0, &[replace]) {
+            Err(gml::Error::Located(located)) => assert!(matches!(
+                located.error,
+                gml::Error::NonexistentAsset(crate::asset::Type::Sprite, 0)
+            )),
+            _ => panic!("replacing a sprite that doesn't exist should fail"),
+        }
+        let mut replace = action("action_replace_background", &["0", "x.png"]);
+        replace.param_types[1] = 1;
+        assert!(run(&mut game, 0.0, 0.0, &[replace]).is_err());
+    }
+
+    #[test]
+    fn old_path_starts_at_instance() {
+        let mut game = test_game();
+        // it's relative to where the instance is whether the box is ticked or not
+        for &relative in &[false, true] {
+            let mut path = action("action_path_old", &["0", "4", "1"]);
+            path.is_relative = relative;
+            let handle = run(&mut game, 32.0, 48.0, &[path]).unwrap();
+            let instance = game.room.instance_list.get(handle);
+            assert_eq!(instance.path_index.get(), 0);
+            assert_eq!(instance.path_speed.get(), 4.into());
+            assert_eq!(instance.path_endaction.get(), 1);
+            assert_eq!((instance.path_xstart.get(), instance.path_ystart.get()), (32.into(), 48.into()));
+        }
+    }
+
+    #[test]
+    fn events_compile_when_they_run() {
+        let mut game = test_game();
+        let tree = Tree::from_list_lazy(vec![action("action_fill_color", &["c_red +"])]);
+        game.assets.objects.get_asset_mut(0).unwrap().events[gml::ev::STEP].insert(0, Rc::new(RefCell::new(tree)));
+        let object = game.assets.objects.get_asset(0).unwrap();
+        let instance = game.room.instance_list.insert(Instance::new(100001, 0.into(), 0.into(), 0, object));
+
+        // the broken code is only noticed now, and it's an error like any other
+        game.show_errors = false;
+        game.run_instance_event(gml::ev::STEP, 0, instance, instance, None).unwrap();
+        assert!(game.error_occurred);
+        game.fatal_errors = true;
+        match game.run_instance_event(gml::ev::STEP, 0, instance, instance, None) {
+            Err(gml::Error::Located(located)) => {
+                assert_eq!(located.location, "ERROR in\nof Step Event\nfor object object0:\n");
+                assert!(matches!(located.error, gml::Error::CompileError(_)));
+            },
+            _ => panic!("expected a compile error"),
+        }
+    }
+
+    #[test]
+    fn events_can_perform_themselves() {
+        let mut game = test_game();
+        let source = "x += 1; if x < 3 event_perform(ev_other, ev_user0)";
+        let tree = Tree::from_list_lazy(vec![code(source)]);
+        game.assets.objects.get_asset_mut(0).unwrap().events[gml::ev::OTHER].insert(10, Rc::new(RefCell::new(tree)));
+        let object = game.assets.objects.get_asset(0).unwrap();
+        let instance = game.room.instance_list.insert(Instance::new(100001, 0.into(), 0.into(), 0, object));
+
+        // the outer run still has the tree borrowed while the inner ones compile and run it
+        game.run_instance_event(gml::ev::OTHER, 10, instance, instance, None).unwrap();
+        assert_eq!(game.room.instance_list.get(instance).x.get(), 3.into());
+    }
+
+    #[test]
+    fn webpage_needs_permission() {
+        let mut game = test_game();
+        let mut webpage = action("action_webpage", &["https://example.com"]);
+        webpage.param_types[0] = 1;
+        let actions = [webpage];
+
+        // nothing's allowed by default, so it doesn't open, and that's what gets recorded
+        game.play_type = PlayType::Record;
+        run(&mut game, 0.0, 0.0, &actions).unwrap();
+        assert!(matches!(game.stored_events.pop_front(), Some(replay::Event::Execute(Ok(None)))));
+
+        // replays get whatever happened when it was recorded, without trying again
+        game.play_type = PlayType::Replay;
+        game.stored_events.push_back(replay::Event::Execute(Err("Cannot execute".into())));
+        assert!(run(&mut game, 0.0, 0.0, &actions).is_err());
+        assert!(run(&mut game, 0.0, 0.0, &actions).is_err());
+    }
+}
@@ -1,11 +1,11 @@
 use crate::{
     game::{string::RCStr, Background, View},
-    gml::runtime::Instruction,
+    gml::{self, bytecode::Program, Compiler},
     tile::Tile,
 };
 use serde::{Deserialize, Serialize};
 use shared::types::{Colour, ID};
-use std::rc::Rc;
+use std::{cell::RefCell, rc::Rc};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Room {
@@ -17,7 +17,7 @@ pub struct Room {
     pub persistent: bool,
     pub bg_colour: Colour,
     pub clear_screen: bool,
-    pub creation_code: Result<Rc<[Instruction]>, String>,
+    pub creation_code: CreationCode,
 
     pub backgrounds: Vec<Background>,
     pub views_enabled: bool,
@@ -33,5 +33,36 @@ pub struct Instance {
     pub y: i32,
     pub object: i32,
     pub id: ID,
-    pub creation: Result<Rc<[Instruction]>, String>,
+    pub creation: CreationCode,
+}
+
+/// Creation code, which gets compiled the first time it runs like in GM8.
+/// Clones share it, so it only gets compiled once.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CreationCode(Rc<RefCell<Code>>);
+
+#[derive(Serialize, Deserialize)]
+enum Code {
+    Source(Box<[u8]>),
+    Compiled(Rc<Program>),
+}
+
+impl CreationCode {
+    pub fn new(source: Box<[u8]>) -> Self {
+        Self(Rc::new(RefCell::new(Code::Source(source))))
+    }
+
+    pub fn compiled(program: Rc<Program>) -> Self {
+        Self(Rc::new(RefCell::new(Code::Compiled(program))))
+    }
+
+    pub fn compile(&self, compiler: &mut Compiler) -> gml::Result<Rc<Program>> {
+        let mut code = self.0.borrow_mut();
+        let program = match &*code {
+            Code::Source(source) => compiler.compile(source).map_err(|e| gml::Error::CompileError(e.to_string()))?,
+            Code::Compiled(program) => return Ok(program.clone()),
+        };
+        *code = Code::Compiled(program.clone());
+        Ok(program)
+    }
 }
@@ -0,0 +1,177 @@
+//! Inspects and edits replay files, so a TAS can be cut up and put back together without re-recording it.
+
+use gm8emulator::Replay;
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process,
+};
+
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+
+const COMMANDS: &str = "Commands:
+    info FILE                       prints the start time, seed, length and stored events
+    truncate FILE FRAMES            keeps only the first FRAMES frames
+    insert FILE AT COUNT            inserts COUNT empty frames before frame AT
+    delete FILE FROM TO             deletes frames FROM up to but not including TO
+    splice FILE SOURCE FROM TO AT   copies SOURCE's frames FROM up to TO over FILE's, starting at frame AT
+    set-seed FILE SEED              changes the RNG seed the replay starts with
+    set-time FILE TIME              changes the time the replay starts at, in nanoseconds
+";
+
+fn help(argv0: &str, opts: getopts::Options) {
+    let name = Path::new(argv0).file_name().and_then(|f| f.to_str()).unwrap_or(argv0);
+    print!("{}\n{}", opts.usage(&format!("Usage: {} COMMAND FILE [ARGS] [options]", name)), COMMANDS);
+}
+
+fn main() {
+    process::exit(xmain());
+}
+
+fn xmain() -> i32 {
+    let args: Vec<String> = env::args().collect();
+    let process = args[0].clone();
+
+    let mut opts = getopts::Options::new();
+    opts.optflag("h", "help", "prints this help message");
+    opts.optopt("o", "output", "where to write the edited replay (default: over FILE)", "FILE");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(err) => {
+            eprintln!("{}", err);
+            return EXIT_FAILURE
+        },
+    };
+    if matches.opt_present("h") || matches.free.len() < 2 {
+        help(&process, opts);
+        return if matches.opt_present("h") { EXIT_SUCCESS } else { EXIT_FAILURE }
+    }
+
+    let (command, path, args) = (matches.free[0].as_str(), PathBuf::from(&matches.free[1]), &matches.free[2..]);
+    let mut replay = match Replay::from_file(&path) {
+        Ok(replay) => replay,
+        Err(e) => {
+            eprintln!("couldn't read {}: {}", path.display(), e);
+            return EXIT_FAILURE
+        },
+    };
+    let output = matches.opt_str("o").map(PathBuf::from).unwrap_or_else(|| path.clone());
+
+    if let ("info", []) = (command, args) {
+        info(&replay);
+        return EXIT_SUCCESS
+    }
+    if let Err(e) = edit(&mut replay, command, args).and_then(|()| replay.to_file(&output)) {
+        eprintln!("{}", e);
+        return EXIT_FAILURE
+    }
+    EXIT_SUCCESS
+}
+
+fn edit(replay: &mut Replay, command: &str, args: &[String]) -> Result<(), String> {
+    match (command, args) {
+        ("truncate", [frames]) => replay.truncate(number(frames)?),
+        ("insert", [at, count]) => replay.insert_frames(number(at)?, number(count)?),
+        ("delete", [from, to]) => replay.delete_frames(number(from)?..number(to)?),
+        ("splice", [source, from, to, at]) => {
+            let source =
+                Replay::from_file(Path::new(source)).map_err(|e| format!("couldn't read {}: {}", source, e))?;
+            replay.splice(number(at)?, &source, number(from)?..number(to)?)
+        },
+        ("set-seed", [seed]) => {
+            replay.set_start(None, Some(number(seed)?));
+            Ok(())
+        },
+        ("set-time", [time]) => {
+            replay.set_start(Some(number(time)?), None);
+            Ok(())
+        },
+        _ => Err(format!("unknown command or wrong number of arguments: {}\n\n{}", command, COMMANDS)),
+    }
+}
+
+fn number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid number: {}", s))
+}
+
+fn info(replay: &Replay) {
+    let frames = (0..replay.frame_count()).filter_map(|i| replay.get_frame(i)).collect::<Vec<_>>();
+    let events = frames.iter().map(|f| f.events.len()).sum::<usize>();
+    let checksums = frames.iter().filter(|f| f.checksum.is_some()).count();
+    println!("start time: {}", replay.start_time);
+    println!("start seed: {}", replay.start_seed);
+    println!("length: {} frames, {:.3} seconds", frames.len(), replay.get_length() / 1000.0);
+    println!("stored events: {} at startup, {} during frames", replay.startup_events.len(), events);
+    println!("frames with checksums: {}", checksums);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replay(frame_count: usize) -> Replay {
+        let mut replay = Replay::new(0, 0);
+        for _ in 0..frame_count {
+            replay.new_frame(30);
+        }
+        replay
+    }
+
+    fn run(replay: &mut Replay, command: &str, args: &[&str]) -> Result<(), String> {
+        edit(replay, command, &args.iter().map(|&a| a.into()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn edits() {
+        let mut r = replay(5);
+        run(&mut r, "truncate", &["3"]).unwrap();
+        assert_eq!(r.frame_count(), 3);
+        run(&mut r, "insert", &["1", "4"]).unwrap();
+        assert_eq!(r.frame_count(), 7);
+        run(&mut r, "delete", &["0", "2"]).unwrap();
+        assert_eq!(r.frame_count(), 5);
+        run(&mut r, "set-seed", &["-42"]).unwrap();
+        run(&mut r, "set-time", &["1000"]).unwrap();
+        assert_eq!((r.start_seed, r.start_time), (-42, 1000));
+    }
+
+    #[test]
+    fn bad_edits_change_nothing() {
+        let mut r = replay(3);
+        let e = run(&mut r, "truncate", &["4"]).unwrap_err();
+        assert_eq!(e, "can't keep 4 frames, the replay only has 3");
+        assert!(run(&mut r, "insert", &["4", "1"]).is_err());
+        assert!(run(&mut r, "delete", &["2", "4"]).is_err());
+        assert!(run(&mut r, "delete", &["2", "1"]).is_err());
+        assert_eq!(r.frame_count(), 3);
+
+        assert_eq!(run(&mut r, "truncate", &["-1"]).unwrap_err(), "invalid number: -1");
+        assert!(run(&mut r, "set-seed", &["99999999999"]).is_err());
+        assert!(run(&mut r, "truncate", &[]).unwrap_err().starts_with("unknown command or wrong number of arguments"));
+        assert!(run(&mut r, "info", &["extra"]).is_err());
+        assert!(run(&mut r, "splice", &["missing.gmtas", "0", "1", "0"]).unwrap_err().starts_with("couldn't read"));
+        assert_eq!(r.frame_count(), 3);
+    }
+
+    #[test]
+    fn splice_from_file() {
+        let dir = std::env::temp_dir().join(format!("gm8emulator-test-replay-tool-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source.gmtas");
+        let mut s = replay(4);
+        s.new_frame(60);
+        s.to_file(&source).unwrap();
+
+        let mut r = replay(2);
+        run(&mut r, "splice", &[source.to_str().unwrap(), "3", "5", "2"]).unwrap();
+        let output = dir.join("output.txt");
+        r.to_file(&output).unwrap();
+        let r = Replay::from_file(&output).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let fps = (0..r.frame_count()).map(|i| r.get_frame(i).unwrap().fps).collect::<Vec<_>>();
+        assert_eq!(fps, [30, 30, 30, 60]);
+    }
+}
@@ -1,5 +1,8 @@
 pub mod background;
+pub mod checksum;
+pub mod compilecache;
 pub mod draw;
+pub mod errordialog;
 pub mod events;
 pub mod external;
 pub mod gm_save;
@@ -26,12 +29,27 @@ use crate::{
         self,
         font::{Character, Font},
         path::{self, Path},
-        room::{self, Room},
+        room::{self, CreationCode, Room},
         sprite::{Collider, Frame, Sprite},
         trigger::{self, Trigger},
         Object, Script, Timeline,
     },
-    gml::{self, ds, ev, file, rand::Random, runtime::Instruction, Compiler, Context},
+    gml::{
+        self,
+        bytecode::Program,
+        cd,
+        debugger,
+        ds,
+        ev,
+        file,
+        mci,
+        network,
+        process,
+        profiler,
+        rand::Random,
+        trace,
+        Compiler, Context,
+    },
     handleman::{HandleArray, HandleList},
     input::InputManager,
     instance::{DummyFieldHolder, Instance, InstanceState},
@@ -43,7 +61,7 @@ use encoding_rs::Encoding;
 use gm8exe::asset::{PascalString, extension::{CallingConvention, FileKind, FunctionValueKind}};
 use gmio::{
     atlas::AtlasBuilder,
-    render::{Renderer, RendererOptions, Scaling},
+    render::{Backend, Renderer, RendererOptions, Scaling},
     window::{self, Window, WindowBuilder},
 };
 use includedfile::IncludedFile;
@@ -60,7 +78,7 @@ use std::{
     collections::{BTreeMap, HashMap, HashSet, VecDeque},
     convert::TryFrom,
     fs::File,
-    io::{BufReader, Write},
+    io::Write,
     net::{SocketAddr, TcpStream},
     path::PathBuf,
     rc::Rc,
@@ -88,6 +106,7 @@ pub struct Game {
     pub extension_finalizers: Vec<usize>,
 
     pub externals: Vec<Option<external::External>>,
+    pub external_state: external::State,
     pub surface_fix: bool,
 
     pub last_instance_id: ID,
@@ -148,6 +167,10 @@ pub struct Game {
 
     pub error_occurred: bool,
     pub error_last: RCStr,
+    pub show_errors: bool, // whether errors get a dialog, or just set error_occurred and error_last
+    pub always_abort: bool, // whether that dialog only lets you abort
+    pub fatal_errors: bool, // end the game at the first error no matter what the settings say
+    pub call_stack: Vec<trace::Frame>, // what's running right now, for saying where errors happened
 
     pub game_id: i32,
     pub program_directory: RCStr,
@@ -156,8 +179,17 @@ pub struct Game {
     pub gm_version: Version,
     pub open_ini: Option<(ini::Ini, RCStr)>, // keep the filename for writing
     pub open_file: Option<file::TextHandle>, // for legacy file functions from GM <= 5.1
-    pub file_finder: Option<Box<dyn Iterator<Item = PathBuf>>>,
+    pub path_resolver: file::PathResolver,
+    pub exec_policy: process::Policy,
+    pub vfs: file::Vfs,
+    pub mplay: network::Multiplayer,
+    pub debugger: Option<debugger::Debugger>,
+    pub profiler: Option<profiler::Profiler>,
+    pub cd: cd::CdDrive,
+    pub mci: mci::Devices,
+    pub file_finder: Option<VecDeque<PathBuf>>,
     pub spoofed_time_nanos: Option<u128>, // use this instead of real time if this is set
+    pub checksums: bool,                  // whether replays get state checksums recorded and checked
     pub parameters: Vec<String>,
     pub encoding: &'static Encoding,
 
@@ -205,7 +237,7 @@ pub enum SceneChange {
 /// A function defined in an extension, which could either be a DLL external or some compiled GML
 pub enum ExtensionFunction {
     Dll(external::External),
-    Gml(Rc<[Instruction]>),
+    Gml(Rc<Program>),
 }
 
 /// A room state originally loaded from a room asset.
@@ -248,6 +280,7 @@ impl From<PascalString> for RCStr {
 }
 
 impl Game {
+    #[allow(clippy::too_many_arguments)] // only main and the test fixtures call it
     pub fn launch(
         assets: gm8exe::GameAssets,
         file_path: PathBuf,
@@ -256,6 +289,10 @@ impl Game {
         encoding: &'static Encoding,
         frame_limiter: bool,
         play_type: PlayType,
+        headless: Option<(i32, i32)>,
+        path_resolver: file::PathResolver,
+        exec_policy: process::Policy,
+        compile_cache: Option<compilecache::Key>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         // Parse file path
         let mut file_path2 = file_path.clone();
@@ -383,6 +420,9 @@ impl Game {
             },
         };
 
+        // anything the game writes while recording or replaying stays in memory, starting with included files
+        let mut vfs = file::Vfs::new(play_type != PlayType::Normal);
+        vfs.set_roots(&file_path2, &temp_directory);
         let included_files = included_files
             .into_iter()
             .map(|i| {
@@ -411,7 +451,7 @@ impl Game {
                     free_after_export: i.free_memory,
                     remove_at_end: i.remove_at_end,
                 };
-                i.export(temp_directory.clone(), program_directory.to_string().into())?;
+                i.export(temp_directory.clone(), program_directory.to_string().into(), &path_resolver, &mut vfs)?;
                 Ok(i)
             })
             .collect::<Result<Vec<_>, std::io::Error>>()
@@ -490,6 +530,19 @@ impl Game {
             }
         }
 
+        // If this game's been compiled before, use that instead of compiling it all again
+        let mut cache_loaded = false;
+        if let Some(key) = compile_cache {
+            match compilecache::load(key) {
+                Some((cached, precompiled)) => {
+                    compiler = cached;
+                    compiler.use_precompiled(precompiled);
+                    cache_loaded = true;
+                },
+                None => compiler.use_precompiled(Default::default()),
+            }
+        }
+
         // Set up a Renderer
         let options = RendererOptions {
             size: (room1_width, room1_height),
@@ -514,8 +567,16 @@ impl Game {
 
         // TODO: specific flags here (make wb mutable)
 
+        // no display means nothing for GL to draw into, so draw everything on the CPU instead
+        let (wb, backend) = match headless {
+            Some((display_width, display_height)) => {
+                (wb.with_headless(display_width, display_height), Backend::Software)
+            },
+            None => (wb, Backend::OpenGL),
+        };
+
         let window = wb.build().expect("oh no");
-        let mut renderer = Renderer::new((), &options, &window, settings.clear_colour.into())?;
+        let mut renderer = Renderer::new(backend, &options, &window, settings.clear_colour.into())?;
 
         let mut atlases = AtlasBuilder::new(renderer.max_texture_size() as _);
 
@@ -534,6 +595,7 @@ impl Game {
         // Code compiling starts here. The order in which things are compiled is important for
         // keeping savestates compatible. This isn't 100% accurate right now, but it's mostly right.
 
+        let mut external_state = external::State::default();
         let mut extension_functions = Vec::with_capacity(extensions.iter().map(|x| x.files.iter().map(|f| f.functions.len()).sum::<usize>()).sum::<usize>());
         for extension in extensions.iter() {
             temp_directory.push(&*String::from_utf8_lossy(extension.folder_name.0.as_ref()));
@@ -569,11 +631,13 @@ impl Game {
                                         FunctionValueKind::GMString => shared::dll::ValueType::Str,
                                     }).collect::<Vec<_>>(),
                                 },
-                                play_type == PlayType::Record,
+                                play_type != PlayType::Normal,
                                 match gm_version {
                                     Version::GameMaker8_0 => encoding,
                                     Version::GameMaker8_1 => encoding_rs::UTF_8,
                                 },
+                                &mut external_state,
+                                &external::Context { vfs: &vfs, path_resolver: &path_resolver, now: 0 },
                             ) {
                                 Ok(external) => extension_functions.push(Some(ExtensionFunction::Dll(external))),
                                 Err(_) => extension_functions.push(None),
@@ -821,22 +885,15 @@ impl Game {
             .into_iter()
             .map(|t| {
                 t.map(|b| {
-                    let mut moments: BTreeMap<i32, Rc<RefCell<Tree>>> = BTreeMap::new();
-                    for (moment, actions) in b.moments.iter() {
-                        match Tree::from_list(actions, &mut compiler) {
-                            Ok(t) => {
-                                moments.insert(*moment as i32, Rc::new(RefCell::new(t)));
-                            },
-                            Err(e) => {
-                                return Err(format!("Compiler error in timeline {} moment {}: {}", b.name, moment, e))
-                            },
-                        };
-                    }
-                    Ok(Box::new(Timeline { name: b.name.into(), moments: Rc::new(RefCell::new(moments)) }))
+                    let moments: BTreeMap<i32, Rc<RefCell<Tree>>> = b
+                        .moments
+                        .into_iter()
+                        .map(|(moment, actions)| (moment as i32, Rc::new(RefCell::new(Tree::from_list_lazy(actions)))))
+                        .collect();
+                    Box::new(Timeline { name: b.name.into(), moments: Rc::new(RefCell::new(moments)) })
                 })
-                .transpose()
             })
-            .collect::<Result<Vec<_>, _>>()?;
+            .collect::<Vec<_>>();
 
         let objects = {
             let mut object_parents: Vec<Option<i32>> = Vec::with_capacity(objects.len());
@@ -848,22 +905,15 @@ impl Game {
                         None => None,
                     });
                     o.map(|b| {
+                        let b = *b;
                         let mut events: [HashMap<u32, Rc<RefCell<Tree>>>; 12] = std::default::Default::default();
-                        for ((i, map), input) in events.iter_mut().enumerate().zip(b.events.iter()) {
+                        for (map, input) in events.iter_mut().zip(b.events) {
                             map.reserve(input.len());
                             for (sub, actions) in input {
-                                map.insert(*sub, match Tree::from_list(actions, &mut compiler) {
-                                    Ok(t) => Rc::new(RefCell::new(t)),
-                                    Err(e) => {
-                                        return Err(format!(
-                                            "Compiler error in object {} event {},{}: {}",
-                                            b.name, i, sub, e
-                                        ))
-                                    },
-                                });
+                                map.insert(sub, Rc::new(RefCell::new(Tree::from_list_lazy(actions))));
                             }
                         }
-                        Ok(Box::new(Object {
+                        Box::new(Object {
                             name: b.name.into(),
                             solid: b.solid,
                             visible: b.visible,
@@ -874,11 +924,10 @@ impl Game {
                             parent_index: b.parent_index,
                             events,
                             children: Rc::new(RefCell::new(HashSet::new())),
-                        }))
+                        })
                     })
-                    .transpose()
                 })
-                .collect::<Result<Vec<_>, _>>()?;
+                .collect::<Vec<_>>();
 
             // Populate identity lists
             for (i, object) in objects.iter_mut().enumerate().filter_map(|(i, x)| x.as_mut().map(|x| (i, x))) {
@@ -908,9 +957,7 @@ impl Game {
             .into_iter()
             .map(|t| {
                 t.map(|b| {
-                    let creation_code = compiler
-                        .compile(&b.creation_code.0)
-                        .map_err(|e| format!("Compiler error in room {} creation code: {}", b.name, e));
+                    let creation_code = CreationCode::new(b.creation_code.0);
                     let width = b.width;
                     let height = b.height;
                     Box::new(Room {
@@ -990,9 +1037,7 @@ impl Game {
                                 y: i.y,
                                 object: i.object,
                                 id: i.id,
-                                creation: compiler.compile(&i.creation_code.0).map_err(|e| {
-                                    format!("Compiler error in creation code of instance {}: {}", i.id, e)
-                                }),
+                                creation: CreationCode::new(i.creation_code.0),
                             })
                             .collect::<Vec<_>>()
                             .into(),
@@ -1022,6 +1067,15 @@ impl Game {
             })
             .collect::<Vec<_>>();
 
+        // Everything that's compiled up front is done now, so save it for next time if it wasn't already
+        if let (Some(key), Some(precompiled)) = (compile_cache, compiler.take_precompiled()) {
+            if !cache_loaded {
+                if let Err(e) = compilecache::save(key, &compiler, &precompiled) {
+                    eprintln!("couldn't save compile cache: {}", e);
+                }
+            }
+        }
+
         // Make event holder lists
         let mut event_holders: [IndexMap<u32, Rc<RefCell<Vec<i32>>>>; 12] = Default::default();
         Self::fill_event_holders(&mut event_holders, &objects);
@@ -1044,6 +1098,7 @@ impl Game {
             extension_initializers,
             extension_finalizers,
             externals: Vec::new(),
+            external_state,
             surface_fix: false,
             input_manager: InputManager::new(),
             assets: Assets { backgrounds, fonts, objects, paths, rooms, scripts, sprites, timelines, triggers },
@@ -1114,8 +1169,17 @@ impl Game {
             gm_version,
             open_ini: None,
             open_file: None,
+            path_resolver,
+            exec_policy,
+            vfs,
+            mplay: network::Multiplayer::new(play_type.clone()),
+            debugger: None,
+            profiler: None,
+            cd: Default::default(),
+            mci: Default::default(),
             file_finder: None,
             spoofed_time_nanos: None,
+            checksums: true,
             frame_limiter,
             fps: 0,
             parameters: game_arguments,
@@ -1127,6 +1191,10 @@ impl Game {
             health_capt_d: false,
             error_occurred: false,
             error_last: "".to_string().into(),
+            show_errors: settings.show_error_messages,
+            always_abort: settings.always_abort,
+            fatal_errors: false,
+            call_stack: Vec::new(),
             window,
             window_border,
             window_icons,
@@ -1440,7 +1508,11 @@ impl Game {
                 // Run this instance's room creation code
                 let mut new_context = Context::with_single_instance(*handle);
                 new_context.event_object = instance.object;
-                self.execute(&instance.creation.clone()?, &mut new_context)?;
+                let frame = trace::Frame::InstanceCreation(instance.id as _);
+                self.with_frame_caught(frame, |game| {
+                    let creation = instance.creation.compile(&mut game.compiler)?;
+                    game.execute(&creation, &mut new_context)
+                })?;
 
                 // Run create event for this instance
                 self.run_instance_event(ev::CREATE, 0, *handle, *handle, None)?;
@@ -1460,7 +1532,11 @@ impl Game {
         if !is_stored {
             let dummy_instance = self.room.instance_list.insert_dummy(Instance::new_dummy(self.assets.objects.get_asset(0).map(|x| x.as_ref())));
             let mut new_context = Context::with_single_instance(dummy_instance);
-            self.execute(&room.creation_code?, &mut new_context)?;
+            let frame = trace::Frame::RoomCreation(room_id);
+            self.with_frame_caught(frame, |game| {
+                let creation_code = room.creation_code.compile(&mut game.compiler)?;
+                game.execute(&creation_code, &mut new_context)
+            })?;
             self.room.instance_list.remove_dummy(dummy_instance);
         }
 
@@ -1568,6 +1644,9 @@ impl Game {
 
     /// Runs a frame loop and draws the screen. Exits immediately, without waiting for any FPS limitation.
     pub fn frame(&mut self) -> gml::Result<()> {
+        self.mplay_recorded("mplay", network::Multiplayer::poll)?;
+        self.debugger_poll();
+
         if self.esc_close_game && self.input_manager.key_get_lastkey() == 0x1b {
             self.scene_change = Some(SceneChange::End);
             return Ok(())
@@ -1598,6 +1677,7 @@ impl Game {
         while let Some(handle) = iter.next(&self.room.instance_list) {
             let instance = self.room.instance_list.get(handle);
             let object_index = instance.object_index.get();
+            let timeline_index = instance.timeline_index.get();
             if instance.timeline_running.get() {
                 if let Some(timeline) = self.assets.timelines.get_asset(instance.timeline_index.get()) {
                     let moments = timeline.moments.clone();
@@ -1615,12 +1695,15 @@ impl Game {
                                     instance.timeline_position.set(new_position)
                                 }
 
-                                for (_, tree) in moments
+                                for (moment, tree) in moments
                                     .borrow()
                                     .iter()
                                     .filter(|(&x, _)| Real::from(x) >= old_position && Real::from(x) < new_position)
                                 {
-                                    self.execute_tree(tree.clone(), handle, handle, 0, 0, object_index)?;
+                                    let frame = trace::Frame::Moment { timeline: timeline_index, moment: *moment };
+                                    self.with_frame(frame, |game| {
+                                        game.execute_tree(tree.clone(), handle, handle, 0, 0, object_index)
+                                    })?;
                                 }
                             },
                             x if x < Real::from(0) => {
@@ -1630,13 +1713,16 @@ impl Game {
                                     instance.timeline_position.set(new_position)
                                 }
 
-                                for (_, tree) in moments
+                                for (moment, tree) in moments
                                     .borrow()
                                     .iter()
                                     .filter(|(&x, _)| Real::from(x) > new_position && Real::from(x) <= old_position)
                                     .rev()
                                 {
-                                    self.execute_tree(tree.clone(), handle, handle, 0, 0, object_index)?;
+                                    let frame = trace::Frame::Moment { timeline: timeline_index, moment: *moment };
+                                    self.with_frame(frame, |game| {
+                                        game.execute_tree(tree.clone(), handle, handle, 0, 0, object_index)
+                                    })?;
                                 }
                             },
                             _ => {},
@@ -1786,7 +1872,9 @@ impl Game {
     pub fn run_extension_function(&mut self, id: usize, mut context: Context) -> gml::Result<gml::Value> {
         match &self.extension_functions[id] {
             Some(ExtensionFunction::Dll(external)) => {
-                external.call(&context.arguments[..context.argument_count])
+                let external_context =
+                    external::Context { vfs: &self.vfs, path_resolver: &self.path_resolver, now: self.media_clock() };
+                external.call(&mut self.external_state, &external_context, &context.arguments[..context.argument_count])
             },
             Some(ExtensionFunction::Gml(gml)) => {
                 let instructions = gml.clone();
@@ -1931,8 +2019,8 @@ impl Game {
                         path.push(&filename);
                         if path.exists() {
                             println!("Project '{}' exists, loading workspace", filename);
-                            let state = bincode::deserialize_from::<_, SaveState>(BufReader::new(File::open(&path)?))?;
-                            replay = state.load_into(self);
+                            let state = SaveState::from_bytes(&std::fs::read(&path)?)?;
+                            replay = state.load_into(self)?;
                         } else {
                             println!("Project '{}' doesn't exist, so loading game at entry point", filename);
                             self.init()?;
@@ -1948,7 +2036,7 @@ impl Game {
                             self.stored_events.clear();
 
                             println!("Creating new workspace...");
-                            let bytes = bincode::serialize(&SaveState::from(self, replay.clone()))?;
+                            let bytes = SaveState::from(self, replay.clone())?.to_bytes()?;
                             File::create(&path)?.write_all(&bytes)?;
                         }
 
@@ -2037,6 +2125,9 @@ impl Game {
                             frame.events.push(ev.clone());
                         }
                         self.stored_events.clear();
+                        if self.checksums {
+                            frame.checksum = Some(self.checksum());
+                        }
 
                         // Fake frame limiter stuff (don't actually frame-limit in record mode)
                         if let Some(t) = self.spoofed_time_nanos.as_mut() {
@@ -2078,7 +2169,7 @@ impl Game {
                         std::fs::create_dir_all(&path)?;
                         path.push(filename);
                         let mut f = File::create(&path)?;
-                        let bytes = bincode::serialize(&SaveState::from(self, replay.clone()))?;
+                        let bytes = SaveState::from(self, replay.clone())?.to_bytes()?;
                         f.write_all(&bytes)?;
                     },
 
@@ -2086,9 +2177,8 @@ impl Game {
                         // Load savestate from a file
                         let mut path = project_path.clone();
                         path.push(filename);
-                        let f = File::open(&path)?;
-                        let state = bincode::deserialize_from::<_, SaveState>(BufReader::new(f))?;
-                        replay = state.load_into(self);
+                        let state = SaveState::from_bytes(&std::fs::read(&path)?)?;
+                        replay = state.load_into(self)?;
 
                         // Send an update
                         stream.send_message(&message::Information::Update {
@@ -2182,7 +2272,7 @@ impl Game {
     }
 
     // Replays some recorded inputs to the game
-    pub fn replay(mut self, replay: Replay) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn replay(&mut self, replay: Replay) -> Result<(), Box<dyn std::error::Error>> {
         let mut frame_count: usize = 0;
         self.rand.set_seed(replay.start_seed);
         self.spoofed_time_nanos = Some(replay.start_time);
@@ -2201,8 +2291,23 @@ impl Game {
 
         let mut time_now = std::time::Instant::now();
         loop {
+            // nobody can close a headless window, so stop once there's nothing left to replay
+            if self.window.is_headless() && frame_count >= replay.frame_count() {
+                if !self.stored_events.is_empty() {
+                    return Err(format!(
+                        "ERROR: {} stored events remaining at end of replay ({} frames); aborting",
+                        self.stored_events.len(),
+                        frame_count,
+                    )
+                    .into())
+                }
+                println!("replay finished after {} frames", frame_count);
+                break Ok(())
+            }
+
             self.window.process_events();
             self.input_manager.mouse_update_previous();
+            let expected_checksum = replay.get_frame(frame_count).and_then(|f| f.checksum).filter(|_| self.checksums);
             if let Some(frame) = replay.get_frame(frame_count) {
                 if !self.stored_events.is_empty() {
                     return Err(format!(
@@ -2246,6 +2351,19 @@ impl Game {
                 None => (),
             }
 
+            // replays recorded before checksums existed don't have any, so they can't be checked
+            if let Some(expected) = expected_checksum {
+                let differences = expected.differences(&self.checksum());
+                if !differences.is_empty() {
+                    return Err(format!(
+                        "ERROR: replay desynced on frame {}, these don't match the recording: {}; aborting",
+                        frame_count,
+                        differences.join(", "),
+                    )
+                    .into())
+                }
+            }
+
             // exit if X pressed or game_end() invoked
             if self.window.close_requested() {
                 break Ok(self.run_game_end_events()?)
@@ -2908,3 +3026,29 @@ impl<T> GetAsset<T> for Vec<Option<T>> {
         self.get_mut(usize::try_from(index).ok()?)?.as_mut()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_game;
+
+    #[test]
+    fn headless_replays_stop_at_the_end() {
+        let mut game = test_game();
+        assert!(game.window.is_headless());
+        let mut replay = Replay::new(0, 0);
+        for _ in 0..5 {
+            replay.new_frame(30);
+        }
+        game.replay(replay.clone()).unwrap();
+        assert_eq!(game.spoofed_time_nanos, Some(5 * u128::from(1_000_000_000u32 / 30)));
+
+        // an event nothing asked for means the replay didn't play back the way it was recorded
+        let mut game = test_game();
+        replay.new_frame(30).events.push(replay::Event::ShowMessage);
+        match game.replay(replay) {
+            Err(e) => assert!(e.to_string().contains("1 stored events remaining at end of replay (6 frames)"), "{}", e),
+            Ok(()) => panic!("expected the leftover event to be reported"),
+        }
+    }
+}
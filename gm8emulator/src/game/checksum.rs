@@ -0,0 +1,259 @@
+//! Hashes of the game state, stored in replays so a replay that's stopped matching what it was recorded from
+//! can say so instead of carrying on with the wrong inputs.
+//! The hashing is done by hand (FNV-1a) rather than with std's hasher, since that's allowed to change between
+//! Rust versions, and the whole point is comparing against replays made by other builds.
+
+use crate::{
+    game::Game,
+    gml::Value,
+    instance::{Field, Instance},
+    math::Real,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The state at the end of a frame, split up so a mismatch can say roughly what went wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checksum {
+    pub instances: u64,
+    pub globals: u64,
+    pub seed: i32,
+    pub room: u64,
+}
+
+impl Checksum {
+    /// Names the parts of the state that don't match.
+    pub fn differences(&self, other: &Self) -> Vec<&'static str> {
+        let mut parts = Vec::new();
+        if self.instances != other.instances {
+            parts.push("instances");
+        }
+        if self.globals != other.globals {
+            parts.push("globals");
+        }
+        if self.seed != other.seed {
+            parts.push("RNG seed");
+        }
+        if self.room != other.room {
+            parts.push("room");
+        }
+        parts
+    }
+}
+
+/// Also used for compile cache keys, which have to stay the same between builds too.
+pub(super) struct Fnv(pub(super) u64);
+
+impl Fnv {
+    pub(super) fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    pub(super) fn bytes(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn int(&mut self, i: i64) {
+        self.bytes(&i.to_le_bytes());
+    }
+
+    fn real(&mut self, r: Real) {
+        self.bytes(&r.into_inner().to_bits().to_le_bytes());
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Real(r) => {
+                self.int(0);
+                self.real(*r);
+            },
+            Value::Str(s) => {
+                self.int(1);
+                self.int(s.as_ref().len() as _);
+                self.bytes(s.as_ref());
+            },
+        }
+    }
+}
+
+impl Game {
+    /// Hashes everything a replay needs to stay in sync with.
+    pub fn checksum(&self) -> Checksum {
+        let mut instances = Fnv::new();
+        let list = &self.room.instance_list;
+        let (mut active, mut inactive) = (list.iter_by_insertion(), list.iter_inactive());
+        while let Some(handle) = active.next(list) {
+            self.hash_instance(&mut instances, list.get(handle));
+        }
+        instances.int(-1);
+        while let Some(handle) = inactive.next(list) {
+            self.hash_instance(&mut instances, list.get(handle));
+        }
+
+        let mut globals = Fnv::new();
+        self.hash_fields(&mut globals, &self.globals.fields);
+        globals.int(self.score.into());
+        globals.int(self.lives.into());
+        globals.real(self.health);
+
+        let mut room = Fnv::new();
+        room.int(self.room.id.into());
+        room.int(self.room.width.into());
+        room.int(self.room.height.into());
+        room.int(self.room.speed.into());
+        for view in &self.room.views {
+            room.int(view.source_x.into());
+            room.int(view.source_y.into());
+        }
+
+        Checksum { instances: instances.0, globals: globals.0, seed: self.rand.seed(), room: room.0 }
+    }
+
+    // position and movement, plus every variable, which covers most of what matters without being too slow
+    fn hash_instance(&self, h: &mut Fnv, instance: &Instance) {
+        h.int(instance.id.get().into());
+        h.int(instance.object_index.get().into());
+        for r in &[
+            &instance.x,
+            &instance.y,
+            &instance.hspeed,
+            &instance.vspeed,
+            &instance.direction,
+            &instance.speed,
+            &instance.image_index,
+            &instance.path_position,
+            &instance.timeline_position,
+        ] {
+            h.real(r.get());
+        }
+        h.int(instance.sprite_index.get().into());
+        let mut alarms = instance.alarms.borrow().iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+        alarms.sort();
+        for (alarm, time) in alarms {
+            h.int(alarm.into());
+            h.int(time.into());
+        }
+        self.hash_fields(h, &instance.fields.borrow());
+    }
+
+    // fields are hashed by name, since ids come from the compiler and aren't stable between builds
+    fn hash_fields(&self, h: &mut Fnv, fields: &HashMap<usize, Field>) {
+        let mut named = fields.iter().map(|(id, f)| (self.compiler.get_field_name(*id), f)).collect::<Vec<_>>();
+        named.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, field) in named {
+            let name = name.unwrap_or_default();
+            h.int(name.len() as _);
+            h.bytes(name.as_bytes());
+            match field {
+                Field::Single(value) => h.value(value),
+                Field::Array(array) => {
+                    h.int(array.len() as _);
+                    let mut array = array.iter().collect::<Vec<_>>();
+                    array.sort_by_key(|(i, _)| **i);
+                    for (i, value) in array {
+                        h.int((*i).into());
+                        h.value(value);
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game::Replay,
+        testing::{code, run, test_game},
+    };
+    use std::cell::Cell;
+
+    fn fnv(bytes: &[u8]) -> u64 {
+        let mut h = Fnv::new();
+        h.bytes(bytes);
+        h.0
+    }
+
+    // replays from other builds have to get the same hashes, so this can never change
+    #[test]
+    fn hashing_is_fnv1a() {
+        assert_eq!(fnv(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn checksums_cover_what_replays_depend_on() {
+        let mut game = test_game();
+        let instance = run(&mut game, 0.0, 0.0, &[]).unwrap();
+        let before = game.checksum();
+        let changed = |game: &mut Game, f: &dyn Fn(&mut Game)| {
+            f(game);
+            let differences = before.differences(&game.checksum());
+            f(game);
+            differences
+        };
+        let toggle = |r: &Cell<Real>| r.set(if r.get() == 0.into() { 1.into() } else { 0.into() });
+
+        assert_eq!(changed(&mut game, &|g| toggle(&g.room.instance_list.get(instance).vspeed)), ["instances"]);
+        let alarm = |g: &mut Game| {
+            let mut alarms = g.room.instance_list.get(instance).alarms.borrow_mut();
+            if alarms.remove(&0).is_none() {
+                alarms.insert(0, 5);
+            }
+        };
+        assert_eq!(changed(&mut game, &alarm), ["instances"]);
+        assert_eq!(changed(&mut game, &|g| g.score = if g.score == 0 { 10 } else { 0 }), ["globals"]);
+        assert_eq!(changed(&mut game, &|g| g.room.speed = if g.room.speed == 30 { 60 } else { 30 }), ["room"]);
+        let seed = game.rand.seed();
+        game.rand.set_seed(seed + 1);
+        assert_eq!(before.differences(&game.checksum()), ["RNG seed"]);
+        game.rand.set_seed(seed);
+
+        // things that only change how the game looks are left out
+        assert!(changed(&mut game, &|g| toggle(&g.room.instance_list.get(instance).image_alpha)).is_empty());
+        game.draw_colour = 0xff.into();
+        assert_eq!(game.checksum(), before);
+    }
+
+    #[test]
+    fn field_order_doesnt_matter() {
+        let mut game = test_game();
+        let a = run(&mut game, 0.0, 0.0, &[code("x = 0; foo = 1; bar = \"2\"")]).unwrap();
+        let mut game2 = test_game();
+        let b = run(&mut game2, 0.0, 0.0, &[code("x = 0; bar = \"2\"; foo = 1")]).unwrap();
+        let (mut h1, mut h2) = (Fnv::new(), Fnv::new());
+        game.hash_instance(&mut h1, game.room.instance_list.get(a));
+        game2.hash_instance(&mut h2, game2.room.instance_list.get(b));
+        assert_eq!(h1.0, h2.0);
+    }
+
+    #[test]
+    fn replays_notice_desyncs() {
+        let mut game = test_game();
+        let instance = run(&mut game, 0.0, 0.0, &[]).unwrap();
+        let before = game.checksum();
+        assert_eq!(before, game.checksum());
+        game.room.instance_list.get(instance).x.set(1.into());
+        run(&mut game, 0.0, 0.0, &[code("global.a = 1")]).unwrap();
+        assert_eq!(before.differences(&game.checksum()), ["instances", "globals"]);
+
+        let mut game = test_game();
+        let mut replay = Replay::new(0, 0);
+        replay.new_frame(30);
+        replay.new_frame(30).checksum = Some(before);
+        match game.replay(replay.clone()) {
+            Err(e) => assert!(e.to_string().contains("desynced on frame 1"), "{}", e),
+            Ok(()) => panic!("expected the replay to desync"),
+        }
+
+        // unless checking is turned off
+        let mut game = test_game();
+        game.checksums = false;
+        game.replay(replay).unwrap();
+    }
+}
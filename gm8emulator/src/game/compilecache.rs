@@ -0,0 +1,221 @@
+//! Everything that gets compiled when a game launches, saved to disk so the next launch can skip compiling it.
+//! That's scripts, triggers and extension functions. Object events, timelines and creation code are compiled
+//! the first time they run, which is after the cache gets saved, so they're compiled again every launch.
+//! Saving them later on would give the compiler field IDs in whatever order the game happened to run things,
+//! so a launch with the cache would stop matching one without it.
+
+use crate::{
+    game::checksum::Fnv,
+    gml::{compiler::Precompiled, Compiler},
+};
+use bincode::Options;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// How many games' cache files to keep. Saving one more deletes whichever was used longest ago.
+const MAX_FILES: usize = 32;
+
+/// Identifies a game's data, so a cache file doesn't get used for a different game or a different version of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Key(u64);
+
+impl Key {
+    /// Hashes the whole game exe, before it's been decrypted or anything.
+    /// This has to come out the same for every build of the emulator, so it doesn't use std's hasher.
+    pub fn of(exe: &[u8]) -> Self {
+        let mut hasher = Fnv::new();
+        hasher.bytes(exe);
+        Self(hasher.0)
+    }
+
+    fn path(self, dir: &Path) -> PathBuf {
+        dir.join(format!("{:016x}.cache", self.0))
+    }
+}
+
+fn cache_dir() -> PathBuf {
+    std::env::temp_dir().join("gm8emulator")
+}
+
+/// Something that changes whenever the emulator does, since bytecode from another build might not mean the same thing.
+/// Dev builds don't bump the version number, so the exe's size and modified time go in too.
+fn emulator_build() -> String {
+    let exe = std::env::current_exe().and_then(fs::metadata).ok();
+    format!(
+        "{} {:?} {:?}",
+        env!("CARGO_PKG_VERSION"),
+        exe.as_ref().map(|m| m.len()),
+        exe.and_then(|m| m.modified().ok()),
+    )
+}
+
+/// Loads the compiler and everything it compiled last time, if there's a cache file for this game
+/// and it was made by this build of the emulator. Anything else means it has to be rebuilt.
+pub fn load(key: Key) -> Option<(Compiler, Precompiled)> {
+    load_from(&cache_dir(), key)
+}
+
+/// Saves the compiler and everything it compiled, for next time.
+pub fn save(key: Key, compiler: &Compiler, precompiled: &Precompiled) -> io::Result<()> {
+    save_to(&cache_dir(), key, compiler, precompiled)
+}
+
+fn load_from(dir: &Path, key: Key) -> Option<(Compiler, Precompiled)> {
+    let path = key.path(dir);
+    let file = File::open(&path).ok()?;
+    let size = file.metadata().ok()?.len();
+    // nothing in it can be bigger than the file, so a broken length can't make it try to allocate something huge
+    let options = bincode::options().with_fixint_encoding().allow_trailing_bytes().with_limit(size);
+    let mut file = BufReader::new(file);
+    let (build, file_key): (String, u64) = options.deserialize_from(&mut file).ok()?;
+    if build != emulator_build() || file_key != key.0 {
+        return None
+    }
+    let cached = options.deserialize_from(&mut file).ok()?;
+    // the modified time is what eviction goes by, so this counts as using it
+    drop(file);
+    let _ = fs::OpenOptions::new().write(true).open(&path).and_then(|f| f.set_modified(SystemTime::now()));
+    Some(cached)
+}
+
+fn save_to(dir: &Path, key: Key, compiler: &Compiler, precompiled: &Precompiled) -> io::Result<()> {
+    let path = key.path(dir);
+    fs::create_dir_all(dir)?;
+    // write to a temporary file first so a half-written cache never gets loaded
+    let temp_path = path.with_extension("tmp");
+    let mut file = BufWriter::new(File::create(&temp_path)?);
+    bincode::serialize_into(&mut file, &(emulator_build(), key.0)).map_err(io::Error::other)?;
+    bincode::serialize_into(&mut file, &(compiler, precompiled)).map_err(io::Error::other)?;
+    file.flush()?;
+    drop(file);
+    fs::rename(temp_path, &path)?;
+    evict(dir, &path)
+}
+
+// deletes the least recently used cache files until there are few enough, never touching the one just saved
+fn evict(dir: &Path, keep: &Path) -> io::Result<()> {
+    let mut files = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| matches!(path.extension(), Some(ext) if ext == "cache") && path != keep)
+        .map(|path| (fs::metadata(&path).and_then(|m| m.modified()).ok(), path))
+        .collect::<Vec<_>>();
+    if files.len() < MAX_FILES {
+        return Ok(())
+    }
+    files.sort();
+    for (_, path) in &files[..files.len() + 1 - MAX_FILES] {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    // a directory of its own, so tests running at the same time don't clash
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gm8emulator-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // what a game would leave behind after launching: a compiler with some names in, and some compiled code
+    fn compiled() -> (Compiler, Precompiled) {
+        let mut compiler = Compiler::new();
+        compiler.register_script(b"scr_test".to_vec().into(), 0);
+        compiler.use_precompiled(Default::default());
+        compiler.compile(b"a = scr_test(b)").unwrap();
+        let precompiled = compiler.take_precompiled().unwrap();
+        (compiler, precompiled)
+    }
+
+    #[test]
+    fn cache_hits_skip_compiling() {
+        let dir = test_dir("hit");
+        let key = Key::of(b"game");
+        assert!(load_from(&dir, key).is_none());
+        let (compiler, precompiled) = compiled();
+        save_to(&dir, key, &compiler, &precompiled).unwrap();
+
+        let (mut cached, precompiled) = load_from(&dir, key).unwrap();
+        assert_eq!(cached.find_field_id(b"a"), compiler.find_field_id(b"a"));
+        assert_eq!(cached.get_script_id(b"scr_test"), Some(0));
+        cached.use_precompiled(precompiled);
+        let program = cached.compile(b"a = scr_test(b)").unwrap();
+        assert!(Rc::ptr_eq(&program, &cached.compile(b"a = scr_test(b)").unwrap()));
+        // and a different game doesn't get it
+        assert!(load_from(&dir, Key::of(b"other game")).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keys_are_stable() {
+        // FNV-1a of the empty string and of "a", which any build has to agree on
+        assert_eq!(Key::of(b""), Key(0xcbf29ce484222325));
+        assert_eq!(Key::of(b"a"), Key(0xaf63dc4c8601ec8c));
+    }
+
+    #[test]
+    fn only_launch_code_is_cached() {
+        let dir = test_dir("scope");
+        let key = Key::of(b"game");
+        let (mut compiler, precompiled) = compiled();
+        save_to(&dir, key, &compiler, &precompiled).unwrap();
+        // like an object event running for the first time, after the cache was saved
+        compiler.compile(b"c = a").unwrap();
+
+        // so it isn't in there, and compiling it again gives it the same field IDs as it got without the cache
+        let (mut cached, precompiled) = load_from(&dir, key).unwrap();
+        cached.use_precompiled(precompiled);
+        assert_eq!(cached.find_field_id(b"c"), None);
+        cached.compile(b"c = a").unwrap();
+        assert_eq!(cached.find_field_id(b"c"), compiler.find_field_id(b"c"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn outdated_or_broken_caches_get_rebuilt() {
+        let dir = test_dir("rebuild");
+        let key = Key::of(b"game");
+        let (compiler, precompiled) = compiled();
+        fs::create_dir_all(&dir).unwrap();
+
+        // made by some other build of the emulator
+        let mut old = bincode::serialize(&("0.0.0 None None", key.0)).unwrap();
+        old.extend(bincode::serialize(&(&compiler, &precompiled)).unwrap());
+        fs::write(key.path(&dir), old).unwrap();
+        assert!(load_from(&dir, key).is_none());
+        save_to(&dir, key, &compiler, &precompiled).unwrap();
+        assert!(load_from(&dir, key).is_some());
+
+        // cut off partway through, or just garbage
+        let whole = fs::read(key.path(&dir)).unwrap();
+        fs::write(key.path(&dir), &whole[..whole.len() / 2]).unwrap();
+        assert!(load_from(&dir, key).is_none());
+        fs::write(key.path(&dir), b"not a cache file").unwrap();
+        assert!(load_from(&dir, key).is_none());
+        save_to(&dir, key, &compiler, &precompiled).unwrap();
+        assert!(load_from(&dir, key).is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn old_caches_are_evicted() {
+        let dir = test_dir("evict");
+        let (compiler, precompiled) = compiled();
+        for i in 0..MAX_FILES as u64 + 5 {
+            save_to(&dir, Key(i), &compiler, &precompiled).unwrap();
+        }
+        let count = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(count, MAX_FILES);
+        assert!(load_from(&dir, Key(MAX_FILES as u64 + 4)).is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
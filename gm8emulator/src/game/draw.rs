@@ -1,7 +1,7 @@
 use crate::{
     asset::{self, font, Font},
     game::{string::RCStr, Game, GetAsset, Version},
-    gml,
+    gml::{self, profiler::Entry},
     math::Real,
 };
 use serde::{Deserialize, Serialize};
@@ -117,6 +117,10 @@ impl Game {
     /// Draws all instances, tiles and backgrounds to the screen, taking all active views into account.
     /// Note that this function runs GML code associated with object draw events, so its usage must match GameMaker 8.
     pub fn draw(&mut self) -> gml::Result<()> {
+        self.profiled(Entry::Draw, Self::draw_views)
+    }
+
+    fn draw_views(&mut self) -> gml::Result<()> {
         // Update views that should be following objects
         if self.room.views_enabled {
             self.renderer.clear_view(self.background_colour, 1.0);
@@ -0,0 +1,255 @@
+//! GM8's error message, with its Ignore and Abort buttons. There's no native message box to borrow on every
+//! platform, so it's drawn over the last frame inside the game window instead, and taken away again afterwards.
+
+use crate::{
+    game::{draw, string::RCStr, Game},
+    gml,
+};
+use gmio::{
+    render::{BlendType, Scaling},
+    window::Event,
+};
+use shared::input::{Key, MouseButton};
+use std::time::Duration;
+
+/// What the player picked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Choice {
+    Ignore,
+    Abort,
+}
+
+const MARGIN: i32 = 16;
+const PADDING: i32 = 12;
+const BUTTON_WIDTH: i32 = 80;
+const BUTTON_HEIGHT: i32 = 24;
+
+#[rustfmt::skip]
+const IDENTITY: [f32; 16] = [
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 1.0, 0.0,
+    0.0, 0.0, 0.0, 1.0,
+];
+
+// x1, y1, x2, y2, all inclusive
+type Rect = (i32, i32, i32, i32);
+
+// where everything goes on the frame
+struct Layout {
+    panel: Rect,
+    text: (i32, i32),
+    ignore: Rect,
+    abort: Rect,
+}
+
+impl Layout {
+    fn new(frame_width: i32, frame_height: i32, text_height: i32) -> Self {
+        let width = (frame_width - MARGIN * 2).max(BUTTON_WIDTH * 2 + PADDING * 3);
+        let height = text_height + BUTTON_HEIGHT + PADDING * 3;
+        let (x1, y1) = ((frame_width - width) / 2, ((frame_height - height) / 2).max(0));
+        let (x2, y2) = (x1 + width - 1, y1 + height - 1);
+        let (button_y1, button_y2) = (y2 - PADDING - BUTTON_HEIGHT + 1, y2 - PADDING);
+        let abort_x2 = x2 - PADDING;
+        let ignore_x2 = abort_x2 - BUTTON_WIDTH - PADDING;
+        Self {
+            panel: (x1, y1, x2, y2),
+            text: (x1 + PADDING, y1 + PADDING),
+            ignore: (ignore_x2 - BUTTON_WIDTH + 1, button_y1, ignore_x2, button_y2),
+            abort: (abort_x2 - BUTTON_WIDTH + 1, button_y1, abort_x2, button_y2),
+        }
+    }
+
+    fn text_width(frame_width: i32) -> i32 {
+        (frame_width - MARGIN * 2).max(BUTTON_WIDTH * 2 + PADDING * 3) - PADDING * 2
+    }
+
+    fn choice_at(&self, x: i32, y: i32) -> Option<Choice> {
+        let inside = |(x1, y1, x2, y2): Rect| x >= x1 && x <= x2 && y >= y1 && y <= y2;
+        if inside(self.ignore) {
+            Some(Choice::Ignore)
+        } else if inside(self.abort) {
+            Some(Choice::Abort)
+        } else {
+            None
+        }
+    }
+}
+
+fn key_choice(key: Key) -> Option<Choice> {
+    match key {
+        Key::I | Key::Enter => Some(Choice::Ignore),
+        Key::A | Key::Escape => Some(Choice::Abort),
+        _ => None,
+    }
+}
+
+/// Maps a point in the window to the frame, undoing however present() scaled the frame to fit.
+fn window_to_frame(x: i32, y: i32, window: (u32, u32), frame: (u32, u32), scaling: Scaling) -> (i32, i32) {
+    let (window_width, window_height) = (window.0 as i32, window.1 as i32);
+    let (frame_width, frame_height) = (frame.0 as i32, frame.1 as i32);
+    let (left, top, width, height) = match scaling {
+        Scaling::Fixed(scale) => {
+            let w = (f64::from(frame_width) * scale) as i32;
+            let h = (f64::from(frame_height) * scale) as i32;
+            ((window_width - w) / 2, (window_height - h) / 2, w, h)
+        },
+        Scaling::Aspect(_) if frame_width > 0 && frame_height > 0 => {
+            let fixed_width = window_height * frame_width / frame_height;
+            if fixed_width < window_width {
+                ((window_width - fixed_width) / 2, 0, fixed_width, window_height)
+            } else {
+                let fixed_height = window_width * frame_height / frame_width;
+                (0, (window_height - fixed_height) / 2, window_width, fixed_height)
+            }
+        },
+        Scaling::Aspect(_) => (0, 0, frame_width, frame_height),
+        Scaling::Full => (0, 0, window_width, window_height),
+    };
+    if width <= 0 || height <= 0 {
+        return (x, y)
+    }
+    ((x - left) * frame_width / width, (y - top) * frame_height / height)
+}
+
+impl Game {
+    /// Shows an error and waits for the player to pick Ignore or Abort, then puts the frame back how it was.
+    /// Returns None if there's no window, so nobody to ask.
+    pub fn error_dialog(&mut self, message: &str) -> Option<Choice> {
+        if self.window.is_headless() {
+            return None
+        }
+        let snapshot = self.renderer.snapshot();
+        let draw_settings = (self.draw_font_id, self.draw_halign, self.draw_valign);
+        self.draw_font_id = -1;
+        self.draw_halign = draw::Halign::Left;
+        self.draw_valign = draw::Valign::Top;
+
+        let frame = (self.unscaled_width, self.unscaled_height);
+        let (frame_width, frame_height) = (frame.0 as i32, frame.1 as i32);
+        let message = RCStr::from(message);
+        let text_width = Layout::text_width(frame_width);
+        let text_height = self.get_string_size(message.clone(), None, Some(text_width)).1;
+        let layout = Layout::new(frame_width, frame_height, text_height);
+
+        self.renderer.reset_target();
+        self.renderer.set_3d(false);
+        self.renderer.set_fog(None);
+        self.renderer.set_lighting_enabled(false);
+        self.renderer.set_alpha_blending(true);
+        self.renderer.set_blend_mode(BlendType::SrcAlpha, BlendType::InvSrcAlpha);
+        self.renderer.set_model_matrix(IDENTITY);
+        self.renderer.set_view(0, 0, frame_width, frame_height, 0.0, 0, 0, frame_width, frame_height);
+
+        let rect = |game: &mut Self, (x1, y1, x2, y2): Rect, colour| {
+            game.renderer.draw_rectangle(x1.into(), y1.into(), x2.into(), y2.into(), colour, 1.0);
+            game.renderer.draw_rectangle_outline(x1.into(), y1.into(), x2.into(), y2.into(), 0, 1.0);
+        };
+        let text = |game: &mut Self, x: i32, y: i32, string, max_width| {
+            game.draw_string(
+                x.into(),
+                y.into(),
+                string,
+                None,
+                max_width,
+                1.0.into(),
+                1.0.into(),
+                0.0.into(),
+                Some((0, 0, 0, 0)),
+                1.0.into(),
+            );
+        };
+        rect(self, layout.panel, 0xc0c0c0);
+        text(self, layout.text.0, layout.text.1, message, Some(text_width));
+        self.draw_halign = draw::Halign::Middle;
+        self.draw_valign = draw::Valign::Middle;
+        for (button, label) in [(layout.ignore, "Ignore"), (layout.abort, "Abort")].iter().copied() {
+            rect(self, button, 0xe0e0e0);
+            text(self, (button.0 + button.2) / 2, (button.1 + button.3) / 2, label.into(), None);
+        }
+
+        let (x, y) = self.input_manager.mouse_get_location();
+        let mut mouse = (x as i32, y as i32);
+        let choice = loop {
+            let (width, height) = self.window.get_inner_size();
+            self.renderer.present(width, height, self.scaling);
+            let mut choice = None;
+            for event in self.window.process_events().copied() {
+                match event {
+                    Event::KeyboardDown(key) => choice = choice.or(key_choice(key)),
+                    Event::MouseMove(x, y) => mouse = (x, y),
+                    Event::MouseButtonDown(MouseButton::Left) => {
+                        let (x, y) = window_to_frame(mouse.0, mouse.1, (width, height), frame, self.scaling);
+                        choice = choice.or(layout.choice_at(x, y));
+                    },
+                    _ => (),
+                }
+            }
+            if self.window.close_requested() {
+                break Choice::Abort
+            }
+            if let Some(choice) = choice {
+                break choice
+            }
+            gml::datetime::sleep(Duration::from_millis(16));
+        };
+
+        self.renderer.restore(snapshot);
+        let (font, halign, valign) = draw_settings;
+        self.draw_font_id = font;
+        self.draw_halign = halign;
+        self.draw_valign = valign;
+        Some(choice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_game;
+
+    #[test]
+    fn buttons_sit_inside_the_panel() {
+        let layout = Layout::new(640, 480, 100);
+        let (x1, y1, x2, y2) = layout.panel;
+        assert_eq!((x2 - x1 + 1, y2 - y1 + 1), (640 - MARGIN * 2, 100 + BUTTON_HEIGHT + PADDING * 3));
+        for &(bx1, by1, bx2, by2) in &[layout.ignore, layout.abort] {
+            assert!(bx1 > x1 && by1 > y1 + 100 && bx2 < x2 && by2 < y2);
+        }
+        assert_eq!(layout.choice_at(layout.ignore.0, layout.ignore.1), Some(Choice::Ignore));
+        assert_eq!(layout.choice_at(layout.abort.2, layout.abort.3), Some(Choice::Abort));
+        assert_eq!(layout.choice_at(layout.abort.0 - 1, layout.abort.1), None);
+        assert_eq!(layout.choice_at(layout.text.0, layout.text.1), None);
+
+        // tiny frames still get both buttons
+        let layout = Layout::new(50, 50, 10);
+        assert!(layout.ignore.2 < layout.abort.0);
+    }
+
+    #[test]
+    fn clicks_are_scaled_back_to_the_frame() {
+        let frame = (320, 240);
+        assert_eq!(window_to_frame(200, 100, (320, 240), frame, Scaling::Fixed(1.0)), (200, 100));
+        assert_eq!(window_to_frame(200, 100, (640, 480), frame, Scaling::Fixed(2.0)), (100, 50));
+        // a fixed size frame in a bigger window sits in the middle
+        assert_eq!(window_to_frame(200, 100, (400, 300), frame, Scaling::Fixed(1.0)), (160, 70));
+        assert_eq!(window_to_frame(320, 240, (640, 240), frame, Scaling::Full), (160, 240));
+        // the window's too wide for the aspect ratio, so there are bars on the left and right
+        assert_eq!(window_to_frame(480, 240, (960, 480), frame, Scaling::Aspect(1.0)), (160, 120));
+        assert_eq!(window_to_frame(160, 0, (960, 480), frame, Scaling::Aspect(1.0)), (0, 0));
+    }
+
+    #[test]
+    fn keys_pick_a_button() {
+        assert_eq!(key_choice(Key::Enter), Some(Choice::Ignore));
+        assert_eq!(key_choice(Key::I), Some(Choice::Ignore));
+        assert_eq!(key_choice(Key::Escape), Some(Choice::Abort));
+        assert_eq!(key_choice(Key::A), Some(Choice::Abort));
+        assert_eq!(key_choice(Key::Space), None);
+    }
+
+    #[test]
+    fn nobody_to_ask_without_a_window() {
+        assert_eq!(test_game().error_dialog("oops"), None);
+    }
+}
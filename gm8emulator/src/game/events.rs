@@ -1,7 +1,7 @@
 use crate::{
     asset::trigger::TriggerTime,
     game::{Game, GetAsset},
-    gml,
+    gml::{self, trace},
     instance::Instance,
 };
 use shared::{input::MouseButton, types::ID};
@@ -67,7 +67,11 @@ impl Game {
                 }
             };
 
-            self.execute_tree(event, instance, other, event_id, event_sub as _, object_id)
+            let frame = trace::Frame::Event { object: object_id, event_type: event_id, event_number: event_sub as _ };
+            self.with_frame(frame, |game| {
+                game.debug_event(object_id, event_id, event_sub as _, instance);
+                game.execute_tree(event, instance, other, event_id, event_sub as _, object_id)
+            })
         } else {
             Ok(())
         }
@@ -115,7 +119,12 @@ impl Game {
                             context.event_type = 11; // ev_trigger
                             context.event_number = trigger_id as _;
                             context.event_object = self.room.instance_list.get(handle).object_index.get();
-                            self.execute(&trigger.condition, &mut context)?;
+                            let frame = trace::Frame::Event {
+                                object: context.event_object,
+                                event_type: gml::ev::TRIGGER,
+                                event_number: trigger_id as _,
+                            };
+                            self.with_frame_caught(frame, |game| game.execute(&trigger.condition, &mut context))?;
                             if context.return_value.is_truthy() {
                                 self.run_instance_event(gml::ev::TRIGGER, trigger_id, handle, handle, None)?;
                             }
@@ -1,6 +1,8 @@
 mod dummy;
+mod hle;
 mod win32;
 mod win64;
+mod x86;
 
 use crate::{
     game::string::RCStr,
@@ -10,7 +12,9 @@ use cfg_if::cfg_if;
 use encoding_rs::Encoding;
 use serde::{Deserialize, Serialize};
 use shared::dll;
+use std::cell::Cell;
 
+pub use hle::Context;
 pub use shared::dll::{CallConv, ValueType as DLLValueType};
 
 cfg_if! {
@@ -25,8 +29,9 @@ cfg_if! {
 }
 
 pub enum Call {
-    DummyNull(dll::ValueType),
-    DummyOne,
+    Native(hle::Function),
+    Emulated(u32),           // address of the function in the x86 emulator
+    Unavailable(Cell<bool>), // whether a call to it has been logged yet
     DllCall(platform::ExternalImpl),
 }
 
@@ -39,6 +44,13 @@ pub struct DefineInfo {
     pub arg_types: Vec<dll::ValueType>,
 }
 
+/// Everything external functions keep track of between calls, which gets saved in savestates.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct State {
+    hle: hle::State,
+    x86: x86::Emulator,
+}
+
 pub struct External {
     call: Call,
     pub info: DefineInfo,
@@ -58,58 +70,201 @@ Spec required for ExternalImpl {
  */
 
 impl External {
-    pub fn new(info: DefineInfo, disable_sound: bool, encoding: &'static Encoding) -> Result<Self, String> {
+    /// `disable_sound` is for recording and replaying, when sound libraries are always done natively so nothing
+    /// depends on the real ones.
+    pub fn new(
+        info: DefineInfo,
+        disable_sound: bool,
+        encoding: &'static Encoding,
+        state: &mut State,
+        context: &Context,
+    ) -> Result<Self, String> {
         if info.arg_types.len() > 4 && info.arg_types.contains(&dll::ValueType::Str) {
             return Err("DLL functions with more than 4 arguments cannot have string arguments".into())
         }
         if info.arg_types.len() >= 16 {
             return Err("DLL functions can have at most 16 arguments".into())
         }
-        let mut dll_name_lower = std::path::Path::new(info.dll_name.decode(encoding).as_ref())
-            .file_name()
-            .unwrap()
-            .to_string_lossy()
-            .to_string();
-        dll_name_lower.make_ascii_lowercase();
-        let call = match dll_name_lower.as_str() {
-            "gmfmodsimple.dll" if disable_sound && info.fn_name.as_ref() == b"FMODSoundAdd" => Call::DummyOne,
-            "gmfmodsimple.dll" | "ssound.dll" | "supersound.dll" | "sxms-3.dll" if disable_sound => {
-                Call::DummyNull(info.res_type)
+        // games use backslashes, which aren't separators everywhere
+        let dll_path = info.dll_name.decode(encoding);
+        let dll_name_lower = dll_path.rsplit(&['/', '\\'][..]).next().unwrap().to_ascii_lowercase();
+        let library = hle::find_library(&dll_name_lower);
+        let plays_sound = matches!(library, Some(library) if library.plays_sound);
+        let native = library.and_then(|library| library.get(info.fn_name.as_ref()));
+        let call = match native {
+            // these behave the same everywhere, except real sound libraries are still used in normal play
+            Some(function) if disable_sound || !plays_sound => Call::Native(function),
+            None if disable_sound && plays_sound => unavailable(&info, "not implemented natively"),
+            _ => match (platform::ExternalImpl::new(&info, encoding), native) {
+                (Ok(external), _) => Call::DllCall(external),
+                // a sound library that can't be loaded can at least keep time
+                (Err(_), Some(function)) => Call::Native(function),
+                (Err(e), None) => {
+                    // the platform can't load it, so try emulating it
+                    let (fn_name, vfs, paths) = (info.fn_name.as_ref(), context.vfs, context.path_resolver);
+                    match state.x86.find_function(&dll_path, fn_name, vfs, paths) {
+                        Ok(address) => Call::Emulated(address),
+                        Err(x86_error) => unavailable(&info, &format!("{}; emulation failed: {}", e, x86_error)),
+                    }
+                },
             },
-            "gmeffect_0.1.dll" => Call::DummyNull(info.res_type), // TODO don't
-            _ => Call::DllCall(platform::ExternalImpl::new(&info, encoding)?),
         };
         Ok(Self { call, info })
     }
 
-    pub fn call(&self, args: &[Value]) -> gml::Result<Value> {
+    pub fn call(&self, state: &mut State, context: &Context, args: &[Value]) -> gml::Result<Value> {
         if args.len() != self.info.arg_types.len() {
             eprintln!(
                 "Warning: call to external function {} from {} with an invalid argument count was ignored",
                 self.info.fn_name, self.info.dll_name
             );
-            Ok(Default::default())
-        } else {
-            let args = args.iter().zip(&self.info.arg_types).map(|(v, t)| match t {
-                dll::ValueType::Real => f64::from(v.clone()).into(),
-                dll::ValueType::Str => RCStr::from(v.clone()).into(),
-            });
-            self.call.call(args)
+            return Ok(Default::default())
         }
-    }
-}
-
-impl Call {
-    fn call(&self, args: impl Iterator<Item = Value>) -> gml::Result<Value> {
-        match self {
-            Call::DummyNull(res_type) => match res_type {
-                dll::ValueType::Real => Ok(0.into()),
-                dll::ValueType::Str => Ok("".into()),
+        let args = args.iter().zip(&self.info.arg_types).map(|(v, t)| match t {
+            dll::ValueType::Real => f64::from(v.clone()).into(),
+            dll::ValueType::Str => RCStr::from(v.clone()).into(),
+        });
+        match &self.call {
+            Call::Native(function) => {
+                let args = args.collect::<Vec<_>>();
+                Ok(hle::convert_result(function(&mut state.hle, context, &args), self.info.res_type))
+            },
+            Call::Emulated(address) => {
+                let args = args.map(dll::Value::from).collect::<Vec<_>>();
+                state
+                    .x86
+                    .call(*address, &args, self.info.res_type)
+                    .map(Value::from)
+                    .map_err(|e| gml::Error::FunctionError("external_call".into(), e))
+            },
+            Call::Unavailable(logged) => {
+                if !logged.replace(true) {
+                    let args = args.map(|v| v.to_string()).collect::<Vec<_>>();
+                    eprintln!(
+                        "Warning: call to unavailable external function {} from {} with arguments ({}) was ignored",
+                        self.info.fn_name,
+                        self.info.dll_name,
+                        args.join(", ")
+                    );
+                }
+                Ok(match self.info.res_type {
+                    dll::ValueType::Real => 0.into(),
+                    dll::ValueType::Str => "".into(),
+                })
             },
-            Call::DummyOne => Ok(1.into()),
             Call::DllCall(call) => {
                 call.call(args).map_err(|e| gml::Error::FunctionError("external_call".into(), e.into()))
             },
         }
     }
 }
+
+fn unavailable(info: &DefineInfo, reason: &str) -> Call {
+    eprintln!(
+        "Warning: external function {} from {} is unavailable ({}), calls to it will be ignored",
+        info.fn_name, info.dll_name, reason
+    );
+    Call::Unavailable(Cell::new(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gml::file::{PathResolver, Vfs};
+
+    fn define(dll_name: &str, fn_name: &str, arg_types: &[dll::ValueType], res_type: dll::ValueType) -> External {
+        define_in(true, dll_name, fn_name, arg_types, res_type)
+    }
+
+    fn define_in(
+        disable_sound: bool,
+        dll_name: &str,
+        fn_name: &str,
+        arg_types: &[dll::ValueType],
+        res_type: dll::ValueType,
+    ) -> External {
+        let info = DefineInfo {
+            dll_name: dll_name.into(),
+            fn_name: fn_name.into(),
+            call_conv: CallConv::Stdcall,
+            res_type,
+            arg_types: arg_types.to_vec(),
+        };
+        let (vfs, path_resolver) = (Vfs::new(false), PathResolver::default());
+        let context = Context { vfs: &vfs, path_resolver: &path_resolver, now: 0 };
+        External::new(info, disable_sound, encoding_rs::UTF_8, &mut State::default(), &context).unwrap()
+    }
+
+    fn call(external: &External, args: &[Value]) -> Value {
+        let (vfs, path_resolver) = (Vfs::new(false), PathResolver::default());
+        let context = Context { vfs: &vfs, path_resolver: &path_resolver, now: 0 };
+        external.call(&mut State::default(), &context, args).unwrap()
+    }
+
+    #[test]
+    fn native_libraries_come_first() {
+        // whatever folder it's in and however it's capitalised
+        let init = define("C:\\Game\\GMFMODSimple.DLL", "FMODinit", &[], dll::ValueType::Str);
+        assert!(matches!(init.call, Call::Native(_)));
+        assert_eq!(call(&init, &[]).to_string(), "\"1\""); // as the string it was defined to return
+
+        // sound libraries are never loaded for real when recording or replaying, even for functions they don't have
+        let missing = define("gmfmodsimple.dll", "FMODSpectrum", &[], dll::ValueType::Real);
+        assert!(matches!(missing.call, Call::Unavailable(_)));
+    }
+
+    #[test]
+    fn real_libraries_do_the_rest() {
+        // the rest of a system library is whatever the platform has
+        let sleep = define("kernel32.dll", "Sleep", &[dll::ValueType::Real], dll::ValueType::Real);
+        assert!(matches!(sleep.call, Call::Native(_)));
+        let tick_count = define("kernel32.dll", "GetTickCount", &[], dll::ValueType::Real);
+        assert!(!matches!(tick_count.call, Call::Native(_)));
+
+        // in normal play, sound libraries are only done natively when the real one can't be loaded
+        let init = define_in(false, "gmfmodsimple.dll", "FMODinit", &[], dll::ValueType::Str);
+        assert!(matches!(init.call, Call::Native(_)));
+        let missing = define_in(false, "sxms-3.dll", "SXMS_Play", &[], dll::ValueType::Real);
+        assert!(!matches!(missing.call, Call::Native(_)));
+    }
+
+    #[test]
+    fn unavailable_functions_do_nothing() {
+        let real = define("nowhere.dll", "Thing", &[dll::ValueType::Real, dll::ValueType::Str], dll::ValueType::Real);
+        let string = define("nowhere.dll", "Thing", &[], dll::ValueType::Str);
+        let logged = |external: &External| match &external.call {
+            Call::Unavailable(logged) => logged.get(),
+            _ => panic!("{} shouldn't be available", external.info.fn_name),
+        };
+        assert!(!logged(&real));
+
+        // calls are only logged the first time, but always return nothing
+        assert!(matches!(call(&real, &[1.into(), "a".into()]), Value::Real(x) if x.into_inner() == 0.0));
+        assert!(logged(&real));
+        assert!(matches!(call(&real, &[2.into(), "b".into()]), Value::Real(x) if x.into_inner() == 0.0));
+        assert!(matches!(call(&string, &[]), Value::Str(s) if s.as_ref().is_empty()));
+
+        // and calls with the wrong number of arguments are ignored before they get that far
+        let string = define("nowhere.dll", "Thing", &[], dll::ValueType::Str);
+        call(&string, &[1.into()]);
+        assert!(!logged(&string));
+    }
+
+    #[test]
+    fn argument_limits() {
+        let info = |arg_types: Vec<dll::ValueType>| DefineInfo {
+            dll_name: "kernel32.dll".into(),
+            fn_name: "Sleep".into(),
+            call_conv: CallConv::Stdcall,
+            res_type: dll::ValueType::Real,
+            arg_types,
+        };
+        let (vfs, path_resolver) = (Vfs::new(false), PathResolver::default());
+        let context = Context { vfs: &vfs, path_resolver: &path_resolver, now: 0 };
+        let new =
+            |arg_types| External::new(info(arg_types), true, encoding_rs::UTF_8, &mut State::default(), &context);
+        assert!(new(vec![dll::ValueType::Str; 5]).is_err());
+        assert!(new(vec![dll::ValueType::Real; 16]).is_err());
+        assert!(new(vec![dll::ValueType::Real; 15]).is_ok());
+    }
+}
@@ -0,0 +1,186 @@
+//! Native implementations of popular extension DLLs, so they behave the same on every platform.
+
+mod dll39;
+mod fmod;
+mod supersound;
+mod system;
+
+use crate::{
+    game::string::RCStr,
+    gml::{
+        audio,
+        file::{PathResolver, Vfs},
+        Value,
+    },
+};
+use serde::{Deserialize, Serialize};
+use shared::dll;
+
+pub type Function = fn(&mut State, &Context, &[Value]) -> Value;
+
+/// What the native libraries can get at outside their own state.
+pub struct Context<'a> {
+    pub vfs: &'a Vfs,
+    pub path_resolver: &'a PathResolver,
+    pub now: u128, // game clock in nanoseconds
+}
+
+pub struct Library {
+    functions: &'static [(&'static str, Function)],
+
+    /// Whether the real library makes noise, so it should be used instead of this one in normal play.
+    pub plays_sound: bool,
+}
+
+impl Library {
+    pub fn get(&self, fn_name: &[u8]) -> Option<Function> {
+        // exports are case-sensitive, so this is too
+        self.functions.iter().find(|(name, _)| name.as_bytes() == fn_name).map(|(_, f)| *f)
+    }
+}
+
+/// DLL file names must be lowercase in here.
+static LIBRARIES: &[(&str, Library)] = &[
+    ("39dll.dll", dll39::LIBRARY),
+    ("gmfmodsimple.dll", fmod::LIBRARY),
+    ("kernel32.dll", system::KERNEL32),
+    ("ssound.dll", supersound::LIBRARY),
+    ("supersound.dll", supersound::LIBRARY),
+    ("user32.dll", system::USER32),
+    ("winmm.dll", system::WINMM),
+];
+
+/// Looks up a library by its lowercase file name.
+pub fn find_library(dll_name: &str) -> Option<&'static Library> {
+    LIBRARIES.iter().find(|(name, _)| *name == dll_name).map(|(_, lib)| lib)
+}
+
+/// Everything the native libraries keep track of between calls. This is saved in savestates.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct State {
+    dll39: dll39::State,
+    fmod: fmod::State,
+    supersound: supersound::State,
+}
+
+/// Converts a native function's result to what the game asked for.
+pub fn convert_result(value: Value, res_type: dll::ValueType) -> Value {
+    match (value, res_type) {
+        (Value::Real(x), dll::ValueType::Str) => x.to_string().into(),
+        (Value::Str(s), dll::ValueType::Real) => parse_real(&s).into(),
+        (value, _) => value,
+    }
+}
+
+fn parse_real(s: &RCStr) -> f64 {
+    std::str::from_utf8(s.as_ref()).ok().and_then(|s| s.trim().parse().ok()).unwrap_or(0.0)
+}
+
+/// Gets a real argument, parsing it if it got passed as a string (some DLLs use strings for handles).
+fn real_arg(args: &[Value], index: usize) -> f64 {
+    match args.get(index) {
+        Some(Value::Real(x)) => x.into_inner(),
+        Some(Value::Str(s)) => parse_real(s),
+        None => 0.0,
+    }
+}
+
+/// Gets a string argument, or an empty string if it got passed as a real.
+fn str_arg(args: &[Value], index: usize) -> &[u8] {
+    args.get(index).map_or(b"", <&[u8]>::from)
+}
+
+/// Works out how long the sound file a game asked for is in milliseconds, or None if it can't be read.
+/// Formats that don't say how long they are come out as 0.
+fn sound_length(context: &Context, name: &[u8]) -> Option<u64> {
+    let path = context.path_resolver.resolve(&String::from_utf8_lossy(name));
+    let data = context.vfs.read(&path).ok()?;
+    Some(audio::length(&data).unwrap_or(0))
+}
+
+/// Gets a handle argument, where handles start at 1 so that 0 can mean failure.
+fn handle_arg(args: &[Value], index: usize) -> Option<usize> {
+    let handle = real_arg(args, index).round();
+    if handle >= 1.0 { Some(handle as usize - 1) } else { None }
+}
+
+/// Puts an item into the first free slot, returning its handle.
+fn insert_handle<T>(list: &mut Vec<Option<T>>, item: T) -> usize {
+    match list.iter().position(Option::is_none) {
+        Some(index) => {
+            list[index] = Some(item);
+            index + 1
+        },
+        None => {
+            list.push(Some(item));
+            list.len()
+        },
+    }
+}
+
+fn get_handle<T>(list: &mut [Option<T>], handle: Option<usize>) -> Option<&mut T> {
+    list.get_mut(handle?)?.as_mut()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(super) const MS: u128 = 1_000_000;
+
+    /// Calls a function from a library at some point on the game clock, in milliseconds.
+    pub(super) fn call(library: &Library, hle: &mut State, function: &str, now: u128, args: &[Value]) -> Value {
+        let (vfs, path_resolver) = (Vfs::new(false), PathResolver::default());
+        let context = Context { vfs: &vfs, path_resolver: &path_resolver, now: now * MS };
+        library.get(function.as_bytes()).unwrap()(hle, &context, args)
+    }
+
+    /// Values can't be compared, so this shows reals as they are and strings in quotes.
+    pub(super) fn show(value: Value) -> String {
+        match value {
+            Value::Real(x) => x.into_inner().to_string(),
+            Value::Str(s) => format!("{:?}", String::from_utf8_lossy(s.as_ref())),
+        }
+    }
+
+    /// Writes a .wav file that's the given number of milliseconds long, and gives back its path.
+    pub(super) fn sound_file(name: &str, length: u32) -> String {
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        wav.extend(&16u32.to_le_bytes());
+        wav.extend(&[1, 0, 1, 0, 0xe8, 0x03, 0, 0, 0xe8, 0x03, 0, 0, 1, 0, 8, 0]); // 1000 bytes a second
+        wav.extend(b"data");
+        wav.extend(&length.to_le_bytes());
+        wav.extend(vec![0; length as usize]);
+        let path = std::env::temp_dir().join(format!("gm8emulator-test-{}-{}.wav", name, std::process::id()));
+        std::fs::write(&path, wav).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn results_are_converted() {
+        assert_eq!(show(convert_result(1.5.into(), dll::ValueType::Str)), "\"1.5\"");
+        assert_eq!(show(convert_result(" 42 ".into(), dll::ValueType::Real)), "42");
+        assert_eq!(show(convert_result("nope".into(), dll::ValueType::Real)), "0");
+        assert_eq!(show(convert_result("as is".into(), dll::ValueType::Str)), "\"as is\"");
+    }
+
+    #[test]
+    fn handles_reuse_free_slots() {
+        let mut list = Vec::new();
+        assert_eq!((insert_handle(&mut list, 'a'), insert_handle(&mut list, 'b')), (1, 2));
+        list[0] = None;
+        assert_eq!(insert_handle(&mut list, 'c'), 1);
+        assert_eq!(get_handle(&mut list, handle_arg(&["2".into()], 0)), Some(&mut 'b'));
+        assert_eq!(handle_arg(&[0.into()], 0), None);
+        assert_eq!(handle_arg(&[], 0), None);
+        assert_eq!(get_handle(&mut list, Some(5)), None);
+    }
+
+    #[test]
+    fn libraries_are_found_by_lowercase_name() {
+        assert!(find_library("gmfmodsimple.dll").unwrap().get(b"FMODSoundAdd").is_some());
+        assert!(find_library("gmfmodsimple.dll").unwrap().get(b"fmodsoundadd").is_none());
+        assert!(find_library("GMFMODSimple.dll").is_none());
+        assert!(find_library("somethingelse.dll").is_none());
+    }
+}
@@ -0,0 +1,920 @@
+//! 39dll, which most GM8 online games use for networking.
+//! The sockets are real, so games can talk to each other (or themselves) over loopback,
+//! but they can't go in savestates: after loading one, every socket is closed and calls on them fail.
+
+use super::{get_handle, handle_arg, insert_handle, real_arg, str_arg, Context, Library, State as HleState};
+use crate::gml::{network, Value};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket},
+};
+
+pub const LIBRARY: Library = Library {
+    functions: &[
+        // sockets
+        ("tcpconnect", tcp_connect),
+        ("tcplisten", tcp_listen),
+        ("tcpaccept", tcp_accept),
+        ("tcpip", tcp_ip),
+        ("tcpconnected", tcp_connected),
+        ("udpconnect", udp_connect),
+        ("sendmessage", send_message),
+        ("receivemessage", receive_message),
+        ("peekmessage", peek_message),
+        ("setformat", set_format),
+        ("setsync", set_sync),
+        ("setnagle", set_nagle),
+        ("closesock", close_sock),
+        ("socklasterror", sock_last_error),
+        ("lastinIP", last_in_ip),
+        ("lastinPort", last_in_port),
+        ("myhost", my_host),
+        ("hostip", host_ip),
+        ("compareip", compare_ip),
+        ("sockstart", sock_start),
+        ("sockexit", sock_exit),
+        // buffers
+        ("writechar", write_byte),
+        ("writebyte", write_byte),
+        ("writeshort", write_short),
+        ("writeushort", write_short),
+        ("writeint", write_int),
+        ("writeuint", write_int),
+        ("writefloat", write_float),
+        ("writedouble", write_double),
+        ("writechars", write_chars),
+        ("writestring", write_string),
+        ("readchar", read_char),
+        ("readbyte", read_byte),
+        ("readshort", read_short),
+        ("readushort", read_ushort),
+        ("readint", read_int),
+        ("readuint", read_uint),
+        ("readfloat", read_float),
+        ("readdouble", read_double),
+        ("readchars", read_chars),
+        ("readstring", read_string),
+        ("getpos", get_pos),
+        ("setpos", set_pos),
+        ("clearbuffer", clear_buffer),
+        ("buffsize", buff_size),
+        ("bytesleft", bytes_left),
+        ("createbuffer", create_buffer),
+        ("freebuffer", free_buffer),
+        ("buffexists", buff_exists),
+        ("copybuffer", copy_buffer),
+        ("copybuffer2", copy_buffer2),
+        ("md5string", md5_string),
+        ("md5buffer", md5_buffer),
+        ("adler32", adler32),
+    ],
+    plays_sound: false,
+};
+
+const SOCKET_ERROR: f64 = -1.0;
+const RECEIVE_SIZE: usize = 8195; // what 39dll reads at once when it isn't told a length
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct State {
+    buffers: Vec<Option<Buffer>>, // buffer 0 always exists, since it's the default for every function
+    #[serde(skip)]
+    sockets: Vec<Option<Socket>>,
+    last_in: Option<SocketAddr>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self { buffers: vec![Some(Buffer::default())], sockets: Vec::new(), last_in: None }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Buffer {
+    data: Vec<u8>,
+    read_pos: usize,
+    write_pos: usize,
+}
+
+impl Buffer {
+    fn write(&mut self, bytes: &[u8]) -> usize {
+        let end = self.write_pos + bytes.len();
+        if self.data.len() < end {
+            self.data.resize(end, 0);
+        }
+        self.data[self.write_pos..end].copy_from_slice(bytes);
+        self.write_pos = end;
+        bytes.len()
+    }
+
+    /// Reads exactly `len` bytes, or nothing if there aren't enough left.
+    fn read(&mut self, len: usize) -> Option<&[u8]> {
+        let start = self.read_pos;
+        let bytes = self.data.get(start..start.checked_add(len)?)?;
+        self.read_pos += len;
+        Some(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> [u8; N] {
+        let mut out = [0; N];
+        if let Some(bytes) = self.read(N) {
+            out.copy_from_slice(bytes);
+        }
+        out
+    }
+
+    fn set(&mut self, data: Vec<u8>) {
+        self.write_pos = data.len();
+        self.read_pos = 0;
+        self.data = data;
+    }
+}
+
+enum Kind {
+    Stream(TcpStream),
+    Listener(TcpListener),
+    Udp(UdpSocket),
+    Closed, // a copy that couldn't be made
+}
+
+#[derive(Clone)]
+enum Format {
+    Binary,        // each message starts with its length as a u16
+    Text(Vec<u8>), // messages end with a separator
+    Raw,
+}
+
+struct Socket {
+    kind: Kind,
+    format: Format,
+    incoming: Vec<u8>, // bytes that have arrived on a stream but aren't a whole message yet
+    last_error: i32,
+}
+
+impl Clone for Socket {
+    // making a savestate clones the state, and the game has to keep its sockets after that
+    fn clone(&self) -> Self {
+        let kind = match &self.kind {
+            Kind::Stream(s) => s.try_clone().map(Kind::Stream),
+            Kind::Listener(l) => l.try_clone().map(Kind::Listener),
+            Kind::Udp(u) => u.try_clone().map(Kind::Udp),
+            Kind::Closed => Ok(Kind::Closed),
+        };
+        Self {
+            kind: kind.unwrap_or(Kind::Closed),
+            format: self.format.clone(),
+            incoming: self.incoming.clone(),
+            last_error: self.last_error,
+        }
+    }
+}
+
+impl Socket {
+    fn new(kind: Kind, mode: f64) -> Self {
+        let mut socket = Self { kind, format: Format::Binary, incoming: Vec::new(), last_error: 0 };
+        socket.set_blocking(mode == 0.0);
+        socket
+    }
+
+    fn set_blocking(&mut self, blocking: bool) {
+        let result = match &self.kind {
+            Kind::Stream(s) => s.set_nonblocking(!blocking),
+            Kind::Listener(l) => l.set_nonblocking(!blocking),
+            Kind::Udp(u) => u.set_nonblocking(!blocking),
+            Kind::Closed => Ok(()),
+        };
+        self.check(result);
+    }
+
+    /// Records the error code from a result, if there is one.
+    fn check<T>(&mut self, result: io::Result<T>) -> Option<T> {
+        result.map_err(|e| self.last_error = wsa_error(&e)).ok()
+    }
+
+    /// Receives a message into `buffer`, returning its size, 0 if the connection closed or SOCKET_ERROR.
+    fn receive(&mut self, len: usize, consume: bool, buffer: &mut Buffer, last_in: &mut Option<SocketAddr>) -> f64 {
+        let Socket { kind, format, incoming, last_error } = self;
+        let mut chunk = vec![0; if len == 0 { RECEIVE_SIZE } else { len }];
+        let result = match kind {
+            Kind::Stream(stream) => loop {
+                if let Some(message) = take_message(format, incoming, len, consume) {
+                    *last_in = stream.peer_addr().ok();
+                    break Ok(message)
+                }
+                match stream.read(&mut chunk) {
+                    Ok(0) => return 0.0,
+                    Ok(n) => incoming.extend_from_slice(&chunk[..n]),
+                    Err(e) => break Err(e),
+                }
+            },
+            Kind::Udp(socket) => {
+                let result = if consume { socket.recv_from(&mut chunk) } else { socket.peek_from(&mut chunk) };
+                result.map(|(n, from)| {
+                    *last_in = Some(from);
+                    chunk.truncate(n);
+                    chunk
+                })
+            },
+            Kind::Listener(_) | Kind::Closed => return SOCKET_ERROR,
+        };
+        match result {
+            Ok(message) => {
+                let size = message.len();
+                buffer.set(message);
+                size as f64
+            },
+            Err(e) => {
+                *last_error = wsa_error(&e);
+                SOCKET_ERROR
+            },
+        }
+    }
+}
+
+/// The Winsock error code for an error, which is what games compare socklasterror against, whatever the host is.
+fn wsa_error(error: &io::Error) -> i32 {
+    if cfg!(target_os = "windows") {
+        if let Some(code) = error.raw_os_error() {
+            return code
+        }
+    }
+    match error.kind() {
+        io::ErrorKind::Interrupted => 10004,       // WSAEINTR
+        io::ErrorKind::PermissionDenied => 10013,  // WSAEACCES
+        io::ErrorKind::InvalidInput => 10022,      // WSAEINVAL
+        io::ErrorKind::WouldBlock => 10035,        // WSAEWOULDBLOCK
+        io::ErrorKind::AddrInUse => 10048,         // WSAEADDRINUSE
+        io::ErrorKind::AddrNotAvailable => 10049,  // WSAEADDRNOTAVAIL
+        io::ErrorKind::ConnectionAborted => 10053, // WSAECONNABORTED
+        io::ErrorKind::ConnectionReset => 10054,   // WSAECONNRESET
+        io::ErrorKind::BrokenPipe => 10054,        // which Windows reports as a reset too
+        io::ErrorKind::NotConnected => 10057,      // WSAENOTCONN
+        io::ErrorKind::TimedOut => 10060,          // WSAETIMEDOUT
+        io::ErrorKind::ConnectionRefused => 10061, // WSAECONNREFUSED
+        io::ErrorKind::NotFound => 11001,          // WSAHOST_NOT_FOUND
+        _ => -1,
+    }
+}
+
+/// Takes a whole message out of what's been received on a stream so far, if there is one.
+fn take_message(format: &Format, incoming: &mut Vec<u8>, len: usize, consume: bool) -> Option<Vec<u8>> {
+    let (start, end, skip) = match format {
+        Format::Binary => {
+            let header = incoming.get(..2)?;
+            let end = 2 + usize::from(u16::from_le_bytes([header[0], header[1]]));
+            if incoming.len() < end {
+                return None
+            }
+            (2, end, end)
+        },
+        Format::Text(sep) => {
+            let end = incoming.windows(sep.len().max(1)).position(|w| w == sep.as_slice())?;
+            (0, end, end + sep.len())
+        },
+        Format::Raw if incoming.is_empty() => return None,
+        Format::Raw => {
+            let end = if len == 0 { incoming.len() } else { len.min(incoming.len()) };
+            (0, end, end)
+        },
+    };
+    let message = incoming[start..end].to_vec();
+    if consume {
+        incoming.drain(..skip);
+    }
+    Some(message)
+}
+
+fn buffer<'a>(hle: &'a mut HleState, args: &[Value], index: usize) -> Option<&'a mut Buffer> {
+    let id = real_arg(args, index);
+    if id < 0.0 {
+        return None
+    }
+    hle.dll39.buffers.get_mut(id as usize)?.as_mut()
+}
+
+/// Runs `f` on the buffer in argument `index`, returning 0 if it doesn't exist.
+fn with_buffer<T: Into<Value>>(
+    hle: &mut HleState,
+    args: &[Value],
+    index: usize,
+    f: impl FnOnce(&mut Buffer) -> T,
+) -> Value {
+    match buffer(hle, args, index) {
+        Some(buffer) => f(buffer).into(),
+        None => 0.into(),
+    }
+}
+
+fn socket<'a>(hle: &'a mut HleState, args: &[Value]) -> Option<&'a mut Socket> {
+    get_handle(&mut hle.dll39.sockets, handle_arg(args, 0))
+}
+
+fn add_socket(hle: &mut HleState, kind: io::Result<Kind>, mode: f64) -> Value {
+    match kind {
+        Ok(kind) => insert_handle(&mut hle.dll39.sockets, Socket::new(kind, mode)).into(),
+        Err(_) => SOCKET_ERROR.into(),
+    }
+}
+
+/// Looks up an IPv4 address, since that's all 39dll supports.
+fn resolve(host: &[u8], port: f64) -> io::Result<SocketAddr> {
+    let host = String::from_utf8_lossy(host);
+    (host.as_ref(), port as u16)
+        .to_socket_addrs()?
+        .find(SocketAddr::is_ipv4)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no IPv4 address"))
+}
+
+fn any_address(port: f64) -> SocketAddr {
+    (Ipv4Addr::UNSPECIFIED, port as u16).into()
+}
+
+fn tcp_connect(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let stream = resolve(str_arg(args, 0), real_arg(args, 1)).and_then(TcpStream::connect);
+    add_socket(hle, stream.map(Kind::Stream), real_arg(args, 2))
+}
+
+fn tcp_listen(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    // std doesn't let the backlog size (argument 1) be chosen, but it's only a hint anyway
+    let listener = TcpListener::bind(any_address(real_arg(args, 0)));
+    add_socket(hle, listener.map(Kind::Listener), real_arg(args, 2))
+}
+
+fn tcp_accept(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let stream = match socket(hle, args) {
+        Some(Socket { kind: Kind::Listener(listener), .. }) => listener.accept().map(|(s, _)| Kind::Stream(s)),
+        _ => return SOCKET_ERROR.into(),
+    };
+    add_socket(hle, stream, real_arg(args, 1))
+}
+
+fn tcp_ip(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    match socket(hle, args) {
+        Some(Socket { kind: Kind::Stream(stream), .. }) => match stream.peer_addr() {
+            Ok(addr) => addr.ip().to_string().into(),
+            Err(_) => "".into(),
+        },
+        _ => "".into(),
+    }
+}
+
+fn tcp_connected(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    match socket(hle, args) {
+        Some(Socket { kind: Kind::Stream(stream), .. }) => stream.peer_addr().is_ok().into(),
+        _ => false.into(),
+    }
+}
+
+fn udp_connect(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let socket = UdpSocket::bind(any_address(real_arg(args, 0))).and_then(|s| s.set_broadcast(true).map(|_| s));
+    add_socket(hle, socket.map(Kind::Udp), real_arg(args, 1))
+}
+
+fn send_message(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let data = match buffer(hle, args, 3) {
+        Some(buffer) => buffer.data.clone(),
+        None => return SOCKET_ERROR.into(),
+    };
+    let socket = match socket(hle, args) {
+        Some(socket) => socket,
+        None => return SOCKET_ERROR.into(),
+    };
+    let result = match &mut socket.kind {
+        Kind::Stream(stream) => {
+            let mut message = Vec::with_capacity(data.len() + 2);
+            match &socket.format {
+                Format::Binary => {
+                    message.extend_from_slice(&(data.len() as u16).to_le_bytes());
+                    message.extend_from_slice(&data);
+                },
+                Format::Text(sep) => {
+                    message.extend_from_slice(&data);
+                    message.extend_from_slice(sep);
+                },
+                Format::Raw => message.extend_from_slice(&data),
+            }
+            stream.write_all(&message).map(|_| data.len())
+        },
+        Kind::Udp(udp) => resolve(str_arg(args, 1), real_arg(args, 2)).and_then(|addr| udp.send_to(&data, addr)),
+        Kind::Listener(_) | Kind::Closed => return SOCKET_ERROR.into(),
+    };
+    match socket.check(result) {
+        Some(size) => size.into(),
+        None => SOCKET_ERROR.into(),
+    }
+}
+
+fn receive(hle: &mut HleState, args: &[Value], consume: bool) -> Value {
+    let len = real_arg(args, 1).max(0.0) as usize;
+    let state = &mut hle.dll39;
+    let id = real_arg(args, 2);
+    let buffer = match state.buffers.get_mut(id.max(0.0) as usize).and_then(Option::as_mut) {
+        Some(buffer) if id >= 0.0 => buffer,
+        _ => return SOCKET_ERROR.into(),
+    };
+    match get_handle(&mut state.sockets, handle_arg(args, 0)) {
+        Some(socket) => socket.receive(len, consume, buffer, &mut state.last_in).into(),
+        None => SOCKET_ERROR.into(),
+    }
+}
+
+fn receive_message(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    receive(hle, args, true)
+}
+
+fn peek_message(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    receive(hle, args, false)
+}
+
+fn set_format(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let format = match real_arg(args, 1) as i32 {
+        0 => Format::Binary,
+        1 => match str_arg(args, 2) {
+            b"" => Format::Text(b"\r\n".to_vec()),
+            sep => Format::Text(sep.to_vec()),
+        },
+        _ => Format::Raw,
+    };
+    match socket(hle, args) {
+        Some(socket) => {
+            socket.format = format;
+            1.into()
+        },
+        None => 0.into(),
+    }
+}
+
+fn set_sync(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let blocking = real_arg(args, 1) == 0.0;
+    match socket(hle, args) {
+        Some(socket) => {
+            socket.set_blocking(blocking);
+            1.into()
+        },
+        None => 0.into(),
+    }
+}
+
+fn set_nagle(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let nagle = real_arg(args, 1) != 0.0;
+    match socket(hle, args) {
+        Some(Socket { kind: Kind::Stream(stream), .. }) => stream.set_nodelay(!nagle).is_ok().into(),
+        _ => 0.into(),
+    }
+}
+
+fn close_sock(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    match handle_arg(args, 0) {
+        Some(handle) if get_handle(&mut hle.dll39.sockets, Some(handle)).is_some() => {
+            hle.dll39.sockets[handle] = None;
+            1.into()
+        },
+        _ => 0.into(),
+    }
+}
+
+fn sock_last_error(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    match socket(hle, args) {
+        Some(socket) => socket.last_error.into(),
+        None => 0.into(),
+    }
+}
+
+fn last_in_ip(hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    match hle.dll39.last_in {
+        Some(addr) => addr.ip().to_string().into(),
+        None => "".into(),
+    }
+}
+
+fn last_in_port(hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    hle.dll39.last_in.map_or(0, |addr| u32::from(addr.port())).into()
+}
+
+fn my_host(_hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    network::get_local_ip().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)).to_string().into()
+}
+
+fn host_ip(_hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    match resolve(str_arg(args, 0), 0.0) {
+        Ok(addr) => addr.ip().to_string().into(),
+        Err(_) => "".into(),
+    }
+}
+
+/// Checks an IP against a mask like "192.168.*.*".
+fn compare_ip(_hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let ip = str_arg(args, 0).split(|&c| c == b'.');
+    let mask = str_arg(args, 1).split(|&c| c == b'.');
+    (ip.clone().count() == mask.clone().count() && ip.zip(mask).all(|(i, m)| m == b"*" || i == m)).into()
+}
+
+fn sock_start(_hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    1.into()
+}
+
+fn sock_exit(hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    hle.dll39.sockets.clear();
+    1.into()
+}
+
+fn write_byte(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let value = real_arg(args, 0) as i64 as u8;
+    with_buffer(hle, args, 1, |b| b.write(&[value]))
+}
+
+fn write_short(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let value = real_arg(args, 0) as i64 as u16;
+    with_buffer(hle, args, 1, |b| b.write(&value.to_le_bytes()))
+}
+
+fn write_int(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let value = real_arg(args, 0) as i64 as u32;
+    with_buffer(hle, args, 1, |b| b.write(&value.to_le_bytes()))
+}
+
+fn write_float(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let value = real_arg(args, 0) as f32;
+    with_buffer(hle, args, 1, |b| b.write(&value.to_le_bytes()))
+}
+
+fn write_double(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let value = real_arg(args, 0);
+    with_buffer(hle, args, 1, |b| b.write(&value.to_le_bytes()))
+}
+
+fn write_chars(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let s = str_arg(args, 0).to_vec();
+    with_buffer(hle, args, 1, |b| b.write(&s))
+}
+
+fn write_string(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let mut s = str_arg(args, 0).to_vec();
+    s.push(0);
+    with_buffer(hle, args, 1, |b| b.write(&s))
+}
+
+fn read_char(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    with_buffer(hle, args, 0, |b| i32::from(i8::from_le_bytes(b.read_array())))
+}
+
+fn read_byte(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    with_buffer(hle, args, 0, |b| u32::from(u8::from_le_bytes(b.read_array())))
+}
+
+fn read_short(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    with_buffer(hle, args, 0, |b| i32::from(i16::from_le_bytes(b.read_array())))
+}
+
+fn read_ushort(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    with_buffer(hle, args, 0, |b| u32::from(u16::from_le_bytes(b.read_array())))
+}
+
+fn read_int(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    with_buffer(hle, args, 0, |b| i32::from_le_bytes(b.read_array()))
+}
+
+fn read_uint(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    with_buffer(hle, args, 0, |b| u32::from_le_bytes(b.read_array()))
+}
+
+fn read_float(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    with_buffer(hle, args, 0, |b| f64::from(f32::from_le_bytes(b.read_array())))
+}
+
+fn read_double(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    with_buffer(hle, args, 0, |b| f64::from_le_bytes(b.read_array()))
+}
+
+fn read_chars(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let len = real_arg(args, 0).max(0.0) as usize;
+    match buffer(hle, args, 1) {
+        Some(b) => {
+            // 39dll gives back whatever's left if there isn't enough
+            let len = len.min(b.data.len().saturating_sub(b.read_pos));
+            b.read(len).unwrap_or_default().into()
+        },
+        None => "".into(),
+    }
+}
+
+fn read_string(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    match buffer(hle, args, 0) {
+        Some(b) => {
+            let rest = b.data.get(b.read_pos..).unwrap_or_default();
+            let len = rest.iter().position(|&c| c == 0).unwrap_or(rest.len());
+            let s = rest[..len].to_vec();
+            b.read_pos = (b.read_pos + len + 1).min(b.data.len());
+            s.into()
+        },
+        None => "".into(),
+    }
+}
+
+fn get_pos(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let read = real_arg(args, 0) != 0.0;
+    with_buffer(hle, args, 1, |b| if read { b.read_pos } else { b.write_pos })
+}
+
+fn set_pos(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let pos = real_arg(args, 0).max(0.0) as usize;
+    with_buffer(hle, args, 1, |b| {
+        b.read_pos = pos;
+        b.write_pos = pos;
+        1
+    })
+}
+
+fn clear_buffer(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    with_buffer(hle, args, 0, |b| {
+        *b = Buffer::default();
+        1
+    })
+}
+
+fn buff_size(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    with_buffer(hle, args, 0, |b| b.data.len())
+}
+
+fn bytes_left(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    with_buffer(hle, args, 0, |b| b.data.len().saturating_sub(b.read_pos))
+}
+
+fn create_buffer(hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    let buffers = &mut hle.dll39.buffers;
+    match buffers.iter().position(Option::is_none) {
+        Some(id) => {
+            buffers[id] = Some(Buffer::default());
+            id.into()
+        },
+        None => {
+            buffers.push(Some(Buffer::default()));
+            (buffers.len() - 1).into()
+        },
+    }
+}
+
+fn free_buffer(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let id = real_arg(args, 0);
+    // the default buffer can't be freed
+    if id >= 1.0 && buffer(hle, args, 0).is_some() {
+        hle.dll39.buffers[id as usize] = None;
+        1.into()
+    } else {
+        0.into()
+    }
+}
+
+fn buff_exists(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    buffer(hle, args, 0).is_some().into()
+}
+
+fn copy_buffer(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let data = match buffer(hle, args, 1) {
+        Some(src) => src.data.clone(),
+        None => return 0.into(),
+    };
+    with_buffer(hle, args, 0, |dest| dest.write(&data))
+}
+
+fn copy_buffer2(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let start = real_arg(args, 1).max(0.0) as usize;
+    let len = real_arg(args, 2).max(0.0) as usize;
+    let data = match buffer(hle, args, 3) {
+        Some(src) => {
+            let start = start.min(src.data.len());
+            src.data[start..(start + len).min(src.data.len())].to_vec()
+        },
+        None => return 0.into(),
+    };
+    with_buffer(hle, args, 0, |dest| dest.write(&data))
+}
+
+fn md5_string(_hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    hex::encode(md5(str_arg(args, 0))).into()
+}
+
+fn md5_buffer(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    match buffer(hle, args, 0) {
+        Some(b) => hex::encode(md5(&b.data)).into(),
+        None => "".into(),
+    }
+}
+
+fn adler32(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    with_buffer(hle, args, 0, |b| {
+        let (a, b) = b.data.iter().fold((1u32, 0u32), |(a, b), &c| {
+            let a = (a + u32::from(c)) % 65521;
+            (a, (b + a) % 65521)
+        });
+        (b << 16) | a
+    })
+}
+
+/// RFC 1321.
+fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    let k = (0..64).map(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32).collect::<Vec<_>>();
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64).wrapping_mul(8).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476];
+    for chunk in message.chunks(64) {
+        let mut words = [0u32; 16];
+        for (word, bytes) in words.iter_mut().zip(chunk.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(k[i]).wrapping_add(words[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i / 16 * 4 + i % 4]));
+        }
+        for (s, x) in state.iter_mut().zip([a, b, c, d].iter()) {
+            *s = s.wrapping_add(*x);
+        }
+    }
+
+    let mut out = [0; 16];
+    for (bytes, word) in out.chunks_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::tests::show, *};
+
+    fn call(hle: &mut HleState, function: &str, args: &[Value]) -> String {
+        show(super::super::tests::call(&LIBRARY, hle, function, 0, args))
+    }
+
+    #[test]
+    fn buffers_round_trip() {
+        let mut hle = HleState::default();
+        assert_eq!(call(&mut hle, "writebyte", &[300.into(), 0.into()]), "1"); // only the low byte is kept
+        assert_eq!(call(&mut hle, "writeshort", &[(-2).into(), 0.into()]), "2");
+        assert_eq!(call(&mut hle, "writeint", &[(-100000).into(), 0.into()]), "4");
+        assert_eq!(call(&mut hle, "writeuint", &[3000000000u32.into(), 0.into()]), "4");
+        assert_eq!(call(&mut hle, "writefloat", &[1.5.into(), 0.into()]), "4");
+        assert_eq!(call(&mut hle, "writedouble", &[0.1.into(), 0.into()]), "8");
+        assert_eq!(call(&mut hle, "writechars", &["ab".into(), 0.into()]), "2");
+        assert_eq!(call(&mut hle, "writestring", &["cd".into(), 0.into()]), "3");
+        assert_eq!(call(&mut hle, "buffsize", &[0.into()]), "28");
+        assert_eq!(call(&mut hle, "getpos", &[0.into(), 0.into()]), "28");
+
+        assert_eq!(call(&mut hle, "readbyte", &[0.into()]), "44");
+        assert_eq!(call(&mut hle, "readushort", &[0.into()]), "65534");
+        assert_eq!(call(&mut hle, "readint", &[0.into()]), "-100000");
+        assert_eq!(call(&mut hle, "readuint", &[0.into()]), "3000000000");
+        assert_eq!(call(&mut hle, "readfloat", &[0.into()]), "1.5");
+        assert_eq!(call(&mut hle, "readdouble", &[0.into()]), "0.1");
+        assert_eq!(call(&mut hle, "readchars", &[2.into(), 0.into()]), "\"ab\"");
+        assert_eq!(call(&mut hle, "readstring", &[0.into()]), "\"cd\"");
+        assert_eq!(call(&mut hle, "bytesleft", &[0.into()]), "0");
+        assert_eq!(call(&mut hle, "getpos", &[1.into(), 0.into()]), "28");
+
+        // reading past the end gives nothing, and whatever's left for chars
+        assert_eq!(call(&mut hle, "readint", &[0.into()]), "0");
+        assert_eq!(call(&mut hle, "setpos", &[1.into(), 0.into()]), "1");
+        assert_eq!(call(&mut hle, "readshort", &[0.into()]), "-2");
+        assert_eq!(call(&mut hle, "setpos", &[25.into(), 0.into()]), "1");
+        assert_eq!(call(&mut hle, "readchars", &[10.into(), 0.into()]), "\"cd\\0\"");
+        assert_eq!(call(&mut hle, "readchar", &[0.into()]), "0");
+    }
+
+    #[test]
+    fn buffers_are_created_copied_and_freed() {
+        let mut hle = HleState::default();
+        assert_eq!(call(&mut hle, "createbuffer", &[]), "1");
+        assert_eq!(call(&mut hle, "createbuffer", &[]), "2");
+        call(&mut hle, "writechars", &["hello".into(), 1.into()]);
+        assert_eq!(call(&mut hle, "copybuffer", &[2.into(), 1.into()]), "5");
+        assert_eq!(call(&mut hle, "copybuffer2", &[2.into(), 1.into(), 3.into(), 1.into()]), "3");
+        assert_eq!(call(&mut hle, "readchars", &[8.into(), 2.into()]), "\"helloell\"");
+
+        // the default buffer is always there
+        assert_eq!(call(&mut hle, "freebuffer", &[0.into()]), "0");
+        assert_eq!(call(&mut hle, "freebuffer", &[1.into()]), "1");
+        assert_eq!(call(&mut hle, "freebuffer", &[1.into()]), "0");
+        assert_eq!(call(&mut hle, "buffexists", &[1.into()]), "0");
+        assert_eq!(call(&mut hle, "buffexists", &[0.into()]), "1");
+        assert_eq!(call(&mut hle, "writebyte", &[1.into(), 1.into()]), "0");
+        assert_eq!(call(&mut hle, "createbuffer", &[]), "1");
+
+        assert_eq!(call(&mut hle, "clearbuffer", &[2.into()]), "1");
+        assert_eq!(call(&mut hle, "buffsize", &[2.into()]), "0");
+    }
+
+    #[test]
+    fn checksums() {
+        let md5s = [
+            ("", "d41d8cd98f00b204e9800998ecf8427e"),
+            ("a", "0cc175b9c0f1b6a831c399e269772661"),
+            ("abc", "900150983cd24fb0d6963f7d28e17f72"),
+            ("message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+            ("abcdefghijklmnopqrstuvwxyz", "c3fcd3d76192e4007dfb496cca67e13b"),
+            (
+                "12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+                "57edf4a22be3c955ac49da2e2107b67a",
+            ),
+        ];
+        let mut hle = HleState::default();
+        for (input, hash) in md5s.iter() {
+            assert_eq!(call(&mut hle, "md5string", &[(*input).into()]), format!("{:?}", hash), "md5 of {:?}", input);
+            call(&mut hle, "clearbuffer", &[0.into()]);
+            call(&mut hle, "writechars", &[(*input).into(), 0.into()]);
+            assert_eq!(call(&mut hle, "md5buffer", &[0.into()]), format!("{:?}", hash), "md5 of {:?}", input);
+        }
+
+        let adlers = [("", 1), ("a", 0x00620062), ("abc", 0x024d0127), ("Wikipedia", 0x11e60398)];
+        for (input, checksum) in adlers.iter() {
+            call(&mut hle, "clearbuffer", &[0.into()]);
+            call(&mut hle, "writechars", &[(*input).into(), 0.into()]);
+            assert_eq!(call(&mut hle, "adler32", &[0.into()]), checksum.to_string(), "adler32 of {:?}", input);
+        }
+    }
+
+    #[test]
+    fn errors_are_winsock_codes() {
+        let codes = [
+            (io::ErrorKind::WouldBlock, 10035),
+            (io::ErrorKind::ConnectionReset, 10054),
+            (io::ErrorKind::BrokenPipe, 10054),
+            (io::ErrorKind::ConnectionRefused, 10061),
+            (io::ErrorKind::NotConnected, 10057),
+            (io::ErrorKind::NotFound, 11001),
+            (io::ErrorKind::Other, -1),
+        ];
+        for (kind, code) in codes.iter() {
+            assert_eq!(wsa_error(&io::Error::from(*kind)), *code, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn sockets_talk_over_loopback() {
+        // a port that was free a moment ago
+        let port = u32::from(TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port());
+        let mut hle = HleState::default();
+        assert_eq!(call(&mut hle, "tcplisten", &[port.into(), 2.into(), 0.into()]), "1");
+        assert_eq!(call(&mut hle, "tcpconnect", &["127.0.0.1".into(), port.into(), 0.into()]), "2");
+        assert_eq!(call(&mut hle, "tcpaccept", &[1.into(), 0.into()]), "3");
+        assert_eq!(call(&mut hle, "tcpconnected", &[3.into()]), "1");
+        assert_eq!(call(&mut hle, "tcpip", &[3.into()]), "\"127.0.0.1\"");
+
+        // binary messages carry their length, so two sent at once come out separately
+        call(&mut hle, "writestring", &["hello".into(), 0.into()]);
+        call(&mut hle, "writeint", &[1234.into(), 0.into()]);
+        assert_eq!(call(&mut hle, "sendmessage", &[2.into(), "".into(), 0.into(), 0.into()]), "10");
+        call(&mut hle, "clearbuffer", &[0.into()]);
+        call(&mut hle, "writebyte", &[7.into(), 0.into()]);
+        assert_eq!(call(&mut hle, "sendmessage", &[2.into(), "".into(), 0.into(), 0.into()]), "1");
+        call(&mut hle, "clearbuffer", &[0.into()]);
+        assert_eq!(call(&mut hle, "peekmessage", &[3.into(), 0.into(), 0.into()]), "10");
+        assert_eq!(call(&mut hle, "receivemessage", &[3.into(), 0.into(), 0.into()]), "10");
+        assert_eq!(call(&mut hle, "readstring", &[0.into()]), "\"hello\"");
+        assert_eq!(call(&mut hle, "readint", &[0.into()]), "1234");
+        assert_eq!(call(&mut hle, "lastinIP", &[]), "\"127.0.0.1\"");
+        assert_eq!(call(&mut hle, "receivemessage", &[3.into(), 0.into(), 0.into()]), "1");
+        assert_eq!(call(&mut hle, "readbyte", &[0.into()]), "7");
+
+        // text messages end with a separator
+        call(&mut hle, "setformat", &[2.into(), 1.into(), "|".into()]);
+        call(&mut hle, "setformat", &[3.into(), 1.into(), "|".into()]);
+        call(&mut hle, "clearbuffer", &[0.into()]);
+        call(&mut hle, "writechars", &["one".into(), 0.into()]);
+        call(&mut hle, "sendmessage", &[2.into(), "".into(), 0.into(), 0.into()]);
+        assert_eq!(call(&mut hle, "receivemessage", &[3.into(), 0.into(), 0.into()]), "3");
+        assert_eq!(call(&mut hle, "readchars", &[3.into(), 0.into()]), "\"one\"");
+
+        // with nothing to read, a non-blocking socket says it would have blocked
+        call(&mut hle, "setsync", &[3.into(), 1.into()]);
+        assert_eq!(call(&mut hle, "receivemessage", &[3.into(), 0.into(), 0.into()]), "-1");
+        assert_eq!(call(&mut hle, "socklasterror", &[3.into()]), "10035");
+
+        // and once the other end's gone, receiving gives 0
+        call(&mut hle, "setsync", &[3.into(), 0.into()]);
+        assert_eq!(call(&mut hle, "closesock", &[2.into()]), "1");
+        assert_eq!(call(&mut hle, "receivemessage", &[3.into(), 0.into(), 0.into()]), "0");
+        assert_eq!(call(&mut hle, "sendmessage", &[2.into(), "".into(), 0.into(), 0.into()]), "-1");
+        assert_eq!(call(&mut hle, "closesock", &[2.into()]), "0");
+
+        // nothing's listening after the listener's closed
+        call(&mut hle, "sockexit", &[]);
+        assert_eq!(call(&mut hle, "tcpconnect", &["127.0.0.1".into(), port.into(), 0.into()]), "-1");
+    }
+}
@@ -0,0 +1,429 @@
+//! GMFMODSimple.dll, the most common FMOD wrapper.
+
+// TODO: nothing here makes any noise, since the emulator doesn't have a mixer to route it into yet, so the
+// real library is used in normal play wherever it can be loaded. When recording or replaying, or where it
+// can't be, sounds keep time with the game's clock, using lengths from the files' headers. Formats that
+// don't have one (like tracker music) have a length of 0: one-shots of them finish as soon as they start,
+// and loops carry on until they're stopped. Pitch doesn't change how fast anything plays.
+
+use super::{
+    get_handle, handle_arg, insert_handle, real_arg, sound_length, str_arg, Context, Library, State as HleState,
+};
+use crate::gml::{cd::Playback, Value};
+use serde::{Deserialize, Serialize};
+
+pub const LIBRARY: Library = Library {
+    functions: &[
+        ("FMODinit", init),
+        ("FMODfree", free),
+        ("FMODUpdate", update),
+        ("FMODGetLastError", get_last_error),
+        ("FMODSoundAdd", sound_add),
+        ("FMODSoundFree", sound_free),
+        ("FMODSoundPlay", sound_play),
+        ("FMODSoundLoop", sound_loop),
+        ("FMODSoundSetGroup", sound_set_group),
+        ("FMODSoundSetMaxVolume", sound_set_max_volume),
+        ("FMODSoundGetMaxVolume", sound_get_max_volume),
+        ("FMODSoundGetLength", sound_get_length),
+        ("FMODInstanceStop", instance_stop),
+        ("FMODInstanceIsPlaying", instance_is_playing),
+        ("FMODInstanceSetPaused", instance_set_paused),
+        ("FMODInstanceGetPaused", instance_get_paused),
+        ("FMODInstanceSetVolume", instance_set_volume),
+        ("FMODInstanceGetVolume", instance_get_volume),
+        ("FMODInstanceSetPan", instance_set_pan),
+        ("FMODInstanceGetPan", instance_get_pan),
+        ("FMODInstanceSetPitch", instance_set_pitch),
+        ("FMODInstanceGetPitch", instance_get_pitch),
+        ("FMODInstanceSetPosition", instance_set_position),
+        ("FMODInstanceGetPosition", instance_get_position),
+        ("FMODGroupSetVolume", group_set_volume),
+        ("FMODGroupGetVolume", group_get_volume),
+        ("FMODGroupSetPaused", group_set_paused),
+        ("FMODGroupStop", group_stop),
+        ("FMODMasterSetVolume", master_set_volume),
+        ("FMODMasterGetVolume", master_get_volume),
+        ("FMODAllStop", all_stop),
+    ],
+    plays_sound: true,
+};
+
+const GROUP_COUNT: usize = 8;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct State {
+    sounds: Vec<Option<Sound>>,
+    instances: Vec<Option<Instance>>,
+    group_volumes: [f64; GROUP_COUNT],
+    master_volume: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Sound {
+    group: usize,
+    max_volume: f64,
+    length: u64, // milliseconds
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Instance {
+    sound: usize,
+    group: usize,
+    playback: Playback,
+    length: u64,
+    volume: f64,
+    pan: f64,
+    pitch: f64,
+}
+
+impl Instance {
+    // paused instances are still playing as far as FMOD's concerned
+    fn is_live(&self, now: u128) -> bool {
+        self.playback.is_playing(now) || self.playback.is_paused()
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self { sounds: Vec::new(), instances: Vec::new(), group_volumes: [1.0; GROUP_COUNT], master_volume: 1.0 }
+    }
+}
+
+fn group_arg(args: &[Value], index: usize) -> Option<usize> {
+    let group = real_arg(args, index).round();
+    if group >= 0.0 && group < GROUP_COUNT as f64 { Some(group as usize) } else { None }
+}
+
+fn set_paused(playback: &mut Playback, paused: bool, now: u128) {
+    if paused { playback.pause(now) } else { playback.resume(now) }
+}
+
+fn init(hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    hle.fmod = Default::default();
+    1.into()
+}
+
+fn free(hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    hle.fmod = Default::default();
+    1.into()
+}
+
+fn update(_hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    1.into()
+}
+
+fn get_last_error(_hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    0.into()
+}
+
+fn sound_add(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    match sound_length(context, str_arg(args, 0)) {
+        Some(length) => insert_handle(&mut hle.fmod.sounds, Sound { group: 0, max_volume: 1.0, length }).into(),
+        None => 0.into(),
+    }
+}
+
+fn sound_free(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let state = &mut hle.fmod;
+    match handle_arg(args, 0) {
+        Some(sound) if get_handle(&mut state.sounds, Some(sound)).is_some() => {
+            state.sounds[sound] = None;
+            for slot in state.instances.iter_mut() {
+                if slot.as_ref().map(|i| i.sound == sound).unwrap_or(false) {
+                    *slot = None;
+                }
+            }
+            1.into()
+        },
+        _ => 0.into(),
+    }
+}
+
+fn play(hle: &mut HleState, context: &Context, args: &[Value], looping: bool) -> Value {
+    let state = &mut hle.fmod;
+    let sound = handle_arg(args, 0);
+    let (group, length) = match get_handle(&mut state.sounds, sound) {
+        Some(s) => (s.group, s.length),
+        None => return 0.into(),
+    };
+    // recycle finished instances, or one-shots would pile up forever
+    for slot in state.instances.iter_mut() {
+        if slot.as_ref().map(|i| !i.is_live(context.now)).unwrap_or(false) {
+            *slot = None;
+        }
+    }
+    let mut playback = Playback::default();
+    playback.play(0, Some(length), looping, context.now);
+    set_paused(&mut playback, real_arg(args, 1) >= 0.5, context.now);
+    let instance = Instance { sound: sound.unwrap(), group, playback, length, volume: 1.0, pan: 0.0, pitch: 1.0 };
+    insert_handle(&mut state.instances, instance).into()
+}
+
+fn sound_play(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    play(hle, context, args, false)
+}
+
+fn sound_loop(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    play(hle, context, args, true)
+}
+
+fn sound_set_group(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    match (get_handle(&mut hle.fmod.sounds, handle_arg(args, 0)), group_arg(args, 1)) {
+        (Some(sound), Some(group)) => {
+            sound.group = group;
+            1.into()
+        },
+        _ => 0.into(),
+    }
+}
+
+fn sound_set_max_volume(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    match get_handle(&mut hle.fmod.sounds, handle_arg(args, 0)) {
+        Some(sound) => {
+            sound.max_volume = real_arg(args, 1).clamp(0.0, 1.0);
+            1.into()
+        },
+        None => 0.into(),
+    }
+}
+
+fn sound_get_max_volume(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    get_handle(&mut hle.fmod.sounds, handle_arg(args, 0)).map(|s| s.max_volume).unwrap_or(0.0).into()
+}
+
+fn sound_get_length(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    get_handle(&mut hle.fmod.sounds, handle_arg(args, 0)).map(|s| s.length as f64).unwrap_or(0.0).into()
+}
+
+/// Gets a live instance, since finished ones can't be changed any more.
+fn playing_instance<'a>(hle: &'a mut HleState, context: &Context, args: &[Value]) -> Option<&'a mut Instance> {
+    get_handle(&mut hle.fmod.instances, handle_arg(args, 0)).filter(|i| i.is_live(context.now))
+}
+
+fn instance_stop(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    match playing_instance(hle, context, args) {
+        Some(instance) => {
+            instance.playback.stop(context.now);
+            1.into()
+        },
+        None => 0.into(),
+    }
+}
+
+fn instance_is_playing(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    playing_instance(hle, context, args).is_some().into()
+}
+
+fn instance_set_paused(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    match playing_instance(hle, context, args) {
+        Some(instance) => {
+            set_paused(&mut instance.playback, real_arg(args, 1) >= 0.5, context.now);
+            1.into()
+        },
+        None => 0.into(),
+    }
+}
+
+fn instance_get_paused(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    playing_instance(hle, context, args).map(|i| i.playback.is_paused()).unwrap_or(false).into()
+}
+
+fn instance_set_volume(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    match playing_instance(hle, context, args) {
+        Some(instance) => {
+            instance.volume = real_arg(args, 1).clamp(0.0, 1.0);
+            1.into()
+        },
+        None => 0.into(),
+    }
+}
+
+fn instance_get_volume(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    playing_instance(hle, context, args).map(|i| i.volume).unwrap_or(0.0).into()
+}
+
+fn instance_set_pan(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    match playing_instance(hle, context, args) {
+        Some(instance) => {
+            instance.pan = real_arg(args, 1).clamp(-1.0, 1.0);
+            1.into()
+        },
+        None => 0.into(),
+    }
+}
+
+fn instance_get_pan(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    playing_instance(hle, context, args).map(|i| i.pan).unwrap_or(0.0).into()
+}
+
+fn instance_set_pitch(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    match playing_instance(hle, context, args) {
+        Some(instance) => {
+            instance.pitch = real_arg(args, 1).max(0.0);
+            1.into()
+        },
+        None => 0.into(),
+    }
+}
+
+fn instance_get_pitch(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    playing_instance(hle, context, args).map(|i| i.pitch).unwrap_or(0.0).into()
+}
+
+// positions are from 0 to 1 through the sound
+fn instance_set_position(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    let position = real_arg(args, 1).clamp(0.0, 1.0);
+    match playing_instance(hle, context, args) {
+        Some(instance) => {
+            instance.playback.seek((position * instance.length as f64) as u64, context.now);
+            1.into()
+        },
+        None => 0.into(),
+    }
+}
+
+fn instance_get_position(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    match playing_instance(hle, context, args) {
+        Some(instance) if instance.length > 0 => {
+            (instance.playback.position(context.now) as f64 / instance.length as f64).into()
+        },
+        _ => 0.into(),
+    }
+}
+
+fn group_set_volume(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    match group_arg(args, 0) {
+        Some(group) => {
+            hle.fmod.group_volumes[group] = real_arg(args, 1).clamp(0.0, 1.0);
+            1.into()
+        },
+        None => 0.into(),
+    }
+}
+
+fn group_get_volume(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    group_arg(args, 0).map(|g| hle.fmod.group_volumes[g]).unwrap_or(0.0).into()
+}
+
+fn group_set_paused(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    match group_arg(args, 0) {
+        Some(group) => {
+            let paused = real_arg(args, 1) >= 0.5;
+            hle.fmod
+                .instances
+                .iter_mut()
+                .flatten()
+                .filter(|i| i.group == group)
+                .for_each(|i| set_paused(&mut i.playback, paused, context.now));
+            1.into()
+        },
+        None => 0.into(),
+    }
+}
+
+fn group_stop(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    match group_arg(args, 0) {
+        Some(group) => {
+            hle.fmod
+                .instances
+                .iter_mut()
+                .flatten()
+                .filter(|i| i.group == group)
+                .for_each(|i| i.playback.stop(context.now));
+            1.into()
+        },
+        None => 0.into(),
+    }
+}
+
+fn master_set_volume(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    hle.fmod.master_volume = real_arg(args, 0).clamp(0.0, 1.0);
+    1.into()
+}
+
+fn master_get_volume(hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    hle.fmod.master_volume.into()
+}
+
+fn all_stop(hle: &mut HleState, context: &Context, _args: &[Value]) -> Value {
+    hle.fmod.instances.iter_mut().flatten().for_each(|i| i.playback.stop(context.now));
+    1.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::tests::{show, sound_file},
+        *,
+    };
+
+    fn call(hle: &mut HleState, function: &str, now: u128, args: &[Value]) -> String {
+        show(super::super::tests::call(&LIBRARY, hle, function, now, args))
+    }
+
+    #[test]
+    fn sounds_keep_time() {
+        let mut hle = HleState::default();
+        let path = sound_file("fmod", 1000);
+        assert_eq!(call(&mut hle, "FMODinit", 0, &[]), "1");
+        assert_eq!(call(&mut hle, "FMODSoundAdd", 0, &[path.as_str().into(), 0.into(), 0.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODSoundAdd", 0, &["not/a/sound.wav".into(), 0.into(), 0.into()]), "0");
+        assert_eq!(call(&mut hle, "FMODSoundGetLength", 0, &[1.into()]), "1000");
+
+        // a one-shot plays for as long as the sound is
+        assert_eq!(call(&mut hle, "FMODSoundPlay", 0, &[1.into(), 0.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODInstanceGetPosition", 250, &[1.into()]), "0.25");
+        assert_eq!(call(&mut hle, "FMODInstanceSetPaused", 250, &[1.into(), 1.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODInstanceGetPaused", 900, &[1.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODInstanceIsPlaying", 900, &[1.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODInstanceSetPaused", 1000, &[1.into(), 0.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODInstanceSetPosition", 1000, &[1.into(), 0.5.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODInstanceIsPlaying", 1499, &[1.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODInstanceIsPlaying", 1500, &[1.into()]), "0");
+        // and once it's finished, it's gone
+        assert_eq!(call(&mut hle, "FMODInstanceSetVolume", 1500, &[1.into(), 0.5.into()]), "0");
+
+        // loops go round until they're stopped, and the finished instance's handle gets reused
+        assert_eq!(call(&mut hle, "FMODSoundLoop", 2000, &[1.into(), 0.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODInstanceGetPosition", 7500, &[1.into()]), "0.5");
+        assert_eq!(call(&mut hle, "FMODInstanceStop", 7500, &[1.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODInstanceIsPlaying", 7500, &[1.into()]), "0");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn groups_and_volumes() {
+        let mut hle = HleState::default();
+        let path = sound_file("fmod-groups", 1000);
+        call(&mut hle, "FMODSoundAdd", 0, &[path.as_str().into(), 0.into(), 0.into()]);
+        assert_eq!(call(&mut hle, "FMODSoundSetGroup", 0, &[1.into(), 3.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODSoundSetGroup", 0, &[1.into(), 8.into()]), "0");
+        assert_eq!(call(&mut hle, "FMODSoundSetMaxVolume", 0, &[1.into(), 2.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODSoundGetMaxVolume", 0, &[1.into()]), "1");
+        call(&mut hle, "FMODSoundLoop", 0, &[1.into(), 0.into()]);
+        call(&mut hle, "FMODSoundLoop", 0, &[1.into(), 0.into()]);
+
+        assert_eq!(call(&mut hle, "FMODGroupSetPaused", 0, &[3.into(), 1.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODInstanceGetPaused", 0, &[2.into()]), "1");
+        call(&mut hle, "FMODGroupSetPaused", 0, &[3.into(), 0.into()]);
+        assert_eq!(call(&mut hle, "FMODInstanceGetPaused", 0, &[2.into()]), "0");
+        assert_eq!(call(&mut hle, "FMODGroupSetVolume", 0, &[3.into(), 0.25.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODGroupGetVolume", 0, &[3.into()]), "0.25");
+        assert_eq!(call(&mut hle, "FMODMasterSetVolume", 0, &[(-1).into()]), "1");
+        assert_eq!(call(&mut hle, "FMODMasterGetVolume", 0, &[]), "0");
+
+        assert_eq!(call(&mut hle, "FMODGroupStop", 100, &[3.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODInstanceIsPlaying", 100, &[1.into()]), "0");
+        call(&mut hle, "FMODSoundLoop", 100, &[1.into(), 0.into()]);
+        assert_eq!(call(&mut hle, "FMODAllStop", 200, &[]), "1");
+        assert_eq!(call(&mut hle, "FMODInstanceIsPlaying", 200, &[1.into()]), "0");
+
+        // freeing a sound takes its instances with it
+        call(&mut hle, "FMODSoundLoop", 300, &[1.into(), 0.into()]);
+        assert_eq!(call(&mut hle, "FMODSoundFree", 300, &[1.into()]), "1");
+        assert_eq!(call(&mut hle, "FMODInstanceIsPlaying", 300, &[1.into()]), "0");
+        assert_eq!(call(&mut hle, "FMODSoundPlay", 300, &[1.into(), 0.into()]), "0");
+        std::fs::remove_file(path).unwrap();
+    }
+}
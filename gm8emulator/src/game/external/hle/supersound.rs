@@ -0,0 +1,241 @@
+//! SuperSound.dll (also distributed as SSound.dll), which hands out sound handles as strings.
+
+// TODO: like the FMOD wrapper, this doesn't make any noise until there's a mixer, so it only keeps time.
+// Positions and lengths are in milliseconds, and frequency doesn't change how fast anything plays.
+
+use super::{
+    get_handle, handle_arg, insert_handle, real_arg, sound_length, str_arg, Context, Library, State as HleState,
+};
+use crate::gml::{cd::Playback, Value};
+use serde::{Deserialize, Serialize};
+
+pub const LIBRARY: Library = Library {
+    functions: &[
+        ("SS_Init", init),
+        ("SS_Unload", unload),
+        ("SS_LoadSound", load_sound),
+        ("SS_FreeSound", free_sound),
+        ("SS_PlaySound", play_sound),
+        ("SS_LoopSound", loop_sound),
+        ("SS_StopSound", stop_sound),
+        ("SS_PauseSound", pause_sound),
+        ("SS_ResumeSound", resume_sound),
+        ("SS_IsSoundPlaying", is_sound_playing),
+        ("SS_IsSoundPaused", is_sound_paused),
+        ("SS_IsSoundLooping", is_sound_looping),
+        ("SS_SetSoundVol", set_sound_vol),
+        ("SS_GetSoundVol", get_sound_vol),
+        ("SS_SetSoundPan", set_sound_pan),
+        ("SS_GetSoundPan", get_sound_pan),
+        ("SS_SetSoundFreq", set_sound_freq),
+        ("SS_GetSoundFreq", get_sound_freq),
+        ("SS_GetSoundLength", get_sound_length),
+        ("SS_GetSoundPosition", get_sound_position),
+        ("SS_SetSoundPosition", set_sound_position),
+    ],
+    plays_sound: true,
+};
+
+const MAX_VOLUME: f64 = 10000.0;
+const DEFAULT_FREQ: f64 = 44100.0; // nothing gets decoded, so just assume CD quality
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct State {
+    sounds: Vec<Option<Sound>>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Sound {
+    length: u64,
+    playback: Playback,
+    looping: bool,
+    volume: f64,
+    pan: f64,
+    freq: f64,
+}
+
+fn sound<'a>(hle: &'a mut HleState, args: &[Value]) -> Option<&'a mut Sound> {
+    get_handle(&mut hle.supersound.sounds, handle_arg(args, 0))
+}
+
+/// Runs `f` on the sound in the first argument, returning whether it exists.
+fn with_sound(hle: &mut HleState, args: &[Value], f: impl FnOnce(&mut Sound)) -> Value {
+    match sound(hle, args) {
+        Some(sound) => {
+            f(sound);
+            1.into()
+        },
+        None => 0.into(),
+    }
+}
+
+fn init(hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    hle.supersound = Default::default();
+    1.into()
+}
+
+fn unload(hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    hle.supersound = Default::default();
+    1.into()
+}
+
+fn load_sound(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    let length = match sound_length(context, str_arg(args, 0)) {
+        Some(length) => length,
+        None => return 0.into(),
+    };
+    let sound = Sound {
+        length,
+        playback: Default::default(),
+        looping: false,
+        volume: MAX_VOLUME,
+        pan: 0.0,
+        freq: DEFAULT_FREQ,
+    };
+    insert_handle(&mut hle.supersound.sounds, sound).into()
+}
+
+fn free_sound(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    match handle_arg(args, 0) {
+        Some(handle) if get_handle(&mut hle.supersound.sounds, Some(handle)).is_some() => {
+            hle.supersound.sounds[handle] = None;
+            1.into()
+        },
+        _ => 0.into(),
+    }
+}
+
+fn play_sound(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    with_sound(hle, args, |s| {
+        s.looping = false;
+        s.playback.play(0, Some(s.length), false, context.now);
+    })
+}
+
+fn loop_sound(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    with_sound(hle, args, |s| {
+        s.looping = true;
+        s.playback.play(0, Some(s.length), true, context.now);
+    })
+}
+
+fn stop_sound(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    with_sound(hle, args, |s| {
+        s.looping = false;
+        s.playback = Default::default();
+    })
+}
+
+fn pause_sound(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    with_sound(hle, args, |s| s.playback.pause(context.now))
+}
+
+fn resume_sound(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    with_sound(hle, args, |s| s.playback.resume(context.now))
+}
+
+fn is_sound_playing(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    sound(hle, args).map(|s| s.playback.is_playing(context.now)).unwrap_or(false).into()
+}
+
+fn is_sound_paused(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    sound(hle, args).map(|s| s.playback.is_paused()).unwrap_or(false).into()
+}
+
+fn is_sound_looping(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    sound(hle, args).map(|s| s.looping).unwrap_or(false).into()
+}
+
+fn set_sound_vol(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let volume = real_arg(args, 1).clamp(0.0, MAX_VOLUME);
+    with_sound(hle, args, |s| s.volume = volume)
+}
+
+fn get_sound_vol(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    sound(hle, args).map(|s| s.volume).unwrap_or(0.0).into()
+}
+
+fn set_sound_pan(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let pan = real_arg(args, 1).clamp(-MAX_VOLUME, MAX_VOLUME);
+    with_sound(hle, args, |s| s.pan = pan)
+}
+
+fn get_sound_pan(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    sound(hle, args).map(|s| s.pan).unwrap_or(0.0).into()
+}
+
+fn set_sound_freq(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    let freq = real_arg(args, 1).max(0.0);
+    with_sound(hle, args, |s| s.freq = freq)
+}
+
+fn get_sound_freq(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    sound(hle, args).map(|s| s.freq).unwrap_or(0.0).into()
+}
+
+fn get_sound_length(hle: &mut HleState, _context: &Context, args: &[Value]) -> Value {
+    sound(hle, args).map(|s| s.length as f64).unwrap_or(0.0).into()
+}
+
+fn get_sound_position(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    sound(hle, args).map(|s| s.playback.position(context.now) as f64).unwrap_or(0.0).into()
+}
+
+fn set_sound_position(hle: &mut HleState, context: &Context, args: &[Value]) -> Value {
+    let position = real_arg(args, 1).max(0.0) as u64;
+    with_sound(hle, args, |s| s.playback.seek(position.min(s.length), context.now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::tests::{show, sound_file},
+        *,
+    };
+
+    fn call(hle: &mut HleState, function: &str, now: u128, args: &[Value]) -> String {
+        show(super::super::tests::call(&LIBRARY, hle, function, now, args))
+    }
+
+    #[test]
+    fn sounds_keep_time() {
+        let mut hle = HleState::default();
+        let path = sound_file("supersound", 2000);
+        assert_eq!(call(&mut hle, "SS_Init", 0, &[]), "1");
+        assert_eq!(call(&mut hle, "SS_LoadSound", 0, &[path.as_str().into(), 0.into()]), "1");
+        // games pass handles back as strings
+        let sound = &[Value::from("1")];
+        assert_eq!(call(&mut hle, "SS_GetSoundLength", 0, sound), "2000");
+        assert_eq!(call(&mut hle, "SS_LoadSound", 0, &["not/a/sound.ogg".into(), 0.into()]), "0");
+
+        assert_eq!(call(&mut hle, "SS_PlaySound", 0, sound), "1");
+        assert_eq!(call(&mut hle, "SS_GetSoundPosition", 500, sound), "500");
+        assert_eq!(call(&mut hle, "SS_PauseSound", 500, sound), "1");
+        assert_eq!(call(&mut hle, "SS_IsSoundPaused", 900, sound), "1");
+        assert_eq!(call(&mut hle, "SS_IsSoundPlaying", 900, sound), "0");
+        assert_eq!(call(&mut hle, "SS_ResumeSound", 1000, sound), "1");
+        assert_eq!(call(&mut hle, "SS_IsSoundPlaying", 2499, sound), "1");
+        assert_eq!(call(&mut hle, "SS_IsSoundPlaying", 2500, sound), "0");
+        assert_eq!(call(&mut hle, "SS_GetSoundPosition", 9000, sound), "2000");
+
+        assert_eq!(call(&mut hle, "SS_LoopSound", 3000, sound), "1");
+        assert_eq!(call(&mut hle, "SS_IsSoundLooping", 3000, sound), "1");
+        assert_eq!(call(&mut hle, "SS_GetSoundPosition", 8500, sound), "1500");
+        assert_eq!(call(&mut hle, "SS_SetSoundPosition", 8500, &[sound[0].clone(), 1200.into()]), "1");
+        assert_eq!(call(&mut hle, "SS_GetSoundPosition", 8600, sound), "1300");
+        // past the end is the end, which for a loop is the start again
+        assert_eq!(call(&mut hle, "SS_SetSoundPosition", 8600, &[sound[0].clone(), 99999.into()]), "1");
+        assert_eq!(call(&mut hle, "SS_GetSoundPosition", 8700, sound), "100");
+        assert_eq!(call(&mut hle, "SS_StopSound", 9000, sound), "1");
+        assert_eq!(call(&mut hle, "SS_IsSoundPlaying", 9000, sound), "0");
+        assert_eq!(call(&mut hle, "SS_GetSoundPosition", 9000, sound), "0");
+
+        assert_eq!(call(&mut hle, "SS_SetSoundVol", 0, &[sound[0].clone(), 20000.into()]), "1");
+        assert_eq!(call(&mut hle, "SS_GetSoundVol", 0, sound), "10000");
+        assert_eq!(call(&mut hle, "SS_SetSoundFreq", 0, &[sound[0].clone(), 22050.into()]), "1");
+        assert_eq!(call(&mut hle, "SS_GetSoundFreq", 0, sound), "22050");
+        assert_eq!(call(&mut hle, "SS_FreeSound", 0, sound), "1");
+        assert_eq!(call(&mut hle, "SS_PlaySound", 0, sound), "0");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,30 @@
+//! Bits of the Windows API that games commonly call directly with external_define.
+
+use super::{Context, Library, State as HleState};
+use crate::gml::Value;
+
+// anything else in these goes to the real library, or is unavailable where there isn't one
+
+pub const KERNEL32: Library = Library { functions: &[("Sleep", sleep), ("Beep", beep)], plays_sound: false };
+
+pub const USER32: Library = Library { functions: &[("MessageBeep", message_beep)], plays_sound: false };
+
+pub const WINMM: Library =
+    Library { functions: &[("timeBeginPeriod", time_period), ("timeEndPeriod", time_period)], plays_sound: false };
+
+fn sleep(_hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    // the frame limiter is in charge of timing, and sleeping would only slow down replays
+    0.into()
+}
+
+fn beep(_hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    1.into()
+}
+
+fn message_beep(_hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    1.into()
+}
+
+fn time_period(_hle: &mut HleState, _context: &Context, _args: &[Value]) -> Value {
+    0.into() // TIMERR_NOERROR
+}
@@ -0,0 +1,451 @@
+//! Runs 32-bit Windows DLLs in an emulated x86 CPU, for platforms that can't load them natively.
+//! Only self-contained DLLs will work: anything they import from Windows goes through the small shim in
+//! `shim.rs`, and everything else has to be sitting next to them so it can be loaded too.
+//! All the state lives in `Emulator`, so it can be saved and restored along with savestates.
+
+mod cpu;
+mod fpu;
+mod heap;
+mod memory;
+mod pe;
+mod shim;
+mod sse;
+
+use crate::gml::file::{PathResolver, Root, Roots, Vfs};
+use cpu::{Cpu, EAX, ESP};
+use heap::Heap;
+use memory::Memory;
+use pe::{Image, Import, Symbol};
+use serde::{Deserialize, Serialize};
+use shared::dll;
+use std::path::{Path, PathBuf};
+
+const STACK_BASE: u32 = 0x0010_0000;
+const STACK_SIZE: u32 = 0x0010_0000;
+const EXE_BASE: u32 = 0x0040_0000; // fake module for GetModuleHandle(NULL)
+const HEAP_BASE: u32 = 0x2000_0000;
+const HEAP_RESERVED: u32 = 0x1000_0000;
+const SYSTEM_MODULE_BASE: u32 = 0x7FC0_0000; // fake handles for shimmed DLLs, 64K apart
+const SYSTEM_MODULE_SPACE: u32 = 0x0030_0000;
+const RETURN_TRAP: u32 = 0x7FF0_0000; // returning here ends a call
+const THUNK_BASE: u32 = 0x7FF0_0010; // jumping here calls into the shim, 4 bytes per function
+const THUNK_LIMIT: u32 = 0x7FFD_0000;
+const TEB_BASE: u32 = 0x7FFD_E000;
+const PEB_BASE: u32 = 0x7FFD_F000;
+
+const TEB_STACK_BASE: u32 = 0x04;
+const TEB_STACK_LIMIT: u32 = 0x08;
+const TEB_SELF: u32 = 0x18;
+const TEB_PROCESS_ID: u32 = 0x20;
+const TEB_THREAD_ID: u32 = 0x24;
+const TEB_TLS_POINTER: u32 = 0x2C;
+const TEB_PEB: u32 = 0x30;
+const TEB_LAST_ERROR: u32 = 0x34;
+const TEB_TLS_SLOTS: u32 = 0xE10;
+const PEB_IMAGE_BASE: u32 = 0x08;
+
+const PROCESS_ID: u32 = 0x1000;
+const THREAD_ID: u32 = 0x1004;
+
+/// How many modules can use static TLS.
+const STATIC_TLS_SLOTS: u32 = 64;
+
+/// How many DLLs can be loaded, including ones that failed to initialise.
+const MODULE_LIMIT: usize = 256;
+
+const DLL_PROCESS_ATTACH: u32 = 1;
+
+/// How many instructions a call can run for before it's assumed to be stuck.
+const INSTRUCTION_LIMIT: u64 = 2_000_000_000;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Emulator {
+    initialised: bool,
+    cpu: Cpu,
+    mem: Memory,
+    heap: Heap,
+    modules: Vec<Module>,
+    thunks: Vec<Thunk>,
+    static_tls_count: u32,
+    shim: shim::State,
+    // where the game's running, which a savestate can't know
+    #[serde(skip)]
+    files: Files,
+}
+
+/// How to find DLLs on the host, and what to keep their paths relative to.
+#[derive(Clone, Default)]
+struct Files {
+    roots: Roots,
+    paths: PathResolver,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Module {
+    root: Root,
+    path: PathBuf, // relative to `root`, so a savestate still knows it when the game's been moved
+    name: String,  // lowercase file name
+    image: Image,
+    error: Option<String>, // if it failed to initialise, so it fails the same way next time
+}
+
+/// A shim function an import got bound to, which may or may not exist.
+#[derive(Clone, Serialize, Deserialize)]
+struct Thunk {
+    dll: String,
+    name: String,
+}
+
+impl Emulator {
+    /// Loads the DLL at `path` (as the game wrote it) if it isn't loaded already, and finds one of its exports.
+    pub fn find_function(&mut self, path: &str, name: &[u8], vfs: &Vfs, paths: &PathResolver) -> Result<u32, String> {
+        self.files = Files { roots: vfs.roots().clone(), paths: paths.clone() };
+        let index = self.load(path)?;
+        self.modules[index].image.export(name).ok_or_else(|| {
+            format!("{} doesn't export a function called {}", self.modules[index].name, String::from_utf8_lossy(name))
+        })
+    }
+
+    /// Calls a function the way GameMaker does, with reals passed as doubles and strings as char pointers.
+    /// Both calling conventions push arguments the same way and the stack gets restored afterwards,
+    /// so it doesn't matter which one the function actually uses.
+    pub fn call(&mut self, address: u32, args: &[dll::Value], res_type: dll::ValueType) -> Result<dll::Value, String> {
+        let esp = self.cpu.regs[ESP];
+        let mut strings = Vec::new();
+        let result = self.push_args(args, &mut strings).and_then(|_| self.run_from(address));
+        self.cpu.regs[ESP] = esp;
+        let result = result.and_then(|_| match res_type {
+            dll::ValueType::Real => Ok(dll::Value::Real(self.cpu.fpu.pop())),
+            dll::ValueType::Str => match self.cpu.regs[EAX] {
+                0 => Ok("".into()),
+                ptr => self.mem.read_cstr(ptr).map(|s| s.as_slice().into()),
+            },
+        });
+        for string in strings {
+            self.heap.free(string);
+        }
+        result
+    }
+
+    fn push_args(&mut self, args: &[dll::Value], strings: &mut Vec<u32>) -> Result<(), String> {
+        for arg in args.iter().rev() {
+            match arg {
+                dll::Value::Real(x) => {
+                    let bits = x.to_bits();
+                    self.cpu.push(&mut self.mem, (bits >> 32) as u32)?;
+                    self.cpu.push(&mut self.mem, bits as u32)?;
+                },
+                dll::Value::Str(s) => {
+                    // this is already in delphi's format with the length in front
+                    let ptr = self.heap.alloc(&mut self.mem, s.len() as u32)?;
+                    self.mem.write(ptr, s)?;
+                    strings.push(ptr);
+                    self.cpu.push(&mut self.mem, ptr + 4)?;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls a function with 32-bit arguments, returning eax. Shim functions use this to call callbacks.
+    fn call_address(&mut self, address: u32, args: &[u32]) -> Result<u32, String> {
+        let esp = self.cpu.regs[ESP];
+        let result = args.iter().rev().try_for_each(|&arg| self.cpu.push(&mut self.mem, arg));
+        let result = result.and_then(|_| self.run_from(address));
+        self.cpu.regs[ESP] = esp;
+        result.map(|_| self.cpu.regs[EAX])
+    }
+
+    /// Runs the function at `address` with whatever's already on the stack until it returns.
+    fn run_from(&mut self, address: u32) -> Result<(), String> {
+        let eip = self.cpu.eip;
+        self.cpu.push(&mut self.mem, RETURN_TRAP)?;
+        self.cpu.eip = address;
+        let limit = self.cpu.ticks + INSTRUCTION_LIMIT;
+        let result = loop {
+            match self.cpu.eip {
+                RETURN_TRAP => break Ok(()),
+                addr @ THUNK_BASE..=THUNK_LIMIT => {
+                    if let Err(e) = self.call_thunk(addr) {
+                        break Err(e)
+                    }
+                },
+                _ => {
+                    if let Err(e) = self.cpu.step(&mut self.mem) {
+                        break Err(e)
+                    }
+                    if self.cpu.ticks > limit {
+                        break Err("DLL function took too long to run".into())
+                    }
+                },
+            }
+        };
+        self.cpu.eip = eip;
+        result
+    }
+
+    fn call_thunk(&mut self, address: u32) -> Result<(), String> {
+        let thunk = self
+            .thunks
+            .get(((address - THUNK_BASE) / 4) as usize)
+            .ok_or_else(|| format!("jumped to invalid address {:#010X}", address))?;
+        let (function, call_conv, arg_bytes) = shim::find(&thunk.dll, &thunk.name)
+            .ok_or_else(|| format!("{}!{} isn't supported", thunk.dll, thunk.name))?;
+        let result = function(self)?;
+        let return_address = self.cpu.pop(&self.mem)?;
+        if let dll::CallConv::Stdcall = call_conv {
+            self.cpu.regs[ESP] += arg_bytes;
+        }
+        self.cpu.eip = return_address;
+        match result {
+            shim::Return::Int(x) => self.cpu.regs[EAX] = x,
+            shim::Return::Long(x) => {
+                self.cpu.regs[EAX] = x as u32;
+                self.cpu.regs[cpu::EDX] = (x >> 32) as u32;
+            },
+            shim::Return::Float(x) => self.cpu.fpu.push(x),
+        }
+        Ok(())
+    }
+
+    /// Gets the thunk address for a shim function, making one if it's the first time it's been asked for.
+    fn thunk(&mut self, dll: &str, name: &str) -> Result<u32, String> {
+        let index = match self.thunks.iter().position(|t| t.dll == dll && t.name == name) {
+            Some(index) => index,
+            None => {
+                self.thunks.push(Thunk { dll: dll.into(), name: name.into() });
+                self.thunks.len() - 1
+            },
+        };
+        let address = THUNK_BASE + index as u32 * 4;
+        if address < THUNK_LIMIT { Ok(address) } else { Err("too many imported functions".into()) }
+    }
+
+    /// Gets the nth stack argument of the shim function being called.
+    fn arg(&self, n: u32) -> Result<u32, String> {
+        self.mem.read_u32(self.cpu.regs[ESP] + 4 + n * 4)
+    }
+
+    /// Gets a double taking up stack arguments n and n + 1.
+    fn arg_f64(&self, n: u32) -> Result<f64, String> {
+        self.mem.read_f64(self.cpu.regs[ESP] + 4 + n * 4)
+    }
+
+    fn set_last_error(&mut self, error: u32) -> Result<(), String> {
+        self.mem.write_u32(TEB_BASE + TEB_LAST_ERROR, error)
+    }
+
+    /// Sets up the stack, heap and thread environment the first time a DLL gets loaded.
+    fn init(&mut self) -> Result<(), String> {
+        if self.initialised {
+            return Ok(())
+        }
+        let mem = &mut self.mem;
+        mem.map(STACK_BASE, STACK_SIZE, STACK_SIZE)?;
+        mem.map(EXE_BASE, 0x1000, 0x1000)?;
+        mem.write(EXE_BASE, b"MZ")?;
+        mem.map(SYSTEM_MODULE_BASE, 0, SYSTEM_MODULE_SPACE)?;
+        mem.map(RETURN_TRAP, 0, THUNK_LIMIT - RETURN_TRAP)?;
+        mem.map(TEB_BASE, PEB_BASE + 0x1000 - TEB_BASE, PEB_BASE + 0x1000 - TEB_BASE)?;
+        self.heap = Heap::new(mem, HEAP_BASE, HEAP_RESERVED)?;
+
+        let tls_pointers = self.heap.alloc(&mut self.mem, STATIC_TLS_SLOTS * 4)?;
+        let mem = &mut self.mem;
+        mem.write_u32(TEB_BASE, u32::MAX)?; // end of the SEH chain
+        mem.write_u32(TEB_BASE + TEB_STACK_BASE, STACK_BASE + STACK_SIZE)?;
+        mem.write_u32(TEB_BASE + TEB_STACK_LIMIT, STACK_BASE)?;
+        mem.write_u32(TEB_BASE + TEB_SELF, TEB_BASE)?;
+        mem.write_u32(TEB_BASE + TEB_PROCESS_ID, PROCESS_ID)?;
+        mem.write_u32(TEB_BASE + TEB_THREAD_ID, THREAD_ID)?;
+        mem.write_u32(TEB_BASE + TEB_TLS_POINTER, tls_pointers)?;
+        mem.write_u32(TEB_BASE + TEB_PEB, PEB_BASE)?;
+        mem.write_u32(PEB_BASE + PEB_IMAGE_BASE, EXE_BASE)?;
+
+        self.cpu.regs[ESP] = STACK_BASE + STACK_SIZE;
+        self.cpu.fs_base = TEB_BASE;
+        self.shim.init(&mut self.heap, &mut self.mem)?;
+        self.initialised = true;
+        Ok(())
+    }
+
+    /// Loads a DLL and any DLLs next to it that it imports, returning its index in `modules`.
+    fn load(&mut self, path: &str) -> Result<usize, String> {
+        let path = self.files.paths.resolve(path);
+        if !path.is_file() {
+            return Err(format!("couldn't find {}", path.display()))
+        }
+        let (root, relative) = self.files.roots.locate(&path);
+        if let Some(index) = self.modules.iter().position(|m| m.root == root && m.path == relative) {
+            return match &self.modules[index].error {
+                Some(e) => Err(e.clone()),
+                None => Ok(index),
+            }
+        }
+        if self.modules.len() >= MODULE_LIMIT {
+            return Err(format!("couldn't load {}: too many DLLs", path.display()))
+        }
+        let file = std::fs::read(&path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        self.init()?;
+        let (image, imports) = Image::load(&mut self.mem, &file)?;
+        let name = path.file_name().map(|n| n.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+        let index = self.modules.len();
+        self.modules.push(Module { root, path: relative, name, image, error: None });
+        let result = self.link(index, imports).and_then(|_| self.attach(index));
+        if let Err(e) = &result {
+            self.modules[index].error = Some(e.clone());
+        }
+        result.map(|_| index)
+    }
+
+    fn link(&mut self, index: usize, imports: Vec<Import>) -> Result<(), String> {
+        let module = &self.modules[index];
+        let dir = self.files.roots.host_path(module.root, module.path.parent().unwrap_or_else(|| Path::new("")));
+        for import in imports {
+            let dll = String::from_utf8_lossy(&import.dll_name).to_ascii_lowercase();
+            let name = match &import.symbol {
+                Symbol::Name(name) => String::from_utf8_lossy(name).into_owned(),
+                Symbol::Ordinal(ordinal) => format!("#{}", ordinal),
+            };
+            let address = if shim::is_system_dll(&dll) {
+                self.thunk(&dll, &name)?
+            } else {
+                let module = match self.modules.iter().position(|m| m.name == dll) {
+                    Some(module) => module,
+                    None => self
+                        .load(&dir.join(&dll).to_string_lossy())
+                        .map_err(|e| format!("couldn't load {}: {}", dll, e))?,
+                };
+                let image = &self.modules[module].image;
+                match &import.symbol {
+                    Symbol::Name(name) => image.export(name),
+                    Symbol::Ordinal(ordinal) => image.export_ordinal(u32::from(*ordinal)),
+                }
+                .ok_or_else(|| format!("{} doesn't export {}", dll, name))?
+            };
+            self.mem.write_u32(import.iat_address, address)?;
+        }
+        Ok(())
+    }
+
+    /// Sets up static TLS and runs the DLL's initialisation.
+    fn attach(&mut self, index: usize) -> Result<(), String> {
+        let image = self.modules[index].image.clone();
+        if let Some(tls) = &image.tls {
+            let slot = self.static_tls_count;
+            if slot >= STATIC_TLS_SLOTS {
+                return Err("too many DLLs using thread-local storage".into())
+            }
+            self.static_tls_count += 1;
+            let template = self
+                .mem
+                .slice(tls.template_start, tls.template_end.saturating_sub(tls.template_start) as usize)?
+                .to_vec();
+            let block = self.heap.alloc(&mut self.mem, template.len() as u32 + tls.zero_fill)?;
+            self.mem.write(block, &template)?;
+            self.mem.write_u32(tls.index_address, slot)?;
+            let tls_pointers = self.mem.read_u32(TEB_BASE + TEB_TLS_POINTER)?;
+            self.mem.write_u32(tls_pointers + slot * 4, block)?;
+            if tls.callbacks != 0 {
+                for i in 0.. {
+                    match self.mem.read_u32(tls.callbacks + i * 4)? {
+                        0 => break,
+                        callback => self.call_address(callback, &[image.base, DLL_PROCESS_ATTACH, 0])?,
+                    };
+                }
+            }
+        }
+        if let Some(entry_point) = image.entry_point {
+            if self.call_address(entry_point, &[image.base, DLL_PROCESS_ATTACH, 0])? == 0 {
+                return Err("DllMain failed".into())
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pe::tests::{Fixture, CODE_RVA};
+
+    #[test]
+    fn imports_are_bound() {
+        let dir = std::env::temp_dir().join(format!("gm8emulator_x86_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // fld qword [seven]; ret; seven: dq 7.0
+        // it wants to go where the fake exe is, so it has to be relocated
+        let mut code = vec![0xDD, 0x05];
+        code.extend((EXE_BASE + CODE_RVA + 8).to_le_bytes());
+        code.extend([0xC3, 0x90]);
+        code.extend(7.0f64.to_le_bytes());
+        let helper =
+            Fixture { base: EXE_BASE, code, exports: vec![("seven", 0)], relocations: vec![2], ..Default::default() };
+        std::fs::write(dir.join("Helper.dll"), helper.build()).unwrap();
+
+        // seven: jmp [helper.dll!seven]
+        // add: fld qword [esp+4]; fadd qword [esp+12]; ret
+        // DllMain: mov eax, 1; ret 12
+        let base = 0x1000_0000;
+        let mut code = vec![0xFF, 0x25];
+        code.extend((base + Fixture::iat(0)).to_le_bytes());
+        code.extend([0xDD, 0x44, 0x24, 0x04, 0xDC, 0x44, 0x24, 0x0C, 0xC3]);
+        code.extend([0xB8, 0x01, 0x00, 0x00, 0x00, 0xC2, 0x0C, 0x00]);
+        let main = Fixture {
+            base,
+            code,
+            entry_point: Some(15),
+            exports: vec![("seven", 0), ("add", 6)],
+            imports: vec![("HELPER.DLL", "seven"), ("kernel32.dll", "GetTickCount")],
+            relocations: vec![2],
+        };
+        std::fs::write(dir.join("Main.dll"), main.build()).unwrap();
+
+        let mut vfs = Vfs::new(false);
+        vfs.set_roots(&dir, &dir.join("temp"));
+        let paths = PathResolver::default();
+        let mut emu = Emulator::default();
+        let main_path = dir.join(if cfg!(target_os = "windows") { "Main.dll" } else { "MAIN.DLL" });
+        let seven = emu.find_function(&main_path.to_string_lossy(), b"seven", &vfs, &paths).unwrap();
+        let add = emu.find_function(&main_path.to_string_lossy(), b"add", &vfs, &paths).unwrap();
+        assert_eq!(emu.modules.len(), 2);
+        assert!(emu.find_function(&main_path.to_string_lossy(), b"subtract", &vfs, &paths).is_err());
+        assert!(emu.find_function(&dir.join("missing.dll").to_string_lossy(), b"add", &vfs, &paths).is_err());
+
+        // both kept relative to the game, so a savestate can find them wherever it's loaded
+        let modules = emu.modules.iter().map(|m| (m.root, m.path.clone(), m.name.as_str())).collect::<Vec<_>>();
+        assert_eq!(modules, [
+            (Root::Game, PathBuf::from("Main.dll"), "main.dll"),
+            (Root::Game, PathBuf::from("Helper.dll"), "helper.dll")
+        ]);
+        assert_ne!(emu.modules[1].image.base, EXE_BASE);
+
+        // imports from next to it go straight there, and system ones go to the shim
+        let main_base = emu.modules[0].image.base;
+        assert_eq!(
+            emu.mem.read_u32(main_base + Fixture::iat(0)).unwrap(),
+            emu.modules[1].image.export(b"seven").unwrap()
+        );
+        assert!((THUNK_BASE..THUNK_LIMIT).contains(&emu.mem.read_u32(main_base + Fixture::iat(1)).unwrap()));
+
+        let real = |x: f64| dll::Value::Real(x);
+        assert!(matches!(emu.call(seven, &[], dll::ValueType::Real), Ok(dll::Value::Real(x)) if x == 7.0));
+        let sum = emu.call(add, &[real(1.5), real(2.25)], dll::ValueType::Real);
+        assert!(matches!(sum, Ok(dll::Value::Real(x)) if x == 3.75));
+
+        // a restored emulator gets given the files again, and finds the DLL already loaded
+        let mut restored = emu.clone();
+        restored.files = Files::default();
+        restored.find_function(&main_path.to_string_lossy(), b"add", &vfs, &paths).unwrap();
+        assert_eq!(restored.modules.len(), 2);
+
+        // and there's only so much room for DLLs
+        let module = emu.modules[1].clone();
+        emu.modules.resize(MODULE_LIMIT, module);
+        std::fs::copy(dir.join("Helper.dll"), dir.join("another.dll")).unwrap();
+        assert!(emu.find_function(&dir.join("another.dll").to_string_lossy(), b"seven", &vfs, &paths).is_err());
+        assert!(emu.find_function(&main_path.to_string_lossy(), b"add", &vfs, &paths).is_ok());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
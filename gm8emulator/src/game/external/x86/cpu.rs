@@ -0,0 +1,1235 @@
+//! The integer half of the interpreter. x87 and SSE instructions live in their own modules.
+
+use super::{fpu::Fpu, memory::Memory};
+use serde::{Deserialize, Serialize};
+
+pub const EAX: usize = 0;
+pub const ECX: usize = 1;
+pub const EDX: usize = 2;
+pub const EBX: usize = 3;
+pub const ESP: usize = 4;
+pub const EBP: usize = 5;
+pub const ESI: usize = 6;
+pub const EDI: usize = 7;
+
+pub const CF: u32 = 1 << 0;
+pub const PF: u32 = 1 << 2;
+pub const AF: u32 = 1 << 4;
+pub const ZF: u32 = 1 << 6;
+pub const SF: u32 = 1 << 7;
+pub const DF: u32 = 1 << 10;
+pub const OF: u32 = 1 << 11;
+
+const EFLAGS_FIXED: u32 = 1 << 1; // reserved bit that always reads as 1
+const EFLAGS_IF: u32 = 1 << 9;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Cpu {
+    pub regs: [u32; 8],
+    pub eip: u32,
+    pub eflags: u32,
+    pub fs_base: u32,
+    pub fpu: Fpu,
+    pub xmm: [[u64; 2]; 8],
+    pub mxcsr: u32,
+    /// How many instructions have been run, which doubles as a deterministic clock.
+    pub ticks: u64,
+}
+
+#[derive(Clone, Copy)]
+pub enum Operand {
+    Reg(u8),
+    Mem(u32),
+}
+
+/// Prefix state for the instruction currently being decoded.
+pub struct Inst {
+    pub opsize: u8,
+    pub rep: Option<u8>,
+    pub seg_base: u32,
+}
+
+pub fn mask(size: u8) -> u32 {
+    match size {
+        1 => 0xFF,
+        2 => 0xFFFF,
+        _ => 0xFFFF_FFFF,
+    }
+}
+
+fn sign_bit(size: u8) -> u32 {
+    1 << (u32::from(size) * 8 - 1)
+}
+
+pub fn sign_extend(value: u32, size: u8) -> u32 {
+    match size {
+        1 => value as u8 as i8 as u32,
+        2 => value as u16 as i16 as u32,
+        _ => value,
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self {
+            regs: [0; 8],
+            eip: 0,
+            eflags: EFLAGS_FIXED | EFLAGS_IF,
+            fs_base: 0,
+            fpu: Fpu::default(),
+            xmm: [[0; 2]; 8],
+            mxcsr: 0x1F80,
+            ticks: 0,
+        }
+    }
+}
+
+impl Cpu {
+    pub fn flag(&self, flag: u32) -> bool {
+        self.eflags & flag != 0
+    }
+
+    pub fn set_flag(&mut self, flag: u32, on: bool) {
+        if on {
+            self.eflags |= flag;
+        } else {
+            self.eflags &= !flag;
+        }
+    }
+
+    fn set_szp(&mut self, result: u32, size: u8) {
+        let result = result & mask(size);
+        self.set_flag(ZF, result == 0);
+        self.set_flag(SF, result & sign_bit(size) != 0);
+        self.set_flag(PF, (result as u8).count_ones() & 1 == 0);
+    }
+
+    fn set_logic_flags(&mut self, result: u32, size: u8) {
+        self.set_flag(CF, false);
+        self.set_flag(OF, false);
+        self.set_flag(AF, false);
+        self.set_szp(result, size);
+    }
+
+    pub fn reg(&self, reg: u8, size: u8) -> u32 {
+        let reg = usize::from(reg);
+        match size {
+            1 if reg < 4 => self.regs[reg] & 0xFF,
+            1 => (self.regs[reg - 4] >> 8) & 0xFF,
+            2 => self.regs[reg] & 0xFFFF,
+            _ => self.regs[reg],
+        }
+    }
+
+    pub fn set_reg(&mut self, reg: u8, size: u8, value: u32) {
+        let reg = usize::from(reg);
+        match size {
+            1 if reg < 4 => self.regs[reg] = (self.regs[reg] & !0xFF) | (value & 0xFF),
+            1 => self.regs[reg - 4] = (self.regs[reg - 4] & !0xFF00) | ((value & 0xFF) << 8),
+            2 => self.regs[reg] = (self.regs[reg] & !0xFFFF) | (value & 0xFFFF),
+            _ => self.regs[reg] = value,
+        }
+    }
+
+    pub fn read_rm(&self, mem: &Memory, op: Operand, size: u8) -> Result<u32, String> {
+        match op {
+            Operand::Reg(r) => Ok(self.reg(r, size)),
+            Operand::Mem(addr) => mem.read_sized(addr, size),
+        }
+    }
+
+    pub fn write_rm(&mut self, mem: &mut Memory, op: Operand, size: u8, value: u32) -> Result<(), String> {
+        match op {
+            Operand::Reg(r) => {
+                self.set_reg(r, size, value);
+                Ok(())
+            },
+            Operand::Mem(addr) => mem.write_sized(addr, size, value),
+        }
+    }
+
+    pub fn fetch8(&mut self, mem: &Memory) -> Result<u8, String> {
+        let value = mem.read_u8(self.eip)?;
+        self.eip = self.eip.wrapping_add(1);
+        Ok(value)
+    }
+
+    pub fn fetch16(&mut self, mem: &Memory) -> Result<u16, String> {
+        let value = mem.read_u16(self.eip)?;
+        self.eip = self.eip.wrapping_add(2);
+        Ok(value)
+    }
+
+    pub fn fetch32(&mut self, mem: &Memory) -> Result<u32, String> {
+        let value = mem.read_u32(self.eip)?;
+        self.eip = self.eip.wrapping_add(4);
+        Ok(value)
+    }
+
+    fn fetch_imm(&mut self, mem: &Memory, size: u8) -> Result<u32, String> {
+        match size {
+            1 => self.fetch8(mem).map(u32::from),
+            2 => self.fetch16(mem).map(u32::from),
+            _ => self.fetch32(mem),
+        }
+    }
+
+    /// Decodes a ModR/M byte (and SIB and displacement), returning the reg field and the r/m operand.
+    pub fn modrm(&mut self, mem: &Memory, inst: &Inst) -> Result<(u8, Operand), String> {
+        let byte = self.fetch8(mem)?;
+        let (md, reg, rm) = (byte >> 6, (byte >> 3) & 7, byte & 7);
+        if md == 3 {
+            return Ok((reg, Operand::Reg(rm)))
+        }
+        let mut addr = if rm == 4 {
+            let sib = self.fetch8(mem)?;
+            let (scale, index, base) = (sib >> 6, (sib >> 3) & 7, sib & 7);
+            let base = if base == 5 && md == 0 { self.fetch32(mem)? } else { self.regs[usize::from(base)] };
+            if index != 4 { base.wrapping_add(self.regs[usize::from(index)] << scale) } else { base }
+        } else if rm == 5 && md == 0 {
+            self.fetch32(mem)?
+        } else {
+            self.regs[usize::from(rm)]
+        };
+        match md {
+            1 => addr = addr.wrapping_add(self.fetch8(mem)? as i8 as u32),
+            2 => addr = addr.wrapping_add(self.fetch32(mem)?),
+            _ => (),
+        }
+        Ok((reg, Operand::Mem(addr.wrapping_add(inst.seg_base))))
+    }
+
+    pub fn push(&mut self, mem: &mut Memory, value: u32) -> Result<(), String> {
+        let esp = self.regs[ESP].wrapping_sub(4);
+        mem.write_u32(esp, value)?;
+        self.regs[ESP] = esp;
+        Ok(())
+    }
+
+    pub fn pop(&mut self, mem: &Memory) -> Result<u32, String> {
+        let value = mem.read_u32(self.regs[ESP])?;
+        self.regs[ESP] = self.regs[ESP].wrapping_add(4);
+        Ok(value)
+    }
+
+    fn push_sized(&mut self, mem: &mut Memory, size: u8, value: u32) -> Result<(), String> {
+        if size == 2 {
+            let esp = self.regs[ESP].wrapping_sub(2);
+            mem.write_u16(esp, value as u16)?;
+            self.regs[ESP] = esp;
+            Ok(())
+        } else {
+            self.push(mem, value)
+        }
+    }
+
+    fn pop_sized(&mut self, mem: &Memory, size: u8) -> Result<u32, String> {
+        if size == 2 {
+            let value = mem.read_u16(self.regs[ESP])?;
+            self.regs[ESP] = self.regs[ESP].wrapping_add(2);
+            Ok(u32::from(value))
+        } else {
+            self.pop(mem)
+        }
+    }
+
+    /// The eight classic ALU operations, in opcode order: add, or, adc, sbb, and, sub, xor, cmp.
+    pub fn alu(&mut self, op: u8, a: u32, b: u32, size: u8) -> u32 {
+        let (mask, sign) = (mask(size), sign_bit(size));
+        let (a, b) = (a & mask, b & mask);
+        match op {
+            0 | 2 => {
+                let carry = u64::from(op == 2 && self.flag(CF));
+                let wide = u64::from(a) + u64::from(b) + carry;
+                let result = wide as u32 & mask;
+                self.set_flag(CF, wide > u64::from(mask));
+                self.set_flag(OF, (a ^ result) & (b ^ result) & sign != 0);
+                self.set_flag(AF, (a ^ b ^ result) & 0x10 != 0);
+                self.set_szp(result, size);
+                result
+            },
+            3 | 5 | 7 => {
+                let borrow = u64::from(op == 3 && self.flag(CF));
+                let result = a.wrapping_sub(b).wrapping_sub(borrow as u32) & mask;
+                self.set_flag(CF, u64::from(a) < u64::from(b) + borrow);
+                self.set_flag(OF, (a ^ b) & (a ^ result) & sign != 0);
+                self.set_flag(AF, (a ^ b ^ result) & 0x10 != 0);
+                self.set_szp(result, size);
+                if op == 7 { a } else { result }
+            },
+            _ => {
+                let result = match op {
+                    1 => a | b,
+                    4 => a & b,
+                    _ => a ^ b,
+                };
+                self.set_logic_flags(result, size);
+                result
+            },
+        }
+    }
+
+    fn inc_dec(&mut self, value: u32, size: u8, dec: bool) -> u32 {
+        let carry = self.flag(CF);
+        let result = self.alu(if dec { 5 } else { 0 }, value, 1, size);
+        self.set_flag(CF, carry);
+        result
+    }
+
+    pub fn condition(&self, cc: u8) -> bool {
+        let result = match cc >> 1 {
+            0 => self.flag(OF),
+            1 => self.flag(CF),
+            2 => self.flag(ZF),
+            3 => self.flag(CF) || self.flag(ZF),
+            4 => self.flag(SF),
+            5 => self.flag(PF),
+            6 => self.flag(SF) != self.flag(OF),
+            _ => self.flag(ZF) || self.flag(SF) != self.flag(OF),
+        };
+        result != (cc & 1 != 0)
+    }
+
+    /// Shift and rotate group: rol, ror, rcl, rcr, shl, shr, sal, sar.
+    fn shift(&mut self, op: u8, value: u32, count: u32, size: u8) -> u32 {
+        let count = count & 0x1F;
+        if count == 0 {
+            return value
+        }
+        let (mask, sign) = (mask(size), sign_bit(size));
+        let bits = u32::from(size) * 8;
+        let value = value & mask;
+        match op {
+            0 | 1 => {
+                let c = count % bits;
+                let result = match (op, c) {
+                    (_, 0) => value,
+                    (0, c) => ((value << c) | (value >> (bits - c))) & mask,
+                    (_, c) => ((value >> c) | (value << (bits - c))) & mask,
+                };
+                if op == 0 {
+                    self.set_flag(CF, result & 1 != 0);
+                    self.set_flag(OF, (result & sign != 0) != self.flag(CF));
+                } else {
+                    self.set_flag(CF, result & sign != 0);
+                    self.set_flag(OF, (result ^ (result << 1)) & sign != 0);
+                }
+                result
+            },
+            2 | 3 => {
+                let mut result = value;
+                let mut carry = self.flag(CF);
+                for _ in 0..count % (bits + 1) {
+                    if op == 2 {
+                        let out = result & sign != 0;
+                        result = ((result << 1) | u32::from(carry)) & mask;
+                        carry = out;
+                    } else {
+                        let out = result & 1 != 0;
+                        result = (result >> 1) | if carry { sign } else { 0 };
+                        carry = out;
+                    }
+                }
+                self.set_flag(CF, carry);
+                if op == 2 {
+                    self.set_flag(OF, (result & sign != 0) != carry);
+                } else {
+                    self.set_flag(OF, (result ^ (result << 1)) & sign != 0);
+                }
+                result
+            },
+            4 | 6 => {
+                let wide = u64::from(value) << count;
+                let result = wide as u32 & mask;
+                self.set_flag(CF, (wide >> bits) & 1 != 0);
+                self.set_flag(OF, (result & sign != 0) != self.flag(CF));
+                self.set_szp(result, size);
+                result
+            },
+            5 => {
+                let result = if count >= bits { 0 } else { value >> count };
+                self.set_flag(CF, (u64::from(value) >> (count - 1)) & 1 != 0);
+                self.set_flag(OF, value & sign != 0);
+                self.set_szp(result, size);
+                result
+            },
+            _ => {
+                let signed = sign_extend(value, size) as i32;
+                let result = (signed >> count.min(31)) as u32 & mask;
+                self.set_flag(CF, (signed >> (count - 1).min(31)) & 1 != 0);
+                self.set_flag(OF, false);
+                self.set_szp(result, size);
+                result
+            },
+        }
+    }
+
+    /// shld and shrd
+    fn double_shift(&mut self, dest: u32, src: u32, count: u32, size: u8, left: bool) -> u32 {
+        let count = count & 0x1F;
+        let bits = u32::from(size) * 8;
+        if count == 0 || count > bits {
+            return dest
+        }
+        let mask = mask(size);
+        let result = if size == 4 {
+            let wide =
+                if left { (u64::from(dest) << 32) | u64::from(src) } else { (u64::from(src) << 32) | u64::from(dest) };
+            if left { (wide << count >> 32) as u32 } else { (wide >> count) as u32 }
+        } else {
+            let wide = if left { (dest << 16) | (src & 0xFFFF) } else { (src << 16) | (dest & 0xFFFF) };
+            if left { (wide << count >> 16) & mask } else { (wide >> count) & mask }
+        };
+        let carry = if left { (dest >> (bits - count)) & 1 } else { (dest >> (count - 1)) & 1 };
+        self.set_flag(CF, carry != 0);
+        self.set_flag(OF, (result ^ dest) & sign_bit(size) != 0);
+        self.set_szp(result, size);
+        result
+    }
+
+    /// Executes one instruction.
+    pub fn step(&mut self, mem: &mut Memory) -> Result<(), String> {
+        let start = self.eip;
+        self.ticks += 1;
+        self.execute(mem).map_err(|e| format!("{} (instruction at {:#010X})", e, start))
+    }
+
+    fn execute(&mut self, mem: &mut Memory) -> Result<(), String> {
+        let mut inst = Inst { opsize: 4, rep: None, seg_base: 0 };
+        let opcode = loop {
+            match self.fetch8(mem)? {
+                0x66 => inst.opsize = 2,
+                b @ 0xF2 | b @ 0xF3 => inst.rep = Some(b),
+                0x64 => inst.seg_base = self.fs_base,
+                0xF0 | 0x26 | 0x2E | 0x36 | 0x3E | 0x65 => (),
+                0x67 => return Err("address size override isn't supported".into()),
+                b => break b,
+            }
+        };
+        let size = if opcode & 1 == 0 { 1 } else { inst.opsize };
+
+        match opcode {
+            0x00..=0x3F if opcode & 7 < 6 => {
+                let op = opcode >> 3;
+                match opcode & 7 {
+                    0 | 1 => {
+                        let (reg, rm) = self.modrm(mem, &inst)?;
+                        let a = self.read_rm(mem, rm, size)?;
+                        let result = self.alu(op, a, self.reg(reg, size), size);
+                        if op != 7 {
+                            self.write_rm(mem, rm, size, result)?;
+                        }
+                    },
+                    2 | 3 => {
+                        let (reg, rm) = self.modrm(mem, &inst)?;
+                        let b = self.read_rm(mem, rm, size)?;
+                        let result = self.alu(op, self.reg(reg, size), b, size);
+                        if op != 7 {
+                            self.set_reg(reg, size, result);
+                        }
+                    },
+                    _ => {
+                        let imm = self.fetch_imm(mem, size)?;
+                        let result = self.alu(op, self.reg(0, size), imm, size);
+                        if op != 7 {
+                            self.set_reg(0, size, result);
+                        }
+                    },
+                }
+            },
+            0x40..=0x4F => {
+                let reg = opcode & 7;
+                let result = self.inc_dec(self.reg(reg, inst.opsize), inst.opsize, opcode >= 0x48);
+                self.set_reg(reg, inst.opsize, result);
+            },
+            0x50..=0x57 => self.push_sized(mem, inst.opsize, self.reg(opcode & 7, inst.opsize))?,
+            0x58..=0x5F => {
+                let value = self.pop_sized(mem, inst.opsize)?;
+                self.set_reg(opcode & 7, inst.opsize, value);
+            },
+            0x60 => {
+                let esp = self.regs[ESP];
+                for reg in 0..8 {
+                    self.push(mem, if reg == ESP { esp } else { self.regs[reg] })?;
+                }
+            },
+            0x61 => {
+                for reg in (0..8).rev() {
+                    let value = self.pop(mem)?;
+                    if reg != ESP {
+                        self.regs[reg] = value;
+                    }
+                }
+            },
+            0x68 => {
+                let imm = self.fetch_imm(mem, inst.opsize)?;
+                self.push_sized(mem, inst.opsize, imm)?;
+            },
+            0x6A => {
+                let imm = self.fetch8(mem)? as i8 as u32;
+                self.push_sized(mem, inst.opsize, imm)?;
+            },
+            0x69 | 0x6B => {
+                let (reg, rm) = self.modrm(mem, &inst)?;
+                let a = self.read_rm(mem, rm, inst.opsize)?;
+                let b = if opcode == 0x6B { self.fetch8(mem)? as i8 as u32 } else { self.fetch_imm(mem, inst.opsize)? };
+                let result = self.imul2(a, b, inst.opsize);
+                self.set_reg(reg, inst.opsize, result);
+            },
+            0x70..=0x7F => {
+                let rel = self.fetch8(mem)? as i8 as u32;
+                if self.condition(opcode & 0xF) {
+                    self.eip = self.eip.wrapping_add(rel);
+                }
+            },
+            0x80..=0x83 => {
+                let size = if opcode == 0x80 || opcode == 0x82 { 1 } else { inst.opsize };
+                let (op, rm) = self.modrm(mem, &inst)?;
+                let imm = if opcode == 0x83 { self.fetch8(mem)? as i8 as u32 } else { self.fetch_imm(mem, size)? };
+                let a = self.read_rm(mem, rm, size)?;
+                let result = self.alu(op, a, imm, size);
+                if op != 7 {
+                    self.write_rm(mem, rm, size, result)?;
+                }
+            },
+            0x84 | 0x85 => {
+                let (reg, rm) = self.modrm(mem, &inst)?;
+                let result = self.read_rm(mem, rm, size)? & self.reg(reg, size);
+                self.set_logic_flags(result, size);
+            },
+            0x86 | 0x87 => {
+                let (reg, rm) = self.modrm(mem, &inst)?;
+                let a = self.read_rm(mem, rm, size)?;
+                self.write_rm(mem, rm, size, self.reg(reg, size))?;
+                self.set_reg(reg, size, a);
+            },
+            0x88 | 0x89 => {
+                let (reg, rm) = self.modrm(mem, &inst)?;
+                self.write_rm(mem, rm, size, self.reg(reg, size))?;
+            },
+            0x8A | 0x8B => {
+                let (reg, rm) = self.modrm(mem, &inst)?;
+                let value = self.read_rm(mem, rm, size)?;
+                self.set_reg(reg, size, value);
+            },
+            0x8D => match self.modrm(mem, &inst)? {
+                (reg, Operand::Mem(addr)) => self.set_reg(reg, inst.opsize, addr.wrapping_sub(inst.seg_base)),
+                _ => return Err("lea with a register operand".into()),
+            },
+            0x8F => {
+                // the operand's address is calculated after esp is incremented
+                let value = self.pop_sized(mem, inst.opsize)?;
+                let (_, rm) = self.modrm(mem, &inst)?;
+                self.write_rm(mem, rm, inst.opsize, value)?;
+            },
+            0x90 => (),
+            0x91..=0x97 => {
+                let reg = opcode & 7;
+                let value = self.reg(reg, inst.opsize);
+                self.set_reg(reg, inst.opsize, self.reg(0, inst.opsize));
+                self.set_reg(0, inst.opsize, value);
+            },
+            0x98 => {
+                if inst.opsize == 2 {
+                    self.set_reg(0, 2, sign_extend(self.regs[EAX], 1));
+                } else {
+                    self.regs[EAX] = sign_extend(self.regs[EAX], 2);
+                }
+            },
+            0x99 => {
+                let sign = self.reg(0, inst.opsize) & sign_bit(inst.opsize) != 0;
+                self.set_reg(EDX as u8, inst.opsize, if sign { 0xFFFF_FFFF } else { 0 });
+            },
+            0x9B => (), // fwait
+            0x9C => self.push_sized(mem, inst.opsize, self.eflags)?,
+            0x9D => {
+                let value = self.pop_sized(mem, inst.opsize)?;
+                let mask = mask(inst.opsize) & 0x0000_0FD5;
+                self.eflags = (self.eflags & !mask) | (value & mask) | EFLAGS_FIXED;
+            },
+            0x9E => {
+                let mask = CF | PF | AF | ZF | SF;
+                self.eflags = (self.eflags & !mask) | ((self.regs[EAX] >> 8) & mask);
+            },
+            0x9F => self.set_reg(4, 1, (self.eflags & 0xFF) | EFLAGS_FIXED),
+            0xA0..=0xA3 => {
+                let addr = self.fetch32(mem)?.wrapping_add(inst.seg_base);
+                if opcode < 0xA2 {
+                    let value = mem.read_sized(addr, size)?;
+                    self.set_reg(0, size, value);
+                } else {
+                    mem.write_sized(addr, size, self.reg(0, size))?;
+                }
+            },
+            0xA4..=0xA7 | 0xAA..=0xAF => self.string_op(mem, &inst, opcode, size)?,
+            0xA8 | 0xA9 => {
+                let imm = self.fetch_imm(mem, size)?;
+                self.set_logic_flags(self.reg(0, size) & imm, size);
+            },
+            0xB0..=0xB7 => {
+                let imm = self.fetch8(mem)?;
+                self.set_reg(opcode & 7, 1, u32::from(imm));
+            },
+            0xB8..=0xBF => {
+                let imm = self.fetch_imm(mem, inst.opsize)?;
+                self.set_reg(opcode & 7, inst.opsize, imm);
+            },
+            0xC0 | 0xC1 | 0xD0..=0xD3 => {
+                let size = if opcode & 1 == 0 { 1 } else { inst.opsize };
+                let (op, rm) = self.modrm(mem, &inst)?;
+                let count = match opcode {
+                    0xC0 | 0xC1 => u32::from(self.fetch8(mem)?),
+                    0xD0 | 0xD1 => 1,
+                    _ => self.regs[ECX] & 0xFF,
+                };
+                let value = self.read_rm(mem, rm, size)?;
+                let result = self.shift(op, value, count, size);
+                self.write_rm(mem, rm, size, result)?;
+            },
+            0xC2 => {
+                let bytes = self.fetch16(mem)?;
+                self.eip = self.pop(mem)?;
+                self.regs[ESP] = self.regs[ESP].wrapping_add(u32::from(bytes));
+            },
+            0xC3 => self.eip = self.pop(mem)?,
+            0xC6 | 0xC7 => {
+                let (_, rm) = self.modrm(mem, &inst)?;
+                let imm = self.fetch_imm(mem, size)?;
+                self.write_rm(mem, rm, size, imm)?;
+            },
+            0xC8 => {
+                let bytes = self.fetch16(mem)?;
+                let level = self.fetch8(mem)?;
+                if level != 0 {
+                    return Err("enter with a nesting level isn't supported".into())
+                }
+                self.push(mem, self.regs[EBP])?;
+                self.regs[EBP] = self.regs[ESP];
+                self.regs[ESP] = self.regs[ESP].wrapping_sub(u32::from(bytes));
+            },
+            0xC9 => {
+                self.regs[ESP] = self.regs[EBP];
+                self.regs[EBP] = self.pop(mem)?;
+            },
+            0xCC => return Err("breakpoint hit".into()),
+            0xD8..=0xDF => self.fpu_op(mem, &inst, opcode)?,
+            0xE0..=0xE3 => {
+                let rel = self.fetch8(mem)? as i8 as u32;
+                let jump = if opcode == 0xE3 {
+                    self.regs[ECX] == 0
+                } else {
+                    self.regs[ECX] = self.regs[ECX].wrapping_sub(1);
+                    self.regs[ECX] != 0
+                        && match opcode {
+                            0xE0 => !self.flag(ZF),
+                            0xE1 => self.flag(ZF),
+                            _ => true,
+                        }
+                };
+                if jump {
+                    self.eip = self.eip.wrapping_add(rel);
+                }
+            },
+            0xE8 => {
+                let rel = self.fetch32(mem)?;
+                self.push(mem, self.eip)?;
+                self.eip = self.eip.wrapping_add(rel);
+            },
+            0xE9 => {
+                let rel = self.fetch32(mem)?;
+                self.eip = self.eip.wrapping_add(rel);
+            },
+            0xEB => {
+                let rel = self.fetch8(mem)? as i8 as u32;
+                self.eip = self.eip.wrapping_add(rel);
+            },
+            0xF5 => self.set_flag(CF, !self.flag(CF)),
+            0xF6 | 0xF7 => self.group3(mem, &inst, size)?,
+            0xF8 => self.set_flag(CF, false),
+            0xF9 => self.set_flag(CF, true),
+            0xFA | 0xFB => (), // cli and sti, nobody cares
+            0xFC => self.set_flag(DF, false),
+            0xFD => self.set_flag(DF, true),
+            0xFE | 0xFF => {
+                let (op, rm) = self.modrm(mem, &inst)?;
+                match op {
+                    0 | 1 => {
+                        let value = self.read_rm(mem, rm, size)?;
+                        let result = self.inc_dec(value, size, op == 1);
+                        self.write_rm(mem, rm, size, result)?;
+                    },
+                    2 if opcode == 0xFF => {
+                        let target = self.read_rm(mem, rm, 4)?;
+                        self.push(mem, self.eip)?;
+                        self.eip = target;
+                    },
+                    4 if opcode == 0xFF => self.eip = self.read_rm(mem, rm, 4)?,
+                    6 if opcode == 0xFF => {
+                        let value = self.read_rm(mem, rm, inst.opsize)?;
+                        self.push_sized(mem, inst.opsize, value)?;
+                    },
+                    _ => return Err(format!("unsupported instruction {:02X} /{}", opcode, op)),
+                }
+            },
+            0x0F => self.two_byte(mem, &inst)?,
+            _ => return Err(format!("unsupported instruction {:02X}", opcode)),
+        }
+        Ok(())
+    }
+
+    /// Two- and three-operand imul, which only keeps the low half.
+    fn imul2(&mut self, a: u32, b: u32, size: u8) -> u32 {
+        let wide = i64::from(sign_extend(a, size) as i32) * i64::from(sign_extend(b, size) as i32);
+        let result = wide as u32 & mask(size);
+        let overflow = i64::from(sign_extend(result, size) as i32) != wide;
+        self.set_flag(CF, overflow);
+        self.set_flag(OF, overflow);
+        result
+    }
+
+    /// test/not/neg/mul/imul/div/idiv
+    fn group3(&mut self, mem: &mut Memory, inst: &Inst, size: u8) -> Result<(), String> {
+        let (op, rm) = self.modrm(mem, inst)?;
+        let value = self.read_rm(mem, rm, size)?;
+        let bits = u32::from(size) * 8;
+        let mask = u64::from(mask(size));
+        // the double-width accumulator: ax for bytes, dx:ax for words, edx:eax for dwords
+        let acc = || -> u64 {
+            match size {
+                1 => u64::from(self.regs[EAX] & 0xFFFF),
+                2 => (u64::from(self.regs[EDX] & 0xFFFF) << 16) | u64::from(self.regs[EAX] & 0xFFFF),
+                _ => (u64::from(self.regs[EDX]) << 32) | u64::from(self.regs[EAX]),
+            }
+        };
+        let set_acc = |cpu: &mut Self, low: u64, high: u64| match size {
+            1 => cpu.set_reg(0, 2, (low as u32 & 0xFF) | ((high as u32 & 0xFF) << 8)),
+            _ => {
+                cpu.set_reg(EAX as u8, size, low as u32);
+                cpu.set_reg(EDX as u8, size, high as u32);
+            },
+        };
+        match op {
+            0 | 1 => {
+                let imm = self.fetch_imm(mem, size)?;
+                self.set_logic_flags(value & imm, size);
+            },
+            2 => self.write_rm(mem, rm, size, !value)?,
+            3 => {
+                let result = self.alu(5, 0, value, size);
+                self.set_flag(CF, value & mask as u32 != 0);
+                self.write_rm(mem, rm, size, result)?;
+            },
+            4 => {
+                let product = u64::from(self.reg(0, size)) * u64::from(value);
+                set_acc(self, product & mask, product >> bits);
+                self.set_flag(CF, product >> bits != 0);
+                self.set_flag(OF, product >> bits != 0);
+            },
+            5 => {
+                let product =
+                    i64::from(sign_extend(self.reg(0, size), size) as i32) * i64::from(sign_extend(value, size) as i32);
+                set_acc(self, product as u64 & mask, (product >> bits) as u64 & mask);
+                let overflow = i64::from(sign_extend(product as u32 & mask as u32, size) as i32) != product;
+                self.set_flag(CF, overflow);
+                self.set_flag(OF, overflow);
+            },
+            6 => {
+                if value == 0 {
+                    return Err("integer division by zero".into())
+                }
+                let dividend = acc();
+                let quotient = dividend / u64::from(value);
+                if quotient > mask {
+                    return Err("integer division overflow".into())
+                }
+                set_acc(self, quotient, dividend % u64::from(value));
+            },
+            _ => {
+                if value == 0 {
+                    return Err("integer division by zero".into())
+                }
+                let dividend = match size {
+                    1 => i64::from(acc() as u16 as i16),
+                    2 => i64::from(acc() as u32 as i32),
+                    _ => acc() as i64,
+                };
+                let divisor = i64::from(sign_extend(value, size) as i32);
+                let (quotient, remainder) = match (dividend.checked_div(divisor), dividend.checked_rem(divisor)) {
+                    (Some(q), Some(r)) => (q, r),
+                    _ => return Err("integer division overflow".into()),
+                };
+                let half = (mask >> 1) as i64;
+                if quotient > half || quotient < -half - 1 {
+                    return Err("integer division overflow".into())
+                }
+                set_acc(self, quotient as u64 & mask, remainder as u64 & mask);
+            },
+        }
+        Ok(())
+    }
+
+    /// movs/cmps/stos/lods/scas, with or without rep
+    fn string_op(&mut self, mem: &mut Memory, inst: &Inst, opcode: u8, size: u8) -> Result<(), String> {
+        let step = if self.flag(DF) { (size as u32).wrapping_neg() } else { u32::from(size) };
+        loop {
+            if inst.rep.is_some() {
+                if self.regs[ECX] == 0 {
+                    break
+                }
+                self.regs[ECX] = self.regs[ECX].wrapping_sub(1);
+            }
+            let (esi, edi) = (self.regs[ESI].wrapping_add(inst.seg_base), self.regs[EDI]);
+            let compares = match opcode {
+                0xA4 | 0xA5 => {
+                    let value = mem.read_sized(esi, size)?;
+                    mem.write_sized(edi, size, value)?;
+                    self.regs[ESI] = self.regs[ESI].wrapping_add(step);
+                    self.regs[EDI] = edi.wrapping_add(step);
+                    false
+                },
+                0xA6 | 0xA7 => {
+                    let (a, b) = (mem.read_sized(esi, size)?, mem.read_sized(edi, size)?);
+                    self.alu(7, a, b, size);
+                    self.regs[ESI] = self.regs[ESI].wrapping_add(step);
+                    self.regs[EDI] = edi.wrapping_add(step);
+                    true
+                },
+                0xAA | 0xAB => {
+                    mem.write_sized(edi, size, self.reg(0, size))?;
+                    self.regs[EDI] = edi.wrapping_add(step);
+                    false
+                },
+                0xAC | 0xAD => {
+                    let value = mem.read_sized(esi, size)?;
+                    self.set_reg(0, size, value);
+                    self.regs[ESI] = self.regs[ESI].wrapping_add(step);
+                    false
+                },
+                _ => {
+                    let value = mem.read_sized(edi, size)?;
+                    self.alu(7, self.reg(0, size), value, size);
+                    self.regs[EDI] = edi.wrapping_add(step);
+                    true
+                },
+            };
+            match inst.rep {
+                None => break,
+                Some(0xF3) if compares && !self.flag(ZF) => break,
+                Some(0xF2) if compares && self.flag(ZF) => break,
+                _ => self.ticks += 1,
+            }
+        }
+        Ok(())
+    }
+
+    fn two_byte(&mut self, mem: &mut Memory, inst: &Inst) -> Result<(), String> {
+        let opcode = self.fetch8(mem)?;
+        match opcode {
+            0x0B => return Err("ud2 hit".into()),
+            0x18..=0x1F => {
+                self.modrm(mem, inst)?; // prefetches and multi-byte nops
+            },
+            0x31 => {
+                self.regs[EAX] = self.ticks as u32;
+                self.regs[EDX] = (self.ticks >> 32) as u32;
+            },
+            0x40..=0x4F => {
+                let (reg, rm) = self.modrm(mem, inst)?;
+                let value = self.read_rm(mem, rm, inst.opsize)?;
+                if self.condition(opcode & 0xF) {
+                    self.set_reg(reg, inst.opsize, value);
+                }
+            },
+            0x80..=0x8F => {
+                let rel = self.fetch32(mem)?;
+                if self.condition(opcode & 0xF) {
+                    self.eip = self.eip.wrapping_add(rel);
+                }
+            },
+            0x90..=0x9F => {
+                let (_, rm) = self.modrm(mem, inst)?;
+                self.write_rm(mem, rm, 1, u32::from(self.condition(opcode & 0xF)))?;
+            },
+            0xA2 => self.cpuid(),
+            0xA3 | 0xAB | 0xB3 | 0xBB | 0xBA => {
+                let (reg, rm) = self.modrm(mem, inst)?;
+                let (op, offset) = if opcode == 0xBA {
+                    (reg, u32::from(self.fetch8(mem)?) & (u32::from(inst.opsize) * 8 - 1))
+                } else {
+                    ((opcode >> 3) & 3 | 4, self.reg(reg, inst.opsize))
+                };
+                // register bit offsets can reach outside the addressed dword
+                let (rm, bit) = match rm {
+                    Operand::Mem(addr) if opcode != 0xBA => {
+                        let bits = i64::from(inst.opsize) * 8;
+                        let signed = i64::from(sign_extend(offset, inst.opsize) as i32);
+                        let index = signed.div_euclid(bits);
+                        (Operand::Mem(addr.wrapping_add((index * bits / 8) as u32)), signed.rem_euclid(bits) as u32)
+                    },
+                    rm => (rm, offset & (u32::from(inst.opsize) * 8 - 1)),
+                };
+                let value = self.read_rm(mem, rm, inst.opsize)?;
+                self.set_flag(CF, (value >> bit) & 1 != 0);
+                let result = match op & 3 {
+                    1 => value | (1 << bit),
+                    2 => value & !(1 << bit),
+                    3 => value ^ (1 << bit),
+                    _ => return Ok(()),
+                };
+                self.write_rm(mem, rm, inst.opsize, result)?;
+            },
+            0xA4 | 0xA5 | 0xAC | 0xAD => {
+                let (reg, rm) = self.modrm(mem, inst)?;
+                let count = if opcode & 1 == 0 { u32::from(self.fetch8(mem)?) } else { self.regs[ECX] & 0xFF };
+                let dest = self.read_rm(mem, rm, inst.opsize)?;
+                let result = self.double_shift(dest, self.reg(reg, inst.opsize), count, inst.opsize, opcode < 0xA8);
+                self.write_rm(mem, rm, inst.opsize, result)?;
+            },
+            0xAF => {
+                let (reg, rm) = self.modrm(mem, inst)?;
+                let value = self.read_rm(mem, rm, inst.opsize)?;
+                let result = self.imul2(self.reg(reg, inst.opsize), value, inst.opsize);
+                self.set_reg(reg, inst.opsize, result);
+            },
+            0xB0 | 0xB1 => {
+                let size = if opcode == 0xB0 { 1 } else { inst.opsize };
+                let (reg, rm) = self.modrm(mem, inst)?;
+                let value = self.read_rm(mem, rm, size)?;
+                self.alu(7, self.reg(0, size), value, size);
+                if self.flag(ZF) {
+                    self.write_rm(mem, rm, size, self.reg(reg, size))?;
+                } else {
+                    self.set_reg(0, size, value);
+                }
+            },
+            0xB6 | 0xB7 | 0xBE | 0xBF => {
+                let src_size = if opcode & 1 == 0 { 1 } else { 2 };
+                let (reg, rm) = self.modrm(mem, inst)?;
+                let value = self.read_rm(mem, rm, src_size)?;
+                let value = if opcode >= 0xBE { sign_extend(value, src_size) } else { value };
+                self.set_reg(reg, inst.opsize, value);
+            },
+            0xBC | 0xBD => {
+                let (reg, rm) = self.modrm(mem, inst)?;
+                let value = self.read_rm(mem, rm, inst.opsize)?;
+                self.set_flag(ZF, value == 0);
+                if value != 0 {
+                    let index = if opcode == 0xBC { value.trailing_zeros() } else { 31 - value.leading_zeros() };
+                    self.set_reg(reg, inst.opsize, index);
+                }
+            },
+            0xC0 | 0xC1 => {
+                let size = if opcode == 0xC0 { 1 } else { inst.opsize };
+                let (reg, rm) = self.modrm(mem, inst)?;
+                let dest = self.read_rm(mem, rm, size)?;
+                let result = self.alu(0, dest, self.reg(reg, size), size);
+                self.set_reg(reg, size, dest);
+                self.write_rm(mem, rm, size, result)?;
+            },
+            0xC7 => match self.modrm(mem, inst)? {
+                (1, Operand::Mem(addr)) => {
+                    let value = mem.read_u64(addr)?;
+                    let expected = (u64::from(self.regs[EDX]) << 32) | u64::from(self.regs[EAX]);
+                    if value == expected {
+                        mem.write_u64(addr, (u64::from(self.regs[ECX]) << 32) | u64::from(self.regs[EBX]))?;
+                        self.set_flag(ZF, true);
+                    } else {
+                        self.regs[EAX] = value as u32;
+                        self.regs[EDX] = (value >> 32) as u32;
+                        self.set_flag(ZF, false);
+                    }
+                },
+                (op, _) => return Err(format!("unsupported instruction 0F C7 /{}", op)),
+            },
+            0xC8..=0xCF => {
+                let reg = usize::from(opcode & 7);
+                self.regs[reg] = self.regs[reg].swap_bytes();
+            },
+            _ => self.sse_op(mem, inst, opcode)?,
+        }
+        Ok(())
+    }
+
+    /// Pretends to be a plain old CPU with no SIMD, so libraries pick their x87 code paths.
+    fn cpuid(&mut self) {
+        let (a, b, c, d) = match self.regs[EAX] {
+            0 => (1, u32::from_le_bytes(*b"Genu"), u32::from_le_bytes(*b"ntel"), u32::from_le_bytes(*b"ineI")),
+            1 => (0x0000_0633, 0, 0, (1 << 0) | (1 << 4) | (1 << 8) | (1 << 15)), // fpu, tsc, cx8, cmov
+            _ => (0, 0, 0, 0),
+        };
+        self.regs[EAX] = a;
+        self.regs[EBX] = b;
+        self.regs[ECX] = c;
+        self.regs[EDX] = d;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: u32 = 0x1000;
+    const DATA: u32 = 0x4000;
+    const STACK: u32 = 0x8000;
+
+    /// Runs the code to the end, stopping early if an instruction faults.
+    fn try_run(code: &[u8], setup: impl FnOnce(&mut Cpu, &mut Memory)) -> (Cpu, Memory, Result<(), String>) {
+        let mut mem = Memory::default();
+        mem.map(CODE, 0x1000, 0x1000).unwrap();
+        mem.map(DATA, 0x1000, 0x1000).unwrap();
+        mem.map(STACK - 0x1000, 0x1000, 0x1000).unwrap();
+        mem.write(CODE, code).unwrap();
+        let mut cpu = Cpu { eip: CODE, ..Cpu::default() };
+        cpu.regs[ESP] = STACK;
+        setup(&mut cpu, &mut mem);
+        let mut result = Ok(());
+        while result.is_ok() && cpu.eip < CODE + code.len() as u32 {
+            result = cpu.step(&mut mem);
+        }
+        (cpu, mem, result)
+    }
+
+    fn run(code: &[u8], setup: impl FnOnce(&mut Cpu)) -> Cpu {
+        let (cpu, _, result) = try_run(code, |cpu, _| setup(cpu));
+        result.unwrap();
+        cpu
+    }
+
+    #[test]
+    fn arithmetic_flags() {
+        // add eax, ebx
+        let cpu = run(&[0x01, 0xD8], |cpu| {
+            cpu.regs[EAX] = 0xFFFF_FFFF;
+            cpu.regs[EBX] = 1;
+        });
+        assert_eq!(cpu.regs[EAX], 0);
+        assert!(cpu.flag(CF) && cpu.flag(ZF) && !cpu.flag(OF));
+
+        // sub al, 1 with al = 0x80
+        let cpu = run(&[0x2C, 0x01], |cpu| cpu.regs[EAX] = 0x80);
+        assert_eq!(cpu.regs[EAX], 0x7F);
+        assert!(cpu.flag(OF) && !cpu.flag(CF));
+
+        // neg ecx
+        let cpu = run(&[0xF7, 0xD9], |cpu| cpu.regs[ECX] = 5);
+        assert_eq!(cpu.regs[ECX], 5u32.wrapping_neg());
+        assert!(cpu.flag(CF) && cpu.flag(SF));
+    }
+
+    #[test]
+    fn loops_and_calls() {
+        // xor eax, eax; mov ecx, 10; l: add eax, ecx; loop l
+        let cpu = run(&[0x31, 0xC0, 0xB9, 0x0A, 0, 0, 0, 0x01, 0xC8, 0xE2, 0xFC], |_| ());
+        assert_eq!(cpu.regs[EAX], 55);
+
+        // call f; jmp end; f: mov eax, 7; ret; end:
+        let cpu = run(&[0xE8, 0x02, 0, 0, 0, 0xEB, 0x06, 0xB8, 0x07, 0, 0, 0, 0xC3], |_| ());
+        assert_eq!(cpu.regs[EAX], 7);
+        assert_eq!(cpu.regs[ESP], STACK);
+    }
+
+    #[test]
+    fn multiply_divide() {
+        // mul ebx
+        let cpu = run(&[0xF7, 0xE3], |cpu| {
+            cpu.regs[EAX] = 0x8000_0000;
+            cpu.regs[EBX] = 4;
+        });
+        assert_eq!((cpu.regs[EDX], cpu.regs[EAX]), (2, 0));
+
+        // cdq; idiv ecx
+        let cpu = run(&[0x99, 0xF7, 0xF9], |cpu| {
+            cpu.regs[EAX] = (-7i32) as u32;
+            cpu.regs[ECX] = 2;
+        });
+        assert_eq!((cpu.regs[EAX] as i32, cpu.regs[EDX] as i32), (-3, -1));
+    }
+
+    #[test]
+    fn shifts() {
+        // sar eax, 4
+        let cpu = run(&[0xC1, 0xF8, 0x04], |cpu| cpu.regs[EAX] = 0xF000_0010);
+        assert_eq!(cpu.regs[EAX], 0xFF00_0001);
+
+        // shld edx, eax, 8
+        let cpu = run(&[0x0F, 0xA4, 0xC2, 0x08], |cpu| {
+            cpu.regs[EDX] = 0x0012_3456;
+            cpu.regs[EAX] = 0xAB00_0000;
+        });
+        assert_eq!(cpu.regs[EDX], 0x1234_56AB);
+    }
+
+    #[test]
+    fn divide_faults() {
+        let fault = |code: &[u8], edx: u32, eax: u32, ecx: u32| {
+            let (cpu, _, result) = try_run(code, |cpu, _| {
+                cpu.regs[EDX] = edx;
+                cpu.regs[EAX] = eax;
+                cpu.regs[ECX] = ecx;
+            });
+            // a faulting divide leaves the accumulator alone
+            assert_eq!((cpu.regs[EDX], cpu.regs[EAX]), (edx, eax));
+            result.unwrap_err()
+        };
+
+        // div ecx
+        assert!(fault(&[0xF7, 0xF1], 0, 1, 0).starts_with("integer division by zero"));
+        assert!(fault(&[0xF7, 0xF1], 1, 0, 1).starts_with("integer division overflow"));
+        assert!(fault(&[0xF7, 0xF1], 5, 0, 5).starts_with("integer division overflow"));
+
+        // idiv ecx, where int_min / -1 doesn't fit
+        assert!(fault(&[0xF7, 0xF9], 0xFFFF_FFFF, 0x8000_0000, 0xFFFF_FFFF).starts_with("integer division overflow"));
+
+        // div cl, where the quotient has to fit in al
+        assert!(fault(&[0xF6, 0xF1], 0, 0x1000, 0x10).starts_with("integer division overflow"));
+        let cpu = run(&[0xF6, 0xF1], |cpu| {
+            cpu.regs[EAX] = 0xFF3;
+            cpu.regs[ECX] = 0x10;
+        });
+        assert_eq!(cpu.regs[EAX], 0x03FF);
+
+        // the one just below the limit is fine: edx:eax = 4 * 2^32 - 1, divided by 4
+        let cpu = run(&[0xF7, 0xF1], |cpu| {
+            cpu.regs[EDX] = 3;
+            cpu.regs[EAX] = 0xFFFF_FFFF;
+            cpu.regs[ECX] = 4;
+        });
+        assert_eq!((cpu.regs[EAX], cpu.regs[EDX]), (0xFFFF_FFFF, 3));
+    }
+
+    #[test]
+    fn string_ops() {
+        let source: Vec<u8> = (1..=16).collect();
+
+        // rep movsd
+        let (cpu, mem, result) = try_run(&[0xF3, 0xA5], |cpu, mem| {
+            mem.write(DATA, &source).unwrap();
+            cpu.regs[ESI] = DATA;
+            cpu.regs[EDI] = DATA + 0x100;
+            cpu.regs[ECX] = 4;
+        });
+        result.unwrap();
+        assert_eq!(mem.slice(DATA + 0x100, 16).unwrap(), &source[..]);
+        assert_eq!((cpu.regs[ECX], cpu.regs[ESI], cpu.regs[EDI]), (0, DATA + 16, DATA + 0x110));
+
+        // std; rep movsb, going backwards from the last byte
+        let (cpu, mem, result) = try_run(&[0xFD, 0xF3, 0xA4], |cpu, mem| {
+            mem.write(DATA, &source).unwrap();
+            cpu.regs[ESI] = DATA + 3;
+            cpu.regs[EDI] = DATA + 0x103;
+            cpu.regs[ECX] = 4;
+        });
+        result.unwrap();
+        assert_eq!(mem.slice(DATA + 0x100, 5).unwrap(), &[1, 2, 3, 4, 0]);
+        assert_eq!((cpu.regs[ECX], cpu.regs[ESI], cpu.regs[EDI]), (0, DATA - 1, DATA + 0xFF));
+
+        // rep stosd, which mustn't write past ecx dwords
+        let (cpu, mem, result) = try_run(&[0xF3, 0xAB], |cpu, _| {
+            cpu.regs[EAX] = 0xDEAD_BEEF;
+            cpu.regs[EDI] = DATA;
+            cpu.regs[ECX] = 3;
+        });
+        result.unwrap();
+        for i in 0..3 {
+            assert_eq!(mem.read_u32(DATA + i * 4).unwrap(), 0xDEAD_BEEF);
+        }
+        assert_eq!(mem.read_u32(DATA + 12).unwrap(), 0);
+        assert_eq!((cpu.regs[ECX], cpu.regs[EDI]), (0, DATA + 12));
+
+        // rep stosb with ecx = 0 does nothing
+        let (cpu, mem, result) = try_run(&[0xF3, 0xAA], |cpu, _| {
+            cpu.regs[EAX] = 0xFF;
+            cpu.regs[EDI] = DATA;
+        });
+        result.unwrap();
+        assert_eq!((mem.read_u8(DATA).unwrap(), cpu.regs[EDI]), (0, DATA));
+
+        // repne scasb, the strlen idiom: stops after the terminator with zf set
+        let (cpu, _, result) = try_run(&[0xF2, 0xAE], |cpu, mem| {
+            mem.write(DATA, b"abc\0").unwrap();
+            cpu.regs[EDI] = DATA;
+            cpu.regs[ECX] = 0xFFFF_FFFF;
+        });
+        result.unwrap();
+        assert!(cpu.flag(ZF));
+        assert_eq!((cpu.regs[ECX], cpu.regs[EDI]), (!4, DATA + 4));
+    }
+
+    #[test]
+    fn fpu_stack() {
+        // fld1; fld1; faddp st1; fldz; fcomip st1
+        let cpu = run(&[0xD9, 0xE8, 0xD9, 0xE8, 0xDE, 0xC1, 0xD9, 0xEE, 0xDF, 0xF1], |_| ());
+        assert!(cpu.flag(CF) && !cpu.flag(ZF) && !cpu.flag(PF));
+        assert_eq!(cpu.fpu.st(0), 2.0);
+        assert_eq!((cpu.fpu.status_word() >> 11) & 7, 7);
+
+        // fld1; fldpi; fxch st1; fsubp st1, which is st1 - st0
+        let cpu = run(&[0xD9, 0xE8, 0xD9, 0xEB, 0xD9, 0xC9, 0xDE, 0xE9], |_| ());
+        assert_eq!(cpu.fpu.st(0), std::f64::consts::PI - 1.0);
+        assert_eq!((cpu.fpu.status_word() >> 11) & 7, 7);
+
+        // fld1; fldz; fcompp; fnstsw ax, which only sets c0 for less-than and leaves the stack empty
+        let cpu = run(&[0xD9, 0xE8, 0xD9, 0xEE, 0xDE, 0xD9, 0xDF, 0xE0], |_| ());
+        assert_eq!(cpu.regs[EAX] & 0x4700, 0x0100);
+        assert_eq!((cpu.fpu.status_word() >> 11) & 7, 0);
+
+        // fld qword [DATA]; fist dword [DATA+8]; fisttp dword [DATA+12]
+        let store = |value: f64| {
+            let code = [0xDD, 0x05, 0x00, 0x40, 0, 0, 0xDB, 0x15, 0x08, 0x40, 0, 0, 0xDB, 0x0D, 0x0C, 0x40, 0, 0];
+            let (cpu, mem, result) = try_run(&code, |_, mem| mem.write_f64(DATA, value).unwrap());
+            result.unwrap();
+            assert_eq!((cpu.fpu.status_word() >> 11) & 7, 0);
+            (mem.read_u32(DATA + 8).unwrap() as i32, mem.read_u32(DATA + 12).unwrap() as i32)
+        };
+        // fist rounds to even by default, fisttp always truncates
+        assert_eq!(store(3.5), (4, 3));
+        assert_eq!(store(2.5), (2, 2));
+        assert_eq!(store(-2.7), (-3, -2));
+        // out of range gives the integer indefinite value
+        assert_eq!(store(1e10), (i32::MIN, i32::MIN));
+        assert_eq!(store(f64::NAN), (i32::MIN, i32::MIN));
+    }
+
+    #[test]
+    fn sse_conversions() {
+        let convert = |code: &[u8], eax: u32, xmm1: [u64; 2]| {
+            run(code, |cpu| {
+                cpu.regs[EAX] = eax;
+                cpu.xmm[0] = [0x1111_1111_2222_2222, 0x3333_3333_4444_4444];
+                cpu.xmm[1] = xmm1;
+            })
+        };
+        let sd = |x: f64| [x.to_bits(), 0];
+
+        // cvtsi2sd xmm0, eax only writes the low lane
+        let cpu = convert(&[0xF2, 0x0F, 0x2A, 0xC0], (-7i32) as u32, [0; 2]);
+        assert_eq!(cpu.xmm[0], [(-7.0f64).to_bits(), 0x3333_3333_4444_4444]);
+
+        // cvtsi2ss xmm0, eax rounds to f32 and keeps the rest of the register
+        let cpu = convert(&[0xF3, 0x0F, 0x2A, 0xC0], 16_777_217, [0; 2]);
+        assert_eq!(cpu.xmm[0][0], 0x1111_1111_0000_0000 | u64::from(16_777_216f32.to_bits()));
+
+        // cvttsd2si eax, xmm1 truncates, cvtsd2si eax, xmm1 rounds to even
+        let cvttsd2si = |x: f64| convert(&[0xF2, 0x0F, 0x2C, 0xC1], 0, sd(x)).regs[EAX] as i32;
+        let cvtsd2si = |x: f64| convert(&[0xF2, 0x0F, 0x2D, 0xC1], 0, sd(x)).regs[EAX] as i32;
+        assert_eq!((cvttsd2si(-2.7), cvtsd2si(-2.7)), (-2, -3));
+        assert_eq!((cvttsd2si(2.5), cvtsd2si(2.5)), (2, 2));
+        assert_eq!((cvttsd2si(3.5), cvtsd2si(3.5)), (3, 4));
+        // out of range and NaN give the integer indefinite value
+        assert_eq!((cvttsd2si(3e9), cvtsd2si(-3e9)), (i32::MIN, i32::MIN));
+        assert_eq!((cvttsd2si(f64::NAN), cvtsd2si(f64::NAN)), (i32::MIN, i32::MIN));
+
+        // cvtss2sd xmm0, xmm1 and back with cvtsd2ss xmm0, xmm1
+        let cpu = convert(&[0xF3, 0x0F, 0x5A, 0xC1], 0, [u64::from(1.5f32.to_bits()), 0]);
+        assert_eq!(cpu.xmm[0], [1.5f64.to_bits(), 0x3333_3333_4444_4444]);
+        let cpu = convert(&[0xF2, 0x0F, 0x5A, 0xC1], 0, sd(0.1));
+        assert_eq!(cpu.xmm[0][0], 0x1111_1111_0000_0000 | u64::from(0.1f32.to_bits()));
+
+        // ucomisd xmm0, xmm1 with a NaN is unordered, which sets zf, pf and cf
+        let cpu = run(&[0x66, 0x0F, 0x2E, 0xC1], |cpu| {
+            cpu.xmm[0] = sd(1.0);
+            cpu.xmm[1] = sd(f64::NAN);
+        });
+        assert!(cpu.flag(ZF) && cpu.flag(PF) && cpu.flag(CF));
+    }
+}
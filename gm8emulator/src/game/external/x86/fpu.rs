@@ -0,0 +1,454 @@
+//! The x87 FPU. Registers are kept as f64 rather than 80-bit extended, which is close enough for GM's purposes
+//! and means results don't depend on the host.
+
+use super::{
+    cpu::{Cpu, Inst, Operand, CF, PF, ZF},
+    memory::Memory,
+};
+use serde::{Deserialize, Serialize};
+
+const C0: u16 = 1 << 8;
+const C1: u16 = 1 << 9;
+const C2: u16 = 1 << 10;
+const C3: u16 = 1 << 14;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Fpu {
+    st: [f64; 8],
+    top: u8,
+    empty: u8, // bit per physical register
+    pub control: u16,
+    status: u16, // condition and exception bits, top gets merged in when read
+}
+
+impl Default for Fpu {
+    fn default() -> Self {
+        Self { st: [0.0; 8], top: 0, empty: 0xFF, control: 0x027F, status: 0 }
+    }
+}
+
+/// Converts an f64 to 80-bit extended precision.
+fn to_f80(value: f64) -> [u8; 10] {
+    let bits = value.to_bits();
+    let sign = ((bits >> 63) as u16) << 15;
+    let exponent = ((bits >> 52) & 0x7FF) as u16;
+    let fraction = bits & 0x000F_FFFF_FFFF_FFFF;
+    let (exponent, mantissa) = match exponent {
+        0 if fraction == 0 => (0, 0),
+        0 => {
+            // subnormal doubles are normal in extended precision
+            let shift = fraction.leading_zeros();
+            ((16383 + 63 - 1074 - shift as i32) as u16, fraction << shift)
+        },
+        0x7FF => (0x7FFF, (1 << 63) | (fraction << 11)),
+        e => (e - 1023 + 16383, (1 << 63) | (fraction << 11)),
+    };
+    let mut out = [0; 10];
+    out[..8].copy_from_slice(&mantissa.to_le_bytes());
+    out[8..].copy_from_slice(&(sign | exponent).to_le_bytes());
+    out
+}
+
+fn from_f80(bytes: [u8; 10]) -> f64 {
+    let mut mantissa = [0; 8];
+    mantissa.copy_from_slice(&bytes[..8]);
+    let mantissa = u64::from_le_bytes(mantissa);
+    let sign_exponent = u16::from_le_bytes([bytes[8], bytes[9]]);
+    let sign = if sign_exponent & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from(sign_exponent & 0x7FFF);
+    if exponent == 0x7FFF {
+        return if mantissa << 1 == 0 { sign * f64::INFINITY } else { f64::NAN }
+    }
+    if mantissa == 0 {
+        return sign * 0.0
+    }
+    // scaling in steps, since powi can't make anything below 2^-1022 and subnormal results would become 0
+    let mut value = sign * (mantissa as f64) * 2f64.powi(-63);
+    let mut exponent = exponent - 16383;
+    while exponent != 0 {
+        let step = exponent.clamp(-1000, 1000);
+        value *= 2f64.powi(step);
+        exponent -= step;
+    }
+    value
+}
+
+impl Fpu {
+    fn physical(&self, i: u8) -> usize {
+        usize::from((self.top + i) & 7)
+    }
+
+    pub fn st(&self, i: u8) -> f64 {
+        self.st[self.physical(i)]
+    }
+
+    pub fn set_st(&mut self, i: u8, value: f64) {
+        let p = self.physical(i);
+        self.st[p] = value;
+        self.empty &= !(1 << p);
+    }
+
+    pub fn push(&mut self, value: f64) {
+        self.top = (self.top + 7) & 7;
+        self.set_st(0, value);
+    }
+
+    pub fn pop(&mut self) -> f64 {
+        let value = self.st(0);
+        self.empty |= 1 << self.physical(0);
+        self.top = (self.top + 1) & 7;
+        value
+    }
+
+    pub fn status_word(&self) -> u16 {
+        (self.status & !(7 << 11)) | (u16::from(self.top) << 11)
+    }
+
+    fn set_condition(&mut self, c3: bool, c2: bool, c1: bool, c0: bool) {
+        self.status &= !(C0 | C1 | C2 | C3);
+        for (on, bit) in [(c3, C3), (c2, C2), (c1, C1), (c0, C0)].iter() {
+            if *on {
+                self.status |= bit;
+            }
+        }
+    }
+
+    fn compare(&mut self, a: f64, b: f64) {
+        match a.partial_cmp(&b) {
+            Some(std::cmp::Ordering::Greater) => self.set_condition(false, false, false, false),
+            Some(std::cmp::Ordering::Less) => self.set_condition(false, false, false, true),
+            Some(std::cmp::Ordering::Equal) => self.set_condition(true, false, false, false),
+            None => self.set_condition(true, true, false, true),
+        }
+    }
+
+    /// Rounds according to the rounding control bits.
+    pub fn round(&self, value: f64) -> f64 {
+        match (self.control >> 10) & 3 {
+            0 => {
+                let rounded = value.round();
+                if (value - value.trunc()).abs() == 0.5 { 2.0 * (value / 2.0).round() } else { rounded }
+            },
+            1 => value.floor(),
+            2 => value.ceil(),
+            _ => value.trunc(),
+        }
+    }
+
+    /// Converts to an integer of the given byte size, giving the "integer indefinite" value if it doesn't fit.
+    fn to_int(&self, value: f64, size: u8, truncate: bool) -> i64 {
+        let rounded = if truncate { value.trunc() } else { self.round(value) };
+        let limit = 2f64.powi(i32::from(size) * 8 - 1);
+        if rounded.is_nan() || rounded >= limit || rounded < -limit {
+            i64::MIN >> (64 - 8 * u32::from(size))
+        } else {
+            rounded as i64
+        }
+    }
+
+    /// The shared arithmetic ops: add, mul, com, comp, sub, subr, div, divr
+    fn arith(&mut self, op: u8, a: f64, b: f64) -> Option<f64> {
+        match op {
+            0 => Some(a + b),
+            1 => Some(a * b),
+            2 | 3 => {
+                self.compare(a, b);
+                None
+            },
+            4 => Some(a - b),
+            5 => Some(b - a),
+            6 => Some(a / b),
+            _ => Some(b / a),
+        }
+    }
+}
+
+impl Cpu {
+    /// Sets ZF, PF and CF like fcomi and ucomisd do.
+    pub fn set_compare_flags(&mut self, a: f64, b: f64) {
+        let (zf, pf, cf) = match a.partial_cmp(&b) {
+            Some(std::cmp::Ordering::Greater) => (false, false, false),
+            Some(std::cmp::Ordering::Less) => (false, false, true),
+            Some(std::cmp::Ordering::Equal) => (true, false, false),
+            None => (true, true, true),
+        };
+        self.set_flag(ZF, zf);
+        self.set_flag(PF, pf);
+        self.set_flag(CF, cf);
+    }
+
+    pub fn fpu_op(&mut self, mem: &mut Memory, inst: &Inst, opcode: u8) -> Result<(), String> {
+        let (op, rm) = self.modrm(mem, inst)?;
+        match rm {
+            Operand::Mem(addr) => self.fpu_mem(mem, opcode, op, addr),
+            Operand::Reg(i) => self.fpu_reg(opcode, op, i),
+        }
+        .map_err(|e| if e.is_empty() { format!("unsupported FPU instruction {:02X} /{}", opcode, op) } else { e })
+    }
+
+    fn fpu_mem(&mut self, mem: &mut Memory, opcode: u8, op: u8, addr: u32) -> Result<(), String> {
+        let fpu = &mut self.fpu;
+        match (opcode, op) {
+            (0xD8, _) | (0xDA, _) | (0xDC, _) | (0xDE, _) => {
+                let value = match opcode {
+                    0xD8 => f64::from(mem.read_f32(addr)?),
+                    0xDA => f64::from(mem.read_u32(addr)? as i32),
+                    0xDC => mem.read_f64(addr)?,
+                    _ => f64::from(mem.read_u16(addr)? as i16),
+                };
+                if let Some(result) = fpu.arith(op, fpu.st(0), value) {
+                    fpu.set_st(0, result);
+                } else if op == 3 {
+                    fpu.pop();
+                }
+            },
+            (0xD9, 0) => fpu.push(f64::from(mem.read_f32(addr)?)),
+            (0xD9, 2) => mem.write_f32(addr, fpu.st(0) as f32)?,
+            (0xD9, 3) => mem.write_f32(addr, fpu.pop() as f32)?,
+            (0xD9, 5) => fpu.control = mem.read_u16(addr)?,
+            (0xD9, 7) => mem.write_u16(addr, fpu.control)?,
+            (0xDB, 0) => fpu.push(f64::from(mem.read_u32(addr)? as i32)),
+            (0xDB, 1) | (0xDB, 2) | (0xDB, 3) => {
+                let value = fpu.to_int(fpu.st(0), 4, op == 1);
+                mem.write_u32(addr, value as u32)?;
+                if op != 2 {
+                    fpu.pop();
+                }
+            },
+            (0xDB, 5) => fpu.push(from_f80(mem.read(addr)?)),
+            (0xDB, 7) => mem.write(addr, &to_f80(fpu.pop()))?,
+            (0xDD, 0) => fpu.push(mem.read_f64(addr)?),
+            (0xDD, 1) => {
+                let x = fpu.pop();
+                let value = fpu.to_int(x, 8, true);
+                mem.write_u64(addr, value as u64)?;
+            },
+            (0xDD, 2) => mem.write_f64(addr, fpu.st(0))?,
+            (0xDD, 3) => mem.write_f64(addr, fpu.pop())?,
+            (0xDD, 7) => mem.write_u16(addr, fpu.status_word())?,
+            (0xDF, 0) => fpu.push(f64::from(mem.read_u16(addr)? as i16)),
+            (0xDF, 1) | (0xDF, 2) | (0xDF, 3) => {
+                let value = fpu.to_int(fpu.st(0), 2, op == 1);
+                mem.write_u16(addr, value as u16)?;
+                if op != 2 {
+                    fpu.pop();
+                }
+            },
+            (0xDF, 5) => fpu.push(mem.read_u64(addr)? as i64 as f64),
+            (0xDF, 7) => {
+                let x = fpu.pop();
+                let value = fpu.to_int(x, 8, false);
+                mem.write_u64(addr, value as u64)?;
+            },
+            _ => return Err(String::new()),
+        }
+        Ok(())
+    }
+
+    fn fpu_reg(&mut self, opcode: u8, op: u8, i: u8) -> Result<(), String> {
+        let fpu = &mut self.fpu;
+        match (opcode, op) {
+            (0xD8, _) => {
+                if let Some(result) = fpu.arith(op, fpu.st(0), fpu.st(i)) {
+                    fpu.set_st(0, result);
+                } else if op == 3 {
+                    fpu.pop();
+                }
+            },
+            (0xDC, 2) | (0xDC, 3) | (0xDE, 3) => {
+                fpu.compare(fpu.st(0), fpu.st(i));
+                let pops = if opcode == 0xDE { 2 } else { op - 2 }; // fcompp pops twice
+                for _ in 0..pops {
+                    fpu.pop();
+                }
+            },
+            (0xDC, _) | (0xDE, _) if op != 2 => {
+                // these go into st(i), and sub/subr and div/divr are the other way around
+                let op = if op >= 4 { op ^ 1 } else { op };
+                if let Some(result) = fpu.arith(op, fpu.st(i), fpu.st(0)) {
+                    fpu.set_st(i, result);
+                }
+                if opcode == 0xDE {
+                    fpu.pop();
+                }
+            },
+            (0xD9, 0) => fpu.push(fpu.st(i)),
+            (0xD9, 1) => {
+                let (a, b) = (fpu.st(0), fpu.st(i));
+                fpu.set_st(0, b);
+                fpu.set_st(i, a);
+            },
+            (0xD9, 2) if i == 0 => (), // fnop
+            (0xD9, 4) => match i {
+                0 => fpu.set_st(0, -fpu.st(0)),
+                1 => fpu.set_st(0, fpu.st(0).abs()),
+                4 => fpu.compare(fpu.st(0), 0.0),
+                5 => {
+                    let value = fpu.st(0);
+                    let negative = value.is_sign_negative();
+                    let (c3, c2, c0) = if fpu.empty & (1 << fpu.physical(0)) != 0 {
+                        (true, false, true)
+                    } else if value.is_nan() {
+                        (false, false, true)
+                    } else if value.is_infinite() {
+                        (false, true, true)
+                    } else if value == 0.0 {
+                        (true, false, false)
+                    } else if value.is_normal() {
+                        (false, true, false)
+                    } else {
+                        (true, true, false)
+                    };
+                    fpu.set_condition(c3, c2, negative, c0);
+                },
+                _ => return Err(String::new()),
+            },
+            (0xD9, 5) => fpu.push(match i {
+                0 => 1.0,
+                1 => std::f64::consts::LOG2_10,
+                2 => std::f64::consts::LOG2_E,
+                3 => std::f64::consts::PI,
+                4 => std::f64::consts::LOG10_2,
+                5 => std::f64::consts::LN_2,
+                6 => 0.0,
+                _ => return Err(String::new()),
+            }),
+            (0xD9, 6) => match i {
+                0 => fpu.set_st(0, fpu.st(0).exp2() - 1.0),
+                1 => {
+                    let result = fpu.st(1) * fpu.st(0).log2();
+                    fpu.pop();
+                    fpu.set_st(0, result);
+                },
+                2 => {
+                    fpu.set_st(0, fpu.st(0).tan());
+                    fpu.push(1.0);
+                    fpu.set_condition(false, false, false, false);
+                },
+                3 => {
+                    let result = fpu.st(1).atan2(fpu.st(0));
+                    fpu.pop();
+                    fpu.set_st(0, result);
+                },
+                4 => {
+                    let value = fpu.st(0);
+                    let exponent = if value == 0.0 { f64::NEG_INFINITY } else { value.abs().log2().floor() };
+                    let significand = if value == 0.0 { value } else { value / exponent.exp2() };
+                    fpu.set_st(0, exponent);
+                    fpu.push(significand);
+                },
+                6 => fpu.top = (fpu.top + 7) & 7,
+                7 => fpu.top = (fpu.top + 1) & 7,
+                5 => {
+                    // fprem1, which rounds the quotient to nearest
+                    let (a, b) = (fpu.st(0), fpu.st(1));
+                    let quotient = (a / b).round();
+                    fpu.set_st(0, a - quotient * b);
+                    fpu.set_condition(
+                        quotient as i64 & 2 != 0,
+                        false,
+                        quotient as i64 & 1 != 0,
+                        quotient as i64 & 4 != 0,
+                    );
+                },
+                _ => return Err(String::new()),
+            },
+            (0xD9, 7) => match i {
+                0 => {
+                    // fprem, which truncates the quotient
+                    let (a, b) = (fpu.st(0), fpu.st(1));
+                    let quotient = (a / b).trunc();
+                    fpu.set_st(0, a % b);
+                    fpu.set_condition(
+                        quotient as i64 & 2 != 0,
+                        false,
+                        quotient as i64 & 1 != 0,
+                        quotient as i64 & 4 != 0,
+                    );
+                },
+                1 => {
+                    let result = fpu.st(1) * (fpu.st(0) + 1.0).log2();
+                    fpu.pop();
+                    fpu.set_st(0, result);
+                },
+                2 => fpu.set_st(0, fpu.st(0).sqrt()),
+                3 => {
+                    let value = fpu.st(0);
+                    fpu.set_st(0, value.sin());
+                    fpu.push(value.cos());
+                    fpu.set_condition(false, false, false, false);
+                },
+                4 => fpu.set_st(0, fpu.round(fpu.st(0))),
+                5 => {
+                    let scale = fpu.st(1).trunc();
+                    fpu.set_st(0, fpu.st(0) * scale.exp2());
+                },
+                6 => {
+                    fpu.set_st(0, fpu.st(0).sin());
+                    fpu.set_condition(false, false, false, false);
+                },
+                _ => {
+                    fpu.set_st(0, fpu.st(0).cos());
+                    fpu.set_condition(false, false, false, false);
+                },
+            },
+            (0xDA, 5) if i == 1 => {
+                fpu.compare(fpu.st(0), fpu.st(1));
+                fpu.pop();
+                fpu.pop();
+            },
+            (0xDA, 0..=3) | (0xDB, 0..=3) => {
+                // fcmovcc: b, e, be, u, then the negated versions
+                let cc = [2, 4, 6, 10][usize::from(op)] | u8::from(opcode == 0xDB);
+                if self.condition(cc) {
+                    let value = self.fpu.st(i);
+                    self.fpu.set_st(0, value);
+                }
+            },
+            (0xDB, 4) if i == 2 => fpu.status &= !0xFF,   // fnclex
+            (0xDB, 4) if i == 3 => *fpu = Fpu::default(), // fninit
+            (0xDB, 5) | (0xDB, 6) | (0xDF, 5) | (0xDF, 6) => {
+                let (a, b) = (fpu.st(0), fpu.st(i));
+                if opcode == 0xDF {
+                    fpu.pop();
+                }
+                self.set_compare_flags(a, b);
+            },
+            (0xDD, 0) | (0xDF, 0) => {
+                fpu.empty |= 1 << fpu.physical(i);
+                if opcode == 0xDF {
+                    fpu.pop();
+                }
+            },
+            (0xDD, 2) => fpu.set_st(i, fpu.st(0)),
+            (0xDD, 3) => {
+                fpu.set_st(i, fpu.st(0));
+                fpu.pop();
+            },
+            (0xDD, 4) | (0xDD, 5) => {
+                fpu.compare(fpu.st(0), fpu.st(i));
+                if op == 5 {
+                    fpu.pop();
+                }
+            },
+            (0xDF, 4) if i == 0 => {
+                let status = fpu.status_word();
+                self.set_reg(0, 2, u32::from(status));
+            },
+            _ => return Err(String::new()),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extended_precision_round_trip() {
+        for &x in &[0.0, -1.5, 1e300, -3.25e-310, std::f64::consts::PI, f64::INFINITY] {
+            assert_eq!(from_f80(to_f80(x)).to_bits(), x.to_bits());
+        }
+    }
+}
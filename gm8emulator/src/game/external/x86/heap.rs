@@ -0,0 +1,83 @@
+use super::memory::Memory;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const ALIGN: u32 = 16;
+
+/// A first-fit allocator living in one growable memory region.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Heap {
+    base: u32,
+    top: u32,                 // offset of the first byte that's never been handed out
+    used: BTreeMap<u32, u32>, // address -> size
+    free: BTreeMap<u32, u32>, // address -> size, always coalesced
+}
+
+impl Heap {
+    pub fn new(mem: &mut Memory, base: u32, reserved: u32) -> Result<Self, String> {
+        mem.map(base, 0, reserved)?;
+        Ok(Self { base, top: 0, used: BTreeMap::new(), free: BTreeMap::new() })
+    }
+
+    pub fn alloc(&mut self, mem: &mut Memory, size: u32) -> Result<u32, String> {
+        let size = size.max(1).checked_add(ALIGN - 1).ok_or("allocation too large")? & !(ALIGN - 1);
+        let addr = match self.free.iter().find(|(_, &s)| s >= size).map(|(&a, &s)| (a, s)) {
+            Some((addr, free_size)) => {
+                self.free.remove(&addr);
+                if free_size > size {
+                    self.free.insert(addr + size, free_size - size);
+                }
+                mem.slice_mut(addr, size as usize)?.iter_mut().for_each(|b| *b = 0);
+                addr
+            },
+            None => {
+                let addr = self.base + self.top;
+                let top = self.top.checked_add(size).ok_or("out of heap memory")?;
+                mem.resize(self.base, top).map_err(|_| "out of heap memory".to_string())?;
+                self.top = top;
+                addr
+            },
+        };
+        self.used.insert(addr, size);
+        Ok(addr)
+    }
+
+    pub fn free(&mut self, addr: u32) -> bool {
+        let mut size = match self.used.remove(&addr) {
+            Some(size) => size,
+            None => return false,
+        };
+        let mut addr = addr;
+        if let Some(next_size) = self.free.remove(&(addr + size)) {
+            size += next_size;
+        }
+        if let Some((&prev, &prev_size)) = self.free.range(..addr).next_back() {
+            if prev + prev_size == addr {
+                self.free.remove(&prev);
+                addr = prev;
+                size += prev_size;
+            }
+        }
+        self.free.insert(addr, size);
+        true
+    }
+
+    pub fn realloc(&mut self, mem: &mut Memory, addr: u32, size: u32) -> Result<u32, String> {
+        let old_size = match self.used.get(&addr) {
+            Some(&old_size) => old_size,
+            None => return Err(format!("realloc of invalid heap pointer {:#010X}", addr)),
+        };
+        if size <= old_size {
+            return Ok(addr)
+        }
+        let new_addr = self.alloc(mem, size)?;
+        let data = mem.slice(addr, old_size as usize)?.to_vec();
+        mem.write(new_addr, &data)?;
+        self.free(addr);
+        Ok(new_addr)
+    }
+
+    pub fn size(&self, addr: u32) -> Option<u32> {
+        self.used.get(&addr).copied()
+    }
+}
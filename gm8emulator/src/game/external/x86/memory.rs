@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+
+/// A sparse 32-bit address space made of contiguous regions.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Memory {
+    regions: Vec<Region>, // sorted by base, never overlapping
+    #[serde(skip)]
+    last_hit: Cell<usize>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Region {
+    base: u32,
+    reserved: u32, // nothing else can be mapped below base + reserved, even if data is shorter
+    data: Vec<u8>,
+}
+
+impl Region {
+    fn end(&self) -> u64 {
+        u64::from(self.base) + u64::from(self.reserved)
+    }
+}
+
+fn violation(addr: u32) -> String {
+    format!("access violation at {:#010X}", addr)
+}
+
+impl Memory {
+    /// Maps a zeroed region of the given size, which can grow up to `reserved` bytes.
+    pub fn map(&mut self, base: u32, size: u32, reserved: u32) -> Result<(), String> {
+        let reserved = reserved.max(size);
+        if !self.is_free(base, reserved) {
+            return Err(format!("{:#010X} is already mapped", base))
+        }
+        let index = self.regions.iter().position(|r| r.base > base).unwrap_or(self.regions.len());
+        self.regions.insert(index, Region { base, reserved, data: vec![0; size as usize] });
+        self.last_hit.set(index);
+        Ok(())
+    }
+
+    pub fn unmap(&mut self, base: u32) -> bool {
+        match self.regions.iter().position(|r| r.base == base) {
+            Some(index) => {
+                self.regions.remove(index);
+                self.last_hit.set(0);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Changes the committed size of the region starting at `base`, within its reserved size.
+    pub fn resize(&mut self, base: u32, size: u32) -> Result<(), String> {
+        match self.regions.iter_mut().find(|r| r.base == base) {
+            Some(region) if size <= region.reserved => {
+                region.data.resize(size as usize, 0);
+                Ok(())
+            },
+            Some(_) => Err(format!("region at {:#010X} can't grow to {:#X} bytes", base, size)),
+            None => Err(violation(base)),
+        }
+    }
+
+    pub fn region_size(&self, base: u32) -> Option<u32> {
+        self.regions.iter().find(|r| r.base == base).map(|r| r.data.len() as u32)
+    }
+
+    /// Gets the base and committed size of the region containing `addr`.
+    pub fn region_containing(&self, addr: u32) -> Option<(u32, u32)> {
+        self.locate(addr, 0).ok().map(|(i, _)| (self.regions[i].base, self.regions[i].data.len() as u32))
+    }
+
+    pub fn is_free(&self, base: u32, size: u32) -> bool {
+        let end = u64::from(base) + u64::from(size);
+        end <= 1 << 32 && self.regions.iter().all(|r| end <= u64::from(r.base) || u64::from(base) >= r.end())
+    }
+
+    /// Finds a free 64K-aligned range of the given size, starting the search at `from`.
+    pub fn find_free(&self, size: u32, from: u32) -> Option<u32> {
+        let mut base = u64::from(from + 0xFFFF) & !0xFFFF;
+        while base + u64::from(size) <= 1 << 32 {
+            match self.regions.iter().find(|r| base + u64::from(size) > u64::from(r.base) && base < r.end()) {
+                Some(r) => base = (r.end() + 0xFFFF) & !0xFFFF,
+                None => return Some(base as u32),
+            }
+        }
+        None
+    }
+
+    /// Finds the region containing `len` bytes at `addr`, returning its index and the offset into it.
+    fn locate(&self, addr: u32, len: usize) -> Result<(usize, usize), String> {
+        let check = |r: &Region| {
+            let offset = addr.wrapping_sub(r.base) as usize;
+            if addr >= r.base && offset + len <= r.data.len() { Some(offset) } else { None }
+        };
+        let last = self.last_hit.get();
+        if let Some(offset) = self.regions.get(last).and_then(check) {
+            return Ok((last, offset))
+        }
+        for (i, region) in self.regions.iter().enumerate() {
+            if let Some(offset) = check(region) {
+                self.last_hit.set(i);
+                return Ok((i, offset))
+            }
+        }
+        Err(violation(addr))
+    }
+
+    pub fn slice(&self, addr: u32, len: usize) -> Result<&[u8], String> {
+        let (index, offset) = self.locate(addr, len)?;
+        Ok(&self.regions[index].data[offset..offset + len])
+    }
+
+    pub fn slice_mut(&mut self, addr: u32, len: usize) -> Result<&mut [u8], String> {
+        let (index, offset) = self.locate(addr, len)?;
+        Ok(&mut self.regions[index].data[offset..offset + len])
+    }
+
+    pub fn read<const N: usize>(&self, addr: u32) -> Result<[u8; N], String> {
+        let mut buf = [0; N];
+        buf.copy_from_slice(self.slice(addr, N)?);
+        Ok(buf)
+    }
+
+    pub fn write(&mut self, addr: u32, data: &[u8]) -> Result<(), String> {
+        self.slice_mut(addr, data.len())?.copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn read_u8(&self, addr: u32) -> Result<u8, String> {
+        Ok(self.read::<1>(addr)?[0])
+    }
+
+    pub fn read_u16(&self, addr: u32) -> Result<u16, String> {
+        self.read(addr).map(u16::from_le_bytes)
+    }
+
+    pub fn read_u32(&self, addr: u32) -> Result<u32, String> {
+        self.read(addr).map(u32::from_le_bytes)
+    }
+
+    pub fn read_u64(&self, addr: u32) -> Result<u64, String> {
+        self.read(addr).map(u64::from_le_bytes)
+    }
+
+    pub fn read_f32(&self, addr: u32) -> Result<f32, String> {
+        self.read(addr).map(f32::from_le_bytes)
+    }
+
+    pub fn read_f64(&self, addr: u32) -> Result<f64, String> {
+        self.read(addr).map(f64::from_le_bytes)
+    }
+
+    pub fn write_u8(&mut self, addr: u32, value: u8) -> Result<(), String> {
+        self.write(addr, &[value])
+    }
+
+    pub fn write_u16(&mut self, addr: u32, value: u16) -> Result<(), String> {
+        self.write(addr, &value.to_le_bytes())
+    }
+
+    pub fn write_u32(&mut self, addr: u32, value: u32) -> Result<(), String> {
+        self.write(addr, &value.to_le_bytes())
+    }
+
+    pub fn write_u64(&mut self, addr: u32, value: u64) -> Result<(), String> {
+        self.write(addr, &value.to_le_bytes())
+    }
+
+    pub fn write_f32(&mut self, addr: u32, value: f32) -> Result<(), String> {
+        self.write(addr, &value.to_le_bytes())
+    }
+
+    pub fn write_f64(&mut self, addr: u32, value: f64) -> Result<(), String> {
+        self.write(addr, &value.to_le_bytes())
+    }
+
+    /// Reads a sized value (1, 2 or 4 bytes) zero-extended to a u32.
+    pub fn read_sized(&self, addr: u32, size: u8) -> Result<u32, String> {
+        match size {
+            1 => self.read_u8(addr).map(u32::from),
+            2 => self.read_u16(addr).map(u32::from),
+            _ => self.read_u32(addr),
+        }
+    }
+
+    pub fn write_sized(&mut self, addr: u32, size: u8, value: u32) -> Result<(), String> {
+        match size {
+            1 => self.write_u8(addr, value as u8),
+            2 => self.write_u16(addr, value as u16),
+            _ => self.write_u32(addr, value),
+        }
+    }
+
+    /// Reads a null-terminated string, not including the terminator.
+    pub fn read_cstr(&self, addr: u32) -> Result<Vec<u8>, String> {
+        let (index, offset) = self.locate(addr, 0)?;
+        let data = &self.regions[index].data[offset..];
+        match data.iter().position(|&b| b == 0) {
+            Some(len) => Ok(data[..len].to_vec()),
+            None => Err(violation(self.regions[index].base + self.regions[index].data.len() as u32)),
+        }
+    }
+
+    /// Reads a null-terminated UTF-16 string, not including the terminator.
+    pub fn read_wstr(&self, addr: u32) -> Result<Vec<u16>, String> {
+        let mut s = Vec::new();
+        loop {
+            match self.read_u16(addr.wrapping_add(s.len() as u32 * 2))? {
+                0 => return Ok(s),
+                c => s.push(c),
+            }
+        }
+    }
+}
@@ -0,0 +1,482 @@
+//! Loading 32-bit PE images into emulated memory.
+
+use super::memory::Memory;
+use serde::{Deserialize, Serialize};
+
+const IMAGE_FILE_MACHINE_I386: u16 = 0x14C;
+const IMAGE_NT_OPTIONAL_HDR32_MAGIC: u16 = 0x10B;
+const IMAGE_FILE_DLL: u16 = 0x2000;
+
+const DIRECTORY_EXPORT: usize = 0;
+const DIRECTORY_IMPORT: usize = 1;
+const DIRECTORY_BASERELOC: usize = 5;
+const DIRECTORY_TLS: usize = 9;
+
+const IMAGE_REL_BASED_ABSOLUTE: u16 = 0;
+const IMAGE_REL_BASED_HIGHLOW: u16 = 3;
+
+/// The lowest address images get moved to if they can't go where they want.
+const RELOCATION_SEARCH_BASE: u32 = 0x1000_0000;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Image {
+    pub base: u32,
+    pub size: u32,
+    pub entry_point: Option<u32>,
+    exports: Vec<(Vec<u8>, u32)>, // sorted by name
+    ordinal_base: u32,
+    ordinals: Vec<u32>, // export addresses by ordinal - ordinal_base, 0 if missing
+    pub tls: Option<Tls>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Tls {
+    pub template_start: u32,
+    pub template_end: u32,
+    pub zero_fill: u32,
+    pub index_address: u32,
+    pub callbacks: u32,
+}
+
+/// An import the emulator has to resolve and write into the IAT.
+pub struct Import {
+    pub dll_name: Vec<u8>,
+    pub symbol: Symbol,
+    pub iat_address: u32,
+}
+
+pub enum Symbol {
+    Name(Vec<u8>),
+    Ordinal(u16),
+}
+
+/// Reads little-endian values out of the raw file, with bounds checking.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&[u8], String> {
+        self.0.get(offset..offset.checked_add(len).ok_or("bad offset")?).ok_or_else(|| "truncated PE file".into())
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, String> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.bytes(offset, 2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, String> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.bytes(offset, 4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+}
+
+impl Image {
+    /// Maps a DLL into memory, applying relocations. Imports are returned rather than resolved.
+    pub fn load(mem: &mut Memory, file: &[u8]) -> Result<(Self, Vec<Import>), String> {
+        let file = Reader(file);
+        if file.bytes(0, 2)? != b"MZ" {
+            return Err("not an executable file".into())
+        }
+        let pe = file.u32(0x3C)? as usize;
+        if file.bytes(pe, 4)? != b"PE\0\0" {
+            return Err("not a PE file".into())
+        }
+        let coff = pe + 4;
+        if file.u16(coff)? != IMAGE_FILE_MACHINE_I386 {
+            return Err("not a 32-bit x86 DLL".into())
+        }
+        if file.u16(coff + 18)? & IMAGE_FILE_DLL == 0 {
+            return Err("not a DLL".into())
+        }
+        let section_count = file.u16(coff + 2)? as usize;
+        let optional = coff + 20;
+        let optional_size = file.u16(coff + 16)? as usize;
+        if file.u16(optional)? != IMAGE_NT_OPTIONAL_HDR32_MAGIC {
+            return Err("unsupported optional header".into())
+        }
+        let entry_rva = file.u32(optional + 16)?;
+        let preferred_base = file.u32(optional + 28)?;
+        let size = file.u32(optional + 56)?;
+        let headers_size = file.u32(optional + 60)?;
+        let directory_count = file.u32(optional + 92)? as usize;
+        let directory = |index: usize| -> Result<(u32, u32), String> {
+            if index < directory_count {
+                Ok((file.u32(optional + 96 + index * 8)?, file.u32(optional + 100 + index * 8)?))
+            } else {
+                Ok((0, 0))
+            }
+        };
+
+        let base = if mem.is_free(preferred_base, size) && preferred_base != 0 {
+            preferred_base
+        } else {
+            mem.find_free(size, RELOCATION_SEARCH_BASE).ok_or("no room to load DLL")?
+        };
+        mem.map(base, size, size)?;
+
+        // headers, then sections
+        mem.write(base, file.bytes(0, (headers_size.min(size) as usize).min(file.0.len()))?)?;
+        let sections = optional + optional_size;
+        for i in 0..section_count {
+            let header = sections + i * 40;
+            let virtual_size = file.u32(header + 8)?;
+            let rva = file.u32(header + 12)?;
+            let mut raw_size = file.u32(header + 16)?;
+            let raw_offset = file.u32(header + 20)?;
+            if virtual_size != 0 {
+                raw_size = raw_size.min(virtual_size);
+            }
+            if raw_size != 0 && raw_offset != 0 {
+                let raw_size = (raw_size as usize).min(file.0.len().saturating_sub(raw_offset as usize));
+                mem.write(base.wrapping_add(rva), file.bytes(raw_offset as usize, raw_size)?)?;
+            }
+        }
+
+        if base != preferred_base {
+            let (reloc_rva, reloc_size) = directory(DIRECTORY_BASERELOC)?;
+            if reloc_rva == 0 {
+                mem.unmap(base);
+                return Err("DLL can't be loaded at its preferred address and has no relocations".into())
+            }
+            apply_relocations(mem, base, reloc_rva, reloc_size, base.wrapping_sub(preferred_base))?;
+        }
+
+        let image =
+            Image::from_memory(mem, base, size, entry_rva, directory(DIRECTORY_EXPORT)?, directory(DIRECTORY_TLS)?)?;
+        let imports = read_imports(mem, base, directory(DIRECTORY_IMPORT)?.0)?;
+        Ok((image, imports))
+    }
+
+    fn from_memory(
+        mem: &Memory,
+        base: u32,
+        size: u32,
+        entry_rva: u32,
+        (export_rva, _): (u32, u32),
+        (tls_rva, _): (u32, u32),
+    ) -> Result<Self, String> {
+        let mut exports = Vec::new();
+        let mut ordinal_base = 0;
+        let mut ordinals = Vec::new();
+        if export_rva != 0 {
+            let dir = base.wrapping_add(export_rva);
+            ordinal_base = mem.read_u32(dir + 16)?;
+            let function_count = mem.read_u32(dir + 20)?;
+            let name_count = mem.read_u32(dir + 24)?;
+            let functions = base.wrapping_add(mem.read_u32(dir + 28)?);
+            let names = base.wrapping_add(mem.read_u32(dir + 32)?);
+            let name_ordinals = base.wrapping_add(mem.read_u32(dir + 36)?);
+            for i in 0..function_count {
+                let rva = mem.read_u32(functions + i * 4)?;
+                ordinals.push(if rva == 0 { 0 } else { base.wrapping_add(rva) });
+            }
+            for i in 0..name_count {
+                let name = mem.read_cstr(base.wrapping_add(mem.read_u32(names + i * 4)?))?;
+                let ordinal = u32::from(mem.read_u16(name_ordinals + i * 2)?);
+                if let Some(&address) = ordinals.get(ordinal as usize) {
+                    exports.push((name, address));
+                }
+            }
+            exports.sort();
+        }
+
+        // these are all absolute addresses, so this has to happen after relocating
+        let tls = if tls_rva != 0 {
+            let dir = base.wrapping_add(tls_rva);
+            Some(Tls {
+                template_start: mem.read_u32(dir)?,
+                template_end: mem.read_u32(dir + 4)?,
+                index_address: mem.read_u32(dir + 8)?,
+                callbacks: mem.read_u32(dir + 12)?,
+                zero_fill: mem.read_u32(dir + 16)?,
+            })
+        } else {
+            None
+        };
+
+        let entry_point = if entry_rva != 0 { Some(base.wrapping_add(entry_rva)) } else { None };
+        Ok(Self { base, size, entry_point, exports, ordinal_base, ordinals, tls })
+    }
+
+    pub fn export(&self, name: &[u8]) -> Option<u32> {
+        self.exports.binary_search_by(|(n, _)| n.as_slice().cmp(name)).ok().map(|i| self.exports[i].1)
+    }
+
+    pub fn export_ordinal(&self, ordinal: u32) -> Option<u32> {
+        self.ordinals.get(ordinal.checked_sub(self.ordinal_base)? as usize).copied().filter(|&a| a != 0)
+    }
+}
+
+fn apply_relocations(mem: &mut Memory, base: u32, rva: u32, size: u32, delta: u32) -> Result<(), String> {
+    let mut block = base.wrapping_add(rva);
+    let end = block.wrapping_add(size);
+    while block < end {
+        let page = base.wrapping_add(mem.read_u32(block)?);
+        let block_size = mem.read_u32(block + 4)?;
+        if block_size < 8 {
+            break
+        }
+        for i in 0..(block_size - 8) / 2 {
+            let entry = mem.read_u16(block + 8 + i * 2)?;
+            let target = page.wrapping_add(u32::from(entry & 0xFFF));
+            match entry >> 12 {
+                IMAGE_REL_BASED_ABSOLUTE => (),
+                IMAGE_REL_BASED_HIGHLOW => mem.write_u32(target, mem.read_u32(target)?.wrapping_add(delta))?,
+                kind => return Err(format!("unsupported relocation type {}", kind)),
+            }
+        }
+        block = block.wrapping_add(block_size);
+    }
+    Ok(())
+}
+
+fn read_imports(mem: &Memory, base: u32, rva: u32) -> Result<Vec<Import>, String> {
+    let mut imports = Vec::new();
+    if rva == 0 {
+        return Ok(imports)
+    }
+    let mut descriptor = base.wrapping_add(rva);
+    loop {
+        let lookup_rva = mem.read_u32(descriptor)?;
+        let name_rva = mem.read_u32(descriptor + 12)?;
+        let iat_rva = mem.read_u32(descriptor + 16)?;
+        if name_rva == 0 && iat_rva == 0 {
+            break
+        }
+        let dll_name = mem.read_cstr(base.wrapping_add(name_rva))?;
+        // some linkers leave the lookup table out, in which case the IAT doubles as one
+        let lookup = base.wrapping_add(if lookup_rva != 0 { lookup_rva } else { iat_rva });
+        let iat = base.wrapping_add(iat_rva);
+        for i in 0.. {
+            let entry = mem.read_u32(lookup + i * 4)?;
+            if entry == 0 {
+                break
+            }
+            let symbol = if entry & 0x8000_0000 != 0 {
+                Symbol::Ordinal(entry as u16)
+            } else {
+                Symbol::Name(mem.read_cstr(base.wrapping_add(entry).wrapping_add(2))?)
+            };
+            imports.push(Import { dll_name: dll_name.clone(), symbol, iat_address: iat + i * 4 });
+        }
+        descriptor += 20;
+    }
+    Ok(imports)
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// Builds a DLL with everything in one section at CODE_RVA: the code first, then the tables.
+    #[derive(Default)]
+    pub struct Fixture {
+        pub base: u32,
+        pub code: Vec<u8>,
+        pub entry_point: Option<u32>,                   // offset into the code
+        pub exports: Vec<(&'static str, u32)>,          // name and offset into the code
+        pub imports: Vec<(&'static str, &'static str)>, // DLL and function, each with its own IAT
+        pub relocations: Vec<u32>, // offsets into the code of addresses, which are written for `base`
+    }
+
+    pub const CODE_RVA: u32 = 0x1000;
+    const IAT_RVA: u32 = 0x1100;
+
+    impl Fixture {
+        /// Where the nth import's address goes, as an RVA.
+        pub fn iat(n: u32) -> u32 {
+            IAT_RVA + n * 8
+        }
+
+        pub fn build(&self) -> Vec<u8> {
+            assert!(self.code.len() <= (IAT_RVA - CODE_RVA) as usize);
+            let mut section = self.code.clone();
+            section.resize((IAT_RVA - CODE_RVA) as usize, 0xCC);
+            let rva = |section: &Vec<u8>| CODE_RVA + section.len() as u32;
+            let put = |section: &mut Vec<u8>, offset: u32, value: u32| {
+                let offset = (offset - CODE_RVA) as usize;
+                section[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            };
+            let cstr = |section: &mut Vec<u8>, s: &str| {
+                let at = rva(section);
+                section.extend(s.as_bytes());
+                section.push(0);
+                at
+            };
+
+            // IATs, then lookup tables, then the import descriptors
+            section.resize(section.len() + self.imports.len() * 16, 0);
+            let descriptors = rva(&section);
+            section.resize(section.len() + (self.imports.len() + 1) * 20, 0);
+            for (i, (dll, function)) in self.imports.iter().enumerate() {
+                let i = i as u32;
+                let lookup = Self::iat(self.imports.len() as u32) + i * 8;
+                let hint_name = rva(&section);
+                section.extend([0, 0]);
+                cstr(&mut section, function);
+                let dll_name = cstr(&mut section, dll);
+                put(&mut section, Self::iat(i), hint_name);
+                put(&mut section, lookup, hint_name);
+                let descriptor = descriptors + i * 20;
+                put(&mut section, descriptor, lookup);
+                put(&mut section, descriptor + 12, dll_name);
+                put(&mut section, descriptor + 16, Self::iat(i));
+            }
+
+            let mut exports = self.exports.clone();
+            exports.sort();
+            let export_dir = rva(&section);
+            section.resize(section.len() + 40, 0);
+            let functions = rva(&section);
+            for (_, offset) in &exports {
+                section.extend((CODE_RVA + offset).to_le_bytes());
+            }
+            let name_ordinals = rva(&section);
+            for i in 0..exports.len() as u16 {
+                section.extend(i.to_le_bytes());
+            }
+            let names = rva(&section);
+            section.resize(section.len() + exports.len() * 4, 0);
+            for (i, (name, _)) in exports.iter().enumerate() {
+                let name = cstr(&mut section, name);
+                put(&mut section, names + i as u32 * 4, name);
+            }
+            for (offset, value) in [(16, 1), (20, exports.len() as u32), (24, exports.len() as u32)] {
+                put(&mut section, export_dir + offset, value);
+            }
+            for (offset, value) in [(28, functions), (32, names), (36, name_ordinals)] {
+                put(&mut section, export_dir + offset, value);
+            }
+
+            while section.len() & 3 != 0 {
+                section.push(0);
+            }
+            let relocs = rva(&section);
+            let mut entries =
+                self.relocations.iter().map(|&r| (IMAGE_REL_BASED_HIGHLOW << 12) | r as u16).collect::<Vec<_>>();
+            if entries.len() % 2 != 0 {
+                entries.push(IMAGE_REL_BASED_ABSOLUTE << 12);
+            }
+            section.extend(CODE_RVA.to_le_bytes());
+            section.extend((8 + entries.len() as u32 * 2).to_le_bytes());
+            section.extend(entries.iter().flat_map(|e| e.to_le_bytes()));
+            let relocs_size = rva(&section) - relocs;
+
+            let section_size = section.len() as u32;
+            let raw_size = (section_size + 0x1FF) & !0x1FF;
+            section.resize(raw_size as usize, 0);
+
+            let mut file = vec![0u8; 0x200];
+            file[..2].copy_from_slice(b"MZ");
+            let mut put = |offset: usize, bytes: &[u8]| file[offset..offset + bytes.len()].copy_from_slice(bytes);
+            put(0x3C, &0x40u32.to_le_bytes());
+            put(0x40, b"PE\0\0");
+            let coff = 0x44;
+            put(coff, &IMAGE_FILE_MACHINE_I386.to_le_bytes());
+            put(coff + 2, &1u16.to_le_bytes());
+            put(coff + 16, &0xE0u16.to_le_bytes());
+            put(coff + 18, &(IMAGE_FILE_DLL | 0x102).to_le_bytes());
+            let optional = coff + 20;
+            put(optional, &IMAGE_NT_OPTIONAL_HDR32_MAGIC.to_le_bytes());
+            put(optional + 16, &self.entry_point.map_or(0, |e| CODE_RVA + e).to_le_bytes());
+            put(optional + 28, &self.base.to_le_bytes());
+            put(optional + 32, &0x1000u32.to_le_bytes());
+            put(optional + 36, &0x200u32.to_le_bytes());
+            put(optional + 56, &(CODE_RVA + ((section_size + 0xFFF) & !0xFFF)).to_le_bytes());
+            put(optional + 60, &0x200u32.to_le_bytes());
+            put(optional + 92, &16u32.to_le_bytes());
+            let directories = [
+                (DIRECTORY_EXPORT, export_dir, 40),
+                (DIRECTORY_IMPORT, descriptors, (self.imports.len() as u32 + 1) * 20),
+                (DIRECTORY_BASERELOC, relocs, relocs_size),
+            ];
+            for (index, rva, size) in directories {
+                put(optional + 96 + index * 8, &rva.to_le_bytes());
+                put(optional + 100 + index * 8, &size.to_le_bytes());
+            }
+            let header = optional + 0xE0;
+            put(header, b".text\0\0\0");
+            put(header + 8, &section_size.to_le_bytes());
+            put(header + 12, &CODE_RVA.to_le_bytes());
+            put(header + 16, &raw_size.to_le_bytes());
+            put(header + 20, &0x200u32.to_le_bytes());
+            put(header + 36, &0xE000_0020u32.to_le_bytes());
+            file.extend(section);
+            file
+        }
+    }
+
+    // nop; nop; mov eax, <address of the 4th byte>; ret
+    fn fixture(base: u32) -> Fixture {
+        let mut code = vec![0x90, 0x90, 0xB8];
+        code.extend((base + CODE_RVA + 3).to_le_bytes());
+        code.push(0xC3);
+        Fixture {
+            base,
+            code,
+            entry_point: Some(1),
+            exports: vec![("second", 1), ("first", 0)],
+            imports: vec![("kernel32.dll", "GetTickCount"), ("other.dll", "Thing")],
+            relocations: vec![3],
+        }
+    }
+
+    #[test]
+    fn headers_exports_and_imports() {
+        let mut mem = Memory::default();
+        let (image, imports) = Image::load(&mut mem, &fixture(0x1000_0000).build()).unwrap();
+        assert_eq!(image.base, 0x1000_0000);
+        assert_eq!(image.entry_point, Some(0x1000_1001));
+        assert_eq!(image.export(b"first"), Some(0x1000_1000));
+        assert_eq!(image.export(b"second"), Some(0x1000_1001));
+        assert_eq!(image.export(b"third"), None);
+        // ordinals follow the sorted names, starting at 1
+        assert_eq!((image.export_ordinal(1), image.export_ordinal(2)), (Some(0x1000_1000), Some(0x1000_1001)));
+        assert_eq!((image.export_ordinal(0), image.export_ordinal(3)), (None, None));
+        assert!(image.tls.is_none());
+
+        let imports = imports.iter().map(|i| match &i.symbol {
+            Symbol::Name(name) => (i.dll_name.as_slice(), name.as_slice(), i.iat_address - image.base),
+            Symbol::Ordinal(_) => panic!("imported by ordinal"),
+        });
+        let expected: &[(&[u8], &[u8], u32)] =
+            &[(b"kernel32.dll", b"GetTickCount", Fixture::iat(0)), (b"other.dll", b"Thing", Fixture::iat(1))];
+        assert_eq!(imports.collect::<Vec<_>>(), expected);
+        // the code's in place, and nothing needed relocating
+        assert_eq!(mem.read_u32(0x1000_1003).unwrap(), 0x1000_1003);
+    }
+
+    #[test]
+    fn relocation() {
+        // something's already where it wants to go
+        let mut mem = Memory::default();
+        mem.map(0x1000_0000, 0x1000, 0x1000).unwrap();
+        let (image, _) = Image::load(&mut mem, &fixture(0x1000_0000).build()).unwrap();
+        assert_ne!(image.base, 0x1000_0000);
+        assert_eq!(mem.read_u32(image.base + CODE_RVA + 3).unwrap(), image.base + CODE_RVA + 3);
+        assert_eq!(image.export(b"first"), Some(image.base + CODE_RVA));
+
+        // which it can't get round without relocations
+        let mut mem = Memory::default();
+        mem.map(0x1000_0000, 0x1000, 0x1000).unwrap();
+        let mut file = fixture(0x1000_0000).build();
+        let relocs = 0x44 + 20 + 96 + DIRECTORY_BASERELOC * 8;
+        file[relocs..relocs + 8].fill(0);
+        assert!(Image::load(&mut mem, &file).is_err());
+    }
+
+    #[test]
+    fn bad_files() {
+        let mut mem = Memory::default();
+        let file = fixture(0x1000_0000).build();
+        assert!(Image::load(&mut mem, b"MZ").is_err());
+        assert!(Image::load(&mut mem, &file[..0x100]).is_err());
+        let mut not_dll = file.clone();
+        not_dll[0x44 + 18..0x44 + 20].copy_from_slice(&0x102u16.to_le_bytes());
+        assert!(Image::load(&mut mem, &not_dll).is_err());
+        let mut x64 = file;
+        x64[0x44..0x46].copy_from_slice(&0x8664u16.to_le_bytes());
+        assert!(Image::load(&mut mem, &x64).is_err());
+    }
+}
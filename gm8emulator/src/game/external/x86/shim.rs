@@ -0,0 +1,192 @@
+//! Just enough of Windows and the C runtime for self-contained DLLs to run.
+//! Anything that would touch the outside world (files, windows, threads) either fails or does nothing,
+//! and clocks are based on how many instructions have run so that results are the same every time.
+
+mod kernel32;
+mod msvcrt;
+mod printf;
+
+use super::{heap::Heap, memory::Memory, Emulator, SYSTEM_MODULE_BASE};
+use serde::{Deserialize, Serialize};
+use shared::dll::CallConv;
+
+pub enum Return {
+    Int(u32),
+    Long(u64),
+    Float(f64), // goes in st0
+}
+
+impl From<u32> for Return {
+    fn from(x: u32) -> Self {
+        Return::Int(x)
+    }
+}
+
+impl From<i32> for Return {
+    fn from(x: i32) -> Self {
+        Return::Int(x as u32)
+    }
+}
+
+impl From<bool> for Return {
+    fn from(x: bool) -> Self {
+        Return::Int(x.into())
+    }
+}
+
+impl From<f64> for Return {
+    fn from(x: f64) -> Self {
+        Return::Float(x)
+    }
+}
+
+pub type Function = fn(&mut Emulator) -> Result<Return, String>;
+
+pub struct Library {
+    call_conv: CallConv,
+    /// Name, bytes of arguments (which only matters for stdcall) and implementation.
+    functions: &'static [(&'static str, u32, Function)],
+}
+
+const NOTHING: Library = Library { call_conv: CallConv::Stdcall, functions: &[] };
+
+/// Libraries are in a fixed order, since it decides their fake module handles.
+static LIBRARIES: &[Library] = &[kernel32::LIBRARY, msvcrt::LIBRARY, kernel32::USER32, NOTHING];
+
+/// Finds the library that stands in for a (lowercase) DLL name, if it's a system one.
+fn library_index(dll: &str) -> Option<usize> {
+    let dll = dll.strip_suffix(".dll").unwrap_or(dll);
+    if dll == "kernel32" || dll == "kernelbase" || dll.starts_with("api-ms-win-core-") {
+        Some(0)
+    } else if dll == "msvcrt"
+        || dll == "ucrtbase"
+        || dll.starts_with("msvcr")
+        || dll.starts_with("vcruntime")
+        || dll.starts_with("api-ms-win-crt-")
+    {
+        Some(1)
+    } else if dll == "user32" {
+        Some(2)
+    } else if dll.starts_with("msvcp")
+        || [
+            "advapi32", "comctl32", "comdlg32", "d3d8", "d3d9", "ddraw", "dsound", "gdi32", "gdiplus", "ntdll",
+            "ole32", "oleaut32", "opengl32", "shell32", "shlwapi", "version", "winmm", "wininet", "ws2_32", "wsock32",
+        ]
+        .contains(&dll)
+    {
+        // these won't work, but it's better to fail when something actually gets called
+        Some(3)
+    } else {
+        None
+    }
+}
+
+/// Whether a DLL is part of Windows, so imports from it should go to the shim rather than a file.
+pub fn is_system_dll(dll: &str) -> bool {
+    library_index(dll).is_some()
+}
+
+/// Finds a shim function, along with how to clean up after it.
+pub fn find(dll: &str, name: &str) -> Option<(Function, CallConv, u32)> {
+    let library = &LIBRARIES[library_index(dll)?];
+    library.functions.iter().find(|(n, _, _)| *n == name).map(|&(_, bytes, f)| (f, library.call_conv, bytes))
+}
+
+/// The fake module handle for a system DLL.
+fn system_module(dll: &str) -> Option<u32> {
+    library_index(dll).map(|i| SYSTEM_MODULE_BASE + i as u32 * 0x10000)
+}
+
+/// Goes from a fake module handle back to a name the library can be found by.
+fn system_module_name(handle: u32) -> Option<&'static str> {
+    match handle.checked_sub(SYSTEM_MODULE_BASE)? / 0x10000 {
+        0 => Some("kernel32.dll"),
+        1 => Some("msvcrt.dll"),
+        2 => Some("user32.dll"),
+        _ => None,
+    }
+}
+
+/// Everything the shim keeps track of between calls.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct State {
+    tls_slots: u64, // bit per TlsAlloc slot in use
+    rand_seed: u32,
+    strtok_next: u32,
+    errno: u32,
+    command_line: u32,
+    command_line_w: u32,
+    environment: u32,
+    locale: u32,
+    iob: u32,
+}
+
+impl State {
+    pub fn init(&mut self, heap: &mut Heap, mem: &mut Memory) -> Result<(), String> {
+        let mut alloc = |data: &[u8]| -> Result<u32, String> {
+            let ptr = heap.alloc(mem, data.len() as u32)?;
+            mem.write(ptr, data)?;
+            Ok(ptr)
+        };
+        self.rand_seed = 1;
+        self.errno = alloc(&[0; 4])?;
+        self.command_line = alloc(b"game.exe\0")?;
+        self.command_line_w = alloc(&wide(b"game.exe\0"))?;
+        self.environment = alloc(&[0; 4])?; // empty for both the narrow and wide versions
+        self.locale = alloc(b"C\0")?;
+        self.iob = alloc(&[0; 32 * 3])?; // stdin, stdout and stderr
+        Ok(())
+    }
+}
+
+/// Widens a Latin-1 string to UTF-16.
+fn wide(s: &[u8]) -> Vec<u8> {
+    s.iter().flat_map(|&c| [c, 0]).collect()
+}
+
+/// Turns a host path into something that looks like a Windows path, the same way Wine does.
+fn windows_path(path: &std::path::Path) -> Vec<u8> {
+    let path = path.to_string_lossy();
+    if cfg!(target_os = "windows") {
+        path.as_bytes().to_vec()
+    } else {
+        format!("Z:{}", path.replace('/', "\\")).into_bytes()
+    }
+}
+
+impl Emulator {
+    /// The address of the nth stack argument, for varargs.
+    fn arg_address(&self, n: u32) -> u32 {
+        self.cpu.regs[super::cpu::ESP] + 4 + n * 4
+    }
+
+    /// Reads a string argument, which could be narrow or wide.
+    fn str_arg(&self, n: u32, wide: bool) -> Result<Vec<u8>, String> {
+        let ptr = self.arg(n)?;
+        if wide {
+            Ok(self.mem.read_wstr(ptr)?.into_iter().map(|c| if c < 0x100 { c as u8 } else { b'?' }).collect())
+        } else {
+            self.mem.read_cstr(ptr)
+        }
+    }
+
+    /// Copies a string into a buffer of `size` characters, Windows style: it gets cut off if it doesn't fit,
+    /// and the length (not including the terminator) is returned.
+    fn write_str(&mut self, ptr: u32, size: u32, s: &[u8], wide: bool) -> Result<u32, String> {
+        if size == 0 {
+            return Ok(0)
+        }
+        let len = (s.len() as u32).min(size - 1);
+        let mut data = s[..len as usize].to_vec();
+        data.push(0);
+        if wide {
+            data = self::wide(&data);
+        }
+        self.mem.write(ptr, &data)?;
+        Ok(len)
+    }
+
+    fn alloc(&mut self, size: u32) -> Result<u32, String> {
+        self.heap.alloc(&mut self.mem, size)
+    }
+}
@@ -0,0 +1,1092 @@
+//! KERNEL32.dll, plus a tiny bit of USER32.dll.
+
+use super::{system_module, system_module_name, windows_path, Library, Return};
+use crate::game::external::x86::{
+    EXE_BASE, Emulator, HEAP_BASE, PROCESS_ID, TEB_BASE, TEB_LAST_ERROR, TEB_TLS_SLOTS, THREAD_ID,
+};
+use shared::dll::CallConv;
+
+pub const LIBRARY: Library = Library {
+    call_conv: CallConv::Stdcall,
+    functions: &[
+        ("AreFileApisANSI", 0, are_file_apis_ansi),
+        ("CloseHandle", 4, close_handle),
+        ("CompareStringA", 24, compare_string_a),
+        ("CompareStringW", 24, compare_string_w),
+        ("CreateFileA", 28, create_file),
+        ("CreateFileW", 28, create_file),
+        ("DecodePointer", 4, encode_pointer),
+        ("DeleteCriticalSection", 4, critical_section),
+        ("DisableThreadLibraryCalls", 4, disable_thread_library_calls),
+        ("EncodePointer", 4, encode_pointer),
+        ("EnterCriticalSection", 4, critical_section),
+        ("ExitProcess", 4, exit_process),
+        ("ExitThread", 4, exit_process),
+        ("FlsAlloc", 4, tls_alloc),
+        ("FlsFree", 4, tls_free),
+        ("FlsGetValue", 4, tls_get_value),
+        ("FlsSetValue", 8, tls_set_value),
+        ("FreeEnvironmentStringsA", 4, free_environment_strings),
+        ("FreeEnvironmentStringsW", 4, free_environment_strings),
+        ("FreeLibrary", 4, free_library),
+        ("GetACP", 0, get_acp),
+        ("GetCPInfo", 8, get_cp_info),
+        ("GetCommandLineA", 0, get_command_line_a),
+        ("GetCommandLineW", 0, get_command_line_w),
+        ("GetCurrentProcess", 0, get_current_process),
+        ("GetCurrentProcessId", 0, get_current_process_id),
+        ("GetCurrentThread", 0, get_current_thread),
+        ("GetCurrentThreadId", 0, get_current_thread_id),
+        ("GetEnvironmentStrings", 0, get_environment_strings),
+        ("GetEnvironmentStringsA", 0, get_environment_strings),
+        ("GetEnvironmentStringsW", 0, get_environment_strings),
+        ("GetEnvironmentVariableA", 12, get_environment_variable),
+        ("GetEnvironmentVariableW", 12, get_environment_variable),
+        ("GetFileAttributesA", 4, get_file_attributes),
+        ("GetFileAttributesW", 4, get_file_attributes),
+        ("GetFileType", 4, get_file_type),
+        ("GetLastError", 0, get_last_error),
+        ("GetLocalTime", 4, get_system_time),
+        ("GetModuleFileNameA", 12, get_module_file_name_a),
+        ("GetModuleFileNameW", 12, get_module_file_name_w),
+        ("GetModuleHandleA", 4, get_module_handle_a),
+        ("GetModuleHandleExA", 12, get_module_handle_ex_a),
+        ("GetModuleHandleExW", 12, get_module_handle_ex_w),
+        ("GetModuleHandleW", 4, get_module_handle_w),
+        ("GetOEMCP", 0, get_oem_cp),
+        ("GetProcAddress", 8, get_proc_address),
+        ("GetProcessHeap", 0, get_process_heap),
+        ("GetStartupInfoA", 4, get_startup_info),
+        ("GetStartupInfoW", 4, get_startup_info),
+        ("GetStdHandle", 4, get_std_handle),
+        ("GetStringTypeA", 20, get_string_type_a),
+        ("GetStringTypeW", 16, get_string_type_w),
+        ("GetSystemInfo", 4, get_system_info),
+        ("GetSystemTime", 4, get_system_time),
+        ("GetSystemTimeAsFileTime", 4, get_system_time_as_file_time),
+        ("GetTickCount", 0, get_tick_count),
+        ("GetTickCount64", 0, get_tick_count_64),
+        ("GetUserDefaultLCID", 0, get_user_default_lcid),
+        ("GetVersion", 0, get_version),
+        ("GetVersionExA", 4, get_version_ex),
+        ("GetVersionExW", 4, get_version_ex),
+        ("GlobalAlloc", 8, local_alloc),
+        ("GlobalFree", 4, local_free),
+        ("GlobalLock", 4, encode_pointer),
+        ("GlobalUnlock", 4, global_unlock),
+        ("HeapAlloc", 12, heap_alloc),
+        ("HeapCreate", 12, heap_create),
+        ("HeapDestroy", 4, heap_destroy),
+        ("HeapFree", 12, heap_free),
+        ("HeapReAlloc", 16, heap_realloc),
+        ("HeapSize", 12, heap_size),
+        ("HeapValidate", 12, heap_validate),
+        ("InitializeCriticalSection", 4, critical_section),
+        ("InitializeCriticalSectionAndSpinCount", 8, initialize_critical_section_ex),
+        ("InitializeCriticalSectionEx", 12, initialize_critical_section_ex),
+        ("InitializeSListHead", 4, initialize_slist_head),
+        ("InterlockedCompareExchange", 12, interlocked_compare_exchange),
+        ("InterlockedDecrement", 4, interlocked_decrement),
+        ("InterlockedExchange", 8, interlocked_exchange),
+        ("InterlockedExchangeAdd", 8, interlocked_exchange_add),
+        ("InterlockedFlushSList", 4, interlocked_flush_slist),
+        ("InterlockedIncrement", 4, interlocked_increment),
+        ("IsDebuggerPresent", 0, is_debugger_present),
+        ("IsProcessorFeaturePresent", 4, is_processor_feature_present),
+        ("IsValidCodePage", 4, is_valid_code_page),
+        ("IsValidLocale", 8, is_valid_locale),
+        ("LCMapStringA", 24, lc_map_string_a),
+        ("LCMapStringEx", 36, lc_map_string_ex),
+        ("LCMapStringW", 24, lc_map_string_w),
+        ("LeaveCriticalSection", 4, critical_section),
+        ("LoadLibraryA", 4, load_library_a),
+        ("LoadLibraryExA", 12, load_library_a),
+        ("LoadLibraryExW", 12, load_library_w),
+        ("LoadLibraryW", 4, load_library_w),
+        ("LocalAlloc", 8, local_alloc),
+        ("LocalFree", 4, local_free),
+        ("MulDiv", 12, mul_div),
+        ("MultiByteToWideChar", 24, multi_byte_to_wide_char),
+        ("OutputDebugStringA", 4, output_debug_string),
+        ("OutputDebugStringW", 4, output_debug_string),
+        ("QueryPerformanceCounter", 4, query_performance_counter),
+        ("QueryPerformanceFrequency", 4, query_performance_frequency),
+        ("RaiseException", 16, raise_exception),
+        ("SetErrorMode", 4, set_error_mode),
+        ("SetHandleCount", 4, encode_pointer),
+        ("SetLastError", 4, set_last_error),
+        ("SetUnhandledExceptionFilter", 4, set_unhandled_exception_filter),
+        ("Sleep", 4, sleep),
+        ("TerminateProcess", 8, exit_process),
+        ("TlsAlloc", 0, tls_alloc),
+        ("TlsFree", 4, tls_free),
+        ("TlsGetValue", 4, tls_get_value),
+        ("TlsSetValue", 8, tls_set_value),
+        ("TryEnterCriticalSection", 4, try_enter_critical_section),
+        ("UnhandledExceptionFilter", 4, unhandled_exception_filter),
+        ("VirtualAlloc", 16, virtual_alloc),
+        ("VirtualFree", 12, virtual_free),
+        ("VirtualProtect", 16, virtual_protect),
+        ("VirtualQuery", 12, virtual_query),
+        ("WideCharToMultiByte", 32, wide_char_to_multi_byte),
+        ("WriteFile", 20, write_file),
+        ("lstrcatA", 8, lstrcat_a),
+        ("lstrcmpA", 8, lstrcmp_a),
+        ("lstrcmpiA", 8, lstrcmpi_a),
+        ("lstrcpyA", 8, lstrcpy_a),
+        ("lstrcpynA", 12, lstrcpyn_a),
+        ("lstrlenA", 4, lstrlen_a),
+        ("lstrlenW", 4, lstrlen_w),
+    ],
+};
+
+pub const USER32: Library = Library {
+    call_conv: CallConv::Stdcall,
+    functions: &[("MessageBoxA", 16, message_box_a), ("MessageBoxW", 16, message_box_w)],
+};
+
+const ERROR_FILE_NOT_FOUND: u32 = 2;
+const ERROR_INVALID_PARAMETER: u32 = 87;
+const ERROR_INSUFFICIENT_BUFFER: u32 = 122;
+const ERROR_MOD_NOT_FOUND: u32 = 126;
+const ERROR_PROC_NOT_FOUND: u32 = 127;
+const ERROR_ENVVAR_NOT_FOUND: u32 = 203;
+
+const INVALID_HANDLE_VALUE: u32 = u32::MAX;
+const PROCESS_HEAP: u32 = HEAP_BASE;
+const PAGE_EXECUTE_READWRITE: u32 = 0x40;
+const VIRTUAL_ALLOC_BASE: u32 = 0x1000_0000;
+const CP_UTF8: u32 = 65001;
+
+/// The emulated CPU pretends to run this many instructions per second, for the purpose of telling the time.
+pub(super) const INSTRUCTIONS_PER_SECOND: u64 = 100_000_000;
+
+/// 2010-01-01 00:00:00 as a FILETIME, which is when the clock starts.
+pub(super) const FILETIME_START: u64 = 129_067_776_000_000_000;
+
+type Result = std::result::Result<Return, String>;
+
+impl Emulator {
+    /// The current time in 100ns units since 1601, which is what FILETIME uses.
+    pub(super) fn file_time(&self) -> u64 {
+        FILETIME_START + self.cpu.ticks / (INSTRUCTIONS_PER_SECOND / 10_000_000)
+    }
+
+    /// Finds the handle for a module name, which could be a shimmed system DLL.
+    fn module_handle(&self, name: &[u8]) -> Option<u32> {
+        let name = String::from_utf8_lossy(name).replace('/', "\\");
+        let mut name = name.rsplit('\\').next().unwrap_or_default().to_ascii_lowercase();
+        if !name.contains('.') {
+            name.push_str(".dll");
+        }
+        match self.modules.iter().find(|m| m.name == name && m.error.is_none()) {
+            Some(module) => Some(module.image.base),
+            None => system_module(&name),
+        }
+    }
+
+    fn get_module_file_name(&mut self, wide: bool) -> Result {
+        let (module, buffer, size) = (self.arg(0)?, self.arg(1)?, self.arg(2)?);
+        let path = match module {
+            0 | EXE_BASE => windows_path(&std::env::current_dir().unwrap_or_default().join("game.exe")),
+            _ => match self.modules.iter().find(|m| m.image.base == module) {
+                Some(module) => windows_path(&module.path),
+                None => match system_module_name(module) {
+                    Some(name) => format!("C:\\Windows\\system32\\{}", name).into_bytes(),
+                    None => {
+                        self.set_last_error(ERROR_MOD_NOT_FOUND)?;
+                        return Ok(0.into())
+                    },
+                },
+            },
+        };
+        let len = self.write_str(buffer, size, &path, wide)?;
+        if (len as usize) < path.len() {
+            self.set_last_error(ERROR_INSUFFICIENT_BUFFER)?;
+            return Ok(size.into())
+        }
+        Ok(len.into())
+    }
+
+    fn get_module_handle(&mut self, wide: bool) -> Result {
+        if self.arg(0)? == 0 {
+            return Ok(EXE_BASE.into())
+        }
+        let name = self.str_arg(0, wide)?;
+        match self.module_handle(&name) {
+            Some(handle) => Ok(handle.into()),
+            None => {
+                self.set_last_error(ERROR_MOD_NOT_FOUND)?;
+                Ok(0.into())
+            },
+        }
+    }
+
+    fn get_module_handle_ex(&mut self, wide: bool) -> Result {
+        const FROM_ADDRESS: u32 = 4;
+        let (flags, name, out) = (self.arg(0)?, self.arg(1)?, self.arg(2)?);
+        let handle = if flags & FROM_ADDRESS != 0 {
+            self.modules.iter().find(|m| name.wrapping_sub(m.image.base) < m.image.size).map(|m| m.image.base)
+        } else if name == 0 {
+            Some(EXE_BASE)
+        } else {
+            let name = self.str_arg(1, wide)?;
+            self.module_handle(&name)
+        };
+        if out != 0 {
+            self.mem.write_u32(out, handle.unwrap_or(0))?;
+        }
+        if handle.is_none() {
+            self.set_last_error(ERROR_MOD_NOT_FOUND)?;
+        }
+        Ok(handle.is_some().into())
+    }
+
+    fn load_library(&mut self, wide: bool) -> Result {
+        let name = self.str_arg(0, wide)?;
+        if let Some(handle) = self.module_handle(&name) {
+            return Ok(handle.into())
+        }
+        match self.load(&String::from_utf8_lossy(&name)) {
+            Ok(index) => Ok(self.modules[index].image.base.into()),
+            Err(_) => {
+                self.set_last_error(ERROR_MOD_NOT_FOUND)?;
+                Ok(0.into())
+            },
+        }
+    }
+
+    fn tls_slot(&self, index: u32) -> Option<u32> {
+        if index < 64 && self.shim.tls_slots & (1 << index) != 0 {
+            Some(TEB_BASE + TEB_TLS_SLOTS + index * 4)
+        } else {
+            None
+        }
+    }
+}
+
+fn are_file_apis_ansi(_emu: &mut Emulator) -> Result {
+    Ok(1.into())
+}
+
+fn close_handle(_emu: &mut Emulator) -> Result {
+    Ok(1.into())
+}
+
+/// Compares two strings, giving CSTR_LESS_THAN, CSTR_EQUAL or CSTR_GREATER_THAN.
+fn compare_strings(emu: &Emulator, wide: bool) -> Result {
+    const NORM_IGNORECASE: u32 = 1;
+    let ignore_case = emu.arg(1)? & NORM_IGNORECASE != 0;
+    let mut a = read_counted(emu, emu.arg(2)?, emu.arg(3)? as i32, wide)?;
+    let mut b = read_counted(emu, emu.arg(4)?, emu.arg(5)? as i32, wide)?;
+    if ignore_case {
+        a.iter_mut().chain(b.iter_mut()).for_each(|c| *c = to_lower(*c));
+    }
+    Ok((a.cmp(&b) as i32 + 2).into())
+}
+
+fn compare_string_a(emu: &mut Emulator) -> Result {
+    compare_strings(emu, false)
+}
+
+fn compare_string_w(emu: &mut Emulator) -> Result {
+    compare_strings(emu, true)
+}
+
+fn create_file(emu: &mut Emulator) -> Result {
+    // TODO: files aren't available to emulated DLLs
+    emu.set_last_error(ERROR_FILE_NOT_FOUND)?;
+    Ok(INVALID_HANDLE_VALUE.into())
+}
+
+fn critical_section(_emu: &mut Emulator) -> Result {
+    // there's only one thread, so these don't need to do anything
+    Ok(0.into())
+}
+
+fn disable_thread_library_calls(_emu: &mut Emulator) -> Result {
+    Ok(1.into())
+}
+
+fn encode_pointer(emu: &mut Emulator) -> Result {
+    Ok(emu.arg(0)?.into())
+}
+
+fn exit_process(emu: &mut Emulator) -> Result {
+    Err(format!("DLL tried to exit the process (exit code {})", emu.arg(0)?))
+}
+
+fn free_environment_strings(_emu: &mut Emulator) -> Result {
+    Ok(1.into())
+}
+
+fn free_library(_emu: &mut Emulator) -> Result {
+    // modules stay loaded so that nothing has to be undone
+    Ok(1.into())
+}
+
+fn get_acp(_emu: &mut Emulator) -> Result {
+    Ok(1252.into())
+}
+
+fn get_cp_info(emu: &mut Emulator) -> Result {
+    let info = emu.arg(1)?;
+    let mut data = [0; 20];
+    data[0] = 1; // MaxCharSize
+    data[4] = b'?'; // DefaultChar
+    emu.mem.write(info, &data)?;
+    Ok(1.into())
+}
+
+fn get_command_line_a(emu: &mut Emulator) -> Result {
+    Ok(emu.shim.command_line.into())
+}
+
+fn get_command_line_w(emu: &mut Emulator) -> Result {
+    Ok(emu.shim.command_line_w.into())
+}
+
+fn get_current_process(_emu: &mut Emulator) -> Result {
+    Ok(u32::MAX.into())
+}
+
+fn get_current_process_id(_emu: &mut Emulator) -> Result {
+    Ok(PROCESS_ID.into())
+}
+
+fn get_current_thread(_emu: &mut Emulator) -> Result {
+    Ok((u32::MAX - 1).into())
+}
+
+fn get_current_thread_id(_emu: &mut Emulator) -> Result {
+    Ok(THREAD_ID.into())
+}
+
+fn get_environment_strings(emu: &mut Emulator) -> Result {
+    Ok(emu.shim.environment.into())
+}
+
+fn get_environment_variable(emu: &mut Emulator) -> Result {
+    emu.set_last_error(ERROR_ENVVAR_NOT_FOUND)?;
+    Ok(0.into())
+}
+
+fn get_file_attributes(emu: &mut Emulator) -> Result {
+    emu.set_last_error(ERROR_FILE_NOT_FOUND)?;
+    Ok(u32::MAX.into())
+}
+
+fn get_file_type(emu: &mut Emulator) -> Result {
+    const FILE_TYPE_CHAR: u32 = 2;
+    Ok(if get_std_handle_value(emu.arg(0)?).is_some() { FILE_TYPE_CHAR } else { 0 }.into())
+}
+
+fn get_last_error(emu: &mut Emulator) -> Result {
+    Ok(emu.mem.read_u32(TEB_BASE + TEB_LAST_ERROR)?.into())
+}
+
+fn get_module_file_name_a(emu: &mut Emulator) -> Result {
+    emu.get_module_file_name(false)
+}
+
+fn get_module_file_name_w(emu: &mut Emulator) -> Result {
+    emu.get_module_file_name(true)
+}
+
+fn get_module_handle_a(emu: &mut Emulator) -> Result {
+    emu.get_module_handle(false)
+}
+
+fn get_module_handle_w(emu: &mut Emulator) -> Result {
+    emu.get_module_handle(true)
+}
+
+fn get_module_handle_ex_a(emu: &mut Emulator) -> Result {
+    emu.get_module_handle_ex(false)
+}
+
+fn get_module_handle_ex_w(emu: &mut Emulator) -> Result {
+    emu.get_module_handle_ex(true)
+}
+
+fn get_oem_cp(_emu: &mut Emulator) -> Result {
+    Ok(437.into())
+}
+
+fn get_proc_address(emu: &mut Emulator) -> Result {
+    let (module, name) = (emu.arg(0)?, emu.arg(1)?);
+    let name = if name < 0x10000 { format!("#{}", name).into_bytes() } else { emu.mem.read_cstr(name)? };
+    let address = match emu.modules.iter().find(|m| m.image.base == module) {
+        Some(module) => match name.strip_prefix(b"#") {
+            Some(ordinal) => std::str::from_utf8(ordinal)
+                .ok()
+                .and_then(|o| o.parse().ok())
+                .and_then(|o| module.image.export_ordinal(o)),
+            None => module.image.export(&name),
+        },
+        None => match system_module_name(module) {
+            // only hand out functions that exist, since callers often have a fallback if they don't
+            Some(dll) => {
+                let name = String::from_utf8_lossy(&name).into_owned();
+                if super::find(dll, &name).is_some() { Some(emu.thunk(dll, &name)?) } else { None }
+            },
+            None => None,
+        },
+    };
+    if address.is_none() {
+        emu.set_last_error(ERROR_PROC_NOT_FOUND)?;
+    }
+    Ok(address.unwrap_or(0).into())
+}
+
+fn get_process_heap(_emu: &mut Emulator) -> Result {
+    Ok(PROCESS_HEAP.into())
+}
+
+fn get_startup_info(emu: &mut Emulator) -> Result {
+    let info = emu.arg(0)?;
+    let mut data = [0; 68];
+    data[0] = 68;
+    emu.mem.write(info, &data)?;
+    Ok(0.into())
+}
+
+/// Standard handles are just their STD_*_HANDLE value, which is -10, -11 or -12.
+fn get_std_handle_value(handle: u32) -> Option<u32> {
+    if (u32::MAX - 11..=u32::MAX - 9).contains(&handle) { Some(handle) } else { None }
+}
+
+fn get_std_handle(emu: &mut Emulator) -> Result {
+    match get_std_handle_value(emu.arg(0)?) {
+        Some(handle) => Ok(handle.into()),
+        None => {
+            emu.set_last_error(ERROR_INVALID_PARAMETER)?;
+            Ok(INVALID_HANDLE_VALUE.into())
+        },
+    }
+}
+
+/// The CT_CTYPE1 classification of a character.
+fn char_type(c: u16) -> u16 {
+    let (upper, lower, digit, space, punct, cntrl, blank, xdigit, alpha) =
+        (0x1, 0x2, 0x4, 0x8, 0x10, 0x20, 0x40, 0x80, 0x100);
+    if c >= 0x80 {
+        return 0
+    }
+    let c = c as u8;
+    let mut t = 0;
+    if c.is_ascii_uppercase() {
+        t |= upper | alpha;
+    }
+    if c.is_ascii_lowercase() {
+        t |= lower | alpha;
+    }
+    if c.is_ascii_digit() {
+        t |= digit;
+    }
+    if c.is_ascii_hexdigit() {
+        t |= xdigit;
+    }
+    if c.is_ascii_whitespace() || c == 0x0B {
+        t |= space;
+    }
+    if c.is_ascii_punctuation() {
+        t |= punct;
+    }
+    if c.is_ascii_control() {
+        t |= cntrl;
+    }
+    if c == b' ' || c == b'\t' {
+        t |= blank;
+    }
+    t
+}
+
+fn to_lower(c: u16) -> u16 {
+    if c < 0x80 { u16::from((c as u8).to_ascii_lowercase()) } else { c }
+}
+
+fn to_upper(c: u16) -> u16 {
+    if c < 0x80 { u16::from((c as u8).to_ascii_uppercase()) } else { c }
+}
+
+/// Reads a string with an explicit length, or up to the terminator if the length is -1 (in which case
+/// the terminator is included).
+fn read_counted(emu: &Emulator, ptr: u32, len: i32, wide: bool) -> std::result::Result<Vec<u16>, String> {
+    let mut s = match (len, wide) {
+        (len, true) if len >= 0 => {
+            (0..len as u32).map(|i| emu.mem.read_u16(ptr + i * 2)).collect::<std::result::Result<_, _>>()?
+        },
+        (len, false) if len >= 0 => emu.mem.slice(ptr, len as usize)?.iter().map(|&c| u16::from(c)).collect(),
+        (_, true) => emu.mem.read_wstr(ptr)?,
+        (_, false) => emu.mem.read_cstr(ptr)?.into_iter().map(u16::from).collect(),
+    };
+    if len < 0 {
+        s.push(0);
+    }
+    Ok(s)
+}
+
+fn get_string_type(emu: &mut Emulator, first_arg: u32, wide: bool) -> Result {
+    const CT_CTYPE1: u32 = 1;
+    let (info_type, src, len, out) =
+        (emu.arg(first_arg)?, emu.arg(first_arg + 1)?, emu.arg(first_arg + 2)? as i32, emu.arg(first_arg + 3)?);
+    if info_type != CT_CTYPE1 {
+        emu.set_last_error(ERROR_INVALID_PARAMETER)?;
+        return Ok(0.into())
+    }
+    let s = read_counted(emu, src, len, wide)?;
+    for (i, &c) in s.iter().enumerate() {
+        emu.mem.write_u16(out + i as u32 * 2, char_type(c))?;
+    }
+    Ok(1.into())
+}
+
+fn get_string_type_a(emu: &mut Emulator) -> Result {
+    // this one has a locale first
+    get_string_type(emu, 1, false)
+}
+
+fn get_string_type_w(emu: &mut Emulator) -> Result {
+    get_string_type(emu, 0, true)
+}
+
+fn get_system_info(emu: &mut Emulator) -> Result {
+    let info = emu.arg(0)?;
+    let fields: [u32; 9] = [0, 0x1000, 0x10000, 0x7FFE_FFFF, 1, 1, 586, 0x10000, 0x0006_0000];
+    for (i, field) in fields.iter().enumerate() {
+        emu.mem.write_u32(info + i as u32 * 4, *field)?;
+    }
+    Ok(0.into())
+}
+
+fn get_system_time(emu: &mut Emulator) -> Result {
+    let out = emu.arg(0)?;
+    let time = emu.file_time();
+    let seconds = time / 10_000_000;
+    let days = (seconds / 86400) as i64 - 134_774; // days between 1601 and 1970
+    let (year, month, day) = civil_from_days(days);
+    let fields = [
+        year as u16,
+        month as u16,
+        ((days + 4).rem_euclid(7)) as u16, // 1970-01-01 was a thursday
+        day as u16,
+        (seconds / 3600 % 24) as u16,
+        (seconds / 60 % 60) as u16,
+        (seconds % 60) as u16,
+        (time / 10_000 % 1000) as u16,
+    ];
+    for (i, field) in fields.iter().enumerate() {
+        emu.mem.write_u16(out + i as u32 * 2, *field)?;
+    }
+    Ok(0.into())
+}
+
+/// Turns days since 1970-01-01 into a (year, month, day) date.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+fn get_system_time_as_file_time(emu: &mut Emulator) -> Result {
+    let out = emu.arg(0)?;
+    emu.mem.write_u64(out, emu.file_time())?;
+    Ok(0.into())
+}
+
+fn get_tick_count(emu: &mut Emulator) -> Result {
+    Ok(Return::Int((emu.cpu.ticks / (INSTRUCTIONS_PER_SECOND / 1000)) as u32))
+}
+
+fn get_tick_count_64(emu: &mut Emulator) -> Result {
+    Ok(Return::Long(emu.cpu.ticks / (INSTRUCTIONS_PER_SECOND / 1000)))
+}
+
+fn get_user_default_lcid(_emu: &mut Emulator) -> Result {
+    Ok(0x409.into())
+}
+
+fn get_version(_emu: &mut Emulator) -> Result {
+    // windows 7 SP1, which is what most of these DLLs were tested on
+    Ok(0x1DB1_0106.into())
+}
+
+fn get_version_ex(emu: &mut Emulator) -> Result {
+    let info = emu.arg(0)?;
+    for (i, field) in [6, 1, 7601, 2].iter().enumerate() {
+        emu.mem.write_u32(info + 4 + i as u32 * 4, *field)?;
+    }
+    Ok(1.into())
+}
+
+fn global_unlock(_emu: &mut Emulator) -> Result {
+    Ok(1.into())
+}
+
+fn heap_alloc(emu: &mut Emulator) -> Result {
+    // the heap hands out zeroed memory anyway, so HEAP_ZERO_MEMORY doesn't matter
+    let size = emu.arg(2)?;
+    Ok(emu.alloc(size).unwrap_or(0).into())
+}
+
+fn heap_create(_emu: &mut Emulator) -> Result {
+    // everyone shares the process heap
+    Ok(PROCESS_HEAP.into())
+}
+
+fn heap_destroy(_emu: &mut Emulator) -> Result {
+    Ok(1.into())
+}
+
+fn heap_free(emu: &mut Emulator) -> Result {
+    let ptr = emu.arg(2)?;
+    emu.heap.free(ptr);
+    Ok(1.into())
+}
+
+fn heap_realloc(emu: &mut Emulator) -> Result {
+    let (ptr, size) = (emu.arg(2)?, emu.arg(3)?);
+    Ok(emu.heap.realloc(&mut emu.mem, ptr, size).unwrap_or(0).into())
+}
+
+fn heap_size(emu: &mut Emulator) -> Result {
+    Ok(emu.heap.size(emu.arg(2)?).unwrap_or(u32::MAX).into())
+}
+
+fn heap_validate(_emu: &mut Emulator) -> Result {
+    Ok(1.into())
+}
+
+fn initialize_critical_section_ex(_emu: &mut Emulator) -> Result {
+    Ok(1.into())
+}
+
+fn initialize_slist_head(emu: &mut Emulator) -> Result {
+    emu.mem.write_u64(emu.arg(0)?, 0)?;
+    Ok(0.into())
+}
+
+fn interlocked_compare_exchange(emu: &mut Emulator) -> Result {
+    let (ptr, exchange, comparand) = (emu.arg(0)?, emu.arg(1)?, emu.arg(2)?);
+    let old = emu.mem.read_u32(ptr)?;
+    if old == comparand {
+        emu.mem.write_u32(ptr, exchange)?;
+    }
+    Ok(old.into())
+}
+
+fn interlocked_decrement(emu: &mut Emulator) -> Result {
+    let ptr = emu.arg(0)?;
+    let value = emu.mem.read_u32(ptr)?.wrapping_sub(1);
+    emu.mem.write_u32(ptr, value)?;
+    Ok(value.into())
+}
+
+fn interlocked_exchange(emu: &mut Emulator) -> Result {
+    let (ptr, value) = (emu.arg(0)?, emu.arg(1)?);
+    let old = emu.mem.read_u32(ptr)?;
+    emu.mem.write_u32(ptr, value)?;
+    Ok(old.into())
+}
+
+fn interlocked_exchange_add(emu: &mut Emulator) -> Result {
+    let (ptr, value) = (emu.arg(0)?, emu.arg(1)?);
+    let old = emu.mem.read_u32(ptr)?;
+    emu.mem.write_u32(ptr, old.wrapping_add(value))?;
+    Ok(old.into())
+}
+
+fn interlocked_flush_slist(emu: &mut Emulator) -> Result {
+    let head = emu.arg(0)?;
+    let first = emu.mem.read_u32(head)?;
+    emu.mem.write_u32(head, 0)?;
+    Ok(first.into())
+}
+
+fn interlocked_increment(emu: &mut Emulator) -> Result {
+    let ptr = emu.arg(0)?;
+    let value = emu.mem.read_u32(ptr)?.wrapping_add(1);
+    emu.mem.write_u32(ptr, value)?;
+    Ok(value.into())
+}
+
+fn is_debugger_present(_emu: &mut Emulator) -> Result {
+    Ok(0.into())
+}
+
+fn is_processor_feature_present(emu: &mut Emulator) -> Result {
+    // this has to agree with what cpuid says
+    const PF_COMPARE_EXCHANGE_DOUBLE: u32 = 2;
+    const PF_RDTSC_INSTRUCTION_AVAILABLE: u32 = 8;
+    let feature = emu.arg(0)?;
+    Ok((feature == PF_COMPARE_EXCHANGE_DOUBLE || feature == PF_RDTSC_INSTRUCTION_AVAILABLE).into())
+}
+
+fn is_valid_code_page(_emu: &mut Emulator) -> Result {
+    Ok(1.into())
+}
+
+fn is_valid_locale(_emu: &mut Emulator) -> Result {
+    Ok(1.into())
+}
+
+/// LCMapString, only supporting case mapping. Arguments start from the flags.
+fn lc_map_string(emu: &mut Emulator, first_arg: u32, wide: bool) -> Result {
+    const LCMAP_LOWERCASE: u32 = 0x100;
+    const LCMAP_UPPERCASE: u32 = 0x200;
+    let flags = emu.arg(first_arg)?;
+    let (src, len) = (emu.arg(first_arg + 1)?, emu.arg(first_arg + 2)? as i32);
+    let (dst, dst_len) = (emu.arg(first_arg + 3)?, emu.arg(first_arg + 4)?);
+    let mut s = read_counted(emu, src, len, wide)?;
+    if flags & LCMAP_LOWERCASE != 0 {
+        s.iter_mut().for_each(|c| *c = to_lower(*c));
+    } else if flags & LCMAP_UPPERCASE != 0 {
+        s.iter_mut().for_each(|c| *c = to_upper(*c));
+    }
+    if dst_len == 0 {
+        return Ok((s.len() as u32).into())
+    }
+    if (dst_len as usize) < s.len() {
+        emu.set_last_error(ERROR_INSUFFICIENT_BUFFER)?;
+        return Ok(0.into())
+    }
+    for (i, &c) in s.iter().enumerate() {
+        if wide {
+            emu.mem.write_u16(dst + i as u32 * 2, c)?;
+        } else {
+            emu.mem.write_u8(dst + i as u32, c as u8)?;
+        }
+    }
+    Ok((s.len() as u32).into())
+}
+
+fn lc_map_string_a(emu: &mut Emulator) -> Result {
+    lc_map_string(emu, 1, false)
+}
+
+fn lc_map_string_ex(emu: &mut Emulator) -> Result {
+    lc_map_string(emu, 1, true)
+}
+
+fn lc_map_string_w(emu: &mut Emulator) -> Result {
+    lc_map_string(emu, 1, true)
+}
+
+fn load_library_a(emu: &mut Emulator) -> Result {
+    emu.load_library(false)
+}
+
+fn load_library_w(emu: &mut Emulator) -> Result {
+    emu.load_library(true)
+}
+
+fn local_alloc(emu: &mut Emulator) -> Result {
+    // there's no such thing as moveable memory here, so handles are just pointers
+    let size = emu.arg(1)?;
+    Ok(emu.alloc(size).unwrap_or(0).into())
+}
+
+fn local_free(emu: &mut Emulator) -> Result {
+    let ptr = emu.arg(0)?;
+    Ok(if emu.heap.free(ptr) || ptr == 0 { 0 } else { ptr }.into())
+}
+
+fn mul_div(emu: &mut Emulator) -> Result {
+    let (a, b, c) = (i64::from(emu.arg(0)? as i32), i64::from(emu.arg(1)? as i32), i64::from(emu.arg(2)? as i32));
+    if c == 0 {
+        return Ok((-1).into())
+    }
+    // rounds half away from zero
+    let product = a * b;
+    let result = (product + (c.abs() / 2) * product.signum() * c.signum()) / c;
+    Ok((if result > i64::from(i32::MAX) || result < i64::from(i32::MIN) { -1 } else { result as i32 }).into())
+}
+
+fn multi_byte_to_wide_char(emu: &mut Emulator) -> Result {
+    let (code_page, src, len, dst, dst_len) = (emu.arg(0)?, emu.arg(2)?, emu.arg(3)? as i32, emu.arg(4)?, emu.arg(5)?);
+    let bytes = read_counted(emu, src, len, false)?.into_iter().map(|c| c as u8).collect::<Vec<_>>();
+    let chars: Vec<u16> = if code_page == CP_UTF8 {
+        String::from_utf8_lossy(&bytes).encode_utf16().collect()
+    } else {
+        bytes.iter().map(|&c| u16::from(c)).collect()
+    };
+    if dst_len == 0 {
+        return Ok((chars.len() as u32).into())
+    }
+    if (dst_len as usize) < chars.len() {
+        emu.set_last_error(ERROR_INSUFFICIENT_BUFFER)?;
+        return Ok(0.into())
+    }
+    for (i, &c) in chars.iter().enumerate() {
+        emu.mem.write_u16(dst + i as u32 * 2, c)?;
+    }
+    Ok((chars.len() as u32).into())
+}
+
+fn wide_char_to_multi_byte(emu: &mut Emulator) -> Result {
+    let (code_page, src, len, dst, dst_len, used_default) =
+        (emu.arg(0)?, emu.arg(2)?, emu.arg(3)? as i32, emu.arg(4)?, emu.arg(5)?, emu.arg(7)?);
+    let chars = read_counted(emu, src, len, true)?;
+    let mut defaulted = false;
+    let bytes: Vec<u8> = if code_page == CP_UTF8 {
+        String::from_utf16_lossy(&chars).into_bytes()
+    } else {
+        chars
+            .iter()
+            .map(|&c| {
+                if c < 0x100 {
+                    c as u8
+                } else {
+                    defaulted = true;
+                    b'?'
+                }
+            })
+            .collect()
+    };
+    if used_default != 0 {
+        emu.mem.write_u32(used_default, defaulted.into())?;
+    }
+    if dst_len == 0 {
+        return Ok((bytes.len() as u32).into())
+    }
+    if (dst_len as usize) < bytes.len() {
+        emu.set_last_error(ERROR_INSUFFICIENT_BUFFER)?;
+        return Ok(0.into())
+    }
+    emu.mem.write(dst, &bytes)?;
+    Ok((bytes.len() as u32).into())
+}
+
+fn output_debug_string(_emu: &mut Emulator) -> Result {
+    Ok(0.into())
+}
+
+fn query_performance_counter(emu: &mut Emulator) -> Result {
+    let out = emu.arg(0)?;
+    emu.mem.write_u64(out, emu.file_time() - FILETIME_START)?;
+    Ok(1.into())
+}
+
+fn query_performance_frequency(emu: &mut Emulator) -> Result {
+    emu.mem.write_u64(emu.arg(0)?, 10_000_000)?;
+    Ok(1.into())
+}
+
+fn raise_exception(emu: &mut Emulator) -> Result {
+    Err(format!("DLL raised exception {:#010X}, which can't be handled", emu.arg(0)?))
+}
+
+fn set_error_mode(_emu: &mut Emulator) -> Result {
+    Ok(0.into())
+}
+
+fn set_last_error(emu: &mut Emulator) -> Result {
+    let error = emu.arg(0)?;
+    emu.set_last_error(error)?;
+    Ok(0.into())
+}
+
+fn set_unhandled_exception_filter(_emu: &mut Emulator) -> Result {
+    Ok(0.into())
+}
+
+fn sleep(emu: &mut Emulator) -> Result {
+    // time only moves when instructions run, so make some time pass for anything waiting on the clock
+    let ms = u64::from(emu.arg(0)?).min(1000);
+    emu.cpu.ticks += ms * (INSTRUCTIONS_PER_SECOND / 1000);
+    Ok(0.into())
+}
+
+fn tls_alloc(emu: &mut Emulator) -> Result {
+    let slot = (!emu.shim.tls_slots).trailing_zeros();
+    if slot >= 64 {
+        return Ok(u32::MAX.into())
+    }
+    emu.shim.tls_slots |= 1 << slot;
+    emu.mem.write_u32(TEB_BASE + TEB_TLS_SLOTS + slot * 4, 0)?;
+    Ok(slot.into())
+}
+
+fn tls_free(emu: &mut Emulator) -> Result {
+    let index = emu.arg(0)?;
+    if emu.tls_slot(index).is_none() {
+        emu.set_last_error(ERROR_INVALID_PARAMETER)?;
+        return Ok(0.into())
+    }
+    emu.shim.tls_slots &= !(1 << index);
+    Ok(1.into())
+}
+
+fn tls_get_value(emu: &mut Emulator) -> Result {
+    match emu.tls_slot(emu.arg(0)?) {
+        Some(slot) => {
+            emu.set_last_error(0)?;
+            Ok(emu.mem.read_u32(slot)?.into())
+        },
+        None => {
+            emu.set_last_error(ERROR_INVALID_PARAMETER)?;
+            Ok(0.into())
+        },
+    }
+}
+
+fn tls_set_value(emu: &mut Emulator) -> Result {
+    match emu.tls_slot(emu.arg(0)?) {
+        Some(slot) => {
+            emu.mem.write_u32(slot, emu.arg(1)?)?;
+            Ok(1.into())
+        },
+        None => {
+            emu.set_last_error(ERROR_INVALID_PARAMETER)?;
+            Ok(0.into())
+        },
+    }
+}
+
+fn try_enter_critical_section(_emu: &mut Emulator) -> Result {
+    Ok(1.into())
+}
+
+fn unhandled_exception_filter(_emu: &mut Emulator) -> Result {
+    Ok(0.into())
+}
+
+fn virtual_alloc(emu: &mut Emulator) -> Result {
+    let (address, size) = (emu.arg(0)?, emu.arg(1)?);
+    if address != 0 && emu.mem.region_containing(address).is_some() {
+        // committing memory that's already been reserved
+        return Ok(address.into())
+    }
+    let size = match size.checked_add(0xFFF) {
+        Some(size) if size > 0xFFF => size & !0xFFF,
+        _ => {
+            emu.set_last_error(ERROR_INVALID_PARAMETER)?;
+            return Ok(0.into())
+        },
+    };
+    let base = if address != 0 && emu.mem.is_free(address & !0xFFFF, size) {
+        Some(address & !0xFFFF)
+    } else {
+        emu.mem.find_free(size, VIRTUAL_ALLOC_BASE)
+    };
+    match base {
+        Some(base) if emu.mem.map(base, size, size).is_ok() => Ok(base.into()),
+        _ => Ok(0.into()),
+    }
+}
+
+fn virtual_free(emu: &mut Emulator) -> Result {
+    const MEM_RELEASE: u32 = 0x8000;
+    if emu.arg(2)? & MEM_RELEASE != 0 {
+        emu.mem.unmap(emu.arg(0)?);
+    }
+    Ok(1.into())
+}
+
+fn virtual_protect(emu: &mut Emulator) -> Result {
+    let old = emu.arg(3)?;
+    if old != 0 {
+        emu.mem.write_u32(old, PAGE_EXECUTE_READWRITE)?;
+    }
+    Ok(1.into())
+}
+
+fn virtual_query(emu: &mut Emulator) -> Result {
+    const MEM_COMMIT: u32 = 0x1000;
+    const MEM_FREE: u32 = 0x10000;
+    const MEM_PRIVATE: u32 = 0x20000;
+    let (address, info) = (emu.arg(0)?, emu.arg(1)?);
+    let fields = match emu.mem.region_containing(address) {
+        Some((base, size)) => {
+            let page = address & !0xFFF;
+            [page, base, PAGE_EXECUTE_READWRITE, base + size - page, MEM_COMMIT, PAGE_EXECUTE_READWRITE, MEM_PRIVATE]
+        },
+        None => [address & !0xFFF, 0, 0, 0x1000, MEM_FREE, 1, 0],
+    };
+    for (i, field) in fields.iter().enumerate() {
+        emu.mem.write_u32(info + i as u32 * 4, *field)?;
+    }
+    Ok(28.into())
+}
+
+fn write_file(emu: &mut Emulator) -> Result {
+    // output just gets thrown away
+    let (len, written) = (emu.arg(2)?, emu.arg(3)?);
+    if written != 0 {
+        emu.mem.write_u32(written, len)?;
+    }
+    Ok(1.into())
+}
+
+fn lstrcat_a(emu: &mut Emulator) -> Result {
+    let (dst, src) = (emu.arg(0)?, emu.arg(1)?);
+    let mut s = emu.mem.read_cstr(src)?;
+    s.push(0);
+    let len = emu.mem.read_cstr(dst)?.len() as u32;
+    emu.mem.write(dst + len, &s)?;
+    Ok(dst.into())
+}
+
+fn lstrcmp_a(emu: &mut Emulator) -> Result {
+    let (a, b) = (emu.mem.read_cstr(emu.arg(0)?)?, emu.mem.read_cstr(emu.arg(1)?)?);
+    Ok((a.cmp(&b) as i32).into())
+}
+
+fn lstrcmpi_a(emu: &mut Emulator) -> Result {
+    let (mut a, mut b) = (emu.mem.read_cstr(emu.arg(0)?)?, emu.mem.read_cstr(emu.arg(1)?)?);
+    a.make_ascii_lowercase();
+    b.make_ascii_lowercase();
+    Ok((a.cmp(&b) as i32).into())
+}
+
+fn lstrcpy_a(emu: &mut Emulator) -> Result {
+    let (dst, src) = (emu.arg(0)?, emu.arg(1)?);
+    let mut s = emu.mem.read_cstr(src)?;
+    s.push(0);
+    emu.mem.write(dst, &s)?;
+    Ok(dst.into())
+}
+
+fn lstrcpyn_a(emu: &mut Emulator) -> Result {
+    let (dst, src, size) = (emu.arg(0)?, emu.arg(1)?, emu.arg(2)?);
+    let s = emu.mem.read_cstr(src)?;
+    emu.write_str(dst, size, &s, false)?;
+    Ok(dst.into())
+}
+
+fn lstrlen_a(emu: &mut Emulator) -> Result {
+    match emu.arg(0)? {
+        0 => Ok(0.into()),
+        ptr => Ok((emu.mem.read_cstr(ptr)?.len() as u32).into()),
+    }
+}
+
+fn lstrlen_w(emu: &mut Emulator) -> Result {
+    match emu.arg(0)? {
+        0 => Ok(0.into()),
+        ptr => Ok((emu.mem.read_wstr(ptr)?.len() as u32).into()),
+    }
+}
+
+fn message_box(emu: &mut Emulator, wide: bool) -> Result {
+    const IDOK: u32 = 1;
+    let text = emu.str_arg(1, wide)?;
+    let caption = if emu.arg(2)? == 0 { b"Error".to_vec() } else { emu.str_arg(2, wide)? };
+    println!("DLL message box - {}: {}", String::from_utf8_lossy(&caption), String::from_utf8_lossy(&text));
+    Ok(IDOK.into())
+}
+
+fn message_box_a(emu: &mut Emulator) -> Result {
+    message_box(emu, false)
+}
+
+fn message_box_w(emu: &mut Emulator) -> Result {
+    message_box(emu, true)
+}
@@ -0,0 +1,1115 @@
+//! The C runtime, which covers msvcrt.dll as well as newer versions like msvcr100.dll and the UCRT.
+
+use super::{
+    Library, Return,
+    kernel32::{FILETIME_START, INSTRUCTIONS_PER_SECOND},
+    printf,
+};
+use crate::game::external::x86::Emulator;
+use shared::dll::CallConv;
+use std::cmp::Ordering;
+
+pub const LIBRARY: Library = Library {
+    call_conv: CallConv::Cdecl,
+    functions: &[
+        ("??2@YAPAXI@Z", 0, malloc), // operator new
+        ("??3@YAXPAX@Z", 0, free),   // operator delete
+        ("??_U@YAPAXI@Z", 0, malloc),
+        ("??_V@YAXPAX@Z", 0, free),
+        ("_CIacos", 0, ci_acos),
+        ("_CIasin", 0, ci_asin),
+        ("_CIatan", 0, ci_atan),
+        ("_CIatan2", 0, ci_atan2),
+        ("_CIcos", 0, ci_cos),
+        ("_CIcosh", 0, ci_cosh),
+        ("_CIexp", 0, ci_exp),
+        ("_CIfmod", 0, ci_fmod),
+        ("_CIlog", 0, ci_log),
+        ("_CIlog10", 0, ci_log10),
+        ("_CIpow", 0, ci_pow),
+        ("_CIsin", 0, ci_sin),
+        ("_CIsinh", 0, ci_sinh),
+        ("_CIsqrt", 0, ci_sqrt),
+        ("_CItan", 0, ci_tan),
+        ("_CItanh", 0, ci_tanh),
+        ("_CxxThrowException", 0, cxx_throw_exception),
+        ("_XcptFilter", 0, nothing),
+        ("__CxxFrameHandler3", 0, exception_handler),
+        ("__acrt_iob_func", 0, acrt_iob_func),
+        ("__dllonexit", 0, onexit),
+        ("__doserrno", 0, errno),
+        ("__iob_func", 0, iob_func),
+        ("__set_app_type", 0, nothing),
+        ("__setusermatherr", 0, nothing),
+        ("__std_type_info_destroy_list", 0, nothing),
+        ("__stdio_common_vfprintf", 0, stdio_common_vfprintf),
+        ("__stdio_common_vsprintf", 0, stdio_common_vsprintf),
+        ("_amsg_exit", 0, amsg_exit),
+        ("_assert", 0, assert),
+        ("_atoi64", 0, atoi64),
+        ("_c_exit", 0, nothing),
+        ("_callnewh", 0, nothing),
+        ("_cexit", 0, nothing),
+        ("_configthreadlocale", 0, nothing),
+        ("_configure_narrow_argv", 0, nothing),
+        ("_control87", 0, controlfp),
+        ("_controlfp", 0, controlfp),
+        ("_crt_debugger_hook", 0, nothing),
+        ("_decode_pointer", 0, encode_pointer),
+        ("_encode_pointer", 0, encode_pointer),
+        ("_errno", 0, errno),
+        ("_except_handler3", 0, exception_handler),
+        ("_except_handler4_common", 0, exception_handler),
+        ("_execute_onexit_table", 0, nothing),
+        ("_exit", 0, exit),
+        ("_ftol", 0, ftol),
+        ("_ftol2", 0, ftol),
+        ("_ftol2_sse", 0, ftol),
+        ("_hypot", 0, hypot),
+        ("_i64toa", 0, i64toa),
+        ("_initialize_narrow_environment", 0, nothing),
+        ("_initialize_onexit_table", 0, initialize_onexit_table),
+        ("_initterm", 0, initterm),
+        ("_initterm_e", 0, initterm_e),
+        ("_invalid_parameter_noinfo", 0, invalid_parameter),
+        ("_itoa", 0, itoa),
+        ("_lock", 0, nothing),
+        ("_ltoa", 0, itoa),
+        ("_msize", 0, msize),
+        ("_onexit", 0, onexit),
+        ("_purecall", 0, purecall),
+        ("_register_onexit_function", 0, nothing),
+        ("_seh_filter_dll", 0, nothing),
+        ("_set_invalid_parameter_handler", 0, nothing),
+        ("_snprintf", 0, snprintf_legacy),
+        ("_strdup", 0, strdup),
+        ("_strcmpi", 0, stricmp),
+        ("_stricmp", 0, stricmp),
+        ("_strlwr", 0, strlwr),
+        ("_strnicmp", 0, strnicmp),
+        ("_strrev", 0, strrev),
+        ("_strupr", 0, strupr),
+        ("_time32", 0, time),
+        ("_time64", 0, time64),
+        ("_ui64toa", 0, ui64toa),
+        ("_ultoa", 0, ultoa),
+        ("_unlock", 0, nothing),
+        ("_vsnprintf", 0, vsnprintf_legacy),
+        ("abort", 0, abort),
+        ("abs", 0, abs),
+        ("acos", 0, acos),
+        ("asin", 0, asin),
+        ("atan", 0, atan),
+        ("atan2", 0, atan2),
+        ("atexit", 0, atexit),
+        ("atof", 0, atof),
+        ("atoi", 0, atoi),
+        ("atol", 0, atoi),
+        ("bsearch", 0, bsearch),
+        ("calloc", 0, calloc),
+        ("ceil", 0, ceil),
+        ("clock", 0, clock),
+        ("cos", 0, cos),
+        ("cosh", 0, cosh),
+        ("exit", 0, exit),
+        ("exp", 0, exp),
+        ("fabs", 0, fabs),
+        ("fflush", 0, nothing),
+        ("floor", 0, floor),
+        ("fmod", 0, fmod),
+        ("fprintf", 0, fprintf),
+        ("fputc", 0, fputc),
+        ("fputs", 0, fputs),
+        ("free", 0, free),
+        ("frexp", 0, frexp),
+        ("fwrite", 0, fwrite),
+        ("isalnum", 0, isalnum),
+        ("isalpha", 0, isalpha),
+        ("iscntrl", 0, iscntrl),
+        ("isdigit", 0, isdigit),
+        ("isgraph", 0, isgraph),
+        ("islower", 0, islower),
+        ("isprint", 0, isprint),
+        ("ispunct", 0, ispunct),
+        ("isspace", 0, isspace),
+        ("isupper", 0, isupper),
+        ("isxdigit", 0, isxdigit),
+        ("labs", 0, abs),
+        ("ldexp", 0, ldexp),
+        ("log", 0, log),
+        ("log10", 0, log10),
+        ("malloc", 0, malloc),
+        ("memchr", 0, memchr),
+        ("memcmp", 0, memcmp),
+        ("memcpy", 0, memmove),
+        ("memmove", 0, memmove),
+        ("memset", 0, memset),
+        ("modf", 0, modf),
+        ("pow", 0, pow),
+        ("printf", 0, printf),
+        ("putchar", 0, fputc),
+        ("puts", 0, fputs),
+        ("qsort", 0, qsort),
+        ("rand", 0, rand),
+        ("realloc", 0, realloc),
+        ("setlocale", 0, setlocale),
+        ("signal", 0, nothing),
+        ("sin", 0, sin),
+        ("sinh", 0, sinh),
+        ("snprintf", 0, snprintf),
+        ("sprintf", 0, sprintf),
+        ("sqrt", 0, sqrt),
+        ("srand", 0, srand),
+        ("strcat", 0, strcat),
+        ("strchr", 0, strchr),
+        ("strcmp", 0, strcmp),
+        ("strcpy", 0, strcpy),
+        ("strcspn", 0, strcspn),
+        ("strdup", 0, strdup),
+        ("strlen", 0, strlen),
+        ("strncat", 0, strncat),
+        ("strncmp", 0, strncmp),
+        ("strncpy", 0, strncpy),
+        ("strnlen", 0, strnlen),
+        ("strpbrk", 0, strpbrk),
+        ("strrchr", 0, strrchr),
+        ("strspn", 0, strspn),
+        ("strstr", 0, strstr),
+        ("strtod", 0, strtod),
+        ("strtok", 0, strtok),
+        ("strtol", 0, strtol),
+        ("strtoul", 0, strtoul),
+        ("tan", 0, tan),
+        ("tanh", 0, tanh),
+        ("terminate", 0, abort),
+        ("time", 0, time),
+        ("tolower", 0, tolower),
+        ("toupper", 0, toupper),
+        ("vfprintf", 0, vfprintf),
+        ("vprintf", 0, vfprintf),
+        ("vsnprintf", 0, vsnprintf),
+        ("vsprintf", 0, vsprintf),
+        ("wcslen", 0, wcslen),
+    ],
+};
+
+const ERANGE: u32 = 34;
+
+/// msvcrt.dll pads exponents to 3 digits, unlike the C standard.
+const EXPONENT_DIGITS: usize = 3;
+const UCRT_EXPONENT_DIGITS: usize = 2;
+
+/// 2010-01-01 00:00:00 as a unix timestamp, to match the FILETIME clock.
+const UNIX_TIME_START: u64 = 1_262_304_000;
+
+type Result = std::result::Result<Return, String>;
+
+impl Emulator {
+    fn set_errno(&mut self, value: u32) -> std::result::Result<(), String> {
+        self.mem.write_u32(self.shim.errno, value)
+    }
+
+    fn unix_time(&self) -> u64 {
+        UNIX_TIME_START + (self.file_time() - FILETIME_START) / 10_000_000
+    }
+
+    /// Writes a formatted string into a buffer, returning how long it would have been.
+    /// In legacy mode (_snprintf and friends) it's -1 if it got cut off and the terminator may be missing.
+    fn write_formatted(&mut self, buffer: u32, size: Option<u32>, s: &[u8], legacy: bool) -> Result {
+        let size = match size {
+            Some(size) => size as usize,
+            None => {
+                self.mem.write(buffer, s)?;
+                self.mem.write_u8(buffer + s.len() as u32, 0)?;
+                return Ok((s.len() as u32).into())
+            },
+        };
+        if s.len() < size {
+            self.mem.write(buffer, s)?;
+            self.mem.write_u8(buffer + s.len() as u32, 0)?;
+            Ok((s.len() as u32).into())
+        } else if legacy {
+            self.mem.write(buffer, &s[..size])?;
+            Ok((-1).into())
+        } else {
+            if size > 0 {
+                self.mem.write(buffer, &s[..size - 1])?;
+                self.mem.write_u8(buffer + size as u32 - 1, 0)?;
+            }
+            Ok((s.len() as u32).into())
+        }
+    }
+
+    /// Calls a comparison callback on two pointers.
+    fn compare(&mut self, compare: u32, a: u32, b: u32) -> std::result::Result<Ordering, String> {
+        Ok((self.call_address(compare, &[a, b])? as i32).cmp(&0))
+    }
+}
+
+/// Reads a C number the way strtod does, returning the value and how many bytes made it up.
+fn parse_float(s: &[u8]) -> (f64, usize) {
+    let start = s.iter().position(|c| !c.is_ascii_whitespace()).unwrap_or(s.len());
+    let mut end = start;
+    let digits = |end: &mut usize| {
+        let from = *end;
+        while matches!(s.get(*end), Some(c) if c.is_ascii_digit()) {
+            *end += 1;
+        }
+        *end - from
+    };
+    if matches!(s.get(end), Some(b'+') | Some(b'-')) {
+        end += 1;
+    }
+    let mut count = digits(&mut end);
+    if s.get(end) == Some(&b'.') {
+        end += 1;
+        count += digits(&mut end);
+    }
+    if count == 0 {
+        return (0.0, 0)
+    }
+    if matches!(s.get(end), Some(b'e') | Some(b'E')) {
+        let mut exponent_end = end + 1;
+        if matches!(s.get(exponent_end), Some(b'+') | Some(b'-')) {
+            exponent_end += 1;
+        }
+        if digits(&mut exponent_end) > 0 {
+            end = exponent_end;
+        }
+    }
+    let text = String::from_utf8_lossy(&s[start..end]);
+    (text.trim_end_matches('.').parse().unwrap_or(0.0), end)
+}
+
+/// Reads an integer the way strtol does, returning whether it was negative, its magnitude (saturated),
+/// whether it overflowed a u32 and how many bytes made it up.
+fn parse_int(s: &[u8], base: u32) -> (bool, u64, usize) {
+    let mut i = s.iter().position(|c| !c.is_ascii_whitespace()).unwrap_or(s.len());
+    let negative = s.get(i) == Some(&b'-');
+    if matches!(s.get(i), Some(b'+') | Some(b'-')) {
+        i += 1;
+    }
+    let hex_prefix = s.get(i) == Some(&b'0')
+        && matches!(s.get(i + 1), Some(b'x') | Some(b'X'))
+        && matches!(s.get(i + 2), Some(c) if c.is_ascii_hexdigit());
+    let base = match base {
+        0 if hex_prefix => 16,
+        0 if s.get(i) == Some(&b'0') => 8,
+        0 => 10,
+        base => base,
+    };
+    if base == 16 && hex_prefix {
+        i += 2;
+    }
+    let start = i;
+    let mut value = 0u64;
+    while let Some(digit) = s.get(i).and_then(|&c| char::from(c).to_digit(36)).filter(|&d| d < base) {
+        value = value.saturating_mul(u64::from(base)).saturating_add(u64::from(digit));
+        i += 1;
+    }
+    if i == start { (false, 0, 0) } else { (negative, value, i) }
+}
+
+fn nothing(_emu: &mut Emulator) -> Result {
+    Ok(0.into())
+}
+
+fn abort(_emu: &mut Emulator) -> Result {
+    Err("DLL called abort".into())
+}
+
+fn abs(emu: &mut Emulator) -> Result {
+    Ok((emu.arg(0)? as i32).wrapping_abs().into())
+}
+
+fn acrt_iob_func(emu: &mut Emulator) -> Result {
+    Ok((emu.shim.iob + emu.arg(0)?.min(2) * 32).into())
+}
+
+fn amsg_exit(emu: &mut Emulator) -> Result {
+    Err(format!("C runtime error R60{:02}", emu.arg(0)?))
+}
+
+fn assert(emu: &mut Emulator) -> Result {
+    let (message, file) = (emu.mem.read_cstr(emu.arg(0)?)?, emu.mem.read_cstr(emu.arg(1)?)?);
+    Err(format!(
+        "assertion failed: {} ({}, line {})",
+        String::from_utf8_lossy(&message),
+        String::from_utf8_lossy(&file),
+        emu.arg(2)?
+    ))
+}
+
+fn atexit(_emu: &mut Emulator) -> Result {
+    // DLLs never get unloaded, so these never get run
+    Ok(0.into())
+}
+
+fn atof(emu: &mut Emulator) -> Result {
+    let s = emu.mem.read_cstr(emu.arg(0)?)?;
+    Ok(parse_float(&s).0.into())
+}
+
+fn atoi(emu: &mut Emulator) -> Result {
+    let s = emu.mem.read_cstr(emu.arg(0)?)?;
+    let (negative, value, _) = parse_int(&s, 10);
+    let value = if negative { (value as u32).wrapping_neg() } else { value as u32 };
+    Ok(value.into())
+}
+
+fn atoi64(emu: &mut Emulator) -> Result {
+    let s = emu.mem.read_cstr(emu.arg(0)?)?;
+    let (negative, value, _) = parse_int(&s, 10);
+    Ok(Return::Long(if negative { value.wrapping_neg() } else { value }))
+}
+
+fn bsearch(emu: &mut Emulator) -> Result {
+    let (key, base, count, size, compare) = (emu.arg(0)?, emu.arg(1)?, emu.arg(2)?, emu.arg(3)?, emu.arg(4)?);
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let element = base + mid * size;
+        match emu.compare(compare, key, element)? {
+            Ordering::Less => high = mid,
+            Ordering::Greater => low = mid + 1,
+            Ordering::Equal => return Ok(element.into()),
+        }
+    }
+    Ok(0.into())
+}
+
+fn calloc(emu: &mut Emulator) -> Result {
+    match emu.arg(0)?.checked_mul(emu.arg(1)?) {
+        Some(size) => Ok(emu.alloc(size).unwrap_or(0).into()),
+        None => Ok(0.into()),
+    }
+}
+
+fn clock(emu: &mut Emulator) -> Result {
+    Ok(((emu.cpu.ticks / (INSTRUCTIONS_PER_SECOND / 1000)) as u32).into())
+}
+
+fn controlfp(emu: &mut Emulator) -> Result {
+    // only rounding is supported, since there's only one precision and exceptions are always masked
+    const MCW_RC: u32 = 0x300;
+    let (new, mask) = (emu.arg(0)?, emu.arg(1)?);
+    let fpu = &mut emu.cpu.fpu;
+    if mask & MCW_RC != 0 {
+        fpu.control = (fpu.control & !0xC00) | (((new & MCW_RC) << 2) as u16);
+    }
+    Ok((0x0009_001F | ((u32::from(fpu.control) & 0xC00) >> 2)).into())
+}
+
+fn cxx_throw_exception(_emu: &mut Emulator) -> Result {
+    Err("DLL threw a C++ exception, which can't be handled".into())
+}
+
+fn encode_pointer(emu: &mut Emulator) -> Result {
+    Ok(emu.arg(0)?.into())
+}
+
+fn errno(emu: &mut Emulator) -> Result {
+    Ok(emu.shim.errno.into())
+}
+
+fn exception_handler(_emu: &mut Emulator) -> Result {
+    Err("DLL raised an exception, which can't be handled".into())
+}
+
+fn exit(emu: &mut Emulator) -> Result {
+    Err(format!("DLL tried to exit the process (exit code {})", emu.arg(0)?))
+}
+
+fn fprintf(emu: &mut Emulator) -> Result {
+    // output just gets thrown away
+    let s = printf::format(emu, emu.arg(1)?, emu.arg_address(2), EXPONENT_DIGITS)?;
+    Ok((s.len() as u32).into())
+}
+
+fn fputc(emu: &mut Emulator) -> Result {
+    Ok(emu.arg(0)?.into())
+}
+
+fn fputs(_emu: &mut Emulator) -> Result {
+    Ok(0.into())
+}
+
+fn free(emu: &mut Emulator) -> Result {
+    let ptr = emu.arg(0)?;
+    emu.heap.free(ptr);
+    Ok(0.into())
+}
+
+fn frexp(emu: &mut Emulator) -> Result {
+    let (x, exponent_ptr) = (emu.arg_f64(0)?, emu.arg(2)?);
+    let (mantissa, exponent) = if x == 0.0 || !x.is_finite() {
+        (x, 0)
+    } else {
+        let mut exponent = x.abs().log2().floor() as i32 + 1;
+        let mut mantissa = x / 2f64.powi(exponent);
+        // log2 can be off by one right at powers of 2
+        if mantissa.abs() >= 1.0 {
+            mantissa /= 2.0;
+            exponent += 1;
+        } else if mantissa.abs() < 0.5 {
+            mantissa *= 2.0;
+            exponent -= 1;
+        }
+        (mantissa, exponent)
+    };
+    emu.mem.write_u32(exponent_ptr, exponent as u32)?;
+    Ok(mantissa.into())
+}
+
+fn ftol(emu: &mut Emulator) -> Result {
+    // the compiler calls this to convert st0 to an integer, always truncating
+    let x = emu.cpu.fpu.pop().trunc();
+    Ok(Return::Long(if x.is_nan() || x.abs() >= 2f64.powi(63) { 1 << 63 } else { x as i64 as u64 }))
+}
+
+fn fwrite(emu: &mut Emulator) -> Result {
+    Ok(emu.arg(2)?.into())
+}
+
+fn hypot(emu: &mut Emulator) -> Result {
+    Ok(emu.arg_f64(0)?.hypot(emu.arg_f64(2)?).into())
+}
+
+/// Writes an integer in the given radix, like _itoa and friends.
+fn write_radix(emu: &mut Emulator, negative: bool, mut value: u64, buffer: u32, radix: u32) -> Result {
+    if !(2..=36).contains(&radix) {
+        emu.set_errno(22)?;
+        return Ok(buffer.into())
+    }
+    let mut digits = Vec::new();
+    loop {
+        digits.push(std::char::from_digit((value % u64::from(radix)) as u32, radix).unwrap() as u8);
+        value /= u64::from(radix);
+        if value == 0 {
+            break
+        }
+    }
+    if negative {
+        digits.push(b'-');
+    }
+    digits.reverse();
+    digits.push(0);
+    emu.mem.write(buffer, &digits)?;
+    Ok(buffer.into())
+}
+
+fn i64toa(emu: &mut Emulator) -> Result {
+    let (value, buffer, radix) = (emu.mem.read_u64(emu.arg_address(0))? as i64, emu.arg(2)?, emu.arg(3)?);
+    write_radix(
+        emu,
+        value < 0 && radix == 10,
+        if radix == 10 { value.unsigned_abs() } else { value as u64 },
+        buffer,
+        radix,
+    )
+}
+
+fn initialize_onexit_table(emu: &mut Emulator) -> Result {
+    emu.mem.write(emu.arg(0)?, &[0; 12])?;
+    Ok(0.into())
+}
+
+fn initterm(emu: &mut Emulator) -> Result {
+    let (start, end) = (emu.arg(0)?, emu.arg(1)?);
+    for entry in (start..end).step_by(4) {
+        let function = emu.mem.read_u32(entry)?;
+        if function != 0 {
+            emu.call_address(function, &[])?;
+        }
+    }
+    Ok(0.into())
+}
+
+fn initterm_e(emu: &mut Emulator) -> Result {
+    let (start, end) = (emu.arg(0)?, emu.arg(1)?);
+    for entry in (start..end).step_by(4) {
+        let function = emu.mem.read_u32(entry)?;
+        if function != 0 {
+            let result = emu.call_address(function, &[])?;
+            if result != 0 {
+                return Ok(result.into())
+            }
+        }
+    }
+    Ok(0.into())
+}
+
+fn invalid_parameter(_emu: &mut Emulator) -> Result {
+    Err("DLL passed an invalid parameter to a C runtime function".into())
+}
+
+fn iob_func(emu: &mut Emulator) -> Result {
+    Ok(emu.shim.iob.into())
+}
+
+fn itoa(emu: &mut Emulator) -> Result {
+    let (value, buffer, radix) = (emu.arg(0)? as i32, emu.arg(1)?, emu.arg(2)?);
+    let magnitude = if radix == 10 { u64::from(value.unsigned_abs()) } else { u64::from(value as u32) };
+    write_radix(emu, value < 0 && radix == 10, magnitude, buffer, radix)
+}
+
+fn ldexp(emu: &mut Emulator) -> Result {
+    Ok((emu.arg_f64(0)? * 2f64.powi(emu.arg(2)? as i32)).into())
+}
+
+fn malloc(emu: &mut Emulator) -> Result {
+    let size = emu.arg(0)?;
+    Ok(emu.alloc(size).unwrap_or(0).into())
+}
+
+fn memchr(emu: &mut Emulator) -> Result {
+    let (ptr, c, len) = (emu.arg(0)?, emu.arg(1)? as u8, emu.arg(2)?);
+    let position = emu.mem.slice(ptr, len as usize)?.iter().position(|&b| b == c);
+    Ok(position.map_or(0, |i| ptr + i as u32).into())
+}
+
+fn memcmp(emu: &mut Emulator) -> Result {
+    let (a, b, len) = (emu.arg(0)?, emu.arg(1)?, emu.arg(2)? as usize);
+    Ok((emu.mem.slice(a, len)?.cmp(emu.mem.slice(b, len)?) as i32).into())
+}
+
+fn memmove(emu: &mut Emulator) -> Result {
+    let (dst, src, len) = (emu.arg(0)?, emu.arg(1)?, emu.arg(2)?);
+    let data = emu.mem.slice(src, len as usize)?.to_vec();
+    emu.mem.write(dst, &data)?;
+    Ok(dst.into())
+}
+
+fn memset(emu: &mut Emulator) -> Result {
+    let (dst, c, len) = (emu.arg(0)?, emu.arg(1)? as u8, emu.arg(2)?);
+    emu.mem.slice_mut(dst, len as usize)?.iter_mut().for_each(|b| *b = c);
+    Ok(dst.into())
+}
+
+fn modf(emu: &mut Emulator) -> Result {
+    let (x, int_ptr) = (emu.arg_f64(0)?, emu.arg(2)?);
+    emu.mem.write_f64(int_ptr, x.trunc())?;
+    Ok(if x.is_infinite() { 0.0f64.copysign(x) } else { x.fract() }.into())
+}
+
+fn msize(emu: &mut Emulator) -> Result {
+    Ok(emu.heap.size(emu.arg(0)?).unwrap_or(u32::MAX).into())
+}
+
+fn onexit(emu: &mut Emulator) -> Result {
+    // like atexit, but returns the function on success
+    Ok(emu.arg(0)?.into())
+}
+
+fn printf(emu: &mut Emulator) -> Result {
+    let s = printf::format(emu, emu.arg(0)?, emu.arg_address(1), EXPONENT_DIGITS)?;
+    Ok((s.len() as u32).into())
+}
+
+fn purecall(_emu: &mut Emulator) -> Result {
+    Err("DLL called a pure virtual function".into())
+}
+
+fn qsort(emu: &mut Emulator) -> Result {
+    let (base, count, size, compare) = (emu.arg(0)?, emu.arg(1)?, emu.arg(2)?, emu.arg(3)?);
+    // merge sort on indices, so the comparisons all happen on the original array and can fail
+    let mut order: Vec<u32> = (0..count).collect();
+    let mut buffer = order.clone();
+    let mut width = 1;
+    while width < order.len() {
+        for start in (0..order.len()).step_by(width * 2) {
+            let mid = (start + width).min(order.len());
+            let end = (start + width * 2).min(order.len());
+            let (mut i, mut j) = (start, mid);
+            for slot in buffer[start..end].iter_mut() {
+                let take_left = j >= end
+                    || (i < mid
+                        && emu.compare(compare, base + order[i] * size, base + order[j] * size)? != Ordering::Greater);
+                if take_left {
+                    *slot = order[i];
+                    i += 1;
+                } else {
+                    *slot = order[j];
+                    j += 1;
+                }
+            }
+        }
+        std::mem::swap(&mut order, &mut buffer);
+        width *= 2;
+    }
+    let data = emu.mem.slice(base, (count * size) as usize)?.to_vec();
+    let sorted = order
+        .iter()
+        .flat_map(|&i| data[(i * size) as usize..((i + 1) * size) as usize].iter().copied())
+        .collect::<Vec<_>>();
+    emu.mem.write(base, &sorted)?;
+    Ok(0.into())
+}
+
+fn rand(emu: &mut Emulator) -> Result {
+    emu.shim.rand_seed = emu.shim.rand_seed.wrapping_mul(214013).wrapping_add(2531011);
+    Ok(((emu.shim.rand_seed >> 16) & 0x7FFF).into())
+}
+
+fn realloc(emu: &mut Emulator) -> Result {
+    let (ptr, size) = (emu.arg(0)?, emu.arg(1)?);
+    if ptr == 0 {
+        return Ok(emu.alloc(size).unwrap_or(0).into())
+    }
+    if size == 0 {
+        emu.heap.free(ptr);
+        return Ok(0.into())
+    }
+    Ok(emu.heap.realloc(&mut emu.mem, ptr, size).unwrap_or(0).into())
+}
+
+fn setlocale(emu: &mut Emulator) -> Result {
+    // only the C locale exists
+    let locale = emu.arg(1)?;
+    if locale == 0 || matches!(emu.mem.read_cstr(locale)?.as_slice(), b"" | b"C") {
+        Ok(emu.shim.locale.into())
+    } else {
+        Ok(0.into())
+    }
+}
+
+fn snprintf(emu: &mut Emulator) -> Result {
+    let (buffer, size) = (emu.arg(0)?, emu.arg(1)?);
+    let s = printf::format(emu, emu.arg(2)?, emu.arg_address(3), EXPONENT_DIGITS)?;
+    emu.write_formatted(buffer, Some(size), &s, false)
+}
+
+fn snprintf_legacy(emu: &mut Emulator) -> Result {
+    let (buffer, size) = (emu.arg(0)?, emu.arg(1)?);
+    let s = printf::format(emu, emu.arg(2)?, emu.arg_address(3), EXPONENT_DIGITS)?;
+    emu.write_formatted(buffer, Some(size), &s, true)
+}
+
+fn sprintf(emu: &mut Emulator) -> Result {
+    let buffer = emu.arg(0)?;
+    let s = printf::format(emu, emu.arg(1)?, emu.arg_address(2), EXPONENT_DIGITS)?;
+    emu.write_formatted(buffer, None, &s, false)
+}
+
+fn srand(emu: &mut Emulator) -> Result {
+    emu.shim.rand_seed = emu.arg(0)?;
+    Ok(0.into())
+}
+
+fn stdio_common_vfprintf(emu: &mut Emulator) -> Result {
+    // (options: u64, stream, format, locale, args)
+    let s = printf::format(emu, emu.arg(3)?, emu.arg(5)?, UCRT_EXPONENT_DIGITS)?;
+    Ok((s.len() as u32).into())
+}
+
+fn stdio_common_vsprintf(emu: &mut Emulator) -> Result {
+    // (options: u64, buffer, size, format, locale, args)
+    const STANDARD_SNPRINTF_BEHAVIOR: u32 = 2;
+    let (options, buffer, size) = (emu.arg(0)?, emu.arg(2)?, emu.arg(3)?);
+    let s = printf::format(emu, emu.arg(4)?, emu.arg(6)?, UCRT_EXPONENT_DIGITS)?;
+    if buffer == 0 {
+        return Ok((s.len() as u32).into())
+    }
+    emu.write_formatted(buffer, Some(size), &s, options & STANDARD_SNPRINTF_BEHAVIOR == 0)
+}
+
+fn strcat(emu: &mut Emulator) -> Result {
+    let (dst, src) = (emu.arg(0)?, emu.arg(1)?);
+    let mut s = emu.mem.read_cstr(src)?;
+    s.push(0);
+    let len = emu.mem.read_cstr(dst)?.len() as u32;
+    emu.mem.write(dst + len, &s)?;
+    Ok(dst.into())
+}
+
+fn strchr(emu: &mut Emulator) -> Result {
+    let (ptr, c) = (emu.arg(0)?, emu.arg(1)? as u8);
+    let s = emu.mem.read_cstr(ptr)?;
+    // the terminator counts as part of the string
+    let position = if c == 0 { Some(s.len()) } else { s.iter().position(|&b| b == c) };
+    Ok(position.map_or(0, |i| ptr + i as u32).into())
+}
+
+fn strcmp(emu: &mut Emulator) -> Result {
+    let (a, b) = (emu.mem.read_cstr(emu.arg(0)?)?, emu.mem.read_cstr(emu.arg(1)?)?);
+    Ok((a.cmp(&b) as i32).into())
+}
+
+fn strcpy(emu: &mut Emulator) -> Result {
+    let (dst, src) = (emu.arg(0)?, emu.arg(1)?);
+    let mut s = emu.mem.read_cstr(src)?;
+    s.push(0);
+    emu.mem.write(dst, &s)?;
+    Ok(dst.into())
+}
+
+fn strcspn(emu: &mut Emulator) -> Result {
+    let (s, reject) = (emu.mem.read_cstr(emu.arg(0)?)?, emu.mem.read_cstr(emu.arg(1)?)?);
+    Ok((s.iter().position(|c| reject.contains(c)).unwrap_or(s.len()) as u32).into())
+}
+
+fn strdup(emu: &mut Emulator) -> Result {
+    let mut s = emu.mem.read_cstr(emu.arg(0)?)?;
+    s.push(0);
+    let ptr = emu.alloc(s.len() as u32)?;
+    emu.mem.write(ptr, &s)?;
+    Ok(ptr.into())
+}
+
+fn stricmp(emu: &mut Emulator) -> Result {
+    let (mut a, mut b) = (emu.mem.read_cstr(emu.arg(0)?)?, emu.mem.read_cstr(emu.arg(1)?)?);
+    a.make_ascii_lowercase();
+    b.make_ascii_lowercase();
+    Ok((a.cmp(&b) as i32).into())
+}
+
+fn strlen(emu: &mut Emulator) -> Result {
+    Ok((emu.mem.read_cstr(emu.arg(0)?)?.len() as u32).into())
+}
+
+fn strlwr(emu: &mut Emulator) -> Result {
+    let ptr = emu.arg(0)?;
+    let mut s = emu.mem.read_cstr(ptr)?;
+    s.make_ascii_lowercase();
+    emu.mem.write(ptr, &s)?;
+    Ok(ptr.into())
+}
+
+/// Reads at most `len` bytes of a string, stopping at the terminator.
+fn read_cstr_max(emu: &Emulator, ptr: u32, len: u32) -> std::result::Result<Vec<u8>, String> {
+    let mut s = Vec::new();
+    for i in 0..len {
+        match emu.mem.read_u8(ptr + i)? {
+            0 => break,
+            c => s.push(c),
+        }
+    }
+    Ok(s)
+}
+
+fn strncat(emu: &mut Emulator) -> Result {
+    let (dst, src, len) = (emu.arg(0)?, emu.arg(1)?, emu.arg(2)?);
+    let mut s = read_cstr_max(emu, src, len)?;
+    s.push(0);
+    let dst_len = emu.mem.read_cstr(dst)?.len() as u32;
+    emu.mem.write(dst + dst_len, &s)?;
+    Ok(dst.into())
+}
+
+fn strncmp(emu: &mut Emulator) -> Result {
+    let (a, b, len) = (emu.arg(0)?, emu.arg(1)?, emu.arg(2)?);
+    let (a, b) = (read_cstr_max(emu, a, len)?, read_cstr_max(emu, b, len)?);
+    Ok((a.cmp(&b) as i32).into())
+}
+
+fn strncpy(emu: &mut Emulator) -> Result {
+    // pads with zeros, and doesn't add a terminator if it doesn't fit
+    let (dst, src, len) = (emu.arg(0)?, emu.arg(1)?, emu.arg(2)?);
+    let mut s = read_cstr_max(emu, src, len)?;
+    s.resize(len as usize, 0);
+    emu.mem.write(dst, &s)?;
+    Ok(dst.into())
+}
+
+fn strnicmp(emu: &mut Emulator) -> Result {
+    let (a, b, len) = (emu.arg(0)?, emu.arg(1)?, emu.arg(2)?);
+    let (mut a, mut b) = (read_cstr_max(emu, a, len)?, read_cstr_max(emu, b, len)?);
+    a.make_ascii_lowercase();
+    b.make_ascii_lowercase();
+    Ok((a.cmp(&b) as i32).into())
+}
+
+fn strnlen(emu: &mut Emulator) -> Result {
+    let (ptr, len) = (emu.arg(0)?, emu.arg(1)?);
+    Ok((read_cstr_max(emu, ptr, len)?.len() as u32).into())
+}
+
+fn strpbrk(emu: &mut Emulator) -> Result {
+    let ptr = emu.arg(0)?;
+    let (s, accept) = (emu.mem.read_cstr(ptr)?, emu.mem.read_cstr(emu.arg(1)?)?);
+    Ok(s.iter().position(|c| accept.contains(c)).map_or(0, |i| ptr + i as u32).into())
+}
+
+fn strrchr(emu: &mut Emulator) -> Result {
+    let (ptr, c) = (emu.arg(0)?, emu.arg(1)? as u8);
+    let s = emu.mem.read_cstr(ptr)?;
+    let position = if c == 0 { Some(s.len()) } else { s.iter().rposition(|&b| b == c) };
+    Ok(position.map_or(0, |i| ptr + i as u32).into())
+}
+
+fn strrev(emu: &mut Emulator) -> Result {
+    let ptr = emu.arg(0)?;
+    let mut s = emu.mem.read_cstr(ptr)?;
+    s.reverse();
+    emu.mem.write(ptr, &s)?;
+    Ok(ptr.into())
+}
+
+fn strspn(emu: &mut Emulator) -> Result {
+    let (s, accept) = (emu.mem.read_cstr(emu.arg(0)?)?, emu.mem.read_cstr(emu.arg(1)?)?);
+    Ok((s.iter().position(|c| !accept.contains(c)).unwrap_or(s.len()) as u32).into())
+}
+
+fn strstr(emu: &mut Emulator) -> Result {
+    let ptr = emu.arg(0)?;
+    let (s, needle) = (emu.mem.read_cstr(ptr)?, emu.mem.read_cstr(emu.arg(1)?)?);
+    if needle.is_empty() {
+        return Ok(ptr.into())
+    }
+    Ok(s.windows(needle.len()).position(|w| w == needle.as_slice()).map_or(0, |i| ptr + i as u32).into())
+}
+
+fn strtod(emu: &mut Emulator) -> Result {
+    let (ptr, end_ptr) = (emu.arg(0)?, emu.arg(1)?);
+    let (value, len) = parse_float(&emu.mem.read_cstr(ptr)?);
+    if end_ptr != 0 {
+        emu.mem.write_u32(end_ptr, ptr + len as u32)?;
+    }
+    if value.is_infinite() {
+        emu.set_errno(ERANGE)?;
+    }
+    Ok(value.into())
+}
+
+fn strtok(emu: &mut Emulator) -> Result {
+    let (ptr, delimiters) = (emu.arg(0)?, emu.mem.read_cstr(emu.arg(1)?)?);
+    let ptr = if ptr == 0 { emu.shim.strtok_next } else { ptr };
+    if ptr == 0 {
+        return Ok(0.into())
+    }
+    let s = emu.mem.read_cstr(ptr)?;
+    let start = match s.iter().position(|c| !delimiters.contains(c)) {
+        Some(start) => start,
+        None => {
+            emu.shim.strtok_next = 0;
+            return Ok(0.into())
+        },
+    };
+    match s[start..].iter().position(|c| delimiters.contains(c)) {
+        Some(len) => {
+            let end = ptr + (start + len) as u32;
+            emu.mem.write_u8(end, 0)?;
+            emu.shim.strtok_next = end + 1;
+        },
+        None => emu.shim.strtok_next = 0,
+    }
+    Ok((ptr + start as u32).into())
+}
+
+fn strtol(emu: &mut Emulator) -> Result {
+    let (ptr, end_ptr, base) = (emu.arg(0)?, emu.arg(1)?, emu.arg(2)?);
+    let (negative, value, len) = parse_int(&emu.mem.read_cstr(ptr)?, base);
+    if end_ptr != 0 {
+        emu.mem.write_u32(end_ptr, ptr + len as u32)?;
+    }
+    let limit = if negative { 1 << 31 } else { i32::MAX as u64 };
+    if value > limit {
+        emu.set_errno(ERANGE)?;
+        return Ok(if negative { i32::MIN } else { i32::MAX }.into())
+    }
+    Ok(if negative { (value as u32).wrapping_neg() } else { value as u32 }.into())
+}
+
+fn strtoul(emu: &mut Emulator) -> Result {
+    let (ptr, end_ptr, base) = (emu.arg(0)?, emu.arg(1)?, emu.arg(2)?);
+    let (negative, value, len) = parse_int(&emu.mem.read_cstr(ptr)?, base);
+    if end_ptr != 0 {
+        emu.mem.write_u32(end_ptr, ptr + len as u32)?;
+    }
+    if value > u64::from(u32::MAX) {
+        emu.set_errno(ERANGE)?;
+        return Ok(u32::MAX.into())
+    }
+    Ok(if negative { (value as u32).wrapping_neg() } else { value as u32 }.into())
+}
+
+fn strupr(emu: &mut Emulator) -> Result {
+    let ptr = emu.arg(0)?;
+    let mut s = emu.mem.read_cstr(ptr)?;
+    s.make_ascii_uppercase();
+    emu.mem.write(ptr, &s)?;
+    Ok(ptr.into())
+}
+
+fn time(emu: &mut Emulator) -> Result {
+    let (time, out) = (emu.unix_time() as u32, emu.arg(0)?);
+    if out != 0 {
+        emu.mem.write_u32(out, time)?;
+    }
+    Ok(time.into())
+}
+
+fn time64(emu: &mut Emulator) -> Result {
+    let (time, out) = (emu.unix_time(), emu.arg(0)?);
+    if out != 0 {
+        emu.mem.write_u64(out, time)?;
+    }
+    Ok(Return::Long(time))
+}
+
+fn ui64toa(emu: &mut Emulator) -> Result {
+    let (value, buffer, radix) = (emu.mem.read_u64(emu.arg_address(0))?, emu.arg(2)?, emu.arg(3)?);
+    write_radix(emu, false, value, buffer, radix)
+}
+
+fn ultoa(emu: &mut Emulator) -> Result {
+    let (value, buffer, radix) = (emu.arg(0)?, emu.arg(1)?, emu.arg(2)?);
+    write_radix(emu, false, u64::from(value), buffer, radix)
+}
+
+fn vfprintf(_emu: &mut Emulator) -> Result {
+    // output just gets thrown away, and there's no way of knowing how long it would have been without
+    // knowing whether this is vprintf or vfprintf
+    Ok(0.into())
+}
+
+fn vsnprintf(emu: &mut Emulator) -> Result {
+    let (buffer, size) = (emu.arg(0)?, emu.arg(1)?);
+    let s = printf::format(emu, emu.arg(2)?, emu.arg(3)?, EXPONENT_DIGITS)?;
+    emu.write_formatted(buffer, Some(size), &s, false)
+}
+
+fn vsnprintf_legacy(emu: &mut Emulator) -> Result {
+    let (buffer, size) = (emu.arg(0)?, emu.arg(1)?);
+    let s = printf::format(emu, emu.arg(2)?, emu.arg(3)?, EXPONENT_DIGITS)?;
+    emu.write_formatted(buffer, Some(size), &s, true)
+}
+
+fn vsprintf(emu: &mut Emulator) -> Result {
+    let buffer = emu.arg(0)?;
+    let s = printf::format(emu, emu.arg(1)?, emu.arg(2)?, EXPONENT_DIGITS)?;
+    emu.write_formatted(buffer, None, &s, false)
+}
+
+fn wcslen(emu: &mut Emulator) -> Result {
+    Ok((emu.mem.read_wstr(emu.arg(0)?)?.len() as u32).into())
+}
+
+macro_rules! char_class {
+    ($($name: ident => $test: expr),* $(,)?) => {
+        $(
+            fn $name(emu: &mut Emulator) -> Result {
+                let c = emu.arg(0)?;
+                let test: fn(u8) -> bool = $test;
+                Ok((c < 0x80 && test(c as u8)).into())
+            }
+        )*
+    };
+}
+
+char_class! {
+    isalnum => |c| c.is_ascii_alphanumeric(),
+    isalpha => |c| c.is_ascii_alphabetic(),
+    iscntrl => |c| c.is_ascii_control(),
+    isdigit => |c| c.is_ascii_digit(),
+    isgraph => |c| c.is_ascii_graphic(),
+    islower => |c| c.is_ascii_lowercase(),
+    isprint => |c| c.is_ascii_graphic() || c == b' ',
+    ispunct => |c| c.is_ascii_punctuation(),
+    isspace => |c| c.is_ascii_whitespace() || c == 0x0B,
+    isupper => |c| c.is_ascii_uppercase(),
+    isxdigit => |c| c.is_ascii_hexdigit(),
+}
+
+fn tolower(emu: &mut Emulator) -> Result {
+    let c = emu.arg(0)?;
+    Ok(if c < 0x80 { u32::from((c as u8).to_ascii_lowercase()) } else { c }.into())
+}
+
+fn toupper(emu: &mut Emulator) -> Result {
+    let c = emu.arg(0)?;
+    Ok(if c < 0x80 { u32::from((c as u8).to_ascii_uppercase()) } else { c }.into())
+}
+
+macro_rules! maths {
+    ($($name: ident => $f: expr),* $(,)?) => {
+        $(
+            fn $name(emu: &mut Emulator) -> Result {
+                let f: fn(f64) -> f64 = $f;
+                Ok(f(emu.arg_f64(0)?).into())
+            }
+        )*
+    };
+}
+
+maths! {
+    acos => f64::acos,
+    asin => f64::asin,
+    atan => f64::atan,
+    ceil => f64::ceil,
+    cos => f64::cos,
+    cosh => f64::cosh,
+    exp => f64::exp,
+    fabs => f64::abs,
+    floor => f64::floor,
+    log => f64::ln,
+    log10 => f64::log10,
+    sin => f64::sin,
+    sinh => f64::sinh,
+    sqrt => f64::sqrt,
+    tan => f64::tan,
+    tanh => f64::tanh,
+}
+
+fn atan2(emu: &mut Emulator) -> Result {
+    Ok(emu.arg_f64(0)?.atan2(emu.arg_f64(2)?).into())
+}
+
+fn fmod(emu: &mut Emulator) -> Result {
+    Ok((emu.arg_f64(0)? % emu.arg_f64(2)?).into())
+}
+
+fn pow(emu: &mut Emulator) -> Result {
+    Ok(emu.arg_f64(0)?.powf(emu.arg_f64(2)?).into())
+}
+
+// the _CI functions are versions the compiler calls with arguments on the FPU stack instead
+
+macro_rules! ci_maths {
+    ($($name: ident => $f: expr),* $(,)?) => {
+        $(
+            fn $name(emu: &mut Emulator) -> Result {
+                let f: fn(f64) -> f64 = $f;
+                Ok(f(emu.cpu.fpu.pop()).into())
+            }
+        )*
+    };
+}
+
+ci_maths! {
+    ci_acos => f64::acos,
+    ci_asin => f64::asin,
+    ci_atan => f64::atan,
+    ci_cos => f64::cos,
+    ci_cosh => f64::cosh,
+    ci_exp => f64::exp,
+    ci_log => f64::ln,
+    ci_log10 => f64::log10,
+    ci_sin => f64::sin,
+    ci_sinh => f64::sinh,
+    ci_sqrt => f64::sqrt,
+    ci_tan => f64::tan,
+    ci_tanh => f64::tanh,
+}
+
+/// Pops (st1, st0) for the two-argument _CI functions.
+fn ci_args(emu: &mut Emulator) -> (f64, f64) {
+    let y = emu.cpu.fpu.pop();
+    let x = emu.cpu.fpu.pop();
+    (x, y)
+}
+
+fn ci_atan2(emu: &mut Emulator) -> Result {
+    let (y, x) = ci_args(emu);
+    Ok(y.atan2(x).into())
+}
+
+fn ci_fmod(emu: &mut Emulator) -> Result {
+    let (x, y) = ci_args(emu);
+    Ok((x % y).into())
+}
+
+fn ci_pow(emu: &mut Emulator) -> Result {
+    let (x, y) = ci_args(emu);
+    Ok(x.powf(y).into())
+}
@@ -0,0 +1,270 @@
+//! printf-style formatting, for the C runtime's string functions.
+
+use crate::game::external::x86::Emulator;
+
+/// Walks through variadic arguments on the emulated stack.
+struct Args<'a> {
+    emu: &'a Emulator,
+    next: u32,
+}
+
+impl Args<'_> {
+    fn u32(&mut self) -> Result<u32, String> {
+        let value = self.emu.mem.read_u32(self.next)?;
+        self.next += 4;
+        Ok(value)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let value = self.emu.mem.read_u64(self.next)?;
+        self.next += 8;
+        Ok(value)
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        self.u64().map(f64::from_bits)
+    }
+}
+
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Pads a formatted value out to the field width. The sign and prefix go before any zero padding.
+    fn pad(&self, out: &mut Vec<u8>, prefix: &[u8], body: &[u8], zero_allowed: bool) {
+        let padding = self.width.saturating_sub(prefix.len() + body.len());
+        if self.left {
+            out.extend_from_slice(prefix);
+            out.extend_from_slice(body);
+            out.resize(out.len() + padding, b' ');
+        } else if self.zero && zero_allowed {
+            out.extend_from_slice(prefix);
+            out.resize(out.len() + padding, b'0');
+            out.extend_from_slice(body);
+        } else {
+            out.resize(out.len() + padding, b' ');
+            out.extend_from_slice(prefix);
+            out.extend_from_slice(body);
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static [u8] {
+        if negative {
+            b"-"
+        } else if self.plus {
+            b"+"
+        } else if self.space {
+            b" "
+        } else {
+            b""
+        }
+    }
+}
+
+/// Formats `x` like %e, with the exponent padded to at least `exponent_digits` digits.
+fn format_exponent(x: f64, precision: usize, exponent_digits: usize, upper: bool) -> String {
+    let s = format!("{:.*e}", precision, x);
+    let (mantissa, exponent) = s.split_at(s.find('e').unwrap_or(s.len()));
+    let exponent: i32 = exponent.get(1..).and_then(|e| e.parse().ok()).unwrap_or(0);
+    let e = if upper { 'E' } else { 'e' };
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}{}{}{:0width$}", mantissa, e, sign, exponent.abs(), width = exponent_digits)
+}
+
+/// Removes trailing zeros after the decimal point, and the point itself if nothing's left after it.
+fn strip_zeros(s: &str) -> String {
+    let (mantissa, exponent) = s.split_at(s.find(&['e', 'E'][..]).unwrap_or(s.len()));
+    if mantissa.contains('.') {
+        format!("{}{}", mantissa.trim_end_matches('0').trim_end_matches('.'), exponent)
+    } else {
+        s.into()
+    }
+}
+
+fn format_float(spec: &Spec, conv: u8, x: f64, exponent_digits: usize) -> String {
+    let upper = conv.is_ascii_uppercase();
+    if !x.is_finite() {
+        let s = if x.is_nan() { "nan" } else { "inf" };
+        return if upper { s.to_ascii_uppercase() } else { s.into() }
+    }
+    let precision = spec.precision.unwrap_or(6);
+    let s = match conv.to_ascii_lowercase() {
+        b'f' => format!("{:.*}", precision, x),
+        b'g' => {
+            let precision = precision.max(1);
+            let exponent = if x == 0.0 {
+                0
+            } else {
+                let s = format!("{:.*e}", precision - 1, x);
+                s[s.find('e').unwrap() + 1..].parse::<i32>().unwrap_or(0)
+            };
+            let s = if exponent < -4 || exponent >= precision as i32 {
+                format_exponent(x, precision - 1, exponent_digits, upper)
+            } else {
+                format!("{:.*}", (precision as i32 - 1 - exponent) as usize, x)
+            };
+            if spec.alternate { s } else { strip_zeros(&s) }
+        },
+        _ => format_exponent(x, precision, exponent_digits, upper),
+    };
+    if spec.alternate && !s.contains('.') && spec.precision == Some(0) { s + "." } else { s }
+}
+
+/// Formats a string from a printf format string and a pointer to its arguments.
+/// The old msvcrt prints three digits of exponent, while the UCRT prints two.
+pub fn format(emu: &Emulator, fmt: u32, args: u32, exponent_digits: usize) -> Result<Vec<u8>, String> {
+    let fmt = emu.mem.read_cstr(fmt)?;
+    let mut args = Args { emu, next: args };
+    let mut out = Vec::new();
+    let mut i = 0;
+    let next_number = |i: &mut usize| {
+        let mut n = 0usize;
+        while let Some(d) = fmt.get(*i).filter(|c| c.is_ascii_digit()) {
+            n = n.saturating_mul(10).saturating_add(usize::from(d - b'0'));
+            *i += 1;
+        }
+        n
+    };
+    while i < fmt.len() {
+        let c = fmt[i];
+        i += 1;
+        if c != b'%' {
+            out.push(c);
+            continue
+        }
+
+        let mut spec = Spec::default();
+        while let Some(&flag) = fmt.get(i) {
+            match flag {
+                b'-' => spec.left = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alternate = true,
+                b'0' => spec.zero = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        if fmt.get(i) == Some(&b'*') {
+            i += 1;
+            let width = args.u32()? as i32;
+            spec.left |= width < 0;
+            spec.width = width.unsigned_abs() as usize;
+        } else {
+            spec.width = next_number(&mut i);
+        }
+        if fmt.get(i) == Some(&b'.') {
+            i += 1;
+            if fmt.get(i) == Some(&b'*') {
+                i += 1;
+                let precision = args.u32()? as i32;
+                spec.precision = if precision < 0 { None } else { Some(precision as usize) };
+            } else {
+                spec.precision = Some(next_number(&mut i));
+            }
+        }
+
+        let mut long = false; // 64-bit integers
+        let mut wide = false;
+        while let Some(&modifier) = fmt.get(i) {
+            match modifier {
+                b'l' if fmt.get(i + 1) == Some(&b'l') => {
+                    long = true;
+                    i += 1;
+                },
+                b'I' if fmt[i + 1..].starts_with(b"64") => {
+                    long = true;
+                    i += 2;
+                },
+                b'I' if fmt[i + 1..].starts_with(b"32") => i += 2,
+                b'l' | b'w' => wide = true,
+                b'h' | b'L' | b'I' | b'z' | b't' | b'j' => (),
+                _ => break,
+            }
+            i += 1;
+        }
+
+        let conv = match fmt.get(i) {
+            Some(&conv) => conv,
+            None => break,
+        };
+        i += 1;
+        match conv {
+            b'd' | b'i' | b'u' | b'x' | b'X' | b'o' => {
+                let value = if long { args.u64()? } else { u64::from(args.u32()?) };
+                let (negative, magnitude) = match (conv, long) {
+                    (b'd', true) | (b'i', true) => ((value as i64) < 0, (value as i64).unsigned_abs()),
+                    (b'd', false) | (b'i', false) => {
+                        ((value as u32 as i32) < 0, u64::from((value as u32 as i32).unsigned_abs()))
+                    },
+                    _ => (false, value),
+                };
+                let mut digits = match conv {
+                    b'x' => format!("{:x}", magnitude),
+                    b'X' => format!("{:X}", magnitude),
+                    b'o' => format!("{:o}", magnitude),
+                    _ => magnitude.to_string(),
+                }
+                .into_bytes();
+                if spec.precision == Some(0) && magnitude == 0 {
+                    digits.clear();
+                }
+                let precision = spec.precision.unwrap_or(0);
+                if digits.len() < precision {
+                    digits.splice(0..0, vec![b'0'; precision - digits.len()]);
+                }
+                let mut prefix = spec.sign(negative).to_vec();
+                if spec.alternate && magnitude != 0 {
+                    match conv {
+                        b'x' => prefix.extend_from_slice(b"0x"),
+                        b'X' => prefix.extend_from_slice(b"0X"),
+                        b'o' if digits.first() != Some(&b'0') => prefix.push(b'0'),
+                        _ => (),
+                    }
+                }
+                spec.pad(&mut out, &prefix, &digits, spec.precision.is_none());
+            },
+            b'f' | b'F' | b'e' | b'E' | b'g' | b'G' | b'a' | b'A' => {
+                let x = args.f64()?;
+                let body = format_float(&spec, conv, x.abs(), exponent_digits);
+                spec.pad(&mut out, spec.sign(x.is_sign_negative() && !x.is_nan()), body.as_bytes(), x.is_finite());
+            },
+            b'c' | b'C' => {
+                let c = args.u32()?;
+                spec.pad(&mut out, b"", &[if c < 0x100 { c as u8 } else { b'?' }], false);
+            },
+            b's' | b'S' => {
+                let ptr = args.u32()?;
+                let mut s = match ptr {
+                    0 => b"(null)".to_vec(),
+                    ptr if wide || conv == b'S' => {
+                        emu.mem.read_wstr(ptr)?.into_iter().map(|c| if c < 0x100 { c as u8 } else { b'?' }).collect()
+                    },
+                    ptr => emu.mem.read_cstr(ptr)?,
+                };
+                if let Some(precision) = spec.precision {
+                    s.truncate(precision);
+                }
+                spec.pad(&mut out, b"", &s, false);
+            },
+            b'p' => {
+                let ptr = args.u32()?;
+                spec.pad(&mut out, b"", format!("{:08X}", ptr).as_bytes(), false);
+            },
+            b'n' => {
+                // this can't write through `emu`, so it's not supported
+                return Err("%n isn't supported in format strings".into())
+            },
+            conv => out.push(conv),
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,467 @@
+//! The common parts of SSE and SSE2: moves, scalar and packed float maths, conversions and 128-bit integer logic.
+//! MMX isn't supported, and neither is anything after SSE2.
+
+use super::{
+    cpu::{Cpu, Inst, Operand, AF, OF, SF},
+    memory::Memory,
+};
+
+/// Which mandatory prefix an SSE instruction had.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Prefix {
+    Ps, // none
+    Pd, // 66
+    Sd, // F2
+    Ss, // F3
+}
+
+fn lanes_f32(x: [u64; 2]) -> [f32; 4] {
+    [
+        f32::from_bits(x[0] as u32),
+        f32::from_bits((x[0] >> 32) as u32),
+        f32::from_bits(x[1] as u32),
+        f32::from_bits((x[1] >> 32) as u32),
+    ]
+}
+
+fn from_lanes_f32(x: [f32; 4]) -> [u64; 2] {
+    [
+        u64::from(x[0].to_bits()) | (u64::from(x[1].to_bits()) << 32),
+        u64::from(x[2].to_bits()) | (u64::from(x[3].to_bits()) << 32),
+    ]
+}
+
+fn lanes_u32(x: [u64; 2]) -> [u32; 4] {
+    [x[0] as u32, (x[0] >> 32) as u32, x[1] as u32, (x[1] >> 32) as u32]
+}
+
+fn from_lanes_u32(x: [u32; 4]) -> [u64; 2] {
+    [u64::from(x[0]) | (u64::from(x[1]) << 32), u64::from(x[2]) | (u64::from(x[3]) << 32)]
+}
+
+/// add, mul, sub, min, div, max by their opcode, with x86's NaN and min/max quirks
+fn float_op(opcode: u8, a: f64, b: f64) -> f64 {
+    match opcode {
+        0x58 => a + b,
+        0x59 => a * b,
+        0x5C => a - b,
+        0x5D => {
+            if a < b {
+                a
+            } else {
+                b
+            }
+        },
+        0x5E => a / b,
+        _ => {
+            if a > b {
+                a
+            } else {
+                b
+            }
+        },
+    }
+}
+
+fn compare(predicate: u8, a: f64, b: f64) -> bool {
+    match predicate & 7 {
+        0 => a == b,
+        1 => a < b,
+        2 => a <= b,
+        3 => a.is_nan() || b.is_nan(),
+        4 => a != b,
+        5 => a >= b || a.is_nan() || b.is_nan(),
+        6 => a > b || a.is_nan() || b.is_nan(),
+        _ => !a.is_nan() && !b.is_nan(),
+    }
+}
+
+impl Cpu {
+    fn xmm_read(&self, mem: &Memory, rm: Operand) -> Result<[u64; 2], String> {
+        match rm {
+            Operand::Reg(r) => Ok(self.xmm[usize::from(r)]),
+            Operand::Mem(addr) => Ok([mem.read_u64(addr)?, mem.read_u64(addr.wrapping_add(8))?]),
+        }
+    }
+
+    fn xmm_read64(&self, mem: &Memory, rm: Operand) -> Result<u64, String> {
+        match rm {
+            Operand::Reg(r) => Ok(self.xmm[usize::from(r)][0]),
+            Operand::Mem(addr) => mem.read_u64(addr),
+        }
+    }
+
+    fn xmm_read32(&self, mem: &Memory, rm: Operand) -> Result<u32, String> {
+        match rm {
+            Operand::Reg(r) => Ok(self.xmm[usize::from(r)][0] as u32),
+            Operand::Mem(addr) => mem.read_u32(addr),
+        }
+    }
+
+    fn xmm_write(&mut self, mem: &mut Memory, rm: Operand, value: [u64; 2]) -> Result<(), String> {
+        match rm {
+            Operand::Reg(r) => self.xmm[usize::from(r)] = value,
+            Operand::Mem(addr) => {
+                mem.write_u64(addr, value[0])?;
+                mem.write_u64(addr.wrapping_add(8), value[1])?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Converts to i32 with the current MXCSR rounding mode, or truncating.
+    fn sse_to_i32(&self, value: f64, truncate: bool) -> u32 {
+        let rounded = if truncate {
+            value.trunc()
+        } else {
+            match (self.mxcsr >> 13) & 3 {
+                0 => {
+                    if (value - value.trunc()).abs() == 0.5 {
+                        2.0 * (value / 2.0).round()
+                    } else {
+                        value.round()
+                    }
+                },
+                1 => value.floor(),
+                2 => value.ceil(),
+                _ => value.trunc(),
+            }
+        };
+        if rounded.is_nan() || !(-2147483648.0..2147483648.0).contains(&rounded) {
+            0x8000_0000
+        } else {
+            rounded as i32 as u32
+        }
+    }
+
+    pub fn sse_op(&mut self, mem: &mut Memory, inst: &Inst, opcode: u8) -> Result<(), String> {
+        let prefix = match (inst.rep, inst.opsize) {
+            (Some(0xF3), _) => Prefix::Ss,
+            (Some(0xF2), _) => Prefix::Sd,
+            (_, 2) => Prefix::Pd,
+            _ => Prefix::Ps,
+        };
+        if opcode == 0x77 {
+            return Ok(()) // emms
+        }
+        let (reg, rm) = self.modrm(mem, inst)?;
+        let r = usize::from(reg);
+        let unsupported = || Err(format!("unsupported instruction 0F {:02X}", opcode));
+        match (opcode, prefix) {
+            (0x10, Prefix::Ps) | (0x10, Prefix::Pd) | (0x28, Prefix::Ps) | (0x28, Prefix::Pd) => {
+                self.xmm[r] = self.xmm_read(mem, rm)?
+            },
+            (0x11, Prefix::Ps) | (0x11, Prefix::Pd) | (0x29, Prefix::Ps) | (0x29, Prefix::Pd) => {
+                self.xmm_write(mem, rm, self.xmm[r])?
+            },
+            (0x10, Prefix::Ss) => match rm {
+                Operand::Reg(s) => {
+                    let low = self.xmm[usize::from(s)][0] & 0xFFFF_FFFF;
+                    self.xmm[r][0] = (self.xmm[r][0] & !0xFFFF_FFFF) | low;
+                },
+                Operand::Mem(addr) => self.xmm[r] = [u64::from(mem.read_u32(addr)?), 0],
+            },
+            (0x10, Prefix::Sd) => match rm {
+                Operand::Reg(s) => self.xmm[r][0] = self.xmm[usize::from(s)][0],
+                Operand::Mem(addr) => self.xmm[r] = [mem.read_u64(addr)?, 0],
+            },
+            (0x11, Prefix::Ss) => match rm {
+                Operand::Reg(d) => {
+                    let low = self.xmm[r][0] & 0xFFFF_FFFF;
+                    self.xmm[usize::from(d)][0] = (self.xmm[usize::from(d)][0] & !0xFFFF_FFFF) | low;
+                },
+                Operand::Mem(addr) => mem.write_u32(addr, self.xmm[r][0] as u32)?,
+            },
+            (0x11, Prefix::Sd) => match rm {
+                Operand::Reg(d) => self.xmm[usize::from(d)][0] = self.xmm[r][0],
+                Operand::Mem(addr) => mem.write_u64(addr, self.xmm[r][0])?,
+            },
+            (0x12, Prefix::Ps) | (0x12, Prefix::Pd) => match rm {
+                Operand::Reg(s) => self.xmm[r][0] = self.xmm[usize::from(s)][1], // movhlps
+                Operand::Mem(addr) => self.xmm[r][0] = mem.read_u64(addr)?,
+            },
+            (0x13, Prefix::Ps) | (0x13, Prefix::Pd) => match rm {
+                Operand::Mem(addr) => mem.write_u64(addr, self.xmm[r][0])?,
+                _ => return unsupported(),
+            },
+            (0x14, Prefix::Ps) => {
+                let (a, b) = (lanes_u32(self.xmm[r]), lanes_u32(self.xmm_read(mem, rm)?));
+                self.xmm[r] = from_lanes_u32([a[0], b[0], a[1], b[1]]);
+            },
+            (0x14, Prefix::Pd) => self.xmm[r][1] = self.xmm_read(mem, rm)?[0],
+            (0x15, Prefix::Ps) => {
+                let (a, b) = (lanes_u32(self.xmm[r]), lanes_u32(self.xmm_read(mem, rm)?));
+                self.xmm[r] = from_lanes_u32([a[2], b[2], a[3], b[3]]);
+            },
+            (0x15, Prefix::Pd) => self.xmm[r] = [self.xmm[r][1], self.xmm_read(mem, rm)?[1]],
+            (0x16, Prefix::Ps) | (0x16, Prefix::Pd) => match rm {
+                Operand::Reg(s) => self.xmm[r][1] = self.xmm[usize::from(s)][0], // movlhps
+                Operand::Mem(addr) => self.xmm[r][1] = mem.read_u64(addr)?,
+            },
+            (0x17, Prefix::Ps) | (0x17, Prefix::Pd) => match rm {
+                Operand::Mem(addr) => mem.write_u64(addr, self.xmm[r][1])?,
+                _ => return unsupported(),
+            },
+            (0x2A, Prefix::Ss) => {
+                let value = self.read_rm(mem, rm, 4)? as i32;
+                self.xmm[r][0] = (self.xmm[r][0] & !0xFFFF_FFFF) | u64::from((value as f32).to_bits());
+            },
+            (0x2A, Prefix::Sd) => {
+                let value = self.read_rm(mem, rm, 4)? as i32;
+                self.xmm[r][0] = f64::from(value).to_bits();
+            },
+            (0x2C, Prefix::Ss) | (0x2D, Prefix::Ss) => {
+                let value = f64::from(f32::from_bits(self.xmm_read32(mem, rm)?));
+                let result = self.sse_to_i32(value, opcode == 0x2C);
+                self.set_reg(reg, 4, result);
+            },
+            (0x2C, Prefix::Sd) | (0x2D, Prefix::Sd) => {
+                let value = f64::from_bits(self.xmm_read64(mem, rm)?);
+                let result = self.sse_to_i32(value, opcode == 0x2C);
+                self.set_reg(reg, 4, result);
+            },
+            (0x2E, Prefix::Ps) | (0x2F, Prefix::Ps) | (0x2E, Prefix::Pd) | (0x2F, Prefix::Pd) => {
+                let (a, b) = if prefix == Prefix::Ps {
+                    (
+                        f64::from(f32::from_bits(self.xmm[r][0] as u32)),
+                        f64::from(f32::from_bits(self.xmm_read32(mem, rm)?)),
+                    )
+                } else {
+                    (f64::from_bits(self.xmm[r][0]), f64::from_bits(self.xmm_read64(mem, rm)?))
+                };
+                self.set_compare_flags(a, b);
+                self.set_flag(OF, false);
+                self.set_flag(SF, false);
+                self.set_flag(AF, false);
+            },
+            (0x50, Prefix::Ps) => {
+                let lanes = lanes_u32(self.xmm_read(mem, rm)?);
+                let mask = lanes.iter().enumerate().fold(0, |m, (i, x)| m | ((x >> 31) << i));
+                self.set_reg(reg, 4, mask);
+            },
+            (0x50, Prefix::Pd) => {
+                let x = self.xmm_read(mem, rm)?;
+                self.set_reg(reg, 4, ((x[0] >> 63) | ((x[1] >> 63) << 1)) as u32);
+            },
+            (0x54, Prefix::Ps) | (0x54, Prefix::Pd) | (0xDB, Prefix::Pd) => {
+                let b = self.xmm_read(mem, rm)?;
+                self.xmm[r] = [self.xmm[r][0] & b[0], self.xmm[r][1] & b[1]];
+            },
+            (0x55, Prefix::Ps) | (0x55, Prefix::Pd) | (0xDF, Prefix::Pd) => {
+                let b = self.xmm_read(mem, rm)?;
+                self.xmm[r] = [!self.xmm[r][0] & b[0], !self.xmm[r][1] & b[1]];
+            },
+            (0x56, Prefix::Ps) | (0x56, Prefix::Pd) | (0xEB, Prefix::Pd) => {
+                let b = self.xmm_read(mem, rm)?;
+                self.xmm[r] = [self.xmm[r][0] | b[0], self.xmm[r][1] | b[1]];
+            },
+            (0x57, Prefix::Ps) | (0x57, Prefix::Pd) | (0xEF, Prefix::Pd) => {
+                let b = self.xmm_read(mem, rm)?;
+                self.xmm[r] = [self.xmm[r][0] ^ b[0], self.xmm[r][1] ^ b[1]];
+            },
+            (0x51, _) | (0x58, _) | (0x59, _) | (0x5C..=0x5F, _) => {
+                let op = |a: f64, b: f64| if opcode == 0x51 { b.sqrt() } else { float_op(opcode, a, b) };
+                match prefix {
+                    Prefix::Ps => {
+                        let (a, b) = (lanes_f32(self.xmm[r]), lanes_f32(self.xmm_read(mem, rm)?));
+                        let mut out = [0.0; 4];
+                        for i in 0..4 {
+                            out[i] = op(f64::from(a[i]), f64::from(b[i])) as f32;
+                        }
+                        self.xmm[r] = from_lanes_f32(out);
+                    },
+                    Prefix::Pd => {
+                        let (a, b) = (self.xmm[r], self.xmm_read(mem, rm)?);
+                        for i in 0..2 {
+                            self.xmm[r][i] = op(f64::from_bits(a[i]), f64::from_bits(b[i])).to_bits();
+                        }
+                    },
+                    Prefix::Ss => {
+                        let a = f64::from(f32::from_bits(self.xmm[r][0] as u32));
+                        let b = f64::from(f32::from_bits(self.xmm_read32(mem, rm)?));
+                        let result = u64::from((op(a, b) as f32).to_bits());
+                        self.xmm[r][0] = (self.xmm[r][0] & !0xFFFF_FFFF) | result;
+                    },
+                    Prefix::Sd => {
+                        let (a, b) = (f64::from_bits(self.xmm[r][0]), f64::from_bits(self.xmm_read64(mem, rm)?));
+                        self.xmm[r][0] = op(a, b).to_bits();
+                    },
+                }
+            },
+            (0x5A, Prefix::Ss) => {
+                let value = f32::from_bits(self.xmm_read32(mem, rm)?);
+                self.xmm[r][0] = f64::from(value).to_bits();
+            },
+            (0x5A, Prefix::Sd) => {
+                let value = f64::from_bits(self.xmm_read64(mem, rm)?);
+                self.xmm[r][0] = (self.xmm[r][0] & !0xFFFF_FFFF) | u64::from((value as f32).to_bits());
+            },
+            (0x5A, Prefix::Ps) => {
+                let lanes = lanes_f32([self.xmm_read64(mem, rm)?, 0]);
+                self.xmm[r] = [f64::from(lanes[0]).to_bits(), f64::from(lanes[1]).to_bits()];
+            },
+            (0x5A, Prefix::Pd) => {
+                let x = self.xmm_read(mem, rm)?;
+                self.xmm[r] = from_lanes_f32([f64::from_bits(x[0]) as f32, f64::from_bits(x[1]) as f32, 0.0, 0.0]);
+            },
+            (0x5B, Prefix::Ps) => {
+                let lanes = lanes_u32(self.xmm_read(mem, rm)?);
+                let mut out = [0.0; 4];
+                for i in 0..4 {
+                    out[i] = lanes[i] as i32 as f32;
+                }
+                self.xmm[r] = from_lanes_f32(out);
+            },
+            (0x5B, Prefix::Pd) | (0x5B, Prefix::Ss) => {
+                let lanes = lanes_f32(self.xmm_read(mem, rm)?);
+                let mut out = [0; 4];
+                for i in 0..4 {
+                    out[i] = self.sse_to_i32(f64::from(lanes[i]), prefix == Prefix::Ss);
+                }
+                self.xmm[r] = from_lanes_u32(out);
+            },
+            (0xE6, Prefix::Ss) => {
+                let value = self.xmm_read64(mem, rm)?;
+                self.xmm[r] = [f64::from(value as i32).to_bits(), f64::from((value >> 32) as i32).to_bits()];
+            },
+            (0xE6, Prefix::Pd) | (0xE6, Prefix::Sd) => {
+                let x = self.xmm_read(mem, rm)?;
+                let truncate = prefix == Prefix::Pd;
+                let (a, b) =
+                    (self.sse_to_i32(f64::from_bits(x[0]), truncate), self.sse_to_i32(f64::from_bits(x[1]), truncate));
+                self.xmm[r] = [u64::from(a) | (u64::from(b) << 32), 0];
+            },
+            (0xC2, _) => {
+                let predicate = self.fetch8(mem)?;
+                match prefix {
+                    Prefix::Ps | Prefix::Ss => {
+                        let (a, b) = (lanes_f32(self.xmm[r]), lanes_f32(self.xmm_read(mem, rm)?));
+                        let mut out = lanes_u32(self.xmm[r]);
+                        for i in 0..if prefix == Prefix::Ss { 1 } else { 4 } {
+                            out[i] = if compare(predicate, f64::from(a[i]), f64::from(b[i])) { !0 } else { 0 };
+                        }
+                        self.xmm[r] = from_lanes_u32(out);
+                    },
+                    Prefix::Pd | Prefix::Sd => {
+                        let (a, b) = (self.xmm[r], self.xmm_read(mem, rm)?);
+                        for i in 0..if prefix == Prefix::Sd { 1 } else { 2 } {
+                            let result = compare(predicate, f64::from_bits(a[i]), f64::from_bits(b[i]));
+                            self.xmm[r][i] = if result { !0 } else { 0 };
+                        }
+                    },
+                }
+            },
+            (0xC6, Prefix::Ps) => {
+                let imm = self.fetch8(mem)?;
+                let (a, b) = (lanes_u32(self.xmm[r]), lanes_u32(self.xmm_read(mem, rm)?));
+                let pick = |x: [u32; 4], shift: u8| x[usize::from((imm >> shift) & 3)];
+                self.xmm[r] = from_lanes_u32([pick(a, 0), pick(a, 2), pick(b, 4), pick(b, 6)]);
+            },
+            (0xC6, Prefix::Pd) => {
+                let imm = self.fetch8(mem)?;
+                let (a, b) = (self.xmm[r], self.xmm_read(mem, rm)?);
+                self.xmm[r] = [a[usize::from(imm & 1)], b[usize::from((imm >> 1) & 1)]];
+            },
+            (0x6E, Prefix::Pd) => self.xmm[r] = [u64::from(self.read_rm(mem, rm, 4)?), 0],
+            (0x7E, Prefix::Pd) => self.write_rm(mem, rm, 4, self.xmm[r][0] as u32)?,
+            (0x7E, Prefix::Ss) => self.xmm[r] = [self.xmm_read64(mem, rm)?, 0],
+            (0xD6, Prefix::Pd) => match rm {
+                Operand::Reg(d) => self.xmm[usize::from(d)] = [self.xmm[r][0], 0],
+                Operand::Mem(addr) => mem.write_u64(addr, self.xmm[r][0])?,
+            },
+            (0x6F, Prefix::Pd) | (0x6F, Prefix::Ss) => self.xmm[r] = self.xmm_read(mem, rm)?,
+            (0x7F, Prefix::Pd) | (0x7F, Prefix::Ss) => self.xmm_write(mem, rm, self.xmm[r])?,
+            (0xD4, Prefix::Pd) | (0xFB, Prefix::Pd) => {
+                let b = self.xmm_read(mem, rm)?;
+                for (a, b) in self.xmm[r].iter_mut().zip(b.iter()) {
+                    *a = if opcode == 0xD4 { a.wrapping_add(*b) } else { a.wrapping_sub(*b) };
+                }
+            },
+            (0xFE, Prefix::Pd) | (0xFA, Prefix::Pd) => {
+                let (mut a, b) = (lanes_u32(self.xmm[r]), lanes_u32(self.xmm_read(mem, rm)?));
+                for i in 0..4 {
+                    a[i] = if opcode == 0xFE { a[i].wrapping_add(b[i]) } else { a[i].wrapping_sub(b[i]) };
+                }
+                self.xmm[r] = from_lanes_u32(a);
+            },
+            (0x62, Prefix::Pd) => {
+                let (a, b) = (lanes_u32(self.xmm[r]), lanes_u32(self.xmm_read(mem, rm)?));
+                self.xmm[r] = from_lanes_u32([a[0], b[0], a[1], b[1]]);
+            },
+            (0x6C, Prefix::Pd) => self.xmm[r][1] = self.xmm_read(mem, rm)?[0],
+            (0x6D, Prefix::Pd) => self.xmm[r] = [self.xmm[r][1], self.xmm_read(mem, rm)?[1]],
+            (0x70, Prefix::Pd) => {
+                let imm = self.fetch8(mem)?;
+                let x = lanes_u32(self.xmm_read(mem, rm)?);
+                let pick = |shift: u8| x[usize::from((imm >> shift) & 3)];
+                self.xmm[r] = from_lanes_u32([pick(0), pick(2), pick(4), pick(6)]);
+            },
+            (0x74, Prefix::Pd) | (0x76, Prefix::Pd) => {
+                let (a, b) = (self.xmm[r], self.xmm_read(mem, rm)?);
+                let lane_bits = if opcode == 0x74 { 8 } else { 32 };
+                let lane_mask = (1u64 << lane_bits) - 1;
+                for i in 0..2 {
+                    let mut out = 0;
+                    for lane in (0..64).step_by(lane_bits) {
+                        if (a[i] >> lane) & lane_mask == (b[i] >> lane) & lane_mask {
+                            out |= lane_mask << lane;
+                        }
+                    }
+                    self.xmm[r][i] = out;
+                }
+            },
+            (0xD7, Prefix::Pd) => {
+                let x = self.xmm_read(mem, rm)?;
+                let mut mask = 0;
+                for i in 0..16 {
+                    if (x[i / 8] >> ((i % 8) * 8 + 7)) & 1 != 0 {
+                        mask |= 1 << i;
+                    }
+                }
+                self.set_reg(reg, 4, mask);
+            },
+            (0x72, Prefix::Pd) | (0x73, Prefix::Pd) => {
+                let count = u32::from(self.fetch8(mem)?);
+                let d = match rm {
+                    Operand::Reg(d) => usize::from(d),
+                    _ => return unsupported(),
+                };
+                let x = self.xmm[d];
+                self.xmm[d] = match (opcode, reg) {
+                    (0x72, 2) | (0x72, 4) | (0x72, 6) => {
+                        let mut lanes = lanes_u32(x);
+                        for lane in lanes.iter_mut() {
+                            *lane = match reg {
+                                2 => lane.checked_shr(count).unwrap_or(0),
+                                4 => ((*lane as i32) >> count.min(31)) as u32,
+                                _ => lane.checked_shl(count).unwrap_or(0),
+                            };
+                        }
+                        from_lanes_u32(lanes)
+                    },
+                    (0x73, 2) => [x[0].checked_shr(count).unwrap_or(0), x[1].checked_shr(count).unwrap_or(0)],
+                    (0x73, 6) => [x[0].checked_shl(count).unwrap_or(0), x[1].checked_shl(count).unwrap_or(0)],
+                    (0x73, 3) | (0x73, 7) => {
+                        let wide = u128::from(x[0]) | (u128::from(x[1]) << 64);
+                        let bits = count.min(16) * 8;
+                        let wide = if reg == 3 {
+                            wide.checked_shr(bits).unwrap_or(0)
+                        } else {
+                            wide.checked_shl(bits).unwrap_or(0)
+                        };
+                        [wide as u64, (wide >> 64) as u64]
+                    },
+                    _ => return unsupported(),
+                };
+            },
+            (0xAE, _) => match (reg, rm) {
+                (2, Operand::Mem(addr)) => self.mxcsr = mem.read_u32(addr)?,
+                (3, Operand::Mem(addr)) => mem.write_u32(addr, self.mxcsr)?,
+                (5..=7, Operand::Reg(_)) => (), // fences
+                _ => return unsupported(),
+            },
+            _ => return unsupported(),
+        }
+        Ok(())
+    }
+}
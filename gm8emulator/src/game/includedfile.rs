@@ -1,3 +1,4 @@
+use crate::gml::file::{PathResolver, Vfs};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
@@ -20,25 +21,31 @@ pub enum ExportSetting {
 }
 
 impl IncludedFile {
-    pub fn export(&mut self, temp_directory: PathBuf, program_directory: PathBuf) -> std::io::Result<()> {
+    pub fn export(
+        &mut self,
+        temp_directory: PathBuf,
+        program_directory: PathBuf,
+        path_resolver: &PathResolver,
+        vfs: &mut Vfs,
+    ) -> std::io::Result<()> {
         if self.data.is_some() {
             if let Some(mut export_path) = match self.export_settings.clone() {
                 ExportSetting::NoExport => None,
                 ExportSetting::TempFolder => Some(temp_directory),
                 ExportSetting::GameFolder => Some(program_directory),
-                ExportSetting::CustomFolder(dir) => Some(dir.clone().into()),
+                ExportSetting::CustomFolder(dir) => Some(path_resolver.resolve(&dir)),
             } {
                 export_path.push(&self.name);
-                self.export_to(&export_path)?;
+                self.export_to(&export_path, vfs)?;
             }
         }
         Ok(())
     }
 
-    pub fn export_to(&mut self, path: &Path) -> std::io::Result<()> {
+    pub fn export_to(&mut self, path: &Path, vfs: &mut Vfs) -> std::io::Result<()> {
         if let Some(data) = self.data.as_ref() {
-            if self.overwrite || !path.exists() {
-                std::fs::write(path, &data)?;
+            if self.overwrite || !vfs.file_exists(path) {
+                vfs.write(path, data.to_vec())?;
             }
             if self.free_after_export {
                 self.data = None;
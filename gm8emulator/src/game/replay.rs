@@ -1,6 +1,18 @@
-use crate::gml::Value;
+use crate::{
+    game::{checksum::Checksum, SaveState},
+    gml::{network, Value},
+};
 use serde::{Deserialize, Serialize};
 use shared::input::{Key, MouseButton};
+use std::{
+    fs::File,
+    io::BufWriter,
+    ops::Range,
+    path::Path,
+};
+
+pub mod text;
+pub mod versions;
 
 // Represents an entire replay (TAS) file
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -29,17 +41,21 @@ pub struct Frame {
     pub events: Vec<Event>,
     pub new_seed: Option<i32>,
     pub new_time: Option<u128>,
+    pub checksum: Option<Checksum>, // of the state at the end of the frame
 }
 
 // Stored events for certain things which must always happen the same way during replay
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Event {
-    GetInteger(Value),   // value returned from get_integer()
-    GetString(Value),    // value returned from get_string()
-    Randomize(i32),      // value assigned to seed by randomize()
-    ShowMenu(Value),     // value returned from show_menu()
-    ShowMessage,         // acknowledges that a show_message() does not need to be shown during replay
-    ShowQuestion(Value), // value returned from show_question()
+    GetInteger(Value),                    // value returned from get_integer()
+    GetString(Value),                     // value returned from get_string()
+    Randomize(i32),                       // value assigned to seed by randomize()
+    ShowMenu(Value),                      // value returned from show_menu()
+    ShowMessage,                          // acknowledges that a show_message() does not need to be shown during replay
+    ShowQuestion(Value),                  // value returned from show_question()
+    Execute(Result<Option<i32>, String>), // outcome of execute_program() or execute_shell(), exit code if waited for
+    IgnoreError,                          // acknowledges that an error dialog was closed with "Ignore"
+    Network(network::Event),              // something a multiplayer session saw or returned
 }
 
 // An input event which takes place during a frame
@@ -53,6 +69,12 @@ pub enum Input {
     MouseWheelDown,
 }
 
+impl Default for Replay {
+    fn default() -> Self {
+        Self::new(0, 0)
+    }
+}
+
 impl Replay {
     pub fn new(start_time: u128, start_seed: i32) -> Self {
         Self { start_time, start_seed, startup_events: Vec::new(), frames: Vec::new() }
@@ -74,6 +96,7 @@ impl Replay {
             events: Vec::new(),
             new_seed: None,
             new_time: None,
+            checksum: None,
         });
         self.frames.last_mut().unwrap() // Last cannot be None since we just pushed an element
     }
@@ -112,4 +135,210 @@ impl Replay {
         }
         ms
     }
+
+    // Reads a replay from a .gmtas file, a text replay (.txt), or the replay inside a savestate (.bin)
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let read = || std::fs::read(path).map_err(|e| e.to_string());
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("gmtas") => versions::read(&mut read()?.as_slice()),
+            Some("txt") => Self::from_text(&std::fs::read_to_string(path).map_err(|e| e.to_string())?),
+            Some("bin") => SaveState::read_replay(&read()?),
+            _ => Err("unknown replay file type, expected '.bin', '.gmtas' or '.txt'".into()),
+        }
+    }
+
+    // Writes a replay to a .gmtas or text (.txt) file
+    pub fn to_file(&self, path: &Path) -> Result<(), String> {
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("gmtas") => {
+                let mut file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+                versions::write(&mut file, self).map_err(|e| e.to_string())
+            },
+            Some("txt") => std::fs::write(path, self.to_text()).map_err(|e| e.to_string()),
+            _ => Err("can only write replays to '.gmtas' or '.txt' files".into()),
+        }
+    }
+
+    // Cuts the replay off after the given number of frames
+    pub fn truncate(&mut self, frame_count: usize) -> Result<(), String> {
+        if frame_count > self.frames.len() {
+            return Err(format!("can't keep {} frames, the replay only has {}", frame_count, self.frames.len()))
+        }
+        self.frames.truncate(frame_count);
+        Ok(())
+    }
+
+    // Inserts empty frames, which carry on with the previous frame's fps and mouse position
+    pub fn insert_frames(&mut self, at: usize, count: usize) -> Result<(), String> {
+        if at > self.frames.len() {
+            return Err(format!("can't insert at frame {}, the replay only has {} frames", at, self.frames.len()))
+        }
+        let template = at.checked_sub(1).and_then(|i| self.frames.get(i)).or_else(|| self.frames.get(at));
+        let (fps, mouse_x, mouse_y) = template.map_or((30, 0.0, 0.0), |f| (f.fps, f.mouse_x, f.mouse_y));
+        let empty = Frame {
+            fps,
+            mouse_x,
+            mouse_y,
+            inputs: Vec::new(),
+            events: Vec::new(),
+            new_seed: None,
+            new_time: None,
+            checksum: None,
+        };
+        self.frames.splice(at..at, vec![empty; count]);
+        self.invalidate_checksums(at);
+        Ok(())
+    }
+
+    // Deletes a range of frames, along with the events stored on them
+    pub fn delete_frames(&mut self, range: Range<usize>) -> Result<(), String> {
+        self.check_range(&range)?;
+        let held = held_after(&self.frames[..range.end]);
+        self.frames.drain(range.clone());
+        self.rejoin(range.start, &held);
+        self.invalidate_checksums(range.start);
+        Ok(())
+    }
+
+    // Copies a range of frames from another replay over this one's, starting at the given frame
+    // This replay gets extended with empty frames if it isn't long enough
+    pub fn splice(&mut self, at: usize, source: &Replay, range: Range<usize>) -> Result<(), String> {
+        source.check_range(&range)?;
+        if at > self.frames.len() {
+            self.insert_frames(self.frames.len(), at - self.frames.len())?;
+        }
+        let end = at + range.len();
+        let held_at_end = held_after(&self.frames[..end.min(self.frames.len())]);
+        let replace = at..end.min(self.frames.len());
+        self.frames.splice(replace, source.frames[range.clone()].iter().cloned());
+        self.rejoin(at, &held_after(&source.frames[..range.start]));
+        self.rejoin(end, &held_at_end);
+        self.invalidate_checksums(at);
+        Ok(())
+    }
+
+    // Changes the time or seed the replay starts with, which means none of its checksums are right any more
+    pub fn set_start(&mut self, start_time: Option<u128>, start_seed: Option<i32>) {
+        self.start_time = start_time.unwrap_or(self.start_time);
+        self.start_seed = start_seed.unwrap_or(self.start_seed);
+        self.invalidate_checksums(0);
+    }
+
+    fn check_range(&self, range: &Range<usize>) -> Result<(), String> {
+        if range.start > range.end || range.end > self.frames.len() {
+            return Err(format!(
+                "frames {}..{} aren't in the replay, it has {} frames",
+                range.start,
+                range.end,
+                self.frames.len()
+            ))
+        }
+        Ok(())
+    }
+
+    // Makes sure the given keys and buttons are held going into a frame, by adding presses and releases to it,
+    // since the frames after an edit were recorded with whatever was held before it
+    fn rejoin(&mut self, frame: usize, held: &[Button]) {
+        if frame >= self.frames.len() {
+            return
+        }
+        let actual = held_after(&self.frames[..frame]);
+        let releases = actual.iter().filter(|b| !held.contains(b)).map(|b| b.input(false));
+        let presses = held.iter().filter(|b| !actual.contains(b)).map(|b| b.input(true));
+        let fixes = releases.chain(presses).collect::<Vec<_>>();
+        self.frames[frame].inputs.splice(0..0, fixes);
+    }
+
+    // checksums describe the state after everything before them, so any edit makes the ones after it wrong
+    fn invalidate_checksums(&mut self, from: usize) {
+        for frame in self.frames.iter_mut().skip(from) {
+            frame.checksum = None;
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Button {
+    Key(Key),
+    Mouse(MouseButton),
+}
+
+impl Button {
+    fn input(self, press: bool) -> Input {
+        match (self, press) {
+            (Button::Key(key), true) => Input::KeyPress(key),
+            (Button::Key(key), false) => Input::KeyRelease(key),
+            (Button::Mouse(button), true) => Input::MousePress(button),
+            (Button::Mouse(button), false) => Input::MouseRelease(button),
+        }
+    }
+}
+
+// what's held down at the end of some frames, going by their inputs
+fn held_after(frames: &[Frame]) -> Vec<Button> {
+    let mut held = Vec::new();
+    for input in frames.iter().flat_map(|f| &f.inputs) {
+        let (button, press) = match input {
+            Input::KeyPress(key) => (Button::Key(*key), true),
+            Input::KeyRelease(key) => (Button::Key(*key), false),
+            Input::MousePress(button) => (Button::Mouse(*button), true),
+            Input::MouseRelease(button) => (Button::Mouse(*button), false),
+            Input::MouseWheelUp | Input::MouseWheelDown => continue,
+        };
+        held.retain(|b| *b != button);
+        if press {
+            held.push(button);
+        }
+    }
+    held
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replay(frames: &[&str]) -> Replay {
+        Replay::from_text(&format!("gm8emulator replay\nstart_time 0\nstart_seed 0\n{}", frames.join("\n"))).unwrap()
+    }
+
+    fn frames(replay: &Replay) -> Vec<String> {
+        replay.to_text().lines().skip(3).map(String::from).collect()
+    }
+
+    #[test]
+    fn delete_keeps_held_keys() {
+        let mut r = replay(&[
+            "30 0,0 | +Left",
+            "30 0,0 | +Z Left | show_message",
+            "30 0,0 | -Left Z",
+            "30 0,0 | Z | checksum=0/0/0/0",
+        ]);
+        r.delete_frames(1..2).unwrap();
+        assert_eq!(frames(&r), ["30 0,0 | +Left", "30 0,0 | +Z -Left", "30 0,0 | Z"]);
+        assert!(r.delete_frames(2..4).is_err());
+    }
+
+    #[test]
+    fn insert_carries_on() {
+        let mut r = replay(&["60 5,5 | +A", "60 5,5 | A"]);
+        r.insert_frames(1, 2).unwrap();
+        assert_eq!(frames(&r), ["60 5,5 | +A", "60 5,5 | A", "60 5,5 | A", "60 5,5 | A"]);
+        r.truncate(1).unwrap();
+        assert_eq!(frames(&r), ["60 5,5 | +A"]);
+        assert!(r.truncate(2).is_err());
+        assert_eq!(r.frame_count(), 1);
+    }
+
+    #[test]
+    fn splice_fixes_both_ends() {
+        let mut r = replay(&["30 0,0", "30 0,0", "30 0,0 | +A"]);
+        let source = replay(&["60 1,1 | +B", "60 1,1 | B | get_integer:3"]);
+        r.splice(1, &source, 1..2).unwrap();
+        assert_eq!(frames(&r), ["30 0,0", "60 1,1 | +B | get_integer:3", "30 0,0 | -B +A"]);
+
+        // past the end, so it needs some empty frames first, and nothing was held when it was recorded
+        r.splice(5, &source, 0..1).unwrap();
+        assert_eq!(frames(&r)[3..], ["30 0,0 | A", "30 0,0 | A", "60 1,1 | -A +B"]);
+        assert!(r.splice(0, &source, 1..3).is_err());
+    }
 }
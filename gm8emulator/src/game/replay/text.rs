@@ -0,0 +1,526 @@
+//! A plain text version of a replay, for keeping TAS projects in git and reviewing them as diffs.
+//!
+//! ```text
+//! gm8emulator replay
+//! start_time 1600000000000000000
+//! start_seed 12345
+//! startup get_integer:5
+//! 30 0,0
+//! 30 0,0 | +Right
+//! 30 12.5,40 | +Z +MouseLeft Right | seed=6
+//! 30 12.5,40 | -Right -MouseLeft Z | show_message get_string:"hi there"
+//! ```
+//!
+//! After the header there's one line per frame: the fps and the mouse position, then everything that's held down
+//! at the end of the frame, then anything else stored for it. `+` and `-` mark presses and releases in the order
+//! they happened. A key that's listed without one just stays held, and a key that stops being listed gets let go,
+//! so held keys can be edited without worrying about where the presses and releases are.
+
+use super::{Event, Frame, Input, Replay};
+use crate::{
+    game::checksum::Checksum,
+    gml::{network, Value},
+};
+use shared::input::{Key, MouseButton};
+use std::{collections::BTreeSet, fmt::Write};
+
+const MAGIC: &str = "gm8emulator replay";
+
+impl Replay {
+    /// Writes the replay out as text. Reading it back with from_text gives exactly the same replay.
+    pub fn to_text(&self) -> String {
+        let mut s = format!("{}\nstart_time {}\nstart_seed {}\n", MAGIC, self.start_time, self.start_seed);
+        if !self.startup_events.is_empty() {
+            let events = self.startup_events.iter().map(write_event).collect::<Vec<_>>();
+            writeln!(s, "startup {}", events.join(" ")).unwrap();
+        }
+
+        let mut held = BTreeSet::new();
+        for frame in &self.frames {
+            let mut changed = BTreeSet::new();
+            let mut inputs = Vec::new();
+            for input in &frame.inputs {
+                let (name, press) = match input {
+                    Input::KeyPress(key) => (format!("{:?}", key), true),
+                    Input::KeyRelease(key) => (format!("{:?}", key), false),
+                    Input::MousePress(button) => (format!("Mouse{:?}", button), true),
+                    Input::MouseRelease(button) => (format!("Mouse{:?}", button), false),
+                    Input::MouseWheelUp => {
+                        inputs.push("WheelUp".to_string());
+                        continue
+                    },
+                    Input::MouseWheelDown => {
+                        inputs.push("WheelDown".to_string());
+                        continue
+                    },
+                };
+                if press {
+                    held.insert(name.clone());
+                    inputs.push(format!("+{}", name));
+                } else {
+                    held.remove(&name);
+                    inputs.push(format!("-{}", name));
+                }
+                changed.insert(name);
+            }
+            inputs.extend(held.difference(&changed).cloned());
+
+            let mut extras = frame.events.iter().map(write_event).collect::<Vec<_>>();
+            if let Some(seed) = frame.new_seed {
+                extras.push(format!("seed={}", seed));
+            }
+            if let Some(time) = frame.new_time {
+                extras.push(format!("time={}", time));
+            }
+            if let Some(c) = frame.checksum {
+                extras.push(format!("checksum={:016x}/{:016x}/{}/{:016x}", c.instances, c.globals, c.seed, c.room));
+            }
+
+            write!(s, "{} {},{}", frame.fps, frame.mouse_x, frame.mouse_y).unwrap();
+            if !inputs.is_empty() || !extras.is_empty() {
+                write!(s, " | {}", inputs.join(" ")).unwrap();
+            }
+            if !extras.is_empty() {
+                write!(s, " | {}", extras.join(" ")).unwrap();
+            }
+            s.push('\n');
+        }
+        s
+    }
+
+    /// Reads a replay written by to_text, or by hand.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty() && !l.starts_with('#'));
+        match lines.next() {
+            Some((_, line)) if line.trim() == MAGIC => (),
+            _ => return Err(format!("not a text replay, expected it to start with \"{}\"", MAGIC)),
+        }
+
+        let mut start_time = None;
+        let mut start_seed = None;
+        let mut replay = Replay::new(0, 0);
+        let mut held = BTreeSet::new();
+        for (i, line) in lines {
+            let error = |e: String| format!("line {}: {}", i + 1, e);
+            let tokens = tokenize(line).map_err(error)?;
+            match tokens.first().map(String::as_str) {
+                Some("start_time") => start_time = Some(parse_one(&tokens).map_err(error)?),
+                Some("start_seed") => start_seed = Some(parse_one(&tokens).map_err(error)?),
+                Some("startup") => {
+                    for token in &tokens[1..] {
+                        replay.startup_events.push(read_event(token).map_err(error)?);
+                    }
+                },
+                _ => {
+                    let frame = read_frame(&tokens, &mut held).map_err(error)?;
+                    replay.frames.push(frame);
+                },
+            }
+        }
+        replay.start_time = start_time.ok_or("missing start_time")?;
+        replay.start_seed = start_seed.ok_or("missing start_seed")?;
+        Ok(replay)
+    }
+}
+
+fn parse_one<T: std::str::FromStr>(tokens: &[String]) -> Result<T, String> {
+    match tokens {
+        [_, value] => value.parse().map_err(|_| format!("invalid {}: {}", tokens[0], value)),
+        _ => Err(format!("expected one value after {}", tokens[0])),
+    }
+}
+
+// `held` is everything held down before this frame, and gets updated to what's held after it
+fn read_frame(tokens: &[String], held: &mut BTreeSet<String>) -> Result<Frame, String> {
+    let mut columns = tokens.split(|t| t == "|");
+    let (fps, mouse) = match columns.next().unwrap_or_default() {
+        [fps, mouse] => (fps, mouse),
+        _ => return Err("expected a frame, starting with the fps and mouse position".into()),
+    };
+    let fps = fps.parse().map_err(|_| format!("invalid fps: {}", fps))?;
+    let (mouse_x, mouse_y) = match mouse.split_once(',').map(|(x, y)| (x.parse(), y.parse())) {
+        Some((Ok(x), Ok(y))) => (x, y),
+        _ => return Err(format!("invalid mouse position: {}", mouse)),
+    };
+    let mut frame = Frame {
+        fps,
+        mouse_x,
+        mouse_y,
+        inputs: Vec::new(),
+        events: Vec::new(),
+        new_seed: None,
+        new_time: None,
+        checksum: None,
+    };
+
+    let mut listed = BTreeSet::new();
+    for token in columns.next().unwrap_or_default() {
+        let (name, press) = match token.as_bytes().first() {
+            Some(b'+') => (&token[1..], Some(true)),
+            Some(b'-') => (&token[1..], Some(false)),
+            _ => (token.as_str(), None),
+        };
+        match (name, press) {
+            ("WheelUp", None) => frame.inputs.push(Input::MouseWheelUp),
+            ("WheelDown", None) => frame.inputs.push(Input::MouseWheelDown),
+            (name, Some(press)) => {
+                frame.inputs.push(read_input(name, press)?);
+                if press {
+                    held.insert(name.to_string());
+                    listed.insert(name.to_string());
+                } else {
+                    held.remove(name);
+                    listed.remove(name);
+                }
+            },
+            (name, None) => {
+                read_input(name, true)?;
+                listed.insert(name.to_string());
+            },
+        }
+    }
+    // anything that went up or down without saying so
+    for name in held.difference(&listed) {
+        frame.inputs.push(read_input(name, false)?);
+    }
+    for name in listed.difference(held) {
+        frame.inputs.push(read_input(name, true)?);
+    }
+    *held = listed;
+
+    for token in columns.next().unwrap_or_default() {
+        if let Some(seed) = token.strip_prefix("seed=") {
+            frame.new_seed = Some(seed.parse().map_err(|_| format!("invalid seed: {}", seed))?);
+        } else if let Some(time) = token.strip_prefix("time=") {
+            frame.new_time = Some(time.parse().map_err(|_| format!("invalid time: {}", time))?);
+        } else if let Some(checksum) = token.strip_prefix("checksum=") {
+            frame.checksum = Some(read_checksum(checksum).ok_or_else(|| format!("invalid checksum: {}", checksum))?);
+        } else {
+            frame.events.push(read_event(token)?);
+        }
+    }
+    if columns.next().is_some() {
+        return Err("too many columns".into())
+    }
+    Ok(frame)
+}
+
+fn read_input(name: &str, press: bool) -> Result<Input, String> {
+    if let Some(button) = name.strip_prefix("Mouse") {
+        let button = match button {
+            "Left" => MouseButton::Left,
+            "Right" => MouseButton::Right,
+            "Middle" => MouseButton::Middle,
+            _ => return Err(format!("unknown mouse button: {}", name)),
+        };
+        return Ok(if press { Input::MousePress(button) } else { Input::MouseRelease(button) })
+    }
+    match (0..=u8::MAX).filter_map(Key::from_winapi).find(|k| format!("{:?}", k) == name) {
+        Some(key) => Ok(if press { Input::KeyPress(key) } else { Input::KeyRelease(key) }),
+        None => Err(format!("unknown key: {}", name)),
+    }
+}
+
+fn read_checksum(s: &str) -> Option<Checksum> {
+    let mut parts = s.split('/');
+    let mut hash = || u64::from_str_radix(parts.next()?, 16).ok();
+    let (instances, globals) = (hash()?, hash()?);
+    let seed = parts.next()?.parse().ok()?;
+    let room = u64::from_str_radix(parts.next()?, 16).ok()?;
+    match parts.next() {
+        Some(_) => None,
+        None => Some(Checksum { instances, globals, seed, room }),
+    }
+}
+
+fn write_event(event: &Event) -> String {
+    match event {
+        Event::GetInteger(v) => format!("get_integer:{}", write_value(v)),
+        Event::GetString(v) => format!("get_string:{}", write_value(v)),
+        Event::Randomize(seed) => format!("randomize:{}", seed),
+        Event::ShowMenu(v) => format!("show_menu:{}", write_value(v)),
+        Event::ShowMessage => "show_message".into(),
+        Event::ShowQuestion(v) => format!("show_question:{}", write_value(v)),
+        Event::Execute(Ok(Some(code))) => format!("execute:{}", code),
+        Event::Execute(Ok(None)) => "execute".into(),
+        Event::Execute(Err(e)) => format!("execute_error:{}", quote(e.as_bytes())),
+        Event::IgnoreError => "ignore_error".into(),
+        Event::Network(event) => write_network_event(event),
+    }
+}
+
+fn write_network_event(event: &network::Event) -> String {
+    use network::Event as N;
+    match event {
+        N::Created(ok) => format!("mplay_create:{}", u8::from(*ok)),
+        N::Found(names) => {
+            let names = names.iter().map(|n| quote(n.as_ref())).collect::<Vec<_>>();
+            format!("mplay_find:{}", names.join(","))
+        },
+        N::Joining(ok) => format!("mplay_join:{}", u8::from(*ok)),
+        N::Sent(ok) => format!("mplay_send:{}", u8::from(*ok)),
+        N::Welcomed(id) => format!("mplay_welcome:{}", id),
+        N::Ended => "mplay_ended".into(),
+        N::PlayerJoined(id, name) => format!("mplay_player_joined:{},{}", id, quote(name.as_ref())),
+        N::PlayerLeft(id) => format!("mplay_player_left:{}", id),
+        N::Data(index, v) => format!("mplay_data:{},{}", index, write_value(v)),
+        N::Message(from, id, v) => format!("mplay_message:{},{},{}", from, id, write_value(v)),
+    }
+}
+
+fn read_event(token: &str) -> Result<Event, String> {
+    let (name, arg) = match token.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (token, None),
+    };
+    let value = || arg.ok_or_else(|| format!("{} needs a value", name)).and_then(read_value);
+    Ok(match (name, arg) {
+        ("get_integer", _) => Event::GetInteger(value()?),
+        ("get_string", _) => Event::GetString(value()?),
+        ("randomize", Some(seed)) => Event::Randomize(seed.parse().map_err(|_| format!("invalid seed: {}", seed))?),
+        ("show_menu", _) => Event::ShowMenu(value()?),
+        ("show_message", None) => Event::ShowMessage,
+        ("show_question", _) => Event::ShowQuestion(value()?),
+        ("execute", None) => Event::Execute(Ok(None)),
+        ("execute", Some(code)) => {
+            Event::Execute(Ok(Some(code.parse().map_err(|_| format!("invalid exit code: {}", code))?)))
+        },
+        ("execute_error", Some(e)) => Event::Execute(Err(String::from_utf8_lossy(&unquote(e)?).into())),
+        ("ignore_error", None) => Event::IgnoreError,
+        (name, arg) if name.starts_with("mplay_") => Event::Network(read_network_event(name, arg.unwrap_or_default())?),
+        _ => return Err(format!("unknown event: {}", token)),
+    })
+}
+
+fn read_network_event(name: &str, arg: &str) -> Result<network::Event, String> {
+    use network::Event as N;
+    let args = split_args(arg);
+    let invalid = || format!("invalid {}: {}", name, arg);
+    let flag = || match arg {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(invalid()),
+    };
+    let number = |i: usize| args.get(i).and_then(|a| a.parse().ok()).ok_or_else(invalid);
+    let count = |n: usize| if args.len() == n { Ok(()) } else { Err(invalid()) };
+    Ok(match name {
+        "mplay_create" => N::Created(flag()?),
+        "mplay_find" if arg.is_empty() => N::Found(Vec::new()),
+        "mplay_find" => N::Found(args.iter().map(|a| unquote(a).map(Into::into)).collect::<Result<_, _>>()?),
+        "mplay_join" => N::Joining(flag()?),
+        "mplay_send" => N::Sent(flag()?),
+        "mplay_welcome" => {
+            count(1)?;
+            N::Welcomed(number(0)?)
+        },
+        "mplay_ended" if arg.is_empty() => N::Ended,
+        "mplay_player_joined" => {
+            count(2)?;
+            N::PlayerJoined(number(0)?, unquote(args[1])?.into())
+        },
+        "mplay_player_left" => {
+            count(1)?;
+            N::PlayerLeft(number(0)?)
+        },
+        "mplay_data" => {
+            count(2)?;
+            N::Data(number(0)?, read_value(args[1])?)
+        },
+        "mplay_message" => {
+            count(3)?;
+            let id = args[1].parse().map_err(|_| invalid())?;
+            N::Message(number(0)?, id, read_value(args[2])?)
+        },
+        _ => return Err(format!("unknown event: {}:{}", name, arg)),
+    })
+}
+
+// splits on commas, except inside quotes
+fn split_args(s: &str) -> Vec<&str> {
+    let mut args = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                args.push(&s[start..i]);
+                start = i + 1;
+            },
+            _ => (),
+        }
+    }
+    args.push(&s[start..]);
+    args
+}
+
+// numbers are written so they read back exactly, and strings are quoted
+fn write_value(value: &Value) -> String {
+    match value {
+        Value::Real(x) => x.into_inner().to_string(),
+        Value::Str(s) => quote(s.as_ref()),
+    }
+}
+
+fn read_value(s: &str) -> Result<Value, String> {
+    if s.starts_with('"') {
+        Ok(unquote(s)?.into())
+    } else {
+        s.parse::<f64>().map(Value::from).map_err(|_| format!("invalid value: {}", s))
+    }
+}
+
+// strings in GML are bytes, so anything that isn't printable ASCII gets escaped
+fn quote(bytes: &[u8]) -> String {
+    let mut s = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'"' => s.push_str("\\\""),
+            b'\\' => s.push_str("\\\\"),
+            b'\n' => s.push_str("\\n"),
+            b'\r' => s.push_str("\\r"),
+            b'\t' => s.push_str("\\t"),
+            b' '..=b'~' => s.push(b as char),
+            b => write!(s, "\\x{:02x}", b).unwrap(),
+        }
+    }
+    s.push('"');
+    s
+}
+
+fn unquote(s: &str) -> Result<Vec<u8>, String> {
+    let inner = match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(inner) if s.len() >= 2 => inner.as_bytes(),
+        _ => return Err(format!("invalid string: {}", s)),
+    };
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut i = 0;
+    while let Some(&b) = inner.get(i) {
+        i += 1;
+        if b != b'\\' {
+            bytes.push(b);
+            continue
+        }
+        let escaped = inner.get(i).copied();
+        i += 1;
+        bytes.push(match escaped {
+            Some(b'n') => b'\n',
+            Some(b'r') => b'\r',
+            Some(b't') => b'\t',
+            Some(b'x') => {
+                let hex = inner.get(i..i + 2).and_then(|h| std::str::from_utf8(h).ok());
+                i += 2;
+                hex.and_then(|h| u8::from_str_radix(h, 16).ok()).ok_or_else(|| format!("invalid escape in {}", s))?
+            },
+            Some(b) => b,
+            None => return Err(format!("invalid string: {}", s)),
+        });
+    }
+    Ok(bytes)
+}
+
+// splits on whitespace, except inside quotes
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut chars = line.chars();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => {
+                token.push(c);
+                if let Some(c) = chars.next() {
+                    token.push(c);
+                }
+                continue
+            },
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                continue
+            },
+            _ => (),
+        }
+        token.push(c);
+    }
+    if quoted {
+        return Err("unterminated string".into())
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut replay = Replay::new(1600000000123456789, -42);
+        replay.startup_events.push(Event::GetString("line\none \"two\" \\ \u{e9}".into()));
+        replay.startup_events.push(Event::Execute(Err("not allowed | nope".into())));
+        replay.new_frame(30);
+        let frame = replay.new_frame(60);
+        frame.mouse_x = 0.1;
+        frame.mouse_y = -3.0;
+        frame.inputs = vec![Input::KeyPress(Key::Z), Input::MousePress(MouseButton::Left), Input::KeyPress(Key::Left)];
+        frame.new_seed = Some(7);
+        frame.new_time = Some(5);
+        frame.checksum = Some(Checksum { instances: u64::MAX, globals: 1, seed: -1, room: 0 });
+        let frame = replay.new_frame(60);
+        frame.inputs = vec![
+            Input::KeyPress(Key::Space),
+            Input::KeyRelease(Key::Space),
+            Input::MouseWheelDown,
+            Input::MouseRelease(MouseButton::Left),
+        ];
+        frame.events = vec![
+            Event::GetInteger(f64::NAN.into()),
+            Event::ShowQuestion((-0.0).into()),
+            Event::ShowMenu(1e300.into()),
+            Event::Randomize(3),
+            Event::ShowMessage,
+            Event::Execute(Ok(Some(-1))),
+            Event::Execute(Ok(None)),
+            Event::IgnoreError,
+            Event::Network(network::Event::Created(true)),
+            Event::Network(network::Event::Found(vec!["a, \"b\"".into(), "".into()])),
+            Event::Network(network::Event::Found(Vec::new())),
+            Event::Network(network::Event::Joining(false)),
+            Event::Network(network::Event::Sent(true)),
+            Event::Network(network::Event::Welcomed(2)),
+            Event::Network(network::Event::PlayerJoined(3, "some,one".into())),
+            Event::Network(network::Event::PlayerLeft(3)),
+            Event::Network(network::Event::Data(9999, "x,y".into())),
+            Event::Network(network::Event::Message(1, 0.5, 2.0.into())),
+            Event::Network(network::Event::Ended),
+        ];
+        let frame = replay.new_frame(60);
+        frame.inputs = vec![Input::KeyRelease(Key::Z)];
+
+        let text = replay.to_text();
+        let read = Replay::from_text(&text).unwrap();
+        assert_eq!(format!("{:?}", read), format!("{:?}", replay));
+        assert_eq!(read.to_text(), text);
+        assert!(text.lines().nth(5).unwrap().starts_with("60 0.1,-3 | +Z +MouseLeft +Left | seed=7 time=5"));
+        assert_eq!(text.lines().nth(7), Some("60 0.1,-3 | -Z Left"));
+    }
+
+    #[test]
+    fn held_keys_can_be_edited() {
+        let text = "gm8emulator replay\nstart_time 0\nstart_seed 0\n30 0,0 | Up\n30 0,0 | Up X\n# a comment\n30 0,0\n";
+        let replay = Replay::from_text(text).unwrap();
+        let inputs = (0..3).map(|i| format!("{:?}", replay.get_frame(i).unwrap().inputs)).collect::<Vec<_>>();
+        assert_eq!(inputs, ["[KeyPress(Up)]", "[KeyPress(X)]", "[KeyRelease(Up), KeyRelease(X)]"]);
+
+        assert!(Replay::from_text("gm8emulator replay\nstart_time 0\nstart_seed 0\n30 0,0 | Nope").is_err());
+        assert!(Replay::from_text("start_time 0\nstart_seed 0\n").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Replays are saved with a magic number and a format version in front, so ones made by older versions of the
+//! emulator can still be read. Each old version keeps a copy of how its replays were laid out, and gets upgraded
+//! to the next version until it's the current one. Replays from before there was a header at all are version 0.
+
+use super::{Event, Frame, Input, Replay};
+use serde::Deserialize;
+use std::io::Write;
+
+const MAGIC: [u8; 8] = *b"GM8EMREP";
+
+/// The version replays get saved as. Bump this and add an upgrade from the old layout whenever Replay changes.
+pub const VERSION: u32 = 1;
+
+/// Writes the header, then the replay.
+pub fn write(w: &mut impl Write, replay: &Replay) -> bincode::Result<()> {
+    w.write_all(&MAGIC)?;
+    bincode::serialize_into(&mut *w, &VERSION)?;
+    bincode::serialize_into(w, replay)
+}
+
+/// Reads a replay saved by any version, upgrading it if it's old. Leaves `bytes` just after the replay.
+pub fn read(bytes: &mut &[u8]) -> Result<Replay, String> {
+    let version = match bytes.strip_prefix(&MAGIC[..]) {
+        Some(rest) => {
+            *bytes = rest;
+            bincode::deserialize_from::<_, u32>(&mut *bytes)
+                .map_err(|e| format!("couldn't read replay header: {}", e))?
+        },
+        None => 0,
+    };
+    let error = |e| format!("couldn't read version {} replay: {}", version, e);
+    match version {
+        0 => bincode::deserialize_from::<_, ReplayV0>(&mut *bytes).map(Replay::from).map_err(error),
+        VERSION => bincode::deserialize_from(&mut *bytes).map_err(error),
+        _ => Err(format!("replay is format version {}, which is newer than this emulator ({})", version, VERSION)),
+    }
+}
+
+// version 0 had no checksums
+// events have been added since, but always on the end, so the ones it has are still numbered the same
+// savestates from back then hold one of these too
+#[derive(Deserialize)]
+pub struct ReplayV0 {
+    start_time: u128,
+    start_seed: i32,
+    startup_events: Vec<Event>,
+    frames: Vec<FrameV0>,
+}
+
+#[derive(Deserialize)]
+struct FrameV0 {
+    fps: u32,
+    mouse_x: f64,
+    mouse_y: f64,
+    inputs: Vec<Input>,
+    events: Vec<Event>,
+    new_seed: Option<i32>,
+    new_time: Option<u128>,
+}
+
+impl From<ReplayV0> for Replay {
+    fn from(old: ReplayV0) -> Self {
+        let frames = old
+            .frames
+            .into_iter()
+            .map(|f| Frame {
+                fps: f.fps,
+                mouse_x: f.mouse_x,
+                mouse_y: f.mouse_y,
+                inputs: f.inputs,
+                events: f.events,
+                new_seed: f.new_seed,
+                new_time: f.new_time,
+                checksum: None,
+            })
+            .collect();
+        Self { start_time: old.start_time, start_seed: old.start_seed, startup_events: old.startup_events, frames }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what the fixtures hold, which were saved by the emulator back when each version was current
+    // these should never change: give each new version its own fixture instead
+    const V0: &str = "gm8emulator replay
+start_time 1600000000000000000
+start_seed 1234
+startup get_string:\"player\"
+50 0,0 | +Right
+50 320,240.5 | +MouseLeft Right | show_question:1 seed=99
+50 320,240.5 | -Right -MouseLeft WheelUp | randomize:5 show_message time=1600000000100000000
+";
+    const V1: &str = "gm8emulator replay
+start_time 1600000000000000000
+start_seed 1234
+startup get_string:\"player\"
+50 0,0 | +Right | checksum=0000000000001111/0000000000abcdef/99/0000000000000007
+50 320,240.5 | +MouseLeft Right | show_question:1 seed=99 checksum=0000000000002222/0000000000abcdef/99/0000000000000007
+50 320,240.5 | -Right -MouseLeft WheelUp | randomize:5 show_message time=1600000000100000000 checksum=0000000000003333/0000000000abcdef/99/0000000000000007
+";
+    const V0_FILE: &[u8] = include_bytes!("../../../tests/fixtures/replay-v0.gmtas");
+    const V1_FILE: &[u8] = include_bytes!("../../../tests/fixtures/replay-v1.gmtas");
+
+    #[test]
+    fn old_versions_still_load() {
+        assert_eq!(read(&mut &V0_FILE[..]).unwrap().to_text(), V0);
+        assert_eq!(read(&mut &V1_FILE[..]).unwrap().to_text(), V1);
+    }
+
+    // if this fails, the layout's changed, so VERSION needs bumping
+    #[test]
+    fn current_version_is_unchanged() {
+        let mut bytes = Vec::new();
+        write(&mut bytes, &Replay::from_text(V1).unwrap()).unwrap();
+        assert_eq!(bytes, V1_FILE);
+
+        bytes[MAGIC.len()] += 1;
+        assert!(read(&mut bytes.as_slice()).unwrap_err().contains("newer than this emulator"));
+    }
+}
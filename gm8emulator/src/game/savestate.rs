@@ -1,7 +1,7 @@
 use crate::{
     game::{
         draw,
-        external::{DefineInfo, External},
+        external::{self, DefineInfo, External},
         includedfile::IncludedFile,
         model::Model,
         particle,
@@ -10,9 +10,16 @@ use crate::{
         string::RCStr,
         surface::Surface,
         transition::UserTransition,
-        Assets, Game, Replay, Version,
+        replay, Assets, Game, PlayType, Replay, Version,
+    },
+    gml::{
+        cd::CdDrive,
+        ds,
+        file::{self, MemoryFile, Vfs},
+        mci,
+        rand::Random,
+        Compiler,
     },
-    gml::{ds, rand::Random, Compiler},
     handleman::HandleList,
     input::InputManager,
     instance::DummyFieldHolder,
@@ -24,10 +31,20 @@ use serde::{Deserialize, Serialize};
 use shared::types::{Colour, ID};
 use std::{
     cell::RefCell,
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
+    path::PathBuf,
     rc::Rc,
 };
 
+mod versions;
+
+const MAGIC: [u8; 8] = *b"GM8EMSAV";
+
+/// The version savestates get saved as. Bump this and add an upgrade from the old layout to the versions module
+/// whenever SaveState or anything in it changes. The replay is stored first in its own versioned format,
+/// so that can be got back out even without an upgrade.
+pub const VERSION: u32 = 1;
+
 /// Represents a savestate. Very similar to the Game struct, but without things which aren't serialized.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SaveState {
@@ -48,6 +65,7 @@ pub struct SaveState {
     pub vsync: bool,
 
     pub externals: Vec<Option<DefineInfo>>,
+    pub external_state: external::State,
     pub surface_fix: bool,
 
     pub view_current: usize,
@@ -124,6 +142,16 @@ pub struct SaveState {
     pub program_directory: RCStr,
     pub included_files: Vec<IncludedFile>,
     pub gm_version: Version,
+
+    // only files opened through the overlay can be saved, but that's all of them when there's a replay
+    pub vfs: Vfs,
+    pub text_files: Vec<Option<MemoryFile>>,
+    pub binary_files: Vec<Option<MemoryFile>>,
+    pub open_file: Option<MemoryFile>,
+    pub open_ini: Option<(Vec<u8>, RCStr)>,
+    pub file_finder: Option<VecDeque<PathBuf>>,
+    pub cd: CdDrive,
+    pub mci: mci::Devices,
     pub spoofed_time_nanos: Option<u128>,
 
     scaling: Scaling,
@@ -132,18 +160,34 @@ pub struct SaveState {
     window_width: u32,
     window_height: u32,
 
+    #[serde(skip)] // saved separately, see to_bytes()
     replay: Replay,
     screenshot: Box<[u8]>,
     zbuffer: Box<[f32]>,
 }
 
 impl SaveState {
-    pub fn from(game: &Game, replay: Replay) -> Self {
+    /// Fails if the game has files open on disk, which only happens when nothing's being recorded.
+    pub fn from(game: &Game, replay: Replay) -> Result<Self, String> {
+        let open_ini = match game.open_ini.as_ref() {
+            Some((ini, path)) => {
+                let mut data = Vec::new();
+                ini.write_to(&mut data).map_err(|e| format!("couldn't save the open ini file: {}", e))?;
+                Some((data, path.clone()))
+            },
+            None => None,
+        };
+        let text_files = memory_files(game.text_files.iter(), file::TextHandle::as_memory)?;
+        let binary_files = memory_files(game.binary_files.iter(), file::BinaryHandle::as_memory)?;
+        let open_file = match game.open_file.as_ref() {
+            Some(f) => Some(f.as_memory().cloned().ok_or_else(on_disk)?),
+            None => None,
+        };
         let (window_width, window_height) = game.window.get_inner_size();
         let screenshot = game.renderer.get_pixels(0, 0, game.unscaled_width as _, game.unscaled_height as _);
         let zbuffer = game.renderer.dump_zbuffer();
 
-        Self {
+        Ok(Self {
             compiler: game.compiler.clone(),
             rand: game.rand.clone(),
             input_manager: game.input_manager.clone(),
@@ -159,6 +203,7 @@ impl SaveState {
             sprite_count: game.renderer.get_sprite_count(),
             vsync: game.renderer.get_vsync(),
             externals: game.externals.iter().map(|e| e.as_ref().map(|e| e.info.clone())).collect(),
+            external_state: game.external_state.clone(),
             surface_fix: game.surface_fix.clone(),
             view_current: game.view_current,
             last_instance_id: game.last_instance_id.clone(),
@@ -224,6 +269,14 @@ impl SaveState {
             program_directory: game.program_directory.clone(),
             included_files: game.included_files.clone(),
             gm_version: game.gm_version.clone(),
+            vfs: game.vfs.clone(),
+            text_files,
+            binary_files,
+            open_file,
+            open_ini,
+            file_finder: game.file_finder.clone(),
+            cd: game.cd.clone(),
+            mci: game.mci.clone(),
             spoofed_time_nanos: game.spoofed_time_nanos,
             scaling: game.scaling,
             unscaled_width: game.unscaled_width,
@@ -233,10 +286,20 @@ impl SaveState {
             replay,
             screenshot,
             zbuffer,
-        }
+        })
     }
 
-    pub fn load_into(self, game: &mut Game) -> Replay {
+    /// Fails without changing anything if the savestate turns out to be broken.
+    pub fn load_into(self, game: &mut Game) -> Result<Replay, String> {
+        let open_ini = match self.open_ini {
+            Some((data, path)) => Some((
+                ini::Ini::read_from(&mut data.as_slice())
+                    .map_err(|e| format!("couldn't load the open ini file from the savestate: {}", e))?,
+                path,
+            )),
+            None => None,
+        };
+
         if game.window.get_inner_size() != (self.window_width, self.window_height) {
             game.window.resize(self.window_width, self.window_height);
         }
@@ -268,19 +331,18 @@ impl SaveState {
         game.renderer.set_vsync(self.vsync);
 
         let mut externals = self.externals;
-        // we're always gonna be recording if we're loading savestates so disable sound
+        // this has to be restored first, so emulated DLLs get found already loaded rather than starting over
+        game.external_state = self.external_state;
+        let encoding = match game.gm_version {
+            Version::GameMaker8_0 => game.encoding,
+            Version::GameMaker8_1 => encoding_rs::UTF_8,
+        };
+        let context = external::Context { vfs: &game.vfs, path_resolver: &game.path_resolver, now: game.media_clock() };
+        let (external_state, disable_sound) = (&mut game.external_state, game.play_type != PlayType::Normal);
         game.externals = externals
             .drain(..)
-            .map(|i| {
-                i.map(|i| {
-                    External::new(i, true, match game.gm_version {
-                        Version::GameMaker8_0 => game.encoding,
-                        Version::GameMaker8_1 => encoding_rs::UTF_8,
-                    })
-                    .unwrap()
-                })
-            })
-            .collect();
+            .map(|i| i.map(|i| External::new(i, disable_sound, encoding, external_state, &context)).transpose())
+            .collect::<Result<_, _>>()?;
 
         game.surface_fix = self.surface_fix;
 
@@ -354,14 +416,189 @@ impl SaveState {
         game.program_directory = self.program_directory;
         game.included_files = self.included_files;
         game.gm_version = self.gm_version;
+        game.vfs.restore(self.vfs);
+        game.text_files = self.text_files.into_iter().map(|f| f.map(file::TextHandle::Memory)).collect();
+        game.binary_files = self.binary_files.into_iter().map(|f| f.map(file::BinaryHandle::Memory)).collect();
+        game.open_file = self.open_file.map(file::TextHandle::Memory);
+        game.open_ini = open_ini;
+        game.file_finder = self.file_finder;
+        game.cd.restore(self.cd);
+        game.mci = self.mci;
         game.spoofed_time_nanos = self.spoofed_time_nanos;
         game.scaling = self.scaling;
         game.unscaled_width = self.unscaled_width;
         game.unscaled_height = self.unscaled_height;
-        self.replay
+        Ok(self.replay)
+    }
+
+    /// Saves the header, then the replay, then everything else.
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        let mut bytes = MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes, &VERSION)?;
+        replay::versions::write(&mut bytes, &self.replay)?;
+        bincode::serialize_into(&mut bytes, self)?;
+        Ok(bytes)
+    }
+
+    /// Reads a savestate saved by any version, upgrading it if it's old.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let (version, mut rest) = Self::header(bytes)?;
+        if version == 0 {
+            // the replay was in the middle of everything else back then
+            return Self::read_v0(rest).map(Into::into)
+        }
+        let replay = replay::versions::read(&mut rest)?;
+        if version != VERSION {
+            return Err(format!(
+                "savestate is format version {}, which is newer than this emulator ({}), so it can't be loaded \
+                (its replay can still be played back with -f)",
+                version, VERSION
+            ))
+        }
+        let mut state: Self = bincode::deserialize(rest).map_err(|e| format!("couldn't read savestate: {}", e))?;
+        state.replay = replay;
+        Ok(state)
+    }
+
+    /// Gets just the replay out of a savestate, which works even if the rest of it is from a newer version.
+    pub fn read_replay(bytes: &[u8]) -> Result<Replay, String> {
+        match Self::header(bytes)? {
+            (0, rest) => Self::read_v0(rest).map(versions::SaveStateV0::into_replay),
+            (_, mut rest) => replay::versions::read(&mut rest),
+        }
+    }
+
+    fn read_v0(bytes: &[u8]) -> Result<versions::SaveStateV0, String> {
+        bincode::deserialize(bytes).map_err(|e| format!("couldn't read version 0 savestate: {}", e))
+    }
+
+    fn header(bytes: &[u8]) -> Result<(u32, &[u8]), String> {
+        match bytes.strip_prefix(&MAGIC[..]) {
+            Some(mut rest) => {
+                let version = bincode::deserialize_from(&mut rest)
+                    .map_err(|e| format!("couldn't read savestate header: {}", e))?;
+                Ok((version, rest))
+            },
+            None => Ok((0, bytes)),
+        }
+    }
+}
+
+// files are only on disk when nothing's being recorded, so there's no overlay to put them back into
+fn on_disk() -> String {
+    "the game has files open on disk, which can't go in savestates".into()
+}
+
+fn memory_files<'a, T: 'a>(
+    handles: impl Iterator<Item = &'a Option<T>>,
+    as_memory: impl Fn(&T) -> Option<&MemoryFile>,
+) -> Result<Vec<Option<MemoryFile>>, String> {
+    handles
+        .map(|f| match f {
+            Some(f) => as_memory(f).cloned().map(Some).ok_or_else(on_disk),
+            None => Ok(None),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game::GetAsset,
+        gml::{self, Value},
+        testing::test_game_sized,
+    };
+    use shared::input::Key;
+
+    // the fixtures are a 16x16 room after this replay, saved by the emulator back when each version was current
+    // these should never change: give each new version its own fixture instead
+    const REPLAY: &str = "gm8emulator replay\nstart_time 0\nstart_seed 5\n30 0,0 | +A\n30 4,8 | A | seed=12\n";
+    const V0_FILE: &[u8] = include_bytes!("../../tests/fixtures/savestate-v0.bin");
+    const V1_FILE: &[u8] = include_bytes!("../../tests/fixtures/savestate-v1.bin");
+
+    fn eval(game: &mut Game, instance: usize, expression: &str) -> String {
+        let expression = game.compiler.compile_expression(expression.as_bytes()).unwrap();
+        match game.eval(&expression, &mut gml::Context::with_single_instance(instance)).unwrap() {
+            Value::Str(s) => String::from_utf8_lossy(s.as_ref()).into(),
+            other => panic!("expected a string, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn old_versions_still_load() {
+        for fixture in &[V0_FILE, V1_FILE] {
+            let mut game = test_game_sized(16, 16);
+            let replay = SaveState::from_bytes(fixture).unwrap().load_into(&mut game).unwrap();
+            assert_eq!(replay.to_text(), REPLAY);
+            assert!(game.input_manager.key_check(Key::A as usize));
+            assert_eq!(game.input_manager.mouse_get_location(), (4.0, 8.0));
+            assert_eq!(SaveState::read_replay(fixture).unwrap().to_text(), REPLAY);
+        }
+    }
+
+    #[test]
+    fn newer_versions_only_give_their_replay() {
+        let mut newer = V1_FILE.to_vec();
+        newer[MAGIC.len()] += 1;
+        assert!(SaveState::from_bytes(&newer).err().unwrap().contains("version 2, which is newer"));
+        assert_eq!(SaveState::read_replay(&newer).unwrap().to_text(), REPLAY);
     }
 
-    pub fn into_replay(self) -> Replay {
-        self.replay
+    // version 0 has a script, an object, a timeline, a trigger and creation code, all compiled the old way
+    #[test]
+    fn old_code_still_runs() {
+        let mut game = test_game_sized(16, 16);
+        SaveState::from_bytes(V0_FILE).unwrap().load_into(&mut game).unwrap();
+        let instance = game.room.instance_list.get_by_instid(100001).unwrap();
+        let mut context = gml::Context::with_single_instance(instance);
+
+        // the create event calls the script, which uses a constant, then moves with arguments
+        let create = game.assets.objects.get_asset(0).unwrap().events[gml::ev::CREATE][&0].clone();
+        game.execute_tree(create, instance, instance, gml::ev::CREATE, 0, 0).unwrap();
+        let room = game.assets.rooms.get_asset(0).unwrap().clone();
+        let creation_code = room.creation_code.compile(&mut game.compiler).unwrap();
+        game.execute(&creation_code, &mut context).unwrap();
+        let moment = game.assets.timelines.get_asset(0).unwrap().moments.borrow()[&10].clone();
+        game.execute_tree(moment, instance, instance, 0, 0, 0).unwrap();
+        let expression = "string(made) + \" \" + string(x) + \",\" + string(y) + \" \" + string(global.moment)";
+        assert_eq!(eval(&mut game, instance, expression), "11 5,10 1");
+
+        let trigger = game.assets.triggers.get_asset(0).unwrap().condition.clone();
+        game.execute(&trigger, &mut context).unwrap();
+        assert!(context.return_value.is_truthy());
+
+        // the instance's creation code didn't compile back then, so it fails when it runs
+        let broken = room.instances[0].creation.compile(&mut game.compiler).unwrap();
+        match game.execute(&broken, &mut context).unwrap_err().innermost() {
+            gml::Error::CompileError(e) => assert_eq!(e, "unexpected end of file"),
+            e => panic!("expected a compile error, got {}", e),
+        }
+    }
+
+    #[test]
+    fn open_files_are_saved_or_refused() {
+        let path = std::env::temp_dir().join(format!("gm8emulator-test-savestate-{}.txt", std::process::id()));
+        let mut game = test_game_sized(16, 16);
+        game.vfs = Vfs::new(true);
+        let mut handle = game.vfs.open_text(&path, file::AccessMode::Write).unwrap();
+        handle.write_string(b"in memory").unwrap();
+        game.text_files = std::iter::once(Some(handle)).collect();
+        game.open_ini = Some((ini::Ini::load_from_str("[a]\nb=c\n").unwrap(), "test.ini".into()));
+        let bytes = SaveState::from(&game, Replay::new(0, 0)).unwrap().to_bytes().unwrap();
+
+        let mut loaded = test_game_sized(16, 16);
+        SaveState::from_bytes(&bytes).unwrap().load_into(&mut loaded).unwrap();
+        let file = loaded.text_files.get_mut(0).unwrap();
+        file.flush(&mut loaded.vfs).unwrap();
+        assert_eq!(loaded.vfs.read(&path).unwrap(), b"in memory");
+        assert_eq!(loaded.open_ini.unwrap().0.get_from(Some("a"), "b"), Some("c"));
+        assert!(!path.exists());
+
+        // without an overlay, files are on disk and can't be put back, so there's no savestate at all
+        game.vfs = Vfs::new(false);
+        game.binary_files = std::iter::once(file::BinaryHandle::open(&path, file::AccessMode::Write).ok()).collect();
+        assert!(SaveState::from(&game, Replay::new(0, 0)).err().unwrap().contains("on disk"));
+        std::fs::remove_file(&path).unwrap();
     }
 }
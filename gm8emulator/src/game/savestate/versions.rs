@@ -0,0 +1,768 @@
+//! Savestates are a dump of the emulator's insides, so each old version keeps a copy of how everything that's changed
+//! since was laid out, and gets upgraded to the current layout. Savestates from before there was a header at all are
+//! version 0.
+
+use super::SaveState;
+use crate::{
+    action::{self, Action, Tree},
+    asset::{self, room::CreationCode, trigger::TriggerTime, Background, Font, Path, Sprite},
+    game::{
+        draw,
+        external::DefineInfo,
+        includedfile::IncludedFile,
+        model::Model,
+        particle,
+        pathfinding::PotentialStepSettings,
+        replay::versions::ReplayV0,
+        string::RCStr,
+        surface::Surface,
+        transition::UserTransition,
+        view::View,
+        Assets, Replay, RoomState, Version,
+    },
+    gml::{
+        bytecode::Program,
+        ds,
+        rand::Random,
+        runtime::{
+            ArrayAccessor, BinaryOperator, Error, FieldAccessor, InstanceIdentifier, Instruction, Node, ReturnType,
+            UnaryOperator, VariableAccessor,
+        },
+        Compiler, InstanceVariable, Value,
+    },
+    handleman::HandleList,
+    input::InputManager,
+    instance::DummyFieldHolder,
+    math::Real,
+    tile::Tile,
+};
+use gml_parser::token::Operator;
+use gmio::render::{BlendType, Fog, PrimitiveBuilder, SavedTexture, Scaling};
+use indexmap::IndexMap;
+use serde::Deserialize;
+use shared::types::{Colour, ID};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    rc::Rc,
+};
+
+// version 0 kept the replay in with everything else, and code was compiled to instructions rather than bytecode
+// none of the files, external function state or CD drive got saved
+// Rc<RefCell<T>> and Box<T> are saved the same as T, so those are left out here
+#[derive(Deserialize)]
+pub struct SaveStateV0 {
+    compiler: CompilerV0,
+    rand: Random,
+    input_manager: InputManager,
+    assets: AssetsV0,
+    event_holders: [IndexMap<u32, Rc<RefCell<Vec<ID>>>>; 12],
+    custom_draw_objects: HashSet<ID>,
+
+    background_colour: Colour,
+    textures: Vec<Option<SavedTexture>>,
+    alpha_blending: bool,
+    blend_mode: (BlendType, BlendType),
+    interpolate_pixels: bool,
+    texture_repeat: bool,
+    sprite_count: i32,
+    vsync: bool,
+
+    externals: Vec<Option<DefineInfo>>,
+    surface_fix: bool,
+
+    view_current: usize,
+
+    last_instance_id: ID,
+    last_tile_id: ID,
+
+    particles: particle::Manager,
+
+    room: RoomState,
+    stored_rooms: Vec<RoomState>,
+    room_order: Box<[i32]>,
+    user_transitions: HashMap<i32, UserTransition>,
+
+    globals: DummyFieldHolder,
+    globalvars: HashSet<usize>,
+    game_start: bool,
+
+    stacks: HandleList<ds::Stack>,
+    queues: HandleList<ds::Queue>,
+    lists: HandleList<ds::List>,
+    maps: HandleList<ds::Map>,
+    priority_queues: HandleList<ds::Priority>,
+    grids: HandleList<ds::Grid>,
+    ds_precision: Real,
+
+    draw_font_id: ID,
+    draw_colour: Colour,
+    draw_alpha: Real,
+    draw_halign: draw::Halign,
+    draw_valign: draw::Valign,
+    using_3d: bool,
+    depth: f32,
+    depth_test: bool,
+    write_depth: bool,
+    culling: bool,
+    perspective: bool,
+    fog: Option<Fog>,
+    gouraud: bool,
+    surfaces: Vec<Option<Surface>>,
+    surface_target: Option<i32>,
+    model_matrix: [f32; 16],
+    models: Vec<Option<Model>>,
+    model_matrix_stack: Vec<[f32; 16]>,
+    auto_draw: bool,
+    circle_precision: i32,
+    primitive_2d: PrimitiveBuilder,
+    primitive_3d: PrimitiveBuilder,
+    zbuf_trashed: bool,
+
+    uninit_fields_are_zero: bool,
+    uninit_args_are_zero: bool,
+
+    potential_step_settings: PotentialStepSettings,
+
+    transition_kind: i32,
+    transition_steps: i32,
+    cursor_sprite: i32,
+    cursor_sprite_frame: u32,
+    score: i32,
+    score_capt: RCStr,
+    score_capt_d: bool,
+    has_set_show_score: bool,
+    lives: i32,
+    lives_capt: RCStr,
+    lives_capt_d: bool,
+    health: Real,
+    health_capt: RCStr,
+    health_capt_d: bool,
+    error_occurred: bool,
+    error_last: RCStr,
+
+    game_id: i32,
+    program_directory: RCStr,
+    included_files: Vec<IncludedFile>,
+    gm_version: Version,
+    spoofed_time_nanos: Option<u128>,
+
+    scaling: Scaling,
+    unscaled_width: u32,
+    unscaled_height: u32,
+    window_width: u32,
+    window_height: u32,
+
+    replay: ReplayV0,
+    screenshot: Box<[u8]>,
+    zbuffer: Box<[f32]>,
+}
+
+impl SaveStateV0 {
+    pub fn into_replay(self) -> Replay {
+        self.replay.into()
+    }
+}
+
+impl From<SaveStateV0> for SaveState {
+    fn from(old: SaveStateV0) -> Self {
+        Self {
+            compiler: old.compiler.into(),
+            rand: old.rand,
+            input_manager: old.input_manager,
+            assets: old.assets.into(),
+            event_holders: old.event_holders,
+            custom_draw_objects: old.custom_draw_objects,
+            background_colour: old.background_colour,
+            textures: old.textures,
+            alpha_blending: old.alpha_blending,
+            blend_mode: old.blend_mode,
+            interpolate_pixels: old.interpolate_pixels,
+            texture_repeat: old.texture_repeat,
+            sprite_count: old.sprite_count,
+            vsync: old.vsync,
+            externals: old.externals,
+            external_state: Default::default(),
+            surface_fix: old.surface_fix,
+            view_current: old.view_current,
+            last_instance_id: old.last_instance_id,
+            last_tile_id: old.last_tile_id,
+            particles: old.particles,
+            room: old.room,
+            stored_rooms: old.stored_rooms,
+            room_order: old.room_order,
+            user_transitions: old.user_transitions,
+            globals: old.globals,
+            globalvars: old.globalvars,
+            game_start: old.game_start,
+            stacks: old.stacks,
+            queues: old.queues,
+            lists: old.lists,
+            maps: old.maps,
+            priority_queues: old.priority_queues,
+            grids: old.grids,
+            ds_precision: old.ds_precision,
+            draw_font_id: old.draw_font_id,
+            draw_colour: old.draw_colour,
+            draw_alpha: old.draw_alpha,
+            draw_halign: old.draw_halign,
+            draw_valign: old.draw_valign,
+            using_3d: old.using_3d,
+            depth: old.depth,
+            depth_test: old.depth_test,
+            write_depth: old.write_depth,
+            culling: old.culling,
+            perspective: old.perspective,
+            fog: old.fog,
+            gouraud: old.gouraud,
+            surfaces: old.surfaces,
+            surface_target: old.surface_target,
+            model_matrix: old.model_matrix,
+            models: old.models,
+            model_matrix_stack: old.model_matrix_stack,
+            auto_draw: old.auto_draw,
+            circle_precision: old.circle_precision,
+            primitive_2d: old.primitive_2d,
+            primitive_3d: old.primitive_3d,
+            zbuf_trashed: old.zbuf_trashed,
+            uninit_fields_are_zero: old.uninit_fields_are_zero,
+            uninit_args_are_zero: old.uninit_args_are_zero,
+            potential_step_settings: old.potential_step_settings,
+            transition_kind: old.transition_kind,
+            transition_steps: old.transition_steps,
+            cursor_sprite: old.cursor_sprite,
+            cursor_sprite_frame: old.cursor_sprite_frame,
+            score: old.score,
+            score_capt: old.score_capt,
+            score_capt_d: old.score_capt_d,
+            has_set_show_score: old.has_set_show_score,
+            lives: old.lives,
+            lives_capt: old.lives_capt,
+            lives_capt_d: old.lives_capt_d,
+            health: old.health,
+            health_capt: old.health_capt,
+            health_capt_d: old.health_capt_d,
+            error_occurred: old.error_occurred,
+            error_last: old.error_last,
+            game_id: old.game_id,
+            program_directory: old.program_directory,
+            included_files: old.included_files,
+            gm_version: old.gm_version,
+            vfs: Default::default(),
+            text_files: Vec::new(),
+            binary_files: Vec::new(),
+            open_file: None,
+            open_ini: None,
+            file_finder: None,
+            cd: Default::default(),
+            mci: Default::default(),
+            spoofed_time_nanos: old.spoofed_time_nanos,
+            scaling: old.scaling,
+            unscaled_width: old.unscaled_width,
+            unscaled_height: old.unscaled_height,
+            window_width: old.window_width,
+            window_height: old.window_height,
+            replay: old.replay.into(),
+            screenshot: old.screenshot,
+            zbuffer: old.zbuffer,
+        }
+    }
+}
+
+// the generation counter and caches are new, everything else is the same
+#[derive(Deserialize)]
+struct CompilerV0 {
+    constants: HashMap<Box<[u8]>, Value>,
+    user_constant_names: HashMap<Box<[u8]>, usize>,
+    script_names: HashMap<Box<[u8]>, usize>,
+    extension_fn_names: HashMap<Box<[u8]>, usize>,
+    fields: Vec<Box<[u8]>>,
+}
+
+impl From<CompilerV0> for Compiler {
+    fn from(old: CompilerV0) -> Self {
+        let mut compiler = Compiler::new();
+        // fields go in the same order, so they get the same IDs the compiled code uses
+        for name in old.fields.iter() {
+            compiler.get_field_id(name);
+        }
+        for (name, value) in old.constants {
+            compiler.register_constant(name, value.into());
+        }
+        for (name, index) in old.user_constant_names {
+            compiler.register_user_constant(name, index);
+        }
+        for (name, index) in old.script_names {
+            compiler.register_script(name, index);
+        }
+        for (name, index) in old.extension_fn_names {
+            compiler.register_extension_function(name, index);
+        }
+        compiler
+    }
+}
+
+#[derive(Deserialize)]
+struct AssetsV0 {
+    backgrounds: Vec<Option<Box<Background>>>,
+    fonts: Vec<Option<Box<Font>>>,
+    objects: Vec<Option<ObjectV0>>,
+    paths: Vec<Option<Box<Path>>>,
+    rooms: Vec<Option<RoomV0>>,
+    scripts: Vec<Option<ScriptV0>>,
+    sprites: Vec<Option<Box<Sprite>>>,
+    timelines: Vec<Option<TimelineV0>>,
+    triggers: Vec<Option<TriggerV0>>,
+}
+
+impl From<AssetsV0> for Assets {
+    fn from(old: AssetsV0) -> Self {
+        fn upgrade<T, U: From<T>>(assets: Vec<Option<T>>) -> Vec<Option<Box<U>>> {
+            assets.into_iter().map(|a| a.map(|a| Box::new(a.into()))).collect()
+        }
+        Self {
+            backgrounds: old.backgrounds,
+            fonts: old.fonts,
+            objects: upgrade(old.objects),
+            paths: old.paths,
+            rooms: upgrade(old.rooms),
+            scripts: upgrade(old.scripts),
+            sprites: old.sprites,
+            timelines: upgrade(old.timelines),
+            triggers: upgrade(old.triggers),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ObjectV0 {
+    name: RCStr,
+    solid: bool,
+    visible: bool,
+    persistent: bool,
+    depth: i32,
+    sprite_index: i32,
+    mask_index: i32,
+    parent_index: i32,
+    events: [HashMap<u32, TreeV0>; 12],
+    children: Rc<RefCell<HashSet<i32>>>,
+}
+
+impl From<ObjectV0> for asset::Object {
+    fn from(old: ObjectV0) -> Self {
+        let mut events: [HashMap<u32, Rc<RefCell<Tree>>>; 12] = Default::default();
+        for (map, old_map) in events.iter_mut().zip(old.events.iter()) {
+            *map = old_map.iter().map(|(sub, tree)| (*sub, Rc::new(RefCell::new(tree.into())))).collect();
+        }
+        Self {
+            name: old.name,
+            solid: old.solid,
+            visible: old.visible,
+            persistent: old.persistent,
+            depth: old.depth,
+            sprite_index: old.sprite_index,
+            mask_index: old.mask_index,
+            parent_index: old.parent_index,
+            events,
+            children: old.children,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TimelineV0 {
+    name: RCStr,
+    moments: BTreeMap<i32, TreeV0>,
+}
+
+impl From<TimelineV0> for asset::Timeline {
+    fn from(old: TimelineV0) -> Self {
+        let moments = old.moments.iter().map(|(moment, tree)| (*moment, Rc::new(RefCell::new(tree.into())))).collect();
+        Self { name: old.name, moments: Rc::new(RefCell::new(moments)) }
+    }
+}
+
+#[derive(Deserialize)]
+struct ScriptV0 {
+    name: RCStr,
+    source: RCStr,
+    compiled: Box<[InstructionV0]>,
+}
+
+impl From<ScriptV0> for asset::Script {
+    fn from(old: ScriptV0) -> Self {
+        Self { name: old.name, source: old.source, compiled: Rc::new(assemble(&old.compiled)) }
+    }
+}
+
+#[derive(Deserialize)]
+struct TriggerV0 {
+    name: RCStr,
+    condition: Box<[InstructionV0]>,
+    moment: TriggerTime,
+}
+
+impl From<TriggerV0> for asset::Trigger {
+    fn from(old: TriggerV0) -> Self {
+        Self { name: old.name, condition: Rc::new(assemble(&old.condition)), moment: old.moment }
+    }
+}
+
+// creation code was compiled when the game started, and kept the compiler error if that failed
+#[derive(Deserialize)]
+struct RoomV0 {
+    name: RCStr,
+    caption: RCStr,
+    width: u32,
+    height: u32,
+    speed: u32,
+    persistent: bool,
+    bg_colour: Colour,
+    clear_screen: bool,
+    creation_code: Result<Box<[InstructionV0]>, String>,
+    backgrounds: Vec<crate::game::Background>,
+    views_enabled: bool,
+    views: Vec<View>,
+    instances: Vec<InstanceV0>,
+    tiles: Vec<Tile>,
+}
+
+#[derive(Deserialize)]
+struct InstanceV0 {
+    x: i32,
+    y: i32,
+    object: i32,
+    id: ID,
+    creation: Result<Box<[InstructionV0]>, String>,
+}
+
+impl From<RoomV0> for asset::Room {
+    fn from(old: RoomV0) -> Self {
+        let instances = old
+            .instances
+            .iter()
+            .map(|i| asset::room::Instance {
+                x: i.x,
+                y: i.y,
+                object: i.object,
+                id: i.id,
+                creation: creation_code(&i.creation),
+            })
+            .collect();
+        Self {
+            name: old.name,
+            caption: old.caption,
+            width: old.width,
+            height: old.height,
+            speed: old.speed,
+            persistent: old.persistent,
+            bg_colour: old.bg_colour,
+            clear_screen: old.clear_screen,
+            creation_code: creation_code(&old.creation_code),
+            backgrounds: old.backgrounds,
+            views_enabled: old.views_enabled,
+            views: old.views,
+            instances,
+            tiles: old.tiles,
+        }
+    }
+}
+
+fn creation_code(old: &Result<Box<[InstructionV0]>, String>) -> CreationCode {
+    match old {
+        Ok(instructions) => CreationCode::compiled(Rc::new(assemble(instructions))),
+        // it still fails when it runs, the same as if it were compiled now
+        Err(e) => CreationCode::compiled(Rc::new(Program::from_instructions(&[Instruction::RuntimeError {
+            error: Error::CompileError(e.clone()),
+        }]))),
+    }
+}
+
+fn assemble(instructions: &[InstructionV0]) -> Program {
+    Program::from_instructions(&upgrade_all(instructions))
+}
+
+fn upgrade_all<T, U: for<'a> From<&'a T>>(old: &[T]) -> Box<[U]> {
+    old.iter().map(U::from).collect()
+}
+
+// actions had their arguments and code compiled when the game started
+#[derive(Deserialize)]
+struct TreeV0(Vec<ActionV0>);
+
+impl From<&TreeV0> for Tree {
+    fn from(old: &TreeV0) -> Self {
+        Tree::from_actions(old.0.iter().map(Action::from).collect())
+    }
+}
+
+#[derive(Deserialize)]
+struct ActionV0 {
+    index: usize,
+    target: Option<i32>,
+    relative: bool,
+    invert_condition: bool,
+    body: BodyV0,
+}
+
+// the actions run when a condition passes and when it fails
+type IfElseV0 = (Box<[ActionV0]>, Box<[ActionV0]>);
+
+#[derive(Deserialize)]
+enum BodyV0 {
+    Normal { args: Box<[NodeV0]>, body: GmlBodyV0, if_else: Option<IfElseV0> },
+    Repeat { count: NodeV0, body: Box<[ActionV0]> },
+    Exit,
+}
+
+#[derive(Deserialize)]
+enum GmlBodyV0 {
+    Function(usize),
+    Code(Box<[InstructionV0]>),
+}
+
+impl From<&ActionV0> for Action {
+    fn from(old: &ActionV0) -> Self {
+        let body = match &old.body {
+            BodyV0::Normal { args, body, if_else } => action::Body::Normal {
+                args: args.iter().map(|arg| Program::from_node(&arg.into())).collect(),
+                body: match body {
+                    GmlBodyV0::Function(id) => action::GmlBody::Function(*id),
+                    GmlBodyV0::Code(code) => action::GmlBody::Code(Rc::new(assemble(code))),
+                },
+                if_else: if_else.as_ref().map(|(if_body, else_body)| (upgrade_all(if_body), upgrade_all(else_body))),
+            },
+            BodyV0::Repeat { count, body } => {
+                action::Body::Repeat { count: Program::from_node(&count.into()), body: upgrade_all(body) }
+            },
+            BodyV0::Exit => action::Body::Exit,
+        };
+        Self {
+            index: old.index,
+            target: old.target,
+            relative: old.relative,
+            invert_condition: old.invert_condition,
+            body,
+        }
+    }
+}
+
+// line numbers and some errors are new, so errors after where they went in are numbered differently
+#[derive(Deserialize)]
+enum InstructionV0 {
+    SetField { accessor: FieldAccessorV0, value: NodeV0 },
+    SetVariable { accessor: VariableAccessorV0, value: NodeV0 },
+    EvalExpression { node: NodeV0 },
+    IfElse { cond: NodeV0, if_body: Box<[InstructionV0]>, else_body: Box<[InstructionV0]> },
+    LoopUntil { cond: NodeV0, body: Box<[InstructionV0]> },
+    LoopWhile { cond: NodeV0, body: Box<[InstructionV0]> },
+    LoopFor { cond: NodeV0, body: Box<[InstructionV0]>, step: Box<[InstructionV0]> },
+    Return { return_type: ReturnType },
+    Repeat { count: NodeV0, body: Box<[InstructionV0]> },
+    SetReturnValue { value: NodeV0 },
+    Switch { input: NodeV0, cases: Box<[(NodeV0, usize)]>, default: Option<usize>, body: Box<[InstructionV0]> },
+    With { target: NodeV0, body: Box<[InstructionV0]> },
+    GlobalVar { fields: Vec<usize> },
+    RuntimeError { error: ErrorV0 },
+}
+
+impl From<&InstructionV0> for Instruction {
+    fn from(old: &InstructionV0) -> Self {
+        match old {
+            InstructionV0::SetField { accessor, value } => {
+                Self::SetField { accessor: accessor.into(), value: value.into() }
+            },
+            InstructionV0::SetVariable { accessor, value } => {
+                Self::SetVariable { accessor: accessor.into(), value: value.into() }
+            },
+            InstructionV0::EvalExpression { node } => Self::EvalExpression { node: node.into() },
+            InstructionV0::IfElse { cond, if_body, else_body } => {
+                Self::IfElse { cond: cond.into(), if_body: upgrade_all(if_body), else_body: upgrade_all(else_body) }
+            },
+            InstructionV0::LoopUntil { cond, body } => Self::LoopUntil { cond: cond.into(), body: upgrade_all(body) },
+            InstructionV0::LoopWhile { cond, body } => Self::LoopWhile { cond: cond.into(), body: upgrade_all(body) },
+            InstructionV0::LoopFor { cond, body, step } => {
+                Self::LoopFor { cond: cond.into(), body: upgrade_all(body), step: upgrade_all(step) }
+            },
+            InstructionV0::Return { return_type } => Self::Return { return_type: *return_type },
+            InstructionV0::Repeat { count, body } => Self::Repeat { count: count.into(), body: upgrade_all(body) },
+            InstructionV0::SetReturnValue { value } => Self::SetReturnValue { value: value.into() },
+            InstructionV0::Switch { input, cases, default, body } => Self::Switch {
+                input: input.into(),
+                cases: cases.iter().map(|(node, pos)| (node.into(), *pos)).collect(),
+                default: *default,
+                body: upgrade_all(body),
+            },
+            InstructionV0::With { target, body } => Self::With { target: target.into(), body: upgrade_all(body) },
+            InstructionV0::GlobalVar { fields } => Self::GlobalVar { fields: fields.clone() },
+            InstructionV0::RuntimeError { error } => Self::RuntimeError { error: upgrade_error(error) },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+enum NodeV0 {
+    Literal { value: Value },
+    Constant { constant_id: usize },
+    Function { args: Box<[NodeV0]>, function_id: usize },
+    Script { args: Box<[NodeV0]>, script_id: usize },
+    ExtensionFunction { args: Box<[NodeV0]>, id: usize },
+    Field { accessor: FieldAccessorV0 },
+    Variable { accessor: VariableAccessorV0 },
+    Binary { left: Box<NodeV0>, right: Box<NodeV0>, operator: BinaryOperator },
+    Unary { child: Box<NodeV0>, operator: UnaryOperator },
+    RuntimeError { error: ErrorV0 },
+}
+
+impl From<&NodeV0> for Node {
+    fn from(old: &NodeV0) -> Self {
+        match old {
+            NodeV0::Literal { value } => Self::Literal { value: value.clone() },
+            NodeV0::Constant { constant_id } => Self::Constant { constant_id: *constant_id },
+            NodeV0::Function { args, function_id } => {
+                Self::Function { args: upgrade_all(args), function_id: *function_id }
+            },
+            NodeV0::Script { args, script_id } => Self::Script { args: upgrade_all(args), script_id: *script_id },
+            NodeV0::ExtensionFunction { args, id } => Self::ExtensionFunction { args: upgrade_all(args), id: *id },
+            NodeV0::Field { accessor } => Self::Field { accessor: accessor.into() },
+            NodeV0::Variable { accessor } => Self::Variable { accessor: accessor.into() },
+            NodeV0::Binary { left, right, operator } => Self::Binary {
+                left: Box::new(left.as_ref().into()),
+                right: Box::new(right.as_ref().into()),
+                operator: *operator,
+            },
+            NodeV0::Unary { child, operator } => {
+                Self::Unary { child: Box::new(child.as_ref().into()), operator: *operator }
+            },
+            NodeV0::RuntimeError { error } => Self::RuntimeError { error: upgrade_error(error) },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FieldAccessorV0 {
+    index: usize,
+    array: ArrayAccessorV0,
+    owner: InstanceIdentifierV0,
+}
+
+impl From<&FieldAccessorV0> for FieldAccessor {
+    fn from(old: &FieldAccessorV0) -> Self {
+        Self { index: old.index, array: (&old.array).into(), owner: (&old.owner).into() }
+    }
+}
+
+#[derive(Deserialize)]
+struct VariableAccessorV0 {
+    var: InstanceVariable,
+    array: ArrayAccessorV0,
+    owner: InstanceIdentifierV0,
+}
+
+impl From<&VariableAccessorV0> for VariableAccessor {
+    fn from(old: &VariableAccessorV0) -> Self {
+        Self { var: old.var, array: (&old.array).into(), owner: (&old.owner).into() }
+    }
+}
+
+#[derive(Deserialize)]
+enum ArrayAccessorV0 {
+    None,
+    Single(Box<NodeV0>),
+    Double(Box<NodeV0>, Box<NodeV0>),
+}
+
+impl From<&ArrayAccessorV0> for ArrayAccessor {
+    fn from(old: &ArrayAccessorV0) -> Self {
+        match old {
+            ArrayAccessorV0::None => Self::None,
+            ArrayAccessorV0::Single(index) => Self::Single(Box::new(index.as_ref().into())),
+            ArrayAccessorV0::Double(index1, index2) => {
+                Self::Double(Box::new(index1.as_ref().into()), Box::new(index2.as_ref().into()))
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+enum InstanceIdentifierV0 {
+    Unknown,
+    Own,
+    Other,
+    Global,
+    Local,
+    Expression(Box<NodeV0>),
+}
+
+impl From<&InstanceIdentifierV0> for InstanceIdentifier {
+    fn from(old: &InstanceIdentifierV0) -> Self {
+        match old {
+            InstanceIdentifierV0::Unknown => Self::Unknown,
+            InstanceIdentifierV0::Own => Self::Own,
+            InstanceIdentifierV0::Other => Self::Other,
+            InstanceIdentifierV0::Global => Self::Global,
+            InstanceIdentifierV0::Local => Self::Local,
+            InstanceIdentifierV0::Expression(node) => Self::Expression(Box::new(node.as_ref().into())),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+enum ErrorV0 {
+    EndOfRoomOrder,
+    ExtensionFunctionNotLoaded(usize),
+    InvalidOperandsUnary(Operator, Value),
+    InvalidOperandsBinary(Operator, Value, Value),
+    InvalidUnaryOperator(Operator),
+    InvalidBinaryOperator(Operator),
+    InvalidAssignment(String),
+    InvalidArrayAccessor(String),
+    InvalidArrayIndex(i32),
+    InvalidDeref(String),
+    InvalidIndexLhs(String),
+    InvalidIndex(String),
+    InvalidRoomSpeed(i32),
+    InvalidSwitchBody(String),
+    NonexistentAsset(asset::Type, i32),
+    ReadOnlyVariable(InstanceVariable),
+    UnknownFunction(String),
+    UnexpectedASTExpr(String),
+    UninitializedVariable(String, u32),
+    UninitializedArgument(usize),
+    TooManyArrayDimensions(usize),
+    WrongArgumentCount(usize, usize),
+    FunctionError(String, String),
+    ReplayError(String),
+    BadDirectoryError(String),
+}
+
+// not a From, since that'd make the ? operator ambiguous for anything that isn't annotated
+fn upgrade_error(old: &ErrorV0) -> Error {
+    match old.clone() {
+        ErrorV0::EndOfRoomOrder => Error::EndOfRoomOrder,
+        ErrorV0::ExtensionFunctionNotLoaded(id) => Error::ExtensionFunctionNotLoaded(id),
+        ErrorV0::InvalidOperandsUnary(op, x) => Error::InvalidOperandsUnary(op, x),
+        ErrorV0::InvalidOperandsBinary(op, x, y) => Error::InvalidOperandsBinary(op, x, y),
+        ErrorV0::InvalidUnaryOperator(op) => Error::InvalidUnaryOperator(op),
+        ErrorV0::InvalidBinaryOperator(op) => Error::InvalidBinaryOperator(op),
+        ErrorV0::InvalidAssignment(expr) => Error::InvalidAssignment(expr),
+        ErrorV0::InvalidArrayAccessor(expr) => Error::InvalidArrayAccessor(expr),
+        ErrorV0::InvalidArrayIndex(index) => Error::InvalidArrayIndex(index),
+        ErrorV0::InvalidDeref(expr) => Error::InvalidDeref(expr),
+        ErrorV0::InvalidIndexLhs(expr) => Error::InvalidIndexLhs(expr),
+        ErrorV0::InvalidIndex(expr) => Error::InvalidIndex(expr),
+        ErrorV0::InvalidRoomSpeed(speed) => Error::InvalidRoomSpeed(speed),
+        ErrorV0::InvalidSwitchBody(expr) => Error::InvalidSwitchBody(expr),
+        ErrorV0::NonexistentAsset(ty, id) => Error::NonexistentAsset(ty, id),
+        ErrorV0::ReadOnlyVariable(var) => Error::ReadOnlyVariable(var),
+        ErrorV0::UnknownFunction(name) => Error::UnknownFunction(name),
+        ErrorV0::UnexpectedASTExpr(expr) => Error::UnexpectedASTExpr(expr),
+        ErrorV0::UninitializedVariable(name, index) => Error::UninitializedVariable(name, index),
+        ErrorV0::UninitializedArgument(n) => Error::UninitializedArgument(n),
+        ErrorV0::TooManyArrayDimensions(n) => Error::TooManyArrayDimensions(n),
+        ErrorV0::WrongArgumentCount(expected, got) => Error::WrongArgumentCount(expected, got),
+        ErrorV0::FunctionError(name, message) => Error::FunctionError(name, message),
+        ErrorV0::ReplayError(message) => Error::ReplayError(message),
+        ErrorV0::BadDirectoryError(dir) => Error::BadDirectoryError(dir),
+    }
+}
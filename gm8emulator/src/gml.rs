@@ -1,13 +1,21 @@
+pub mod audio;
+pub mod bytecode;
+pub mod cd;
 pub mod compiler;
 pub mod context;
 pub mod datetime;
+pub mod debugger;
 pub mod ds;
 pub mod file;
 pub mod kernel;
 pub mod mappings;
+pub mod mci;
 pub mod network;
+pub mod process;
+pub mod profiler;
 pub mod rand;
 pub mod runtime;
+pub mod trace;
 pub mod value;
 
 pub use compiler::Compiler;
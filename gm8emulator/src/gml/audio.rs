@@ -0,0 +1,158 @@
+//! Works out how long sound files are from their headers, for the things that keep time without decoding them.
+
+use std::convert::TryInto;
+
+/// The length of a .wav, .ogg or .mp3 file in milliseconds, if it's one of those.
+pub fn length(data: &[u8]) -> Option<u64> {
+    wav_length(data).or_else(|| ogg_length(data)).or_else(|| mp3_length(data))
+}
+
+pub fn wav_length(data: &[u8]) -> Option<u64> {
+    if data.get(0..4)? != b"RIFF" || data.get(8..12)? != b"WAVE" {
+        return None
+    }
+    let mut byte_rate = None;
+    let mut chunks = data.get(12..)?;
+    while chunks.len() >= 8 {
+        let size = u32::from_le_bytes(chunks[4..8].try_into().unwrap()) as usize;
+        match &chunks[0..4] {
+            b"fmt " => byte_rate = Some(u32::from_le_bytes(chunks.get(16..20)?.try_into().unwrap())),
+            b"data" => return Some(size as u64 * 1000 / u64::from(byte_rate.filter(|&r| r != 0)?)),
+            _ => (),
+        }
+        // chunks are padded to an even length
+        chunks = chunks.get(8 + size + size % 2..)?;
+    }
+    None
+}
+
+// Ogg Vorbis: the sample rate's in the first packet, and the last page says how many samples came before its end
+fn ogg_length(data: &[u8]) -> Option<u64> {
+    if data.get(0..4)? != b"OggS" {
+        return None
+    }
+    let segments = usize::from(*data.get(26)?);
+    let packet = data.get(27 + segments..)?;
+    if packet.get(0..7)? != b"\x01vorbis" {
+        return None
+    }
+    let rate = u32::from_le_bytes(packet.get(12..16)?.try_into().unwrap());
+    let last_page = data.windows(4).rposition(|w| w == b"OggS")?;
+    let samples = u64::from_le_bytes(data.get(last_page + 6..last_page + 14)?.try_into().unwrap());
+    Some(samples.checked_mul(1000)? / u64::from(rate).max(1))
+}
+
+// MP3 has no header for the whole file, so this adds up the frames, which are all MPEG layer III
+fn mp3_length(data: &[u8]) -> Option<u64> {
+    const BITRATES_V1: [u64; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+    const BITRATES_V2: [u64; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+    const RATES: [u64; 3] = [44100, 48000, 32000];
+
+    let mut frames = match data {
+        // an ID3v2 tag, whose size is 7 bits per byte, and there may be a footer after it
+        [b'I', b'D', b'3', _, _, flags, size @ ..] if size.len() >= 4 => {
+            let size = size[..4].iter().fold(0, |acc, &b| acc << 7 | usize::from(b & 0x7f));
+            data.get(10 + size + if flags & 0x10 != 0 { 10 } else { 0 }..)?
+        },
+        _ => data,
+    };
+    let (mut samples, mut rate) = (0, None);
+    while let [0xff, b1, b2, ..] = *frames {
+        let version = (b1 >> 3) & 3; // 3 is MPEG 1, 2 is MPEG 2, 0 is MPEG 2.5
+        let bitrate = usize::from(b2 >> 4);
+        let rate_index = usize::from((b2 >> 2) & 3);
+        if b1 & 0xe0 != 0xe0 || version == 1 || (b1 >> 1) & 3 != 1 || bitrate == 0 || bitrate == 15 || rate_index == 3 {
+            break
+        }
+        let frame_rate = RATES[rate_index] >> [2, 0, 1, 0][usize::from(version)];
+        let (bitrate, frame_samples) =
+            if version == 3 { (BITRATES_V1[bitrate], 1152) } else { (BITRATES_V2[bitrate], 576) };
+        let size = (frame_samples / 8 * bitrate * 1000 / frame_rate) as usize + usize::from((b2 >> 1) & 1);
+        samples += frame_samples;
+        rate = Some(frame_rate);
+        frames = match frames.get(size..) {
+            Some(rest) => rest,
+            None => break,
+        };
+    }
+    Some(samples * 1000 / rate?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a wav file with just enough in it to work out the length: 1000 bytes a second
+    fn wav(data_size: u32) -> Vec<u8> {
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        wav.extend(&16u32.to_le_bytes());
+        wav.extend(&[1, 0, 1, 0, 0xe8, 0x03, 0, 0, 0xe8, 0x03, 0, 0, 1, 0, 8, 0]);
+        wav.extend(b"data");
+        wav.extend(&data_size.to_le_bytes());
+        wav.extend(vec![0; data_size as usize]);
+        wav
+    }
+
+    fn ogg_page(granule: u64, packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\0\0".to_vec();
+        page.extend(&granule.to_le_bytes());
+        page.extend(&[0; 12]); // serial, sequence number and checksum
+        page.extend(&[1, packet.len() as u8]);
+        page.extend(packet);
+        page
+    }
+
+    #[test]
+    fn wavs() {
+        assert_eq!(length(&wav(2500)), Some(2500));
+        // chunks before the data are skipped, including their padding
+        let mut padded = wav(1000);
+        padded.splice(36..36, b"LIST\x03\0\0\0abc\0".iter().copied());
+        assert_eq!(length(&padded), Some(1000));
+        assert_eq!(length(&wav(1000)[..36]), None);
+        assert_eq!(length(b"RIFF\0\0\0\0AVI data"), None);
+    }
+
+    #[test]
+    fn oggs() {
+        let mut ident = b"\x01vorbis\0\0\0\0\x02".to_vec();
+        ident.extend(&22050u32.to_le_bytes());
+        ident.extend(&[0; 14]);
+        let mut ogg = ogg_page(0, &ident);
+        ogg.extend(ogg_page(4096, b"audio"));
+        ogg.extend(ogg_page(55125, b"more audio"));
+        assert_eq!(length(&ogg), Some(2500));
+        // a different codec in the same container
+        ogg[29..35].copy_from_slice(b"theora");
+        assert_eq!(length(&ogg), None);
+    }
+
+    #[test]
+    fn mp3s() {
+        // 128kbps at 44100Hz, so each frame is 417 bytes, plus one for padding
+        let frame = |padded: bool| {
+            let mut frame = vec![0xff, 0xfb, if padded { 0x92 } else { 0x90 }, 0x00];
+            frame.resize(if padded { 418 } else { 417 }, 0);
+            frame
+        };
+        let mut mp3 = b"ID3\x04\0\0\0\0\x01\x00".to_vec();
+        mp3.extend(vec![0; 128]);
+        for i in 0..100 {
+            mp3.extend(frame(i % 3 == 0));
+        }
+        mp3.extend(b"TAG");
+        mp3.extend(vec![0; 125]);
+        assert_eq!(length(&mp3), Some(2612));
+
+        // MPEG 2 at 16kbps, 22050Hz: 576 samples and 52 bytes a frame
+        let mut mp3 = Vec::new();
+        for _ in 0..50 {
+            mp3.extend(&[0xff, 0xf3, 0x20, 0x00]);
+            mp3.extend(vec![0; 48]);
+        }
+        assert_eq!(length(&mp3), Some(1306));
+
+        assert_eq!(length(b"ID3\x04\0\0\0\0\x7f\x7f"), None);
+        assert_eq!(length(b"just some text"), None);
+    }
+}
@@ -0,0 +1,632 @@
+//! Flattens the compiler's instruction trees into bytecode for a small stack machine, which is what actually runs.
+//! Loops and branches become jumps, values go on a stack instead of being returned up a tree, and anything that
+//! can be worked out ahead of time (where a variable lives, whether a loop runs at all) is worked out here.
+
+use super::{
+    runtime::{ArrayAccessor, BinaryOperator, Error, InstanceIdentifier, Instruction, Node, ReturnType, UnaryOperator},
+    InstanceVariable, Value,
+};
+use serde::{Deserialize, Serialize};
+
+/// Some compiled GML, ready to be run by Game::execute or Game::eval.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Program {
+    pub ops: Box<[Op]>,
+
+    /// Where each line of code starts in `ops`, in order. Used for saying where an error happened.
+    lines: Box<[(usize, Option<usize>)]>,
+
+    /// The most values that will ever be on the stack at once, so it can be allocated up front
+    pub stack_size: usize,
+}
+
+/// One bytecode instruction. Expressions push their result onto the stack, and anything that takes
+/// arguments pops them off again, in the order they were pushed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Op {
+    Push(Value),
+    Constant(usize),
+    Call { function_id: usize, arg_count: usize },
+    Script { script_id: usize, arg_count: usize },
+    Extension { id: usize, arg_count: usize },
+    GetField { index: usize, owner: Owner, indexed: bool },
+    GetVariable { var: InstanceVariable, owner: Owner, indexed: bool },
+    Binary(BinaryOperator),
+    Unary(UnaryOperator),
+    Error(Error),
+
+    // pops one or two array indices and sets them aside as one for the next get or set, or errors if
+    // they're out of range
+    Index { dims: usize },
+
+    // for working out who owns a variable before its indices and value are evaluated, when that matters
+    Target,               // pops an instance, object or keyword
+    TargetUnknown(usize), // unqualified field, which might turn out to be a globalvar
+
+    SetField { index: usize, owner: Owner, indexed: bool },
+    SetVariable { var: InstanceVariable, owner: Owner, indexed: bool },
+    SetReturnValue,
+    GlobalVar(Box<[usize]>),
+    Pop,
+
+    Jump(usize),
+    JumpIfFalse(usize),
+    Case(usize), // pops a case, and if it matches the switch input under it, pops that too and jumps
+
+    RepeatStart,
+    RepeatCheck(usize), // jumps if there are no repeats left
+    RepeatLoop(usize),  // counts one repeat and jumps back
+    RepeatEnd,
+
+    WithStart,
+    WithNext(usize), // moves onto the next instance, or jumps if there aren't any left
+    WithEnd,
+
+    Return(ReturnType),
+}
+
+/// Who a variable belongs to, as far as can be told at compile time.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Owner {
+    Own,
+    Other,
+    Global,
+    Local,
+    Unknown, // self, unless it's a field that's been declared globalvar
+    Target,  // already worked out and pushed by a Target op
+}
+
+impl Program {
+    /// Assembles a list of instructions, such as a script, into a program.
+    pub fn from_instructions(instructions: &[Instruction]) -> Self {
+        let mut asm = Assembler::default();
+        asm.body(instructions);
+        asm.finish()
+    }
+
+    /// Assembles an expression into a program that leaves its value on the stack.
+    pub fn from_node(node: &Node) -> Self {
+        let mut asm = Assembler::default();
+        asm.node(node);
+        asm.finish()
+    }
+
+    /// Which line of code the op at the given position came from, if that's known.
+    pub fn line_at(&self, pos: usize) -> Option<usize> {
+        match self.lines.binary_search_by_key(&pos, |&(start, _)| start) {
+            Ok(i) => self.lines[i].1,
+            Err(0) => None,
+            Err(i) => self.lines[i - 1].1,
+        }
+    }
+}
+
+impl Op {
+    // how many values this takes off the stack and puts back on
+    fn stack_effect(&self) -> (usize, usize) {
+        match self {
+            Self::Push(_) | Self::Constant(_) => (0, 1),
+            Self::Call { arg_count, .. } | Self::Script { arg_count, .. } | Self::Extension { arg_count, .. } => {
+                (*arg_count, 1)
+            },
+            Self::GetField { .. } | Self::GetVariable { .. } => (0, 1),
+            Self::Binary(_) => (2, 1),
+            Self::Unary(_) => (1, 1),
+            Self::Index { dims } => (*dims, 0),
+            Self::SetField { .. } | Self::SetVariable { .. } => (1, 0),
+            Self::Target | Self::SetReturnValue | Self::Pop | Self::JumpIfFalse(_) | Self::Case(_) => (1, 0),
+            Self::RepeatStart | Self::WithStart => (1, 0),
+            _ => (0, 0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ScopeKind {
+    Loop,
+    Repeat,
+    With,
+    Switch,
+    Step, // the step of a for loop, which GM8 runs without caring how it ends
+}
+
+// something that break, continue or exit can jump out of
+struct Scope {
+    kind: ScopeKind,
+    continue_to: Option<usize>,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+#[derive(Default)]
+struct Assembler {
+    ops: Vec<Op>,
+    lines: Vec<(usize, Option<usize>)>,
+    line: Option<usize>,
+    depth: usize,
+    stack_size: usize,
+    scopes: Vec<Scope>,
+}
+
+impl Assembler {
+    fn finish(self) -> Program {
+        Program { ops: self.ops.into(), lines: self.lines.into(), stack_size: self.stack_size }
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let (pops, pushes) = op.stack_effect();
+        self.depth = self.depth.saturating_sub(pops) + pushes;
+        self.stack_size = self.stack_size.max(self.depth);
+        self.ops.push(op);
+        self.ops.len() - 1
+    }
+
+    // points a jump emitted earlier at wherever we are now
+    fn patch(&mut self, jump: usize) {
+        let here = self.ops.len();
+        self.patch_to(jump, here);
+    }
+
+    fn patch_to(&mut self, jump: usize, to: usize) {
+        match &mut self.ops[jump] {
+            Op::Jump(target)
+            | Op::JumpIfFalse(target)
+            | Op::Case(target)
+            | Op::RepeatCheck(target)
+            | Op::WithNext(target) => *target = to,
+            op => unreachable!("tried to patch {:?}", op),
+        }
+    }
+
+    fn set_line(&mut self, line: Option<usize>) {
+        if self.line != line {
+            self.line = line;
+            match self.lines.last_mut() {
+                Some((start, l)) if *start == self.ops.len() => *l = line,
+                _ => self.lines.push((self.ops.len(), line)),
+            }
+        }
+    }
+
+    fn open(&mut self, kind: ScopeKind, continue_to: Option<usize>) {
+        self.scopes.push(Scope { kind, continue_to, breaks: Vec::new(), continues: Vec::new() });
+    }
+
+    // closes the innermost scope, landing its breaks here
+    fn close(&mut self) -> Scope {
+        let scope = self.scopes.pop().unwrap();
+        for &jump in &scope.breaks {
+            self.patch(jump);
+        }
+        scope
+    }
+
+    fn body(&mut self, instructions: &[Instruction]) {
+        let outer = self.line;
+        for instruction in instructions {
+            self.instruction(instruction);
+        }
+        self.set_line(outer);
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) {
+        match instruction {
+            Instruction::SetField { accessor, value } => {
+                let calls = array_has_calls(&accessor.array) || has_calls(value);
+                let owner = self.owner(&accessor.owner, Some(accessor.index), calls);
+                let indexed = self.index(&accessor.array);
+                self.node(value);
+                self.emit(Op::SetField { index: accessor.index, owner, indexed });
+            },
+            Instruction::SetVariable { accessor, value } => {
+                let owner = self.owner(&accessor.owner, None, false);
+                let indexed = self.index(&accessor.array);
+                self.node(value);
+                self.emit(Op::SetVariable { var: accessor.var, owner, indexed });
+            },
+            Instruction::EvalExpression { node: value } | Instruction::SetReturnValue { value } => {
+                self.node(value);
+                self.emit(Op::SetReturnValue);
+            },
+            Instruction::IfElse { cond, if_body, else_body } => match cond {
+                Node::Literal { value } => self.body(if value.is_truthy() { if_body } else { else_body }),
+                cond => {
+                    self.node(cond);
+                    let to_else = self.emit(Op::JumpIfFalse(0));
+                    self.body(if_body);
+                    if else_body.is_empty() {
+                        self.patch(to_else);
+                    } else {
+                        let to_end = self.emit(Op::Jump(0));
+                        self.patch(to_else);
+                        self.body(else_body);
+                        self.patch(to_end);
+                    }
+                },
+            },
+            Instruction::LoopUntil { cond, body } => {
+                // continue goes straight back to the start without checking the condition, like in GM8
+                let start = self.ops.len();
+                self.open(ScopeKind::Loop, Some(start));
+                self.body(body);
+                match cond {
+                    Node::Literal { value } if value.is_truthy() => (),
+                    Node::Literal { .. } => {
+                        self.emit(Op::Jump(start));
+                    },
+                    cond => {
+                        self.node(cond);
+                        self.emit(Op::JumpIfFalse(start));
+                    },
+                }
+                self.close();
+            },
+            Instruction::LoopWhile { cond, body } => {
+                if is_false(cond) {
+                    return
+                }
+                let start = self.ops.len();
+                let exit = self.test(cond);
+                self.open(ScopeKind::Loop, Some(start));
+                self.body(body);
+                self.emit(Op::Jump(start));
+                if let Some(jump) = exit {
+                    self.patch(jump);
+                }
+                self.close();
+            },
+            Instruction::LoopFor { cond, body, step } => {
+                if is_false(cond) {
+                    return
+                }
+                let start = self.ops.len();
+                let exit = self.test(cond);
+                self.open(ScopeKind::Loop, None);
+                self.body(body);
+                let step_start = self.ops.len();
+                self.open(ScopeKind::Step, None);
+                self.body(step);
+                self.close();
+                self.emit(Op::Jump(start));
+                if let Some(jump) = exit {
+                    self.patch(jump);
+                }
+                let scope = self.close();
+                for jump in scope.continues {
+                    self.patch_to(jump, step_start);
+                }
+            },
+            Instruction::Return { return_type: ReturnType::Normal } => (),
+            Instruction::Return { return_type } => self.jump_out(*return_type),
+            Instruction::Repeat { count, body } => {
+                if let Node::Literal { value } = count {
+                    if value.round() <= 0 {
+                        return
+                    }
+                }
+                // continue doesn't count as a repeat, again like in GM8
+                self.node(count);
+                self.emit(Op::RepeatStart);
+                let check = self.emit(Op::RepeatCheck(0));
+                self.open(ScopeKind::Repeat, Some(check));
+                self.body(body);
+                self.emit(Op::RepeatLoop(check));
+                self.patch(check);
+                self.close();
+                self.emit(Op::RepeatEnd);
+            },
+            Instruction::Switch { input, cases, default, body } => {
+                self.node(input);
+                let case_jumps = cases
+                    .iter()
+                    .map(|(case, start)| {
+                        self.node(case);
+                        (self.emit(Op::Case(0)), *start)
+                    })
+                    .collect::<Vec<_>>();
+                self.emit(Op::Pop);
+                let fallback = self.emit(Op::Jump(0));
+
+                let outer = self.line;
+                let mut starts = Vec::with_capacity(body.len() + 1);
+                self.open(ScopeKind::Switch, None);
+                for instruction in body.iter() {
+                    starts.push(self.ops.len());
+                    self.instruction(instruction);
+                }
+                self.set_line(outer);
+                starts.push(self.ops.len());
+                for (jump, start) in case_jumps {
+                    self.patch_to(jump, starts[start]);
+                }
+                match default {
+                    Some(start) => self.patch_to(fallback, starts[*start]),
+                    None => self.patch(fallback),
+                }
+                self.close();
+            },
+            Instruction::With { target, body } => {
+                self.node(target);
+                self.emit(Op::WithStart);
+                let next = self.emit(Op::WithNext(0));
+                self.open(ScopeKind::With, Some(next));
+                self.body(body);
+                self.emit(Op::Jump(next));
+                self.patch(next);
+                self.close();
+                self.emit(Op::WithEnd);
+            },
+            Instruction::GlobalVar { fields } => {
+                self.emit(Op::GlobalVar(fields.clone().into_boxed_slice()));
+            },
+            Instruction::RuntimeError { error } => {
+                self.emit(Op::Error(error.clone()));
+            },
+            Instruction::Line { number } => self.set_line(Some(*number)),
+        }
+    }
+
+    // emits a loop condition, giving back the jump out of the loop if it can fail
+    fn test(&mut self, cond: &Node) -> Option<usize> {
+        match cond {
+            Node::Literal { .. } => None,
+            cond => {
+                self.node(cond);
+                Some(self.emit(Op::JumpIfFalse(0)))
+            },
+        }
+    }
+
+    // emits a break, continue or exit, which goes to the innermost thing that handles it
+    fn jump_out(&mut self, return_type: ReturnType) {
+        let mut cleanup = Vec::new();
+        for i in (0..self.scopes.len()).rev() {
+            let kind = self.scopes[i].kind;
+            let stops_here = match return_type {
+                ReturnType::Break => true,
+                ReturnType::Continue => kind != ScopeKind::Switch,
+                _ => kind == ScopeKind::Step,
+            };
+            if !stops_here {
+                match kind {
+                    ScopeKind::Repeat => cleanup.push(Op::RepeatEnd),
+                    ScopeKind::With => cleanup.push(Op::WithEnd),
+                    _ => (),
+                }
+                continue
+            }
+            if return_type == ReturnType::Continue && kind != ScopeKind::Step {
+                match self.scopes[i].continue_to {
+                    Some(to) => {
+                        self.emit(Op::Jump(to));
+                    },
+                    None => {
+                        let jump = self.emit(Op::Jump(0));
+                        self.scopes[i].continues.push(jump);
+                    },
+                }
+            } else {
+                for op in cleanup {
+                    self.emit(op);
+                }
+                let jump = self.emit(Op::Jump(0));
+                self.scopes[i].breaks.push(jump);
+            }
+            return
+        }
+        self.emit(Op::Return(return_type));
+    }
+
+    fn node(&mut self, node: &Node) {
+        match node {
+            Node::Literal { value } => {
+                self.emit(Op::Push(value.clone()));
+            },
+            Node::Constant { constant_id } => {
+                self.emit(Op::Constant(*constant_id));
+            },
+            Node::Function { args, function_id } => {
+                self.args(args);
+                self.emit(Op::Call { function_id: *function_id, arg_count: args.len() });
+            },
+            Node::Script { args, script_id } => {
+                self.args(args);
+                self.emit(Op::Script { script_id: *script_id, arg_count: args.len() });
+            },
+            Node::ExtensionFunction { args, id } => {
+                self.args(args);
+                self.emit(Op::Extension { id: *id, arg_count: args.len() });
+            },
+            Node::Field { accessor } => {
+                let owner = self.owner(&accessor.owner, Some(accessor.index), array_has_calls(&accessor.array));
+                let indexed = self.index(&accessor.array);
+                self.emit(Op::GetField { index: accessor.index, owner, indexed });
+            },
+            Node::Variable { accessor } => {
+                let owner = self.owner(&accessor.owner, None, false);
+                let indexed = self.index(&accessor.array);
+                self.emit(Op::GetVariable { var: accessor.var, owner, indexed });
+            },
+            Node::Binary { left, right, operator } => {
+                self.node(left);
+                self.node(right);
+                self.emit(Op::Binary(*operator));
+            },
+            Node::Unary { child, operator } => {
+                self.node(child);
+                self.emit(Op::Unary(*operator));
+            },
+            Node::RuntimeError { error } => {
+                self.emit(Op::Error(error.clone()));
+            },
+        }
+    }
+
+    fn args(&mut self, args: &[Node]) {
+        for arg in args {
+            self.node(arg);
+        }
+    }
+
+    // Works out who owns a variable. Normally that's left until it's accessed, but if there's an expression
+    // to evaluate, or a function call in the indices or value could make it a globalvar, it's done first.
+    fn owner(&mut self, owner: &InstanceIdentifier, field: Option<usize>, calls_later: bool) -> Owner {
+        match owner {
+            InstanceIdentifier::Own => Owner::Own,
+            InstanceIdentifier::Other => Owner::Other,
+            InstanceIdentifier::Global => Owner::Global,
+            InstanceIdentifier::Local => Owner::Local,
+            InstanceIdentifier::Unknown => match field {
+                Some(index) if calls_later => {
+                    self.emit(Op::TargetUnknown(index));
+                    Owner::Target
+                },
+                _ => Owner::Unknown,
+            },
+            InstanceIdentifier::Expression(node) => {
+                self.node(node);
+                self.emit(Op::Target);
+                Owner::Target
+            },
+        }
+    }
+
+    fn index(&mut self, array: &ArrayAccessor) -> bool {
+        match array {
+            ArrayAccessor::None => false,
+            ArrayAccessor::Single(index) => {
+                self.node(index);
+                self.emit(Op::Index { dims: 1 });
+                true
+            },
+            ArrayAccessor::Double(index1, index2) => {
+                self.node(index1);
+                self.node(index2);
+                self.emit(Op::Index { dims: 2 });
+                true
+            },
+        }
+    }
+}
+
+fn is_false(cond: &Node) -> bool {
+    matches!(cond, Node::Literal { value } if !value.is_truthy())
+}
+
+// whether evaluating something could run other code, which could do just about anything
+fn has_calls(node: &Node) -> bool {
+    match node {
+        Node::Function { .. } | Node::Script { .. } | Node::ExtensionFunction { .. } => true,
+        Node::Field { accessor } => owner_has_calls(&accessor.owner) || array_has_calls(&accessor.array),
+        Node::Variable { accessor } => owner_has_calls(&accessor.owner) || array_has_calls(&accessor.array),
+        Node::Binary { left, right, .. } => has_calls(left) || has_calls(right),
+        Node::Unary { child, .. } => has_calls(child),
+        Node::Literal { .. } | Node::Constant { .. } | Node::RuntimeError { .. } => false,
+    }
+}
+
+fn owner_has_calls(owner: &InstanceIdentifier) -> bool {
+    matches!(owner, InstanceIdentifier::Expression(node) if has_calls(node))
+}
+
+fn array_has_calls(array: &ArrayAccessor) -> bool {
+    match array {
+        ArrayAccessor::None => false,
+        ArrayAccessor::Single(index) => has_calls(index),
+        ArrayAccessor::Double(index1, index2) => has_calls(index1) || has_calls(index2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gml::{self, Compiler},
+        testing::{code, run, test_game},
+    };
+    use std::rc::Rc;
+
+    fn compile(source: &str) -> Rc<Program> {
+        Compiler::new().compile(source.as_bytes()).unwrap()
+    }
+
+    fn jump_targets(program: &Program) -> Vec<usize> {
+        program
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                Op::Jump(to) | Op::JumpIfFalse(to) | Op::Case(to) | Op::RepeatCheck(to) | Op::WithNext(to) => Some(*to),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dead_code_is_left_out() {
+        assert!(compile("while (false) a = 1").ops.is_empty());
+        assert!(compile("repeat (0) a = 1").ops.is_empty());
+        assert!(compile("repeat (-2) { a = 1; b = 2 }").ops.is_empty());
+        let program = compile("if (true) a = 1 else b = 2");
+        assert!(jump_targets(&program).is_empty());
+        assert_eq!(program.ops.len(), 2);
+    }
+
+    #[test]
+    fn stack_size_is_the_deepest_it_gets() {
+        assert_eq!(compile("").stack_size, 0);
+        assert_eq!(compile("a = b + c * d").stack_size, 3);
+        assert_eq!(compile("a = max(b, c, d, e) + f").stack_size, 4);
+    }
+
+    #[test]
+    fn jumps_are_all_patched() {
+        let program = compile(concat!(
+            "for (i = 0; i < 3; i += 1) {\n",
+            "    if (i == 1) continue;\n",
+            "    repeat (2) with (all) { if (x) break }\n",
+            "    switch (i) { case 0: a = 1; break; case 2: continue; default: exit }\n",
+            "}\n",
+            "do a -= 1 until (a < 0)",
+        ));
+        let targets = jump_targets(&program);
+        assert!(targets.len() > 5);
+        // a jump to 0 that's anything but the first loop's start would have been left unpatched
+        assert!(targets.iter().all(|&to| to > 0 && to <= program.ops.len()));
+    }
+
+    #[test]
+    fn ops_know_their_line() {
+        let program = compile("a = 1;\nb = 2;\n\nc = 3");
+        let lines = (0..program.ops.len()).map(|i| program.line_at(i)).collect::<Vec<_>>();
+        assert_eq!(lines, [Some(1), Some(1), Some(2), Some(2), Some(4), Some(4)]);
+    }
+
+    #[test]
+    fn loops_keep_gm8_quirks() {
+        let mut game = test_game();
+        let source = concat!(
+            "i = 0; n = 0;\n",
+            // continue in repeat doesn't count down, and in do-until it skips the check
+            "repeat (3) { i += 1; if (i < 3) continue; n += 1 }\n",
+            "j = 0; do { j += 1; if (j < 5) continue } until (true)\n",
+            "m = 0;\n",
+            "for (k = 0; k < 10; k += 1) {\n",
+            "    switch (k) {\n",
+            "        case 1: continue; case 2: m += 10; case 3: m += 100; break; case 5: break; default: m += 1\n",
+            "    }\n",
+            "    if (k == 6) break\n",
+            "}\n",
+            "instance_create(0, 0, 0); c = 0;\n",
+            "with (all) other.c += 1;\n",
+            "with (all) { other.c += 10; break }\n",
+            "global.result = string(i) + \" \" + string(n) + \" \" + string(j) + \" \"\n",
+            "    + string(m) + \" \" + string(k) + \" \" + string(c)",
+        );
+        let instance = run(&mut game, 0.0, 0.0, &[code(source)]).unwrap();
+        let result = game.compiler.compile_expression(b"global.result").unwrap();
+        match game.eval(&result, &mut gml::Context::with_single_instance(instance)).unwrap() {
+            Value::Str(s) => assert_eq!(s.as_ref(), b"5 3 5 213 6 12"),
+            other => panic!("expected a string, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! A virtual CD drive for the cd_* functions, which can have a folder of audio tracks put in it.
+//! Positions are worked out from the game's clock, so they come out the same in replays.
+
+// TODO: like the sound DLLs, this keeps time but doesn't make any noise until there's a mixer to route it into.
+// The emulator doesn't have one for anything yet, so actually playing the tracks is left for when it does.
+
+use crate::gml::audio;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    convert::TryInto,
+    io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct CdDrive {
+    // a path on whatever machine this is running on, so it's always saved as None, see restore()
+    #[serde(serialize_with = "save_no_folder", deserialize_with = "load_no_folder")]
+    folder: Option<PathBuf>,
+    tracks: Vec<u64>, // lengths in milliseconds
+    door_open: bool,
+    playback: Playback,
+}
+
+fn save_no_folder<S: Serializer>(_folder: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+    None::<PathBuf>.serialize(serializer)
+}
+
+fn load_no_folder<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PathBuf>, D::Error> {
+    Option::<PathBuf>::deserialize(deserializer).map(|_| None)
+}
+
+/// How far into something that's playing it's got to, in milliseconds.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Playback {
+    state: PlayState,
+    start: u64,       // where it started playing from, and where a repeat goes back to
+    position: u64,    // where it was when it was last started, paused or stopped
+    end: Option<u64>, // where it stops, None if it only stops when it's told to
+    repeat: bool,
+    started_at: u128, // game clock in nanoseconds
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum PlayState {
+    #[default]
+    Stopped,
+    Playing,
+    Paused,
+}
+
+impl Playback {
+    pub fn position(&self, now: u128) -> u64 {
+        if self.state != PlayState::Playing {
+            return self.position
+        }
+        let elapsed = (now.saturating_sub(self.started_at) / 1_000_000).try_into().unwrap_or(u64::MAX);
+        let position = self.position.saturating_add(elapsed);
+        match self.end {
+            Some(end) if self.repeat && end > self.start && position >= end => {
+                self.start + (position - self.start) % (end - self.start)
+            },
+            Some(end) => position.min(end),
+            None => position,
+        }
+    }
+
+    pub fn is_playing(&self, now: u128) -> bool {
+        self.state == PlayState::Playing && (self.repeat || !matches!(self.end, Some(end) if self.position(now) >= end))
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == PlayState::Paused
+    }
+
+    pub fn play(&mut self, from: u64, end: Option<u64>, repeat: bool, now: u128) {
+        *self = Self { state: PlayState::Playing, start: from, position: from, end, repeat, started_at: now };
+    }
+
+    pub fn stop(&mut self, now: u128) {
+        self.position = self.position(now);
+        self.state = PlayState::Stopped;
+    }
+
+    pub fn pause(&mut self, now: u128) {
+        if self.is_playing(now) {
+            self.position = self.position(now);
+            self.state = PlayState::Paused;
+        }
+    }
+
+    pub fn resume(&mut self, now: u128) {
+        if self.state == PlayState::Paused {
+            self.state = PlayState::Playing;
+            self.started_at = now;
+        }
+    }
+
+    pub fn seek(&mut self, position: u64, now: u128) {
+        self.position = position;
+        self.started_at = now;
+    }
+}
+
+impl CdDrive {
+    /// Puts a folder of tracks in the drive. They're the .wav files and raw CD audio (.raw, .bin or .cdda)
+    /// in it, in order of name.
+    pub fn insert(&mut self, folder: PathBuf) -> io::Result<()> {
+        let mut files = std::fs::read_dir(&folder)?.filter_map(|e| e.ok()).map(|e| e.path()).collect::<Vec<_>>();
+        files.sort();
+        self.tracks = files.iter().filter_map(|p| track_length(p)).collect();
+        self.folder = Some(folder);
+        self.playback = Default::default();
+        Ok(())
+    }
+
+    /// Looks at the disc again, in case it's changed.
+    pub fn init(&mut self) {
+        if let Some(folder) = self.folder.clone() {
+            if let Err(e) = self.insert(folder) {
+                eprintln!("Warning: couldn't read the CD folder: {}", e);
+            }
+        }
+    }
+
+    /// Takes on the disc and playback from a saved drive, but keeps reading from this drive's folder,
+    /// since the one it was saved with may not exist here.
+    pub fn restore(&mut self, saved: CdDrive) {
+        *self = Self { folder: self.folder.take(), ..saved };
+    }
+
+    pub fn present(&self) -> bool {
+        !self.door_open && !self.tracks.is_empty()
+    }
+
+    fn disc(&self) -> &[u64] {
+        if self.door_open { &[] } else { &self.tracks }
+    }
+
+    pub fn track_count(&self) -> usize {
+        self.disc().len()
+    }
+
+    pub fn length(&self) -> u64 {
+        self.disc().iter().sum()
+    }
+
+    /// Length of a track, counting from 1.
+    pub fn track_length(&self, track: usize) -> u64 {
+        track.checked_sub(1).and_then(|i| self.disc().get(i)).copied().unwrap_or(0)
+    }
+
+    fn track_start(&self, track: usize) -> u64 {
+        self.disc().iter().take(track.saturating_sub(1)).sum()
+    }
+
+    pub fn playing(&self, now: u128) -> bool {
+        self.present() && self.playback.is_playing(now)
+    }
+
+    pub fn paused(&self) -> bool {
+        self.present() && self.playback.is_paused()
+    }
+
+    pub fn position(&self, now: u128) -> u64 {
+        if self.present() { self.playback.position(now) } else { 0 }
+    }
+
+    /// The track that's playing, counting from 1, or 0 with no disc.
+    pub fn track(&self, now: u128) -> usize {
+        let position = self.position(now);
+        let mut start = 0;
+        for (i, length) in self.disc().iter().enumerate() {
+            start += length;
+            if position < start || i + 1 == self.track_count() {
+                return i + 1
+            }
+        }
+        0
+    }
+
+    pub fn track_position(&self, now: u128) -> u64 {
+        self.position(now) - self.track_start(self.track(now))
+    }
+
+    /// Plays from the start of one track to the end of another.
+    pub fn play(&mut self, first: usize, last: usize, now: u128) {
+        if self.present() {
+            let first = first.clamp(1, self.track_count());
+            let last = last.clamp(first, self.track_count());
+            self.playback.play(self.track_start(first), Some(self.track_start(last + 1)), false, now);
+        }
+    }
+
+    pub fn stop(&mut self, now: u128) {
+        self.playback.stop(now);
+    }
+
+    pub fn pause(&mut self, now: u128) {
+        self.playback.pause(now);
+    }
+
+    pub fn resume(&mut self, now: u128) {
+        self.playback.resume(now);
+    }
+
+    pub fn set_position(&mut self, position: u64, now: u128) {
+        self.playback.seek(position.min(self.length()), now);
+    }
+
+    pub fn set_track_position(&mut self, position: u64, now: u128) {
+        let track = self.track(now);
+        self.set_position(self.track_start(track) + position.min(self.track_length(track)), now);
+    }
+
+    pub fn set_door_open(&mut self, open: bool, now: u128) {
+        if open {
+            self.stop(now);
+        }
+        self.door_open = open;
+    }
+}
+
+/// Works out how long a sound file is in milliseconds, if it's a format that can be done for without decoding it.
+pub fn track_length(path: &Path) -> Option<u64> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "wav" => audio::wav_length(&std::fs::read(path).ok()?),
+        // 44100Hz, 16-bit stereo
+        "raw" | "bin" | "cdda" => Some(path.metadata().ok()?.len() * 1000 / 176400),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u128 = 1_000_000;
+
+    fn drive() -> CdDrive {
+        CdDrive { tracks: vec![1000, 2000, 3000], ..Default::default() }
+    }
+
+    // a wav file with just enough in it to work out the length: 1000 bytes a second
+    fn wav(data_size: u32) -> Vec<u8> {
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        wav.extend(&16u32.to_le_bytes());
+        wav.extend(&[1, 0, 1, 0, 0xe8, 0x03, 0, 0, 0xe8, 0x03, 0, 0, 1, 0, 8, 0]);
+        wav.extend(b"data");
+        wav.extend(&data_size.to_le_bytes());
+        wav.extend(vec![0; data_size as usize]);
+        wav
+    }
+
+    #[test]
+    fn tracks_play_in_order() {
+        let mut cd = drive();
+        assert_eq!((cd.track_count(), cd.length(), cd.track_length(2), cd.track_length(4)), (3, 6000, 2000, 0));
+        assert_eq!(cd.track(0), 1);
+        cd.play(2, 3, 0);
+        assert!(cd.playing(0));
+        assert_eq!((cd.track(0), cd.position(0), cd.track_position(0)), (2, 1000, 0));
+        assert_eq!((cd.track(2500 * MS), cd.position(2500 * MS), cd.track_position(2500 * MS)), (3, 3500, 500));
+        // it stops at the end of the last track it was asked for
+        assert_eq!(cd.position(9000 * MS), 6000);
+        assert!(!cd.playing(9000 * MS));
+
+        // out of range tracks are clamped to what's on the disc
+        cd.play(0, 1, 0);
+        assert_eq!(cd.position(5000 * MS), 1000);
+        cd.play(3, 1, 0);
+        assert_eq!((cd.track(0), cd.position(5000 * MS)), (3, 6000));
+    }
+
+    #[test]
+    fn pausing_holds_the_position() {
+        let mut cd = drive();
+        cd.play(1, 3, 0);
+        cd.pause(500 * MS);
+        assert!(cd.paused() && !cd.playing(500 * MS));
+        assert_eq!(cd.position(2000 * MS), 500);
+        cd.resume(2000 * MS);
+        assert!(cd.playing(2000 * MS));
+        assert_eq!(cd.position(2100 * MS), 600);
+
+        // resuming something that isn't paused does nothing, and neither does pausing something that's stopped
+        cd.stop(2200 * MS);
+        cd.resume(2300 * MS);
+        assert!(!cd.playing(2300 * MS));
+        cd.pause(2400 * MS);
+        assert!(!cd.paused());
+        assert_eq!(cd.position(3000 * MS), 700);
+    }
+
+    #[test]
+    fn seeking_stays_on_the_disc() {
+        let mut cd = drive();
+        cd.play(1, 3, 0);
+        cd.set_position(99999, 100 * MS);
+        assert_eq!(cd.position(100 * MS), 6000);
+        cd.set_position(1500, 100 * MS);
+        assert_eq!((cd.track(100 * MS), cd.position(200 * MS)), (2, 1600));
+        // within whichever track it's on
+        cd.set_track_position(700, 200 * MS);
+        assert_eq!(cd.position(200 * MS), 1700);
+        cd.set_track_position(99999, 200 * MS);
+        assert_eq!(cd.position(200 * MS), 3000);
+    }
+
+    #[test]
+    fn opening_the_door_takes_the_disc_out() {
+        let mut cd = drive();
+        cd.play(1, 3, 0);
+        cd.set_door_open(true, 500 * MS);
+        assert!(!cd.present() && !cd.playing(500 * MS));
+        assert_eq!((cd.track_count(), cd.length(), cd.position(500 * MS), cd.track(500 * MS)), (0, 0, 0, 0));
+        cd.play(1, 3, 600 * MS);
+        assert!(!cd.playing(600 * MS));
+
+        // closing it puts the disc back, stopped where it was
+        cd.set_door_open(false, 1000 * MS);
+        assert!(cd.present() && !cd.playing(1000 * MS));
+        assert_eq!((cd.track_count(), cd.position(2000 * MS)), (3, 500));
+
+        // with nothing in the drive, there's no disc either way
+        assert!(!CdDrive::default().present());
+    }
+
+    #[test]
+    fn folders_become_discs() {
+        let dir = std::env::temp_dir().join(format!("gm8emulator-test-cd-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("2.raw"), vec![0; 176400 * 3]).unwrap();
+        std::fs::write(dir.join("1.WAV"), wav(1500)).unwrap();
+        std::fs::write(dir.join("3.txt"), b"not a track").unwrap();
+        std::fs::write(dir.join("4.wav"), b"not a wav either").unwrap();
+
+        let mut cd = CdDrive::default();
+        cd.insert(dir.clone()).unwrap();
+        assert_eq!(cd.tracks, [1500, 3000]);
+        std::fs::write(dir.join("0.cdda"), vec![0; 176400]).unwrap();
+        cd.init();
+        assert_eq!(cd.tracks, [1000, 1500, 3000]);
+
+        // a savestate brings its disc but not its folder, so this one's still read next time
+        let mut saved = drive();
+        saved.folder = Some(PathBuf::from("somewhere/else"));
+        let saved: CdDrive = bincode::deserialize(&bincode::serialize(&saved).unwrap()).unwrap();
+        assert!(saved.folder.is_none());
+        cd.restore(saved);
+        assert_eq!(cd.tracks, [1000, 2000, 3000]);
+        cd.init();
+        assert_eq!(cd.tracks, [1000, 1500, 3000]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
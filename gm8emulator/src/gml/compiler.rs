@@ -1,4 +1,5 @@
 use super::{
+    bytecode::Program,
     mappings,
     runtime::{
         ArrayAccessor, BinaryOperator, FieldAccessor, InstanceIdentifier, Instruction, Node, ReturnType, UnaryOperator,
@@ -8,6 +9,7 @@ use super::{
 };
 use crate::{gml, math::Real};
 use gml_parser::{ast, token::Operator};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, rc::Rc, str};
 
@@ -27,6 +29,36 @@ pub struct Compiler {
 
     /// Lookup table of unique field names
     fields: Vec<Box<[u8]>>,
+
+    /// Bumped whenever a name gets registered, since that can change what some code compiles to
+    generation: usize,
+
+    /// Recently compiled code, for execute_string and friends
+    #[serde(skip)]
+    cache: CodeCache,
+
+    /// Everything compiled while this is set, or loaded from a compile cache file
+    #[serde(skip)]
+    precompiled: Option<Precompiled>,
+}
+
+/// Every program compiled from some source, so it can be saved and loaded instead of compiled again.
+/// This is only valid for the compiler it came from, since the field IDs in it have to match.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Precompiled {
+    code: HashMap<Box<[u8]>, Rc<Program>>,
+    expressions: HashMap<Box<[u8]>, Program>,
+}
+
+/// How many compiled strings to hold onto before forgetting the oldest
+const CODE_CACHE_SIZE: usize = 256;
+
+#[derive(Clone, Debug, Default)]
+struct CodeCache {
+    programs: IndexMap<Box<[u8]>, Rc<Program>>,
+    generation: usize,
+    hits: usize,
+    misses: usize,
 }
 
 impl Compiler {
@@ -38,6 +70,9 @@ impl Compiler {
             script_names: HashMap::new(),
             extension_fn_names: HashMap::new(),
             fields: Vec::new(),
+            generation: 0,
+            cache: Default::default(),
+            precompiled: None,
         }
     }
 
@@ -66,25 +101,43 @@ impl Compiler {
     /// registered twice, the old one will NOT be overwritten and the value will be dropped, as per GM8.
     pub fn register_constant(&mut self, name: Box<[u8]>, value: f64) {
         self.constants.entry(name).or_insert(Value::Real(value.into()));
+        self.generation += 1;
     }
 
     /// Register a script name and its index. Duplicate script names are ignored.
     pub fn register_script(&mut self, name: Box<[u8]>, index: usize) {
         self.script_names.entry(name).or_insert(index);
+        self.generation += 1;
     }
 
     /// Register an ExtensionFunction and its index.
     pub fn register_extension_function(&mut self, name: Box<[u8]>, index: usize) {
         self.extension_fn_names.entry(name).or_insert(index);
+        self.generation += 1;
     }
 
     /// Register a user constant and its index.
     pub fn register_user_constant(&mut self, name: Box<[u8]>, index: usize) {
         self.user_constant_names.insert(name, index);
+        self.generation += 1;
+    }
+
+    /// Start remembering everything that gets compiled, starting with what's already been compiled.
+    /// Anything found in there is used instead of compiling it again.
+    pub fn use_precompiled(&mut self, precompiled: Precompiled) {
+        self.precompiled = Some(precompiled);
+    }
+
+    /// Stop remembering what gets compiled, and return everything that was.
+    pub fn take_precompiled(&mut self) -> Option<Precompiled> {
+        self.precompiled.take()
     }
 
-    /// Compile a GML string into instructions.
-    pub fn compile(&mut self, source: &[u8]) -> Result<Rc<[Instruction]>, ast::Error> {
+    /// Compile a GML string into a program.
+    pub fn compile(&mut self, source: &[u8]) -> Result<Rc<Program>, ast::Error> {
+        if let Some(program) = self.precompiled.as_ref().and_then(|p| p.code.get(source)) {
+            return Ok(program.clone())
+        }
         let ast = ast::AST::new(source)?;
 
         let mut instructions = Vec::new();
@@ -92,40 +145,80 @@ impl Compiler {
         for node in ast.iter() {
             self.compile_ast_line(node, &mut instructions, &mut locals);
         }
-        Ok(instructions.into())
+        let program = Rc::new(Program::from_instructions(&instructions));
+        if let Some(precompiled) = self.precompiled.as_mut() {
+            precompiled.code.insert(source.into(), program.clone());
+        }
+        Ok(program)
+    }
+
+    /// Compile a GML string, reusing the program from last time if the same string was compiled recently.
+    /// Only successful compiles are remembered.
+    pub fn compile_cached(&mut self, source: &[u8]) -> Result<Rc<Program>, ast::Error> {
+        if self.cache.generation != self.generation {
+            self.cache.programs.clear();
+            self.cache.generation = self.generation;
+        }
+        if let Some(program) = self.cache.programs.get(source) {
+            self.cache.hits += 1;
+            return Ok(program.clone())
+        }
+        self.cache.misses += 1;
+        let program = self.compile(source)?;
+        if self.cache.programs.len() >= CODE_CACHE_SIZE {
+            self.cache.programs.shift_remove_index(0);
+        }
+        self.cache.programs.insert(source.into(), program.clone());
+        Ok(program)
+    }
+
+    /// How many times compile_cached was (hit, missed).
+    pub fn cache_stats(&self) -> (usize, usize) {
+        (self.cache.hits, self.cache.misses)
     }
 
     /// Compile an expression into a format which can be evaluated.
-    pub fn compile_expression(&mut self, source: &[u8]) -> Result<Node, ast::Error> {
+    pub fn compile_expression(&mut self, source: &[u8]) -> Result<Program, ast::Error> {
+        if let Some(program) = self.precompiled.as_ref().and_then(|p| p.expressions.get(source)) {
+            return Ok(program.clone())
+        }
         let expr = ast::AST::expression(source)?;
-        Ok(self.compile_ast_expr(&expr, &[]))
+        let program = Program::from_node(&self.compile_ast_expr(&expr, &[]));
+        if let Some(precompiled) = self.precompiled.as_mut() {
+            precompiled.expressions.insert(source.into(), program.clone());
+        }
+        Ok(program)
     }
 
     /// Compile a single line of code from an AST expression.
     fn compile_ast_line<'a>(&mut self, line: &'a ast::Expr, output: &mut Vec<Instruction>, locals: &mut Vec<&'a [u8]>) {
-        match line {
+        match &line.kind {
+            ast::ExprKind::Group(_) | ast::ExprKind::Var(_) => (),
+            _ => Self::mark_line(output, line.span.line),
+        }
+        match &line.kind {
             // Line of code identified by an assignment operator
-            ast::Expr::Binary(binary_expr) => {
+            ast::ExprKind::Binary(binary_expr) => {
                 output.push(self.binary_to_instruction(binary_expr.as_ref(), &locals));
             },
 
             // Break
-            ast::Expr::Break => {
+            ast::ExprKind::Break => {
                 output.push(Instruction::Return { return_type: ReturnType::Break });
             },
 
             // Continue
-            ast::Expr::Continue => {
+            ast::ExprKind::Continue => {
                 output.push(Instruction::Return { return_type: ReturnType::Continue });
             },
 
             // Exit
-            ast::Expr::Exit => {
+            ast::ExprKind::Exit => {
                 output.push(Instruction::Return { return_type: ReturnType::Exit });
             },
 
             // For loop
-            ast::Expr::For(for_expr) => {
+            ast::ExprKind::For(for_expr) => {
                 self.compile_ast_line(&for_expr.start, output, locals);
                 let cond = self.compile_ast_expr(&for_expr.cond, locals);
                 let mut body = Vec::new();
@@ -140,19 +233,19 @@ impl Compiler {
             },
 
             // Function or Script
-            f @ ast::Expr::Function(_) => {
-                output.push(Instruction::EvalExpression { node: self.compile_ast_expr(f, locals) });
+            ast::ExprKind::Function(_) => {
+                output.push(Instruction::EvalExpression { node: self.compile_ast_expr(line, locals) });
             },
 
             // Group of expressions
-            ast::Expr::Group(group) => {
+            ast::ExprKind::Group(group) => {
                 for expr in group {
                     self.compile_ast_line(expr, output, locals);
                 }
             },
 
             // If/else body
-            ast::Expr::If(if_expr) => {
+            ast::ExprKind::If(if_expr) => {
                 let cond = self.compile_ast_expr(&if_expr.cond, locals);
                 if let Node::Literal { value: v } = cond {
                     // The "if" condition is constant, so we can optimize this away
@@ -177,7 +270,7 @@ impl Compiler {
             },
 
             // "repeat" block
-            ast::Expr::Repeat(repeat_expr) => {
+            ast::ExprKind::Repeat(repeat_expr) => {
                 let count = self.compile_ast_expr(&repeat_expr.count, locals);
                 let mut body = Vec::new();
                 self.compile_ast_line(&repeat_expr.body, &mut body, locals);
@@ -185,25 +278,25 @@ impl Compiler {
             },
 
             // Return
-            ast::Expr::Return(expr) => {
+            ast::ExprKind::Return(expr) => {
                 let value = self.compile_ast_expr(&expr, locals);
                 output.push(Instruction::SetReturnValue { value });
                 output.push(Instruction::Return { return_type: ReturnType::Exit });
             },
 
             // "switch" block
-            ast::Expr::Switch(switch_expr) => {
+            ast::ExprKind::Switch(switch_expr) => {
                 let input = self.compile_ast_expr(&switch_expr.input, locals);
-                if let ast::Expr::Group(group) = &switch_expr.body {
+                if let ast::ExprKind::Group(group) = &switch_expr.body.kind {
                     let mut cases = Vec::new();
                     let mut body = Vec::new();
                     let mut default: Option<usize> = None;
                     for expr in group {
-                        if let ast::Expr::Case(case_expr) = expr {
+                        if let ast::ExprKind::Case(case_expr) = &expr.kind {
                             if default.is_none() {
                                 cases.push((self.compile_ast_expr(case_expr, locals), body.len()));
                             }
-                        } else if let ast::Expr::Default = expr {
+                        } else if let ast::ExprKind::Default = expr.kind {
                             if default.is_none() {
                                 default = Some(body.len());
                             }
@@ -225,7 +318,7 @@ impl Compiler {
             },
 
             // "do-until" block
-            ast::Expr::DoUntil(while_expr) => {
+            ast::ExprKind::DoUntil(while_expr) => {
                 let cond = self.compile_ast_expr(&while_expr.cond, locals);
                 let mut body = Vec::new();
                 self.compile_ast_line(&while_expr.body, &mut body, locals);
@@ -233,18 +326,18 @@ impl Compiler {
             },
 
             // "var" declaration
-            ast::Expr::Var(var_expr) => {
+            ast::ExprKind::Var(var_expr) => {
                 locals.extend_from_slice(&var_expr.vars);
             },
 
-            ast::Expr::GlobalVar(globalvar_expr) => {
+            ast::ExprKind::GlobalVar(globalvar_expr) => {
                 // globalvar doesn't work on builtins
                 let fields = globalvar_expr.vars.iter().map(|x| self.get_field_id(x)).collect();
                 output.push(Instruction::GlobalVar { fields });
             },
 
             // "while" block
-            ast::Expr::While(while_expr) => {
+            ast::ExprKind::While(while_expr) => {
                 let cond = self.compile_ast_expr(&while_expr.cond, locals);
                 let mut body = Vec::new();
                 self.compile_ast_line(&while_expr.body, &mut body, locals);
@@ -252,7 +345,7 @@ impl Compiler {
             },
 
             // "with" block
-            ast::Expr::With(with_expr) => {
+            ast::ExprKind::With(with_expr) => {
                 let target = self.compile_ast_expr(&with_expr.target, locals);
                 let mut body = Vec::new();
                 self.compile_ast_line(&with_expr.body, &mut body, locals);
@@ -268,12 +361,12 @@ impl Compiler {
 
     /// Compile an AST expression into a Node.
     fn compile_ast_expr(&mut self, expr: &ast::Expr, locals: &[&[u8]]) -> Node {
-        match expr {
-            ast::Expr::LiteralReal(real) => Node::Literal { value: Value::Real(Real::from(*real)) },
+        match &expr.kind {
+            ast::ExprKind::LiteralReal(real) => Node::Literal { value: Value::Real(Real::from(*real)) },
 
-            ast::Expr::LiteralString(string) => Node::Literal { value: Value::Str((*string).into()) },
+            ast::ExprKind::LiteralString(string) => Node::Literal { value: Value::Str((*string).into()) },
 
-            ast::Expr::LiteralIdentifier(string) => {
+            ast::ExprKind::LiteralIdentifier(string) => {
                 if let Some(entry) = self.constants.get(*string) {
                     Node::Literal { value: entry.clone() }
                 } else if let Some(constant_id) = self.user_constant_names.get(*string) {
@@ -285,33 +378,30 @@ impl Compiler {
                 }
             },
 
-            ast::Expr::Binary(binary_expr) => match &binary_expr.op {
-                Operator::Deref => match &binary_expr.right {
-                    ast::Expr::LiteralIdentifier(var_name) => {
+            ast::ExprKind::Binary(binary_expr) => match &binary_expr.op {
+                Operator::Deref => match &binary_expr.right.kind {
+                    ast::ExprKind::LiteralIdentifier(var_name) => {
                         let owner = self.make_instance_identifier(&binary_expr.left, locals);
                         self.identifier_to_variable(var_name, Some(owner), ArrayAccessor::None, locals)
                     },
                     _ => Node::RuntimeError { error: gml::Error::InvalidDeref(binary_expr.right.to_string()) },
                 },
 
-                Operator::Index => match &binary_expr.right {
-                    ast::Expr::Group(dimensions) => {
+                Operator::Index => match &binary_expr.right.kind {
+                    ast::ExprKind::Group(dimensions) => {
                         let accessor = match self.make_array_accessor(dimensions, locals) {
                             Ok(a) => a,
                             Err(e) => return Node::RuntimeError { error: gml::Error::TooManyArrayDimensions(e) },
                         };
-                        match &binary_expr.left {
-                            ast::Expr::LiteralIdentifier(string) => {
+                        match &binary_expr.left.kind {
+                            ast::ExprKind::LiteralIdentifier(string) => {
                                 self.identifier_to_variable(string, None, accessor, locals)
                             },
-                            ast::Expr::Binary(binary_expr) => {
-                                if let ast::BinaryExpr {
-                                    left,
-                                    right: ast::Expr::LiteralIdentifier(i),
-                                    op: Operator::Deref,
-                                } = binary_expr.as_ref()
+                            ast::ExprKind::Binary(binary_expr) => {
+                                if let (ast::ExprKind::LiteralIdentifier(i), Operator::Deref) =
+                                    (&binary_expr.right.kind, &binary_expr.op)
                                 {
-                                    let owner = self.make_instance_identifier(left, locals);
+                                    let owner = self.make_instance_identifier(&binary_expr.left, locals);
                                     self.identifier_to_variable(i, Some(owner), accessor, locals)
                                 } else {
                                     Node::RuntimeError {
@@ -369,7 +459,7 @@ impl Compiler {
                 },
             },
 
-            ast::Expr::Function(function) => {
+            ast::ExprKind::Function(function) => {
                 let args = function.params.iter().map(|x| self.compile_ast_expr(&x, locals)).collect::<Vec<_>>().into_boxed_slice();
 
                 if let Some(script_id) = self.get_script_id(function.name) {
@@ -387,7 +477,7 @@ impl Compiler {
                 }
             },
 
-            ast::Expr::Unary(unary_expr) => {
+            ast::ExprKind::Unary(unary_expr) => {
                 let new_node = self.compile_ast_expr(&unary_expr.child, locals);
                 let operator = match unary_expr.op {
                     Operator::Add => return new_node,
@@ -409,6 +499,14 @@ impl Compiler {
         }
     }
 
+    /// Marks which line the next instructions came from, so errors can say where they happened.
+    fn mark_line(output: &mut Vec<Instruction>, number: usize) {
+        match output.last_mut() {
+            Some(Instruction::Line { number: n }) => *n = number,
+            _ => output.push(Instruction::Line { number }),
+        }
+    }
+
     /// Searches for the fieldname id.
     pub fn find_field_id(&self, name: &[u8]) -> Option<usize> {
         self.fields.iter().position(|x| x.as_ref() == name)
@@ -445,16 +543,16 @@ impl Compiler {
         };
 
         let value = self.compile_ast_expr(&binary_expr.right, locals);
-        match &binary_expr.left {
-            ast::Expr::LiteralIdentifier(string) => {
+        match &binary_expr.left.kind {
+            ast::ExprKind::LiteralIdentifier(string) => {
                 if let Some(mod_type) = modification_type {
                     self.make_modify_instruction(string, None, ArrayAccessor::None, mod_type, value, locals)
                 } else {
                     self.make_set_instruction(string, None, ArrayAccessor::None, value, locals)
                 }
             },
-            ast::Expr::Binary(binary_expr) if binary_expr.op == Operator::Deref => {
-                if let ast::Expr::LiteralIdentifier(string) = binary_expr.right {
+            ast::ExprKind::Binary(binary_expr) if binary_expr.op == Operator::Deref => {
+                if let ast::ExprKind::LiteralIdentifier(string) = binary_expr.right.kind {
                     let owner = self.make_instance_identifier(&binary_expr.left, locals);
                     if let Some(mod_type) = modification_type {
                         self.make_modify_instruction(string, Some(owner), ArrayAccessor::None, mod_type, value, locals)
@@ -465,22 +563,22 @@ impl Compiler {
                     Instruction::RuntimeError { error: gml::Error::InvalidDeref(binary_expr.right.to_string()) }
                 }
             },
-            ast::Expr::Binary(binary_expr) if binary_expr.op == Operator::Index => {
-                if let ast::Expr::Group(dimensions) = &binary_expr.right {
+            ast::ExprKind::Binary(binary_expr) if binary_expr.op == Operator::Index => {
+                if let ast::ExprKind::Group(dimensions) = &binary_expr.right.kind {
                     let accessor = match self.make_array_accessor(dimensions, locals) {
                         Ok(a) => a,
                         Err(e) => return Instruction::RuntimeError { error: gml::Error::TooManyArrayDimensions(e) },
                     };
-                    match &binary_expr.left {
-                        ast::Expr::LiteralIdentifier(string) => {
+                    match &binary_expr.left.kind {
+                        ast::ExprKind::LiteralIdentifier(string) => {
                             if let Some(mod_type) = modification_type {
                                 self.make_modify_instruction(string, None, accessor, mod_type, value, locals)
                             } else {
                                 self.make_set_instruction(string, None, accessor, value, locals)
                             }
                         },
-                        ast::Expr::Binary(binary_expr) if binary_expr.op == Operator::Deref => {
-                            if let ast::Expr::LiteralIdentifier(string) = binary_expr.right {
+                        ast::ExprKind::Binary(binary_expr) if binary_expr.op == Operator::Deref => {
+                            if let ast::ExprKind::LiteralIdentifier(string) = binary_expr.right.kind {
                                 let owner = self.make_instance_identifier(&binary_expr.left, locals);
                                 if let Some(mod_type) = modification_type {
                                     self.make_modify_instruction(string, Some(owner), accessor, mod_type, value, locals)
@@ -640,3 +738,75 @@ impl Compiler {
         self.fields.get(id).map(|s| String::from_utf8_lossy(s).into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{code, run, test_game};
+
+    fn ptr(program: &Rc<Program>) -> *const Program {
+        Rc::as_ptr(program)
+    }
+
+    #[test]
+    fn errors_say_which_line() {
+        let mut game = test_game();
+        game.fatal_errors = true;
+        match run(&mut game, 0.0, 0.0, &[code("a = 1;\n\nif a {\n    b = nope\n}")]) {
+            Err(gml::Error::Located(located)) => {
+                assert_eq!(located.location, "ERROR in\naction number 1\n\nError in code at line 4:\n");
+            },
+            _ => panic!("expected an error with a location"),
+        }
+    }
+
+    #[test]
+    fn cached_code_is_reused_until_names_change() {
+        let mut compiler = Compiler::new();
+        let first = compiler.compile_cached(b"a = 1").unwrap();
+        assert_eq!(ptr(&compiler.compile_cached(b"a = 1").unwrap()), ptr(&first));
+        assert!(compiler.compile_cached(b"a = ").is_err());
+        assert!(compiler.compile_cached(b"a = ").is_err());
+        // failures aren't remembered, so they're misses every time
+        assert_eq!(compiler.cache_stats(), (1, 3));
+
+        // any new name could change what the code means
+        compiler.register_script(b"scr_a".to_vec().into_boxed_slice(), 0);
+        assert_ne!(ptr(&compiler.compile_cached(b"a = 1").unwrap()), ptr(&first));
+        assert_eq!(compiler.cache_stats(), (1, 4));
+    }
+
+    #[test]
+    fn cache_forgets_the_oldest_code() {
+        let mut compiler = Compiler::new();
+        let oldest = compiler.compile_cached(b"a = 0").unwrap();
+        for i in 1..CODE_CACHE_SIZE {
+            compiler.compile_cached(format!("a = {}", i).as_bytes()).unwrap();
+        }
+        assert_eq!(ptr(&compiler.compile_cached(b"a = 0").unwrap()), ptr(&oldest));
+        compiler.compile_cached(b"a = -1").unwrap();
+        // "a = 0" was used again just now, but the cache goes by when things were added
+        assert_eq!(compiler.cache.programs.len(), CODE_CACHE_SIZE);
+        assert!(!compiler.cache.programs.contains_key(&b"a = 0"[..]));
+    }
+
+    #[test]
+    fn execute_string_reuses_code() {
+        let mut game = test_game();
+        let instance = run(&mut game, 0.0, 0.0, &[]).unwrap();
+        let mut context = gml::Context::with_single_instance(instance);
+        let code = [Value::from("return foo")];
+        assert!(game.execute_string(&mut context, &code).is_err());
+        assert!(game.execute_string(&mut context, &code).is_err());
+        // it only failed at runtime, so the second time round it was already compiled
+        assert_eq!(game.compiler.cache_stats(), (1, 1));
+
+        // a new name can change what the same code means
+        game.compiler.register_constant(b"foo".to_vec().into_boxed_slice(), 7.0);
+        match game.execute_string(&mut context, &code).unwrap() {
+            Value::Real(x) => assert_eq!(x, 7.into()),
+            other => panic!("expected a number, got {:?}", other),
+        }
+        assert_eq!(game.compiler.cache_stats(), (1, 2));
+    }
+}
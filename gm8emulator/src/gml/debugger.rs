@@ -0,0 +1,279 @@
+//! A GML debugger that a client can attach to over TCP, using the same Message and Information types as the
+//! control panel. It pauses the game at breakpoints, steps through code line by line, and sends back the
+//! call stack and variables whenever the game pauses. The whole game stops while it's paused, window included.
+
+use crate::{
+    game::{Game, GetAsset},
+    gml::{trace::Frame, Context, Value},
+    instance::Field,
+};
+use shared::message::{Breakpoint, Information, Message, MessageStream, StepKind};
+use std::{
+    collections::HashMap,
+    io,
+    net::{SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+pub struct Debugger {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    read_buffer: Vec<u8>,
+    breakpoints: Vec<Breakpoint>,
+    step: Option<(StepKind, usize)>, // and how deep the call stack was when it was asked for
+}
+
+impl Debugger {
+    /// Starts listening for a client on the given port. Nothing pauses until one connects.
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], port)))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener, client: None, read_buffer: Vec::new(), breakpoints: Vec::new(), step: None })
+    }
+
+    pub fn port(&self) -> io::Result<u16> {
+        Ok(self.listener.local_addr()?.port())
+    }
+
+    /// Whether the interpreter needs to say whenever it gets to a different line.
+    pub fn watching_lines(&self) -> bool {
+        self.step.is_some() || self.breakpoints.iter().any(|b| matches!(b, Breakpoint::Line { .. }))
+    }
+
+    // anything the client set up goes away with it, so the game doesn't pause with nobody to resume it
+    fn disconnect(&mut self) {
+        self.client = None;
+        self.breakpoints.clear();
+        self.step = None;
+    }
+
+    // picks up a new client if there is one, then gets the next message from it
+    // if `wait` is set this blocks until there is one, unless the client goes away
+    fn receive(&mut self, wait: bool) -> Option<Message> {
+        if self.client.is_none() {
+            if let Ok((stream, _)) = self.listener.accept() {
+                self.client = stream.set_nonblocking(true).ok().map(|_| stream);
+            }
+        }
+        loop {
+            let client = self.client.as_mut()?;
+            match client.receive_message::<Message>(&mut self.read_buffer) {
+                Ok(Some(Some(message))) => return Some(message),
+                Ok(Some(None)) if wait => std::thread::sleep(Duration::from_millis(5)),
+                Ok(Some(None)) => return None,
+                Ok(None) | Err(_) => {
+                    self.disconnect();
+                    return None
+                },
+            }
+        }
+    }
+
+    fn send(&mut self, info: &Information) {
+        if let Some(client) = self.client.as_mut() {
+            if client.send_message(info).is_err() {
+                self.disconnect();
+            }
+        }
+    }
+
+    // returns whether a paused game should carry on
+    fn handle(&mut self, message: Message, depth: usize) -> bool {
+        match message {
+            Message::SetBreakpoints { breakpoints } => self.breakpoints = breakpoints,
+            Message::DebugPause => self.step = Some((StepKind::In, depth)),
+            Message::DebugContinue => {
+                self.step = None;
+                return true
+            },
+            Message::DebugStep { kind } => {
+                self.step = Some((kind, depth));
+                return true
+            },
+            _ => (),
+        }
+        false
+    }
+}
+
+impl Game {
+    /// Lets a debugger client attach, and deals with anything it's sent since last frame.
+    pub fn debugger_poll(&mut self) {
+        if let Some(debugger) = self.debugger.as_mut() {
+            while let Some(message) = debugger.receive(false) {
+                // nothing's running between frames, so any step is as good as pausing
+                debugger.handle(message, 0);
+            }
+        }
+    }
+
+    /// Called by the interpreter whenever it gets to a different line, in case the debugger wants to stop there.
+    pub fn debug_line(&mut self, line: usize, context: &Context) {
+        let debugger = match &self.debugger {
+            Some(debugger) => debugger,
+            None => return,
+        };
+        let depth = self.code_depth();
+        let stepped = match debugger.step {
+            Some((StepKind::In, _)) => true,
+            Some((StepKind::Over, from)) => depth <= from,
+            Some((StepKind::Out, from)) => depth < from,
+            None => false,
+        };
+        let hit = || match self.call_stack.iter().rev().find(|f| !matches!(f, Frame::With(_))) {
+            Some(Frame::Script(id)) => match self.assets.scripts.get(*id).and_then(|s| s.as_ref()) {
+                Some(script) => {
+                    let name = self.decode_str(script.name.as_ref());
+                    debugger.breakpoints.iter().any(|b| match b {
+                        Breakpoint::Line { script, line: l } => *l == line && *script == name,
+                        _ => false,
+                    })
+                },
+                None => false,
+            },
+            _ => false,
+        };
+        if stepped || hit() {
+            self.debug_pause(Some(line), Some(context), context.this);
+        }
+    }
+
+    /// Called when an object's event is about to run, in case there's a breakpoint on it.
+    pub fn debug_event(&mut self, object: i32, event_type: usize, event_number: usize, instance: usize) {
+        if let Some(debugger) = &self.debugger {
+            let object = match self.assets.objects.get_asset(object) {
+                Some(o) => self.decode_str(o.name.as_ref()).into_owned(),
+                None => return,
+            };
+            if debugger.breakpoints.contains(&Breakpoint::Event { object, event_type, event_number }) {
+                self.debug_pause(None, None, instance);
+            }
+        }
+    }
+
+    // everything that's running code, which doesn't count with statements since they're inside some other code
+    fn code_depth(&self) -> usize {
+        self.call_stack.iter().filter(|f| !matches!(f, Frame::With(_))).count()
+    }
+
+    // tells the client where the game is, then waits until it says to carry on
+    fn debug_pause(&mut self, line: Option<usize>, context: Option<&Context>, this: usize) {
+        let call_stack = self.call_stack.iter().map(|f| self.describe_frame(*f)).collect::<Vec<_>>();
+        let location = match (line, call_stack.last()) {
+            (Some(line), Some(frame)) => format!("line {} of {}", line, frame),
+            (None, Some(frame)) => format!("start of {}", frame),
+            (_, None) => String::new(),
+        };
+        let info = Information::Paused {
+            location,
+            call_stack,
+            locals: context.map(|c| self.list_fields(&c.locals.fields)).unwrap_or_default(),
+            instance: self.list_fields(&self.room.instance_list.get(this).fields.borrow()),
+            globals: self.list_fields(&self.globals.fields),
+        };
+        let depth = self.code_depth();
+        if let Some(debugger) = self.debugger.as_mut() {
+            debugger.send(&info);
+            debugger.step = None;
+            while let Some(message) = debugger.receive(true) {
+                if debugger.handle(message, depth) {
+                    break
+                }
+            }
+        }
+    }
+
+    fn list_fields(&self, fields: &HashMap<usize, Field>) -> Vec<(String, String)> {
+        let show = |value: &Value| match value {
+            Value::Real(x) => x.to_string(),
+            Value::Str(s) => format!("\"{}\"", self.decode_str(s.as_ref())),
+        };
+        let mut list = Vec::new();
+        for (index, field) in fields {
+            let name = self.compiler.get_field_name(*index).unwrap_or_default();
+            match field {
+                Field::Single(value) => list.push((name, show(value))),
+                Field::Array(array) => {
+                    for (i, value) in array {
+                        let name = match (i / 32000, i % 32000) {
+                            (0, i) => format!("{}[{}]", name, i),
+                            (i, j) => format!("{}[{},{}]", name, i, j),
+                        };
+                        list.push((name, show(value)));
+                    }
+                },
+            }
+        }
+        list.sort();
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{add_script, code, run, test_game};
+
+    #[test]
+    fn steps_remember_where_they_started() {
+        let mut debugger = Debugger::listen(0).unwrap();
+        assert!(!debugger.watching_lines());
+
+        // pausing doesn't resume, but stepping and continuing do
+        assert!(!debugger.handle(Message::DebugPause, 3));
+        assert_eq!(debugger.step, Some((StepKind::In, 3)));
+        assert!(debugger.watching_lines());
+        assert!(debugger.handle(Message::DebugStep { kind: StepKind::Out }, 2));
+        assert_eq!(debugger.step, Some((StepKind::Out, 2)));
+        assert!(debugger.handle(Message::DebugContinue, 2));
+        assert!(!debugger.watching_lines());
+
+        // only line breakpoints need every line reported
+        let event = Breakpoint::Event { object: "object0".into(), event_type: 0, event_number: 0 };
+        debugger.handle(Message::SetBreakpoints { breakpoints: vec![event.clone()] }, 0);
+        assert!(!debugger.watching_lines());
+        let line = Breakpoint::Line { script: "scr_add".into(), line: 2 };
+        debugger.handle(Message::SetBreakpoints { breakpoints: vec![event, line] }, 0);
+        assert!(debugger.watching_lines());
+
+        // and a client leaving takes them all with it
+        debugger.disconnect();
+        assert!(!debugger.watching_lines() && debugger.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn debugger_stops_at_breakpoints() {
+        let mut game = test_game();
+        add_script(&mut game, "scr_add", "a = 1;\nb = 2;\nc = 3;\nreturn a + b + c");
+        game.debugger = Some(Debugger::listen(0).unwrap());
+        let port = game.debugger.as_ref().unwrap().port().unwrap();
+
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let mut buffer = Vec::new();
+            let mut stops = Vec::new();
+            let breakpoints = vec![Breakpoint::Line { script: "scr_add".into(), line: 2 }];
+            stream.send_message(Message::SetBreakpoints { breakpoints }).unwrap();
+            for next in [Message::DebugStep { kind: StepKind::Over }, Message::DebugContinue] {
+                match stream.receive_message::<Information>(&mut buffer).unwrap() {
+                    Some(Some(Information::Paused { location, instance, .. })) => stops.push((location, instance)),
+                    _ => panic!("expected the game to pause"),
+                }
+                stream.send_message(next).unwrap();
+            }
+            stops
+        });
+        while !game.debugger.as_ref().unwrap().watching_lines() {
+            game.debugger_poll();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        run(&mut game, 0.0, 0.0, &[code("scr_add()")]).unwrap();
+
+        let stops = client.join().unwrap();
+        assert_eq!(stops[0].0, "line 2 of script scr_add");
+        assert!(stops[0].1.contains(&("a".into(), "1".into())));
+        assert!(!stops[0].1.iter().any(|(name, _)| name == "b"));
+        assert_eq!(stops[1].0, "line 3 of script scr_add");
+        assert!(stops[1].1.contains(&("b".into(), "2".into())));
+    }
+}
@@ -1,7 +1,13 @@
-use image::{codecs::gif::GifDecoder, AnimationDecoder, ImageError, ImageFormat, Pixel, RgbaImage};
+mod path;
+mod vfs;
+
+pub use path::PathResolver;
+pub use vfs::{MemoryFile, Root, Roots, Vfs};
+
+use image::{codecs::gif::GifDecoder, AnimationDecoder, DynamicImage, ImageError, ImageFormat, Pixel, RgbaImage};
 use std::{
     fs::{File, OpenOptions},
-    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    io::{self, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
     path::Path,
 };
 
@@ -11,14 +17,19 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum TextHandle {
     Read(BufReader<File>),
     Write(BufWriter<File>),
+    Memory(MemoryFile),
 }
 #[derive(Debug)]
 pub enum BinaryHandle {
     Read(BufReader<File>),
     Write(BufWriter<File>),
     ReadWrite(File),
+    Memory(MemoryFile),
 }
 
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
 #[derive(Clone, Copy, Debug)]
 pub enum AccessMode {
     Read,
@@ -92,7 +103,7 @@ where
 }
 
 impl TextHandle {
-    pub fn open(path: &str, mode: AccessMode) -> io::Result<Self> {
+    pub fn open(path: &Path, mode: AccessMode) -> io::Result<Self> {
         #[rustfmt::skip]
         let (read, write, append) = match mode {
             AccessMode::Read    => (true,  false, false),
@@ -114,20 +125,29 @@ impl TextHandle {
         })
     }
 
-    fn get_reader(&mut self) -> Result<&mut BufReader<File>> {
+    fn get_reader(&mut self) -> Result<&mut dyn ReadSeek> {
         match self {
             Self::Read(f) => Ok(f),
+            Self::Memory(f) if f.read => Ok(f),
             _ => Err(Error::CantRead),
         }
     }
 
-    fn get_writer(&mut self) -> Result<&mut BufWriter<File>> {
+    fn get_writer(&mut self) -> Result<&mut dyn Write> {
         match self {
             Self::Write(f) => Ok(f),
+            Self::Memory(f) if f.write => Ok(f),
             _ => Err(Error::CantWrite),
         }
     }
 
+    pub fn as_memory(&self) -> Option<&MemoryFile> {
+        match self {
+            Self::Memory(f) => Some(f),
+            _ => None,
+        }
+    }
+
     pub fn read_real(&mut self) -> Result<f64> {
         Ok(read_real(self.get_reader()?)?)
     }
@@ -186,16 +206,17 @@ impl TextHandle {
         Ok(bytes_read == 0 || (buf[0] == 0x0d && buf[1] == 0x0a))
     }
 
-    pub fn flush(&mut self) -> Result<()> {
+    pub fn flush(&mut self, vfs: &mut Vfs) -> Result<()> {
         match self {
             Self::Read(_) => Ok(()),
             Self::Write(f) => f.flush().map_err(|e| e.into()),
+            Self::Memory(f) => vfs.commit(f).map_err(|e| e.into()),
         }
     }
 }
 
 impl BinaryHandle {
-    pub fn open(path: &str, mode: AccessMode) -> io::Result<Self> {
+    pub fn open(path: &Path, mode: AccessMode) -> io::Result<Self> {
         let file = Self::_open(path, mode)?;
         match mode {
             AccessMode::Read => Ok(Self::Read(BufReader::new(file))),
@@ -213,7 +234,7 @@ impl BinaryHandle {
     // same name between testing and opening, and also would require an additional
     // function call every time. Instead, when a read-only or write-only mode was
     // requested for a file, we try first to create it and fail if it's exists.
-    fn _open(path: &str, mode: AccessMode) -> io::Result<File> {
+    fn _open(path: &Path, mode: AccessMode) -> io::Result<File> {
         let mut opts = OpenOptions::new();
 
         #[rustfmt::skip]
@@ -247,6 +268,8 @@ impl BinaryHandle {
             Self::Read(f) => Ok(f),
             Self::Write(_) => Err(Error::CantRead),
             Self::ReadWrite(f) => Ok(f),
+            Self::Memory(f) if f.read => Ok(f),
+            Self::Memory(_) => Err(Error::CantRead),
         }
     }
 
@@ -255,6 +278,8 @@ impl BinaryHandle {
             Self::Read(_) => Err(Error::CantWrite),
             Self::Write(f) => Ok(f),
             Self::ReadWrite(f) => Ok(f),
+            Self::Memory(f) if f.write => Ok(f),
+            Self::Memory(_) => Err(Error::CantWrite),
         }
     }
 
@@ -263,6 +288,14 @@ impl BinaryHandle {
             Self::Read(f) => f,
             Self::Write(f) => f,
             Self::ReadWrite(f) => f,
+            Self::Memory(f) => f,
+        }
+    }
+
+    pub fn as_memory(&self) -> Option<&MemoryFile> {
+        match self {
+            Self::Memory(f) => Some(f),
+            _ => None,
         }
     }
 
@@ -271,6 +304,7 @@ impl BinaryHandle {
             Self::Read(_) => return Err(Error::CantWrite),
             Self::Write(f) => f.get_mut(),
             Self::ReadWrite(f) => f,
+            Self::Memory(f) => return f.clear(),
         };
         f.seek(SeekFrom::Start(0))?;
         f.set_len(0)?;
@@ -301,11 +335,12 @@ impl BinaryHandle {
         Ok(self.get_seeker().stream_len()?)
     }
 
-    pub fn flush(&mut self) -> Result<()> {
+    pub fn flush(&mut self, vfs: &mut Vfs) -> Result<()> {
         match self {
             Self::Read(_) => Ok(()),
             Self::Write(f) => f.flush(),
             Self::ReadWrite(f) => f.flush(),
+            Self::Memory(f) => vfs.commit(f),
         }
         .map_err(|e| e.into())
     }
@@ -369,50 +404,80 @@ pub fn skip_line(f: impl Read) -> io::Result<()> {
     Ok(())
 }
 
-pub fn file_exists(path: &str) -> bool {
-    Path::new(path).is_file()
+pub fn file_exists(path: &Path) -> bool {
+    path.is_file()
 }
 
-pub fn rename(from: &str, to: &str) -> Result<()> {
-    if !Path::new(to).exists() {
+pub fn rename(from: &Path, to: &Path) -> Result<()> {
+    if !to.exists() {
         std::fs::rename(from, to)?;
     }
     Ok(())
 }
 
-pub fn copy(from: &str, to: &str) -> Result<()> {
+pub fn copy(from: &Path, to: &Path) -> Result<()> {
     std::fs::copy(from, to)?;
     Ok(())
 }
 
-pub fn dir_exists(path: &str) -> bool {
-    Path::new(path).is_dir()
+pub fn dir_exists(path: &Path) -> bool {
+    path.is_dir()
 }
 
-pub fn dir_create(path: &str) -> Result<()> {
+pub fn dir_create(path: &Path) -> Result<()> {
     std::fs::create_dir_all(path)?;
     Ok(())
 }
 
-pub fn delete(path: &str) -> Result<()> {
-    if Path::new(path).exists() {
+pub fn delete(path: &Path) -> Result<()> {
+    if path.exists() {
         std::fs::remove_file(path)?;
     }
     Ok(())
 }
 
-pub fn load_image(path: &str) -> Result<RgbaImage> {
-    Ok(image::open(path)?.into_rgba8())
+pub const FA_READONLY: u32 = 1;
+pub const FA_HIDDEN: u32 = 2;
+pub const FA_DIRECTORY: u32 = 16;
+pub const FA_ARCHIVE: u32 = 32;
+
+/// Gets the attributes Windows would give a file, or None if it doesn't exist.
+#[cfg(target_os = "windows")]
+pub fn attributes(path: &Path) -> Option<u32> {
+    use std::os::windows::fs::MetadataExt;
+    Some(path.metadata().ok()?.file_attributes() & 0x3f)
 }
 
-pub fn load_animation(path: &str, imgnumb: usize) -> Result<Vec<RgbaImage>> {
-    if ImageFormat::from_path(path)? == ImageFormat::Gif {
-        GifDecoder::new(BufReader::new(File::open(path)?))?
+/// Gets the attributes Windows would give a file, or None if it doesn't exist.
+/// Dotfiles count as hidden, and files are always ready for archiving since nothing here ever clears that.
+#[cfg(not(target_os = "windows"))]
+pub fn attributes(path: &Path) -> Option<u32> {
+    let md = path.metadata().ok()?;
+    let hidden = matches!(path.file_name().and_then(|n| n.to_str()), Some(n) if n.starts_with('.'));
+    let mut attributes = 0;
+    if md.permissions().readonly() {
+        attributes |= FA_READONLY;
+    }
+    if hidden {
+        attributes |= FA_HIDDEN;
+    }
+    attributes |= if md.is_dir() { FA_DIRECTORY } else { FA_ARCHIVE };
+    Some(attributes)
+}
+
+pub fn load_image(vfs: &Vfs, path: &Path) -> Result<RgbaImage> {
+    Ok(image::load_from_memory(&vfs.read(path)?)?.into_rgba8())
+}
+
+pub fn load_animation(vfs: &Vfs, path: &Path, imgnumb: usize) -> Result<Vec<RgbaImage>> {
+    let data = vfs.read(path)?;
+    if image::guess_format(&data)? == ImageFormat::Gif {
+        GifDecoder::new(data.as_slice())?
             .into_frames()
             .map(|r| r.map(|f| f.into_buffer()).map_err(Error::from))
             .collect()
     } else {
-        let image = load_image(path.as_ref())?;
+        let image = image::load_from_memory(&data)?.into_rgba8();
         let sprite_width = image.width() as usize / imgnumb;
         let sprite_height = image.height() as usize;
         // get pixel data for each frame
@@ -434,12 +499,15 @@ pub fn load_animation(path: &str, imgnumb: usize) -> Result<Vec<RgbaImage>> {
     }
 }
 
-pub fn save_image<P: AsRef<Path>>(path: P, image: RgbaImage) -> Result<()> {
+pub fn save_image(vfs: &mut Vfs, path: &Path, image: RgbaImage) -> Result<()> {
     // save to png if the filename is .png otherwise bmp regardless of filename
-    if path.as_ref().extension().and_then(|s| s.to_str()).map(|s| s.eq_ignore_ascii_case("png")).unwrap_or(false) {
-        image.save_with_format(path, ImageFormat::Png)?;
+    let format = if path.extension().and_then(|s| s.to_str()).map(|s| s.eq_ignore_ascii_case("png")).unwrap_or(false) {
+        ImageFormat::Png
     } else {
-        image.save_with_format(path, ImageFormat::Bmp)?;
-    }
+        ImageFormat::Bmp
+    };
+    let mut data = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(image).write_to(&mut data, format)?;
+    vfs.write(path, data.into_inner())?;
     Ok(())
 }
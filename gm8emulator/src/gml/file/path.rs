@@ -0,0 +1,116 @@
+//! Turns the Windows paths games use into ones the host understands.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+/// How many resolved paths are remembered before starting over, so games that make lots of files don't fill memory.
+const CACHE_LIMIT: usize = 4096;
+
+/// On Windows, paths are used as they are. Everywhere else backslashes become slashes, drive letters are mapped
+/// to directories (by default they're all the root directory, like Wine's Z: drive) and each part of the path
+/// is matched case-insensitively against what's on disk, since games often get the case wrong.
+#[derive(Clone, Default)]
+pub struct PathResolver {
+    drives: HashMap<u8, PathBuf>, // keyed by lowercase letter
+    cache: RefCell<HashMap<String, PathBuf>>,
+}
+
+impl PathResolver {
+    /// Maps a drive letter to a directory on the host.
+    pub fn set_drive(&mut self, letter: char, root: PathBuf) -> Result<(), String> {
+        if !letter.is_ascii_alphabetic() {
+            return Err(format!("{} isn't a drive letter", letter))
+        }
+        self.drives.insert(letter.to_ascii_lowercase() as u8, root);
+        self.cache.borrow_mut().clear();
+        Ok(())
+    }
+
+    pub fn resolve(&self, path: &str) -> PathBuf {
+        if cfg!(target_os = "windows") {
+            return path.into()
+        }
+
+        let path = path.replace('\\', "/");
+        let (mut resolved, rest) = match path.as_bytes() {
+            [letter, b':', ..] if letter.is_ascii_alphabetic() => {
+                let root = self.drives.get(&letter.to_ascii_lowercase()).cloned().unwrap_or_else(|| "/".into());
+                (root, &path[2..])
+            },
+            [b'/', ..] => (PathBuf::from("/"), path.as_str()),
+            _ => (PathBuf::new(), path.as_str()),
+        };
+        let key = format!("{}/{}", resolved.to_string_lossy(), rest);
+        if let Some(cached) = self.cache.borrow().get(&key).filter(|p| p.exists()) {
+            return cached.clone()
+        }
+
+        // once something doesn't exist, the rest of the path can't either, so it's left how it was
+        let mut found = true;
+        for part in rest.split('/').filter(|p| !p.is_empty()) {
+            if found && part != "." && part != ".." && !resolved.join(part).exists() {
+                match find_ignoring_case(&resolved, part) {
+                    Some(name) => {
+                        resolved.push(name);
+                        continue
+                    },
+                    None => found = false,
+                }
+            }
+            resolved.push(part);
+        }
+        if found {
+            let mut cache = self.cache.borrow_mut();
+            if cache.len() >= CACHE_LIMIT {
+                cache.clear();
+            }
+            cache.insert(key, resolved.clone());
+        }
+        resolved
+    }
+}
+
+fn find_ignoring_case(dir: &Path, name: &str) -> Option<OsString> {
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let name = name.to_lowercase();
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.file_name())
+        .find(|entry| matches!(entry.to_str(), Some(entry) if entry.to_lowercase() == name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn resolve() {
+        let root = std::env::temp_dir().join(format!("gm8emulator_paths_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("Data/Levels")).unwrap();
+        std::fs::write(root.join("Data/Levels/Level1.TXT"), b"").unwrap();
+
+        let mut paths = PathResolver::default();
+        paths.set_drive('C', root.clone()).unwrap();
+        assert_eq!(paths.resolve("c:\\data\\levels\\level1.txt"), root.join("Data/Levels/Level1.TXT"));
+        assert_eq!(paths.resolve("C:\\DATA\\new\\file.txt"), root.join("Data/new/file.txt"));
+        assert_eq!(paths.resolve("Z:\\tmp"), PathBuf::from("/tmp"));
+        let relative = format!("{}\\data\\LEVELS", root.to_string_lossy());
+        assert_eq!(paths.resolve(&relative), root.join("Data/Levels"));
+        assert_eq!(paths.resolve(&relative), root.join("Data/Levels")); // cached
+
+        // the cache doesn't grow forever
+        for i in 0..CACHE_LIMIT * 2 {
+            std::fs::write(root.join(format!("{}.txt", i)), b"").unwrap();
+            paths.resolve(&format!("C:\\{}.txt", i));
+        }
+        assert!(paths.cache.borrow().len() <= CACHE_LIMIT);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,442 @@
+//! The filesystem as the game sees it.
+//! When recording or replaying, nothing the game writes goes to disk. It goes into an overlay in memory instead,
+//! which gets saved in savestates along with any open files, so loading one puts the files back how they were.
+//! Paths in the overlay are kept relative to the game's folder or temp folder where they can be, so a savestate
+//! still finds its files when it's loaded somewhere else.
+
+use super::{AccessMode, BinaryHandle, Error, Result, TextHandle, FA_ARCHIVE, FA_DIRECTORY};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Vfs {
+    overlay: Option<HashMap<String, Entry>>, // None means straight to disk
+    // these belong to wherever the game's running, so they never come from a savestate
+    #[serde(skip)]
+    roots: Roots,
+}
+
+/// The folders paths are kept relative to.
+#[derive(Clone, Default)]
+pub struct Roots {
+    game: PathBuf,
+    temp: Option<PathBuf>,
+}
+
+/// What a path in the overlay is relative to.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Root {
+    Game,
+    Temp,
+    Host, // anywhere else, so the path's absolute
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+enum Entry {
+    File(Root, PathBuf, Vec<u8>),
+    Dir(Root, PathBuf),
+    Deleted,
+}
+
+/// A file opened through the overlay. Changes only go back into it when it's flushed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MemoryFile {
+    path: PathBuf,
+    data: Vec<u8>,
+    pos: u64,
+    pub(super) read: bool,
+    pub(super) write: bool,
+    dirty: bool,
+}
+
+impl Vfs {
+    pub fn new(overlay: bool) -> Self {
+        let game = std::env::current_dir().unwrap_or_default();
+        Self { overlay: if overlay { Some(HashMap::new()) } else { None }, roots: Roots { game, temp: None } }
+    }
+
+    /// Sets the folders that paths in the overlay are kept relative to. Relative paths are in the game's folder.
+    pub fn set_roots(&mut self, game: &Path, temp: &Path) {
+        let game = normalise(Path::new(""), game);
+        let temp = Some(normalise(&game, temp)).filter(|temp| *temp != game);
+        self.roots = Roots { game, temp };
+    }
+
+    /// Takes the overlay from a savestate, keeping the folders it's relative to.
+    pub fn restore(&mut self, saved: Vfs) {
+        self.overlay = saved.overlay;
+    }
+
+    pub fn roots(&self) -> &Roots {
+        &self.roots
+    }
+
+    // games expect paths to be case-insensitive, like they are on Windows
+    fn key(&self, path: &Path) -> String {
+        let (root, path) = self.roots.locate(path);
+        format!("{:?}:{}", root, path.to_string_lossy().to_lowercase())
+    }
+
+    // None if it's not in the overlay, or there's no overlay at all
+    fn entry(&self, path: &Path) -> Option<&Entry> {
+        self.overlay.as_ref()?.get(&self.key(path))
+    }
+
+    fn set(&mut self, path: &Path, entry: Entry) {
+        let key = self.key(path);
+        if let Some(overlay) = self.overlay.as_mut() {
+            overlay.insert(key, entry);
+        }
+    }
+
+    pub fn open_text(&mut self, path: &Path, mode: AccessMode) -> io::Result<TextHandle> {
+        if self.overlay.is_none() {
+            return TextHandle::open(path, mode)
+        }
+        let file = match mode {
+            AccessMode::Read => MemoryFile::new(path, self.read(path)?, true, false),
+            AccessMode::Write => {
+                self.write(path, Vec::new())?;
+                MemoryFile::new(path, Vec::new(), false, true)
+            },
+            AccessMode::Special => {
+                let data = self.read_or_create(path)?.unwrap_or_default();
+                let mut file = MemoryFile::new(path, data, false, true);
+                file.pos = file.data.len() as u64;
+                file
+            },
+        };
+        Ok(TextHandle::Memory(file))
+    }
+
+    pub fn open_binary(&mut self, path: &Path, mode: AccessMode) -> io::Result<BinaryHandle> {
+        if self.overlay.is_none() {
+            return BinaryHandle::open(path, mode)
+        }
+        // same as on disk, a file that had to be created can always be read and written
+        let file = match self.read_or_create(path)? {
+            Some(data) => match mode {
+                AccessMode::Read => MemoryFile::new(path, data, true, false),
+                AccessMode::Write => MemoryFile::new(path, data, false, true),
+                AccessMode::Special => MemoryFile::new(path, data, true, true),
+            },
+            None => MemoryFile::new(path, Vec::new(), true, true),
+        };
+        Ok(BinaryHandle::Memory(file))
+    }
+
+    // Some(contents) if it was there, None if it had to be created
+    fn read_or_create(&mut self, path: &Path) -> io::Result<Option<Vec<u8>>> {
+        match self.read(path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.write(path, Vec::new())?;
+                Ok(None)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Puts whatever was written to a file opened through the overlay back into the overlay.
+    pub fn commit(&mut self, file: &mut MemoryFile) -> io::Result<()> {
+        if file.dirty {
+            self.write(&file.path, file.data.clone())?;
+            file.dirty = false;
+        }
+        Ok(())
+    }
+
+    pub fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.entry(path) {
+            Some(Entry::File(_, _, data)) => Ok(data.clone()),
+            Some(Entry::Dir(..)) => Err(io::Error::other("is a directory")),
+            Some(Entry::Deleted) => Err(io::ErrorKind::NotFound.into()),
+            None => std::fs::read(path),
+        }
+    }
+
+    pub fn write(&mut self, path: &Path, data: Vec<u8>) -> io::Result<()> {
+        if self.overlay.is_none() {
+            return std::fs::write(path, data)
+        }
+        if self.dir_exists(path) {
+            return Err(io::Error::other("is a directory"))
+        }
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            if !self.dir_exists(parent) {
+                return Err(io::ErrorKind::NotFound.into())
+            }
+        }
+        let (root, relative) = self.roots.locate(path);
+        self.set(path, Entry::File(root, relative, data));
+        Ok(())
+    }
+
+    pub fn file_exists(&self, path: &Path) -> bool {
+        match self.entry(path) {
+            Some(entry) => matches!(entry, Entry::File(..)),
+            None => super::file_exists(path),
+        }
+    }
+
+    pub fn dir_exists(&self, path: &Path) -> bool {
+        match self.entry(path) {
+            Some(entry) => matches!(entry, Entry::Dir(..)),
+            None => super::dir_exists(path),
+        }
+    }
+
+    pub fn dir_create(&mut self, path: &Path) -> Result<()> {
+        if self.overlay.is_none() {
+            return super::dir_create(path)
+        }
+        for dir in path.ancestors().filter(|p| !p.as_os_str().is_empty()) {
+            if self.dir_exists(dir) {
+                break
+            }
+            if self.file_exists(dir) {
+                return Err(io::Error::from(io::ErrorKind::AlreadyExists).into())
+            }
+            let (root, relative) = self.roots.locate(dir);
+            self.set(dir, Entry::Dir(root, relative));
+        }
+        Ok(())
+    }
+
+    pub fn delete(&mut self, path: &Path) -> Result<()> {
+        if self.overlay.is_none() {
+            return super::delete(path)
+        }
+        if self.file_exists(path) {
+            self.set(path, Entry::Deleted);
+        }
+        Ok(())
+    }
+
+    pub fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+        if self.overlay.is_none() {
+            return super::rename(from, to)
+        }
+        if !self.file_exists(to) && !self.dir_exists(to) {
+            let data = self.read(from)?;
+            self.write(to, data)?;
+            self.set(from, Entry::Deleted);
+        }
+        Ok(())
+    }
+
+    pub fn copy(&mut self, from: &Path, to: &Path) -> Result<()> {
+        if self.overlay.is_none() {
+            return super::copy(from, to)
+        }
+        let data = self.read(from)?;
+        self.write(to, data)?;
+        Ok(())
+    }
+
+    pub fn attributes(&self, path: &Path) -> Option<u32> {
+        match self.entry(path) {
+            Some(Entry::File(..)) => Some(FA_ARCHIVE),
+            Some(Entry::Dir(..)) => Some(FA_DIRECTORY),
+            Some(Entry::Deleted) => None,
+            None => super::attributes(path),
+        }
+    }
+
+    /// Everything in a directory with a name matching the pattern, sorted by name.
+    pub fn find(&self, dir: &Path, pattern: &str) -> std::result::Result<Vec<PathBuf>, glob::PatternError> {
+        let pattern = glob::Pattern::new(pattern)?;
+        let options = glob::MatchOptions { case_sensitive: false, ..Default::default() };
+        let read_dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let mut found = std::fs::read_dir(read_dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|p| self.entry(p).is_none())
+            .collect::<Vec<_>>();
+        if let Some(overlay) = self.overlay.as_ref() {
+            let dir = self.key(dir);
+            let in_dir = overlay
+                .values()
+                .filter_map(|entry| match entry {
+                    Entry::File(root, p, _) | Entry::Dir(root, p) => Some(self.roots.host_path(*root, p)),
+                    Entry::Deleted => None,
+                })
+                .filter(|p| p.parent().map(|p| self.key(p) == dir).unwrap_or(false));
+            found.extend(in_dir);
+        }
+        let matches = |p: &PathBuf| p.file_name().and_then(|n| n.to_str()).map(|n| pattern.matches_with(n, options));
+        found.retain(|p| matches(p) == Some(true));
+        found.sort_by_cached_key(|p| p.file_name().map(|n| n.to_string_lossy().to_lowercase()));
+        Ok(found)
+    }
+}
+
+// relative to `base` and without any . or .., so the same file always gets the same path
+impl Roots {
+    /// Which root a path is in, and where it is relative to that.
+    pub fn locate(&self, path: &Path) -> (Root, PathBuf) {
+        let path = normalise(&self.game, path);
+        // the temp folder comes first, since it can be inside the game's folder
+        let roots = self.temp.iter().map(|t| (Root::Temp, t)).chain(Some((Root::Game, &self.game)));
+        for (root, dir) in roots {
+            if let Ok(relative) = path.strip_prefix(dir) {
+                return (root, relative.into())
+            }
+        }
+        (Root::Host, path)
+    }
+
+    /// Where a path given by `locate` is on this host.
+    pub fn host_path(&self, root: Root, path: &Path) -> PathBuf {
+        match (root, &self.temp) {
+            (Root::Game, _) => self.game.join(path),
+            (Root::Temp, Some(temp)) => temp.join(path),
+            (Root::Temp, None) => std::env::temp_dir().join(path),
+            (Root::Host, _) => path.into(),
+        }
+    }
+}
+
+fn normalise(base: &Path, path: &Path) -> PathBuf {
+    let mut normalised = PathBuf::new();
+    for component in base.join(path).components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalised.pop();
+            },
+            c => normalised.push(c),
+        }
+    }
+    normalised
+}
+
+impl MemoryFile {
+    fn new(path: &Path, data: Vec<u8>, read: bool, write: bool) -> Self {
+        Self { path: path.into(), data, pos: 0, read, write, dirty: false }
+    }
+
+    pub fn clear(&mut self) -> Result<()> {
+        if !self.write {
+            return Err(Error::CantWrite)
+        }
+        self.data.clear();
+        self.pos = 0;
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+impl Read for MemoryFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = (self.pos as usize).min(self.data.len());
+        let count = (&self.data[start..]).read(buf)?;
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = self.pos as usize;
+        let end = start + buf.len();
+        if self.data.len() < end {
+            self.data.resize(end, 0);
+        }
+        self.data[start..end].copy_from_slice(buf);
+        self.pos = end as u64;
+        self.dirty = true;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => (offset, 0),
+            SeekFrom::End(offset) => (self.data.len() as u64, offset),
+            SeekFrom::Current(offset) => (self.pos, offset),
+        };
+        match base.checked_add_signed(offset) {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            },
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative or overflowing position")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlay() {
+        let root = std::env::temp_dir().join(format!("gm8emulator_vfs_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("disk.txt"), b"on disk").unwrap();
+
+        let mut vfs = Vfs::new(true);
+        let mut file = match vfs.open_text(&root.join("save.txt"), AccessMode::Write).unwrap() {
+            TextHandle::Memory(f) => f,
+            _ => panic!("file was opened on disk"),
+        };
+        file.write_all(b"hello").unwrap();
+        vfs.commit(&mut file).unwrap();
+        assert_eq!(vfs.read(&root.join("SAVE.TXT")).unwrap(), b"hello");
+        vfs.dir_create(&root.join("a/b")).unwrap();
+        vfs.copy(&root.join("disk.txt"), &root.join("a/b/copy.txt")).unwrap();
+        vfs.delete(&root.join("disk.txt")).unwrap();
+        assert!(!vfs.file_exists(&root.join("disk.txt")));
+        assert_eq!(vfs.attributes(&root.join("a")), Some(FA_DIRECTORY));
+        assert_eq!(vfs.find(&root.join("a/b"), "*.TXT").unwrap(), vec![root.join("a/b/copy.txt")]);
+        assert_eq!(vfs.find(&root, "*").unwrap(), vec![root.join("a"), root.join("save.txt")]);
+
+        // none of that touched the disk
+        assert_eq!(std::fs::read(root.join("disk.txt")).unwrap(), b"on disk");
+        assert!(!root.join("save.txt").exists() && !root.join("a").exists());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn overlays_move_with_the_game() {
+        let root = std::env::temp_dir().join(format!("gm8emulator_vfs_roots_{}", std::process::id()));
+        let (here, there) = (root.join("here"), root.join("there"));
+        for dir in [&here, &there] {
+            std::fs::create_dir_all(dir.join("temp")).unwrap();
+        }
+        let mut vfs = Vfs::new(true);
+        vfs.set_roots(&here, &here.join("temp"));
+        vfs.write(Path::new("save.txt"), b"relative".to_vec()).unwrap();
+        vfs.write(&here.join("./temp/../Options.ini"), b"absolute".to_vec()).unwrap();
+        vfs.write(&here.join("temp/scratch.dat"), b"temporary".to_vec()).unwrap();
+        assert_eq!(vfs.read(&here.join("SAVE.TXT")).unwrap(), b"relative");
+        assert_eq!(vfs.roots.locate(&here.join("temp/x")), (Root::Temp, PathBuf::from("x")));
+        assert_eq!(vfs.roots.locate(&root.join("x")), (Root::Host, root.join("x")));
+
+        // a savestate's overlay goes wherever the game that loads it is
+        let saved: Vfs = bincode::deserialize(&bincode::serialize(&vfs).unwrap()).unwrap();
+        let mut vfs = Vfs::new(true);
+        vfs.set_roots(&there, &there.join("temp"));
+        vfs.restore(saved);
+        assert_eq!(vfs.read(&there.join("save.txt")).unwrap(), b"relative");
+        assert_eq!(vfs.read(&there.join("options.ini")).unwrap(), b"absolute");
+        assert_eq!(vfs.read(&there.join("temp/scratch.dat")).unwrap(), b"temporary");
+        assert!(!vfs.file_exists(&here.join("save.txt")));
+        assert_eq!(vfs.find(&there, "*.*").unwrap(), vec![there.join("Options.ini"), there.join("save.txt")]);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -11,7 +11,9 @@ use crate::{
     gml::{
         self,
         datetime::{self, DateTime},
-        ds, file, mappings, network, Context, Value,
+        ds, file, mappings, mci, network, process,
+        trace::{self, Frame},
+        Context, Value,
     },
     handleman::HandleManager,
     instance::{Field, Instance, InstanceState},
@@ -19,7 +21,7 @@ use crate::{
     tile::Tile,
 };
 use gmio::{
-    render::{BlendType, Fog, Light, Renderer, RendererOptions, Scaling},
+    render::{Backend, BlendType, Fog, Light, Renderer, RendererOptions, Scaling},
     window,
     window::Cursor,
 };
@@ -28,7 +30,6 @@ use shared::{input::MouseButton, types::Colour};
 use std::{
     convert::TryFrom,
     io::{Read, Write},
-    process::Command,
 };
 
 macro_rules! _arg_into {
@@ -456,7 +457,7 @@ impl Game {
         let rgba = self.renderer.get_pixels(0, 0, width as _, height as _);
         let mut image = RgbaImage::from_vec(width, height, rgba.into()).unwrap();
         asset::sprite::process_image(&mut image, false, false, true);
-        match file::save_image(fname.as_ref(), image) {
+        match file::save_image(&mut self.vfs, &self.path_resolver.resolve(fname.as_ref()), image) {
             Ok(()) => Ok(Default::default()),
             Err(e) => Err(gml::Error::FunctionError("screen_save".into(), e.to_string())),
         }
@@ -472,7 +473,7 @@ impl Game {
         let rgba = self.renderer.get_pixels(x, y, w, h);
         let mut image = RgbaImage::from_vec(w as _, h as _, rgba.into()).unwrap();
         asset::sprite::process_image(&mut image, false, false, true);
-        match file::save_image(fname.as_ref(), image) {
+        match file::save_image(&mut self.vfs, &self.path_resolver.resolve(fname.as_ref()), image) {
             Ok(()) => Ok(Default::default()),
             Err(e) => Err(gml::Error::FunctionError("screen_save_part".into(), e.to_string())),
         }
@@ -2281,7 +2282,7 @@ impl Game {
                 RgbaImage::from_vec(surf.width, surf.height, self.renderer.dump_sprite(&surf.atlas_ref).into())
                     .unwrap();
             asset::sprite::process_image(&mut image, false, false, true);
-            match file::save_image(fname.as_ref(), image) {
+            match file::save_image(&mut self.vfs, &self.path_resolver.resolve(fname.as_ref()), image) {
                 Ok(()) => Ok(Default::default()),
                 Err(e) => Err(gml::Error::FunctionError("surface_save".into(), e.to_string())),
             }
@@ -2304,7 +2305,7 @@ impl Game {
                 RgbaImage::from_vec(w as _, h as _, self.renderer.dump_sprite_part(&surf.atlas_ref, x, y, w, h).into())
                     .unwrap();
             asset::sprite::process_image(&mut image, false, false, true);
-            match file::save_image(fname.as_ref(), image) {
+            match file::save_image(&mut self.vfs, &self.path_resolver.resolve(fname.as_ref()), image) {
                 Ok(()) => Ok(Default::default()),
                 Err(e) => Err(gml::Error::FunctionError("surface_save_part".into(), e.to_string())),
             }
@@ -2328,9 +2329,10 @@ impl Game {
         unimplemented!("Called unimplemented kernel function surface_copy_part")
     }
 
-    pub fn action_path_old(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 3
-        unimplemented!("Called unimplemented kernel function action_path_old")
+    pub fn action_path_old(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
+        let (path_id, speed, end_action) = expect_args!(args, [any, any, any])?;
+        // paths used to always start from wherever the instance was
+        self.path_start(context, &[path_id, speed, end_action, false.into()])
     }
 
     pub fn action_set_sprite(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
@@ -2342,24 +2344,29 @@ impl Game {
         Ok(Default::default())
     }
 
-    pub fn action_draw_font(&mut self, _context: &mut Context, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function action_draw_font")
+    pub fn action_draw_font(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let font_id = expect_args!(args, [any])?;
+        self.draw_set_font(&[font_id])
     }
 
-    pub fn action_draw_font_old(&mut self, _context: &mut Context, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 6
-        unimplemented!("Called unimplemented kernel function action_draw_font_old")
+    pub fn action_draw_font_old(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (_name, _size, colour, _bold, _italic, align) = expect_args!(args, [any, any, any, any, any, any])?;
+        // TODO: this picked a system font by name, which there's no way to do yet, so it gets the default font
+        self.draw_font_id = -1;
+        self.draw_set_color(&[colour])?;
+        self.draw_set_halign(&[align])
     }
 
-    pub fn action_fill_color(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function action_fill_color")
+    // fill and line colours were separate once, but now they're both the drawing colour
+
+    pub fn action_fill_color(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let colour = expect_args!(args, [any])?;
+        self.draw_set_color(&[colour])
     }
 
-    pub fn action_line_color(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function action_line_color")
+    pub fn action_line_color(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let colour = expect_args!(args, [any])?;
+        self.draw_set_color(&[colour])
     }
 
     pub fn action_highscore(&mut self, _args: &[Value]) -> gml::Result<Value> {
@@ -2745,16 +2752,14 @@ impl Game {
         Ok(Default::default())
     }
 
-    pub fn action_sound(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 2
-        //unimplemented!("Called unimplemented kernel function action_sound")
-        // TODO
-        Ok(Default::default())
+    pub fn action_sound(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (sound_id, looping) = expect_args!(args, [any, bool])?;
+        if looping { self.sound_loop(&[sound_id]) } else { self.sound_play(&[sound_id]) }
     }
 
-    pub fn action_if_sound(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function action_if_sound")
+    pub fn action_if_sound(&self, args: &[Value]) -> gml::Result<Value> {
+        let sound_id = expect_args!(args, [any])?;
+        self.sound_isplaying(&[sound_id])
     }
 
     pub fn action_another_room(&mut self, args: &[Value]) -> gml::Result<Value> {
@@ -2896,19 +2901,32 @@ impl Game {
         unimplemented!("Called unimplemented kernel function action_splash_settings")
     }
 
-    pub fn action_replace_sprite(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 3
-        unimplemented!("Called unimplemented kernel function action_replace_sprite")
+    pub fn action_replace_sprite(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (sprite_id, fname, imgnumb) = expect_args!(args, [int, any, any])?;
+        // the new sprite keeps the old one's origin
+        let (origin_x, origin_y) = match self.assets.sprites.get_asset(sprite_id) {
+            Some(sprite) => (sprite.origin_x, sprite.origin_y),
+            None => return Err(gml::Error::NonexistentAsset(asset::Type::Sprite, sprite_id)),
+        };
+        self.sprite_replace(&[
+            sprite_id.into(),
+            fname,
+            imgnumb,
+            false.into(),
+            false.into(),
+            origin_x.into(),
+            origin_y.into(),
+        ])
     }
 
-    pub fn action_replace_sound(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 2
-        unimplemented!("Called unimplemented kernel function action_replace_sound")
+    pub fn action_replace_sound(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (sound_id, fname) = expect_args!(args, [any, any])?;
+        self.sound_replace(&[sound_id, fname, 0.into(), true.into()])
     }
 
-    pub fn action_replace_background(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 2
-        unimplemented!("Called unimplemented kernel function action_replace_background")
+    pub fn action_replace_background(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (background_id, fname) = expect_args!(args, [any, any])?;
+        self.background_replace(&[background_id, fname, false.into(), false.into()])
     }
 
     pub fn action_if_empty(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
@@ -3014,7 +3032,7 @@ impl Game {
                     Default::default(),
                     Default::default(),
                 ], 5);
-            self.execute(&instructions, &mut new_context)?;
+            self.with_frame(Frame::Script(script_id as usize), |game| game.execute(&instructions, &mut new_context))?;
             Ok(new_context.return_value)
         } else {
             Err(gml::Error::NonexistentAsset(asset::Type::Script, script_id))
@@ -3385,9 +3403,9 @@ impl Game {
         Ok(Default::default())
     }
 
-    pub fn action_webpage(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function action_webpage")
+    pub fn action_webpage(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let url = expect_args!(args, [any])?;
+        self.execute_shell(&[url, "".into()])
     }
 
     pub fn action_draw_sprite(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
@@ -5345,9 +5363,11 @@ impl Game {
 
     pub fn game_load(&mut self, args: &[Value]) -> gml::Result<Value> {
         let fname = expect_args!(args, [string])?;
-        let mut file = std::fs::File::open(fname.as_ref())
-            .map(std::io::BufReader::new)
+        let data = self
+            .vfs
+            .read(&self.path_resolver.resolve(fname.as_ref()))
             .map_err(|e| gml::Error::FunctionError("game_load".into(), format!("{}", e)))?;
+        let mut file = data.as_slice();
         let mut magnum = [0u8; 4];
         file.read(&mut magnum).map_err(|e| gml::Error::FunctionError("game_load".into(), format!("{}", e)))?;
         if magnum != [0x1d, 0x02, 0x00, 0x00] {
@@ -5362,15 +5382,13 @@ impl Game {
     pub fn game_save(&mut self, args: &[Value]) -> gml::Result<Value> {
         let fname = expect_args!(args, [string])?;
         let save = GMSave::from_game(self);
-        let mut file = std::fs::File::create(fname.as_ref())
-            .map(std::io::BufWriter::new)
-            .map_err(|e| gml::Error::FunctionError("game_save".into(), format!("{}", e)))?;
         // write magic number (0x21c in GM8)
-        file.write(&[0x1d, 0x02, 0x00, 0x00])
-            .map_err(|e| gml::Error::FunctionError("game_save".into(), format!("{}", e)))?;
+        let mut file = vec![0x1d, 0x02, 0x00, 0x00];
         bincode::serialize_into(&mut file, &save)
             .map_err(|e| gml::Error::FunctionError("game_save".into(), format!("{}", e)))?;
-        file.flush().map_err(|e| gml::Error::FunctionError("game_save".into(), e.to_string()))?;
+        self.vfs
+            .write(&self.path_resolver.resolve(fname.as_ref()), file)
+            .map_err(|e| gml::Error::FunctionError("game_save".into(), e.to_string()))?;
         Ok(Default::default())
     }
 
@@ -5474,7 +5492,8 @@ impl Game {
             1 => file::AccessMode::Write,
             2 | _ => file::AccessMode::Special,
         };
-        match self.binary_files.add_from(|| Ok(file::BinaryHandle::open(filename.as_ref(), mode)?)) {
+        let file = self.vfs.open_binary(&self.path_resolver.resolve(filename.as_ref()), mode);
+        match self.binary_files.add_from(|| Ok(file?)) {
             Ok(i) => Ok((i + 1).into()),
             Err(e) => Err(gml::Error::FunctionError("file_bin_open".into(), e.to_string())),
         }
@@ -5492,10 +5511,11 @@ impl Game {
         let handle = expect_args!(args, [int])?;
 
         // flush buffer if possible
-        self.binary_files
-            .get_mut(handle - 1)
-            .map_or(Err(file::Error::InvalidFile(handle)), |f| f.flush())
-            .map_err(|e| gml::Error::FunctionError("file_bin_close".into(), e.to_string()))?;
+        match self.binary_files.get_mut(handle - 1) {
+            Some(f) => f.flush(&mut self.vfs),
+            None => Err(file::Error::InvalidFile(handle)),
+        }
+        .map_err(|e| gml::Error::FunctionError("file_bin_close".into(), e.to_string()))?;
 
         if self.binary_files.delete(handle - 1) {
             Ok(Default::default())
@@ -5552,7 +5572,8 @@ impl Game {
         let filename = expect_args!(args, [string])?;
         use std::error::Error as _; // for .source() trait method
 
-        match self.text_files.add_from(|| Ok(file::TextHandle::open(filename.as_ref(), file::AccessMode::Read)?)) {
+        let file = self.vfs.open_text(&self.path_resolver.resolve(filename.as_ref()), file::AccessMode::Read);
+        match self.text_files.add_from(|| Ok(file?)) {
             Ok(i) => Ok((i + 1).into()),
             Err(e)
                 if e.source()
@@ -5567,7 +5588,8 @@ impl Game {
 
     pub fn file_text_open_write(&mut self, args: &[Value]) -> gml::Result<Value> {
         let filename = expect_args!(args, [string])?;
-        match self.text_files.add_from(|| Ok(file::TextHandle::open(filename.as_ref(), file::AccessMode::Write)?)) {
+        let file = self.vfs.open_text(&self.path_resolver.resolve(filename.as_ref()), file::AccessMode::Write);
+        match self.text_files.add_from(|| Ok(file?)) {
             Ok(i) => Ok((i + 1).into()),
             Err(e) => Err(gml::Error::FunctionError("file_text_open_write".into(), e.to_string())),
         }
@@ -5575,7 +5597,8 @@ impl Game {
 
     pub fn file_text_open_append(&mut self, args: &[Value]) -> gml::Result<Value> {
         let filename = expect_args!(args, [string])?;
-        match self.text_files.add_from(|| Ok(file::TextHandle::open(filename.as_ref(), file::AccessMode::Special)?)) {
+        let file = self.vfs.open_text(&self.path_resolver.resolve(filename.as_ref()), file::AccessMode::Special);
+        match self.text_files.add_from(|| Ok(file?)) {
             Ok(i) => Ok((i + 1).into()),
             Err(e) => Err(gml::Error::FunctionError("file_text_open_append".into(), e.to_string())),
         }
@@ -5586,10 +5609,11 @@ impl Game {
         let c = self.text_files.capacity();
 
         // flush buffer if possible
-        self.text_files
-            .get_mut(handle - 1)
-            .map_or(Err(file::Error::InvalidFile(handle)), |f| f.flush())
-            .map_err(|e| gml::Error::FunctionError("file_text_close".into(), e.to_string()))?;
+        match self.text_files.get_mut(handle - 1) {
+            Some(f) => f.flush(&mut self.vfs),
+            None => Err(file::Error::InvalidFile(handle)),
+        }
+        .map_err(|e| gml::Error::FunctionError("file_text_close".into(), e.to_string()))?;
 
         // NB: .delete() MUST be called - beware the short-circuit evaluation here!
         if self.text_files.delete(handle - 1) || (1..=c).contains(&handle) {
@@ -5673,7 +5697,8 @@ impl Game {
 
     pub fn file_open_read(&mut self, args: &[Value]) -> gml::Result<Value> {
         let filename = expect_args!(args, [string])?;
-        match file::TextHandle::open(filename.as_ref(), file::AccessMode::Read) {
+        self.file_close(&[])?;
+        match self.vfs.open_text(&self.path_resolver.resolve(filename.as_ref()), file::AccessMode::Read) {
             Ok(f) => {
                 self.open_file.replace(f);
             },
@@ -5689,7 +5714,8 @@ impl Game {
 
     pub fn file_open_write(&mut self, args: &[Value]) -> gml::Result<Value> {
         let filename = expect_args!(args, [string])?;
-        match file::TextHandle::open(filename.as_ref(), file::AccessMode::Write) {
+        self.file_close(&[])?;
+        match self.vfs.open_text(&self.path_resolver.resolve(filename.as_ref()), file::AccessMode::Write) {
             Ok(f) => {
                 self.open_file.replace(f);
                 Ok(Default::default())
@@ -5703,7 +5729,8 @@ impl Game {
 
     pub fn file_open_append(&mut self, args: &[Value]) -> gml::Result<Value> {
         let filename = expect_args!(args, [string])?;
-        match file::TextHandle::open(filename.as_ref(), file::AccessMode::Special) {
+        self.file_close(&[])?;
+        match self.vfs.open_text(&self.path_resolver.resolve(filename.as_ref()), file::AccessMode::Special) {
             Ok(f) => {
                 self.open_file.replace(f);
                 Ok(Default::default())
@@ -5718,7 +5745,7 @@ impl Game {
     pub fn file_close(&mut self, args: &[Value]) -> gml::Result<Value> {
         expect_args!(args, [])?;
         match self.open_file.take() {
-            Some(mut f) => match f.flush() {
+            Some(mut f) => match f.flush(&mut self.vfs) {
                 Ok(()) => Ok(Default::default()),
                 Err(e) => Err(gml::Error::FunctionError("file_close".into(), e.to_string())),
             },
@@ -5792,31 +5819,33 @@ impl Game {
 
     pub fn file_exists(&self, args: &[Value]) -> gml::Result<Value> {
         expect_args!(args, [any]).map(|x| match x {
-            Value::Str(s) => file::file_exists(&self.decode_str(s.as_ref())).into(),
+            Value::Str(s) => self.vfs.file_exists(&self.path_resolver.resolve(&self.decode_str(s.as_ref()))).into(),
             Value::Real(_) => gml::FALSE.into(),
         })
     }
 
-    pub fn file_delete(&self, args: &[Value]) -> gml::Result<Value> {
+    pub fn file_delete(&mut self, args: &[Value]) -> gml::Result<Value> {
         let filename = expect_args!(args, [string])?;
-        match file::delete(filename.as_ref()) {
+        match self.vfs.delete(&self.path_resolver.resolve(filename.as_ref())) {
             Ok(()) => Ok(Default::default()),
             Err(e) => Err(gml::Error::FunctionError("file_delete".into(), e.to_string())),
         }
     }
 
-    pub fn file_rename(&self, args: &[Value]) -> gml::Result<Value> {
+    pub fn file_rename(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (from, to) = expect_args!(args, [string, string])?;
-        if file::rename(from.as_ref(), to.as_ref()).is_err() {
+        let (from_path, to_path) = (self.path_resolver.resolve(from.as_ref()), self.path_resolver.resolve(to.as_ref()));
+        if self.vfs.rename(&from_path, &to_path).is_err() {
             // Fail silently
             eprintln!("Warning (file_rename): could not rename {} to {}", from, to);
         }
         Ok(Default::default())
     }
 
-    pub fn file_copy(&self, args: &[Value]) -> gml::Result<Value> {
+    pub fn file_copy(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (from, to) = expect_args!(args, [string, string])?;
-        if file::copy(from.as_ref(), to.as_ref()).is_err() {
+        let (from_path, to_path) = (self.path_resolver.resolve(from.as_ref()), self.path_resolver.resolve(to.as_ref()));
+        if self.vfs.copy(&from_path, &to_path).is_err() {
             // Fail silently
             eprintln!("Warning (file_copy): could not copy {} to {}", from, to);
         }
@@ -5825,14 +5854,14 @@ impl Game {
 
     pub fn directory_exists(&self, args: &[Value]) -> gml::Result<Value> {
         expect_args!(args, [any]).map(|x| match x {
-            Value::Str(s) => file::dir_exists(&self.decode_str(s.as_ref())).into(),
+            Value::Str(s) => self.vfs.dir_exists(&self.path_resolver.resolve(&self.decode_str(s.as_ref()))).into(),
             Value::Real(_) => gml::FALSE.into(),
         })
     }
 
-    pub fn directory_create(&self, args: &[Value]) -> gml::Result<Value> {
+    pub fn directory_create(&mut self, args: &[Value]) -> gml::Result<Value> {
         let path = expect_args!(args, [string])?;
-        match file::dir_create(path.as_ref()) {
+        match self.vfs.dir_create(&self.path_resolver.resolve(path.as_ref())) {
             Ok(()) => Ok(Default::default()),
             Err(e) => Err(gml::Error::FunctionError("directory_create".into(), e.to_string())),
         }
@@ -5845,44 +5874,28 @@ impl Game {
             self.file_finder = None;
             return Ok(b"".as_ref().into())
         }
-        // unwrap arguments
+        // only the directory gets resolved, the file name is the pattern
         let path: &str = path.as_ref();
-        let include_read_only = (attribs & 1) != 0;
-        let include_hidden = (attribs & 2) != 0;
-        let include_sys_file = (attribs & 4) != 0;
-        let include_volume_id = (attribs & 8) != 0;
-        let include_directory = (attribs & 16) != 0;
-        let include_archive = (attribs & 32) != 0;
-        match glob::glob_with(path, glob::MatchOptions { case_sensitive: false, ..Default::default() }) {
+        let (dir, pattern) = match path.rfind(&['/', '\\'][..]) {
+            Some(i) => (self.path_resolver.resolve(&path[..i]), &path[i + 1..]),
+            None => (std::path::PathBuf::new(), path),
+        };
+        // normal files always get found, the rest only if they're asked for
+        let attribs = attribs as u32 | file::FA_ARCHIVE;
+        match self.vfs.find(&dir, pattern) {
             Ok(paths) => {
                 // add . and .. to start if necessary
-                let path: &std::path::Path = path.as_ref();
-                let preceding: Vec<std::path::PathBuf> = match path.file_name().and_then(|p| p.to_str()) {
-                    Some("*") | Some(".*") | Some("*.") => vec![".".into(), "..".into()],
-                    Some(".") => vec![".".into()],
-                    Some("..") => vec!["..".into()],
+                let preceding: Vec<std::path::PathBuf> = match pattern {
+                    "*" | ".*" | "*." => vec![".".into(), "..".into()],
+                    "." => vec![".".into()],
+                    ".." => vec!["..".into()],
                     _ => vec![],
                 };
-                self.file_finder = Some(Box::new(
-                    preceding.into_iter().chain(
-                        paths
-                            .filter_map(Result::ok)
-                            .filter(move |p| {
-                                let md = match p.metadata() {
-                                    Ok(m) => m,
-                                    Err(_) => return false,
-                                };
-                                // false means the check isn't in yet
-                                (include_read_only || !md.permissions().readonly())
-                                    && (include_hidden || !false)
-                                    && (include_sys_file || !false)
-                                    && (include_volume_id || !false)
-                                    && (include_directory || !md.is_dir())
-                                    && (include_archive || !false)
-                            })
-                            .map(|p| p.file_name().map(|p| p.into()).unwrap_or(p)),
-                    ),
-                ));
+                let paths = paths
+                    .into_iter()
+                    .filter(|p| matches!(self.vfs.attributes(p), Some(a) if a & !attribs == 0))
+                    .map(|p| p.file_name().map(|p| p.into()).unwrap_or(p));
+                self.file_finder = Some(preceding.into_iter().chain(paths).collect());
                 self.file_find_next(&[])
             },
             Err(e) => Err(gml::Error::FunctionError("file_find_first".into(), e.to_string())),
@@ -5891,7 +5904,7 @@ impl Game {
 
     pub fn file_find_next(&mut self, args: &[Value]) -> gml::Result<Value> {
         expect_args!(args, [])?;
-        while let Some(p) = self.file_finder.as_mut().and_then(|ff| ff.next()) {
+        while let Some(p) = self.file_finder.as_mut().and_then(|ff| ff.pop_front()) {
             if let Some(p) = p.to_str().and_then(|p| self.encode_str_maybe(p)) {
                 return Ok(Value::from(p.as_ref()))
             }
@@ -5906,9 +5919,11 @@ impl Game {
         Ok(Default::default())
     }
 
-    pub fn file_attributes(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 2
-        unimplemented!("Called unimplemented kernel function file_attributes")
+    pub fn file_attributes(&self, args: &[Value]) -> gml::Result<Value> {
+        let (fname, attr) = expect_args!(args, [string, int])?;
+        let attr = attr as u32;
+        let attributes = self.vfs.attributes(&self.path_resolver.resolve(fname.as_ref()));
+        Ok(matches!(attributes, Some(a) if a & attr == attr).into())
     }
 
     pub fn filename_name(args: &[Value]) -> gml::Result<Value> {
@@ -5966,7 +5981,7 @@ impl Game {
         let program_directory = self.decode_str(self.program_directory.as_ref()).into_owned().into();
         if let Some(file) = self.included_files.iter_mut().filter(|i| name.eq_ignore_ascii_case(i.name.as_ref())).next()
         {
-            match file.export(temp_directory, program_directory) {
+            match file.export(temp_directory, program_directory, &self.path_resolver, &mut self.vfs) {
                 Ok(()) => Ok(Default::default()),
                 Err(e) => Err(gml::Error::FunctionError("export_include_file".into(), e.to_string())),
             }
@@ -5979,8 +5994,7 @@ impl Game {
         let (name, path) = expect_args!(args, [bytes, string])?;
         if let Some(file) = self.included_files.iter_mut().filter(|i| name.eq_ignore_ascii_case(i.name.as_ref())).next()
         {
-            let path_ref: &str = path.as_ref();
-            match file.export_to(path_ref.as_ref()) {
+            match file.export_to(&self.path_resolver.resolve(path.as_ref()), &mut self.vfs) {
                 Ok(()) => Ok(Default::default()),
                 Err(e) => Err(gml::Error::FunctionError("export_include_file_location".into(), e.to_string())),
             }
@@ -6003,66 +6017,55 @@ impl Game {
         }
     }
 
-    pub fn execute_program(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (prog, prog_args, wait) = expect_args!(args, [string, string, bool])?;
-        // Rust doesn't let you execute a program with just a string, so unescape it manually
-        let mut command_array = Vec::new();
-        let mut buf = Some(String::new());
-        let mut quote_count = 0;
-        for c in format!("{} {}", prog, prog_args).replace("\\\"", "\"\"\"").chars() {
-            match c {
-                '"' => {
-                    buf = buf.or(Some("".into()));
-                    quote_count += 1;
-                    if quote_count > 2 {
-                        quote_count = 0;
-                        buf.as_mut().unwrap().push('"');
-                    }
-                },
-                c if c.is_whitespace() && quote_count != 1 => {
-                    quote_count %= 2;
-                    if let Some(s) = buf {
-                        command_array.push(s);
-                        buf = None;
-                    }
-                },
-                c => {
-                    quote_count %= 2;
-                    buf = buf.or(Some("".into()));
-                    buf.as_mut().unwrap().push(c);
-                },
+    // runs something outside the game if it's allowed, or gets what happened when it was recorded
+    fn execute_external(
+        &mut self,
+        function: &str,
+        target: &str,
+        run: impl FnOnce(&process::Policy, &file::PathResolver) -> std::io::Result<Option<i32>>,
+    ) -> gml::Result<Value> {
+        let outcome = if self.play_type == PlayType::Replay {
+            match self.stored_events.pop_front() {
+                Some(replay::Event::Execute(outcome)) => outcome,
+                _ => return Err(gml::Error::ReplayError(function.into())),
             }
+        } else {
+            let outcome = if self.exec_policy.allows(target, &self.path_resolver) {
+                run(&self.exec_policy, &self.path_resolver).map_err(|e| format!("Cannot execute {}: {}", target, e))
+            } else {
+                eprintln!("Warning ({}): not allowed to run {}, see --allow-exec", function, target);
+                Ok(None)
+            };
+            if self.play_type == PlayType::Record {
+                self.stored_events.push_back(replay::Event::Execute(outcome.clone()));
+            }
+            if let Ok(Some(_)) = outcome {
+                self.process_window_events();
+            }
+            outcome
+        };
+        match outcome {
+            Ok(_) => Ok(Default::default()),
+            Err(e) => Err(gml::Error::FunctionError(function.into(), e)),
         }
-        if let Some(s) = buf {
-            command_array.push(s);
-        }
-        if command_array.is_empty() {
+    }
+
+    pub fn execute_program(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (prog, prog_args, wait) = expect_args!(args, [string, string, bool])?;
+        let command = process::split_command_line(&format!("{} {}", prog, prog_args));
+        if command.is_empty() {
             return Err(gml::Error::FunctionError("execute_program".into(), "Cannot execute an empty string".into()))
         }
-        // Actually run the program
-        match Command::new(&command_array[0]).args(&command_array[1..]).spawn() {
-            Ok(mut child) => {
-                if wait {
-                    // wait() closes stdin. This is inaccurate, but Rust doesn't offer an alternative.
-                    if let Err(e) = child.wait() {
-                        return Err(gml::Error::FunctionError(
-                            "execute_program".into(),
-                            format!("Cannot wait for {}: {}", prog, e),
-                        ))
-                    }
-                    self.process_window_events();
-                }
-                Ok(Default::default())
-            },
-            Err(e) => {
-                Err(gml::Error::FunctionError("execute_program".into(), format!("Cannot execute {}: {}", prog, e)))
-            },
-        }
+        self.execute_external("execute_program", &command[0], |policy, paths| {
+            process::run(&command, policy, paths, wait)
+        })
     }
 
-    pub fn execute_shell(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 2
-        unimplemented!("Called unimplemented kernel function execute_shell")
+    pub fn execute_shell(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (fname, fargs) = expect_args!(args, [string, string])?;
+        self.execute_external("execute_shell", &fname, |policy, paths| {
+            process::shell(&fname, &fargs, policy, paths).map(|()| None)
+        })
     }
 
     pub fn parameter_count(&self, args: &[Value]) -> gml::Result<Value> {
@@ -6150,9 +6153,14 @@ impl Game {
 
     pub fn ini_open(&mut self, args: &[Value]) -> gml::Result<Value> {
         let name = expect_args!(args, [bytes])?;
-        let name_str = self.decode_str(name.as_ref());
-        if file::file_exists(&name_str) {
-            match ini::Ini::load_from_file(name_str.as_ref()) {
+        let path = self.path_resolver.resolve(&self.decode_str(name.as_ref()));
+        if self.vfs.file_exists(&path) {
+            match self
+                .vfs
+                .read(&path)
+                .map_err(ini::Error::from)
+                .and_then(|data| ini::Ini::read_from(&mut data.as_slice()))
+            {
                 Ok(ini) => {
                     self.open_ini = Some((ini, name));
                     Ok(Default::default())
@@ -6168,12 +6176,16 @@ impl Game {
     pub fn ini_close(&mut self, args: &[Value]) -> gml::Result<Value> {
         expect_args!(args, [])?;
         match self.open_ini.as_ref() {
-            Some((ini, path)) => match ini.write_to_file(self.decode_str(path.as_ref()).as_ref()) {
-                Ok(()) => {
-                    self.open_ini = None;
-                    Ok(Default::default())
-                },
-                Err(e) => Err(gml::Error::FunctionError("ini_close".into(), format!("{}", e))),
+            Some((ini, path)) => {
+                let path = self.path_resolver.resolve(&self.decode_str(path.as_ref()));
+                let mut data = Vec::new();
+                match ini.write_to(&mut data).and_then(|()| self.vfs.write(&path, data)) {
+                    Ok(()) => {
+                        self.open_ini = None;
+                        Ok(Default::default())
+                    },
+                    Err(e) => Err(gml::Error::FunctionError("ini_close".into(), format!("{}", e))),
+                }
             },
             None => Ok(Default::default()),
         }
@@ -6420,6 +6432,10 @@ impl Game {
 
     pub fn show_message(&mut self, args: &[Value]) -> gml::Result<Value> {
         let _text = expect_args!(args, [string])?;
+        if self.window.is_headless() {
+            // there's nobody around to close the message, so act like they already did
+            return Ok(Default::default())
+        }
         let width = 300;
         let height = 200;
 
@@ -6432,7 +6448,7 @@ impl Game {
 
         let wb = window::WindowBuilder::new().with_size(width, height);
         let mut window = wb.build().map_err(|e| gml::Error::FunctionError("show_message".into(), e))?;
-        let mut renderer = Renderer::new((), &options, &window, clear_colour)
+        let mut renderer = Renderer::new(Backend::OpenGL, &options, &window, clear_colour)
             .map_err(|e| gml::Error::FunctionError("show_message".into(), e))?;
         window.set_visible(true);
         renderer.set_vsync(false);
@@ -6459,9 +6475,10 @@ impl Game {
         unimplemented!("Called unimplemented kernel function show_question")
     }
 
-    pub fn show_error(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 2
-        unimplemented!("Called unimplemented kernel function show_error")
+    pub fn show_error(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (message, abort) = expect_args!(args, [string, bool])?;
+        self.raise_error(format!("{}\n{}", trace::SEPARATOR, message), abort)?;
+        Ok(Default::default())
     }
 
     pub fn show_info(&mut self, _args: &[Value]) -> gml::Result<Value> {
@@ -6949,144 +6966,182 @@ impl Game {
         unimplemented!("Called unimplemented kernel function mouse_wait")
     }
 
-    pub fn mplay_init_ipx(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function mplay_init_ipx")
+    // runs an mplay function that the network can affect, keeping what it saw in the replay or getting it from there
+    pub fn mplay_recorded<T>(
+        &mut self,
+        function: &str,
+        f: impl FnOnce(&mut network::Multiplayer) -> T,
+    ) -> gml::Result<T> {
+        if self.play_type == PlayType::Replay {
+            while let Some(replay::Event::Network(_)) = self.stored_events.front() {
+                if let Some(replay::Event::Network(event)) = self.stored_events.pop_front() {
+                    self.mplay.replay(event);
+                }
+            }
+        }
+        let result = f(&mut self.mplay);
+        let (events, desynced) = self.mplay.take_events();
+        match self.play_type {
+            PlayType::Normal => (),
+            PlayType::Record => self.stored_events.extend(events.into_iter().map(replay::Event::Network)),
+            PlayType::Replay => {
+                // not needed yet, so they're put back for whatever's next
+                for event in events.into_iter().rev() {
+                    self.stored_events.push_front(replay::Event::Network(event));
+                }
+            },
+        }
+        if desynced {
+            return Err(gml::Error::ReplayError(function.into()))
+        }
+        Ok(result)
     }
 
-    pub fn mplay_init_tcpip(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function mplay_init_tcpip")
+    pub fn mplay_init_ipx(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        // DirectPlay's IPX support was removed long before any of this, so nothing has it now
+        Ok(gml::FALSE.into())
     }
 
-    pub fn mplay_init_modem(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 2
-        unimplemented!("Called unimplemented kernel function mplay_init_modem")
+    pub fn mplay_init_tcpip(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let address = expect_args!(args, [string])?;
+        Ok(self.mplay.init_tcpip(address.as_ref()).into())
     }
 
-    pub fn mplay_init_serial(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 5
-        unimplemented!("Called unimplemented kernel function mplay_init_serial")
+    pub fn mplay_init_modem(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [any, any])?;
+        Ok(gml::FALSE.into())
     }
 
-    pub fn mplay_connect_status(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function mplay_connect_status")
+    pub fn mplay_init_serial(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [any, any, any, any, any])?;
+        Ok(gml::FALSE.into())
     }
 
-    pub fn mplay_end(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function mplay_end")
+    pub fn mplay_connect_status(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok(self.mplay.connect_status().into())
     }
 
-    pub fn mplay_session_mode(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function mplay_session_mode")
+    pub fn mplay_end(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        self.mplay.end();
+        Ok(Default::default())
     }
 
-    pub fn mplay_session_create(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 3
-        unimplemented!("Called unimplemented kernel function mplay_session_create")
+    pub fn mplay_session_mode(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let move_host = expect_args!(args, [bool])?;
+        self.mplay.set_session_mode(move_host);
+        Ok(Default::default())
     }
 
-    pub fn mplay_session_find(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function mplay_session_find")
+    pub fn mplay_session_create(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (name, max_players, player_name) = expect_args!(args, [bytes, int, bytes])?;
+        Ok(self.mplay_recorded("mplay_session_create", |m| m.session_create(name, max_players, player_name))?.into())
     }
 
-    pub fn mplay_session_name(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function mplay_session_name")
+    pub fn mplay_session_find(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok(self.mplay_recorded("mplay_session_find", |m| m.session_find())?.into())
     }
 
-    pub fn mplay_session_join(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 2
-        unimplemented!("Called unimplemented kernel function mplay_session_join")
+    pub fn mplay_session_name(&self, args: &[Value]) -> gml::Result<Value> {
+        let index = expect_args!(args, [int])?;
+        Ok(self.mplay.session_name(index).into())
     }
 
-    pub fn mplay_session_status(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function mplay_session_status")
+    pub fn mplay_session_join(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (index, player_name) = expect_args!(args, [int, bytes])?;
+        Ok(self.mplay_recorded("mplay_session_join", |m| m.session_join(index, player_name))?.into())
     }
 
-    pub fn mplay_session_end(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function mplay_session_end")
+    pub fn mplay_session_status(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok(self.mplay.session_status().into())
     }
 
-    pub fn mplay_player_find(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function mplay_player_find")
+    pub fn mplay_session_end(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        self.mplay.session_end();
+        Ok(Default::default())
     }
 
-    pub fn mplay_player_name(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function mplay_player_name")
+    pub fn mplay_player_find(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok(self.mplay.player_find().into())
     }
 
-    pub fn mplay_player_id(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function mplay_player_id")
+    pub fn mplay_player_name(&self, args: &[Value]) -> gml::Result<Value> {
+        let index = expect_args!(args, [int])?;
+        Ok(self.mplay.player_name(index).into())
     }
 
-    pub fn mplay_data_write(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 2
-        unimplemented!("Called unimplemented kernel function mplay_data_write")
+    pub fn mplay_player_id(&self, args: &[Value]) -> gml::Result<Value> {
+        let index = expect_args!(args, [int])?;
+        Ok(self.mplay.player_id(index).into())
     }
 
-    pub fn mplay_data_read(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function mplay_data_read")
+    pub fn mplay_data_write(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (index, value) = expect_args!(args, [int, any])?;
+        self.mplay.data_write(index, value);
+        Ok(Default::default())
     }
 
-    pub fn mplay_data_mode(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function mplay_data_mode")
+    pub fn mplay_data_read(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let index = expect_args!(args, [int])?;
+        Ok(self.mplay.data_read(index))
     }
 
-    pub fn mplay_message_send(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 3
-        unimplemented!("Called unimplemented kernel function mplay_message_send")
+    pub fn mplay_data_mode(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let guaranteed = expect_args!(args, [bool])?;
+        self.mplay.set_data_mode(guaranteed);
+        Ok(Default::default())
     }
 
-    pub fn mplay_message_send_guaranteed(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 3
-        unimplemented!("Called unimplemented kernel function mplay_message_send_guaranteed")
+    pub fn mplay_message_send(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (player, id, value) = expect_args!(args, [any, real, any])?;
+        Ok(self.mplay_recorded("mplay_message_send", |m| m.message_send(&player, id.into(), value))?.into())
     }
 
-    pub fn mplay_message_receive(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function mplay_message_receive")
+    pub fn mplay_message_send_guaranteed(&mut self, args: &[Value]) -> gml::Result<Value> {
+        // everything goes over TCP, so this is the same as mplay_message_send
+        self.mplay_message_send(args)
     }
 
-    pub fn mplay_message_id(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function mplay_message_id")
+    pub fn mplay_message_receive(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let player = expect_args!(args, [any])?;
+        Ok(self.mplay.message_receive(&player).into())
     }
 
-    pub fn mplay_message_value(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function mplay_message_value")
+    pub fn mplay_message_id(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok(self.mplay.message_id().into())
     }
 
-    pub fn mplay_message_player(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function mplay_message_player")
+    pub fn mplay_message_value(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok(self.mplay.message_value())
     }
 
-    pub fn mplay_message_name(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function mplay_message_name")
+    pub fn mplay_message_player(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok(self.mplay.message_player().into())
     }
 
-    pub fn mplay_message_count(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function mplay_message_count")
+    pub fn mplay_message_name(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok(self.mplay.message_name().into())
     }
 
-    pub fn mplay_message_clear(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function mplay_message_clear")
+    pub fn mplay_message_count(&self, args: &[Value]) -> gml::Result<Value> {
+        let player = expect_args!(args, [any])?;
+        Ok(self.mplay.message_count(&player).into())
+    }
+
+    pub fn mplay_message_clear(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let player = expect_args!(args, [any])?;
+        self.mplay.message_clear(&player);
+        Ok(Default::default())
     }
 
     pub fn mplay_ipaddress(&mut self, args: &[Value]) -> gml::Result<Value> {
@@ -7159,14 +7214,17 @@ impl Game {
                     _ => external::DLLValueType::Str,
                 })
                 .collect::<Vec<_>>();
+            let now = self.media_clock();
             self.externals.push(Some(
                 external::External::new(
                     external::DefineInfo { dll_name, fn_name, call_conv, res_type, arg_types },
-                    self.play_type == PlayType::Record,
+                    self.play_type != PlayType::Normal,
                     match self.gm_version {
                         Version::GameMaker8_0 => self.encoding,
                         Version::GameMaker8_1 => encoding_rs::UTF_8,
                     },
+                    &mut self.external_state,
+                    &external::Context { vfs: &self.vfs, path_resolver: &self.path_resolver, now },
                 )
                 .map_err(|e| gml::Error::FunctionError("external_define".into(), e))?,
             ));
@@ -7180,7 +7238,9 @@ impl Game {
         if let Some(id) = args.get(0) {
             let id = id.round();
             if let Some(external) = self.externals.get_asset(id) {
-                return external.call(&args[1..])
+                let context =
+                    external::Context { vfs: &self.vfs, path_resolver: &self.path_resolver, now: self.media_clock() };
+                return external.call(&mut self.external_state, &context, &args[1..])
             }
         }
         Ok(Default::default())
@@ -7299,7 +7359,7 @@ impl Game {
 
     pub fn execute_string(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
         if let Some(Value::Str(code)) = args.get(0) {
-            match self.compiler.compile(code.as_ref()) {
+            match self.compiler.compile_cached(code.as_ref()) {
                 Ok(instrs) => {
                     let mut new_args: [Value; 16] = Default::default();
                     for (src, dest) in args[1..].iter().zip(new_args.iter_mut()) {
@@ -7310,7 +7370,7 @@ impl Game {
                     self.execute(&instrs, &mut new_context)?;
                     Ok(new_context.return_value)
                 },
-                Err(e) => Err(gml::Error::FunctionError("execute_string".into(), e.message)),
+                Err(e) => Err(gml::Error::FunctionError("execute_string".into(), e.to_string())),
             }
         } else {
             // eg execute_string(42) - does nothing, returns 0
@@ -7324,7 +7384,7 @@ impl Game {
             for (src, dest) in args.iter().zip(new_args.iter_mut()) {
                 *dest = src.clone();
             }
-            match std::fs::read(self.decode_str(path.as_ref()).as_ref()) {
+            match self.vfs.read(&self.path_resolver.resolve(&self.decode_str(path.as_ref()))) {
                 Ok(code) => {
                     new_args[0] = code.into();
                     self.execute_string(context, &new_args)
@@ -8098,7 +8158,7 @@ impl Game {
         let (fname, imgnumb, removeback, smooth, origin_x, origin_y) =
             expect_args!(args, [string, int, bool, bool, int, int])?;
         let imgnumb = imgnumb.max(1) as usize;
-        let mut images = match file::load_animation(fname.as_ref(), imgnumb) {
+        let mut images = match file::load_animation(&self.vfs, &self.path_resolver.resolve(fname.as_ref()), imgnumb) {
             Ok(frames) => frames,
             Err(e) => {
                 eprintln!("Warning: sprite_add on {} failed: {}", fname, e);
@@ -8152,7 +8212,8 @@ impl Game {
                 self.renderer.delete_sprite(frame.atlas_ref);
             }
             let imgnumb = imgnumb.max(1) as usize;
-            let mut images = match file::load_animation(fname.as_ref(), imgnumb) {
+            let path = self.path_resolver.resolve(fname.as_ref());
+            let mut images = match file::load_animation(&self.vfs, &path, imgnumb) {
                 Ok(frames) => frames,
                 Err(e) => {
                     eprintln!("Warning: sprite_replace on {} failed: {}", fname, e);
@@ -8244,7 +8305,8 @@ impl Game {
             if let Some(frame) = sprite.get_frame(image_index) {
                 // get RGBA
                 if let Err(e) = file::save_image(
-                    fname.as_ref(),
+                    &mut self.vfs,
+                    &self.path_resolver.resolve(fname.as_ref()),
                     RgbaImage::from_vec(frame.width, frame.height, self.renderer.dump_sprite(&frame.atlas_ref).into())
                         .unwrap(),
                 ) {
@@ -8470,7 +8532,7 @@ impl Game {
 
     pub fn background_add(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (fname, removeback, smooth) = expect_args!(args, [string, bool, bool])?;
-        let mut image = match file::load_image(fname.as_ref()) {
+        let mut image = match file::load_image(&self.vfs, &self.path_resolver.resolve(fname.as_ref())) {
             Ok(im) => im,
             Err(e) => {
                 eprintln!("Warning: background_add on {} failed: {}", fname, e);
@@ -8500,7 +8562,7 @@ impl Game {
             if let Some(atlas_ref) = background.atlas_ref {
                 self.renderer.delete_sprite(atlas_ref);
             }
-            let mut image = match file::load_image(fname.as_ref()) {
+            let mut image = match file::load_image(&self.vfs, &self.path_resolver.resolve(fname.as_ref())) {
                 Ok(im) => im,
                 Err(e) => {
                     eprintln!("Warning: background_replace on {} failed: {}", fname, e);
@@ -8598,7 +8660,8 @@ impl Game {
             if let Some(atlas_ref) = background.atlas_ref.as_ref() {
                 // get RGBA
                 if let Err(e) = file::save_image(
-                    fname.as_ref(),
+                    &mut self.vfs,
+                    &self.path_resolver.resolve(fname.as_ref()),
                     RgbaImage::from_vec(
                         background.width,
                         background.height,
@@ -8799,7 +8862,8 @@ impl Game {
                     *dest = src.clone();
                 }
                 let mut new_context = Context::copy_with_args(context, new_args, args.len() - 1);
-                self.execute(&instructions, &mut new_context)?;
+                let script_id = script_id as usize;
+                self.with_frame(Frame::Script(script_id), |game| game.execute(&instructions, &mut new_context))?;
                 Ok(new_context.return_value)
             } else {
                 Err(gml::Error::NonexistentAsset(asset::Type::Script, script_id))
@@ -9117,10 +9181,13 @@ impl Game {
         let (timeline, moment, code) = expect_args!(args, [int, int, bytes])?;
         // Note: GM8 does not attempt to compile the string if the timeline doesn't exist
         if let Some(timeline) = self.assets.timelines.get_asset(timeline) {
-            let instrs = self.compiler.compile(code.as_ref())
-                .map_err(|e| gml::Error::FunctionError("timeline_moment_add".into(), e.message))?;
+            let instrs = self.compiler.compile_cached(code.as_ref())
+                .map_err(|e| gml::Error::FunctionError("timeline_moment_add".into(), e.to_string()))?;
             
-            timeline.moments.borrow_mut().entry(moment).or_insert(Default::default()).borrow_mut().push_code(instrs);
+            let mut moments = timeline.moments.borrow_mut();
+            let mut tree = moments.entry(moment).or_insert(Default::default()).borrow_mut();
+            tree.compile(&mut self.compiler).map_err(gml::Error::CompileError)?;
+            tree.push_code(instrs);
         }
         Ok(Default::default())
     }
@@ -9305,14 +9372,16 @@ impl Game {
     pub fn object_event_add(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (object_index, ev_type, ev_number, code) = expect_args!(args, [int, int, int, bytes])?;
         if let Some(object) = self.assets.objects.get_asset_mut(object_index) {
-            let instrs = match self.compiler.compile(code.as_ref()) {
+            let instrs = match self.compiler.compile_cached(code.as_ref()) {
                 Ok(instrs) => instrs,
-                Err(e) => return Err(gml::Error::FunctionError("object_event_add".into(), e.message)),
+                Err(e) => return Err(gml::Error::FunctionError("object_event_add".into(), e.to_string())),
             };
             let object_event_map = &mut object.events[ev_type as usize];
             match object_event_map.get_mut(&(ev_number as u32)) {
                 Some(tree) => {
-                    tree.borrow_mut().push_code(instrs);
+                    let mut tree = tree.borrow_mut();
+                    tree.compile(&mut self.compiler).map_err(gml::Error::CompileError)?;
+                    tree.push_code(instrs);
                 },
                 None => {
                     object_event_map.insert(ev_number as u32, action::Tree::new_from_code(instrs));
@@ -9474,7 +9543,7 @@ impl Game {
                 y,
                 object,
                 id: self.last_instance_id,
-                creation: Ok(std::rc::Rc::new([])),
+                creation: asset::room::CreationCode::new(Box::new([])),
             });
             Ok(Default::default())
         } else {
@@ -11727,99 +11796,130 @@ impl Game {
         unimplemented!("Called unimplemented kernel function sound_3d_set_sound_cone")
     }
 
-    pub fn cd_init(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_init")
+    /// The clock the CD drive, MCI devices and sound DLLs keep time with.
+    pub fn media_clock(&self) -> u128 {
+        self.spoofed_time_nanos.unwrap_or_else(datetime::now_as_nanos)
     }
 
-    pub fn cd_present(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_present")
+    pub fn cd_init(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        self.cd.init();
+        Ok(Default::default())
     }
 
-    pub fn cd_number(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_number")
+    pub fn cd_present(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok(self.cd.present().into())
     }
 
-    pub fn cd_playing(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_playing")
+    pub fn cd_number(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok(self.cd.track_count().into())
     }
 
-    pub fn cd_paused(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_paused")
+    pub fn cd_playing(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok(self.cd.playing(self.media_clock()).into())
     }
 
-    pub fn cd_track(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_track")
+    pub fn cd_paused(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok(self.cd.paused().into())
     }
 
-    pub fn cd_length(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_length")
+    pub fn cd_track(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok(self.cd.track(self.media_clock()).into())
     }
 
-    pub fn cd_track_length(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function cd_track_length")
+    pub fn cd_length(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok((self.cd.length() as f64).into())
     }
 
-    pub fn cd_position(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_position")
+    pub fn cd_track_length(&self, args: &[Value]) -> gml::Result<Value> {
+        let track = expect_args!(args, [int])?;
+        Ok((self.cd.track_length(track.max(0) as usize) as f64).into())
     }
 
-    pub fn cd_track_position(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_track_position")
+    pub fn cd_position(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok((self.cd.position(self.media_clock()) as f64).into())
     }
 
-    pub fn cd_play(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 2
-        unimplemented!("Called unimplemented kernel function cd_play")
+    pub fn cd_track_position(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        Ok((self.cd.track_position(self.media_clock()) as f64).into())
     }
 
-    pub fn cd_stop(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_stop")
+    pub fn cd_play(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (first, last) = expect_args!(args, [int, int])?;
+        let now = self.media_clock();
+        self.cd.play(first.max(0) as usize, last.max(0) as usize, now);
+        Ok(Default::default())
     }
 
-    pub fn cd_pause(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_pause")
+    pub fn cd_stop(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        let now = self.media_clock();
+        self.cd.stop(now);
+        Ok(Default::default())
     }
 
-    pub fn cd_resume(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_resume")
+    pub fn cd_pause(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        let now = self.media_clock();
+        self.cd.pause(now);
+        Ok(Default::default())
     }
 
-    pub fn cd_set_position(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function cd_set_position")
+    pub fn cd_resume(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        let now = self.media_clock();
+        self.cd.resume(now);
+        Ok(Default::default())
     }
 
-    pub fn cd_set_track_position(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function cd_set_track_position")
+    pub fn cd_set_position(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let position = expect_args!(args, [int])?;
+        let now = self.media_clock();
+        self.cd.set_position(position.max(0) as u64, now);
+        Ok(Default::default())
     }
 
-    pub fn cd_open_door(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_open_door")
+    pub fn cd_set_track_position(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let position = expect_args!(args, [int])?;
+        let now = self.media_clock();
+        self.cd.set_track_position(position.max(0) as u64, now);
+        Ok(Default::default())
     }
 
-    pub fn cd_close_door(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function cd_close_door")
+    pub fn cd_open_door(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        let now = self.media_clock();
+        self.cd.set_door_open(true, now);
+        Ok(Default::default())
     }
 
-    pub fn mci_command(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function MCI_command")
+    pub fn cd_close_door(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        let now = self.media_clock();
+        self.cd.set_door_open(false, now);
+        Ok(Default::default())
+    }
+
+    pub fn mci_command(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let command = expect_args!(args, [string])?;
+        let now = self.media_clock();
+        let context = mci::Context { cd: &mut self.cd, vfs: &self.vfs, path_resolver: &self.path_resolver, now };
+        match self.mci.command(command.as_ref(), context) {
+            Ok(result) => Ok(result.into()),
+            Err(e) => {
+                // it just gives back an empty string when something goes wrong, but it's worth knowing about
+                eprintln!("Warning (MCI_command): {}", e);
+                Ok("".into())
+            },
+        }
     }
 
     pub fn d3d_start(&mut self, args: &[Value]) -> gml::Result<Value> {
@@ -12585,8 +12685,8 @@ impl Game {
 
     pub fn d3d_model_load(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (model_id, fname) = expect_args!(args, [int, string])?;
-        fn load_model(fname: &str) -> Result<model::Model, Box<dyn std::error::Error>> {
-            let mut file = std::io::BufReader::new(std::fs::File::open(fname)?);
+        fn load_model(data: &[u8]) -> Result<model::Model, Box<dyn std::error::Error>> {
+            let mut file = std::io::Cursor::new(data);
             let version = file::read_real(&mut file)?;
             if version != 100.0 {
                 return Err("invalid version".into())
@@ -12693,7 +12793,12 @@ impl Game {
             Ok(model::Model { old_draw_colour: None, commands, cache: None })
         }
         if let Some(model) = self.models.get_asset_mut(model_id) {
-            match load_model(&fname) {
+            match self
+                .vfs
+                .read(&self.path_resolver.resolve(fname.as_ref()))
+                .map_err(|e| e.into())
+                .and_then(|data| load_model(&data))
+            {
                 Ok(new_model) => *model = new_model,
                 Err(e) => return Err(gml::Error::FunctionError("d3d_model_load".into(), e.to_string())),
             }
@@ -12703,8 +12808,8 @@ impl Game {
 
     pub fn d3d_model_save(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (model_id, fname) = expect_args!(args, [int, string])?;
-        fn save_model(model: &model::Model, fname: &str) -> std::io::Result<()> {
-            let mut file = std::io::BufWriter::new(std::fs::File::create(fname)?);
+        fn save_model(model: &model::Model) -> std::io::Result<Vec<u8>> {
+            let mut file = Vec::new();
             writeln!(&mut file, "100\r\n{}\r", model.commands.len())?;
             for cmd in &model.commands {
                 let (cmd, args) = cmd.to_line();
@@ -12714,11 +12819,11 @@ impl Game {
                 }
                 writeln!(&mut file, "\r")?;
             }
-            file.flush()?;
-            Ok(())
+            Ok(file)
         }
         if let Some(model) = self.models.get_asset(model_id) {
-            if let Err(e) = save_model(model, &fname) {
+            let path = self.path_resolver.resolve(fname.as_ref());
+            if let Err(e) = save_model(model).and_then(|data| self.vfs.write(&path, data)) {
                 return Err(gml::Error::FunctionError("d3d_model_save".into(), format!("{}", e)))
             }
         }
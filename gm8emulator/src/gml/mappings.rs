@@ -1062,10 +1062,10 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "surface_getpixel" => Function::Engine(Game::surface_getpixel),
     "surface_copy" => Function::Engine(Game::surface_copy),
     "surface_copy_part" => Function::Engine(Game::surface_copy_part),
-    "action_path_old" => Function::Engine(Game::action_path_old),
+    "action_path_old" => Function::Runtime(Game::action_path_old),
     "action_set_sprite" => Function::Runtime(Game::action_set_sprite),
-    "action_draw_font" => Function::Runtime(Game::action_draw_font),
-    "action_draw_font_old" => Function::Runtime(Game::action_draw_font_old),
+    "action_draw_font" => Function::Engine(Game::action_draw_font),
+    "action_draw_font_old" => Function::Engine(Game::action_draw_font_old),
     "action_fill_color" => Function::Engine(Game::action_fill_color),
     "action_line_color" => Function::Engine(Game::action_line_color),
     "action_highscore" => Function::Engine(Game::action_highscore),
@@ -1173,10 +1173,10 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "action_partemit_destroy" => Function::Engine(Game::action_partemit_destroy),
     "action_partemit_burst" => Function::Engine(Game::action_partemit_burst),
     "action_partemit_stream" => Function::Engine(Game::action_partemit_stream),
-    "action_cd_play" => Function::Volatile(Game::cd_play),
-    "action_cd_stop" => Function::Volatile(Game::cd_stop),
-    "action_cd_pause" => Function::Volatile(Game::cd_pause),
-    "action_cd_resume" => Function::Volatile(Game::cd_resume),
+    "action_cd_play" => Function::Engine(Game::cd_play),
+    "action_cd_stop" => Function::Engine(Game::cd_stop),
+    "action_cd_pause" => Function::Engine(Game::cd_pause),
+    "action_cd_resume" => Function::Engine(Game::cd_resume),
     "action_cd_present" => Function::Volatile(Game::cd_present),
     "action_cd_playing" => Function::Volatile(Game::cd_playing),
     "action_set_cursor" => Function::Engine(Game::action_set_cursor),
@@ -1394,11 +1394,11 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "file_write_real" => Function::Engine(Game::file_write_real),
     "file_writeln" => Function::Engine(Game::file_writeln),
     "file_exists" => Function::Volatile(Game::file_exists),
-    "file_delete" => Function::Volatile(Game::file_delete),
-    "file_rename" => Function::Volatile(Game::file_rename),
-    "file_copy" => Function::Volatile(Game::file_copy),
+    "file_delete" => Function::Engine(Game::file_delete),
+    "file_rename" => Function::Engine(Game::file_rename),
+    "file_copy" => Function::Engine(Game::file_copy),
     "directory_exists" => Function::Volatile(Game::directory_exists),
-    "directory_create" => Function::Volatile(Game::directory_create),
+    "directory_create" => Function::Engine(Game::directory_create),
     "file_find_first" => Function::Engine(Game::file_find_first),
     "file_find_next" => Function::Engine(Game::file_find_next),
     "file_find_close" => Function::Engine(Game::file_find_close),
@@ -1557,8 +1557,8 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "mplay_data_write" => Function::Engine(Game::mplay_data_write),
     "mplay_data_read" => Function::Engine(Game::mplay_data_read),
     "mplay_data_mode" => Function::Engine(Game::mplay_data_mode),
-    "mplay_message_send" => Function::Volatile(Game::mplay_message_send),
-    "mplay_message_send_guaranteed" => Function::Volatile(Game::mplay_message_send_guaranteed),
+    "mplay_message_send" => Function::Engine(Game::mplay_message_send),
+    "mplay_message_send_guaranteed" => Function::Engine(Game::mplay_message_send_guaranteed),
     "mplay_message_receive" => Function::Engine(Game::mplay_message_receive),
     "mplay_message_id" => Function::Constant(Game::mplay_message_id),
     "mplay_message_value" => Function::Constant(Game::mplay_message_value),
@@ -2039,7 +2039,7 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "sound_3d_set_sound_velocity" => Function::Engine(Game::sound_3d_set_sound_velocity),
     "sound_3d_set_sound_distance" => Function::Engine(Game::sound_3d_set_sound_distance),
     "sound_3d_set_sound_cone" => Function::Engine(Game::sound_3d_set_sound_cone),
-    "cd_init" => Function::Volatile(Game::cd_init),
+    "cd_init" => Function::Engine(Game::cd_init),
     "cd_present" => Function::Volatile(Game::cd_present),
     "cd_number" => Function::Volatile(Game::cd_number),
     "cd_playing" => Function::Volatile(Game::cd_playing),
@@ -2049,15 +2049,15 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "cd_track_length" => Function::Volatile(Game::cd_track_length),
     "cd_position" => Function::Volatile(Game::cd_position),
     "cd_track_position" => Function::Volatile(Game::cd_track_position),
-    "cd_play" => Function::Volatile(Game::cd_play),
-    "cd_stop" => Function::Volatile(Game::cd_stop),
-    "cd_pause" => Function::Volatile(Game::cd_pause),
-    "cd_resume" => Function::Volatile(Game::cd_resume),
-    "cd_set_position" => Function::Volatile(Game::cd_set_position),
-    "cd_set_track_position" => Function::Volatile(Game::cd_set_track_position),
-    "cd_open_door" => Function::Volatile(Game::cd_open_door),
-    "cd_close_door" => Function::Volatile(Game::cd_close_door),
-    "MCI_command" => Function::Volatile(Game::mci_command),
+    "cd_play" => Function::Engine(Game::cd_play),
+    "cd_stop" => Function::Engine(Game::cd_stop),
+    "cd_pause" => Function::Engine(Game::cd_pause),
+    "cd_resume" => Function::Engine(Game::cd_resume),
+    "cd_set_position" => Function::Engine(Game::cd_set_position),
+    "cd_set_track_position" => Function::Engine(Game::cd_set_track_position),
+    "cd_open_door" => Function::Engine(Game::cd_open_door),
+    "cd_close_door" => Function::Engine(Game::cd_close_door),
+    "MCI_command" => Function::Engine(Game::mci_command),
     "d3d_start" => Function::Engine(Game::d3d_start),
     "d3d_end" => Function::Engine(Game::d3d_end),
     "d3d_set_perspective" => Function::Engine(Game::d3d_set_perspective),
@@ -0,0 +1,244 @@
+//! The bit of the Media Control Interface that games use through MCI_command: opening a sound file or the CD drive
+//! under an alias, then playing it, stopping it and asking how it's doing with command strings.
+
+// TODO: same as the CD drive, nothing here makes any noise until there's a mixer.
+
+use crate::gml::{
+    audio,
+    cd::{CdDrive, Playback},
+    file::{PathResolver, Vfs},
+};
+use serde::{Deserialize, Serialize};
+
+/// Everything that's been opened, by alias.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Devices {
+    devices: Vec<(String, Device)>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+enum Device {
+    // the length's None if it can't be worked out, so it plays until it's stopped
+    File { length: Option<u64>, playback: Playback },
+    CdAudio,
+}
+
+/// What a command needs to get at outside the devices themselves.
+pub struct Context<'a> {
+    pub cd: &'a mut CdDrive,
+    pub vfs: &'a Vfs,
+    pub path_resolver: &'a PathResolver,
+    pub now: u128,
+}
+
+// splits on spaces, except inside quotes
+fn tokenize(command: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token: Option<String> = None;
+    let mut quoted = false;
+    for c in command.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                token.get_or_insert_with(String::new);
+            },
+            c if c.is_whitespace() && !quoted => tokens.extend(token.take()),
+            c => token.get_or_insert_with(String::new).push(c),
+        }
+    }
+    tokens.extend(token);
+    tokens
+}
+
+// the value after a keyword, like "from 100"
+fn keyword_value(args: &[String], keyword: &str) -> Option<String> {
+    args.windows(2).find(|w| w[0].eq_ignore_ascii_case(keyword)).map(|w| w[1].clone())
+}
+
+fn has_keyword(args: &[String], keyword: &str) -> bool {
+    args.iter().any(|a| a.eq_ignore_ascii_case(keyword))
+}
+
+fn number(value: Option<String>) -> Result<Option<u64>, String> {
+    value.map(|v| v.parse().map_err(|_| format!("not a number: {}", v))).transpose()
+}
+
+impl Devices {
+    fn get_mut(&mut self, alias: &str) -> Result<&mut Device, String> {
+        self.devices
+            .iter_mut()
+            .find(|(a, _)| a.eq_ignore_ascii_case(alias))
+            .map(|(_, d)| d)
+            .ok_or_else(|| format!("no device called {}", alias))
+    }
+
+    /// Runs a command string, and gives back the return string, or what went wrong.
+    pub fn command(&mut self, command: &str, context: Context) -> Result<String, String> {
+        let tokens = tokenize(command);
+        let (verb, device, args) = match tokens.as_slice() {
+            [verb, device, args @ ..] => (verb.to_lowercase(), device.as_str(), args),
+            _ => return Err(format!("missing device name in \"{}\"", command)),
+        };
+        match verb.as_str() {
+            "open" => self.open(device, args, context),
+            "close" => {
+                if device.eq_ignore_ascii_case("all") {
+                    self.devices.clear();
+                } else {
+                    self.get_mut(device)?;
+                    self.devices.retain(|(a, _)| !a.eq_ignore_ascii_case(device));
+                }
+                Ok(String::new())
+            },
+            _ => {
+                let now = context.now;
+                match self.get_mut(device)? {
+                    Device::File { length, playback } => file_command(&verb, args, *length, playback, now),
+                    Device::CdAudio => cd_command(&verb, args, context.cd, now),
+                }
+            },
+        }
+    }
+
+    fn open(&mut self, device: &str, args: &[String], context: Context) -> Result<String, String> {
+        // "open type!file" is the same as "open file type type"
+        let (device_type, name) = match device.split_once('!') {
+            Some((device_type, name)) => (Some(device_type.to_string()), name),
+            None => (keyword_value(args, "type"), device),
+        };
+        let alias = keyword_value(args, "alias").unwrap_or_else(|| name.to_string());
+        if self.devices.iter().any(|(a, _)| a.eq_ignore_ascii_case(&alias)) {
+            return Err(format!("alias {} is already in use", alias))
+        }
+        let opened = if device_type.as_deref().unwrap_or(name).eq_ignore_ascii_case("cdaudio") {
+            Device::CdAudio
+        } else {
+            let path = context.path_resolver.resolve(name);
+            let data = context.vfs.read(&path).map_err(|e| format!("couldn't open {}: {}", name, e))?;
+            Device::File { length: audio::length(&data), playback: Default::default() }
+        };
+        self.devices.push((alias, opened));
+        Ok(String::new())
+    }
+}
+
+fn file_command(
+    verb: &str,
+    args: &[String],
+    length: Option<u64>,
+    playback: &mut Playback,
+    now: u128,
+) -> Result<String, String> {
+    match verb {
+        "play" => {
+            // without "from" it carries on from wherever it was, even if that's the end
+            let from = number(keyword_value(args, "from"))?.unwrap_or_else(|| playback.position(now));
+            let to = number(keyword_value(args, "to"))?.or(length);
+            playback.play(from, to, has_keyword(args, "repeat"), now);
+        },
+        "stop" => playback.stop(now),
+        "pause" => playback.pause(now),
+        "resume" => playback.resume(now),
+        "seek" => {
+            let to = match keyword_value(args, "to") {
+                Some(to) if to.eq_ignore_ascii_case("start") => 0,
+                Some(to) if to.eq_ignore_ascii_case("end") => length.unwrap_or(0),
+                to => number(to)?.ok_or("seek without \"to\"")?,
+            };
+            playback.stop(now);
+            playback.seek(to, now);
+        },
+        "status" => {
+            let item = args.join(" ").to_lowercase();
+            return Ok(match item.as_str() {
+                "mode" if playback.is_playing(now) => "playing".into(),
+                "mode" if playback.is_paused() => "paused".into(),
+                "mode" => "stopped".into(),
+                "length" => length.unwrap_or(0).to_string(),
+                "position" => playback.position(now).to_string(),
+                "ready" => "true".into(),
+                _ => return Err(format!("unsupported status item: {}", item)),
+            })
+        },
+        // time formats, volume and so on, which don't change anything here
+        "set" | "setaudio" | "window" | "put" => (),
+        _ => return Err(format!("unsupported command: {}", verb)),
+    }
+    Ok(String::new())
+}
+
+// positions for "play" are track numbers, as if the time format was tmsf with only the track given
+fn cd_command(verb: &str, args: &[String], cd: &mut CdDrive, now: u128) -> Result<String, String> {
+    match verb {
+        "play" => {
+            let from = number(keyword_value(args, "from"))?.map_or_else(|| cd.track(now).max(1), |t| t as usize);
+            let to = number(keyword_value(args, "to"))?.map_or(usize::MAX, |t| t as usize);
+            cd.play(from, to, now);
+        },
+        "stop" => cd.stop(now),
+        "pause" => cd.pause(now),
+        "resume" => cd.resume(now),
+        "set" if has_keyword(args, "door") => {
+            cd.set_door_open(has_keyword(args, "open"), now);
+        },
+        "set" => (),
+        "status" => {
+            let item = args.join(" ").to_lowercase();
+            return Ok(match item.as_str() {
+                "mode" if !cd.present() => "not ready".into(),
+                "mode" if cd.playing(now) => "playing".into(),
+                "mode" if cd.paused() => "paused".into(),
+                "mode" => "stopped".into(),
+                "length" => cd.length().to_string(),
+                "position" => cd.position(now).to_string(),
+                "number of tracks" => cd.track_count().to_string(),
+                "current track" => cd.track(now).to_string(),
+                "media present" | "ready" => cd.present().to_string(),
+                _ => return Err(format!("unsupported status item: {}", item)),
+            })
+        },
+        _ => return Err(format!("unsupported command: {}", verb)),
+    }
+    Ok(String::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn play_file() {
+        let dir = std::env::temp_dir().join(format!("gm8emulator_mci_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("music file.wav");
+        // 2 seconds of 8-bit mono at 1000Hz
+        let mut wav =
+            b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0\xe8\x03\0\0\xe8\x03\0\0\x01\0\x08\0data\xd0\x07\0\0".to_vec();
+        wav.resize(wav.len() + 2000, 0x80);
+        std::fs::write(&path, wav).unwrap();
+
+        let mut cd = CdDrive::default();
+        let (vfs, path_resolver) = (Vfs::new(false), PathResolver::default());
+        let mut devices = Devices::default();
+        let mut run = |command: &str, now: u128| {
+            devices.command(command, Context { cd: &mut cd, vfs: &vfs, path_resolver: &path_resolver, now })
+        };
+        let open = format!("open \"{}\" type mpegvideo alias music", path.display());
+        assert_eq!(run(&open, 0), Ok(String::new()));
+        assert!(run(&open, 0).is_err());
+        assert_eq!(run("status music length", 0).unwrap(), "2000");
+        run("play music", 0).unwrap();
+        assert_eq!(run("status music mode", 500_000_000).unwrap(), "playing");
+        assert_eq!(run("status music position", 500_000_000).unwrap(), "500");
+        assert_eq!(run("status music mode", 3_000_000_000).unwrap(), "stopped");
+        run("play music from 1500 repeat", 3_000_000_000).unwrap();
+        assert_eq!(run("status music position", 4_200_000_000).unwrap(), "1700");
+        run("close all", 0).unwrap();
+        assert!(run("status music mode", 0).is_err());
+        assert_eq!(run("status cdaudio mode", 0), Err("no device called cdaudio".into()));
+        run("open cdaudio", 0).unwrap();
+        assert_eq!(run("status cdaudio mode", 0).unwrap(), "not ready");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
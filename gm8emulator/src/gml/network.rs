@@ -1,4 +1,16 @@
-use std::{io, net};
+use crate::{
+    game::{string::RCStr, PlayType},
+    gml::Value,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryFrom,
+    io::{self, Read, Write},
+    net::{self, Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
 
 pub fn get_local_ip() -> io::Result<net::IpAddr> {
     // For the meaning of 0.0.0.0, see 'INADDR_ANY'. Port 0 states that we don't expect any
@@ -42,3 +54,905 @@ pub fn get_local_ip() -> io::Result<net::IpAddr> {
     socket.connect(&broadcast[..])?;
     Ok(socket.local_addr()?.ip())
 }
+
+/// The UDP port hosts answer session searches on. It's the same one DirectPlay uses.
+const SEARCH_PORT: u16 = 47624;
+const SEARCH_REQUEST: &[u8] = b"GM8MPLAY?";
+const SEARCH_RESPONSE: &[u8] = b"GM8MPLAY!";
+const SEARCH_EXPIRY: Duration = Duration::from_secs(3); // how long a session that's stopped answering stays found
+const JOIN_TIMEOUT: Duration = Duration::from_secs(2);
+const DATA_SIZE: usize = 10000;
+
+#[derive(Serialize, Deserialize)]
+struct SessionInfo {
+    key: u64, // tells sessions apart when the same one answers more than once
+    name: RCStr,
+    port: u16,
+    full: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Player {
+    id: u32,
+    name: RCStr,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Packet {
+    Join(RCStr),
+    Welcome { id: u32, players: Vec<Player>, data: Vec<(u32, Value)> },
+    Refused,
+    PlayerJoined(Player),
+    PlayerLeft(u32),
+    Data(u32, Value),
+    Message { from: u32, to: u32, id: f64, value: Value }, // `to` is 0 for everyone but the sender
+}
+
+/// Something the network did that the game could see, which is all a replay needs to play a session back.
+/// The first four are what mplay functions returned, and the rest came in at the start of a frame.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Event {
+    Created(bool),
+    Found(Vec<RCStr>),
+    Joining(bool),
+    Sent(bool),
+    Welcomed(u32), // let into the session that was being joined, with this player ID
+    Ended,         // refused, or the host left
+    PlayerJoined(u32, RCStr),
+    PlayerLeft(u32),
+    Data(u32, Value),
+    Message(u32, f64, Value), // who it's from, its ID and its value
+}
+
+impl Event {
+    fn is_incoming(&self) -> bool {
+        !matches!(self, Self::Created(_) | Self::Found(_) | Self::Joining(_) | Self::Sent(_))
+    }
+}
+
+/// A TCP connection carrying length-prefixed packets.
+struct Connection {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self { stream, incoming: Vec::new() })
+    }
+
+    fn send(&mut self, packet: &Packet) -> io::Result<()> {
+        let body = bincode::serialize(packet).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut data = (body.len() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&body);
+        let mut rest = data.as_slice();
+        while !rest.is_empty() {
+            match self.stream.write(rest) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => rest = &rest[n..],
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads whatever has arrived, returning the whole packets and whether the connection is still open.
+    fn receive(&mut self) -> (Vec<Packet>, bool) {
+        let mut alive = true;
+        let mut chunk = [0; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => alive = false,
+                Ok(n) => {
+                    self.incoming.extend_from_slice(&chunk[..n]);
+                    continue
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                Err(_) => alive = false,
+            }
+            break
+        }
+        let mut packets = Vec::new();
+        while let Some(header) = self.incoming.get(..4) {
+            let end = 4 + u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            if self.incoming.len() < end {
+                break
+            }
+            match bincode::deserialize(&self.incoming[4..end]) {
+                Ok(packet) => packets.push(packet),
+                Err(_) => alive = false,
+            }
+            self.incoming.drain(..end);
+        }
+        (packets, alive)
+    }
+}
+
+/// Asks for sessions and listens for the answers in the background, so looking for them never holds up a frame.
+struct Search {
+    socket: UdpSocket,
+    targets: Vec<SocketAddr>,
+    found: Vec<(SocketAddr, SessionInfo, Instant)>, // with when each last answered
+}
+
+impl Search {
+    fn start(address: &str) -> io::Result<Self> {
+        let targets = if address.is_empty() {
+            vec![(Ipv4Addr::LOCALHOST, SEARCH_PORT).into(), (Ipv4Addr::BROADCAST, SEARCH_PORT).into()]
+        } else {
+            (address, SEARCH_PORT).to_socket_addrs()?.collect()
+        };
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_nonblocking(true)?;
+        let _ = socket.set_broadcast(true);
+        let search = Self { socket, targets, found: Vec::new() };
+        search.ask();
+        Ok(search)
+    }
+
+    fn ask(&self) {
+        for target in &self.targets {
+            let _ = self.socket.send_to(SEARCH_REQUEST, target);
+        }
+    }
+
+    /// Takes in any answers that have arrived, and forgets sessions that have stopped answering.
+    fn collect(&mut self) {
+        let mut buf = [0; 1024];
+        while let Ok((n, from)) = self.socket.recv_from(&mut buf) {
+            let info: SessionInfo =
+                match buf[..n].strip_prefix(SEARCH_RESPONSE).and_then(|b| bincode::deserialize(b).ok()) {
+                    Some(info) => info,
+                    None => continue,
+                };
+            self.found.retain(|(_, found, _)| found.key != info.key);
+            self.found.push((SocketAddr::new(from.ip(), info.port), info, Instant::now()));
+        }
+        self.found.retain(|(_, _, seen)| seen.elapsed() < SEARCH_EXPIRY);
+    }
+}
+
+struct Host {
+    listener: TcpListener,
+    searches: UdpSocket,
+    key: u64,
+    max_players: usize, // 0 for no limit
+    joining: Vec<Connection>,
+    clients: Vec<(u32, Connection)>,
+    next_id: u32,
+}
+
+impl Host {
+    fn new(max_players: i32) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        listener.set_nonblocking(true)?;
+        let searches = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, SEARCH_PORT))?;
+        searches.set_nonblocking(true)?;
+        let key = u64::from(std::process::id()) << 16 | u64::from(listener.local_addr()?.port());
+        let max_players = max_players.max(0) as usize;
+        Ok(Self { listener, searches, key, max_players, joining: Vec::new(), clients: Vec::new(), next_id: 2 })
+    }
+
+    fn is_full(&self, players: usize) -> bool {
+        self.max_players != 0 && players >= self.max_players
+    }
+
+    /// Passes a message on to whoever it's for, returning whether that includes the host.
+    fn route(&mut self, me: u32, to: u32, from: u32, id: f64, value: &Value) -> bool {
+        for (client_id, client) in self.clients.iter_mut() {
+            if (to == 0 && *client_id != from) || to == *client_id {
+                let _ = client.send(&Packet::Message { from, to, id, value: value.clone() });
+            }
+        }
+        (to == 0 && from != me) || to == me
+    }
+}
+
+/// Getting into someone else's session, which happens in the background so it never holds up a frame.
+struct Joining {
+    connecting: Option<mpsc::Receiver<io::Result<Connection>>>,
+    connection: Option<Connection>, // waiting to be welcomed
+    deadline: Instant,
+}
+
+impl Joining {
+    fn start(address: SocketAddr, player_name: RCStr) -> Self {
+        let (sender, connecting) = mpsc::channel();
+        // RCStr can't go to another thread
+        let player_name = player_name.as_ref().to_vec();
+        std::thread::spawn(move || {
+            let connection = TcpStream::connect_timeout(&address, JOIN_TIMEOUT).and_then(Connection::new);
+            let _ = sender.send(connection.and_then(|mut c| c.send(&Packet::Join(player_name.into())).map(|_| c)));
+        });
+        Self { connecting: Some(connecting), connection: None, deadline: Instant::now() + JOIN_TIMEOUT * 2 }
+    }
+}
+
+enum Role {
+    Host(Host),
+    Joining(Joining),
+    Client(Connection), // connected to the host, who passes everything on
+    Replayed(bool),     // played back from a replay, so nothing goes over the network, and true for the host
+}
+
+struct Session {
+    name: RCStr,
+    me: u32, // 0 until a session that's being joined lets this player in
+    players: Vec<Player>,
+    role: Role,
+}
+
+#[derive(Clone)]
+struct Message {
+    from: u32,
+    name: RCStr,
+    id: f64,
+    value: Value,
+}
+
+/// The mplay_* functions, which are a layer over DirectPlay in GM8. This does the same sort of thing over TCP:
+/// whoever creates a session is a hub for everyone who joins, and answers searches for it over UDP.
+/// IPX, modems and serial ports aren't supported, and when the host leaves the session ends rather than moving.
+///
+/// Searching and joining happen in the background, so a search straight after mplay_init_tcpip may not have heard
+/// from anyone yet, and a session being joined has no players until the host lets this one in.
+///
+/// When recording, the network's only checked at the start of each frame, and everything it did that the game
+/// could see is kept as an Event. Replays are fed those instead of using the network at all.
+pub struct Multiplayer {
+    play_type: PlayType,
+    events: VecDeque<Event>, // waiting to go in the replay, or to be played back from it
+    desynced: bool,          // the replay didn't have what was needed
+    address: Option<String>, // where to search for sessions, empty for the local network, or None if not initialised
+    search: Option<Search>,
+    sessions: Vec<(Option<SocketAddr>, RCStr)>, // the results of the last search
+    session: Option<Session>,
+    move_host: bool,
+    guaranteed_data: bool,
+    data: HashMap<u32, Value>,
+    players: Vec<Player>, // as of the last mplay_player_find
+    messages: VecDeque<Message>,
+    message: Option<Message>, // the last one received
+}
+
+/// What a packet the host sent a client means for the game.
+fn client_event(packet: Packet) -> Option<Event> {
+    match packet {
+        Packet::PlayerJoined(player) => Some(Event::PlayerJoined(player.id, player.name)),
+        Packet::PlayerLeft(id) => Some(Event::PlayerLeft(id)),
+        Packet::Data(index, value) => Some(Event::Data(index, value)),
+        Packet::Message { from, id, value, .. } => Some(Event::Message(from, id, value)),
+        Packet::Join(_) | Packet::Welcome { .. } | Packet::Refused => None,
+    }
+}
+
+/// Whether a player argument refers to a player, where 0 means anyone.
+fn player_matches(arg: &Value, id: u32, name: &RCStr) -> bool {
+    match arg {
+        Value::Real(x) => x.round().to_i32() == 0 || i64::from(x.round().to_i32()) == i64::from(id),
+        Value::Str(s) => s.as_ref() == name.as_ref(),
+    }
+}
+
+fn player_name(players: &[Player], id: u32) -> RCStr {
+    players.iter().find(|p| p.id == id).map(|p| p.name.clone()).unwrap_or_else(|| "".into())
+}
+
+impl Multiplayer {
+    pub fn new(play_type: PlayType) -> Self {
+        Self {
+            play_type,
+            events: VecDeque::new(),
+            desynced: false,
+            address: None,
+            search: None,
+            sessions: Vec::new(),
+            session: None,
+            move_host: false,
+            guaranteed_data: true,
+            data: HashMap::new(),
+            players: Vec::new(),
+            messages: VecDeque::new(),
+            message: None,
+        }
+    }
+
+    /// Hands over an event from the replay, for whatever needs it next.
+    pub fn replay(&mut self, event: Event) {
+        self.events.push_back(event);
+    }
+
+    /// Takes the events that were recorded, or that were played back but not needed yet,
+    /// and whether anything was needed from the replay that wasn't there.
+    pub fn take_events(&mut self) -> (VecDeque<Event>, bool) {
+        (std::mem::take(&mut self.events), std::mem::replace(&mut self.desynced, false))
+    }
+
+    fn record(&mut self, event: Event) {
+        if self.play_type == PlayType::Record {
+            self.events.push_back(event);
+        }
+    }
+
+    // the next event from the replay, if it's the right sort
+    fn replayed(&mut self, right_sort: fn(&Event) -> bool) -> Option<Event> {
+        match self.events.front() {
+            Some(event) if right_sort(event) => self.events.pop_front(),
+            _ => {
+                self.desynced = true;
+                None
+            },
+        }
+    }
+
+    pub fn init_tcpip(&mut self, address: &str) -> bool {
+        self.end();
+        if self.play_type != PlayType::Replay {
+            match Search::start(address) {
+                Ok(search) => self.search = Some(search),
+                Err(e) => eprintln!("Warning: couldn't search for multiplayer sessions: {}", e),
+            }
+        }
+        self.address = Some(address.into());
+        true
+    }
+
+    /// 0 for no connection, or 2 for TCP/IP.
+    pub fn connect_status(&self) -> i32 {
+        if self.address.is_some() { 2 } else { 0 }
+    }
+
+    pub fn end(&mut self) {
+        self.session_end();
+        self.address = None;
+        self.search = None;
+        self.sessions.clear();
+    }
+
+    pub fn set_session_mode(&mut self, move_host: bool) {
+        // only stored, since hosts can't be moved
+        self.move_host = move_host;
+    }
+
+    pub fn set_data_mode(&mut self, guaranteed: bool) {
+        // everything goes over TCP anyway
+        self.guaranteed_data = guaranteed;
+    }
+
+    /// 0 for no session, 1 if this created it or 2 if this joined it.
+    pub fn session_status(&self) -> i32 {
+        match &self.session {
+            None => 0,
+            Some(Session { role: Role::Host(_) | Role::Replayed(true), .. }) => 1,
+            Some(_) => 2,
+        }
+    }
+
+    fn start_session(&mut self, session: Session) {
+        self.data.clear();
+        self.messages.clear();
+        self.message = None;
+        self.session = Some(session);
+    }
+
+    pub fn session_create(&mut self, name: RCStr, max_players: i32, player_name: RCStr) -> bool {
+        if self.address.is_none() || self.session.is_some() {
+            return false
+        }
+        let role = match self.play_type {
+            PlayType::Replay => match self.replayed(|e| matches!(e, Event::Created(_))) {
+                Some(Event::Created(true)) => Some(Role::Replayed(true)),
+                _ => None,
+            },
+            _ => match Host::new(max_players) {
+                Ok(host) => Some(Role::Host(host)),
+                Err(e) => {
+                    eprintln!("Warning: couldn't create multiplayer session: {}", e);
+                    None
+                },
+            },
+        };
+        self.record(Event::Created(role.is_some()));
+        match role {
+            Some(role) => {
+                let players = vec![Player { id: 1, name: player_name }];
+                self.start_session(Session { name, me: 1, players, role });
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Gives how many joinable sessions have answered recently, and asks again for next time.
+    pub fn session_find(&mut self) -> usize {
+        if self.address.is_none() {
+            self.sessions.clear();
+            return 0
+        }
+        self.sessions = match self.play_type {
+            PlayType::Replay => match self.replayed(|e| matches!(e, Event::Found(_))) {
+                Some(Event::Found(names)) => names.into_iter().map(|name| (None, name)).collect(),
+                _ => Vec::new(),
+            },
+            _ => match &mut self.search {
+                Some(search) => {
+                    search.collect();
+                    search.ask();
+                    let joinable = search.found.iter().filter(|(_, info, _)| !info.full);
+                    joinable.map(|(address, info, _)| (Some(*address), info.name.clone())).collect()
+                },
+                None => Vec::new(),
+            },
+        };
+        self.record(Event::Found(self.sessions.iter().map(|(_, name)| name.clone()).collect()));
+        self.sessions.len()
+    }
+
+    pub fn session_name(&self, index: i32) -> RCStr {
+        match usize::try_from(index).ok().and_then(|i| self.sessions.get(i)) {
+            Some((_, name)) => name.clone(),
+            None => "".into(),
+        }
+    }
+
+    /// Starts joining a session. It counts as joined straight away, but it's empty until the host lets this player
+    /// in, and ends if the host doesn't.
+    pub fn session_join(&mut self, index: i32, player_name: RCStr) -> bool {
+        if self.session.is_some() {
+            return false
+        }
+        let (address, name) = match usize::try_from(index).ok().and_then(|i| self.sessions.get(i)) {
+            Some((address, name)) => (*address, name.clone()),
+            None => return false,
+        };
+        let role = match self.play_type {
+            PlayType::Replay => match self.replayed(|e| matches!(e, Event::Joining(_))) {
+                Some(Event::Joining(true)) => Some(Role::Replayed(false)),
+                _ => None,
+            },
+            _ => address.map(|address| Role::Joining(Joining::start(address, player_name))),
+        };
+        self.record(Event::Joining(role.is_some()));
+        match role {
+            Some(role) => {
+                self.start_session(Session { name, me: 0, players: Vec::new(), role });
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn session_end(&mut self) {
+        // dropping the connections lets everyone else know
+        self.session = None;
+        self.players.clear();
+    }
+
+    /// Deals with everything that's come in over the network. This gets called at the start of every frame.
+    pub fn poll(&mut self) {
+        if self.play_type == PlayType::Replay {
+            // what came in at the start of this frame when it was recorded
+            while self.events.front().map(Event::is_incoming).unwrap_or(false) {
+                let event = self.events.pop_front().unwrap();
+                self.apply(event);
+            }
+            return
+        }
+        if let Some(search) = &mut self.search {
+            search.collect();
+        }
+        for event in self.receive() {
+            self.record(event.clone());
+            self.apply(event);
+        }
+    }
+
+    // the functions that read what's come in only check for more themselves when nothing's being recorded,
+    // so that when it is, everything comes in at the start of a frame where the replay can put it back
+    fn refresh(&mut self) {
+        if self.play_type == PlayType::Normal {
+            self.poll();
+        }
+    }
+
+    /// Does the networking for the session, giving back what came in.
+    fn receive(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => return events,
+        };
+        let Session { name, me, players, role } = session;
+        match role {
+            Role::Host(host) => {
+                // who's in the session so far, counting who's joined and left while this was going on
+                let mut roster = players.clone();
+                let mut buf = [0; 64];
+                while let Ok((n, from)) = host.searches.recv_from(&mut buf) {
+                    if &buf[..n] == SEARCH_REQUEST {
+                        let full = host.is_full(roster.len());
+                        let port = host.listener.local_addr().map_or(0, |a| a.port());
+                        let info = SessionInfo { key: host.key, name: name.clone(), port, full };
+                        let mut response = SEARCH_RESPONSE.to_vec();
+                        response.extend(bincode::serialize(&info).unwrap_or_default());
+                        let _ = host.searches.send_to(&response, from);
+                    }
+                }
+                while let Ok((stream, _)) = host.listener.accept() {
+                    if let Ok(connection) = Connection::new(stream) {
+                        host.joining.push(connection);
+                    }
+                }
+
+                for mut connection in std::mem::take(&mut host.joining) {
+                    let (packets, alive) = connection.receive();
+                    let player_name = packets.into_iter().find_map(|p| match p {
+                        Packet::Join(name) => Some(name),
+                        _ => None,
+                    });
+                    match player_name {
+                        Some(_) if host.is_full(roster.len()) => {
+                            let _ = connection.send(&Packet::Refused);
+                        },
+                        Some(name) => {
+                            let player = Player { id: host.next_id, name };
+                            host.next_id += 1;
+                            for (_, client) in host.clients.iter_mut() {
+                                let _ = client.send(&Packet::PlayerJoined(player.clone()));
+                            }
+                            roster.push(player.clone());
+                            let data = self.data.iter().map(|(k, v)| (*k, v.clone())).collect();
+                            let welcome = Packet::Welcome { id: player.id, players: roster.clone(), data };
+                            if connection.send(&welcome).is_ok() {
+                                host.clients.push((player.id, connection));
+                                events.push(Event::PlayerJoined(player.id, player.name));
+                            } else {
+                                roster.pop();
+                            }
+                        },
+                        None if alive => host.joining.push(connection),
+                        None => (),
+                    }
+                }
+
+                let mut left = Vec::new();
+                for i in 0..host.clients.len() {
+                    let (packets, alive) = host.clients[i].1.receive();
+                    let sender = host.clients[i].0;
+                    for packet in packets {
+                        match packet {
+                            Packet::Data(index, value) => {
+                                for (id, client) in host.clients.iter_mut().filter(|(id, _)| *id != sender) {
+                                    if client.send(&Packet::Data(index, value.clone())).is_err() {
+                                        left.push(*id);
+                                    }
+                                }
+                                events.push(Event::Data(index, value));
+                            },
+                            Packet::Message { to, id, value, .. } if host.route(*me, to, sender, id, &value) => {
+                                events.push(Event::Message(sender, id, value));
+                            },
+                            _ => (),
+                        }
+                    }
+                    if !alive {
+                        left.push(sender);
+                    }
+                }
+                for id in left {
+                    if host.clients.iter().any(|(i, _)| *i == id) {
+                        host.clients.retain(|(i, _)| *i != id);
+                        for (_, client) in host.clients.iter_mut() {
+                            let _ = client.send(&Packet::PlayerLeft(id));
+                        }
+                        events.push(Event::PlayerLeft(id));
+                    }
+                }
+            },
+            Role::Joining(joining) => {
+                if let Some(connecting) = &joining.connecting {
+                    match connecting.try_recv() {
+                        Ok(Ok(connection)) => {
+                            joining.connection = Some(connection);
+                            joining.connecting = None;
+                        },
+                        Err(mpsc::TryRecvError::Empty) => (),
+                        Ok(Err(_)) | Err(mpsc::TryRecvError::Disconnected) => return vec![Event::Ended],
+                    }
+                }
+                let mut welcomed = false;
+                if let Some(connection) = &mut joining.connection {
+                    let (packets, alive) = connection.receive();
+                    for packet in packets {
+                        match packet {
+                            Packet::Welcome { id, players, data } if !welcomed => {
+                                events.push(Event::Welcomed(id));
+                                events.extend(players.into_iter().map(|p| Event::PlayerJoined(p.id, p.name)));
+                                events.extend(data.into_iter().map(|(index, value)| Event::Data(index, value)));
+                                welcomed = true;
+                            },
+                            Packet::Refused if !welcomed => return vec![Event::Ended],
+                            packet if welcomed => events.extend(client_event(packet)),
+                            _ => (),
+                        }
+                    }
+                    if !alive {
+                        events.push(Event::Ended);
+                        return events
+                    }
+                }
+                if welcomed {
+                    let connection = joining.connection.take().unwrap();
+                    *role = Role::Client(connection);
+                } else if Instant::now() >= joining.deadline {
+                    return vec![Event::Ended]
+                }
+            },
+            Role::Client(connection) => {
+                let (packets, alive) = connection.receive();
+                events.extend(packets.into_iter().filter_map(client_event));
+                if !alive {
+                    // the host left, and there's nobody to take over
+                    events.push(Event::Ended);
+                }
+            },
+            Role::Replayed(_) => (),
+        }
+        events
+    }
+
+    /// Makes something that came in over the network (or the replay) visible to the game.
+    fn apply(&mut self, event: Event) {
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => return,
+        };
+        match event {
+            Event::Welcomed(id) => {
+                session.me = id;
+                session.players.clear();
+                self.data.clear();
+            },
+            Event::Ended => self.session_end(),
+            Event::PlayerJoined(id, name) => session.players.push(Player { id, name }),
+            Event::PlayerLeft(id) => session.players.retain(|p| p.id != id),
+            Event::Data(index, value) => {
+                self.data.insert(index, value);
+            },
+            Event::Message(from, id, value) => {
+                self.messages.push_back(Message { from, name: player_name(&session.players, from), id, value })
+            },
+            Event::Created(_) | Event::Found(_) | Event::Joining(_) | Event::Sent(_) => (),
+        }
+    }
+
+    /// Finds all the players in the session, including this one, returning how many there are.
+    pub fn player_find(&mut self) -> usize {
+        self.refresh();
+        self.players = self.session.as_ref().map(|s| s.players.clone()).unwrap_or_default();
+        self.players.len()
+    }
+
+    pub fn player_name(&self, index: i32) -> RCStr {
+        match usize::try_from(index).ok().and_then(|i| self.players.get(i)) {
+            Some(player) => player.name.clone(),
+            None => "".into(),
+        }
+    }
+
+    pub fn player_id(&self, index: i32) -> u32 {
+        usize::try_from(index).ok().and_then(|i| self.players.get(i)).map_or(0, |p| p.id)
+    }
+
+    pub fn data_write(&mut self, index: i32, value: Value) {
+        let index = match usize::try_from(index) {
+            Ok(i) if i < DATA_SIZE => i as u32,
+            _ => return,
+        };
+        self.data.insert(index, value.clone());
+        match &mut self.session {
+            Some(Session { role: Role::Host(host), .. }) => {
+                for (_, client) in host.clients.iter_mut() {
+                    let _ = client.send(&Packet::Data(index, value.clone()));
+                }
+            },
+            Some(Session { role: Role::Client(connection), .. }) => {
+                let _ = connection.send(&Packet::Data(index, value));
+            },
+            // anything written while joining gets replaced by the host's data anyway
+            Some(Session { role: Role::Joining(_) | Role::Replayed(_), .. }) | None => (),
+        }
+    }
+
+    pub fn data_read(&mut self, index: i32) -> Value {
+        self.refresh();
+        self.data.get(&(index as u32)).cloned().unwrap_or_default()
+    }
+
+    /// Sends a message to a player (by ID or name) or everyone else if `player` is 0.
+    pub fn message_send(&mut self, player: &Value, id: f64, value: Value) -> bool {
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => return false,
+        };
+        let to = match player {
+            Value::Real(x) if x.round().to_i32() == 0 => 0,
+            _ => match session.players.iter().find(|p| player_matches(player, p.id, &p.name)) {
+                Some(p) => p.id,
+                None => return false,
+            },
+        };
+        let me = session.me;
+        let message = Message { from: me, name: player_name(&session.players, me), id, value: value.clone() };
+        // the host hands messages out itself, so it always manages to, but anyone else is at the mercy of the network
+        let sent = match &mut session.role {
+            Role::Host(host) => {
+                if host.route(me, to, me, id, &value) {
+                    self.messages.push_back(message);
+                }
+                return true
+            },
+            Role::Replayed(true) => {
+                if to == me {
+                    self.messages.push_back(message);
+                }
+                return true
+            },
+            Role::Client(connection) => Some(connection.send(&Packet::Message { from: me, to, id, value }).is_ok()),
+            Role::Joining(_) => Some(false),
+            Role::Replayed(false) => None,
+        };
+        let sent =
+            sent.unwrap_or_else(|| matches!(self.replayed(|e| matches!(e, Event::Sent(_))), Some(Event::Sent(true))));
+        self.record(Event::Sent(sent));
+        sent
+    }
+
+    /// Takes the next message from a player (by ID or name, or anyone if it's 0), returning whether there was one.
+    pub fn message_receive(&mut self, player: &Value) -> bool {
+        self.refresh();
+        match self.messages.iter().position(|m| player_matches(player, m.from, &m.name)) {
+            Some(i) => {
+                self.message = self.messages.remove(i);
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn message_id(&self) -> f64 {
+        self.message.as_ref().map_or(0.0, |m| m.id)
+    }
+
+    pub fn message_value(&self) -> Value {
+        self.message.as_ref().map(|m| m.value.clone()).unwrap_or_default()
+    }
+
+    pub fn message_player(&self) -> u32 {
+        self.message.as_ref().map_or(0, |m| m.from)
+    }
+
+    pub fn message_name(&self) -> RCStr {
+        self.message.as_ref().map_or_else(|| "".into(), |m| m.name.clone())
+    }
+
+    pub fn message_count(&self, player: &Value) -> usize {
+        self.messages.iter().filter(|m| player_matches(player, m.from, &m.name)).count()
+    }
+
+    pub fn message_clear(&mut self, player: &Value) {
+        self.messages.retain(|m| !player_matches(player, m.from, &m.name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a game that joins the first session it finds and swaps data and messages with the host, one frame at a time
+    #[derive(Default)]
+    struct Client {
+        stage: u32,
+        log: Vec<String>,
+    }
+
+    impl Client {
+        fn frame(&mut self, mplay: &mut Multiplayer) {
+            mplay.poll();
+            match self.stage {
+                0 => {
+                    assert!(mplay.init_tcpip(""));
+                    self.stage = 1;
+                },
+                1 if mplay.session_find() > 0 => {
+                    let name = mplay.session_name(0);
+                    let joined = mplay.session_join(0, "bob".into());
+                    self.log.push(format!("found {} joined {} status {}", name, joined, mplay.session_status()));
+                    self.stage = 2;
+                },
+                2 if mplay.player_find() == 2 => {
+                    let players = (0..2).map(|i| format!("{}={}", mplay.player_name(i), mplay.player_id(i)));
+                    self.log.push(format!(
+                        "players {} data {}",
+                        players.collect::<Vec<_>>().join(","),
+                        mplay.data_read(5)
+                    ));
+                    mplay.data_write(1, 3.0.into());
+                    let sent = mplay.message_send(&0.0.into(), 7.0, "hi".into());
+                    self.log.push(format!("sent {}", sent));
+                    self.stage = 3;
+                },
+                3 if mplay.message_receive(&"alice".into()) => {
+                    let (id, name, value) = (mplay.message_id(), mplay.message_name(), mplay.message_value());
+                    self.log.push(format!("message {} from {} {}", id, name, value));
+                    self.stage = 4;
+                },
+                4 if mplay.session_status() == 0 => {
+                    self.log.push("ended".into());
+                    self.stage = 5;
+                },
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn sessions_over_loopback() {
+        let mut host = Multiplayer::new(PlayType::Normal);
+        assert!(host.init_tcpip(""));
+        assert!(host.session_create("room".into(), 2, "alice".into()));
+        assert_eq!(host.session_status(), 1);
+        host.data_write(5, "hello".into());
+
+        let mut client = Client::default();
+        let mut mplay = Multiplayer::new(PlayType::Record);
+        let mut frames = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while client.stage < 5 {
+            assert!(Instant::now() < deadline, "stuck at stage {}: {:?}", client.stage, client.log);
+            host.poll();
+            if host.message_receive(&0.0.into()) {
+                assert_eq!((host.message_id(), host.message_player()), (7.0, 2));
+                assert_eq!(host.message_name().as_ref(), b"bob");
+                assert_eq!(format!("{:?}", host.data_read(1)), format!("{:?}", Value::from(3.0)));
+                assert!(host.message_send(&"bob".into(), 8.0, 1.0.into()));
+            }
+            if client.stage == 4 {
+                host.session_end();
+            }
+            client.frame(&mut mplay);
+            let (events, desynced) = mplay.take_events();
+            assert!(!desynced);
+            frames.push(events);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(client.log, [
+            "found room joined true status 2",
+            "players alice=1,bob=2 data \"hello\"",
+            "sent true",
+            "message 8 from alice 1",
+            "ended",
+        ]);
+
+        // playing it back gets exactly the same without touching the network
+        let mut replayed = Client::default();
+        let mut mplay = Multiplayer::new(PlayType::Replay);
+        for events in frames {
+            events.into_iter().for_each(|e| mplay.replay(e));
+            replayed.frame(&mut mplay);
+            let (left, desynced) = mplay.take_events();
+            assert!(left.is_empty() && !desynced);
+            assert!(mplay.search.is_none());
+        }
+        assert_eq!(replayed.log, client.log);
+
+        // and a replay that runs out is noticed
+        let mut mplay = Multiplayer::new(PlayType::Replay);
+        mplay.init_tcpip("");
+        assert_eq!(mplay.session_find(), 0);
+        assert!(mplay.take_events().1);
+    }
+}
@@ -0,0 +1,302 @@
+//! Running other programs for execute_program and execute_shell.
+//! Nothing runs unless the user allowed it, since games get to pick any command line they like.
+
+use crate::gml::file::PathResolver;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+const UNIX_SYSTEM_DIRS: &[&str] = &["/usr/local/bin", "/usr/bin", "/bin", "/usr/local/sbin", "/usr/sbin", "/sbin"];
+
+/// Which programs, files and URLs the game is allowed to run or open. Denies everything by default.
+#[derive(Default)]
+pub struct Policy {
+    allowed: Vec<String>,
+    game_dir: Option<PathBuf>,
+}
+
+impl Policy {
+    /// Allows a full path, a URL scheme like "https", or * for anything. A name on its own (with or without .exe)
+    /// only allows that program or file in the game's directory or a system directory, not just anything called that.
+    pub fn allow(&mut self, name: &str) {
+        self.allowed.push(name.into());
+    }
+
+    /// Sets where names without a path are looked for first, before the system directories.
+    pub fn set_game_dir(&mut self, dir: &Path) {
+        self.game_dir = dir.canonicalize().ok();
+    }
+
+    pub fn allows(&self, target: &str, paths: &PathResolver) -> bool {
+        if self.allowed.iter().any(|a| a == "*") {
+            return true
+        }
+        if let Some(scheme) = url_scheme(target) {
+            return self.allowed.iter().any(|a| a.eq_ignore_ascii_case(scheme))
+        }
+        let path = match self.locate(target, paths) {
+            Some(path) => path,
+            None => return false,
+        };
+        let trusted = path.parent().map(|dir| self.search_dirs().any(|d| d == dir)).unwrap_or(false);
+        let file_name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let stem = file_name.strip_suffix(".exe").unwrap_or(&file_name);
+        self.allowed.iter().any(|a| {
+            if looks_like_path(a) {
+                self.full_path(a, paths).map(|a| same_path(&a, &path)).unwrap_or(false)
+            } else {
+                trusted && (a.eq_ignore_ascii_case(&file_name) || a.eq_ignore_ascii_case(stem))
+            }
+        })
+    }
+
+    /// Finds what a target refers to, the same way it'll be run: names without a path are looked for in the
+    /// game's directory and then the system directories, so something else on PATH can't stand in for them.
+    pub fn locate(&self, target: &str, paths: &PathResolver) -> Option<PathBuf> {
+        if looks_like_path(target) {
+            return self.full_path(target, paths)
+        }
+        let names = if Path::new(target).extension().is_none() {
+            vec![target.to_string(), format!("{}.exe", target)]
+        } else {
+            vec![target.to_string()]
+        };
+        self.search_dirs()
+            .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+            .find_map(|path| self.full_path(&path.to_string_lossy(), paths))
+    }
+
+    // the host path for something, with its folder canonicalised but not the file itself,
+    // since that may be a link to something with another name
+    fn full_path(&self, target: &str, paths: &PathResolver) -> Option<PathBuf> {
+        let path = paths.resolve(target);
+        let path = match &self.game_dir {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path,
+        };
+        if !path.exists() {
+            return None
+        }
+        Some(path.parent()?.canonicalize().ok()?.join(path.file_name()?))
+    }
+
+    fn search_dirs(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        let system_dirs: Vec<PathBuf> = if cfg!(target_os = "windows") {
+            let root = std::env::var_os("SystemRoot").map_or_else(|| PathBuf::from("C:\\Windows"), PathBuf::from);
+            vec![root.join("System32"), root]
+        } else {
+            UNIX_SYSTEM_DIRS.iter().map(PathBuf::from).collect()
+        };
+        self.game_dir.iter().cloned().chain(system_dirs.into_iter().filter_map(|d| d.canonicalize().ok()))
+    }
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    if cfg!(target_os = "windows") { a.to_string_lossy().eq_ignore_ascii_case(&b.to_string_lossy()) } else { a == b }
+}
+
+// "https" for "https://example.com", but not "c" for "c:\whatever"
+fn url_scheme(target: &str) -> Option<&str> {
+    let (scheme, _) = target.split_once(':')?;
+    if scheme.len() > 1 && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) {
+        Some(scheme)
+    } else {
+        None
+    }
+}
+
+/// Splits a command line the way Windows programs do, since Rust wants the arguments separately.
+/// Backslashes are only special before a quote, where each pair becomes one and an odd one out makes the quote
+/// literal. Inside quotes, two quotes in a row make a literal one.
+pub fn split_command_line(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None; // None until something's started it, so that "" is still an argument
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let mut backslashes = 1;
+                while chars.next_if_eq(&'\\').is_some() {
+                    backslashes += 1;
+                }
+                let arg = arg.get_or_insert_with(String::new);
+                if chars.peek() == Some(&'"') {
+                    arg.push_str(&"\\".repeat(backslashes / 2));
+                    if backslashes % 2 == 1 {
+                        arg.push(chars.next().unwrap());
+                    }
+                } else {
+                    arg.push_str(&"\\".repeat(backslashes));
+                }
+            },
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                if quoted && chars.next_if_eq(&'"').is_some() {
+                    arg.push('"');
+                } else {
+                    quoted = !quoted;
+                }
+            },
+            c if c.is_whitespace() && !quoted => args.extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    args
+}
+
+fn has_drive(arg: &str) -> bool {
+    matches!(arg.as_bytes(), [letter, b':', b'\\' | b'/', ..] if letter.is_ascii_alphabetic())
+}
+
+fn looks_like_path(arg: &str) -> bool {
+    has_drive(arg) || arg.contains(&['/', '\\'][..])
+}
+
+// anything that looks like a Windows path gets translated, everything else is left alone
+fn translate(arg: &str, paths: &PathResolver) -> String {
+    if has_drive(arg) || arg.contains('\\') { paths.resolve(arg).to_string_lossy().into_owned() } else { arg.into() }
+}
+
+/// Starts a program, and gives its exit code if it was waited for.
+pub fn run(command: &[String], policy: &Policy, paths: &PathResolver, wait: bool) -> io::Result<Option<i32>> {
+    let (program, args) = command.split_first().ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+    // it runs whatever was checked against the policy, rather than whatever PATH turns up
+    let program =
+        policy.locate(program, paths).map_or_else(|| translate(program, paths).into(), PathBuf::into_os_string);
+    let mut child = Command::new(program).args(args.iter().map(|arg| translate(arg, paths))).spawn()?;
+    if wait {
+        // wait() closes stdin. This is inaccurate, but Rust doesn't offer an alternative.
+        Ok(Some(child.wait()?.code().unwrap_or(-1)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Opens a file, folder or URL with whatever the system would open it with.
+pub fn shell(target: &str, args: &str, policy: &Policy, paths: &PathResolver) -> io::Result<()> {
+    let target = match url_scheme(target) {
+        Some(_) => target.into(),
+        None => policy.locate(target, paths).map_or_else(|| translate(target, paths), |p| p.to_string_lossy().into()),
+    };
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]).arg(&target).args(split_command_line(args));
+        command
+    } else {
+        // arguments only mean anything to programs, which can't be opened like this outside Windows
+        if url_scheme(&target).is_none() && !Path::new(&target).exists() {
+            return Err(io::ErrorKind::NotFound.into())
+        }
+        let mut command = Command::new(if cfg!(target_os = "macos") { "open" } else { "xdg-open" });
+        command.arg(&target);
+        command
+    };
+    command.stdout(Stdio::null()).stderr(Stdio::null()).spawn()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_lines() {
+        let cases: &[(&str, &[&str])] = &[
+            ("", &[]),
+            ("   ", &[]),
+            ("a b  c", &["a", "b", "c"]),
+            ("\"a b\" c", &["a b", "c"]),
+            ("a\"b c\"d", &["ab cd"]),
+            ("a \"\" b", &["a", "", "b"]),
+            ("say \\\"hi\\\"", &["say", "\"hi\""]),
+            ("\"a \"\"quoted\"\" word\"", &["a \"quoted\" word"]),
+            ("C:\\Games\\a\\\\b", &["C:\\Games\\a\\\\b"]),
+            ("\"a\\\\\" b", &["a\\", "b"]),
+            ("\"a\\\\\\\"b\"", &["a\\\"b"]),
+            ("\"unfinished quote", &["unfinished quote"]),
+        ];
+        for (line, args) in cases {
+            assert_eq!(split_command_line(line), *args, "splitting {:?}", line);
+        }
+    }
+
+    #[test]
+    fn url_schemes() {
+        let cases = [
+            ("https://example.com", Some("https")),
+            ("mailto:someone@example.com", Some("mailto")),
+            ("svn+ssh://host/repo", Some("svn+ssh")),
+            ("c:\\windows\\notepad.exe", None),
+            ("C:/game/readme.txt", None),
+            ("notes.txt", None),
+            ("a b:c", None),
+        ];
+        for (target, scheme) in cases {
+            assert_eq!(url_scheme(target), scheme, "scheme of {:?}", target);
+        }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn policies() {
+        let root = std::env::temp_dir().join(format!("gm8emulator-test-policy-{}", std::process::id()));
+        let (game, elsewhere) = (root.join("game"), root.join("elsewhere"));
+        for dir in [&game, &elsewhere] {
+            std::fs::create_dir_all(dir).unwrap();
+            std::fs::write(dir.join("tool.exe"), b"").unwrap();
+        }
+        std::fs::write(game.join("readme.txt"), b"").unwrap();
+        let paths = PathResolver::default();
+        let policy = |allowed: &[&str]| {
+            let mut policy = Policy::default();
+            policy.set_game_dir(&game);
+            allowed.iter().for_each(|name| policy.allow(name));
+            policy
+        };
+        let game_tool = game.join("tool.exe").to_string_lossy().into_owned();
+        let other_tool = elsewhere.join("tool.exe").to_string_lossy().into_owned();
+
+        // nothing by default
+        let nothing = policy(&[]);
+        assert!(!nothing.allows("tool", &paths));
+        assert!(!nothing.allows("https://example.com", &paths));
+
+        // a name only covers the copy in the game's folder, however it's written
+        let name = policy(&["TOOL"]);
+        assert!(name.allows("tool", &paths));
+        assert!(name.allows("tool.exe", &paths));
+        assert!(name.allows(&game_tool, &paths));
+        assert!(!name.allows(&other_tool, &paths));
+        assert!(!name.allows("readme.txt", &paths));
+        assert_eq!(name.locate("tool", &paths), Some(game.canonicalize().unwrap().join("tool.exe")));
+
+        // a path only covers exactly that file
+        let path = policy(&[&other_tool]);
+        assert!(path.allows(&other_tool, &paths));
+        assert!(!path.allows("tool", &paths));
+        assert!(!path.allows(&game_tool, &paths));
+        let relative = policy(&["readme.txt", "./tool.exe"]);
+        assert!(relative.allows("readme.txt", &paths));
+        assert!(relative.allows(&game_tool, &paths));
+
+        // system programs are found in the system directories
+        let system = policy(&["sh"]);
+        assert!(system.allows("sh", &paths));
+        assert!(!system.allows("tool", &paths));
+
+        // URLs are allowed by scheme, and * allows anything
+        let https = policy(&["HTTPS"]);
+        assert!(https.allows("https://example.com", &paths));
+        assert!(!https.allows("http://example.com", &paths));
+        assert!(!https.allows("tool", &paths));
+        let anything = policy(&["*"]);
+        assert!(anything.allows(&other_tool, &paths));
+        assert!(anything.allows("file:///etc/passwd", &paths));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+//! Times everything that runs GML, plus kernel functions and drawing, so it's possible to tell where a slow game
+//! spends its time. At the end it writes out folded stacks (the format flamegraph.pl and inferno take, in
+//! microseconds) and prints a table of the worst offenders.
+
+use crate::{
+    game::Game,
+    gml::{mappings, trace::Frame},
+};
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// How many rows the summary table shows.
+const SUMMARY_ROWS: usize = 40;
+
+/// Something that gets timed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Entry {
+    Frame(Frame),
+    Function(usize), // kernel function id
+    Draw,
+}
+
+#[derive(Default)]
+struct Totals {
+    calls: u64,
+    self_time: Duration,
+    total_time: Duration,
+}
+
+pub struct Profiler {
+    output: PathBuf,
+    stack: Vec<Entry>,
+    open: Vec<(Instant, Duration)>, // when each thing on the stack started, and how long its children took
+    folded: HashMap<Vec<Entry>, Duration>,
+    totals: HashMap<Entry, Totals>,
+}
+
+impl Profiler {
+    /// Makes a profiler that writes its folded stacks to the given file when it's done.
+    pub fn new(output: PathBuf) -> Self {
+        Self { output, stack: Vec::new(), open: Vec::new(), folded: HashMap::new(), totals: HashMap::new() }
+    }
+
+    fn enter(&mut self, entry: Entry) {
+        self.stack.push(entry);
+        self.open.push((Instant::now(), Duration::default()));
+    }
+
+    fn exit(&mut self) {
+        let (start, children) = match self.open.pop() {
+            Some(open) => open,
+            None => return,
+        };
+        let total = start.elapsed();
+        let self_time = total.saturating_sub(children);
+        if let Some((_, parent_children)) = self.open.last_mut() {
+            *parent_children += total;
+        }
+        if let Some(time) = self.folded.get_mut(self.stack.as_slice()) {
+            *time += self_time;
+        } else {
+            self.folded.insert(self.stack.clone(), self_time);
+        }
+        let entry = self.stack.pop().unwrap();
+        let totals = self.totals.entry(entry).or_default();
+        totals.calls += 1;
+        totals.self_time += self_time;
+        // recursive calls are already inside the outer call's total
+        if !self.stack.contains(&entry) {
+            totals.total_time += total;
+        }
+    }
+}
+
+impl Game {
+    /// Runs something, timing it if the profiler's on.
+    pub fn profiled<T>(&mut self, entry: Entry, f: impl FnOnce(&mut Self) -> T) -> T {
+        match self.profiler.as_mut() {
+            Some(profiler) => profiler.enter(entry),
+            None => return f(self),
+        }
+        let result = f(self);
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.exit();
+        }
+        result
+    }
+
+    /// Writes out the folded stacks and prints the summary table, if the profiler's on.
+    pub fn profiler_report(&self) -> io::Result<()> {
+        let profiler = match &self.profiler {
+            Some(profiler) => profiler,
+            None => return Ok(()),
+        };
+
+        let mut file = BufWriter::new(File::create(&profiler.output)?);
+        for (stack, time) in &profiler.folded {
+            let names = stack.iter().map(|e| self.profile_name(*e)).collect::<Vec<_>>();
+            writeln!(file, "{} {}", names.join(";"), time.as_micros())?;
+        }
+        file.flush()?;
+
+        let mut rows = profiler.totals.iter().collect::<Vec<_>>();
+        rows.sort_by_key(|(_, totals)| Reverse(totals.self_time));
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        println!("{:>10} {:>12} {:>12}  name", "calls", "self ms", "total ms");
+        for (entry, totals) in rows.iter().take(SUMMARY_ROWS) {
+            let (self_ms, total_ms) = (ms(totals.self_time), ms(totals.total_time));
+            println!("{:>10} {:>12.3} {:>12.3}  {}", totals.calls, self_ms, total_ms, self.profile_name(**entry));
+        }
+        if rows.len() > SUMMARY_ROWS {
+            println!("({} more not shown)", rows.len() - SUMMARY_ROWS);
+        }
+        Ok(())
+    }
+
+    // semicolons separate the frames in a folded stack, so they can't be in a name
+    fn profile_name(&self, entry: Entry) -> String {
+        let name = match entry {
+            Entry::Frame(frame) => self.describe_frame(frame),
+            Entry::Function(id) => format!("{}()", mappings::FUNCTIONS.index(id).map_or("<unknown>", |(name, _)| name)),
+            Entry::Draw => "drawing".into(),
+        };
+        name.replace(';', ":")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{add_script, code, run, test_game};
+
+    #[test]
+    fn recursion_is_only_timed_once() {
+        let mut profiler = Profiler::new(PathBuf::new());
+        let script = Entry::Frame(Frame::Script(0));
+        profiler.enter(script);
+        profiler.enter(script);
+        std::thread::sleep(Duration::from_millis(2));
+        profiler.exit();
+        profiler.exit();
+        profiler.exit(); // one too many, which shouldn't do anything
+
+        let totals = &profiler.totals[&script];
+        assert_eq!(totals.calls, 2);
+        assert_eq!(profiler.folded.len(), 2);
+        // the inner call is part of the outer one, so the total is just the outer call, which is every bit of self time
+        let self_time = profiler.folded.values().sum::<Duration>();
+        assert_eq!(totals.self_time, self_time);
+        assert_eq!(totals.total_time, self_time);
+    }
+
+    #[test]
+    fn profiler_folds_stacks() {
+        let mut game = test_game();
+        let fib = "if (argument0 < 2) return abs(argument0);\nreturn scr_fib(argument0 - 1) + scr_fib(argument0 - 2)";
+        add_script(&mut game, "scr_fib", fib);
+        let path = std::env::temp_dir().join("gm8emulator-profile-test.folded");
+        game.profiler = Some(Profiler::new(path.clone()));
+        run(&mut game, 0.0, 0.0, &[code("scr_fib(3)")]).unwrap();
+        game.profiler_report().unwrap();
+
+        let folded = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let mut stacks = folded.lines().map(|l| l.rsplit_once(' ').unwrap().0).collect::<Vec<_>>();
+        stacks.sort();
+        assert_eq!(stacks, [
+            "script scr_fib",
+            "script scr_fib;script scr_fib",
+            "script scr_fib;script scr_fib;abs()",
+            "script scr_fib;script scr_fib;script scr_fib",
+            "script scr_fib;script scr_fib;script scr_fib;abs()",
+        ]);
+    }
+}
@@ -3,11 +3,15 @@ use crate::{
     game::{Game, GetAsset, SceneChange, Version},
     gml::{
         self,
+        bytecode::{Op, Owner, Program},
         datetime::DateTime,
         mappings::{self, constants as gml_constants},
+        profiler::Entry,
+        trace::{self, Frame},
         Context, InstanceVariable, Value,
     },
     instance::Field,
+    instancelist::{ILIterInsertOrder, IdentityIter, InstanceList},
     math::Real,
 };
 use gml_parser::token::Operator;
@@ -19,7 +23,7 @@ use std::{
 
 const DEFAULT_ALARM: i32 = -1;
 
-/// A compiled runtime instruction. Generally represents a line of code.
+/// A compiled instruction, before it gets assembled into bytecode. Generally represents a line of code.
 #[derive(Serialize, Deserialize)]
 pub enum Instruction {
     SetField { accessor: FieldAccessor, value: Node },
@@ -36,6 +40,7 @@ pub enum Instruction {
     With { target: Node, body: Box<[Instruction]> },
     GlobalVar { fields: Vec<usize> },
     RuntimeError { error: Error },
+    Line { number: usize }, // where the following instructions came from in the source code
 }
 
 /// Node representing one value in an expression.
@@ -87,8 +92,9 @@ pub enum UnaryOperator {
 }
 
 /// The reason for stopping execution of the current function.
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum ReturnType {
+    #[default]
     Normal,
     Continue,
     Break,
@@ -159,8 +165,21 @@ pub enum Error {
     TooManyArrayDimensions(usize),
     WrongArgumentCount(usize, usize),
     FunctionError(String, String),
+    CompileError(String),
     ReplayError(String),
     BadDirectoryError(String),
+    Located(Box<trace::Located>),
+    Aborted(String), // the game was ended from an error dialog, with the message it showed
+}
+
+impl Error {
+    /// Gets the actual error out of any location info that's been wrapped around it.
+    pub fn innermost(&self) -> &Self {
+        match self {
+            Self::Located(located) => located.error.innermost(),
+            e => e,
+        }
+    }
 }
 
 impl std::error::Error for Error {}
@@ -204,8 +223,11 @@ impl Display for Error {
             Self::TooManyArrayDimensions(n) => write!(f, "too many array dimensions ({})", n),
             Self::WrongArgumentCount(exp, got) => write!(f, "wrong argument count (expected: {}, got: {})", exp, got),
             Self::FunctionError(fname, s) => write!(f, "{}: {}", fname, s),
+            Self::CompileError(s) => write!(f, "compilation error: {}", s),
             Self::ReplayError(s) => write!(f, "{}", s),
             Self::BadDirectoryError(s) => write!(f, "cannot encode working directory {} with current encoding", s),
+            Self::Located(located) => write!(f, "{}", located),
+            Self::Aborted(message) => write!(f, "{}", message),
         }
     }
 }
@@ -219,6 +241,29 @@ enum Target {
     Local,
 }
 
+// A with statement that's running, and who self and other were before it started.
+struct With {
+    instances: WithInstances,
+    this: usize,
+    other: usize,
+}
+
+enum WithInstances {
+    Once(Option<usize>),
+    All(ILIterInsertOrder),
+    Object(IdentityIter),
+}
+
+impl WithInstances {
+    fn next(&mut self, list: &InstanceList) -> Option<usize> {
+        match self {
+            Self::Once(instance) => instance.take(),
+            Self::All(iter) => iter.next(list),
+            Self::Object(iter) => iter.next(list),
+        }
+    }
+}
+
 impl fmt::Debug for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -240,6 +285,7 @@ impl fmt::Debug for Instruction {
             Instruction::With { target, body } => write!(f, "With({:?}, {:?})", target, body),
             Instruction::GlobalVar { fields } => write!(f, "GlobalVar({:?})", fields),
             Instruction::RuntimeError { error } => write!(f, "RuntimeError({:?})", error),
+            Instruction::Line { number } => write!(f, "Line({:?})", number),
         }
     }
 }
@@ -307,506 +353,451 @@ impl UnaryOperator {
 
 impl Game {
     pub fn invoke(&mut self, function_id: usize, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
-        mappings::FUNCTIONS.index(function_id).unwrap().1.invoke(self, context, args)
+        let function = mappings::FUNCTIONS.index(function_id).unwrap().1;
+        self.profiled(Entry::Function(function_id), |game| function.invoke(game, context, args))
+    }
+
+    /// Runs some compiled code, giving back how it ended.
+    pub fn execute(&mut self, program: &Program, context: &mut Context) -> gml::Result<ReturnType> {
+        let depth = self.call_stack.len();
+        let mut stack = Vec::with_capacity(program.stack_size);
+        self.interpret(program, context, &mut stack).map_err(|(error, pos)| {
+            let error = self.locate_error(error, program.line_at(pos));
+            // take off anything pushed by with statements that didn't get to finish
+            self.call_stack.truncate(depth);
+            error
+        })
+    }
+
+    /// Evaluates a compiled expression.
+    pub fn eval(&mut self, program: &Program, context: &mut Context) -> gml::Result<Value> {
+        let mut stack = Vec::with_capacity(program.stack_size);
+        self.interpret(program, context, &mut stack).map_err(|(error, _)| error)?;
+        Ok(stack.pop().unwrap_or_default())
     }
 
-    pub fn execute(&mut self, instructions: &[Instruction], context: &mut Context) -> gml::Result<ReturnType> {
-        for instruction in instructions.iter() {
-            match self.exec_instruction(instruction, context)? {
-                ReturnType::Normal => (),
-                r => return Ok(r),
+    // The bytecode interpreter. If anything goes wrong, the error comes back with the position of the op that did it.
+    fn interpret(
+        &mut self,
+        program: &Program,
+        context: &mut Context,
+        stack: &mut Vec<Value>,
+    ) -> Result<ReturnType, (gml::Error, usize)> {
+        let mut indices = Vec::new();
+        let mut targets = Vec::new();
+        let mut repeats = Vec::new();
+        let mut withs = Vec::new();
+        let mut pos = 0;
+        let mut line = None; // only kept track of for the debugger
+
+        macro_rules! check {
+            ($result: expr) => {
+                match $result {
+                    Ok(value) => value,
+                    Err(error) => return Err((error, pos - 1)),
+                }
+            };
+        }
+
+        while let Some(op) = program.ops.get(pos) {
+            if matches!(&self.debugger, Some(d) if d.watching_lines()) && program.line_at(pos) != line {
+                line = program.line_at(pos);
+                if let Some(line) = line {
+                    self.debug_line(line, context);
+                }
+            }
+            pos += 1;
+            match op {
+                Op::Push(value) => stack.push(value.clone()),
+                Op::Constant(constant_id) => match self.constants.get(*constant_id) {
+                    Some(value) => stack.push(value.clone()),
+                    None => check!(Err(Error::NonexistentAsset(asset::Type::Constant, *constant_id as i32))),
+                },
+                Op::Call { function_id, arg_count } => {
+                    let args = stack.len() - arg_count;
+                    let value = self.invoke(*function_id, context, &stack[args..]);
+                    stack.truncate(args);
+                    stack.push(check!(value));
+                },
+                Op::Script { script_id, arg_count } => {
+                    let args = take_args(stack, *arg_count);
+                    let value = self.run_script(*script_id, context, args, *arg_count);
+                    stack.push(check!(value));
+                },
+                Op::Extension { id, arg_count } => {
+                    let args = take_args(stack, *arg_count);
+                    let value = self.run_extension_function(*id, Context::copy_with_args(context, args, *arg_count));
+                    stack.push(check!(value));
+                },
+                Op::GetField { index, owner, indexed } => {
+                    let array_index = if *indexed { indices.pop().unwrap() } else { 0 };
+                    let target = self.target(*owner, Some(*index), context, &mut targets);
+                    let value = self.get_field(target, *index, array_index, context);
+                    stack.push(check!(value));
+                },
+                Op::GetVariable { var, owner, indexed } => {
+                    let array_index = if *indexed { indices.pop().unwrap() } else { 0 };
+                    let target = self.target(*owner, None, context, &mut targets);
+                    let value = self.get_var(target, var, array_index, context);
+                    stack.push(check!(value));
+                },
+                Op::Binary(operator) => {
+                    let right = stack.pop().unwrap();
+                    let left = stack.pop().unwrap();
+                    stack.push(check!(operator.call(left, right)));
+                },
+                Op::Unary(operator) => {
+                    let value = stack.pop().unwrap();
+                    stack.push(check!(operator.call(value)));
+                },
+                Op::Error(error) => check!(Err(error.clone())),
+                Op::Index { dims } => indices.push(check!(pop_array_index(stack, *dims))),
+                Op::Target => {
+                    let target = self.target_of(i32::from(stack.pop().unwrap()), context);
+                    targets.push(target);
+                },
+                Op::TargetUnknown(index) => {
+                    let target = self.target(Owner::Unknown, Some(*index), context, &mut targets);
+                    targets.push(target);
+                },
+                Op::SetField { index, owner, indexed } => {
+                    let value = stack.pop().unwrap();
+                    let array_index = if *indexed { indices.pop().unwrap() } else { 0 };
+                    let target = self.target(*owner, Some(*index), context, &mut targets);
+                    context.return_value = value.clone();
+                    self.set_field(target, *index, array_index, value, context);
+                },
+                Op::SetVariable { var, owner, indexed } => {
+                    let value = stack.pop().unwrap();
+                    let array_index = if *indexed { indices.pop().unwrap() } else { 0 };
+                    let target = self.target(*owner, None, context, &mut targets);
+                    context.return_value = value.clone();
+                    check!(self.set_var(target, var, array_index, value, context));
+                },
+                Op::SetReturnValue => context.return_value = stack.pop().unwrap(),
+                Op::GlobalVar(fields) => self.globalvars.extend(fields.iter()),
+                Op::Pop => {
+                    stack.pop();
+                },
+                Op::Jump(to) => pos = *to,
+                Op::JumpIfFalse(to) => {
+                    if !stack.pop().unwrap().is_truthy() {
+                        pos = *to;
+                    }
+                },
+                Op::Case(to) => {
+                    let case = stack.pop().unwrap();
+                    if case.almost_equals(stack.last().unwrap()) {
+                        stack.pop();
+                        pos = *to;
+                    }
+                },
+                Op::RepeatStart => repeats.push(stack.pop().unwrap().round()),
+                Op::RepeatCheck(to) => {
+                    if *repeats.last().unwrap() <= 0 {
+                        pos = *to;
+                    }
+                },
+                Op::RepeatLoop(to) => {
+                    *repeats.last_mut().unwrap() -= 1;
+                    pos = *to;
+                },
+                Op::RepeatEnd => {
+                    repeats.pop();
+                },
+                Op::WithStart => {
+                    let with = self.start_with(i32::from(stack.pop().unwrap()), context);
+                    withs.push(with);
+                },
+                Op::WithNext(to) => match withs.last_mut().unwrap().instances.next(&self.room.instance_list) {
+                    Some(instance) => context.this = instance,
+                    None => pos = *to,
+                },
+                Op::WithEnd => {
+                    let with = withs.pop().unwrap();
+                    self.end_with(with, context);
+                },
+                Op::Return(return_type) => {
+                    while let Some(with) = withs.pop() {
+                        self.end_with(with, context);
+                    }
+                    return Ok(*return_type)
+                },
             }
         }
+
         Ok(ReturnType::Normal)
     }
 
-    fn exec_instruction(&mut self, instruction: &Instruction, context: &mut Context) -> gml::Result<ReturnType> {
-        match instruction {
-            Instruction::SetField { accessor, value } => {
-                let target = self.get_target(context, &accessor.owner, self.globalvars.contains(&accessor.index))?;
-                let array_index = self.get_array_index(&accessor.array, context)?;
-                let value = self.eval(value, context)?;
-                context.return_value = value.clone();
-                match target {
-                    Target::Single(None) => (),
-                    Target::Single(Some(instance)) => {
-                        self.set_instance_field(instance, accessor.index, array_index, value);
-                    },
-                    Target::Objects(index) => {
-                        if let Some(Some(object)) = self.assets.objects.get(index as usize) {
-                            let ids = object.children.clone();
-                            let mut iter = self.room.instance_list.iter_by_identity(ids);
-                            while let Some(instance) = iter.next(&self.room.instance_list) {
-                                self.set_instance_field(instance, accessor.index, array_index, value.clone());
-                            }
-                        }
-                    },
-                    Target::All => {
-                        let mut iter = self.room.instance_list.iter_by_insertion();
-                        while let Some(instance) = iter.next(&self.room.instance_list) {
-                            self.set_instance_field(instance, accessor.index, array_index, value.clone());
-                        }
-                    },
-                    Target::Global => {
-                        if let Some(field) = self.globals.fields.get_mut(&accessor.index) {
-                            field.set(array_index, value)
-                        } else {
-                            self.globals.fields.insert(accessor.index, Field::new(array_index, value));
-                        }
-                    },
-                    Target::Local => {
-                        if let Some(field) = context.locals.fields.get_mut(&accessor.index) {
-                            field.set(array_index, value)
-                        } else {
-                            context.locals.fields.insert(accessor.index, Field::new(array_index, value));
-                        }
-                    },
-                }
-            },
-            Instruction::SetVariable { accessor, value } => {
-                let target = self.get_target(context, &accessor.owner, false)?;
-                let array_index = self.get_array_index(&accessor.array, context)?;
-                let value = self.eval(value, context)?;
-                context.return_value = value.clone();
-                match target {
-                    Target::Single(None) => (),
-                    Target::Single(Some(instance)) => {
-                        self.set_instance_var(instance, &accessor.var, array_index, value, context)?;
-                    },
-                    Target::Objects(index) => {
-                        if let Some(Some(object)) = self.assets.objects.get(index as usize) {
-                            let ids = object.children.clone();
-                            let mut iter = self.room.instance_list.iter_by_identity(ids);
-                            while let Some(instance) = iter.next(&self.room.instance_list) {
-                                self.set_instance_var(instance, &accessor.var, array_index, value.clone(), context)?;
-                            }
-                        }
-                    },
-                    Target::All => {
-                        let mut iter = self.room.instance_list.iter_by_insertion();
-                        while let Some(instance) = iter.next(&self.room.instance_list) {
-                            self.set_instance_var(instance, &accessor.var, array_index, value.clone(), context)?;
-                        }
-                    },
-                    Target::Global => {
-                        if let Some(field) = self.globals.vars.get_mut(&accessor.var) {
-                            field.set(array_index, value)
-                        } else {
-                            self.globals.vars.insert(accessor.var, Field::new(array_index, value));
-                        }
-                    },
-                    Target::Local => {
-                        if let Some(field) = context.locals.vars.get_mut(&accessor.var) {
-                            field.set(array_index, value)
-                        } else {
-                            context.locals.vars.insert(accessor.var, Field::new(array_index, value));
-                        }
+    fn run_script(
+        &mut self,
+        script_id: usize,
+        context: &Context,
+        args: [Value; 16],
+        arg_count: usize,
+    ) -> gml::Result<Value> {
+        if let Some(Some(script)) = self.assets.scripts.get(script_id) {
+            let instructions = script.compiled.clone();
+            let mut new_context = Context::copy_with_args(context, args, arg_count);
+            self.with_frame(Frame::Script(script_id), |game| game.execute(&instructions, &mut new_context))?;
+            Ok(new_context.return_value)
+        } else {
+            Err(Error::NonexistentAsset(asset::Type::Script, script_id as i32))
+        }
+    }
+
+    // Sets up a with statement, which runs its body once for each instance it applies to
+    fn start_with(&mut self, target: i32, context: &mut Context) -> With {
+        let (this, other) = (context.this, context.other);
+        context.other = context.this;
+        let instances = match target {
+            gml::SELF | gml::UNSPECIFIED => WithInstances::Once(Some(this)),
+            gml::OTHER => WithInstances::Once(Some(other)),
+            gml::ALL => WithInstances::All(self.room.instance_list.iter_by_insertion()),
+            i if i < 0 => WithInstances::Once(None),
+            i if i < 100_000 => match self.assets.objects.get(i as usize) {
+                Some(Some(object)) => {
+                    WithInstances::Object(self.room.instance_list.iter_by_identity(object.children.clone()))
+                },
+                _ => WithInstances::Once(None),
+            },
+            i => WithInstances::Once(self.room.instance_list.get_by_instid(i)),
+        };
+        self.call_stack.push(Frame::With(target));
+        With { instances, this, other }
+    }
+
+    fn end_with(&mut self, with: With, context: &mut Context) {
+        context.this = with.this;
+        context.other = with.other;
+        self.call_stack.pop();
+    }
+
+    // Get a field from whoever it belongs to
+    fn get_field(&self, target: Target, index: usize, array_index: u32, context: &Context) -> gml::Result<Value> {
+        match target {
+            Target::Single(None) if self.uninit_fields_are_zero => Ok(Default::default()),
+            Target::Single(None) => {
+                Err(Error::UninitializedVariable(self.compiler.get_field_name(index).unwrap(), array_index))
+            },
+            Target::Single(Some(instance)) => self.get_instance_field(instance, index, array_index),
+            Target::Objects(object_index) => {
+                if let Some(instance) = self.assets.objects.get(object_index as usize).and_then(|x| match x {
+                    Some(x) => {
+                        self.room.instance_list.iter_by_identity(x.children.clone()).next(&self.room.instance_list)
                     },
-                }
-            },
-            Instruction::EvalExpression { node } => {
-                context.return_value = self.eval(node, context)?;
-            },
-            Instruction::IfElse { cond, if_body, else_body } => {
-                let return_type = if self.eval(cond, context)?.is_truthy() {
-                    self.execute(if_body, context)
+                    None => None,
+                }) {
+                    self.get_instance_field(instance, index, array_index)
                 } else {
-                    self.execute(else_body, context)
-                }?;
-                if return_type != ReturnType::Normal {
-                    return Ok(return_type)
-                }
-            },
-            Instruction::LoopUntil { cond, body } => loop {
-                match self.execute(body, context)? {
-                    ReturnType::Normal => (),
-                    ReturnType::Continue => continue,
-                    ReturnType::Break => break,
-                    ReturnType::Exit => return Ok(ReturnType::Exit),
-                }
-                if self.eval(cond, context)?.is_truthy() {
-                    break
+                    if self.uninit_fields_are_zero {
+                        Ok(Default::default())
+                    } else {
+                        Err(Error::UninitializedVariable(self.compiler.get_field_name(index).unwrap(), array_index))
+                    }
                 }
             },
-            Instruction::LoopWhile { cond, body } => {
-                while self.eval(cond, context)?.is_truthy() {
-                    match self.execute(body, context)? {
-                        ReturnType::Normal => (),
-                        ReturnType::Continue => continue,
-                        ReturnType::Break => break,
-                        ReturnType::Exit => return Ok(ReturnType::Exit),
+            Target::All => {
+                if let Some(instance) = self.room.instance_list.iter_by_insertion().next(&self.room.instance_list) {
+                    self.get_instance_field(instance, index, array_index)
+                } else {
+                    if self.uninit_fields_are_zero {
+                        Ok(Default::default())
+                    } else {
+                        Err(Error::UninitializedVariable(self.compiler.get_field_name(index).unwrap(), array_index))
                     }
                 }
             },
-            Instruction::LoopFor { cond, body, step } => {
-                while self.eval(cond, context)?.is_truthy() {
-                    match self.execute(body, context)? {
-                        ReturnType::Normal => {
-                            self.execute(step, context)?;
-                        },
-                        ReturnType::Continue => {
-                            self.execute(step, context)?;
-                            continue
-                        },
-                        ReturnType::Break => break,
-                        ReturnType::Exit => return Ok(ReturnType::Exit),
+            Target::Global => match self.globals.fields.get(&index).and_then(|x| x.get(array_index)) {
+                Some(i) => Ok(i),
+                None => {
+                    if self.uninit_fields_are_zero {
+                        Ok(Default::default())
+                    } else {
+                        Err(Error::UninitializedVariable(
+                            self.compiler.get_field_name(index).unwrap(),
+                            array_index,
+                        ))
                     }
-                }
+                },
+            },
+            Target::Local => match context.locals.fields.get(&index).and_then(|x| x.get(array_index)) {
+                Some(i) => Ok(i),
+                None => {
+                    if self.uninit_fields_are_zero {
+                        Ok(Default::default())
+                    } else {
+                        Err(Error::UninitializedVariable(
+                            self.compiler.get_field_name(index).unwrap(),
+                            array_index,
+                        ))
+                    }
+                },
             },
-            Instruction::Return { return_type } => return Ok(*return_type),
-            Instruction::Repeat { count, body } => {
-                let mut count = self.eval(count, context)?.round();
-                while count > 0 {
-                    match self.execute(body, context)? {
-                        ReturnType::Normal => (),
-                        ReturnType::Continue => continue,
-                        ReturnType::Break => break,
-                        ReturnType::Exit => return Ok(ReturnType::Exit),
+        }
+    }
+
+    // Get an instance variable from whoever it belongs to
+    fn get_var(
+        &self,
+        target: Target,
+        var: &InstanceVariable,
+        array_index: u32,
+        context: &Context,
+    ) -> gml::Result<Value> {
+        match target {
+            Target::Single(None) if self.uninit_fields_are_zero => Ok(Default::default()),
+            Target::Single(None) => Err(Error::UninitializedVariable(
+                String::from(mappings::INSTANCE_VARIABLES.iter().find(|(_, x)| x == var).unwrap().0),
+                array_index,
+            )),
+            Target::Single(Some(instance)) => self.get_instance_var(instance, var, array_index, context),
+            Target::Objects(object_index) => {
+                if let Some(instance) = self.assets.objects.get(object_index as usize).and_then(|x| match x {
+                    Some(x) => {
+                        self.room.instance_list.iter_by_identity(x.children.clone()).next(&self.room.instance_list)
+                    },
+                    None => None,
+                }) {
+                    self.get_instance_var(instance, var, array_index, context)
+                } else {
+                    if self.uninit_fields_are_zero {
+                        Ok(Default::default())
+                    } else {
+                        Err(Error::UninitializedVariable(
+                            String::from(mappings::INSTANCE_VARIABLES.iter().find(|(_, x)| x == var).unwrap().0),
+                            array_index,
+                        ))
                     }
-                    count -= 1;
                 }
             },
-            Instruction::SetReturnValue { value } => {
-                context.return_value = self.eval(value, context)?;
-            },
-            Instruction::Switch { input, cases, default, body } => {
-                let input = self.eval(input, context)?;
-                for (cond, start) in cases.iter() {
-                    if self.eval(cond, context)?.almost_equals(&input) {
-                        return Ok(match self.execute(&body[*start..], context)? {
-                            ReturnType::Break => ReturnType::Normal,
-                            x => x,
-                        })
+            Target::All => {
+                if let Some(instance) = self.room.instance_list.iter_by_insertion().next(&self.room.instance_list) {
+                    self.get_instance_var(instance, var, array_index, context)
+                } else {
+                    if self.uninit_fields_are_zero {
+                        Ok(Default::default())
+                    } else {
+                        Err(Error::UninitializedVariable(
+                            String::from(mappings::INSTANCE_VARIABLES.iter().find(|(_, x)| x == var).unwrap().0),
+                            array_index,
+                        ))
                     }
                 }
-                if let Some(start) = default {
-                    return Ok(match self.execute(&body[*start..], context)? {
-                        ReturnType::Break => ReturnType::Normal,
-                        x => x,
-                    })
-                }
             },
-            Instruction::With { target, body } => {
-                let old_this = context.this;
-                let old_other = context.other;
-
-                let target_id = i32::from(self.eval(target, context)?);
-                context.other = context.this;
-
-                match target_id {
-                    gml::SELF | gml::UNSPECIFIED => {
-                        if self.execute(body, context)? == ReturnType::Exit {
-                            context.other = old_other;
-                            return Ok(ReturnType::Exit)
-                        }
-                    },
-                    gml::OTHER => {
-                        context.this = old_other;
-                        if self.execute(body, context)? == ReturnType::Exit {
-                            context.this = old_this;
-                            context.other = old_other;
-                            return Ok(ReturnType::Exit)
-                        }
-                    },
-                    gml::ALL => {
-                        let mut iter = self.room.instance_list.iter_by_insertion();
-                        while let Some(instance) = iter.next(&self.room.instance_list) {
-                            context.this = instance;
-                            match self.execute(body, context)? {
-                                ReturnType::Normal => (),
-                                ReturnType::Continue => continue,
-                                ReturnType::Break => break,
-                                ReturnType::Exit => {
-                                    context.this = old_this;
-                                    context.other = old_other;
-                                    return Ok(ReturnType::Exit)
-                                },
-                            }
-                        }
-                    },
-                    i if i < 0 => (),
-                    i if i < 100_000 => {
-                        if let Some(Some(object)) = self.assets.objects.get(i as usize) {
-                            let mut iter = self.room.instance_list.iter_by_identity(object.children.clone());
-                            while let Some(instance) = iter.next(&self.room.instance_list) {
-                                context.this = instance;
-                                match self.execute(body, context)? {
-                                    ReturnType::Normal => (),
-                                    ReturnType::Continue => continue,
-                                    ReturnType::Break => break,
-                                    ReturnType::Exit => {
-                                        context.this = old_this;
-                                        context.other = old_other;
-                                        return Ok(ReturnType::Exit)
-                                    },
-                                }
-                            }
-                        }
-                    },
-                    i => {
-                        if let Some(instance) = self.room.instance_list.get_by_instid(i) {
-                            context.this = instance;
-                            match self.execute(body, context)? {
-                                ReturnType::Exit => {
-                                    context.this = old_this;
-                                    context.other = old_other;
-                                    return Ok(ReturnType::Exit)
-                                },
-                                _ => (),
-                            }
-                        }
-                    },
-                }
-
-                context.this = old_this;
-                context.other = old_other;
+            Target::Global => match self.globals.vars.get(var).and_then(|x| x.get(array_index)) {
+                Some(i) => Ok(i),
+                None => {
+                    if self.uninit_fields_are_zero {
+                        Ok(Default::default())
+                    } else {
+                        Err(Error::UninitializedVariable(
+                            String::from(mappings::INSTANCE_VARIABLES.iter().find(|(_, x)| x == var).unwrap().0),
+                            array_index,
+                        ))
+                    }
+                },
+            },
+            Target::Local => match context.locals.vars.get(var).and_then(|x| x.get(array_index)) {
+                Some(i) => Ok(i),
+                None => {
+                    if self.uninit_fields_are_zero {
+                        Ok(Default::default())
+                    } else {
+                        Err(Error::UninitializedVariable(
+                            String::from(mappings::INSTANCE_VARIABLES.iter().find(|(_, x)| x == var).unwrap().0),
+                            array_index,
+                        ))
+                    }
+                },
             },
-            Instruction::GlobalVar { fields } => self.globalvars.extend(fields),
-            Instruction::RuntimeError { error } => return Err(error.clone()),
         }
-
-        Ok(ReturnType::Normal)
     }
 
-    pub fn eval(&mut self, node: &Node, context: &mut Context) -> gml::Result<Value> {
-        match node {
-            Node::Literal { value } => Ok(value.clone()),
-            Node::Constant { constant_id } => {
-                if let Some(value) = self.constants.get(*constant_id) {
-                    Ok(value.clone())
-                } else {
-                    Err(gml::Error::NonexistentAsset(asset::Type::Constant, *constant_id as i32))
+    // Set a field on whoever it belongs to
+    fn set_field(&mut self, target: Target, index: usize, array_index: u32, value: Value, context: &mut Context) {
+        match target {
+            Target::Single(None) => (),
+            Target::Single(Some(instance)) => {
+                self.set_instance_field(instance, index, array_index, value);
+            },
+            Target::Objects(object_index) => {
+                if let Some(Some(object)) = self.assets.objects.get(object_index as usize) {
+                    let ids = object.children.clone();
+                    let mut iter = self.room.instance_list.iter_by_identity(ids);
+                    while let Some(instance) = iter.next(&self.room.instance_list) {
+                        self.set_instance_field(instance, index, array_index, value.clone());
+                    }
                 }
             },
-            Node::Function { args, function_id } => {
-                let mut arg_values: [Value; 16] = Default::default();
-                for (src, dest) in args.iter().zip(arg_values.iter_mut()) {
-                    *dest = self.eval(src, context)?;
+            Target::All => {
+                let mut iter = self.room.instance_list.iter_by_insertion();
+                while let Some(instance) = iter.next(&self.room.instance_list) {
+                    self.set_instance_field(instance, index, array_index, value.clone());
                 }
-                self.invoke(*function_id, context, &arg_values[..args.len()])
             },
-            Node::Script { args, script_id } => {
-                if let Some(Some(script)) = self.assets.scripts.get(*script_id) {
-                    let instructions = script.compiled.clone();
-
-                    let mut arg_values: [Value; 16] = Default::default();
-                    for (src, dest) in args.iter().zip(arg_values.iter_mut()) {
-                        *dest = self.eval(src, context)?;
-                    }
-
-                    let mut new_context = Context::copy_with_args(context, arg_values, args.len());
-                    self.execute(&instructions, &mut new_context)?;
-                    Ok(new_context.return_value)
+            Target::Global => {
+                if let Some(field) = self.globals.fields.get_mut(&index) {
+                    field.set(array_index, value)
                 } else {
-                    Err(Error::NonexistentAsset(asset::Type::Script, *script_id as i32))
+                    self.globals.fields.insert(index, Field::new(array_index, value));
                 }
             },
-            Node::ExtensionFunction { args, id } => {
-                let mut arg_values: [Value; 16] = Default::default();
-                for (src, dest) in args.iter().zip(arg_values.iter_mut()) {
-                    *dest = self.eval(src, context)?;
+            Target::Local => {
+                if let Some(field) = context.locals.fields.get_mut(&index) {
+                    field.set(array_index, value)
+                } else {
+                    context.locals.fields.insert(index, Field::new(array_index, value));
                 }
+            },
+        }
+    }
 
-                self.run_extension_function(*id, Context::copy_with_args(context, arg_values, args.len()))
-            },
-            Node::Field { accessor } => {
-                let target = self.get_target(context, &accessor.owner, self.globalvars.contains(&accessor.index))?;
-                let array_index = self.get_array_index(&accessor.array, context)?;
-                match target {
-                    Target::Single(None) if self.uninit_fields_are_zero => Ok(Default::default()),
-                    Target::Single(None) => Err(Error::UninitializedVariable(
-                        self.compiler.get_field_name(accessor.index).unwrap(),
-                        array_index,
-                    )),
-                    Target::Single(Some(instance)) => self.get_instance_field(instance, accessor.index, array_index),
-                    Target::Objects(index) => {
-                        if let Some(instance) = self.assets.objects.get(index as usize).and_then(|x| match x {
-                            Some(x) => {
-                                self.room.instance_list.iter_by_identity(x.children.clone()).next(&self.room.instance_list)
-                            },
-                            None => None,
-                        }) {
-                            self.get_instance_field(instance, accessor.index, array_index)
-                        } else {
-                            if self.uninit_fields_are_zero {
-                                Ok(Default::default())
-                            } else {
-                                Err(Error::UninitializedVariable(
-                                    self.compiler.get_field_name(accessor.index).unwrap(),
-                                    array_index,
-                                ))
-                            }
-                        }
-                    },
-                    Target::All => {
-                        if let Some(instance) = self.room.instance_list.iter_by_insertion().next(&self.room.instance_list) {
-                            self.get_instance_field(instance, accessor.index, array_index)
-                        } else {
-                            if self.uninit_fields_are_zero {
-                                Ok(Default::default())
-                            } else {
-                                Err(Error::UninitializedVariable(
-                                    self.compiler.get_field_name(accessor.index).unwrap(),
-                                    array_index,
-                                ))
-                            }
-                        }
-                    },
-                    Target::Global => match self.globals.fields.get(&accessor.index).and_then(|x| x.get(array_index)) {
-                        Some(i) => Ok(i),
-                        None => {
-                            if self.uninit_fields_are_zero {
-                                Ok(Default::default())
-                            } else {
-                                return Err(Error::UninitializedVariable(
-                                    self.compiler.get_field_name(accessor.index).unwrap(),
-                                    array_index,
-                                ))
-                            }
-                        },
-                    },
-                    Target::Local => {
-                        match context.locals.fields.get(&accessor.index).and_then(|x| x.get(array_index)) {
-                            Some(i) => Ok(i),
-                            None => {
-                                if self.uninit_fields_are_zero {
-                                    Ok(Default::default())
-                                } else {
-                                    return Err(Error::UninitializedVariable(
-                                        self.compiler.get_field_name(accessor.index).unwrap(),
-                                        array_index,
-                                    ))
-                                }
-                            },
-                        }
-                    },
+    // Set an instance variable on whoever it belongs to
+    fn set_var(
+        &mut self,
+        target: Target,
+        var: &InstanceVariable,
+        array_index: u32,
+        value: Value,
+        context: &mut Context,
+    ) -> gml::Result<()> {
+        match target {
+            Target::Single(None) => (),
+            Target::Single(Some(instance)) => {
+                self.set_instance_var(instance, var, array_index, value, context)?;
+            },
+            Target::Objects(object_index) => {
+                if let Some(Some(object)) = self.assets.objects.get(object_index as usize) {
+                    let ids = object.children.clone();
+                    let mut iter = self.room.instance_list.iter_by_identity(ids);
+                    while let Some(instance) = iter.next(&self.room.instance_list) {
+                        self.set_instance_var(instance, var, array_index, value.clone(), context)?;
+                    }
                 }
             },
-            Node::Variable { accessor } => {
-                let target = self.get_target(context, &accessor.owner, false)?;
-                let array_index = self.get_array_index(&accessor.array, context)?;
-                match target {
-                    Target::Single(None) if self.uninit_fields_are_zero => Ok(Default::default()),
-                    Target::Single(None) => Err(Error::UninitializedVariable(
-                        String::from(mappings::INSTANCE_VARIABLES.iter().find(|(_, x)| x == &accessor.var).unwrap().0),
-                        array_index,
-                    )),
-                    Target::Single(Some(instance)) => {
-                        self.get_instance_var(instance, &accessor.var, array_index, context)
-                    },
-                    Target::Objects(index) => {
-                        if let Some(instance) = self.assets.objects.get(index as usize).and_then(|x| match x {
-                            Some(x) => {
-                                self.room.instance_list.iter_by_identity(x.children.clone()).next(&self.room.instance_list)
-                            },
-                            None => None,
-                        }) {
-                            self.get_instance_var(instance, &accessor.var, array_index, context)
-                        } else {
-                            if self.uninit_fields_are_zero {
-                                Ok(Default::default())
-                            } else {
-                                Err(Error::UninitializedVariable(
-                                    String::from(
-                                        mappings::INSTANCE_VARIABLES
-                                            .iter()
-                                            .find(|(_, x)| x == &accessor.var)
-                                            .unwrap()
-                                            .0,
-                                    ),
-                                    array_index,
-                                ))
-                            }
-                        }
-                    },
-                    Target::All => {
-                        if let Some(instance) = self.room.instance_list.iter_by_insertion().next(&self.room.instance_list) {
-                            self.get_instance_var(instance, &accessor.var, array_index, context)
-                        } else {
-                            if self.uninit_fields_are_zero {
-                                Ok(Default::default())
-                            } else {
-                                Err(Error::UninitializedVariable(
-                                    String::from(
-                                        mappings::INSTANCE_VARIABLES
-                                            .iter()
-                                            .find(|(_, x)| x == &accessor.var)
-                                            .unwrap()
-                                            .0,
-                                    ),
-                                    array_index,
-                                ))
-                            }
-                        }
-                    },
-                    Target::Global => match self.globals.vars.get(&accessor.var).and_then(|x| x.get(array_index)) {
-                        Some(i) => Ok(i),
-                        None => {
-                            if self.uninit_fields_are_zero {
-                                Ok(Default::default())
-                            } else {
-                                return Err(Error::UninitializedVariable(
-                                    String::from(
-                                        mappings::INSTANCE_VARIABLES
-                                            .iter()
-                                            .find(|(_, x)| x == &accessor.var)
-                                            .unwrap()
-                                            .0,
-                                    ),
-                                    array_index,
-                                ))
-                            }
-                        },
-                    },
-                    Target::Local => match context.locals.vars.get(&accessor.var).and_then(|x| x.get(array_index)) {
-                        Some(i) => Ok(i),
-                        None => {
-                            if self.uninit_fields_are_zero {
-                                Ok(Default::default())
-                            } else {
-                                return Err(Error::UninitializedVariable(
-                                    String::from(
-                                        mappings::INSTANCE_VARIABLES
-                                            .iter()
-                                            .find(|(_, x)| x == &accessor.var)
-                                            .unwrap()
-                                            .0,
-                                    ),
-                                    array_index,
-                                ))
-                            }
-                        },
-                    },
+            Target::All => {
+                let mut iter = self.room.instance_list.iter_by_insertion();
+                while let Some(instance) = iter.next(&self.room.instance_list) {
+                    self.set_instance_var(instance, var, array_index, value.clone(), context)?;
                 }
             },
-            Node::Binary { left, right, operator } => {
-                operator.call(self.eval(left, context)?, self.eval(right, context)?)
+            Target::Global => {
+                if let Some(field) = self.globals.vars.get_mut(var) {
+                    field.set(array_index, value)
+                } else {
+                    self.globals.vars.insert(*var, Field::new(array_index, value));
+                }
             },
-            Node::Unary { child, operator } => operator.call(self.eval(child, context)?),
-            Node::RuntimeError { error } => Err(error.clone()),
-        }
-    }
-
-    // Resolves an ArrayAccessor to an index (u32)
-    fn get_array_index(&mut self, accessor: &ArrayAccessor, context: &mut Context) -> gml::Result<u32> {
-        match accessor {
-            ArrayAccessor::None => Ok(0),
-            ArrayAccessor::Single(node) => {
-                let index = self.eval(node, context)?.round();
-                if index < 0 || index >= 32000 { Err(Error::InvalidArrayIndex(index)) } else { Ok(index as u32) }
-            },
-            ArrayAccessor::Double(node1, node2) => {
-                let index1 = self.eval(node1, context)?.round();
-                let index2 = self.eval(node2, context)?.round();
-                if index1 < 0 || index1 >= 32000 {
-                    Err(Error::InvalidArrayIndex(index1))
-                } else if index2 < 0 || index2 >= 32000 {
-                    Err(Error::InvalidArrayIndex(index2))
+            Target::Local => {
+                if let Some(field) = context.locals.vars.get_mut(var) {
+                    field.set(array_index, value)
                 } else {
-                    Ok(((index1 * 32000) + index2) as u32)
+                    context.locals.vars.insert(*var, Field::new(array_index, value));
                 }
             },
         }
+        Ok(())
     }
 
     // Get a field value from an instance
@@ -1543,38 +1534,53 @@ impl Game {
         }
     }
 
-    // Resolves an InstanceIdentifier to a Target
-    fn get_target(
-        &mut self,
-        context: &mut Context,
-        identifier: &InstanceIdentifier,
-        in_globalvars: bool,
-    ) -> gml::Result<Target> {
-        match identifier {
-            InstanceIdentifier::Own => Ok(Target::Single(Some(context.this))),
-            InstanceIdentifier::Other => Ok(Target::Single(Some(context.other))),
-            InstanceIdentifier::Global => Ok(Target::Global),
-            InstanceIdentifier::Local => Ok(Target::Local),
-            InstanceIdentifier::Unknown => {
-                if in_globalvars {
-                    Ok(Target::Global)
-                } else {
-                    Ok(Target::Single(Some(context.this)))
-                }
-            },
-            InstanceIdentifier::Expression(node) => {
-                let value = self.eval(node, context).map(i32::from)?;
-                match value {
-                    gml::SELF | gml::UNSPECIFIED => Ok(Target::Single(Some(context.this))),
-                    gml::OTHER => Ok(Target::Single(Some(context.other))),
-                    gml::ALL => Ok(Target::All),
-                    gml::NOONE => Ok(Target::Single(None)),
-                    gml::GLOBAL => Ok(Target::Global),
-                    gml::LOCAL => Ok(Target::Local),
-                    i if i >= 100_000 => Ok(Target::Single(self.room.instance_list.get_by_instid(i))),
-                    i => Ok(Target::Objects(i)),
-                }
-            },
+    // Works out who owns a variable. Fields with no owner given belong to self, unless they've been declared globalvar.
+    fn target(&self, owner: Owner, field: Option<usize>, context: &Context, targets: &mut Vec<Target>) -> Target {
+        match owner {
+            Owner::Own => Target::Single(Some(context.this)),
+            Owner::Other => Target::Single(Some(context.other)),
+            Owner::Global => Target::Global,
+            Owner::Local => Target::Local,
+            Owner::Unknown => match field {
+                Some(index) if self.globalvars.contains(&index) => Target::Global,
+                _ => Target::Single(Some(context.this)),
+            },
+            Owner::Target => targets.pop().unwrap(),
+        }
+    }
+
+    // Works out what the instance part of something like `a.b` refers to
+    fn target_of(&self, value: i32, context: &Context) -> Target {
+        match value {
+            gml::SELF | gml::UNSPECIFIED => Target::Single(Some(context.this)),
+            gml::OTHER => Target::Single(Some(context.other)),
+            gml::ALL => Target::All,
+            gml::NOONE => Target::Single(None),
+            gml::GLOBAL => Target::Global,
+            gml::LOCAL => Target::Local,
+            i if i >= 100_000 => Target::Single(self.room.instance_list.get_by_instid(i)),
+            i => Target::Objects(i),
+        }
+    }
+}
+
+// Takes a call's arguments off the stack.
+fn take_args(stack: &mut Vec<Value>, count: usize) -> [Value; 16] {
+    let mut args: [Value; 16] = Default::default();
+    for (dest, src) in args.iter_mut().zip(stack.drain(stack.len() - count..)) {
+        *dest = src;
+    }
+    args
+}
+
+// Pops one or two array indices, combining them into one like GM8 does with 2D arrays.
+fn pop_array_index(stack: &mut Vec<Value>, dims: usize) -> gml::Result<u32> {
+    let index = stack.pop().unwrap().round();
+    let outer = if dims == 2 { stack.pop().unwrap().round() } else { 0 };
+    for &i in &[outer, index] {
+        if !(0..32000).contains(&i) {
+            return Err(Error::InvalidArrayIndex(i))
         }
     }
+    Ok((outer * 32000 + index) as u32)
 }
@@ -0,0 +1,372 @@
+//! Keeps track of what code is running and why, so runtime errors can say where they came from
+//! the same way GM8 does: "ERROR in action number 1 of Step Event for object obj_player".
+//! Also decides what happens after an error, since in GM8 most of them don't end the game.
+
+use crate::{
+    game::{errordialog::Choice, replay, Game, GetAsset, PlayType},
+    gml::{self, ev, profiler::Entry},
+};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+/// The line at the top of every error message.
+pub const SEPARATOR: &str = "___________________________________________";
+
+/// One level of the stack, pushed by whatever started running some code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Frame {
+    Event { object: i32, event_type: usize, event_number: usize },
+    Moment { timeline: i32, moment: i32 },
+    Action(usize), // index in the event or moment's list, starting at 0
+    Script(usize),
+    RoomCreation(i32),
+    InstanceCreation(i32), // instance id
+    With(i32),             // the target, which might be an object or a keyword like all
+}
+
+/// An error along with where it happened.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Located {
+    pub error: gml::Error,
+    pub location: String,
+}
+
+impl Display for Located {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", SEPARATOR)?;
+        write!(f, "{}\n{}", self.location, self.error)
+    }
+}
+
+impl Game {
+    /// Runs something with a frame on the stack. If it fails, the error gets told where it happened,
+    /// unless something further in already did that.
+    pub fn with_frame<T>(&mut self, frame: Frame, f: impl FnOnce(&mut Self) -> gml::Result<T>) -> gml::Result<T> {
+        self.call_stack.push(frame);
+        let result = match frame {
+            // actions are too small to be worth timing by themselves
+            Frame::Action(_) => f(self),
+            frame => self.profiled(Entry::Frame(frame), f),
+        }
+        .map_err(|e| self.locate_error(e, None));
+        self.call_stack.pop();
+        result
+    }
+
+    /// Attaches the current location, and the line of code if known, to an error that doesn't have one yet.
+    /// Errors raised outside of any frame are left as they are, since there's nothing to say about them.
+    pub fn locate_error(&self, error: gml::Error, line: Option<usize>) -> gml::Error {
+        match error {
+            gml::Error::Located(_) | gml::Error::Aborted(_) => error,
+            error if self.call_stack.is_empty() => error,
+            error => gml::Error::Located(Box::new(Located { location: self.describe_call_stack(line), error })),
+        }
+    }
+
+    /// Like with_frame, but for a whole action or piece of creation code. If that fails, the error is
+    /// handled like GM8 would and the game carries on as if the code returned nothing.
+    pub fn with_frame_caught<T: Default>(
+        &mut self,
+        frame: Frame,
+        f: impl FnOnce(&mut Self) -> gml::Result<T>,
+    ) -> gml::Result<T> {
+        match self.with_frame(frame, f) {
+            Err(e) => self.catch_error(e),
+            result => result,
+        }
+    }
+
+    /// Handles an error like with_frame_caught does, for when it didn't come from running something in a frame.
+    pub fn catch_error<T: Default>(&mut self, error: gml::Error) -> gml::Result<T> {
+        if self.fatal_errors || matches!(error.innermost(), gml::Error::ReplayError(_) | gml::Error::Aborted(_)) {
+            return Err(error)
+        }
+        self.raise_error(error.to_string(), false)?;
+        Ok(Default::default())
+    }
+
+    /// Reports an error the way GM8 does. If the game displays errors the player gets to ignore or abort,
+    /// otherwise it just ends up in error_occurred and error_last. Returns Err if the game should end.
+    pub fn raise_error(&mut self, message: String, abort: bool) -> gml::Result<()> {
+        self.error_occurred = true;
+        self.error_last = message.clone().into();
+        if abort || self.fatal_errors || (self.show_errors && self.always_abort) {
+            return Err(gml::Error::Aborted(message))
+        }
+        if !self.show_errors {
+            return Ok(())
+        }
+        let choice = match self.play_type {
+            PlayType::Replay => match self.stored_events.pop_front() {
+                Some(replay::Event::IgnoreError) => Choice::Ignore,
+                _ => return Err(gml::Error::ReplayError(format!("unexpected error:\n{}", message))),
+            },
+            // with nobody to ask, the error ends the game like it would without the dialog
+            _ => self.error_dialog(&message).unwrap_or(Choice::Abort),
+        };
+        match choice {
+            Choice::Ignore => {
+                if self.play_type == PlayType::Record {
+                    self.stored_events.push_back(replay::Event::IgnoreError);
+                }
+                Ok(())
+            },
+            Choice::Abort => Err(gml::Error::Aborted(message)),
+        }
+    }
+
+    // the innermost thing that ran some code, and the innermost script if there's one inside that
+    fn describe_call_stack(&self, line: Option<usize>) -> String {
+        let origin =
+            self.call_stack.iter().rposition(|f| !matches!(f, Frame::Action(_) | Frame::Script(_) | Frame::With(_)));
+        let frames = &self.call_stack[origin.unwrap_or(0)..];
+        let action = frames.iter().find_map(|f| if let Frame::Action(i) = f { Some(i + 1) } else { None });
+        let script = frames.iter().rev().find_map(|f| if let Frame::Script(id) = f { Some(*id) } else { None });
+
+        let mut s = String::from("ERROR in\n");
+        if let Some(action) = action {
+            s += &format!("action number {}\n", action);
+        }
+        match origin.map(|i| self.call_stack[i]) {
+            Some(Frame::Event { object, event_type, event_number }) => {
+                let (event, object) = (self.event_name(event_type, event_number), self.object_name(object));
+                s += &format!("of {}\nfor object {}:\n", event, object);
+            },
+            Some(Frame::Moment { timeline, moment }) => {
+                let name = self.assets.timelines.get_asset(timeline).map(|t| t.name.to_string());
+                s += &format!("of time line moment {}\nof time line {}:\n", moment, name.unwrap_or_default());
+            },
+            Some(Frame::RoomCreation(room)) => {
+                let name = self.assets.rooms.get_asset(room).map(|r| r.name.to_string());
+                s += &format!("creation code of room {}:\n", name.unwrap_or_default());
+            },
+            Some(Frame::InstanceCreation(id)) => s += &format!("creation code of instance {}:\n", id),
+            _ => (),
+        }
+        let script_name = script.and_then(|id| self.assets.scripts.get_asset(id as _)).map(|s| &s.name);
+        if let Some(name) = script_name {
+            s += &format!("\nIn script {}:\n", name);
+        }
+        if let Some(line) = line {
+            if script_name.is_none() {
+                s.push('\n');
+            }
+            s += &format!("Error in code at line {}:\n", line);
+        }
+        s
+    }
+
+    /// Describes one frame of the call stack by itself, like "script scr_move" or "action number 2".
+    pub fn describe_frame(&self, frame: Frame) -> String {
+        match frame {
+            Frame::Event { object, event_type, event_number } => {
+                format!("{} for object {}", self.event_name(event_type, event_number), self.object_name(object))
+            },
+            Frame::Moment { timeline, moment } => {
+                let name = self.assets.timelines.get_asset(timeline).map(|t| t.name.to_string());
+                format!("time line moment {} of time line {}", moment, name.unwrap_or_default())
+            },
+            Frame::Action(i) => format!("action number {}", i + 1),
+            Frame::Script(id) => {
+                let name = self.assets.scripts.get_asset(id as _).map(|s| s.name.to_string());
+                format!("script {}", name.unwrap_or_default())
+            },
+            Frame::RoomCreation(room) => {
+                let name = self.assets.rooms.get_asset(room).map(|r| r.name.to_string());
+                format!("creation code of room {}", name.unwrap_or_default())
+            },
+            Frame::InstanceCreation(id) => format!("creation code of instance {}", id),
+            Frame::With(target) => match target {
+                gml::SELF => "with (self)".into(),
+                gml::OTHER => "with (other)".into(),
+                gml::ALL => "with (all)".into(),
+                gml::NOONE => "with (noone)".into(),
+                t if t >= 100000 => format!("with ({})", t),
+                t => format!("with ({})", self.object_name(t)),
+            },
+        }
+    }
+
+    fn object_name(&self, object: i32) -> String {
+        self.assets.objects.get_asset(object).map(|o| o.name.to_string()).unwrap_or_else(|| "<undefined>".into())
+    }
+
+    fn event_name(&self, event_type: usize, event_number: usize) -> String {
+        match event_type {
+            ev::CREATE => "Create Event".into(),
+            ev::DESTROY => "Destroy Event".into(),
+            ev::ALARMS => format!("Alarm Event for alarm {}", event_number),
+            ev::STEP => match event_number {
+                1 => "Begin Step Event".into(),
+                2 => "End Step Event".into(),
+                _ => "Step Event".into(),
+            },
+            ev::COLLISION => format!("Collision Event with object {}", self.object_name(event_number as _)),
+            ev::KEYBOARD => format!("Keyboard Event for key {}", event_number),
+            ev::MOUSE => format!("Mouse Event {}", event_number),
+            ev::OTHER => match event_number {
+                0 => "Other Event: Outside Room".into(),
+                1 => "Other Event: Intersect Boundary".into(),
+                2 => "Other Event: Game Start".into(),
+                3 => "Other Event: Game End".into(),
+                4 => "Other Event: Room Start".into(),
+                5 => "Other Event: Room End".into(),
+                6 => "Other Event: No More Lives".into(),
+                7 => "Other Event: Animation End".into(),
+                8 => "Other Event: End of Path".into(),
+                9 => "Other Event: No More Health".into(),
+                n @ 10..=25 => format!("Other Event: User Defined {}", n - 10),
+                n => format!("Other Event {}", n),
+            },
+            ev::DRAW => "Draw Event".into(),
+            ev::KEYPRESS => format!("Key Press Event for key {}", event_number),
+            ev::KEYRELEASE => format!("Key Release Event for key {}", event_number),
+            ev::TRIGGER => {
+                let name = self.assets.triggers.get(event_number).and_then(|t| t.as_ref()).map(|t| t.name.to_string());
+                format!("Trigger Event: {}", name.unwrap_or_default())
+            },
+            n => format!("Event {}", n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        action::Tree,
+        instance::Instance,
+        testing::{action, add_script, run, test_game},
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    fn location(error: gml::Error) -> String {
+        match error {
+            gml::Error::Located(located) => located.location,
+            other => panic!("expected an error with a location, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn errors_say_where_they_happened() {
+        let mut game = test_game();
+        let actions = [action("action_fill_color", &["c_red"]), action("action_fill_color", &["nope"])];
+        let tree = Tree::from_list(&actions, &mut game.compiler).unwrap();
+        game.assets.objects.get_asset_mut(0).unwrap().events[gml::ev::STEP].insert(2, Rc::new(RefCell::new(tree)));
+        let object = game.assets.objects.get_asset(0).unwrap();
+        let instance = game.room.instance_list.insert(Instance::new(100001, 0.into(), 0.into(), 0, object));
+        game.fatal_errors = true;
+        match game.run_instance_event(gml::ev::STEP, 2, instance, instance, None) {
+            Err(gml::Error::Located(located)) => {
+                assert_eq!(located.location, "ERROR in\naction number 2\nof End Step Event\nfor object object0:\n");
+                assert!(matches!(located.error, gml::Error::UninitializedVariable(..)));
+            },
+            _ => panic!("expected an error with a location"),
+        }
+        assert!(game.call_stack.is_empty());
+    }
+
+    #[test]
+    fn scripts_say_what_called_them() {
+        let mut game = test_game();
+        add_script(&mut game, "scr_move", "");
+        game.call_stack = vec![
+            Frame::Event { object: 0, event_type: ev::STEP, event_number: 1 },
+            Frame::Action(0),
+            Frame::With(gml::OTHER),
+            Frame::Script(0),
+        ];
+        let error = game.locate_error(gml::Error::InvalidArrayIndex(-1), Some(3));
+        assert_eq!(
+            location(error.clone()),
+            concat!(
+                "ERROR in\naction number 1\nof Begin Step Event\nfor object object0:\n",
+                "\nIn script scr_move:\nError in code at line 3:\n",
+            ),
+        );
+
+        // an event run from inside that one is where its errors come from
+        game.call_stack.push(Frame::Event { object: 0, event_type: ev::CREATE, event_number: 0 });
+        let inner = game.locate_error(gml::Error::InvalidArrayIndex(-1), None);
+        assert_eq!(location(inner), "ERROR in\nof Create Event\nfor object object0:\n");
+        game.call_stack.push(Frame::RoomCreation(0));
+        let inner = game.locate_error(gml::Error::InvalidArrayIndex(-1), None);
+        assert_eq!(location(inner), "ERROR in\ncreation code of room room0:\n");
+
+        // errors keep the first location they were given, and ones from outside of anything don't get one
+        assert!(location(game.locate_error(error, None)).contains("In script scr_move"));
+        game.call_stack.clear();
+        assert!(matches!(game.locate_error(gml::Error::InvalidArrayIndex(-1), None), gml::Error::InvalidArrayIndex(_)));
+    }
+
+    #[test]
+    fn frames_describe_themselves() {
+        let mut game = test_game();
+        add_script(&mut game, "scr_move", "");
+        let frames = [
+            (
+                Frame::Event { object: 0, event_type: ev::OTHER, event_number: 12 },
+                "Other Event: User Defined 2 for object object0",
+            ),
+            (
+                Frame::Event { object: 5, event_type: ev::ALARMS, event_number: 3 },
+                "Alarm Event for alarm 3 for object <undefined>",
+            ),
+            (Frame::Moment { timeline: 0, moment: 10 }, "time line moment 10 of time line "),
+            (Frame::Action(1), "action number 2"),
+            (Frame::Script(0), "script scr_move"),
+            (Frame::RoomCreation(0), "creation code of room room0"),
+            (Frame::InstanceCreation(100001), "creation code of instance 100001"),
+            (Frame::With(gml::ALL), "with (all)"),
+            (Frame::With(0), "with (object0)"),
+            (Frame::With(100001), "with (100001)"),
+        ];
+        for (frame, description) in &frames {
+            assert_eq!(game.describe_frame(*frame), *description);
+        }
+    }
+
+    #[test]
+    fn errors_can_be_ignored() {
+        let mut game = test_game();
+        let actions = [action("action_fill_color", &["nope"]), action("action_fill_color", &["c_red"])];
+
+        // there's no window to ask in, so the error ends the game and nothing is recorded
+        game.play_type = PlayType::Record;
+        assert!(matches!(run(&mut game, 0.0, 0.0, &actions), Err(gml::Error::Aborted(_))));
+        assert!(game.error_occurred);
+        assert!(game.error_last.as_ref().starts_with(b"___"));
+        assert!(game.stored_events.is_empty());
+
+        // a replay does whatever the player chose, and "Ignore" skips the rest of the action
+        game.play_type = PlayType::Replay;
+        assert!(matches!(run(&mut game, 0.0, 0.0, &actions), Err(gml::Error::ReplayError(_))));
+        game.stored_events.push_back(replay::Event::IgnoreError);
+        run(&mut game, 0.0, 0.0, &actions).unwrap();
+        assert_eq!(u32::from(game.draw_colour), 0x0000FF);
+
+        // without error messages there's nothing to ignore
+        game.show_errors = false;
+        game.error_occurred = false;
+        run(&mut game, 0.0, 0.0, &actions).unwrap();
+        assert!(game.error_occurred && game.stored_events.is_empty());
+
+        game.show_errors = true;
+        game.always_abort = true;
+        game.stored_events.push_back(replay::Event::IgnoreError);
+        assert!(matches!(run(&mut game, 0.0, 0.0, &actions), Err(gml::Error::Aborted(_))));
+    }
+
+    #[test]
+    fn show_error_can_abort() {
+        let mut game = test_game();
+        game.show_errors = false;
+        game.show_error(&["careful".into(), false.into()]).unwrap();
+        assert_eq!(game.error_last.as_ref(), b"___________________________________________\ncareful");
+        assert!(matches!(game.show_error(&["bye".into(), true.into()]), Err(gml::Error::Aborted(_))));
+
+        // displaying it with nobody to show it to is fatal
+        game.show_errors = true;
+        assert!(matches!(game.show_error(&["careful".into(), false.into()]), Err(gml::Error::Aborted(_))));
+    }
+}
@@ -81,6 +81,20 @@ impl<T, const LEN: usize> HandleArray<T, LEN> {
     pub fn capacity(&self) -> i32 {
         LEN.try_into().unwrap()
     }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Option<T>> {
+        self.0.iter()
+    }
+}
+
+impl<T, const LEN: usize> std::iter::FromIterator<Option<T>> for HandleArray<T, LEN> {
+    fn from_iter<I: IntoIterator<Item = Option<T>>>(iter: I) -> Self {
+        let mut array = Self::new();
+        for (slot, handle) in array.0.iter_mut().zip(iter) {
+            *slot = handle;
+        }
+        array
+    }
 }
 
 #[inline]
@@ -0,0 +1,20 @@
+#![feature(seek_stream_len)]
+#![allow(dead_code)] // Shut up.
+
+mod action;
+mod asset;
+mod game;
+mod gml;
+mod handleman;
+mod input;
+mod instance;
+mod instancelist;
+mod math;
+#[cfg(test)]
+mod testing;
+mod tile;
+mod util;
+
+// what the emulator and replay-tool binaries use, everything else is internal
+pub use game::{compilecache::Key as CompileCacheKey, Game, PlayType, Replay};
+pub use gml::{datetime::now_as_nanos, debugger::Debugger, file::PathResolver, process::Policy, profiler::Profiler};
@@ -1,21 +1,6 @@
-#![feature(seek_stream_len)]
-#![allow(dead_code)] // Shut up.
-
-mod action;
-mod asset;
-mod game;
-mod gml;
-mod handleman;
-mod input;
-mod instance;
-mod instancelist;
-mod math;
-mod tile;
-mod util;
-
+use gm8emulator::{now_as_nanos, CompileCacheKey, Debugger, Game, PathResolver, PlayType, Policy, Profiler, Replay};
 use std::{
     env, fs,
-    io::{BufReader, Write},
     path::{Path, PathBuf},
     process,
 };
@@ -48,10 +33,20 @@ fn xmain() -> i32 {
     opts.optflag("v", "verbose", "enables verbose logging");
     opts.optflag("r", "realtime", "disables clock spoofing");
     opts.optflag("l", "no-framelimit", "disables the frame-limiter");
+    opts.optflagopt("", "headless", "runs without a window or GPU, as fast as possible (default 1920x1080)", "WxH");
     opts.optopt("p", "port", "port to open for external game control (default 15560)", "PORT");
     opts.optopt("n", "project-name", "name of TAS project to create or load", "NAME");
     opts.optopt("f", "replay-file", "path to savestate file to replay", "FILE");
+    opts.optopt("", "export-replay", "writes the -f replay to FILE as text, then exits", "FILE");
     opts.optmulti("a", "game-arg", "argument to pass to the game", "ARG");
+    opts.optmulti("", "drive", "maps a Windows drive letter to a directory (by default they're all /)", "LETTER=DIR");
+    opts.optmulti("", "allow-exec", "lets the game run a path, URL scheme or name in its folder (* for all)", "NAME");
+    opts.optflag("", "fatal-errors", "ends the game at the first runtime error instead of carrying on like GM8");
+    opts.optflag("", "no-checksums", "doesn't record state checksums in replays, or check the ones already there");
+    opts.optopt("", "cd", "puts a folder of .wav or raw CD tracks in the virtual CD drive (timed, not heard)", "DIR");
+    opts.optflag("", "no-compile-cache", "compiles all the game's code at startup instead of using the cached copy");
+    opts.optopt("", "debug", "lets a GML debugger attach on this port", "PORT");
+    opts.optopt("", "profile", "times scripts, events and functions, and writes folded stacks to FILE at exit", "FILE");
 
     let matches = match opts.parse(&args[1..]) {
         Ok(matches) => matches,
@@ -76,7 +71,18 @@ fn xmain() -> i32 {
     let strict = matches.opt_present("s");
     let multithread = !matches.opt_present("t");
     let spoof_time = !matches.opt_present("r");
-    let frame_limiter = !matches.opt_present("l");
+    let headless = match matches.opt_default("headless", "").as_deref() {
+        None => None,
+        Some("") => Some(gmio::window::headless::DEFAULT_DISPLAY_SIZE),
+        Some(size) => match size.split_once('x').map(|(w, h)| (w.parse::<i32>(), h.parse::<i32>())) {
+            Some((Ok(w), Ok(h))) if w > 0 && h > 0 => Some((w, h)),
+            _ => {
+                eprintln!("invalid display size provided: {}, expected WxH", size);
+                return EXIT_FAILURE
+            },
+        },
+    };
+    let frame_limiter = !matches.opt_present("l") && headless.is_none();
     let verbose = matches.opt_present("v");
     let port = match matches.opt_str("p").map(|x| x.parse::<u16>()).transpose() {
         Ok(p) => p,
@@ -86,6 +92,30 @@ fn xmain() -> i32 {
         },
     }
     .unwrap_or(15560);
+    let debug_port = match matches.opt_str("debug").map(|x| x.parse::<u16>()).transpose() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("invalid debugger port provided: {}", e);
+            return EXIT_FAILURE
+        },
+    };
+    let mut path_resolver = PathResolver::default();
+    for drive in matches.opt_strs("drive") {
+        let mapped = match drive.split_once('=') {
+            Some((letter, dir)) if letter.chars().count() == 1 => {
+                path_resolver.set_drive(letter.chars().next().unwrap(), dir.into())
+            },
+            _ => Err("expected LETTER=DIR".into()),
+        };
+        if let Err(e) = mapped {
+            eprintln!("invalid drive mapping provided: {}, {}", drive, e);
+            return EXIT_FAILURE
+        }
+    }
+    let mut exec_policy = Policy::default();
+    for name in matches.opt_strs("allow-exec") {
+        exec_policy.allow(&name);
+    }
     let project_path = matches.opt_str("n").map(|name| {
         let mut p = env::current_dir().expect("std::env::current_dir() failed");
         p.push("projects");
@@ -119,27 +149,38 @@ fn xmain() -> i32 {
             })
     });
     let can_clear_temp_dir = temp_dir.is_none();
-    let replay = matches.opt_str("f").map(|filename| {
-        let mut filepath = PathBuf::from(&filename);
-        match filepath.extension().and_then(|x| x.to_str()) {
-            Some("bin") => {
-                let f = fs::File::open(&filepath).unwrap();
-                let replay = bincode::deserialize_from::<_, game::SaveState>(BufReader::new(f)).unwrap().into_replay();
-                filepath.set_extension("gmtas");
-                fs::File::create(&filepath).unwrap().write_all(&bincode::serialize(&replay).unwrap()).unwrap();
-                replay
+    let replay = match matches.opt_str("f").map(PathBuf::from) {
+        Some(path) => match Replay::from_file(&path) {
+            Ok(replay) => {
+                // savestates get their replay saved next to them as a .gmtas
+                if path.extension().and_then(|x| x.to_str()) == Some("bin") {
+                    if let Err(e) = replay.to_file(&path.with_extension("gmtas")) {
+                        eprintln!("couldn't save replay from {}: {}", path.display(), e);
+                    }
+                }
+                Some(replay)
             },
-
-            Some("gmtas") => {
-                bincode::deserialize_from::<_, game::Replay>(BufReader::new(fs::File::open(&filepath).unwrap()))
-                    .unwrap()
+            Err(e) => {
+                eprintln!("couldn't read replay {}: {}", path.display(), e);
+                return EXIT_FAILURE
             },
-
-            _ => {
-                panic!("Unknown filetype for -f, expected '.bin' or '.gmtas'");
+        },
+        None => None,
+    };
+    if let Some(path) = matches.opt_str("export-replay") {
+        let replay = match &replay {
+            Some(replay) => replay,
+            None => {
+                eprintln!("--export-replay needs a replay to export, given with -f");
+                return EXIT_FAILURE
             },
+        };
+        if let Err(e) = fs::write(&path, replay.to_text()) {
+            eprintln!("couldn't write replay to {}: {}", path, e);
+            return EXIT_FAILURE
         }
-    });
+        return EXIT_SUCCESS
+    }
     let input = {
         if matches.free.len() == 1 {
             &matches.free[0]
@@ -170,6 +211,8 @@ fn xmain() -> i32 {
         println!("loading '{}'...", input);
     }
 
+    let compile_cache = if matches.opt_present("no-compile-cache") { None } else { Some(CompileCacheKey::of(&file)) };
+
     #[rustfmt::skip]
     let assets = gm8exe::reader::from_exe(
         &mut file,                              // mut exe: AsRef<[u8]>
@@ -196,18 +239,42 @@ fn xmain() -> i32 {
             return EXIT_FAILURE
         },
     };
+    if let Some(dir) = absolute_path.parent() {
+        exec_policy.set_game_dir(dir);
+    }
 
     let encoding = encoding_rs::SHIFT_JIS; // TODO: argument
 
+    if headless.is_some() && project_path.is_some() {
+        eprintln!("can't record a TAS project headless, there would be nothing to control it with");
+        return EXIT_FAILURE
+    }
+    if headless.is_some() && replay.is_none() {
+        eprintln!("--headless needs a replay to play (-f), nothing would ever end the game otherwise");
+        return EXIT_FAILURE
+    }
+
     let play_type = if project_path.is_some() {
-        game::PlayType::Record
+        PlayType::Record
     } else if replay.is_some() {
-        game::PlayType::Replay
+        PlayType::Replay
     } else {
-        game::PlayType::Normal
+        PlayType::Normal
     };
 
-    let mut components = match game::Game::launch(assets, absolute_path, game_args, temp_dir, encoding, frame_limiter, play_type) {
+    let mut components = match Game::launch(
+        assets,
+        absolute_path,
+        game_args,
+        temp_dir,
+        encoding,
+        frame_limiter,
+        play_type,
+        headless,
+        path_resolver,
+        exec_policy,
+        compile_cache,
+    ) {
         Ok(g) => g,
         Err(e) => {
             eprintln!("Failed to launch game: {}", e);
@@ -215,9 +282,32 @@ fn xmain() -> i32 {
         },
     };
 
-    let time_now = gml::datetime::now_as_nanos();
+    components.fatal_errors = matches.opt_present("fatal-errors");
+    components.checksums = !matches.opt_present("no-checksums");
 
-    if let Err(err) = if let Some(path) = project_path {
+    if let Some(dir) = matches.opt_str("cd") {
+        if let Err(e) = components.cd.insert(dir.into()) {
+            eprintln!("couldn't read CD folder: {}", e);
+            return EXIT_FAILURE
+        }
+        println!("note: there's no audio output yet, so CD tracks will keep time but won't be heard");
+    }
+
+    if let Some(port) = debug_port {
+        match Debugger::listen(port) {
+            Ok(debugger) => components.debugger = Some(debugger),
+            Err(e) => {
+                eprintln!("couldn't open debugger port {}: {}", port, e);
+                return EXIT_FAILURE
+            },
+        }
+    }
+
+    components.profiler = matches.opt_str("profile").map(|path| Profiler::new(path.into()));
+
+    let time_now = now_as_nanos();
+
+    let result = if let Some(path) = project_path {
         components.spoofed_time_nanos = Some(time_now);
         components.record(path, port)
     } else {
@@ -230,8 +320,8 @@ fn xmain() -> i32 {
         let files_to_delete = components
             .included_files
             .iter()
-            .filter(|i| i.remove_at_end)
-            .map(|i| PathBuf::from(components.decode_str(i.name.as_ref()).into_owned()))
+            .filter(|i| i.remove_at_end && replay.is_none()) // replays never wrote them in the first place
+            .map(|i| components.path_resolver.resolve(&components.decode_str(i.name.as_ref())))
             .collect::<Vec<_>>();
         let result = if let Some(replay) = replay {
             components.replay(replay)
@@ -246,10 +336,26 @@ fn xmain() -> i32 {
             std::fs::remove_dir_all(temp_dir).ok();
         }
         result
-    } {
-        println!("Runtime error: {}", err);
+    };
+
+    report(&mut components, verbose);
+
+    if let Err(err) = result {
+        println!("Runtime error:\n{}", err);
         EXIT_FAILURE
     } else {
         EXIT_SUCCESS
     }
 }
+
+// prints the compiled code cache stats if verbose, and writes the profile if there is one
+fn report(components: &mut Game, verbose: bool) {
+    if verbose {
+        let (hits, misses) = components.compiler.cache_stats();
+        println!("compiled code cache: {} hits, {} misses", hits, misses);
+    }
+
+    if let Err(e) = components.profiler_report() {
+        eprintln!("couldn't write profile: {}", e);
+    }
+}
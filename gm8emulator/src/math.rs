@@ -437,7 +437,7 @@ mod tests {
     #[test]
     fn round() {
         for i in 0..1000 {
-            assert_eq!(0, Real(f64::from(i) + 0.5).round() % 2);
+            assert_eq!(Real(0.0), Real(f64::from(i) + 0.5).round() % Real(2.0));
         }
     }
 
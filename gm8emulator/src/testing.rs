@@ -0,0 +1,186 @@
+//! Shared fixtures for tests that need a whole game to run.
+
+use crate::{
+    action::{execution_type, kind, Tree},
+    game::{Game, GetAsset, PlayType},
+    gml::{self, file::PathResolver, process::Policy},
+    instance::Instance,
+};
+use gm8exe::asset::{object::Object, room::Room, CodeAction, PascalString};
+use std::{cell::RefCell, rc::Rc};
+
+// a game with one empty object and one empty room, that doesn't need a window to run
+pub fn test_game() -> Game {
+    test_game_sized(640, 480)
+}
+
+pub fn test_game_sized(width: u32, height: u32) -> Game {
+    let settings = gm8exe::settings::Settings {
+        fullscreen: false,
+        scaling: 100,
+        interpolate_pixels: false,
+        clear_colour: 0,
+        allow_resize: false,
+        window_on_top: false,
+        dont_draw_border: false,
+        dont_show_buttons: false,
+        display_cursor: true,
+        freeze_on_lose_focus: false,
+        disable_screensaver: false,
+        force_cpu_render: false,
+        set_resolution: false,
+        colour_depth: 0,
+        resolution: 0,
+        frequency: 0,
+        vsync: false,
+        esc_close_game: true,
+        treat_close_as_esc: true,
+        f1_help_menu: false,
+        f4_fullscreen_toggle: false,
+        f5_save_f6_load: false,
+        f9_screenshot: false,
+        priority: 0,
+        custom_load_image: None,
+        transparent: false,
+        translucency: 255,
+        loading_bar: 0,
+        backdata: None,
+        frontdata: None,
+        scale_progress_bar: false,
+        show_error_messages: true,
+        log_errors: false,
+        always_abort: false,
+        zero_uninitialized_vars: false,
+        error_on_uninitialized_args: true,
+    };
+    let object = Object {
+        name: "object0".into(),
+        sprite_index: -1,
+        solid: false,
+        visible: true,
+        depth: 0,
+        persistent: false,
+        parent_index: -1,
+        mask_index: -1,
+        events: (0..12).map(|_| Vec::new()).collect(),
+    };
+    let room = Room {
+        name: "room0".into(),
+        caption: "".into(),
+        width,
+        height,
+        speed: 30,
+        persistent: false,
+        bg_colour: 0.into(),
+        clear_screen: true,
+        clear_region: true,
+        creation_code: "".into(),
+        backgrounds: Vec::new(),
+        views_enabled: false,
+        views: Vec::new(),
+        instances: Vec::new(),
+        tiles: Vec::new(),
+    };
+    let assets = gm8exe::GameAssets {
+        triggers: Vec::new(),
+        constants: Vec::new(),
+        extensions: Vec::new(),
+        sprites: Vec::new(),
+        sounds: Vec::new(),
+        backgrounds: Vec::new(),
+        paths: Vec::new(),
+        scripts: Vec::new(),
+        fonts: Vec::new(),
+        timelines: Vec::new(),
+        objects: vec![Some(Box::new(object))],
+        rooms: vec![Some(Box::new(room))],
+        included_files: Vec::new(),
+        version: gm8exe::GameVersion::GameMaker8_0,
+        dx_dll: Vec::new(),
+        ico_file_raw: None,
+        help_dialog: gm8exe::settings::GameHelpDialog {
+            bg_colour: 0.into(),
+            new_window: false,
+            caption: "".into(),
+            left: 0,
+            top: 0,
+            width: 0,
+            height: 0,
+            border: false,
+            resizable: false,
+            window_on_top: false,
+            freeze_game: false,
+            info: "".into(),
+        },
+        last_instance_id: 100000,
+        last_tile_id: 10000000,
+        library_init_strings: Vec::new(),
+        room_order: vec![0],
+        settings,
+        game_id: 0,
+        guid: [0; 4],
+    };
+    Game::launch(
+        assets,
+        std::env::temp_dir().join("game.exe"),
+        vec!["game.exe".into()],
+        None,
+        encoding_rs::WINDOWS_1252,
+        false,
+        PlayType::Normal,
+        Some((width as _, height as _)),
+        PathResolver::default(),
+        Policy::default(),
+        None,
+    )
+    .unwrap()
+}
+
+// a function action with expressions for arguments
+pub fn action(function: &str, args: &[&str]) -> CodeAction {
+    let mut param_strings: [PascalString; gm8exe::asset::code_action::PARAM_COUNT] = Default::default();
+    for (param, arg) in param_strings.iter_mut().zip(args) {
+        *param = (*arg).into();
+    }
+    CodeAction {
+        id: 0,
+        applies_to: gml::SELF,
+        is_condition: function.starts_with("action_if"),
+        invert_condition: false,
+        is_relative: false,
+        lib_id: 1,
+        action_kind: kind::NORMAL,
+        execution_type: execution_type::FUNCTION,
+        can_be_relative: 0,
+        applies_to_something: true,
+        fn_name: function.into(),
+        fn_code: "".into(),
+        param_count: args.len(),
+        param_types: Default::default(),
+        param_strings,
+    }
+}
+
+// runs some actions for a new instance at (x, y), and gives back its handle
+pub fn run(game: &mut Game, x: f64, y: f64, actions: &[CodeAction]) -> gml::Result<usize> {
+    let object = game.assets.objects.get_asset(0).unwrap();
+    let instance = game.room.instance_list.insert(Instance::new(100001, x.into(), y.into(), 0, object));
+    let tree = Tree::from_list(actions, &mut game.compiler).unwrap();
+    game.execute_tree(Rc::new(RefCell::new(tree)), instance, instance, 0, 0, 0)?;
+    Ok(instance)
+}
+
+pub fn add_script(game: &mut Game, name: &str, source: &str) {
+    // registered first so it can call itself
+    game.compiler.register_script(name.as_bytes().to_vec().into_boxed_slice(), game.assets.scripts.len());
+    let compiled = game.compiler.compile(source.as_bytes()).unwrap();
+    let script = crate::asset::Script { name: name.into(), source: source.into(), compiled };
+    game.assets.scripts.push(Some(Box::new(script)));
+}
+
+// a code action, as in "Execute a piece of code"
+pub fn code(source: &str) -> CodeAction {
+    let mut code = action("", &[source]);
+    code.action_kind = kind::CODE;
+    code
+}
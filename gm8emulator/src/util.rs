@@ -25,7 +25,8 @@ pub fn rotate_around_center(x: &mut f64, y: &mut f64, sin: f64, cos: f64) {
 
 #[cfg(test)]
 mod tests {
-    use super::{bgra2rgba, ieee_round, rgba2bgra};
+    use super::{bgra2rgba, rgba2bgra};
+    use crate::math::ieee_round;
 
     #[test]
     fn bgra_rgba() {
@@ -41,16 +42,16 @@ mod tests {
 
     #[test]
     fn round() {
-        assert_eq!(ieee_round(-3.5), -4);
-        assert_eq!(ieee_round(-2.5), -2);
-        assert_eq!(ieee_round(-1.5), -2);
-        assert_eq!(ieee_round(-0.5), 0);
-        assert_eq!(ieee_round(0.5), 0);
-        assert_eq!(ieee_round(1.5), 2);
-        assert_eq!(ieee_round(2.5), 2);
-        assert_eq!(ieee_round(3.5), 4);
+        assert_eq!(ieee_round(-3.5), -4.0);
+        assert_eq!(ieee_round(-2.5), -2.0);
+        assert_eq!(ieee_round(-1.5), -2.0);
+        assert_eq!(ieee_round(-0.5), 0.0);
+        assert_eq!(ieee_round(0.5), 0.0);
+        assert_eq!(ieee_round(1.5), 2.0);
+        assert_eq!(ieee_round(2.5), 2.0);
+        assert_eq!(ieee_round(3.5), 4.0);
         for i in 0..1000 {
-            assert_eq!(ieee_round(i as f64 + 0.5) % 2, 0);
+            assert_eq!(ieee_round(i as f64 + 0.5) % 2.0, 0.0);
         }
     }
 }
@@ -32,6 +32,8 @@ pub use self::{
 
 use crate::GameVersion;
 use byteorder::LE;
+#[cfg(feature = "runner-serde-derives")]
+use serde::{Deserialize, Serialize};
 use std::{
     fmt::{self, Display},
     io,
@@ -86,6 +88,7 @@ fn assert_ver(got: u32, expected: u32) -> Result<(), Error> {
 }
 
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "runner-serde-derives", derive(Serialize, Deserialize))]
 pub struct PascalString(pub Box<[u8]>);
 
 impl Display for PascalString {
@@ -4,11 +4,15 @@ use crate::{
     GameVersion,
 };
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+#[cfg(feature = "runner-serde-derives")]
+use serde::{Deserialize, Serialize};
 use std::io::{self, Read};
 
 pub const VERSION: u32 = 440;
 pub const PARAM_COUNT: usize = 8;
 
+#[derive(Debug)]
+#[cfg_attr(feature = "runner-serde-derives", derive(Serialize, Deserialize))]
 pub struct CodeAction {
     /// Unique ID that identifies what type of DnD action this is.
     pub id: u32,
@@ -0,0 +1,125 @@
+//! Loading shared libraries at runtime, for the ones only some systems have (Wayland, EGL).
+//! Linking them normally would stop the binary from starting at all on systems without them.
+
+#![cfg(target_os = "linux")]
+
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_void,
+};
+
+/// A library opened with dlopen. It never gets closed, since what's loaded out of it is kept in statics.
+pub struct Library(*mut c_void);
+
+impl Library {
+    pub fn open(name: &str) -> Result<Self, String> {
+        let c_name = CString::new(name).map_err(|_| format!("bad library name {:?}", name))?;
+        let handle = unsafe { libc::dlopen(c_name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() { Err(last_error(name)) } else { Ok(Self(handle)) }
+    }
+
+    /// Finds a function or static by name. What it points to is up to the caller.
+    pub fn symbol(&self, name: &str) -> Result<*mut c_void, String> {
+        let c_name = CString::new(name).map_err(|_| format!("bad symbol name {:?}", name))?;
+        let symbol = unsafe { libc::dlsym(self.0, c_name.as_ptr()) };
+        if symbol.is_null() { Err(last_error(name)) } else { Ok(symbol) }
+    }
+}
+
+fn last_error(name: &str) -> String {
+    unsafe {
+        let error = libc::dlerror();
+        if error.is_null() {
+            format!("couldn't load {}", name)
+        } else {
+            CStr::from_ptr(error).to_string_lossy().into_owned()
+        }
+    }
+}
+
+/// Declares a struct of function pointers and statics, along with an `open` function that fills it in
+/// from the given libraries, failing if any of them or anything in them is missing.
+/// Attributes go on both the struct and its impl.
+macro_rules! library {
+    (
+        $(#[$attr: meta])*
+        pub struct $name: ident {
+            $($library: literal {
+                $(fn $function: ident: $function_type: ty,)*
+                $(static $static: ident: $static_type: ty,)*
+            })+
+        }
+    ) => {
+        $(#[$attr])*
+        pub struct $name {
+            $(
+                $(pub $function: $function_type,)*
+                $(pub $static: &'static $static_type,)*
+            )+
+        }
+
+        $(#[$attr])*
+        impl $name {
+            pub unsafe fn open() -> Result<Self, String> {
+                $(
+                    let library = crate::dl::Library::open($library)?;
+                    $(let $function = std::mem::transmute::<*mut std::os::raw::c_void, $function_type>(
+                        library.symbol(stringify!($function))?,
+                    );)*
+                    $(let $static = &*(library.symbol(stringify!($static))? as *const $static_type);)*
+                )+
+                Ok(Self { $($($function,)* $($static,)*)+ })
+            }
+        }
+    };
+}
+
+pub(crate) use library;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::raw::c_char;
+
+    #[test]
+    fn symbols() {
+        let libc = Library::open("libc.so.6").unwrap();
+        let strlen = libc.symbol("strlen").unwrap();
+        type Strlen = unsafe extern "C" fn(*const c_char) -> usize;
+        let strlen = unsafe { std::mem::transmute::<*mut c_void, Strlen>(strlen) };
+        assert_eq!(unsafe { strlen(b"gmio\0".as_ptr().cast()) }, 4);
+        assert!(libc.symbol("not_a_real_function").unwrap_err().contains("not_a_real_function"));
+        match Library::open("libnot-a-real-library.so.0") {
+            Ok(_) => panic!("opened a library that doesn't exist"),
+            Err(e) => assert!(e.contains("libnot-a-real-library.so.0"), "{}", e),
+        }
+    }
+
+    library! {
+        pub struct Libc {
+            "libc.so.6" {
+                fn abs: unsafe extern "C" fn(i32) -> i32,
+                static environ: *const *const c_char,
+            }
+        }
+    }
+
+    library! {
+        pub struct Missing {
+            "libc.so.6" {
+                fn abs: unsafe extern "C" fn(i32) -> i32,
+                fn not_a_real_function: unsafe extern "C" fn(),
+            }
+        }
+    }
+
+    #[test]
+    fn libraries() {
+        unsafe {
+            let libc = Libc::open().unwrap();
+            assert_eq!((libc.abs)(-3), 3);
+            assert!(!libc.environ.is_null());
+            assert!(Missing::open().is_err());
+        }
+    }
+}
@@ -1,3 +1,4 @@
 pub mod atlas;
+mod dl;
 pub mod render;
 pub mod window;
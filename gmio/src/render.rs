@@ -1,6 +1,7 @@
 //! Game rendering functionality
 
 mod opengl;
+mod software;
 
 use crate::{atlas::AtlasBuilder, window::Window};
 use serde::{Deserialize, Serialize};
@@ -8,7 +9,7 @@ use shared::types::Colour;
 use std::any::Any;
 
 // Re-export for more logical module pathing
-pub use crate::atlas::AtlasRef;
+pub use crate::{atlas::AtlasRef, window::Backend};
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Scaling {
@@ -167,6 +168,55 @@ impl PrimitiveBuilder {
     }
 }
 
+impl From<AtlasRef> for [f32; 4] {
+    fn from(ar: AtlasRef) -> Self {
+        [ar.x as f32, ar.y as f32, ar.w as f32, ar.h as f32]
+    }
+}
+
+/// A builder to be used for building basic shapes.
+struct ShapeBuilder {
+    primitive: PrimitiveBuilder,
+    outline: bool,
+    depth: f32,
+    alpha: f64,
+}
+
+impl ShapeBuilder {
+    fn new(outline: bool, atlas_ref: AtlasRef, alpha: f64, depth: f32) -> Self {
+        Self {
+            primitive: PrimitiveBuilder::new(
+                atlas_ref,
+                if outline { PrimitiveType::LineStrip } else { PrimitiveType::TriFan },
+            ),
+            outline,
+            depth,
+            alpha,
+        }
+    }
+
+    /// Shortcut for basic shapes.
+    fn push_point(&mut self, x: f64, y: f64, colour: i32) -> &mut Self {
+        self.primitive.push_vertex([x as f32, y as f32, self.depth], [0.0, 0.0], split_colour(colour, self.alpha), [
+            0.0, 0.0, 0.0,
+        ]);
+        self
+    }
+
+    /// Should only be called once. This is only used for basic shapes, so it's fine for it to be *possible* to
+    /// call it multiple times, as that makes things easier elsewhere.
+    fn build(&mut self) -> &PrimitiveBuilder {
+        if self.outline {
+            let vertices = self.primitive.get_vertices();
+            if vertices.len() > 2 {
+                let vertex = vertices[0];
+                self.primitive.push_vertex_raw(vertex);
+            }
+        }
+        &self.primitive
+    }
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct VertexBuffer {
     points: Vec<Vertex>,
@@ -174,8 +224,23 @@ pub struct VertexBuffer {
     tris: Vec<Vertex>,
 }
 
+impl VertexBuffer {
+    pub fn swap_colour(&mut self, old: (i32, f64), new: (i32, f64)) {
+        let old = split_colour(old.0, old.1);
+        let new = split_colour(new.0, new.1);
+        for vert in self.points.iter_mut().chain(&mut self.lines).chain(&mut self.tris) {
+            if vert.blend == old {
+                vert.blend = new;
+            }
+        }
+    }
+}
+
 pub struct Renderer(Box<dyn RendererTrait>);
 
+/// A renderer's state at some point, from Renderer::snapshot.
+pub struct Snapshot(Box<dyn Any>);
+
 pub trait RendererTrait {
     fn as_any(&self) -> &dyn Any;
     fn max_texture_size(&self) -> u32;
@@ -328,6 +393,8 @@ pub trait RendererTrait {
         window_h: u32,
         scaling: Scaling,
     );
+    fn snapshot(&mut self) -> Box<dyn Any>;
+    fn restore(&mut self, snapshot: Box<dyn Any>);
 
     fn dump_dynamic_textures(&self) -> Vec<Option<SavedTexture>>;
     fn upload_dynamic_textures(&mut self, textures: &[Option<SavedTexture>]);
@@ -523,10 +590,17 @@ impl Default for RendererOptions {
 }
 
 impl Renderer {
-    pub fn new(backend: (), options: &RendererOptions, window: &Window, clear_colour: Colour) -> Result<Self, String> {
-        Ok(Self(Box::new(match backend {
-            () => opengl::RendererImpl::new(options, window, clear_colour)?,
-        })))
+    pub fn new(
+        backend: Backend,
+        options: &RendererOptions,
+        window: &Window,
+        clear_colour: Colour,
+    ) -> Result<Self, String> {
+        Ok(Self(match backend {
+            Backend::OpenGL => Box::new(opengl::RendererImpl::new(options, window, clear_colour)?),
+            Backend::Software => Box::new(software::RendererImpl::new(options, clear_colour)),
+            Backend::Vulkan => return Err("The Vulkan backend is not implemented".into()),
+        }))
     }
 
     pub fn max_texture_size(&self) -> u32 {
@@ -875,6 +949,17 @@ impl Renderer {
         self.0.draw_raw_frame(rgba, zbuf, fb_w, fb_h, window_w, window_h, scaling)
     }
 
+    /// Saves the framebuffer, the draw target and every render setting, so something can be drawn over
+    /// the current frame and shown, then taken away again with restore().
+    pub fn snapshot(&mut self) -> Snapshot {
+        Snapshot(self.0.snapshot())
+    }
+
+    /// Puts everything back how it was when the snapshot was taken.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.0.restore(snapshot.0)
+    }
+
     pub fn dump_dynamic_textures(&self) -> Vec<Option<SavedTexture>> {
         self.0.dump_dynamic_textures()
     }
@@ -1093,3 +1178,46 @@ fn mat4mult(m1: [f32; 16], m2: [f32; 16]) -> [f32; 16] {
         (m1[12] * m2[3]) + (m1[13] * m2[7]) + (m1[14] * m2[11]) + (m1[15] * m2[15]),
     ]
 }
+
+fn make_view_matrix(x: f64, y: f64, z: f64, w: f64, h: f64, angle: f64) -> [f32; 16] {
+    // Note: sin is negated because it's the same as negating the angle, which is how GM8 does view angles
+    let angle = angle.to_radians();
+    let sin_angle = -angle.sin() as f32;
+    let cos_angle = angle.cos() as f32;
+
+    #[rustfmt::skip]
+    let view_matrix: [f32; 16] = {
+        // source rectangle's center coordinates aka -(x + w/2) and -(y + h/2)
+        let scx = -((x as f32) + (w as f32 / 2.0));
+        let scy = -((y as f32) + (h as f32 / 2.0));
+        let scz = -z as f32;
+        mat4mult(
+            // Place camera at (scx, scy, scz)
+            [
+                1.0, 0.0, 0.0, 0.0,
+                0.0, 1.0, 0.0, 0.0,
+                0.0, 0.0, 1.0, 0.0,
+                scx, scy, scz, 1.0,
+            ],
+            // Rotate to view_angle
+            [
+                cos_angle,  sin_angle, 0.0, 0.0,
+                -sin_angle, cos_angle, 0.0, 0.0,
+                0.0,        0.0,       1.0, 0.0,
+                0.0,        0.0,       0.0, 1.0,
+            ]
+        )
+    };
+
+    view_matrix
+}
+
+#[allow(clippy::manual_clamp)] // NaN alpha has to become 0
+fn split_colour(rgb: i32, alpha: f64) -> [f32; 4] {
+    [
+        ((rgb & 0xFF) as f32) / 255.0,
+        (((rgb >> 8) & 0xFF) as f32) / 255.0,
+        (((rgb >> 16) & 0xFF) as f32) / 255.0,
+        alpha.max(0.0).min(1.0) as f32,
+    ]
+}
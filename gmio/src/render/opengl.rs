@@ -1,10 +1,11 @@
+mod egl;
 mod wgl;
 
 use crate::{
     atlas::{AtlasBuilder, AtlasRef},
     render::{
-        mat4mult, BlendType, Fog, Light, PrimitiveBuilder, PrimitiveShape, PrimitiveType, RendererOptions,
-        RendererTrait, SavedTexture, Scaling, Vertex, VertexBuffer,
+        make_view_matrix, mat4mult, split_colour, BlendType, Fog, Light, PrimitiveBuilder, PrimitiveShape,
+        PrimitiveType, RendererOptions, RendererTrait, SavedTexture, Scaling, ShapeBuilder, Vertex, VertexBuffer,
     },
     window::Window,
 };
@@ -26,8 +27,8 @@ cfg_if! {
         use crate::window::win32 as w_imp;
         use wgl as imp;
     } else {
-        // TODO: This won't work when Wayland but that's okay just make a function for it.
-        use crate::window::xorg as w_imp;
+        // EGL takes both Wayland and X11 windows, so it works out which one it's been given itself
+        use egl as imp;
     }
 }
 
@@ -165,6 +166,21 @@ impl RenderState {
     }
 }
 
+// surfaces aren't saved, since nothing gets drawn to them in between
+struct Snapshot {
+    pixels: Box<[u8]>,
+    zbuf: Box<[f32]>,
+    width: i32,
+    height: i32,
+    framebuffer: GLuint, // whichever one's being drawn to, which might be a surface
+    viewport: [GLint; 4],
+    scissor: [GLint; 4],
+    state: RenderState,
+    using_3d: bool,
+    perspective: bool,
+    depth: f32,
+}
+
 pub struct RendererImpl {
     imp: imp::PlatformImpl,
     gl: gl::Gl,
@@ -217,67 +233,6 @@ unsafe fn shader_info_log(gl: &gl::Gl, name: &str, id: GLuint) -> String {
     )
 }
 
-fn make_view_matrix(x: f64, y: f64, z: f64, w: f64, h: f64, angle: f64) -> [f32; 16] {
-    // Note: sin is negated because it's the same as negating the angle, which is how GM8 does view angles
-    let angle = angle.to_radians();
-    let sin_angle = -angle.sin() as f32;
-    let cos_angle = angle.cos() as f32;
-
-    #[rustfmt::skip]
-    let view_matrix: [f32; 16] = {
-        // source rectangle's center coordinates aka -(x + w/2) and -(y + h/2)
-        let scx = -((x as f32) + (w as f32 / 2.0));
-        let scy = -((y as f32) + (h as f32 / 2.0));
-        let scz = -z as f32;
-        mat4mult(
-            // Place camera at (scx, scy, scz)
-            [
-                1.0, 0.0, 0.0, 0.0,
-                0.0, 1.0, 0.0, 0.0,
-                0.0, 0.0, 1.0, 0.0,
-                scx, scy, scz, 1.0,
-            ],
-            // Rotate to view_angle
-            [
-                cos_angle,  sin_angle, 0.0, 0.0,
-                -sin_angle, cos_angle, 0.0, 0.0,
-                0.0,        0.0,       1.0, 0.0,
-                0.0,        0.0,       0.0, 1.0,
-            ]
-        )
-    };
-
-    view_matrix
-}
-
-fn split_colour(rgb: i32, alpha: f64) -> [f32; 4] {
-    [
-        ((rgb & 0xFF) as f32) / 255.0,
-        (((rgb >> 8) & 0xFF) as f32) / 255.0,
-        (((rgb >> 16) & 0xFF) as f32) / 255.0,
-        alpha.max(0.0).min(1.0) as f32,
-    ]
-}
-
-// TODO: probably put this in render.rs instead
-impl VertexBuffer {
-    pub fn swap_colour(&mut self, old: (i32, f64), new: (i32, f64)) {
-        let old = split_colour(old.0, old.1);
-        let new = split_colour(new.0, new.1);
-        for vert in self.points.iter_mut().chain(&mut self.lines).chain(&mut self.tris) {
-            if vert.blend == old {
-                vert.blend = new;
-            }
-        }
-    }
-}
-
-impl From<AtlasRef> for [f32; 4] {
-    fn from(ar: AtlasRef) -> Self {
-        [ar.x as f32, ar.y as f32, ar.w as f32, ar.h as f32]
-    }
-}
-
 #[derive(Debug)]
 struct LightUniform {
     enabled: GLint,
@@ -334,58 +289,18 @@ impl From<PrimitiveShape> for GLenum {
     }
 }
 
-/// A builder to be used for building basic shapes.
-struct ShapeBuilder {
-    primitive: PrimitiveBuilder,
-    outline: bool,
-    depth: f32,
-    alpha: f64,
-}
-
-impl ShapeBuilder {
-    fn new(outline: bool, atlas_ref: AtlasRef, alpha: f64, depth: f32) -> Self {
-        Self {
-            primitive: PrimitiveBuilder::new(
-                atlas_ref,
-                if outline { PrimitiveType::LineStrip } else { PrimitiveType::TriFan },
-            ),
-            outline,
-            depth,
-            alpha,
-        }
-    }
-
-    /// Shortcut for basic shapes.
-    fn push_point(&mut self, x: f64, y: f64, colour: i32) -> &mut Self {
-        self.primitive.push_vertex([x as f32, y as f32, self.depth], [0.0, 0.0], split_colour(colour, self.alpha), [
-            0.0, 0.0, 0.0,
-        ]);
-        self
-    }
-
-    /// Should only be called once. This is only used for basic shapes, so it's fine for it to be *possible* to
-    /// call it multiple times, as that makes things easier elsewhere.
-    fn build(&mut self) -> &PrimitiveBuilder {
-        if self.outline {
-            let vertices = self.primitive.get_vertices();
-            if vertices.len() > 2 {
-                let vertex = vertices[0];
-                self.primitive.push_vertex_raw(vertex);
-            }
-        }
-        &self.primitive
-    }
-}
-
 impl RendererImpl {
     pub fn new(options: &RendererOptions, window: &Window, clear_colour: Colour) -> Result<Self, String> {
-        let window_impl: &w_imp::WindowImpl = match window.as_any().downcast_ref() {
+        #[cfg(target_os = "windows")]
+        let window: &w_imp::WindowImpl = match window.as_any().downcast_ref() {
             Some(x) => x,
             None => return Err("Wrong backend provided to OpenGLRenderer::new()".into()),
         };
+        #[cfg(not(target_os = "windows"))]
+        let window = window.as_any();
 
         unsafe {
-            let imp = imp::PlatformImpl::new(window_impl)?;
+            let imp = imp::PlatformImpl::new(window)?;
 
             // gl function pointers
             let gl = gl::Gl::load_with(imp::PlatformImpl::get_function_loader()?);
@@ -1407,6 +1322,75 @@ impl RendererTrait for RendererImpl {
         self.set_view(0, 0, fb_w, fb_h, 0.0, 0, 0, fb_w, fb_h);
     }
 
+    fn snapshot(&mut self) -> Box<dyn Any> {
+        self.flush_queue();
+        let (mut width, mut height, mut framebuffer) = (0, 0, 0);
+        let (mut viewport, mut scissor) = ([0; 4], [0; 4]);
+        unsafe {
+            self.gl.BindTexture(gl::TEXTURE_2D, self.framebuffer_texture);
+            self.gl.GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_WIDTH, &mut width);
+            self.gl.GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_HEIGHT, &mut height);
+            self.gl.GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut framebuffer);
+            self.gl.GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+            self.gl.GetIntegerv(gl::SCISSOR_BOX, scissor.as_mut_ptr());
+            assert_eq!(self.gl.GetError(), 0);
+        }
+        Box::new(Snapshot {
+            pixels: self.get_pixels(0, 0, width, height),
+            zbuf: self.dump_zbuffer(),
+            width,
+            height,
+            framebuffer: framebuffer as _,
+            viewport,
+            scissor,
+            state: self.next_render_state.clone(),
+            using_3d: self.using_3d,
+            perspective: self.perspective,
+            depth: self.depth,
+        })
+    }
+
+    fn restore(&mut self, snapshot: Box<dyn Any>) {
+        let snapshot = snapshot.downcast::<Snapshot>().expect("not an OpenGL renderer snapshot");
+        self.flush_queue();
+        unsafe {
+            self.gl.BindTexture(gl::TEXTURE_2D, self.framebuffer_texture);
+            self.gl.TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                snapshot.width,
+                snapshot.height,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                snapshot.pixels.as_ptr().cast(),
+            );
+            self.gl.BindTexture(gl::TEXTURE_2D, self.framebuffer_zbuf);
+            self.gl.TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                snapshot.width,
+                snapshot.height,
+                gl::DEPTH_COMPONENT,
+                gl::FLOAT,
+                snapshot.zbuf.as_ptr().cast(),
+            );
+            self.gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, snapshot.framebuffer);
+            let ([x, y, w, h], [sx, sy, sw, sh]) = (snapshot.viewport, snapshot.scissor);
+            self.gl.Viewport(x, y, w, h);
+            self.gl.Scissor(sx, sy, sw, sh);
+            assert_eq!(self.gl.GetError(), 0);
+        }
+        self.next_render_state = snapshot.state;
+        self.render_state_updated = true;
+        self.using_3d = snapshot.using_3d;
+        self.perspective = snapshot.perspective;
+        self.depth = snapshot.depth;
+    }
+
     fn dump_dynamic_textures(&self) -> Vec<Option<SavedTexture>> {
         unsafe {
             // store previous
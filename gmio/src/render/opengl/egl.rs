@@ -0,0 +1,303 @@
+//! EGL-based OpenGL loading, for both Wayland and X11 windows.
+
+#![cfg(target_os = "linux")]
+
+use crate::window::{wayland, xorg};
+use std::{
+    any::Any,
+    cell::Cell,
+    ffi::CString,
+    ops::Drop,
+    os::raw::{c_char, c_void},
+    ptr,
+    rc::Rc,
+    sync::OnceLock,
+};
+use x11::xlib;
+
+type EGLDisplay = *mut c_void;
+type EGLConfig = *mut c_void;
+type EGLContext = *mut c_void;
+type EGLSurface = *mut c_void;
+type EGLint = i32;
+type EGLBoolean = u32;
+
+const EGL_FALSE: EGLBoolean = 0;
+const EGL_NONE: EGLint = 0x3038;
+const EGL_ALPHA_SIZE: EGLint = 0x3021;
+const EGL_BLUE_SIZE: EGLint = 0x3022;
+const EGL_GREEN_SIZE: EGLint = 0x3023;
+const EGL_RED_SIZE: EGLint = 0x3024;
+const EGL_DEPTH_SIZE: EGLint = 0x3025;
+const EGL_STENCIL_SIZE: EGLint = 0x3026;
+const EGL_NATIVE_VISUAL_ID: EGLint = 0x302E;
+const EGL_SURFACE_TYPE: EGLint = 0x3033;
+const EGL_RENDERABLE_TYPE: EGLint = 0x3040;
+const EGL_WINDOW_BIT: EGLint = 0x0004;
+const EGL_OPENGL_BIT: EGLint = 0x0008;
+const EGL_OPENGL_API: u32 = 0x30A2;
+const EGL_CONTEXT_MAJOR_VERSION: EGLint = 0x3098;
+const EGL_CONTEXT_MINOR_VERSION: EGLint = 0x30FB;
+const EGL_CONTEXT_OPENGL_PROFILE_MASK: EGLint = 0x30FD;
+const EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT: EGLint = 0x0001;
+
+crate::dl::library! {
+    #[allow(non_snake_case)]
+    pub struct Egl {
+        "libEGL.so.1" {
+            fn eglGetDisplay: unsafe extern "C" fn(*mut c_void) -> EGLDisplay,
+            fn eglInitialize: unsafe extern "C" fn(EGLDisplay, *mut EGLint, *mut EGLint) -> EGLBoolean,
+            fn eglTerminate: unsafe extern "C" fn(EGLDisplay) -> EGLBoolean,
+            fn eglBindAPI: unsafe extern "C" fn(u32) -> EGLBoolean,
+            fn eglChooseConfig:
+                unsafe extern "C" fn(EGLDisplay, *const EGLint, *mut EGLConfig, EGLint, *mut EGLint) -> EGLBoolean,
+            fn eglGetConfigAttrib:
+                unsafe extern "C" fn(EGLDisplay, EGLConfig, EGLint, *mut EGLint) -> EGLBoolean,
+            fn eglCreateContext:
+                unsafe extern "C" fn(EGLDisplay, EGLConfig, EGLContext, *const EGLint) -> EGLContext,
+            fn eglDestroyContext: unsafe extern "C" fn(EGLDisplay, EGLContext) -> EGLBoolean,
+            fn eglCreateWindowSurface:
+                unsafe extern "C" fn(EGLDisplay, EGLConfig, *mut c_void, *const EGLint) -> EGLSurface,
+            fn eglDestroySurface: unsafe extern "C" fn(EGLDisplay, EGLSurface) -> EGLBoolean,
+            fn eglMakeCurrent: unsafe extern "C" fn(EGLDisplay, EGLSurface, EGLSurface, EGLContext) -> EGLBoolean,
+            fn eglGetCurrentContext: unsafe extern "C" fn() -> EGLContext,
+            fn eglSwapInterval: unsafe extern "C" fn(EGLDisplay, EGLint) -> EGLBoolean,
+            fn eglSwapBuffers: unsafe extern "C" fn(EGLDisplay, EGLSurface) -> EGLBoolean,
+            fn eglGetProcAddress: unsafe extern "C" fn(*const c_char) -> *const c_void,
+            fn eglGetError: unsafe extern "C" fn() -> EGLint,
+        }
+    }
+}
+
+static EGL: OnceLock<Result<Egl, String>> = OnceLock::new();
+
+/// Loads libEGL if that hasn't been tried yet.
+fn load() -> Result<&'static Egl, String> {
+    EGL.get_or_init(|| unsafe { Egl::open() }).as_ref().map_err(Clone::clone)
+}
+
+/// The loaded libEGL. Only usable after a successful `load`, which `PlatformImpl::new` does first.
+fn egl() -> &'static Egl {
+    match EGL.get() {
+        Some(Ok(egl)) => egl,
+        _ => panic!("libEGL used before being loaded"),
+    }
+}
+
+/// Config for the default framebuffer, matching what wgl asks for.
+#[rustfmt::skip]
+static CONFIG_ATTRIBS: &[EGLint] = &[
+    EGL_SURFACE_TYPE,    EGL_WINDOW_BIT,
+    EGL_RENDERABLE_TYPE, EGL_OPENGL_BIT,
+    EGL_RED_SIZE,        8,
+    EGL_GREEN_SIZE,      8,
+    EGL_BLUE_SIZE,       8,
+    EGL_ALPHA_SIZE,      8,
+    EGL_DEPTH_SIZE,      24,
+    EGL_STENCIL_SIZE,    8,
+    EGL_NONE,
+];
+
+/// Flags for eglCreateContext
+#[rustfmt::skip]
+static CONTEXT_ATTRIBS: &[EGLint] = &[
+    EGL_CONTEXT_MAJOR_VERSION,       3,
+    EGL_CONTEXT_MINOR_VERSION,       3,
+    EGL_CONTEXT_OPENGL_PROFILE_MASK, EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT,
+    EGL_NONE,
+];
+
+macro_rules! egl_call {
+    ($ex: expr) => {{
+        match $ex {
+            x if x as usize != 0 => Ok(x),
+            _ => {
+                let error = (egl().eglGetError)();
+                Err(format!("[{} @ line {}: {}] EGL error {:#X}", file!(), line!(), stringify!($ex), error))
+            },
+        }
+    }};
+}
+
+pub struct PlatformImpl {
+    display: EGLDisplay,
+    context: EGLContext,
+    surface: EGLSurface,
+    swap_interval: Cell<u32>,
+    swap_failed: Cell<bool>, // so a failure only gets logged once, rather than every frame
+    mapped: Rc<Cell<bool>>,
+}
+
+impl PlatformImpl {
+    pub unsafe fn new(window: &dyn Any) -> Result<Self, String> {
+        let (native_display, native_window, native_visual, mapped) =
+            if let Some(window) = window.downcast_ref::<wayland::WindowImpl>() {
+                (window.get_display(), window.get_egl_window(), None, window.mapped_flag())
+            } else if let Some(window) = window.downcast_ref::<xorg::WindowImpl>() {
+                // X11 never holds back a swap, so there's no need to track whether it's mapped
+                let visual = xlib::XVisualIDFromVisual(xlib::XDefaultVisual(window.display, window.screen_id));
+                let native_window = window.window_id as usize as *mut c_void;
+                (window.display.cast(), native_window, Some(visual as EGLint), Rc::new(Cell::new(true)))
+            } else {
+                return Err("Wrong backend provided to OpenGLRenderer::new()".into())
+            };
+        let egl = load()?;
+
+        let display = egl_call!((egl.eglGetDisplay)(native_display))?;
+        egl_call!((egl.eglInitialize)(display, ptr::null_mut(), ptr::null_mut()))?;
+        // from here on, dropping this cleans up whatever got made if something fails
+        let mut platform = Self {
+            display,
+            context: ptr::null_mut(),
+            surface: ptr::null_mut(),
+            swap_interval: Cell::new(1),
+            swap_failed: Cell::new(false),
+            mapped,
+        };
+        egl_call!((egl.eglBindAPI)(EGL_OPENGL_API))?;
+
+        let mut configs: [EGLConfig; 64] = [ptr::null_mut(); 64];
+        let mut config_count: EGLint = 0;
+        egl_call!((egl.eglChooseConfig)(
+            display,
+            CONFIG_ATTRIBS.as_ptr(),
+            configs.as_mut_ptr(),
+            configs.len() as EGLint,
+            &mut config_count,
+        ))?;
+        // an X11 window already has a visual, so the config has to be one that renders to it
+        let matches_window = |config: &EGLConfig| match native_visual {
+            Some(visual) => {
+                let mut id: EGLint = 0;
+                (egl.eglGetConfigAttrib)(display, *config, EGL_NATIVE_VISUAL_ID, &mut id) != EGL_FALSE && id == visual
+            },
+            None => true,
+        };
+        let config = match configs[..config_count as usize].iter().find(|c| matches_window(c)) {
+            Some(config) => *config,
+            None => return Err("No suitable EGL config found".into()),
+        };
+
+        platform.context =
+            egl_call!((egl.eglCreateContext)(display, config, ptr::null_mut(), CONTEXT_ATTRIBS.as_ptr()))?;
+        platform.surface = egl_call!((egl.eglCreateWindowSurface)(display, config, native_window, ptr::null()))?;
+        egl_call!((egl.eglMakeCurrent)(display, platform.surface, platform.surface, platform.context))?;
+        Ok(platform)
+    }
+
+    pub unsafe fn get_function_loader() -> Result<Box<dyn FnMut(&'static str) -> *const std::os::raw::c_void>, String> {
+        // Mesa and co. give out core functions here too, so there's no need to dlopen libGL
+        Ok(Box::new(|s: &'static str| match CString::new(s) {
+            Ok(name) => (egl().eglGetProcAddress)(name.as_ptr()),
+            Err(_) => ptr::null(),
+        }))
+    }
+
+    pub unsafe fn clean_function_loader() {
+        // nothing to clean
+    }
+
+    pub unsafe fn swap_buffers(&self) {
+        // an unmapped surface never gets frame callbacks, so with vsync on this would block forever
+        if self.mapped.get() {
+            match egl_call!((egl().eglSwapBuffers)(self.display, self.surface)) {
+                Ok(_) => self.swap_failed.set(false),
+                Err(e) => {
+                    if !self.swap_failed.replace(true) {
+                        eprintln!("Warning: couldn't present the frame: {}", e);
+                    }
+                },
+            }
+        }
+    }
+
+    pub unsafe fn set_swap_interval(&self, n: u32) -> bool {
+        if (egl().eglSwapInterval)(self.display, n as EGLint) != EGL_FALSE {
+            self.swap_interval.set(n);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub unsafe fn get_swap_interval(&self) -> u32 {
+        self.swap_interval.get()
+    }
+
+    pub unsafe fn wait_vsync(&self) {
+        // TODO: there's no way to just wait for vblank on Wayland, it'd have to be a frame callback
+    }
+}
+
+impl Drop for PlatformImpl {
+    fn drop(&mut self) {
+        let egl = egl();
+        unsafe {
+            // unset if we're the current context
+            if (egl.eglGetCurrentContext)() == self.context {
+                (egl.eglMakeCurrent)(self.display, ptr::null_mut(), ptr::null_mut(), ptr::null_mut());
+            }
+
+            if !self.surface.is_null() {
+                (egl.eglDestroySurface)(self.display, self.surface);
+            }
+            if !self.context.is_null() {
+                (egl.eglDestroyContext)(self.display, self.context);
+            }
+            (egl.eglTerminate)(self.display);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::{WindowBuilder, WindowTrait};
+
+    const EGL_SUCCESS: EGLint = 0x3000;
+
+    #[test]
+    fn library() {
+        // systems without EGL have to get a clean error rather than failing to start
+        match load() {
+            Ok(egl) => assert_eq!(unsafe { (egl.eglGetError)() }, EGL_SUCCESS),
+            Err(e) => assert!(e.contains("libEGL.so.1"), "{}", e),
+        }
+    }
+
+    fn check_context(window: &mut dyn WindowTrait) {
+        window.set_visible(true);
+        unsafe {
+            // twice, to check the display gets torn down properly in between
+            for _ in 0..2 {
+                let platform = PlatformImpl::new(window.as_any()).unwrap();
+                assert!(!PlatformImpl::get_function_loader().unwrap()("glClear").is_null());
+                platform.swap_buffers();
+                assert!(!platform.swap_failed.get());
+                drop(platform);
+                assert!((egl().eglGetCurrentContext)().is_null());
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a Wayland compositor, which CI runs these with"]
+    fn wayland_context() {
+        check_context(&mut wayland::WindowImpl::new(&WindowBuilder::new().with_size(64, 64)).unwrap());
+    }
+
+    #[test]
+    #[ignore = "needs an X server, which CI runs these with"]
+    fn x11_context() {
+        check_context(&mut xorg::WindowImpl::new(&WindowBuilder::new().with_size(64, 64)).unwrap());
+    }
+
+    #[test]
+    fn wrong_window() {
+        let window = crate::window::headless::WindowImpl::new(&WindowBuilder::new(), (640, 480));
+        match unsafe { PlatformImpl::new(window.as_any()) } {
+            Ok(_) => panic!("a headless window shouldn't get a context"),
+            Err(e) => assert!(e.starts_with("Wrong backend"), "{}", e),
+        }
+    }
+}
@@ -0,0 +1,1705 @@
+//! Pure-software renderer.
+//!
+//! This follows the OpenGL renderer (shaders included) as closely as possible, but does all of its work on the CPU.
+//! It doesn't need a GPU or even a window, and the frames it draws only depend on what it was told to draw.
+
+use crate::{
+    atlas::{AtlasBuilder, AtlasRef},
+    render::{
+        make_view_matrix, mat4mult, split_colour, BlendType, Fog, Light, PrimitiveBuilder, PrimitiveShape,
+        PrimitiveType, RendererOptions, RendererTrait, SavedTexture, Scaling, ShapeBuilder, Vertex, VertexBuffer,
+    },
+};
+use rect_packer::DensePacker;
+use shared::types::Colour;
+use std::{any::Any, cell::Cell, collections::HashMap, f64::consts::PI};
+
+/// There's no hardware limit, but textures bigger than this would be silly anyway.
+const MAX_TEXTURE_SIZE: u32 = 8192;
+
+/// Window coordinates get snapped to this many steps per pixel, like GPUs do.
+const SUBPIXEL_STEPS: f64 = 256.0;
+
+#[derive(Clone)]
+struct RenderState {
+    model_matrix: [f32; 16],
+    view_matrix: [f32; 16],
+    proj_matrix: [f32; 16],
+    viewproj_matrix: [f32; 16],
+    lights: [(bool, Light); 8],
+    ambient_colour: i32,
+    lighting: bool,
+    gouraud: bool,
+    texture_repeat: bool,
+    interpolate_pixels: bool,
+    depth_test: bool,
+    fog: Option<Fog>,
+    alpha_blending: bool,
+    blend_mode: (BlendType, BlendType),
+    write_depth: bool,
+    culling: bool,
+}
+
+impl Default for RenderState {
+    fn default() -> Self {
+        #[rustfmt::skip]
+        let identity_matrix: [f32; 16] = [
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ];
+        Self {
+            model_matrix: identity_matrix,
+            view_matrix: identity_matrix,
+            proj_matrix: identity_matrix,
+            viewproj_matrix: identity_matrix,
+            lights: [(false, Light::Directional { direction: [0.0; 3], colour: 0 }); 8],
+            ambient_colour: 0,
+            lighting: false,
+            gouraud: true,
+            texture_repeat: false,
+            interpolate_pixels: false,
+            depth_test: false,
+            fog: None,
+            alpha_blending: true,
+            blend_mode: (BlendType::SrcAlpha, BlendType::InvSrcAlpha),
+            // GL's depth mask starts out enabled, and so does D3D's, so do the same here
+            write_depth: true,
+            culling: false,
+        }
+    }
+}
+
+impl RenderState {
+    /// Same thing as glsl/vertex.glsl.
+    fn shade_vertex(&self, v: &Vertex, normalize_normals: bool) -> ClipVertex {
+        let world_pos = transform(&self.model_matrix, [v.pos[0], v.pos[1], v.pos[2], 1.0]);
+        let mut blend = v.blend;
+        let mut blend_flat = [1.0; 4];
+        if self.lighting {
+            let normal = transform(&self.model_matrix, [v.normal[0], v.normal[1], v.normal[2], 0.0]);
+            let mut normal = [-normal[0], -normal[1], -normal[2]];
+            if normalize_normals {
+                normal = normalize(normal);
+            }
+            let mut light_col = [0.0f32; 3];
+            for (_, light) in self.lights.iter().filter(|(enabled, _)| *enabled) {
+                let (this_light_col, ray) = match *light {
+                    Light::Directional { direction, colour } => (split_colour(colour, 1.0), direction),
+                    Light::Point { position, range, colour } => {
+                        let ray = [world_pos[0] - position[0], world_pos[1] - position[1], world_pos[2] - position[2]];
+                        let dist = dot(ray, ray).sqrt();
+                        let colour = if dist < range {
+                            split_colour(colour, 1.0).map(|c| c / (1.0 + (4.0 / range) * dist))
+                        } else {
+                            [0.0; 4]
+                        };
+                        (colour, ray)
+                    },
+                };
+                let intensity = clamp01(dot(normalize(ray), normal));
+                for (col, this_col) in light_col.iter_mut().zip(&this_light_col) {
+                    *col += this_col * intensity;
+                }
+            }
+            let ambient = split_colour(self.ambient_colour, 1.0);
+            let lit = if self.gouraud { &mut blend } else { &mut blend_flat };
+            for ((col, light), ambient) in lit.iter_mut().zip(&light_col).zip(&ambient) {
+                *col = *col * light + ambient;
+            }
+        }
+        ClipVertex {
+            pos: transform(&self.viewproj_matrix, world_pos),
+            tex_coord: v.tex_coord,
+            blend,
+            blend_flat,
+            atlas_xywh: v.atlas_xywh,
+        }
+    }
+}
+
+/// A vertex as it comes out of the vertex shader, in clip space.
+#[derive(Clone, Copy)]
+struct ClipVertex {
+    pos: [f32; 4],
+    tex_coord: [f32; 2],
+    blend: [f32; 4],
+    blend_flat: [f32; 4],
+    atlas_xywh: [f32; 4],
+}
+
+impl ClipVertex {
+    /// Interpolates everything but the flat attributes, which are only ever taken from the provoking vertex.
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            pos: lerp(self.pos, other.pos, t),
+            tex_coord: lerp(self.tex_coord, other.tex_coord, t),
+            blend: lerp(self.blend, other.blend, t),
+            ..*self
+        }
+    }
+}
+
+/// A vertex after the perspective divide and viewport transform.
+#[derive(Clone, Copy)]
+struct WindowVertex {
+    x: f64,
+    y: f64,
+    depth: f64,
+    inv_w: f64,
+    fog_z: f32,
+    tex_coord: [f32; 2],
+    blend: [f32; 4],
+}
+
+/// The interpolated inputs to the fragment stage.
+struct Fragment {
+    depth: f64,
+    fog_z: f32,
+    tex_coord: [f32; 2],
+    blend: [f32; 4],
+}
+
+impl Fragment {
+    /// Perspective-correct interpolation between some vertices, given their screen-space weights.
+    fn interpolate(verts: &[&WindowVertex], weights: &[f64]) -> Self {
+        let depth = verts.iter().zip(weights).map(|(v, w)| v.depth * w).sum();
+        let corrected = weights.iter().zip(verts).map(|(w, v)| w * v.inv_w).collect::<Vec<_>>();
+        let total: f64 = corrected.iter().sum();
+        let mut frag = Self { depth, fog_z: 0.0, tex_coord: [0.0; 2], blend: [0.0; 4] };
+        for (v, w) in verts.iter().zip(corrected) {
+            let w = (w / total) as f32;
+            frag.fog_z += v.fog_z * w;
+            frag.tex_coord.iter_mut().zip(&v.tex_coord).for_each(|(a, b)| *a += b * w);
+            frag.blend.iter_mut().zip(&v.blend).for_each(|(a, b)| *a += b * w);
+        }
+        frag
+    }
+}
+
+/// Turns primitives into pixels on a render target.
+struct Rasteriser<'a> {
+    state: &'a RenderState,
+    texture: &'a SavedTexture,
+    target: &'a mut SavedTexture,
+    depth_attached: bool,
+    viewport: [f64; 4],
+    scissor: [i32; 4], // left, top, right, bottom, exclusive
+    fog: Option<([f32; 4], f32, f32)>,
+}
+
+impl Rasteriser<'_> {
+    fn to_window(&self, v: &ClipVertex) -> WindowVertex {
+        let inv_w = 1.0 / f64::from(v.pos[3]);
+        let [x, y, w, h] = self.viewport;
+        let snap = |n: f64| (n * SUBPIXEL_STEPS).round() / SUBPIXEL_STEPS;
+        // the GL renderer shifts everything by half a pixel because GL's screen space is offset vs DX's,
+        // and our y axis already points the same way as GL's window space so there's no need to flip it
+        WindowVertex {
+            x: snap(x + (1.0 + f64::from(v.pos[0]) * inv_w) * w / 2.0 + 0.5),
+            y: snap(y + (1.0 - f64::from(v.pos[1]) * inv_w) * h / 2.0 + 0.5),
+            depth: (f64::from(v.pos[2]) * inv_w + 1.0) / 2.0,
+            inv_w,
+            fog_z: v.pos[2],
+            tex_coord: v.tex_coord,
+            blend: v.blend,
+        }
+    }
+
+    fn in_scissor(&self, x: i32, y: i32) -> bool {
+        x >= self.scissor[0] && y >= self.scissor[1] && x < self.scissor[2] && y < self.scissor[3]
+    }
+
+    fn point(&mut self, v: &ClipVertex) {
+        if v.pos[2] < -v.pos[3] || v.pos[2] > v.pos[3] {
+            return
+        }
+        let wv = self.to_window(v);
+        let (x, y) = (wv.x.floor() as i32, wv.y.floor() as i32);
+        if self.in_scissor(x, y) {
+            self.fragment(x, y, &Fragment::interpolate(&[&wv], &[1.0]), &v.blend_flat, &v.atlas_xywh);
+        }
+    }
+
+    fn line(&mut self, a: &ClipVertex, b: &ClipVertex) {
+        let (ca, cb) = match clip_line(a, b) {
+            Some(line) => line,
+            None => return,
+        };
+        let (v0, v1) = (self.to_window(&ca), self.to_window(&cb));
+        let (dx, dy) = (v1.x - v0.x, v1.y - v0.y);
+        let x_major = dx.abs() >= dy.abs();
+        let (start, end, delta) = if x_major { (v0.x, v1.x, dx) } else { (v0.y, v1.y, dy) };
+        if delta == 0.0 || !delta.is_finite() {
+            return
+        }
+
+        // diamond-exit rule: every pixel whose centre is passed along the major axis, but not the last one
+        let (first, last) = if delta > 0.0 {
+            ((start - 0.5).ceil(), (end - 0.5).ceil() - 1.0)
+        } else {
+            ((end - 0.5).floor() + 1.0, (start - 0.5).floor())
+        };
+        let (lo, hi) = if x_major { (self.scissor[0], self.scissor[2]) } else { (self.scissor[1], self.scissor[3]) };
+        let first = first.max(f64::from(lo)) as i32;
+        let last = last.min(f64::from(hi - 1)) as i32;
+
+        for i in first..=last {
+            let t = (f64::from(i) + 0.5 - start) / delta;
+            let (x, y) =
+                if x_major { (i, (v0.y + dy * t).floor() as i32) } else { ((v0.x + dx * t).floor() as i32, i) };
+            if self.in_scissor(x, y) {
+                let frag = Fragment::interpolate(&[&v0, &v1], &[1.0 - t, t]);
+                self.fragment(x, y, &frag, &a.blend_flat, &a.atlas_xywh);
+            }
+        }
+    }
+
+    fn triangle(&mut self, a: &ClipVertex, b: &ClipVertex, c: &ClipVertex) {
+        let polygon = clip_polygon(vec![*a, *b, *c]);
+        if polygon.len() < 3 {
+            return
+        }
+        let verts = polygon.iter().map(|v| self.to_window(v)).collect::<Vec<_>>();
+        for i in 1..verts.len() - 1 {
+            self.fill_triangle(&verts[0], &verts[i], &verts[i + 1], &a.blend_flat, &a.atlas_xywh);
+        }
+    }
+
+    fn fill_triangle(
+        &mut self,
+        v0: &WindowVertex,
+        v1: &WindowVertex,
+        v2: &WindowVertex,
+        blend_flat: &[f32; 4],
+        atlas_xywh: &[f32; 4],
+    ) {
+        let edge =
+            |a: &WindowVertex, b: &WindowVertex, x: f64, y: f64| (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x);
+        let area = edge(v0, v1, v2.x, v2.y);
+        if area == 0.0 || !area.is_finite() {
+            return
+        }
+        // front faces are counter-clockwise like in GL, and back faces are the ones that get culled
+        if self.state.culling && area < 0.0 {
+            return
+        }
+
+        // flip the edges around for clockwise triangles so the inside is always positive
+        let sign = area.signum();
+        let area = area.abs();
+        let top_left = |a: &WindowVertex, b: &WindowVertex| {
+            let (dx, dy) = ((b.x - a.x) * sign, (b.y - a.y) * sign);
+            dy < 0.0 || (dy == 0.0 && dx > 0.0)
+        };
+        let inside = |w: f64, top_left: bool| w > 0.0 || (w == 0.0 && top_left);
+        let (tl0, tl1, tl2) = (top_left(v1, v2), top_left(v2, v0), top_left(v0, v1));
+
+        let min_x = (v0.x.min(v1.x).min(v2.x).floor() as i32).max(self.scissor[0]);
+        let min_y = (v0.y.min(v1.y).min(v2.y).floor() as i32).max(self.scissor[1]);
+        let max_x = (v0.x.max(v1.x).max(v2.x).ceil() as i32).min(self.scissor[2]);
+        let max_y = (v0.y.max(v1.y).max(v2.y).ceil() as i32).min(self.scissor[3]);
+
+        for y in min_y..max_y {
+            let py = f64::from(y) + 0.5;
+            for x in min_x..max_x {
+                let px = f64::from(x) + 0.5;
+                let w0 = edge(v1, v2, px, py) * sign;
+                let w1 = edge(v2, v0, px, py) * sign;
+                let w2 = edge(v0, v1, px, py) * sign;
+                if inside(w0, tl0) && inside(w1, tl1) && inside(w2, tl2) {
+                    let frag = Fragment::interpolate(&[v0, v1, v2], &[w0 / area, w1 / area, w2 / area]);
+                    self.fragment(x, y, &frag, blend_flat, atlas_xywh);
+                }
+            }
+        }
+    }
+
+    /// Same thing as glsl/fragment.glsl, followed by the depth test and blending.
+    fn fragment(&mut self, x: i32, y: i32, frag: &Fragment, blend_flat: &[f32; 4], atlas_xywh: &[f32; 4]) {
+        let tex_col = self.sample(frag.tex_coord, atlas_xywh);
+        let mut colour = [0.0; 4];
+        for (i, col) in colour.iter_mut().enumerate() {
+            *col = tex_col[i] * frag.blend[i] * blend_flat[i];
+        }
+        // apply fog
+        if let Some((fog_colour, fog_begin, fog_end)) = self.fog {
+            let f = clamp01((fog_end - frag.fog_z) / (fog_end - fog_begin));
+            for (col, fog_col) in colour.iter_mut().zip(&fog_colour).take(3) {
+                *col = mix(*fog_col, *col, f);
+            }
+        }
+        // alpha test
+        if self.state.depth_test && colour[3] <= 0.0 {
+            return
+        }
+
+        let index = (y * self.target.width + x) as usize;
+        if self.depth_attached && self.state.depth_test {
+            if let Some(zbuf) = self.target.zbuf.as_mut() {
+                let depth = frag.depth as f32;
+                if depth > zbuf[index] {
+                    return
+                }
+                if self.state.write_depth {
+                    zbuf[index] = depth;
+                }
+            }
+        }
+
+        let src = colour.map(clamp01);
+        let pixel = &mut self.target.pixels[index * 4..index * 4 + 4];
+        let out = if self.state.alpha_blending {
+            let dst = [pixel[0], pixel[1], pixel[2], pixel[3]].map(|c| f32::from(c) / 255.0);
+            let (src_mode, dst_mode) = self.state.blend_mode;
+            let src_factor = blend_factor(src_mode, &src, &dst);
+            let dst_factor = blend_factor(dst_mode, &src, &dst);
+            let mut out = [0.0; 4];
+            for (i, col) in out.iter_mut().enumerate() {
+                *col = clamp01(src[i] * src_factor[i] + dst[i] * dst_factor[i]);
+            }
+            out
+        } else {
+            src
+        };
+        for (p, col) in pixel.iter_mut().zip(&out) {
+            *p = (col * 255.0).round() as u8;
+        }
+    }
+
+    fn sample(&self, tex_coord: [f32; 2], atlas_xywh: &[f32; 4]) -> [f32; 4] {
+        // keep in mind: the center of a pixel is where its colour is in full; we get the top-left as input
+        let [atlas_x, atlas_y, atlas_w, atlas_h] = *atlas_xywh;
+        if self.state.texture_repeat {
+            let sprite_x = fract(tex_coord[0]) * atlas_w;
+            let sprite_y = fract(tex_coord[1]) * atlas_h;
+            if self.state.interpolate_pixels {
+                // get the exact colour of each of the four pixels this coordinate is near, and mix them
+                let floor_x = (sprite_x - 0.5).floor();
+                let floor_y = (sprite_y - 0.5).floor();
+                let left = atlas_x + glsl_mod(floor_x + 0.5, atlas_w);
+                let top = atlas_y + glsl_mod(floor_y + 0.5, atlas_h);
+                let right = atlas_x + glsl_mod(floor_x + 1.5, atlas_w);
+                let bottom = atlas_y + glsl_mod(floor_y + 1.5, atlas_h);
+                let factor_x = fract(sprite_x + 0.5);
+                let factor_y = fract(sprite_y + 0.5);
+                let mix_top = lerp(self.texel(left, top), self.texel(right, top), factor_x);
+                let mix_bot = lerp(self.texel(left, bottom), self.texel(right, bottom), factor_x);
+                lerp(mix_top, mix_bot, factor_y)
+            } else {
+                // we've already done the wrapping, so clamp to center of edge pixels
+                let sprite_x = sprite_x.max(0.5).min(atlas_w - 0.5);
+                let sprite_y = sprite_y.max(0.5).min(atlas_h - 0.5);
+                self.texel(atlas_x + sprite_x, atlas_y + sprite_y)
+            }
+        } else {
+            // clamp to center of edge pixels
+            let sprite_x = (tex_coord[0] * atlas_w).max(0.5).min(atlas_w - 0.5);
+            let sprite_y = (tex_coord[1] * atlas_h).max(0.5).min(atlas_h - 0.5);
+            if self.state.interpolate_pixels {
+                self.texel_linear(atlas_x + sprite_x, atlas_y + sprite_y)
+            } else {
+                self.texel(atlas_x + sprite_x, atlas_y + sprite_y)
+            }
+        }
+    }
+
+    /// Nearest-neighbour texture lookup, with coordinates in texels. Wraps around like GL_REPEAT.
+    fn texel(&self, x: f32, y: f32) -> [f32; 4] {
+        self.fetch(x.floor() as i32, y.floor() as i32)
+    }
+
+    /// Bilinear texture lookup, with coordinates in texels. Wraps around like GL_REPEAT.
+    fn texel_linear(&self, x: f32, y: f32) -> [f32; 4] {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (left, top) = (x.floor() as i32, y.floor() as i32);
+        let (factor_x, factor_y) = (fract(x), fract(y));
+        let mix_top = lerp(self.fetch(left, top), self.fetch(left + 1, top), factor_x);
+        let mix_bot = lerp(self.fetch(left, top + 1), self.fetch(left + 1, top + 1), factor_x);
+        lerp(mix_top, mix_bot, factor_y)
+    }
+
+    fn fetch(&self, x: i32, y: i32) -> [f32; 4] {
+        let (width, height) = (self.texture.width, self.texture.height);
+        if width <= 0 || height <= 0 {
+            return [0.0, 0.0, 0.0, 1.0]
+        }
+        let index = (y.rem_euclid(height) * width + x.rem_euclid(width)) as usize * 4;
+        let pixel = &self.texture.pixels[index..index + 4];
+        [pixel[0], pixel[1], pixel[2], pixel[3]].map(|c| f32::from(c) / 255.0)
+    }
+}
+
+// surfaces aren't saved, since nothing gets drawn to them in between
+struct Snapshot {
+    framebuffer: SavedTexture,
+    target: Option<u32>,
+    viewport: [i32; 4],
+    state: RenderState,
+    using_3d: bool,
+    perspective: bool,
+    depth: f32,
+}
+
+pub struct RendererImpl {
+    atlas_packers: Vec<DensePacker>,
+    textures: Vec<Option<SavedTexture>>,
+    sprites: HashMap<i32, AtlasRef>,
+    sprite_count: i32,
+    stock_atlas_count: u32,
+    framebuffer: SavedTexture,
+    target: Option<u32>,
+    viewport: [i32; 4],
+    zbuf_trashed: bool,
+    white_pixel: AtlasRef,
+    state: RenderState,
+    normalize_normals: bool,
+    vsync: Cell<bool>,
+    circle_precision: i32,
+    using_3d: bool,
+    perspective: bool,
+    depth: f32,
+    primitive_2d: PrimitiveBuilder,
+    primitive_3d: PrimitiveBuilder,
+}
+
+impl RendererImpl {
+    pub fn new(options: &RendererOptions, clear_colour: Colour) -> Self {
+        let (width, height) = (options.size.0 as i32, options.size.1 as i32);
+        let mut renderer = Self {
+            atlas_packers: vec![],
+            textures: vec![],
+            sprites: HashMap::new(),
+            sprite_count: 0,
+            stock_atlas_count: 0,
+            framebuffer: blank_texture(width, height, true),
+            target: None,
+            viewport: [0, 0, width, height],
+            zbuf_trashed: false,
+            white_pixel: Default::default(),
+            state: RenderState { interpolate_pixels: options.interpolate_pixels, ..Default::default() },
+            normalize_normals: options.normalize_normals,
+            vsync: Cell::new(options.vsync),
+            circle_precision: 24,
+            using_3d: false,
+            perspective: false,
+            depth: 0.0,
+            primitive_2d: PrimitiveBuilder::new(Default::default(), PrimitiveType::PointList),
+            primitive_3d: PrimitiveBuilder::new(Default::default(), PrimitiveType::PointList),
+        };
+
+        // Start first frame
+        renderer.setup_frame(clear_colour);
+        renderer
+    }
+
+    fn setup_frame(&mut self, clear_colour: Colour) {
+        let (width, height) = (self.framebuffer.width, self.framebuffer.height);
+        self.set_view(0, 0, width, height, 0.0, 0, 0, width, height);
+        self.clear_view(clear_colour, 1.0);
+    }
+
+    fn update_matrix(&mut self) {
+        self.state.viewproj_matrix = mat4mult(self.state.view_matrix, self.state.proj_matrix);
+    }
+
+    fn push_primitive(&mut self, builder: &PrimitiveBuilder) {
+        self.draw_buffer(builder.get_atlas_id(), builder.get_shape(), builder.get_vertices());
+    }
+
+    fn draw_buffer(&mut self, atlas_id: u32, shape: PrimitiveShape, buffer: &[Vertex]) {
+        if buffer.is_empty() {
+            return
+        }
+        let verts = buffer.iter().map(|v| self.state.shade_vertex(v, self.normalize_normals)).collect::<Vec<_>>();
+
+        // take the target out so it can be drawn to while other textures are being read from
+        let (mut target, depth_attached) = match self.target {
+            Some(id) => match self.textures.get_mut(id as usize).and_then(Option::take) {
+                Some(target) => (target, true),
+                None => return,
+            },
+            None => (std::mem::replace(&mut self.framebuffer, blank_texture(0, 0, false)), !self.zbuf_trashed),
+        };
+        // drawing a surface onto itself is undefined in GL, so just draw whatever it was beforehand
+        let feedback = (self.target == Some(atlas_id)).then(|| target.clone());
+        let texture = match feedback.as_ref() {
+            Some(texture) => Some(texture),
+            None => self.textures.get(atlas_id as usize).and_then(Option::as_ref),
+        };
+
+        if let Some(texture) = texture {
+            let [x, y, w, h] = self.viewport;
+            let mut raster = Rasteriser {
+                state: &self.state,
+                texture,
+                scissor: scissor_rect(self.viewport, &target),
+                target: &mut target,
+                depth_attached,
+                viewport: [x.into(), y.into(), w.into(), h.into()],
+                fog: self.state.fog.as_ref().map(|fog| (split_colour(fog.colour, 1.0), fog.begin, fog.end)),
+            };
+            match shape {
+                PrimitiveShape::Point => verts.iter().for_each(|v| raster.point(v)),
+                PrimitiveShape::Line => verts.chunks_exact(2).for_each(|l| raster.line(&l[0], &l[1])),
+                PrimitiveShape::Triangle => verts.chunks_exact(3).for_each(|t| raster.triangle(&t[0], &t[1], &t[2])),
+            }
+        }
+
+        match self.target {
+            Some(id) => self.textures[id as usize] = Some(target),
+            None => self.framebuffer = target,
+        }
+    }
+
+    fn clear(&mut self, colour: Option<[u8; 4]>, zbuf: bool) {
+        let viewport = self.viewport;
+        let (target, depth_attached) = match self.target {
+            Some(id) => match self.textures.get_mut(id as usize) {
+                Some(Some(target)) => (target, true),
+                _ => return,
+            },
+            None => (&mut self.framebuffer, !self.zbuf_trashed),
+        };
+        // clearing is limited to the scissor box, and depth is only cleared if it's writable
+        let [left, top, right, bottom] = scissor_rect(viewport, target);
+        let clear_zbuf = zbuf && depth_attached && self.state.write_depth;
+        for y in top..bottom {
+            for x in left..right {
+                let index = (y * target.width + x) as usize;
+                if let Some(colour) = colour {
+                    target.pixels[index * 4..index * 4 + 4].copy_from_slice(&colour);
+                }
+                if let (true, Some(zbuf)) = (clear_zbuf, target.zbuf.as_mut()) {
+                    zbuf[index] = 1.0;
+                }
+            }
+        }
+    }
+}
+
+impl RendererTrait for RendererImpl {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn max_texture_size(&self) -> u32 {
+        MAX_TEXTURE_SIZE
+    }
+
+    fn push_atlases(&mut self, mut atl: AtlasBuilder) -> Result<(), String> {
+        assert!(self.atlas_packers.is_empty(), "atlases should be initialized only once");
+        self.white_pixel =
+            atl.texture(1, 1, 0, 0, Box::new([0xFF, 0xFF, 0xFF, 0xFF])).ok_or("Couldn't pack white_pixel")?;
+        // update primitive buffers with white pixel
+        self.reset_primitive_2d(PrimitiveType::PointList, None);
+        self.reset_primitive_3d(PrimitiveType::PointList, None);
+
+        let (packers, sprites) = atl.into_inner();
+
+        let mut textures = packers
+            .iter()
+            .map(|packer| {
+                let (width, height) = packer.size();
+                blank_texture(width, height, false)
+            })
+            .collect::<Vec<_>>();
+
+        // upload textures, which come in as BGRA
+        for (atl_ref, pixels) in &sprites {
+            let rgba = pixels.chunks_exact(4).flat_map(|p| [p[2], p[1], p[0], p[3]]).collect::<Vec<_>>();
+            write_pixels(&mut textures[atl_ref.atlas_id as usize], atl_ref.x, atl_ref.y, atl_ref.w, atl_ref.h, &rgba);
+            self.sprite_count += 1;
+        }
+
+        self.stock_atlas_count = textures.len() as u32;
+        self.textures = textures.into_iter().map(Some).collect();
+
+        // store packers, discard pixeldata
+        self.atlas_packers = packers;
+
+        Ok(())
+    }
+
+    fn upload_sprite(
+        &mut self,
+        data: Box<[u8]>,
+        width: i32,
+        height: i32,
+        origin_x: i32,
+        origin_y: i32,
+    ) -> Result<AtlasRef, String> {
+        let atlas_ref = AtlasRef {
+            origin_x: origin_x as f32 / width as f32,
+            origin_y: origin_y as f32 / height as f32,
+            ..self.create_surface(width, height, false)?
+        };
+        if let Some(Some(texture)) = self.textures.get_mut(atlas_ref.atlas_id as usize) {
+            write_pixels(texture, atlas_ref.x, atlas_ref.y, atlas_ref.w, atlas_ref.h, &data);
+        }
+        Ok(atlas_ref)
+    }
+
+    fn duplicate_sprite(&mut self, atlas_ref: &AtlasRef) -> Result<AtlasRef, String> {
+        let pixels = match self.textures.get(atlas_ref.atlas_id as usize) {
+            Some(Some(texture)) => read_pixels(texture, atlas_ref.x, atlas_ref.y, atlas_ref.w, atlas_ref.h),
+            _ => return Err("Failed to duplicate texture! (texture doesn't exist)".into()),
+        };
+        let new_sprite = self.create_surface(atlas_ref.w, atlas_ref.h, false)?;
+        if let Some(Some(texture)) = self.textures.get_mut(new_sprite.atlas_id as usize) {
+            write_pixels(texture, 0, 0, new_sprite.w, new_sprite.h, &pixels);
+        }
+        Ok(new_sprite)
+    }
+
+    fn delete_sprite(&mut self, atlas_ref: AtlasRef) {
+        // this only deletes sprites created with upload_sprite
+        self.sprites.remove(&atlas_ref.sprite_id);
+        if atlas_ref.atlas_id >= self.stock_atlas_count {
+            if let Some(texture) = self.textures.get_mut(atlas_ref.atlas_id as usize) {
+                *texture = None;
+            }
+        }
+    }
+
+    fn set_vsync(&self, vsync: bool) {
+        self.vsync.set(vsync);
+    }
+
+    fn get_vsync(&self) -> bool {
+        self.vsync.get()
+    }
+
+    fn wait_vsync(&self) {
+        // there's no display to wait for
+    }
+
+    fn create_sprite_colour(&mut self, width: i32, height: i32, col: Colour) -> Result<AtlasRef, String> {
+        let atlas_ref = self.create_surface(width, height, false)?;
+        if let Some(Some(texture)) = self.textures.get_mut(atlas_ref.atlas_id as usize) {
+            let colour = colour_bytes(col, 1.0);
+            texture.pixels.chunks_exact_mut(4).for_each(|p| p.copy_from_slice(&colour));
+        }
+        Ok(atlas_ref)
+    }
+
+    fn create_surface(&mut self, width: i32, height: i32, has_zbuffer: bool) -> Result<AtlasRef, String> {
+        if width < 0 || height < 0 || width as u32 > MAX_TEXTURE_SIZE || height as u32 > MAX_TEXTURE_SIZE {
+            return Err(format!("Failed to allocate {}x{} texture", width, height))
+        }
+        let atlas_id = if let Some(id) = self.textures.iter().position(|x| x.is_none()) {
+            id as u32
+        } else {
+            self.textures.push(None);
+            self.textures.len() as u32 - 1
+        };
+        self.textures[atlas_id as usize] = Some(blank_texture(width, height, has_zbuffer));
+        let sprite_id = self.sprite_count;
+        self.sprite_count += 1;
+        Ok(AtlasRef { atlas_id, sprite_id, x: 0, y: 0, w: width, h: height, origin_x: 0.0, origin_y: 0.0 })
+    }
+
+    fn set_target(&mut self, atlas_ref: &AtlasRef) {
+        if let Some(Some(_)) = self.textures.get(atlas_ref.atlas_id as usize) {
+            self.target = Some(atlas_ref.atlas_id);
+            // set viewport here since set_view doesn't
+            self.viewport = [atlas_ref.x, atlas_ref.y, atlas_ref.w, atlas_ref.h];
+            self.set_view(
+                atlas_ref.x,
+                atlas_ref.y,
+                atlas_ref.w,
+                atlas_ref.h,
+                0.0,
+                atlas_ref.x,
+                atlas_ref.y,
+                atlas_ref.w,
+                atlas_ref.h,
+            );
+        }
+    }
+
+    fn reset_target(&mut self) {
+        self.target = None;
+        let (fb_width, fb_height) = (self.framebuffer.width, self.framebuffer.height);
+        self.set_view(0, 0, fb_width, fb_height, 0.0, 0, 0, fb_width, fb_height);
+    }
+
+    fn set_zbuf_trashed(&mut self, trashed: bool) {
+        self.zbuf_trashed = trashed;
+    }
+
+    fn get_zbuf_trashed(&self) -> bool {
+        self.zbuf_trashed
+    }
+
+    fn resize_framebuffer(&mut self, width: u32, height: u32) {
+        let (width, height) = (width as i32, height as i32);
+        let old = std::mem::replace(&mut self.framebuffer, blank_texture(width, height, true));
+        // copy old fb onto new
+        let copy_width = width.min(old.width).max(0) as usize;
+        for y in 0..height.min(old.height) {
+            let src = (y * old.width) as usize;
+            let dst = (y * width) as usize;
+            self.framebuffer.pixels[dst * 4..(dst + copy_width) * 4]
+                .copy_from_slice(&old.pixels[src * 4..(src + copy_width) * 4]);
+            if let (Some(new_zbuf), Some(old_zbuf)) = (self.framebuffer.zbuf.as_mut(), old.zbuf.as_ref()) {
+                new_zbuf[dst..dst + copy_width].copy_from_slice(&old_zbuf[src..src + copy_width]);
+            }
+        }
+    }
+
+    fn get_texture_id(&mut self, atl_ref: &AtlasRef) -> i32 {
+        self.sprites.entry(atl_ref.sprite_id).or_insert(*atl_ref);
+        atl_ref.sprite_id
+    }
+
+    fn get_texture_from_id(&self, id: i32) -> Option<&AtlasRef> {
+        if id >= 0 { self.sprites.get(&id) } else { None }
+    }
+
+    fn get_sprite_count(&self) -> i32 {
+        self.sprite_count
+    }
+
+    fn set_sprite_count(&mut self, sprite_count: i32) {
+        self.sprite_count = sprite_count;
+    }
+
+    fn dump_sprite(&self, atlas_ref: &AtlasRef) -> Box<[u8]> {
+        let texture = self.textures[atlas_ref.atlas_id as usize].as_ref().expect("Trying to dump nonexistent sprite");
+        read_pixels(texture, atlas_ref.x, atlas_ref.y, atlas_ref.w, atlas_ref.h)
+    }
+
+    fn get_pixels(&self, x: i32, y: i32, w: i32, h: i32) -> Box<[u8]> {
+        read_pixels(&self.framebuffer, x, y, w, h)
+    }
+
+    fn dump_zbuffer(&self) -> Box<[f32]> {
+        self.framebuffer.zbuf.clone().unwrap_or_default()
+    }
+
+    fn draw_raw_frame(
+        &mut self,
+        rgba: Box<[u8]>,
+        zbuf: Box<[f32]>,
+        fb_w: i32,
+        fb_h: i32,
+        window_w: u32,
+        window_h: u32,
+        scaling: Scaling,
+    ) {
+        // resize framebuffer
+        self.resize_framebuffer(fb_w as _, fb_h as _);
+        // upload new frame
+        if rgba.len() == self.framebuffer.pixels.len() {
+            self.framebuffer.pixels = rgba;
+        }
+        if Some(zbuf.len()) == self.framebuffer.zbuf.as_ref().map(|z| z.len()) {
+            self.framebuffer.zbuf = Some(zbuf);
+        }
+        self.present(window_w as _, window_h as _, scaling);
+        self.set_view(0, 0, fb_w, fb_h, 0.0, 0, 0, fb_w, fb_h);
+    }
+
+    fn snapshot(&mut self) -> Box<dyn Any> {
+        Box::new(Snapshot {
+            framebuffer: self.framebuffer.clone(),
+            target: self.target,
+            viewport: self.viewport,
+            state: self.state.clone(),
+            using_3d: self.using_3d,
+            perspective: self.perspective,
+            depth: self.depth,
+        })
+    }
+
+    fn restore(&mut self, snapshot: Box<dyn Any>) {
+        let snapshot = snapshot.downcast::<Snapshot>().expect("not a software renderer snapshot");
+        self.framebuffer = snapshot.framebuffer;
+        self.target = snapshot.target;
+        self.viewport = snapshot.viewport;
+        self.state = snapshot.state;
+        self.using_3d = snapshot.using_3d;
+        self.perspective = snapshot.perspective;
+        self.depth = snapshot.depth;
+    }
+
+    fn dump_dynamic_textures(&self) -> Vec<Option<SavedTexture>> {
+        self.textures[self.stock_atlas_count as usize..].to_vec()
+    }
+
+    fn upload_dynamic_textures(&mut self, textures: &[Option<SavedTexture>]) {
+        self.textures.truncate(self.stock_atlas_count as usize);
+        self.textures.extend_from_slice(textures);
+    }
+
+    fn draw_sprite_general(
+        &mut self,
+        texture: &AtlasRef,
+        part_x: f64,
+        part_y: f64,
+        part_w: f64,
+        part_h: f64,
+        x: f64,
+        y: f64,
+        xscale: f64,
+        yscale: f64,
+        angle: f64,
+        col1: i32,
+        col2: i32,
+        col3: i32,
+        col4: i32,
+        alpha: f64,
+        use_origin: bool,
+    ) {
+        let atlas_ref = *texture;
+
+        if !matches!(self.textures.get(atlas_ref.atlas_id as usize), Some(Some(_))) {
+            return // fail silently when drawing deleted sprite fonts
+        }
+        self.set_texture_repeat(false);
+
+        // get angle
+        let angle = -angle.to_radians();
+        let angle_sin = angle.sin();
+        let angle_cos = angle.cos();
+
+        // get real width of drawn sprite
+        let width: f64 = xscale * part_w;
+        let height: f64 = yscale * part_h;
+        // calculate pre-rotation corner offsets from sprite origin
+        // incl. subtraction 0.5 from left and top (GM does this in an attempt to combat the DX half-pixel offset)
+        let (left, top): (f64, f64) = if use_origin {
+            (-width * f64::from(atlas_ref.origin_x) - 0.5, -height * f64::from(atlas_ref.origin_y) - 0.5)
+        } else {
+            (-0.5, -0.5)
+        };
+        let right: f64 = left + width;
+        let bottom: f64 = top + height;
+
+        // get texture corners
+        let tex_left = part_x / f64::from(atlas_ref.w);
+        let tex_top = part_y / f64::from(atlas_ref.h);
+        let tex_right = tex_left + part_w / f64::from(atlas_ref.w);
+        let tex_bottom = tex_top + part_h / f64::from(atlas_ref.h);
+
+        let (tex_left, tex_top, tex_right, tex_bottom) =
+            (tex_left as f32, tex_top as f32, tex_right as f32, tex_bottom as f32);
+
+        let normal = [0.0, 0.0, 0.0];
+        let depth = self.depth;
+
+        // rotate around draw origin
+        let rotate = |xoff, yoff| {
+            [(x + xoff * angle_cos - yoff * angle_sin) as f32, (y + yoff * angle_cos + xoff * angle_sin) as f32, depth]
+        };
+
+        // push the vertices
+        self.push_primitive(
+            PrimitiveBuilder::new(atlas_ref, PrimitiveType::TriFan)
+                .push_vertex(rotate(left, top), [tex_left, tex_top], split_colour(col1, alpha), normal)
+                .push_vertex(rotate(right, top), [tex_right, tex_top], split_colour(col2, alpha), normal)
+                .push_vertex(rotate(right, bottom), [tex_right, tex_bottom], split_colour(col3, alpha), normal)
+                .push_vertex(rotate(left, bottom), [tex_left, tex_bottom], split_colour(col4, alpha), normal),
+        );
+    }
+
+    fn draw_rectangle(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, colour: i32, alpha: f64) {
+        let x2 = if x2 == x2.floor() { x2 + 0.01 } else { x2 };
+        let y2 = if y2 == y2.floor() { y2 + 0.01 } else { y2 };
+        self.push_primitive(
+            ShapeBuilder::new(false, self.white_pixel, alpha, self.depth)
+                .push_point(x1, y1, colour)
+                .push_point(x2, y1, colour)
+                .push_point(x2, y2, colour)
+                .push_point(x1, y2, colour)
+                .build(),
+        );
+    }
+
+    fn draw_rectangle_outline(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, colour: i32, alpha: f64) {
+        let x2 = if x2 == x2.floor() { x2 + 0.01 } else { x2 };
+        let y2 = if y2 == y2.floor() { y2 + 0.01 } else { y2 };
+        self.push_primitive(
+            ShapeBuilder::new(true, self.white_pixel, alpha, self.depth)
+                .push_point(x1, y1, colour)
+                .push_point(x2, y1, colour)
+                .push_point(x2, y2, colour)
+                .push_point(x1, y2, colour)
+                .build(),
+        );
+    }
+
+    fn draw_rectangle_gradient(
+        &mut self,
+        x1: f64,
+        y1: f64,
+        x2: f64,
+        y2: f64,
+        c1: i32,
+        c2: i32,
+        c3: i32,
+        c4: i32,
+        alpha: f64,
+        outline: bool,
+    ) {
+        let x2 = if x2 == x2.floor() { x2 + 0.01 } else { x2 };
+        let y2 = if y2 == y2.floor() { y2 + 0.01 } else { y2 };
+        self.push_primitive(
+            ShapeBuilder::new(outline, self.white_pixel, alpha, self.depth)
+                .push_point(x1, y1, c1)
+                .push_point(x2, y1, c2)
+                .push_point(x2, y2, c3)
+                .push_point(x1, y2, c4)
+                .build(),
+        );
+    }
+
+    fn draw_point(&mut self, x: f64, y: f64, colour: i32, alpha: f64) {
+        self.draw_buffer(self.white_pixel.atlas_id, PrimitiveShape::Point, &[Vertex {
+            pos: [x as f32, y as f32, self.depth],
+            tex_coord: [0.0, 0.0],
+            blend: split_colour(colour, alpha),
+            atlas_xywh: self.white_pixel.into(),
+            normal: [0.0, 0.0, 0.0],
+        }]);
+    }
+
+    fn draw_line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, width: Option<f64>, c1: i32, c2: i32, alpha: f64) {
+        if let Some(width) = width {
+            let length = (x2 - x1).hypot(y2 - y1);
+            // on the off chance that they're in different points but the length is still somehow 0, check length
+            if length != 0.0 {
+                // calculate corners
+                let width_x = (y2 - y1) * (width / 2.0) / length;
+                let width_y = (x2 - x1) * (width / 2.0) / length;
+                // actually push the rectangle
+                self.push_primitive(
+                    ShapeBuilder::new(false, self.white_pixel, alpha, self.depth)
+                        .push_point(x1 - width_x, y1 + width_y, c1)
+                        .push_point(x1 + width_x, y1 - width_y, c1)
+                        .push_point(x2 + width_x, y2 - width_y, c2)
+                        .push_point(x2 - width_x, y2 + width_y, c2)
+                        .build(),
+                );
+            }
+        } else {
+            self.push_primitive(
+                ShapeBuilder::new(true, self.white_pixel, alpha, self.depth)
+                    .push_point(x1, y1, c1)
+                    .push_point(x2, y2, c2)
+                    .build(),
+            );
+        }
+    }
+
+    fn draw_triangle(
+        &mut self,
+        x1: f64,
+        y1: f64,
+        x2: f64,
+        y2: f64,
+        x3: f64,
+        y3: f64,
+        c1: i32,
+        c2: i32,
+        c3: i32,
+        alpha: f64,
+        outline: bool,
+    ) {
+        self.push_primitive(
+            ShapeBuilder::new(outline, self.white_pixel, alpha, self.depth)
+                .push_point(x1, y1, c1)
+                .push_point(x2, y2, c2)
+                .push_point(x3, y3, c3)
+                .build(),
+        );
+    }
+
+    fn draw_ellipse(&mut self, x: f64, y: f64, rad_x: f64, rad_y: f64, c1: i32, c2: i32, alpha: f64, outline: bool) {
+        let mut builder = ShapeBuilder::new(outline, self.white_pixel, alpha, self.depth);
+        if !outline {
+            builder.push_point(x, y, c1);
+        }
+        for i in 0..=self.circle_precision {
+            let angle = f64::from(i) * 2.0 * PI / f64::from(self.circle_precision);
+            builder.push_point(x + rad_x * angle.cos(), y + rad_y * angle.sin(), c2);
+        }
+        self.push_primitive(builder.build());
+    }
+
+    fn draw_roundrect(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, c1: i32, c2: i32, alpha: f64, outline: bool) {
+        let x2 = if x2 == x2.floor() { x2 + 0.01 } else { x2 };
+        let y2 = if y2 == y2.floor() { y2 + 0.01 } else { y2 };
+        let xcenter = (x1 + x2) / 2.0;
+        let ycenter = (y1 + y2) / 2.0;
+        let width = (x2 - x1).abs();
+        let height = (y2 - y1).abs();
+        let rad_x = width.min(10.0) / 2.0;
+        let rad_y = height.min(10.0) / 2.0;
+        let rect_half_w = (width / 2.0 - rad_x).max(0.0);
+        let rect_half_h = (height / 2.0 - rad_y).max(0.0);
+        let mut builder = ShapeBuilder::new(outline, self.white_pixel, alpha, self.depth);
+        if !outline {
+            builder.push_point(xcenter, ycenter, c1);
+        }
+        let quarter_circle = self.circle_precision / 4;
+        for quad in 0..4 {
+            let circle_x = xcenter + if quad == 0 || quad == 3 { rect_half_w } else { -rect_half_w };
+            let circle_y = ycenter + if quad < 2 { rect_half_h } else { -rect_half_h };
+            for i in quarter_circle * quad..=quarter_circle * (quad + 1) {
+                let angle = f64::from(i) * 2.0 * PI / f64::from(self.circle_precision);
+                builder.push_point(circle_x + rad_x * angle.cos(), circle_y + rad_y * angle.sin(), c2);
+            }
+        }
+        self.push_primitive(builder.push_point(xcenter + rect_half_w + rad_x, ycenter + rect_half_h, c2).build());
+    }
+
+    fn set_circle_precision(&mut self, prec: i32) {
+        self.circle_precision = (prec.clamp(4, 64) >> 2) << 2;
+    }
+
+    fn get_circle_precision(&self) -> i32 {
+        self.circle_precision
+    }
+
+    fn reset_primitive_2d(&mut self, ptype: PrimitiveType, atlas_ref: Option<AtlasRef>) {
+        self.primitive_2d = PrimitiveBuilder::new(atlas_ref.unwrap_or(self.white_pixel), ptype);
+    }
+
+    fn vertex_2d(&mut self, x: f64, y: f64, xtex: f64, ytex: f64, col: i32, alpha: f64) {
+        self.primitive_2d.push_vertex(
+            [x as f32, y as f32, self.depth],
+            [xtex as f32, ytex as f32],
+            split_colour(col, alpha),
+            [0.0, 0.0, 0.0],
+        );
+    }
+
+    fn draw_primitive_2d(&mut self) {
+        // move it out of self to satisfy the borrow checker
+        let primitive = std::mem::replace(
+            &mut self.primitive_2d,
+            PrimitiveBuilder::new(self.white_pixel, PrimitiveType::PointList),
+        );
+        self.push_primitive(&primitive);
+        self.primitive_2d = primitive;
+    }
+
+    fn get_primitive_2d(&self) -> PrimitiveBuilder {
+        self.primitive_2d.clone()
+    }
+
+    fn set_primitive_2d(&mut self, prim: PrimitiveBuilder) {
+        self.primitive_2d = prim;
+    }
+
+    fn reset_primitive_3d(&mut self, ptype: PrimitiveType, atlas_ref: Option<AtlasRef>) {
+        self.primitive_3d = PrimitiveBuilder::new(atlas_ref.unwrap_or(self.white_pixel), ptype);
+    }
+
+    fn vertex_3d(
+        &mut self,
+        x: f64,
+        y: f64,
+        z: f64,
+        nx: f64,
+        ny: f64,
+        nz: f64,
+        xtex: f64,
+        ytex: f64,
+        col: i32,
+        alpha: f64,
+    ) {
+        self.primitive_3d.push_vertex(
+            [x as f32, y as f32, z as f32],
+            [xtex as f32, ytex as f32],
+            split_colour(col, alpha),
+            [nx as f32, ny as f32, nz as f32],
+        );
+    }
+
+    fn draw_primitive_3d(&mut self) {
+        // See draw_primitive_2d.
+        let primitive = std::mem::replace(
+            &mut self.primitive_3d,
+            PrimitiveBuilder::new(self.white_pixel, PrimitiveType::PointList),
+        );
+        self.push_primitive(&primitive);
+        self.primitive_3d = primitive;
+    }
+
+    fn get_primitive_3d(&self) -> PrimitiveBuilder {
+        self.primitive_3d.clone()
+    }
+
+    fn set_primitive_3d(&mut self, prim: PrimitiveBuilder) {
+        self.primitive_3d = prim;
+    }
+
+    fn extend_buffers(&self, buf: &mut VertexBuffer) {
+        let verts = self.primitive_3d.get_vertices();
+        match self.primitive_3d.get_shape() {
+            PrimitiveShape::Point => buf.points.extend_from_slice(verts),
+            PrimitiveShape::Line => buf.lines.extend_from_slice(&verts[..verts.len() / 2 * 2]),
+            PrimitiveShape::Triangle => buf.tris.extend_from_slice(&verts[..verts.len() / 3 * 3]),
+        }
+    }
+
+    fn draw_buffers(&mut self, atlas_ref: Option<AtlasRef>, buf: &VertexBuffer) {
+        let atlas_id = atlas_ref.unwrap_or(self.white_pixel).atlas_id;
+        self.draw_buffer(atlas_id, PrimitiveShape::Point, &buf.points);
+        self.draw_buffer(atlas_id, PrimitiveShape::Line, &buf.lines);
+        self.draw_buffer(atlas_id, PrimitiveShape::Triangle, &buf.tris);
+    }
+
+    fn get_alpha_blending(&self) -> bool {
+        self.state.alpha_blending
+    }
+
+    fn set_alpha_blending(&mut self, alphablend: bool) {
+        self.state.alpha_blending = alphablend;
+    }
+
+    fn get_blend_mode(&self) -> (BlendType, BlendType) {
+        self.state.blend_mode
+    }
+
+    fn set_blend_mode(&mut self, src: BlendType, dst: BlendType) {
+        self.state.blend_mode = (src, dst);
+    }
+
+    fn get_pixel_interpolation(&self) -> bool {
+        self.state.interpolate_pixels
+    }
+
+    fn set_pixel_interpolation(&mut self, lerping: bool) {
+        self.state.interpolate_pixels = lerping;
+    }
+
+    fn get_texture_repeat(&self) -> bool {
+        self.state.texture_repeat
+    }
+
+    fn set_texture_repeat(&mut self, repeat: bool) {
+        self.state.texture_repeat = repeat;
+    }
+
+    fn flush_queue(&mut self) {
+        // everything is drawn straight away, so there's never a queue
+    }
+
+    fn set_view_matrix(&mut self, view: [f32; 16]) {
+        self.state.view_matrix = view;
+        self.update_matrix();
+    }
+
+    fn set_viewproj_matrix(&mut self, view: [f32; 16], proj: [f32; 16]) {
+        self.state.view_matrix = view;
+        self.state.proj_matrix = proj;
+        self.update_matrix();
+    }
+
+    fn get_model_matrix(&self) -> [f32; 16] {
+        self.state.model_matrix
+    }
+
+    fn set_model_matrix(&mut self, model: [f32; 16]) {
+        self.state.model_matrix = model;
+    }
+
+    fn mult_model_matrix(&mut self, model: [f32; 16]) {
+        self.state.model_matrix = mat4mult(self.state.model_matrix, model);
+    }
+
+    fn set_projection_ortho(&mut self, x: f64, y: f64, w: f64, h: f64, angle: f64) {
+        #[rustfmt::skip]
+        let proj_matrix: [f32; 16] = {
+            // Squish to screen, flip vertically, and constrain z to range 1 - 32000
+            [
+                2.0 / w as f32, 0.0,             0.0,            0.0,
+                0.0,            -2.0 / h as f32, 0.0,            0.0,
+                0.0,            0.0,             1.0 / 31999.0,  0.0,
+                0.0,            0.0,             -1.0 / 31999.0, 1.0,
+            ]
+        };
+
+        self.set_viewproj_matrix(make_view_matrix(x, y, -16000.0, w, h, angle), proj_matrix);
+    }
+
+    fn set_projection_perspective(&mut self, x: f64, y: f64, w: f64, h: f64, angle: f64) {
+        #[rustfmt::skip]
+        let proj_matrix: [f32; 16] = {
+            // Squish to screen, flip vertically, and constrain z to range 1 - 32000
+            [
+                2.0, 0.0,                  0.0,                0.0,
+                0.0, 2.0 * (w / h) as f32, 0.0,                0.0,
+                0.0, 0.0,                  32000.0 / 31999.0,  1.0,
+                0.0, 0.0,                  -32000.0 / 31999.0, 0.0,
+            ]
+        };
+
+        self.set_viewproj_matrix(make_view_matrix(x, y, -w, w, h, angle), proj_matrix);
+    }
+
+    fn set_view(
+        &mut self,
+        src_x: i32,
+        src_y: i32,
+        src_w: i32,
+        src_h: i32,
+        src_angle: f64,
+        port_x: i32,
+        port_y: i32,
+        port_w: i32,
+        port_h: i32,
+    ) {
+        // DX8's viewport function doesn't do anything if a surface is set as the draw target, so emulate that
+        if self.target.is_none() && port_x >= 0 && port_y >= 0 && port_w >= 0 && port_h >= 0 {
+            self.viewport = [port_x, port_y, port_w, port_h];
+        }
+        if self.using_3d && self.perspective {
+            self.set_projection_perspective(src_x.into(), src_y.into(), src_w.into(), src_h.into(), src_angle);
+        } else {
+            self.set_projection_ortho(src_x.into(), src_y.into(), src_w.into(), src_h.into(), src_angle);
+        }
+    }
+
+    fn clear_view(&mut self, colour: Colour, alpha: f64) {
+        self.clear(Some(colour_bytes(colour, alpha)), true);
+    }
+
+    fn clear_view_no_zbuf(&mut self, colour: Colour, alpha: f64) {
+        self.clear(Some(colour_bytes(colour, alpha)), false);
+    }
+
+    fn clear_zbuf(&mut self) {
+        if self.using_3d {
+            self.clear(None, true);
+        }
+    }
+
+    fn get_3d(&self) -> bool {
+        self.using_3d
+    }
+
+    fn set_3d(&mut self, use_3d: bool) {
+        self.using_3d = use_3d;
+        self.set_depth_test(use_3d);
+        self.set_perspective(use_3d);
+    }
+
+    fn get_depth(&self) -> f32 {
+        self.depth
+    }
+
+    #[allow(clippy::manual_clamp)] // NaN ends up at -16000 in the GL renderer too
+    fn set_depth(&mut self, depth: f32) {
+        self.depth = if self.using_3d { depth.max(-16000.0).min(16000.0) } else { 0.0 };
+    }
+
+    fn get_depth_test(&self) -> bool {
+        self.state.depth_test
+    }
+
+    fn set_depth_test(&mut self, depth_test: bool) {
+        self.state.depth_test = depth_test && self.using_3d;
+    }
+
+    fn get_write_depth(&self) -> bool {
+        self.state.write_depth
+    }
+
+    fn set_write_depth(&mut self, write_depth: bool) {
+        self.state.write_depth = write_depth;
+    }
+
+    fn get_culling(&self) -> bool {
+        self.state.culling
+    }
+
+    fn set_culling(&mut self, culling: bool) {
+        self.state.culling = culling;
+    }
+
+    fn get_perspective(&self) -> bool {
+        self.perspective
+    }
+
+    fn set_perspective(&mut self, perspective: bool) {
+        self.perspective = perspective;
+    }
+
+    fn get_fog(&self) -> Option<Fog> {
+        self.state.fog.clone()
+    }
+
+    fn set_fog(&mut self, fog: Option<Fog>) {
+        self.state.fog = fog;
+    }
+
+    fn get_gouraud(&self) -> bool {
+        self.state.gouraud
+    }
+
+    fn set_gouraud(&mut self, gouraud: bool) {
+        self.state.gouraud = gouraud;
+    }
+
+    fn get_lighting_enabled(&self) -> bool {
+        self.state.lighting
+    }
+
+    fn set_lighting_enabled(&mut self, enabled: bool) {
+        self.state.lighting = enabled;
+    }
+
+    fn get_ambient_colour(&self) -> i32 {
+        self.state.ambient_colour
+    }
+
+    fn set_ambient_colour(&mut self, colour: i32) {
+        self.state.ambient_colour = colour;
+    }
+
+    fn get_lights(&self) -> [(bool, Light); 8] {
+        self.state.lights
+    }
+
+    fn set_light_enabled(&mut self, id: usize, enabled: bool) {
+        self.state.lights[id].0 = enabled;
+    }
+
+    fn set_light(&mut self, id: usize, light: Light) {
+        self.state.lights[id].1 = light;
+    }
+
+    fn present(&mut self, _window_width: u32, _window_height: u32, _scaling: Scaling) {
+        // there's no swapchain, so the framebuffer itself is the finished frame, read it back with get_pixels
+    }
+
+    fn finish(&mut self, window_width: u32, window_height: u32, clear_colour: Colour) {
+        // Present screen
+        self.present(window_width, window_height, Scaling::Fixed(1.0));
+
+        // Start next frame
+        self.setup_frame(clear_colour)
+    }
+}
+
+fn blank_texture(width: i32, height: i32, has_zbuffer: bool) -> SavedTexture {
+    let len = (width.max(0) * height.max(0)) as usize;
+    SavedTexture {
+        width,
+        height,
+        pixels: vec![0; len * 4].into_boxed_slice(),
+        zbuf: if has_zbuffer { Some(vec![1.0; len].into_boxed_slice()) } else { None },
+    }
+}
+
+/// Copies a rectangle of RGBA pixels out of a texture. Anything out of bounds is left as zeroes.
+fn read_pixels(texture: &SavedTexture, x: i32, y: i32, w: i32, h: i32) -> Box<[u8]> {
+    let (w, h) = (w.max(0), h.max(0));
+    let mut data = vec![0u8; (w * h * 4) as usize];
+    for row in 0..h {
+        for col in 0..w {
+            let (tx, ty) = (x + col, y + row);
+            if tx >= 0 && ty >= 0 && tx < texture.width && ty < texture.height {
+                let src = ((ty * texture.width + tx) * 4) as usize;
+                let dst = ((row * w + col) * 4) as usize;
+                data[dst..dst + 4].copy_from_slice(&texture.pixels[src..src + 4]);
+            }
+        }
+    }
+    data.into_boxed_slice()
+}
+
+/// Copies a rectangle of RGBA pixels into a texture. Anything out of bounds is ignored.
+fn write_pixels(texture: &mut SavedTexture, x: i32, y: i32, w: i32, h: i32, data: &[u8]) {
+    for row in 0..h.max(0) {
+        for col in 0..w.max(0) {
+            let (tx, ty) = (x + col, y + row);
+            let src = ((row * w + col) * 4) as usize;
+            if tx >= 0 && ty >= 0 && tx < texture.width && ty < texture.height && src + 4 <= data.len() {
+                let dst = ((ty * texture.width + tx) * 4) as usize;
+                texture.pixels[dst..dst + 4].copy_from_slice(&data[src..src + 4]);
+            }
+        }
+    }
+}
+
+/// Gets the scissor box for a viewport, limited to the bounds of the target.
+fn scissor_rect(viewport: [i32; 4], target: &SavedTexture) -> [i32; 4] {
+    let [x, y, w, h] = viewport;
+    [x.max(0), y.max(0), (x + w).min(target.width), (y + h).min(target.height)]
+}
+
+#[allow(clippy::manual_clamp)] // NaN has to become 0, like on the GPU
+fn colour_bytes(colour: Colour, alpha: f64) -> [u8; 4] {
+    [colour.r, colour.g, colour.b, alpha].map(|c| (c.max(0.0).min(1.0) * 255.0).round() as u8)
+}
+
+fn blend_factor(blend_type: BlendType, src: &[f32; 4], dst: &[f32; 4]) -> [f32; 4] {
+    match blend_type {
+        BlendType::Zero => [0.0; 4],
+        BlendType::One => [1.0; 4],
+        BlendType::SrcColour => *src,
+        BlendType::InvSrcColour => src.map(|c| 1.0 - c),
+        BlendType::SrcAlpha => [src[3]; 4],
+        BlendType::InvSrcAlpha => [1.0 - src[3]; 4],
+        BlendType::DestAlpha => [dst[3]; 4],
+        BlendType::InvDestAlpha => [1.0 - dst[3]; 4],
+        BlendType::DestColour => *dst,
+        BlendType::InvDestColour => dst.map(|c| 1.0 - c),
+        BlendType::SrcAlphaSaturate => {
+            let f = src[3].min(1.0 - dst[3]);
+            [f, f, f, 1.0]
+        },
+    }
+}
+
+/// Clips a polygon against the near and far planes. The sides are left to the scissor test.
+fn clip_polygon(mut polygon: Vec<ClipVertex>) -> Vec<ClipVertex> {
+    for &sign in &[1.0, -1.0] {
+        let dist = |v: &ClipVertex| sign * v.pos[2] + v.pos[3];
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+        for (i, cur) in polygon.iter().enumerate() {
+            let next = &polygon[(i + 1) % polygon.len()];
+            let (d_cur, d_next) = (dist(cur), dist(next));
+            if d_cur >= 0.0 {
+                clipped.push(*cur);
+            }
+            if (d_cur >= 0.0) != (d_next >= 0.0) {
+                clipped.push(cur.lerp(next, d_cur / (d_cur - d_next)));
+            }
+        }
+        polygon = clipped;
+    }
+    polygon
+}
+
+/// Clips a line against the near and far planes.
+fn clip_line(a: &ClipVertex, b: &ClipVertex) -> Option<(ClipVertex, ClipVertex)> {
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    for &sign in &[1.0, -1.0] {
+        let d_a = sign * a.pos[2] + a.pos[3];
+        let d_b = sign * b.pos[2] + b.pos[3];
+        if d_a < 0.0 && d_b < 0.0 {
+            return None
+        } else if d_a < 0.0 {
+            t0 = t0.max(d_a / (d_a - d_b));
+        } else if d_b < 0.0 {
+            t1 = t1.min(d_a / (d_a - d_b));
+        }
+    }
+    if t0 > t1 {
+        return None
+    }
+    // the flat attributes need to stay with the first vertex
+    Some((a.lerp(b, t0), ClipVertex { blend_flat: a.blend_flat, ..a.lerp(b, t1) }))
+}
+
+fn transform(m: &[f32; 16], v: [f32; 4]) -> [f32; 4] {
+    let mut out = [0.0; 4];
+    for (i, o) in out.iter_mut().enumerate() {
+        *o = v[0] * m[i] + v[1] * m[4 + i] + v[2] * m[8 + i] + v[3] * m[12 + i];
+    }
+    out
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let len = dot(v, v).sqrt();
+    [v[0] / len, v[1] / len, v[2] / len]
+}
+
+/// Like GLSL's clamp(x, 0.0, 1.0), except NaN becomes 0.
+#[allow(clippy::manual_clamp)] // f32::clamp would keep the NaN
+fn clamp01(x: f32) -> f32 {
+    x.max(0.0).min(1.0)
+}
+
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+fn glsl_mod(x: f32, y: f32) -> f32 {
+    x - y * (x / y).floor()
+}
+
+fn mix(x: f32, y: f32, a: f32) -> f32 {
+    x * (1.0 - a) + y * a
+}
+
+fn lerp<const N: usize>(a: [f32; N], b: [f32; N], t: f32) -> [f32; N] {
+    let mut out = a;
+    for (o, b) in out.iter_mut().zip(&b) {
+        *o = mix(*o, *b, t);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // colours as GML has them, which is BGR
+    const RED: i32 = 0x0000FF;
+    const GREEN: i32 = 0x00FF00;
+    const BLUE: i32 = 0xFF0000;
+    const WHITE: i32 = 0xFFFFFF;
+
+    fn blank(width: u32, height: u32) -> RendererImpl {
+        let options = RendererOptions { size: (width, height), ..Default::default() };
+        let mut renderer = RendererImpl::new(&options, Colour::new(0.0, 0.0, 0.0));
+        renderer.push_atlases(AtlasBuilder::new(64)).unwrap();
+        renderer
+    }
+
+    /// Draws the framebuffer as one character per pixel, so it can be compared against a picture.
+    fn picture(renderer: &RendererImpl) -> Vec<String> {
+        let (width, height) = (renderer.framebuffer.width, renderer.framebuffer.height);
+        let pixels = renderer.get_pixels(0, 0, width, height);
+        pixels
+            .chunks_exact(width as usize * 4)
+            .map(|row| {
+                row.chunks_exact(4)
+                    .map(|p| match [p[0], p[1], p[2]] {
+                        [0, 0, 0] => '.',
+                        [255, 0, 0] => 'R',
+                        [0, 255, 0] => 'G',
+                        [0, 0, 255] => 'B',
+                        [255, 255, 255] => 'W',
+                        _ => '?',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn pixel(renderer: &RendererImpl, x: i32, y: i32) -> [u8; 4] {
+        let mut out = [0; 4];
+        out.copy_from_slice(&renderer.get_pixels(x, y, 1, 1));
+        out
+    }
+
+    #[test]
+    fn sprites() {
+        let mut renderer = blank(8, 8);
+        #[rustfmt::skip]
+        let pixels = [
+            255, 0, 0, 255,   0, 255, 0, 255,
+            0, 0, 255, 255,   255, 255, 255, 255,
+        ];
+        let sprite = renderer.upload_sprite(Box::new(pixels), 2, 2, 0, 0).unwrap();
+        renderer.draw_sprite(&sprite, 1.0, 1.0, 1.0, 1.0, 0.0, WHITE, 1.0);
+        renderer.draw_sprite(&sprite, 4.0, 4.0, 2.0, 1.0, 0.0, WHITE, 1.0);
+        renderer.draw_sprite(&sprite, 3.0, 6.0, -1.0, 1.0, 0.0, WHITE, 1.0);
+        // turned so only its first column shows, tinted so only blue comes through
+        renderer.draw_sprite(&sprite, 6.0, 0.0, 1.0, 1.0, 90.0, BLUE, 1.0);
+        #[rustfmt::skip]
+        let expected = [
+            ".......B",
+            ".RG.....",
+            ".BW.....",
+            "........",
+            "....RRGG",
+            "....BBWW",
+            ".GR.....",
+            ".WB.....",
+        ];
+        assert_eq!(picture(&renderer), expected);
+
+        // the origin is what gets put at the position
+        let centred = renderer.upload_sprite(Box::new(pixels), 2, 2, 1, 1).unwrap();
+        renderer.draw_sprite(&centred, 1.0, 1.0, 1.0, 1.0, 0.0, WHITE, 1.0);
+        assert_eq!(&picture(&renderer)[..2], ["RG.....B", "BWG....."]);
+    }
+
+    #[test]
+    fn primitives() {
+        let mut renderer = blank(8, 8);
+        renderer.draw_rectangle(1.0, 1.0, 3.0, 2.0, RED, 1.0);
+        renderer.draw_rectangle_outline(4.0, 0.0, 7.0, 3.0, GREEN, 1.0);
+        renderer.draw_line(0.0, 5.0, 7.0, 5.0, None, BLUE, BLUE, 1.0);
+        renderer.draw_point(0.0, 7.0, WHITE, 1.0);
+        renderer.draw_triangle(2.0, 7.0, 6.0, 7.0, 6.0, 3.0, WHITE, WHITE, WHITE, 1.0, false);
+        #[rustfmt::skip]
+        let expected = [
+            "....GGGG",
+            ".RRRG..G",
+            ".RRRG..G",
+            "....GGGG",
+            ".....W..",
+            "BBBBWWB.",
+            "...WWW..",
+            "W.......",
+        ];
+        assert_eq!(picture(&renderer), expected);
+
+        // gradients get interpolated across the shape
+        let mut renderer = blank(4, 1);
+        renderer.draw_rectangle_gradient(0.0, 0.0, 3.0, 0.0, RED, BLUE, BLUE, RED, 1.0, false);
+        let red_blue = renderer.get_pixels(0, 0, 4, 1).chunks_exact(4).map(|p| (p[0], p[2])).collect::<Vec<_>>();
+        assert_eq!(red_blue, [(255, 0), (170, 85), (86, 169), (1, 254)]);
+    }
+
+    #[test]
+    fn blending() {
+        let mut renderer = blank(4, 1);
+        renderer.draw_rectangle(0.0, 0.0, 3.0, 0.0, BLUE, 1.0);
+        renderer.draw_point(0.0, 0.0, RED, 0.5);
+        renderer.set_blend_mode(BlendType::One, BlendType::One);
+        renderer.draw_point(1.0, 0.0, RED, 1.0);
+        renderer.set_blend_mode(BlendType::SrcAlpha, BlendType::InvSrcAlpha);
+        renderer.set_alpha_blending(false);
+        renderer.draw_point(2.0, 0.0, RED, 0.5);
+        renderer.set_alpha_blending(true);
+        renderer.draw_point(3.0, 0.0, RED, 0.0);
+        assert_eq!(pixel(&renderer, 0, 0), [128, 0, 128, 191]);
+        assert_eq!(pixel(&renderer, 1, 0), [255, 0, 255, 255]);
+        assert_eq!(pixel(&renderer, 2, 0), [255, 0, 0, 128]);
+        assert_eq!(pixel(&renderer, 3, 0), [0, 0, 255, 255]);
+
+        // half-transparent texels blend the same way
+        let sprite = renderer.upload_sprite(Box::new([0, 255, 0, 128]), 1, 1, 0, 0).unwrap();
+        renderer.draw_sprite(&sprite, 3.0, 0.0, 1.0, 1.0, 0.0, WHITE, 1.0);
+        assert_eq!(pixel(&renderer, 3, 0), [0, 128, 127, 191]);
+    }
+
+    #[test]
+    fn clipping() {
+        // a room twice the size of the port gets squashed into it, and nothing goes outside
+        let mut renderer = blank(8, 8);
+        renderer.set_view(0, 0, 8, 8, 0.0, 2, 2, 4, 4);
+        renderer.draw_rectangle(-10.0, -10.0, 20.0, 20.0, WHITE, 1.0);
+        renderer.draw_rectangle(0.0, 0.0, 3.0, 3.0, RED, 1.0);
+        #[rustfmt::skip]
+        let expected = [
+            "........",
+            "........",
+            "..RRWW..",
+            "..RRWW..",
+            "..WWWW..",
+            "..WWWW..",
+            "........",
+            "........",
+        ];
+        assert_eq!(picture(&renderer), expected);
+
+        // surfaces only get drawn inside themselves, and leave the screen alone
+        let mut renderer = blank(4, 4);
+        let surface = renderer.create_surface(2, 2, false).unwrap();
+        renderer.set_target(&surface);
+        renderer.draw_rectangle(-10.0, -10.0, 20.0, 20.0, GREEN, 1.0);
+        renderer.reset_target();
+        renderer.draw_sprite(&surface, 1.0, 1.0, 1.0, 1.0, 0.0, WHITE, 1.0);
+        renderer.draw_triangle(-100.0, 3.0, 100.0, 3.0, 0.0, 100.0, BLUE, BLUE, BLUE, 1.0, false);
+        assert_eq!(picture(&renderer), ["....", ".GG.", ".GG.", "BBBB"]);
+        assert_eq!(renderer.dump_sprite(&surface).len(), 2 * 2 * 4);
+    }
+}
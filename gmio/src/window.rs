@@ -1,5 +1,7 @@
 //! Windowing magic.
 
+pub mod headless;
+pub mod wayland;
 pub mod win32;
 pub mod xorg;
 
@@ -12,10 +14,11 @@ use win32 as platform;
 #[cfg(target_os = "linux")]
 use xorg as platform;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Backend {
     OpenGL,
-    Vulkan, // unimplemented so far
+    Software, // CPU rasteriser, doesn't need a window to draw into
+    Vulkan,   // unimplemented so far
 }
 
 #[derive(Copy, Clone)]
@@ -109,7 +112,27 @@ impl Window {
 
     /// Creates a new Window, invisible by default.
     pub fn new(builder: &WindowBuilder) -> Result<Self, String> {
-        Ok(Self(Box::new(platform::WindowImpl::new(builder)?)))
+        match builder.headless {
+            Some(display_size) => Ok(Self(Box::new(headless::WindowImpl::new(builder, display_size)))),
+            None => {
+                // use Wayland natively if there's a compositor, rather than going through XWayland
+                #[cfg(target_os = "linux")]
+                {
+                    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+                        match wayland::WindowImpl::new(builder) {
+                            Ok(window) => return Ok(Self(Box::new(window))),
+                            Err(e) => eprintln!("Warning: couldn't use Wayland, falling back to X11: {}", e),
+                        }
+                    }
+                }
+                Ok(Self(Box::new(platform::WindowImpl::new(builder)?)))
+            },
+        }
+    }
+
+    /// Returns whether this is a headless window, meaning there's no actual window or user.
+    pub fn is_headless(&self) -> bool {
+        self.as_any().is::<headless::WindowImpl>()
     }
 
     /// Returns whether the window requested to be closed.
@@ -223,6 +246,7 @@ impl Window {
 
 pub struct WindowBuilder {
     cursor: Cursor,
+    headless: Option<(i32, i32)>,
     size: (u32, u32),
     style: Style,
     title: String,
@@ -230,7 +254,13 @@ pub struct WindowBuilder {
 
 impl Default for WindowBuilder {
     fn default() -> Self {
-        Self { cursor: Cursor::default(), size: (640, 480), style: Style::Regular, title: String::new() }
+        Self {
+            cursor: Cursor::default(),
+            headless: None,
+            size: (640, 480),
+            style: Style::Regular,
+            title: String::new(),
+        }
     }
 }
 
@@ -243,6 +273,11 @@ impl WindowBuilder {
         Self { cursor, ..self }
     }
 
+    /// Makes a headless window which pretends to be on a display of the given size.
+    pub fn with_headless(self, display_width: i32, display_height: i32) -> Self {
+        Self { headless: Some((display_width, display_height)), ..self }
+    }
+
     pub fn with_size(self, width: u32, height: u32) -> Self {
         Self { size: (width, height), ..self }
     }
@@ -0,0 +1,156 @@
+//! A window that never actually exists, for running without a display server.
+
+use super::{Cursor, Event, Style, WindowBuilder, WindowTrait};
+use std::{any::Any, slice};
+
+/// The display size reported when none is specified.
+pub const DEFAULT_DISPLAY_SIZE: (i32, i32) = (1920, 1080);
+
+pub struct WindowImpl {
+    close_requested: bool,
+    cursor: Cursor,
+    display_size: (i32, i32),
+    inner_size: (u32, u32),
+    pos: (i32, i32),
+    title: String,
+    visible: bool,
+    events: Vec<Event>, // always empty, nothing can happen to a window nobody sees
+}
+
+impl WindowImpl {
+    pub fn new(builder: &WindowBuilder, display_size: (i32, i32)) -> Self {
+        let mut window = Self {
+            close_requested: false,
+            cursor: builder.cursor,
+            display_size,
+            inner_size: builder.size,
+            pos: (0, 0),
+            title: builder.title.clone(),
+            visible: false,
+            events: Vec::new(),
+        };
+        window.center();
+        window
+    }
+}
+
+impl WindowTrait for WindowImpl {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn close_requested(&self) -> bool {
+        self.close_requested
+    }
+
+    fn set_close_requested(&mut self, value: bool) {
+        self.close_requested = value;
+    }
+
+    fn get_inner_size(&self) -> (u32, u32) {
+        self.inner_size
+    }
+
+    fn process_events<'a>(&'a mut self) -> slice::Iter<'a, Event> {
+        self.events.iter()
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        self.inner_size = (width, height);
+    }
+
+    fn center(&mut self) {
+        let (width, height) = self.inner_size;
+        self.pos = ((self.display_size.0 - width as i32) / 2, (self.display_size.1 - height as i32) / 2);
+    }
+
+    fn get_pos(&self) -> (i32, i32) {
+        self.pos
+    }
+
+    fn set_pos(&mut self, x: i32, y: i32) {
+        self.pos = (x, y);
+    }
+
+    fn get_cursor(&self) -> Cursor {
+        self.cursor
+    }
+
+    fn set_cursor(&mut self, cursor: Cursor) {
+        self.cursor = cursor;
+    }
+
+    fn set_style(&mut self, _style: Style) {}
+
+    fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title = title.into();
+    }
+
+    fn get_visible(&self) -> bool {
+        self.visible
+    }
+
+    fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    fn show_context_menu(&mut self, _options: &[(String, usize)]) {
+        // nobody's there to click anything, so no MenuOption event will ever come
+    }
+
+    fn window_handle(&self) -> usize {
+        0
+    }
+
+    fn display_width(&self) -> i32 {
+        self.display_size.0
+    }
+
+    fn display_height(&self) -> i32 {
+        self.display_size.1
+    }
+
+    fn display_frequency(&self) -> i32 {
+        60
+    }
+
+    fn display_colour(&self) -> i32 {
+        32
+    }
+
+    fn disk_free(&self, _drive: Option<char>) -> Option<u64> {
+        None
+    }
+
+    fn disk_size(&self, _drive: Option<char>) -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::Window;
+
+    #[test]
+    fn window() {
+        let mut window = Window::new(&WindowBuilder::new().with_size(640, 480).with_headless(1024, 768)).unwrap();
+        assert!(window.is_headless());
+        assert_eq!((window.display_width(), window.display_height()), (1024, 768));
+        assert_eq!(window.get_pos(), (192, 144));
+        assert!(!window.get_visible());
+
+        window.set_visible(true);
+        window.resize(800, 600);
+        window.center();
+        window.show_context_menu(&[("Option".into(), 1)]);
+        assert_eq!(window.get_inner_size(), (800, 600));
+        assert_eq!(window.get_pos(), (112, 84));
+        assert_eq!(window.process_events().count(), 0);
+        assert!(!window.close_requested());
+    }
+}
@@ -0,0 +1,884 @@
+#![cfg(target_os = "linux")]
+
+mod ffi;
+
+use super::{Cursor, Event, Style, WindowBuilder, WindowTrait};
+use ffi::*;
+use shared::input::{Key, MouseButton};
+use std::{
+    any::Any,
+    cell::Cell,
+    ffi::{CStr, CString},
+    mem,
+    os::raw::{c_char, c_void},
+    ptr,
+    rc::Rc,
+    slice,
+};
+
+/// What to report if the compositor doesn't tell us about any outputs (some headless ones don't).
+const FALLBACK_DISPLAY_SIZE: (i32, i32) = (1920, 1080);
+
+const CURSOR_SIZE: i32 = 24;
+
+/// Everything the event listeners need access to. This is boxed so its address is stable.
+struct State {
+    compositor: *mut wl_proxy,
+    shm: *mut wl_proxy,
+    wm_base: *mut wl_proxy,
+    seat: *mut wl_proxy,
+    output: *mut wl_proxy,
+    pointer: *mut wl_proxy,
+    keyboard: *mut wl_proxy,
+
+    surface: *mut wl_proxy,
+    xdg_surface: *mut wl_proxy,
+    toplevel: *mut wl_proxy,
+    egl_window: *mut wl_egl_window,
+    configured: bool,
+    pending_size: (i32, i32),
+
+    cursor: Cursor,
+    cursor_theme: *mut wl_cursor_theme,
+    cursor_surface: *mut wl_proxy,
+    pointer_serial: Option<u32>,
+
+    close_requested: bool,
+    inner_size: (u32, u32),
+    display_size: (i32, i32),
+    display_frequency: i32,
+    events: Vec<Event>,
+}
+
+pub struct WindowImpl {
+    display: *mut wl_display,
+    state: Box<State>,
+    mapped: Rc<Cell<bool>>,
+    pos: (i32, i32),
+    style: Style,
+    title: String,
+}
+
+impl WindowImpl {
+    pub fn new(builder: &WindowBuilder) -> Result<Self, String> {
+        let wl = load()?;
+        unsafe {
+            let display = (wl.wl_display_connect)(ptr::null());
+            if display.is_null() {
+                return Err("wl_display_connect failed".into())
+            }
+
+            let mut state = Box::new(State {
+                compositor: ptr::null_mut(),
+                shm: ptr::null_mut(),
+                wm_base: ptr::null_mut(),
+                seat: ptr::null_mut(),
+                output: ptr::null_mut(),
+                pointer: ptr::null_mut(),
+                keyboard: ptr::null_mut(),
+                surface: ptr::null_mut(),
+                xdg_surface: ptr::null_mut(),
+                toplevel: ptr::null_mut(),
+                egl_window: ptr::null_mut(),
+                configured: false,
+                pending_size: (0, 0),
+                cursor: builder.cursor,
+                cursor_theme: ptr::null_mut(),
+                cursor_surface: ptr::null_mut(),
+                pointer_serial: None,
+                close_requested: false,
+                inner_size: builder.size,
+                display_size: FALLBACK_DISPLAY_SIZE,
+                display_frequency: 60,
+                events: Vec::with_capacity(8),
+            });
+            let data = state.as_mut() as *mut State as *mut c_void;
+
+            // bind globals, then do another roundtrip so the seat and output can introduce themselves
+            let registry = (wl.wl_proxy_marshal_flags)(
+                display.cast(),
+                WL_DISPLAY_GET_REGISTRY,
+                wl.wl_registry_interface,
+                1,
+                0,
+                ptr::null_mut::<c_void>(),
+            );
+            (wl.wl_proxy_add_listener)(registry, &REGISTRY_LISTENER as *const _ as _, data);
+            (wl.wl_display_roundtrip)(display);
+            (wl.wl_proxy_destroy)(registry);
+            if state.compositor.is_null() || state.wm_base.is_null() {
+                (wl.wl_display_disconnect)(display);
+                return Err("Wayland compositor doesn't support wl_compositor and xdg_wm_base".into())
+            }
+            (wl.wl_display_roundtrip)(display);
+
+            state.surface = request(state.compositor, WL_COMPOSITOR_CREATE_SURFACE, wl.wl_surface_interface, 0);
+            state.egl_window = (wl.wl_egl_window_create)(state.surface, builder.size.0 as _, builder.size.1 as _);
+            if state.egl_window.is_null() {
+                (wl.wl_display_disconnect)(display);
+                return Err("wl_egl_window_create failed".into())
+            }
+            if !state.shm.is_null() {
+                state.cursor_theme = (wl.wl_cursor_theme_load)(ptr::null(), CURSOR_SIZE, state.shm);
+                state.cursor_surface =
+                    request(state.compositor, WL_COMPOSITOR_CREATE_SURFACE, wl.wl_surface_interface, 0);
+            }
+
+            Ok(Self {
+                display,
+                state,
+                mapped: Rc::new(Cell::new(false)),
+                pos: (0, 0),
+                style: builder.style,
+                title: builder.title.clone(),
+            })
+        }
+    }
+
+    pub fn get_display(&self) -> *mut c_void {
+        self.display.cast()
+    }
+
+    pub fn get_egl_window(&self) -> *mut c_void {
+        self.state.egl_window.cast()
+    }
+
+    /// Whether the surface currently has a role, meaning it's on the screen and will get frame callbacks.
+    /// Presenting to it while it doesn't would block forever on vsync, so the renderer has to check.
+    pub fn mapped_flag(&self) -> Rc<Cell<bool>> {
+        self.mapped.clone()
+    }
+
+    unsafe fn map(&mut self) {
+        let wl = wl();
+        let state = self.state.as_mut();
+        let data = state as *mut State as *mut c_void;
+        state.xdg_surface = (wl.wl_proxy_marshal_flags)(
+            state.wm_base,
+            XDG_WM_BASE_GET_XDG_SURFACE,
+            &xdg_surface_interface,
+            (wl.wl_proxy_get_version)(state.wm_base),
+            0,
+            ptr::null_mut::<c_void>(),
+            state.surface,
+        );
+        (wl.wl_proxy_add_listener)(state.xdg_surface, &XDG_SURFACE_LISTENER as *const _ as _, data);
+        state.toplevel = request(state.xdg_surface, XDG_SURFACE_GET_TOPLEVEL, &xdg_toplevel_interface, 0);
+        (wl.wl_proxy_add_listener)(state.toplevel, &XDG_TOPLEVEL_LISTENER as *const _ as _, data);
+        self.apply_title();
+        self.apply_style();
+
+        // the first configure has to be acked before attaching any buffers, so wait for it
+        self.state.configured = false;
+        request(self.state.surface, WL_SURFACE_COMMIT, ptr::null(), 0);
+        // the listener writes through its own pointer, so don't let this get optimised into one read
+        while !ptr::read_volatile(&self.state.configured) {
+            if (wl.wl_display_roundtrip)(self.display) < 0 {
+                break
+            }
+        }
+        self.mapped.set(true);
+    }
+
+    unsafe fn unmap(&mut self) {
+        let wl = wl();
+        self.mapped.set(false);
+        let state = self.state.as_mut();
+        (wl.wl_proxy_marshal_flags)(state.toplevel, XDG_TOPLEVEL_DESTROY, ptr::null(), 1, WL_MARSHAL_FLAG_DESTROY);
+        (wl.wl_proxy_marshal_flags)(state.xdg_surface, XDG_SURFACE_DESTROY, ptr::null(), 1, WL_MARSHAL_FLAG_DESTROY);
+        state.toplevel = ptr::null_mut();
+        state.xdg_surface = ptr::null_mut();
+        (wl.wl_proxy_marshal_flags)(
+            state.surface,
+            WL_SURFACE_ATTACH,
+            ptr::null(),
+            1,
+            0,
+            ptr::null_mut::<c_void>(),
+            0i32,
+            0i32,
+        );
+        request(state.surface, WL_SURFACE_COMMIT, ptr::null(), 0);
+        (wl.wl_display_flush)(self.display);
+    }
+
+    unsafe fn apply_title(&self) {
+        let wl = wl();
+        if !self.state.toplevel.is_null() {
+            let title = CString::new(self.title.replace('\0', "")).unwrap_or_default();
+            let toplevel = self.state.toplevel;
+            (wl.wl_proxy_marshal_flags)(toplevel, XDG_TOPLEVEL_SET_TITLE, ptr::null(), 1, 0, title.as_ptr());
+            let app_id = b"gm8emulator\0".as_ptr().cast::<c_char>();
+            (wl.wl_proxy_marshal_flags)(toplevel, XDG_TOPLEVEL_SET_APP_ID, ptr::null(), 1, 0, app_id);
+        }
+    }
+
+    unsafe fn apply_style(&self) {
+        let wl = wl();
+        // there's no way to ask for a borderless window without xdg-decoration, but most compositors that
+        // would draw borders in the first place support that, so it's left up to the compositor for now
+        let toplevel = self.state.toplevel;
+        if toplevel.is_null() {
+            return
+        }
+        match self.style {
+            Style::BorderlessFullscreen => {
+                (wl.wl_proxy_marshal_flags)(
+                    toplevel,
+                    XDG_TOPLEVEL_SET_FULLSCREEN,
+                    ptr::null(),
+                    1,
+                    0,
+                    ptr::null_mut::<c_void>(),
+                );
+            },
+            _ => {
+                (wl.wl_proxy_marshal_flags)(toplevel, XDG_TOPLEVEL_UNSET_FULLSCREEN, ptr::null(), 1, 0);
+            },
+        }
+        let (width, height) = match self.style {
+            Style::Resizable | Style::BorderlessFullscreen => (0, 0),
+            _ => (self.state.inner_size.0 as i32, self.state.inner_size.1 as i32),
+        };
+        (wl.wl_proxy_marshal_flags)(toplevel, XDG_TOPLEVEL_SET_MIN_SIZE, ptr::null(), 1, 0, width, height);
+        (wl.wl_proxy_marshal_flags)(toplevel, XDG_TOPLEVEL_SET_MAX_SIZE, ptr::null(), 1, 0, width, height);
+    }
+}
+
+impl Drop for WindowImpl {
+    fn drop(&mut self) {
+        let wl = wl();
+        unsafe {
+            if self.mapped.get() {
+                self.unmap();
+            }
+            let state = self.state.as_mut();
+            (wl.wl_egl_window_destroy)(state.egl_window);
+            if !state.cursor_theme.is_null() {
+                (wl.wl_cursor_theme_destroy)(state.cursor_theme);
+            }
+            for surface in &[state.cursor_surface, state.surface] {
+                if !surface.is_null() {
+                    (wl.wl_proxy_marshal_flags)(*surface, WL_SURFACE_DESTROY, ptr::null(), 1, WL_MARSHAL_FLAG_DESTROY);
+                }
+            }
+            if !state.wm_base.is_null() {
+                let flags = WL_MARSHAL_FLAG_DESTROY;
+                (wl.wl_proxy_marshal_flags)(state.wm_base, XDG_WM_BASE_DESTROY, ptr::null(), 1, flags);
+            }
+            for proxy in &[state.pointer, state.keyboard, state.seat, state.output, state.shm, state.compositor] {
+                if !proxy.is_null() {
+                    (wl.wl_proxy_destroy)(*proxy);
+                }
+            }
+            (wl.wl_display_disconnect)(self.display);
+        }
+    }
+}
+
+impl WindowTrait for WindowImpl {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn close_requested(&self) -> bool {
+        self.state.close_requested
+    }
+
+    fn set_close_requested(&mut self, value: bool) {
+        self.state.close_requested = value;
+    }
+
+    fn get_inner_size(&self) -> (u32, u32) {
+        self.state.inner_size
+    }
+
+    fn process_events<'a>(&'a mut self) -> slice::Iter<'a, Event> {
+        let wl = wl();
+        self.state.events.clear();
+        unsafe {
+            // read whatever's waiting on the socket without blocking, then run the listeners
+            while (wl.wl_display_prepare_read)(self.display) != 0 {
+                (wl.wl_display_dispatch_pending)(self.display);
+            }
+            (wl.wl_display_flush)(self.display);
+            let mut fd = libc::pollfd { fd: (wl.wl_display_get_fd)(self.display), events: libc::POLLIN, revents: 0 };
+            if libc::poll(&mut fd, 1, 0) > 0 {
+                (wl.wl_display_read_events)(self.display);
+            } else {
+                (wl.wl_display_cancel_read)(self.display);
+            }
+            (wl.wl_display_dispatch_pending)(self.display);
+        }
+        self.state.events.iter()
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        self.state.inner_size = (width, height);
+        unsafe {
+            (wl().wl_egl_window_resize)(self.state.egl_window, width as _, height as _, 0, 0);
+            self.apply_style();
+        }
+    }
+
+    fn center(&mut self) {
+        // clients don't get to decide where their windows go
+    }
+
+    fn get_pos(&self) -> (i32, i32) {
+        self.pos
+    }
+
+    fn set_pos(&mut self, x: i32, y: i32) {
+        // see center()
+        self.pos = (x, y);
+    }
+
+    fn get_cursor(&self) -> Cursor {
+        self.state.cursor
+    }
+
+    fn set_cursor(&mut self, cursor: Cursor) {
+        self.state.cursor = cursor;
+        unsafe { apply_cursor(&self.state) };
+    }
+
+    fn set_style(&mut self, style: Style) {
+        self.style = style;
+        unsafe { self.apply_style() };
+    }
+
+    fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title = title.into();
+        unsafe { self.apply_title() };
+    }
+
+    fn get_visible(&self) -> bool {
+        self.mapped.get()
+    }
+
+    fn set_visible(&mut self, visible: bool) {
+        // there's no such thing as a hidden window on Wayland, so unmapping is the closest thing to it
+        if self.mapped.get() != visible {
+            unsafe {
+                if visible {
+                    self.map();
+                } else {
+                    self.unmap();
+                }
+            }
+        }
+    }
+
+    fn show_context_menu(&mut self, _options: &[(String, usize)]) {
+        // TODO: no native menus on Wayland, this'll have to be drawn by hand in a popup surface
+    }
+
+    fn window_handle(&self) -> usize {
+        self.state.surface as usize
+    }
+
+    fn display_width(&self) -> i32 {
+        self.state.display_size.0
+    }
+
+    fn display_height(&self) -> i32 {
+        self.state.display_size.1
+    }
+
+    fn display_frequency(&self) -> i32 {
+        self.state.display_frequency
+    }
+
+    fn display_colour(&self) -> i32 {
+        32
+    }
+
+    #[allow(clippy::unnecessary_cast)] // they're 32-bit on 32-bit targets
+    fn disk_free(&self, _drive: Option<char>) -> Option<u64> {
+        // there are no drive letters, so all of them are the root filesystem
+        statvfs_root().map(|stat| stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+
+    #[allow(clippy::unnecessary_cast)] // they're 32-bit on 32-bit targets
+    fn disk_size(&self, _drive: Option<char>) -> Option<u64> {
+        statvfs_root().map(|stat| stat.f_blocks as u64 * stat.f_frsize as u64)
+    }
+}
+
+fn statvfs_root() -> Option<libc::statvfs> {
+    unsafe {
+        let mut stat: libc::statvfs = mem::zeroed();
+        if libc::statvfs(b"/\0".as_ptr().cast(), &mut stat) == 0 { Some(stat) } else { None }
+    }
+}
+
+/// Sends a request that has no arguments other than an optional new_id.
+unsafe fn request(proxy: *mut wl_proxy, opcode: u32, interface: *const wl_interface, flags: u32) -> *mut wl_proxy {
+    let wl = wl();
+    if interface.is_null() {
+        (wl.wl_proxy_marshal_flags)(proxy, opcode, interface, (wl.wl_proxy_get_version)(proxy), flags)
+    } else {
+        let new_id = ptr::null_mut::<c_void>();
+        (wl.wl_proxy_marshal_flags)(proxy, opcode, interface, (wl.wl_proxy_get_version)(proxy), flags, new_id)
+    }
+}
+
+unsafe fn bind(registry: *mut wl_proxy, name: u32, interface: &wl_interface, version: u32) -> *mut wl_proxy {
+    (wl().wl_proxy_marshal_flags)(
+        registry,
+        WL_REGISTRY_BIND,
+        interface,
+        version,
+        0,
+        name,
+        interface.name,
+        version,
+        ptr::null_mut::<c_void>(),
+    )
+}
+
+unsafe fn apply_cursor(state: &State) {
+    let wl = wl();
+    let serial = match (state.pointer_serial, state.pointer.is_null()) {
+        (Some(serial), false) => serial,
+        _ => return,
+    };
+    let name: &[u8] = match state.cursor {
+        Cursor::Arrow => b"left_ptr\0",
+        Cursor::AppStart => b"left_ptr_watch\0",
+        Cursor::Beam => b"xterm\0",
+        Cursor::Cross => b"crosshair\0",
+        Cursor::Hand => b"hand2\0",
+        Cursor::Hourglass => b"watch\0",
+        Cursor::Invisible => {
+            let null = ptr::null_mut::<c_void>();
+            let pointer = state.pointer;
+            (wl.wl_proxy_marshal_flags)(pointer, WL_POINTER_SET_CURSOR, ptr::null(), 1, 0, serial, null, 0i32, 0i32);
+            return
+        },
+        Cursor::SizeNESW => b"fd_double_arrow\0",
+        Cursor::SizeNS => b"sb_v_double_arrow\0",
+        Cursor::SizeNWSE => b"bd_double_arrow\0",
+        Cursor::SizeWE => b"sb_h_double_arrow\0",
+        Cursor::SizeAll => b"fleur\0",
+        Cursor::Up => b"sb_up_arrow\0",
+    };
+    if state.cursor_theme.is_null() {
+        return
+    }
+    // not every theme has every cursor, so fall back to the arrow rather than leaving whatever was there
+    let mut cursor = (wl.wl_cursor_theme_get_cursor)(state.cursor_theme, name.as_ptr().cast());
+    if cursor.is_null() {
+        cursor = (wl.wl_cursor_theme_get_cursor)(state.cursor_theme, b"left_ptr\0".as_ptr().cast());
+    }
+    if cursor.is_null() || (*cursor).image_count == 0 {
+        return
+    }
+    let image = *(*cursor).images;
+    let buffer = (wl.wl_cursor_image_get_buffer)(image);
+    let surface = state.cursor_surface;
+    (wl.wl_proxy_marshal_flags)(
+        state.pointer,
+        WL_POINTER_SET_CURSOR,
+        ptr::null(),
+        1,
+        0,
+        serial,
+        surface,
+        (*image).hotspot_x as i32,
+        (*image).hotspot_y as i32,
+    );
+    (wl.wl_proxy_marshal_flags)(surface, WL_SURFACE_ATTACH, ptr::null(), 1, 0, buffer, 0i32, 0i32);
+    (wl.wl_proxy_marshal_flags)(surface, WL_SURFACE_DAMAGE, ptr::null(), 1, 0, 0i32, 0i32, i32::MAX, i32::MAX);
+    request(surface, WL_SURFACE_COMMIT, ptr::null(), 0);
+}
+
+unsafe fn state<'a>(data: *mut c_void) -> &'a mut State {
+    &mut *(data as *mut State)
+}
+
+/// Maps an evdev scancode to a key, using the same keys as a US layout would (like DirectInput does).
+#[rustfmt::skip]
+fn key_from_evdev(code: u32) -> Option<Key> {
+    Some(match code {
+        1 => Key::Escape,
+        2 => Key::NumRow1, 3 => Key::NumRow2, 4 => Key::NumRow3, 5 => Key::NumRow4, 6 => Key::NumRow5,
+        7 => Key::NumRow6, 8 => Key::NumRow7, 9 => Key::NumRow8, 10 => Key::NumRow9, 11 => Key::NumRow0,
+        12 => Key::Minus,
+        13 => Key::Plus,
+        14 => Key::Backspace,
+        15 => Key::Tab,
+        16 => Key::Q, 17 => Key::W, 18 => Key::E, 19 => Key::R, 20 => Key::T,
+        21 => Key::Y, 22 => Key::U, 23 => Key::I, 24 => Key::O, 25 => Key::P,
+        26 => Key::OEM4,
+        27 => Key::OEM6,
+        28 | 96 => Key::Enter,
+        29 | 97 => Key::Control,
+        30 => Key::A, 31 => Key::S, 32 => Key::D, 33 => Key::F, 34 => Key::G,
+        35 => Key::H, 36 => Key::J, 37 => Key::K, 38 => Key::L,
+        39 => Key::OEM1,
+        40 => Key::OEM7,
+        41 => Key::OEM3,
+        42 | 54 => Key::Shift,
+        43 => Key::OEM5,
+        44 => Key::Z, 45 => Key::X, 46 => Key::C, 47 => Key::V, 48 => Key::B, 49 => Key::N, 50 => Key::M,
+        51 => Key::Comma,
+        52 => Key::Period,
+        53 => Key::OEM2,
+        55 => Key::Multiply,
+        56 | 100 => Key::Alt,
+        57 => Key::Space,
+        58 => Key::CapsLock,
+        59 => Key::F1, 60 => Key::F2, 61 => Key::F3, 62 => Key::F4, 63 => Key::F5,
+        64 => Key::F6, 65 => Key::F7, 66 => Key::F8, 67 => Key::F9, 68 => Key::F10,
+        69 => Key::NumLock,
+        70 => Key::ScrollLock,
+        71 => Key::Numpad7, 72 => Key::Numpad8, 73 => Key::Numpad9,
+        74 => Key::Subtract,
+        75 => Key::Numpad4, 76 => Key::Numpad5, 77 => Key::Numpad6,
+        78 => Key::Add,
+        79 => Key::Numpad1, 80 => Key::Numpad2, 81 => Key::Numpad3,
+        82 => Key::Numpad0,
+        83 => Key::Decimal,
+        86 => Key::OEM102,
+        87 => Key::F11,
+        88 => Key::F12,
+        98 => Key::Divide,
+        99 => Key::PrintScreen,
+        102 => Key::Home,
+        103 => Key::Up,
+        104 => Key::PageUp,
+        105 => Key::Left,
+        106 => Key::Right,
+        107 => Key::End,
+        108 => Key::Down,
+        109 => Key::PageDown,
+        110 => Key::Insert,
+        111 => Key::Delete,
+        119 => Key::Pause,
+        125 => Key::LeftWin,
+        126 => Key::RightWin,
+        _ => return None,
+    })
+}
+
+static REGISTRY_LISTENER: wl_registry_listener = wl_registry_listener { global: registry_global, global_remove };
+static SEAT_LISTENER: wl_seat_listener = wl_seat_listener { capabilities: seat_capabilities, name: seat_name };
+static POINTER_LISTENER: wl_pointer_listener = wl_pointer_listener {
+    enter: pointer_enter,
+    leave: pointer_leave,
+    motion: pointer_motion,
+    button: pointer_button,
+    axis: pointer_axis,
+};
+static KEYBOARD_LISTENER: wl_keyboard_listener = wl_keyboard_listener {
+    keymap: keyboard_keymap,
+    enter: keyboard_enter,
+    leave: keyboard_leave,
+    key: keyboard_key,
+    modifiers: keyboard_modifiers,
+};
+static OUTPUT_LISTENER: wl_output_listener =
+    wl_output_listener { geometry: output_geometry, mode: output_mode, done: output_done, scale: output_scale };
+static WM_BASE_LISTENER: xdg_wm_base_listener = xdg_wm_base_listener { ping: wm_base_ping };
+static XDG_SURFACE_LISTENER: xdg_surface_listener = xdg_surface_listener { configure: xdg_surface_configure };
+static XDG_TOPLEVEL_LISTENER: xdg_toplevel_listener =
+    xdg_toplevel_listener { configure: toplevel_configure, close: toplevel_close };
+
+unsafe extern "C" fn registry_global(
+    data: *mut c_void,
+    registry: *mut wl_proxy,
+    name: u32,
+    interface: *const c_char,
+    version: u32,
+) {
+    let wl = wl();
+    let state = state(data);
+    match CStr::from_ptr(interface).to_bytes() {
+        b"wl_compositor" if state.compositor.is_null() => {
+            state.compositor = bind(registry, name, wl.wl_compositor_interface, version.min(4));
+        },
+        b"wl_shm" if state.shm.is_null() => {
+            state.shm = bind(registry, name, wl.wl_shm_interface, 1);
+        },
+        b"xdg_wm_base" if state.wm_base.is_null() => {
+            state.wm_base = bind(registry, name, &xdg_wm_base_interface, 1);
+            (wl.wl_proxy_add_listener)(state.wm_base, &WM_BASE_LISTENER as *const _ as _, data);
+        },
+        b"wl_seat" if state.seat.is_null() => {
+            state.seat = bind(registry, name, wl.wl_seat_interface, version.min(3));
+            (wl.wl_proxy_add_listener)(state.seat, &SEAT_LISTENER as *const _ as _, data);
+        },
+        b"wl_output" if state.output.is_null() => {
+            state.output = bind(registry, name, wl.wl_output_interface, version.min(2));
+            (wl.wl_proxy_add_listener)(state.output, &OUTPUT_LISTENER as *const _ as _, data);
+        },
+        _ => (),
+    }
+}
+
+unsafe extern "C" fn global_remove(_data: *mut c_void, _registry: *mut wl_proxy, _name: u32) {}
+
+unsafe extern "C" fn seat_capabilities(data: *mut c_void, seat: *mut wl_proxy, caps: u32) {
+    let wl = wl();
+    let state = state(data);
+    if caps & WL_SEAT_CAPABILITY_POINTER != 0 && state.pointer.is_null() {
+        state.pointer = request(seat, WL_SEAT_GET_POINTER, wl.wl_pointer_interface, 0);
+        (wl.wl_proxy_add_listener)(state.pointer, &POINTER_LISTENER as *const _ as _, data);
+    }
+    if caps & WL_SEAT_CAPABILITY_KEYBOARD != 0 && state.keyboard.is_null() {
+        state.keyboard = request(seat, WL_SEAT_GET_KEYBOARD, wl.wl_keyboard_interface, 0);
+        (wl.wl_proxy_add_listener)(state.keyboard, &KEYBOARD_LISTENER as *const _ as _, data);
+    }
+}
+
+unsafe extern "C" fn seat_name(_data: *mut c_void, _seat: *mut wl_proxy, _name: *const c_char) {}
+
+unsafe extern "C" fn pointer_enter(
+    data: *mut c_void,
+    _pointer: *mut wl_proxy,
+    serial: u32,
+    _surface: *mut wl_proxy,
+    x: wl_fixed_t,
+    y: wl_fixed_t,
+) {
+    let state = state(data);
+    state.pointer_serial = Some(serial);
+    apply_cursor(state);
+    state.events.push(Event::MouseMove(x >> 8, y >> 8));
+}
+
+unsafe extern "C" fn pointer_leave(data: *mut c_void, _pointer: *mut wl_proxy, _serial: u32, _surface: *mut wl_proxy) {
+    state(data).pointer_serial = None;
+}
+
+unsafe extern "C" fn pointer_motion(
+    data: *mut c_void,
+    _pointer: *mut wl_proxy,
+    _time: u32,
+    x: wl_fixed_t,
+    y: wl_fixed_t,
+) {
+    state(data).events.push(Event::MouseMove(x >> 8, y >> 8));
+}
+
+unsafe extern "C" fn pointer_button(
+    data: *mut c_void,
+    _pointer: *mut wl_proxy,
+    _serial: u32,
+    _time: u32,
+    button: u32,
+    button_state: u32,
+) {
+    let button = match button {
+        BTN_LEFT => MouseButton::Left,
+        BTN_RIGHT => MouseButton::Right,
+        BTN_MIDDLE => MouseButton::Middle,
+        _ => return,
+    };
+    state(data).events.push(if button_state == WL_POINTER_BUTTON_STATE_PRESSED {
+        Event::MouseButtonDown(button)
+    } else {
+        Event::MouseButtonUp(button)
+    });
+}
+
+unsafe extern "C" fn pointer_axis(
+    data: *mut c_void,
+    _pointer: *mut wl_proxy,
+    _time: u32,
+    axis: u32,
+    value: wl_fixed_t,
+) {
+    if axis == WL_POINTER_AXIS_VERTICAL_SCROLL {
+        let state = state(data);
+        if value > 0 {
+            state.events.push(Event::MouseWheelDown);
+        } else if value < 0 {
+            state.events.push(Event::MouseWheelUp);
+        }
+    }
+}
+
+unsafe extern "C" fn keyboard_keymap(_data: *mut c_void, _keyboard: *mut wl_proxy, _format: u32, fd: i32, _size: u32) {
+    // keys are mapped by scancode, so the keymap is no use to us, but we're responsible for the fd
+    libc::close(fd);
+}
+
+unsafe extern "C" fn keyboard_enter(
+    _data: *mut c_void,
+    _keyboard: *mut wl_proxy,
+    _serial: u32,
+    _surface: *mut wl_proxy,
+    _keys: *mut wl_array,
+) {
+}
+
+unsafe extern "C" fn keyboard_leave(
+    _data: *mut c_void,
+    _keyboard: *mut wl_proxy,
+    _serial: u32,
+    _surface: *mut wl_proxy,
+) {
+}
+
+unsafe extern "C" fn keyboard_key(
+    data: *mut c_void,
+    _keyboard: *mut wl_proxy,
+    _serial: u32,
+    _time: u32,
+    key: u32,
+    key_state: u32,
+) {
+    if let Some(key) = key_from_evdev(key) {
+        state(data).events.push(if key_state == WL_KEYBOARD_KEY_STATE_PRESSED {
+            Event::KeyboardDown(key)
+        } else {
+            Event::KeyboardUp(key)
+        });
+    }
+}
+
+unsafe extern "C" fn keyboard_modifiers(
+    _data: *mut c_void,
+    _keyboard: *mut wl_proxy,
+    _serial: u32,
+    _depressed: u32,
+    _latched: u32,
+    _locked: u32,
+    _group: u32,
+) {
+}
+
+unsafe extern "C" fn output_geometry(
+    _data: *mut c_void,
+    _output: *mut wl_proxy,
+    _x: i32,
+    _y: i32,
+    _physical_width: i32,
+    _physical_height: i32,
+    _subpixel: i32,
+    _make: *const c_char,
+    _model: *const c_char,
+    _transform: i32,
+) {
+}
+
+unsafe extern "C" fn output_mode(
+    data: *mut c_void,
+    _output: *mut wl_proxy,
+    flags: u32,
+    width: i32,
+    height: i32,
+    refresh: i32,
+) {
+    if flags & WL_OUTPUT_MODE_CURRENT != 0 {
+        let state = state(data);
+        state.display_size = (width, height);
+        // refresh rate is in mHz
+        if refresh > 0 {
+            state.display_frequency = (refresh + 500) / 1000;
+        }
+    }
+}
+
+unsafe extern "C" fn output_done(_data: *mut c_void, _output: *mut wl_proxy) {}
+
+unsafe extern "C" fn output_scale(_data: *mut c_void, _output: *mut wl_proxy, _factor: i32) {}
+
+unsafe extern "C" fn wm_base_ping(_data: *mut c_void, wm_base: *mut wl_proxy, serial: u32) {
+    (wl().wl_proxy_marshal_flags)(wm_base, XDG_WM_BASE_PONG, ptr::null(), 1, 0, serial);
+}
+
+unsafe extern "C" fn xdg_surface_configure(data: *mut c_void, xdg_surface: *mut wl_proxy, serial: u32) {
+    let wl = wl();
+    let state = state(data);
+    (wl.wl_proxy_marshal_flags)(xdg_surface, XDG_SURFACE_ACK_CONFIGURE, ptr::null(), 1, 0, serial);
+    state.configured = true;
+
+    // 0 means we get to pick, so keep whatever size we already are
+    let (width, height) = mem::take(&mut state.pending_size);
+    if width > 0 && height > 0 && (width as u32, height as u32) != state.inner_size {
+        let (width, height) = (width as u32, height as u32);
+        state.inner_size = (width, height);
+        (wl.wl_egl_window_resize)(state.egl_window, width as _, height as _, 0, 0);
+        match state.events.last_mut() {
+            Some(Event::Resize(w, h)) => {
+                *w = width;
+                *h = height;
+            },
+            _ => state.events.push(Event::Resize(width, height)),
+        }
+    }
+}
+
+unsafe extern "C" fn toplevel_configure(
+    data: *mut c_void,
+    _toplevel: *mut wl_proxy,
+    width: i32,
+    height: i32,
+    _states: *mut wl_array,
+) {
+    state(data).pending_size = (width, height);
+}
+
+unsafe extern "C" fn toplevel_close(data: *mut c_void, _toplevel: *mut wl_proxy) {
+    state(data).close_requested = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evdev_keys() {
+        assert!(matches!(key_from_evdev(1), Some(Key::Escape)));
+        assert!(matches!(key_from_evdev(30), Some(Key::A)));
+        assert!(matches!(key_from_evdev(57), Some(Key::Space)));
+        assert!(matches!(key_from_evdev(105), Some(Key::Left)));
+        assert!(key_from_evdev(0).is_none());
+        assert!(key_from_evdev(0x110).is_none()); // BTN_LEFT comes through wl_pointer, not here
+
+        // GM doesn't tell left and right modifiers apart, or the keypad's enter from the main one
+        for (left, right) in [(42, 54), (29, 97), (56, 100), (28, 96)].iter().copied() {
+            assert_eq!(key_from_evdev(left), key_from_evdev(right));
+        }
+        assert!(matches!(key_from_evdev(98), Some(Key::Divide)));
+        assert!(matches!(key_from_evdev(111), Some(Key::Delete)));
+    }
+
+    #[test]
+    fn libraries() {
+        // systems without Wayland have to get a clean error rather than failing to start
+        match load() {
+            Ok(wl) => unsafe {
+                assert_eq!(CStr::from_ptr(wl.wl_surface_interface.name).to_bytes(), b"wl_surface");
+                // xdg_wm_base.get_xdg_surface's second argument is a wl_surface, which only exists once loaded
+                let types = (*xdg_wm_base_interface.methods.add(XDG_WM_BASE_GET_XDG_SURFACE as usize)).types;
+                assert!(ptr::eq((*types.add(1)).unwrap(), wl.wl_surface_interface));
+            },
+            Err(e) => assert!(e.contains("libwayland"), "{}", e),
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a Wayland compositor, which CI runs these with"]
+    fn window() {
+        let mut window = WindowImpl::new(&WindowBuilder::new().with_size(320, 240)).unwrap();
+        assert_eq!(window.get_inner_size(), (320, 240));
+        assert!(!window.get_visible());
+        window.set_visible(true);
+        assert!(window.get_visible());
+        assert!(window.mapped_flag().get());
+        window.resize(200, 100);
+        assert_eq!(window.get_inner_size(), (200, 100));
+        let _ = window.process_events();
+        window.set_visible(false);
+        assert!(!window.mapped_flag().get());
+    }
+}
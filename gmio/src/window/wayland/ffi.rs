@@ -0,0 +1,322 @@
+//! Bindings to libwayland-client, libwayland-cursor and libwayland-egl, which get loaded at runtime.
+//! Only the parts we use are here, plus the bits of xdg-shell that wayland-scanner would normally generate.
+
+#![allow(non_camel_case_types, non_upper_case_globals)]
+
+use std::{
+    os::raw::{c_char, c_int, c_uint, c_void},
+    ptr,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        OnceLock,
+    },
+};
+
+pub type wl_fixed_t = i32;
+
+#[repr(C)]
+pub struct wl_display {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct wl_proxy {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct wl_egl_window {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct wl_cursor_theme {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct wl_cursor_image {
+    pub width: u32,
+    pub height: u32,
+    pub hotspot_x: u32,
+    pub hotspot_y: u32,
+    pub delay: u32,
+}
+
+#[repr(C)]
+pub struct wl_cursor {
+    pub image_count: c_uint,
+    pub images: *mut *mut wl_cursor_image,
+    pub name: *mut c_char,
+}
+
+#[repr(C)]
+pub struct wl_array {
+    pub size: usize,
+    pub alloc: usize,
+    pub data: *mut c_void,
+}
+
+#[repr(C)]
+pub struct wl_message {
+    pub name: *const c_char,
+    pub signature: *const c_char,
+    pub types: *const Option<&'static wl_interface>,
+}
+
+#[repr(C)]
+pub struct wl_interface {
+    pub name: *const c_char,
+    pub version: c_int,
+    pub method_count: c_int,
+    pub methods: *const wl_message,
+    pub event_count: c_int,
+    pub events: *const wl_message,
+}
+
+// they're only ever read, and the pointers all point to other statics
+unsafe impl Sync for wl_message {}
+unsafe impl Sync for wl_interface {}
+
+pub const WL_MARSHAL_FLAG_DESTROY: u32 = 1 << 0;
+
+crate::dl::library! {
+    pub struct Wayland {
+        "libwayland-client.so.0" {
+            fn wl_display_connect: unsafe extern "C" fn(*const c_char) -> *mut wl_display,
+            fn wl_display_disconnect: unsafe extern "C" fn(*mut wl_display),
+            fn wl_display_get_fd: unsafe extern "C" fn(*mut wl_display) -> c_int,
+            fn wl_display_roundtrip: unsafe extern "C" fn(*mut wl_display) -> c_int,
+            fn wl_display_dispatch_pending: unsafe extern "C" fn(*mut wl_display) -> c_int,
+            fn wl_display_prepare_read: unsafe extern "C" fn(*mut wl_display) -> c_int,
+            fn wl_display_read_events: unsafe extern "C" fn(*mut wl_display) -> c_int,
+            fn wl_display_cancel_read: unsafe extern "C" fn(*mut wl_display),
+            fn wl_display_flush: unsafe extern "C" fn(*mut wl_display) -> c_int,
+            fn wl_proxy_marshal_flags:
+                unsafe extern "C" fn(*mut wl_proxy, u32, *const wl_interface, u32, u32, ...) -> *mut wl_proxy,
+            fn wl_proxy_add_listener: unsafe extern "C" fn(*mut wl_proxy, *const c_void, *mut c_void) -> c_int,
+            fn wl_proxy_get_version: unsafe extern "C" fn(*mut wl_proxy) -> u32,
+            fn wl_proxy_destroy: unsafe extern "C" fn(*mut wl_proxy),
+            static wl_registry_interface: wl_interface,
+            static wl_compositor_interface: wl_interface,
+            static wl_surface_interface: wl_interface,
+            static wl_seat_interface: wl_interface,
+            static wl_pointer_interface: wl_interface,
+            static wl_keyboard_interface: wl_interface,
+            static wl_output_interface: wl_interface,
+            static wl_shm_interface: wl_interface,
+        }
+        "libwayland-cursor.so.0" {
+            fn wl_cursor_theme_load: unsafe extern "C" fn(*const c_char, c_int, *mut wl_proxy) -> *mut wl_cursor_theme,
+            fn wl_cursor_theme_destroy: unsafe extern "C" fn(*mut wl_cursor_theme),
+            fn wl_cursor_theme_get_cursor: unsafe extern "C" fn(*mut wl_cursor_theme, *const c_char) -> *mut wl_cursor,
+            fn wl_cursor_image_get_buffer: unsafe extern "C" fn(*mut wl_cursor_image) -> *mut wl_proxy,
+        }
+        "libwayland-egl.so.1" {
+            fn wl_egl_window_create: unsafe extern "C" fn(*mut wl_proxy, c_int, c_int) -> *mut wl_egl_window,
+            fn wl_egl_window_destroy: unsafe extern "C" fn(*mut wl_egl_window),
+            fn wl_egl_window_resize: unsafe extern "C" fn(*mut wl_egl_window, c_int, c_int, c_int, c_int),
+        }
+    }
+}
+
+static WAYLAND: OnceLock<Result<Wayland, String>> = OnceLock::new();
+
+/// Loads the Wayland libraries if that hasn't been tried yet.
+pub fn load() -> Result<&'static Wayland, String> {
+    let wayland = WAYLAND.get_or_init(|| {
+        let wayland = unsafe { Wayland::open()? };
+        // the xdg-shell messages that take core objects can only point to their interfaces once they're loaded
+        let core_interface = |interface: &'static wl_interface| interface as *const _ as *mut wl_interface;
+        XDG_WM_BASE_GET_XDG_SURFACE_TYPES[1].store(core_interface(wayland.wl_surface_interface), Ordering::Relaxed);
+        XDG_TOPLEVEL_SEAT_TYPES[0].store(core_interface(wayland.wl_seat_interface), Ordering::Relaxed);
+        XDG_TOPLEVEL_SET_FULLSCREEN_TYPES[0].store(core_interface(wayland.wl_output_interface), Ordering::Relaxed);
+        Ok(wayland)
+    });
+    wayland.as_ref().map_err(Clone::clone)
+}
+
+/// The loaded Wayland libraries. Only usable after a successful `load`, which `WindowImpl::new` does first.
+pub fn wl() -> &'static Wayland {
+    match WAYLAND.get() {
+        Some(Ok(wayland)) => wayland,
+        _ => panic!("Wayland libraries used before being loaded"),
+    }
+}
+
+// request opcodes, in the order they appear in the protocol xml
+pub const WL_DISPLAY_GET_REGISTRY: u32 = 1;
+pub const WL_REGISTRY_BIND: u32 = 0;
+pub const WL_COMPOSITOR_CREATE_SURFACE: u32 = 0;
+pub const WL_SURFACE_DESTROY: u32 = 0;
+pub const WL_SURFACE_ATTACH: u32 = 1;
+pub const WL_SURFACE_DAMAGE: u32 = 2;
+pub const WL_SURFACE_COMMIT: u32 = 6;
+pub const WL_SEAT_GET_POINTER: u32 = 0;
+pub const WL_SEAT_GET_KEYBOARD: u32 = 1;
+pub const WL_POINTER_SET_CURSOR: u32 = 0;
+pub const XDG_WM_BASE_DESTROY: u32 = 0;
+pub const XDG_WM_BASE_GET_XDG_SURFACE: u32 = 2;
+pub const XDG_WM_BASE_PONG: u32 = 3;
+pub const XDG_SURFACE_DESTROY: u32 = 0;
+pub const XDG_SURFACE_GET_TOPLEVEL: u32 = 1;
+pub const XDG_SURFACE_ACK_CONFIGURE: u32 = 4;
+pub const XDG_TOPLEVEL_DESTROY: u32 = 0;
+pub const XDG_TOPLEVEL_SET_TITLE: u32 = 2;
+pub const XDG_TOPLEVEL_SET_APP_ID: u32 = 3;
+pub const XDG_TOPLEVEL_SET_MAX_SIZE: u32 = 7;
+pub const XDG_TOPLEVEL_SET_MIN_SIZE: u32 = 8;
+pub const XDG_TOPLEVEL_SET_FULLSCREEN: u32 = 11;
+pub const XDG_TOPLEVEL_UNSET_FULLSCREEN: u32 = 12;
+
+pub const WL_SEAT_CAPABILITY_POINTER: u32 = 1;
+pub const WL_SEAT_CAPABILITY_KEYBOARD: u32 = 2;
+pub const WL_POINTER_BUTTON_STATE_PRESSED: u32 = 1;
+pub const WL_POINTER_AXIS_VERTICAL_SCROLL: u32 = 0;
+pub const WL_KEYBOARD_KEY_STATE_PRESSED: u32 = 1;
+pub const WL_OUTPUT_MODE_CURRENT: u32 = 1;
+
+// linux/input-event-codes.h
+pub const BTN_LEFT: u32 = 0x110;
+pub const BTN_RIGHT: u32 = 0x111;
+pub const BTN_MIDDLE: u32 = 0x112;
+
+#[repr(C)]
+pub struct wl_registry_listener {
+    pub global: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, *const c_char, u32),
+    pub global_remove: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32),
+}
+
+#[repr(C)]
+pub struct wl_seat_listener {
+    pub capabilities: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32),
+    pub name: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, *const c_char),
+}
+
+#[repr(C)]
+pub struct wl_pointer_listener {
+    pub enter: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, *mut wl_proxy, wl_fixed_t, wl_fixed_t),
+    pub leave: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, *mut wl_proxy),
+    pub motion: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, wl_fixed_t, wl_fixed_t),
+    pub button: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, u32, u32, u32),
+    pub axis: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, u32, wl_fixed_t),
+}
+
+#[repr(C)]
+pub struct wl_keyboard_listener {
+    pub keymap: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, i32, u32),
+    pub enter: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, *mut wl_proxy, *mut wl_array),
+    pub leave: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, *mut wl_proxy),
+    pub key: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, u32, u32, u32),
+    pub modifiers: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, u32, u32, u32, u32),
+}
+
+#[repr(C)]
+pub struct wl_output_listener {
+    pub geometry:
+        unsafe extern "C" fn(*mut c_void, *mut wl_proxy, i32, i32, i32, i32, i32, *const c_char, *const c_char, i32),
+    pub mode: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32, i32, i32, i32),
+    pub done: unsafe extern "C" fn(*mut c_void, *mut wl_proxy),
+    pub scale: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, i32),
+}
+
+#[repr(C)]
+pub struct xdg_wm_base_listener {
+    pub ping: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32),
+}
+
+#[repr(C)]
+pub struct xdg_surface_listener {
+    pub configure: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, u32),
+}
+
+#[repr(C)]
+pub struct xdg_toplevel_listener {
+    pub configure: unsafe extern "C" fn(*mut c_void, *mut wl_proxy, i32, i32, *mut wl_array),
+    pub close: unsafe extern "C" fn(*mut c_void, *mut wl_proxy),
+}
+
+macro_rules! c_str {
+    ($s: expr) => {
+        concat!($s, "\0").as_ptr() as *const c_char
+    };
+}
+
+macro_rules! message {
+    ($name: expr, $signature: expr, $types: expr) => {
+        wl_message { name: c_str!($name), signature: c_str!($signature), types: $types.as_ptr().cast() }
+    };
+}
+
+// xdg-shell, stable version 1 (what wayland-scanner would generate from xdg-shell.xml)
+// xdg_positioner and xdg_popup never get used, so they're left as null in the type lists,
+// and the ones pointing to core interfaces are filled in by load() (AtomicPtr is laid out like Option<&T>)
+
+static NULL_TYPES: [Option<&wl_interface>; 4] = [None; 4];
+static XDG_WM_BASE_GET_XDG_SURFACE_TYPES: [AtomicPtr<wl_interface>; 2] =
+    [AtomicPtr::new(&xdg_surface_interface as *const _ as *mut _), AtomicPtr::new(ptr::null_mut())];
+static XDG_SURFACE_GET_TOPLEVEL_TYPES: [Option<&wl_interface>; 1] = [Some(&xdg_toplevel_interface)];
+static XDG_TOPLEVEL_SET_PARENT_TYPES: [Option<&wl_interface>; 1] = [Some(&xdg_toplevel_interface)];
+static XDG_TOPLEVEL_SEAT_TYPES: [AtomicPtr<wl_interface>; 3] =
+    [AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut())];
+static XDG_TOPLEVEL_SET_FULLSCREEN_TYPES: [AtomicPtr<wl_interface>; 1] = [AtomicPtr::new(ptr::null_mut())];
+
+static XDG_WM_BASE_REQUESTS: [wl_message; 4] = [
+    message!("destroy", "", NULL_TYPES),
+    message!("create_positioner", "n", NULL_TYPES),
+    message!("get_xdg_surface", "no", XDG_WM_BASE_GET_XDG_SURFACE_TYPES),
+    message!("pong", "u", NULL_TYPES),
+];
+static XDG_WM_BASE_EVENTS: [wl_message; 1] = [message!("ping", "u", NULL_TYPES)];
+pub static xdg_wm_base_interface: wl_interface = wl_interface {
+    name: c_str!("xdg_wm_base"),
+    version: 1,
+    method_count: XDG_WM_BASE_REQUESTS.len() as _,
+    methods: XDG_WM_BASE_REQUESTS.as_ptr(),
+    event_count: XDG_WM_BASE_EVENTS.len() as _,
+    events: XDG_WM_BASE_EVENTS.as_ptr(),
+};
+
+static XDG_SURFACE_REQUESTS: [wl_message; 5] = [
+    message!("destroy", "", NULL_TYPES),
+    message!("get_toplevel", "n", XDG_SURFACE_GET_TOPLEVEL_TYPES),
+    message!("get_popup", "n?oo", NULL_TYPES),
+    message!("set_window_geometry", "iiii", NULL_TYPES),
+    message!("ack_configure", "u", NULL_TYPES),
+];
+static XDG_SURFACE_EVENTS: [wl_message; 1] = [message!("configure", "u", NULL_TYPES)];
+pub static xdg_surface_interface: wl_interface = wl_interface {
+    name: c_str!("xdg_surface"),
+    version: 1,
+    method_count: XDG_SURFACE_REQUESTS.len() as _,
+    methods: XDG_SURFACE_REQUESTS.as_ptr(),
+    event_count: XDG_SURFACE_EVENTS.len() as _,
+    events: XDG_SURFACE_EVENTS.as_ptr(),
+};
+
+static XDG_TOPLEVEL_REQUESTS: [wl_message; 14] = [
+    message!("destroy", "", NULL_TYPES),
+    message!("set_parent", "?o", XDG_TOPLEVEL_SET_PARENT_TYPES),
+    message!("set_title", "s", NULL_TYPES),
+    message!("set_app_id", "s", NULL_TYPES),
+    message!("show_window_menu", "ouii", XDG_TOPLEVEL_SEAT_TYPES),
+    message!("move", "ou", XDG_TOPLEVEL_SEAT_TYPES),
+    message!("resize", "ouu", XDG_TOPLEVEL_SEAT_TYPES),
+    message!("set_max_size", "ii", NULL_TYPES),
+    message!("set_min_size", "ii", NULL_TYPES),
+    message!("set_maximized", "", NULL_TYPES),
+    message!("unset_maximized", "", NULL_TYPES),
+    message!("set_fullscreen", "?o", XDG_TOPLEVEL_SET_FULLSCREEN_TYPES),
+    message!("unset_fullscreen", "", NULL_TYPES),
+    message!("set_minimized", "", NULL_TYPES),
+];
+static XDG_TOPLEVEL_EVENTS: [wl_message; 2] =
+    [message!("configure", "iia", NULL_TYPES), message!("close", "", NULL_TYPES)];
+pub static xdg_toplevel_interface: wl_interface = wl_interface {
+    name: c_str!("xdg_toplevel"),
+    version: 1,
+    method_count: XDG_TOPLEVEL_REQUESTS.len() as _,
+    methods: XDG_TOPLEVEL_REQUESTS.as_ptr(),
+    event_count: XDG_TOPLEVEL_EVENTS.len() as _,
+    events: XDG_TOPLEVEL_EVENTS.as_ptr(),
+};
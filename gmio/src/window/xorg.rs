@@ -28,7 +28,7 @@ impl WindowImpl {
             let screen_id = xlib::XDefaultScreen(display);
             let root = xlib::XRootWindow(display, screen_id);
 
-            let mut attributes: xlib::XSetWindowAttributes = std::mem::MaybeUninit::uninit().assume_init();
+            let mut attributes: xlib::XSetWindowAttributes = std::mem::zeroed();
             attributes.background_pixel = xlib::XWhitePixel(display, screen_id);
 
             let window_id = xlib::XCreateWindow(
@@ -91,6 +91,28 @@ impl WindowTrait for WindowImpl {
         }
     }
 
+    fn center(&mut self) {
+        let (width, height) = self.inner_size;
+        let x = (self.display_width() - width as i32) / 2;
+        let y = (self.display_height() - height as i32) / 2;
+        self.set_pos(x, y);
+    }
+
+    fn get_pos(&self) -> (i32, i32) {
+        let (mut x, mut y, mut child) = (0, 0, 0);
+        unsafe {
+            let root = xlib::XRootWindow(self.display, self.screen_id);
+            xlib::XTranslateCoordinates(self.display, self.window_id, root, 0, 0, &mut x, &mut y, &mut child);
+        }
+        (x, y)
+    }
+
+    fn set_pos(&mut self, x: i32, y: i32) {
+        unsafe {
+            xlib::XMoveWindow(self.display, self.window_id, x, y);
+        }
+    }
+
     fn get_cursor(&self) -> Cursor {
         todo!()
     }
@@ -131,15 +153,35 @@ impl WindowTrait for WindowImpl {
         }
     }
 
+    fn show_context_menu(&mut self, _options: &[(String, usize)]) {
+        // TODO
+    }
+
     fn window_handle(&self) -> usize {
         todo!()
     }
 
     fn display_width(&self) -> i32 {
-        unsafe { XDisplayWidth(self.display, self.window_id) }
+        unsafe { XDisplayWidth(self.display, self.screen_id) }
     }
 
     fn display_height(&self) -> i32 {
-        unsafe { XDisplayHeight(self.display, self.window_id) }
+        unsafe { XDisplayHeight(self.display, self.screen_id) }
+    }
+
+    fn display_frequency(&self) -> i32 {
+        60 // TODO: needs XRandR
+    }
+
+    fn display_colour(&self) -> i32 {
+        unsafe { xlib::XDefaultDepth(self.display, self.screen_id) }
+    }
+
+    fn disk_free(&self, _drive: Option<char>) -> Option<u64> {
+        None // TODO
+    }
+
+    fn disk_size(&self, _drive: Option<char>) -> Option<u64> {
+        None // TODO
     }
 }
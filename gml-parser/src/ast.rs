@@ -1,19 +1,26 @@
 use crate::{
-    lexer::Lexer,
+    lexer::{Lexer, Span},
     token::{Keyword, Operator, Separator, Token},
 };
 
 use std::{
     error, fmt,
-    iter::{IntoIterator, Peekable},
+    iter::IntoIterator,
     ops::{Deref, DerefMut},
 };
 
 #[derive(Debug, PartialEq)]
 pub struct AST<'a>(Vec<Expr<'a>>);
 
+/// An expression along with where it came from in the source code.
+#[derive(Debug)]
+pub struct Expr<'a> {
+    pub kind: ExprKind<'a>,
+    pub span: Span,
+}
+
 #[derive(Debug, PartialEq)]
-pub enum Expr<'a> {
+pub enum ExprKind<'a> {
     LiteralIdentifier(&'a [u8]),
     LiteralReal(f64),
     LiteralString(&'a [u8]),
@@ -120,35 +127,56 @@ pub struct WhileExpr<'a> {
 #[derive(Debug)]
 pub struct Error {
     pub message: String,
+    pub span: Span,
 }
 
 impl Error {
-    pub fn new(message: String) -> Self {
-        Error { message }
+    pub fn new(message: String, span: Span) -> Self {
+        Error { message, span }
+    }
+}
+
+// Where an expression is doesn't change what it is, so spans are left out of comparisons.
+impl<'a> PartialEq for Expr<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+// Mostly for building expressions that didn't come from any source code.
+impl<'a> From<ExprKind<'a>> for Expr<'a> {
+    fn from(kind: ExprKind<'a>) -> Self {
+        Expr { kind, span: Span::default() }
     }
 }
 
 impl<'a> fmt::Display for Expr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl<'a> fmt::Display for ExprKind<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Expr::LiteralIdentifier(id) => write!(f, "{}", String::from_utf8_lossy(id)),
-            Expr::LiteralReal(r) => write!(f, "{}", r),
-            Expr::LiteralString(s) => write!(f, "\"{}\"", String::from_utf8_lossy(s)),
+            ExprKind::LiteralIdentifier(id) => write!(f, "{}", String::from_utf8_lossy(id)),
+            ExprKind::LiteralReal(r) => write!(f, "{}", r),
+            ExprKind::LiteralString(s) => write!(f, "\"{}\"", String::from_utf8_lossy(s)),
 
-            Expr::Unary(unary) => write!(f, "({} {})", unary.op, unary.child),
-            Expr::Binary(binary) => write!(f, "({} {} {})", binary.op, binary.left, binary.right),
+            ExprKind::Unary(unary) => write!(f, "({} {})", unary.op, unary.child),
+            ExprKind::Binary(binary) => write!(f, "({} {} {})", binary.op, binary.left, binary.right),
 
-            Expr::DoUntil(dountil) => write!(f, "(do {} until {})", dountil.body, dountil.cond),
-            Expr::For(for_ex) => {
+            ExprKind::DoUntil(dountil) => write!(f, "(do {} until {})", dountil.body, dountil.cond),
+            ExprKind::For(for_ex) => {
                 write!(f, "(for ({}, {}, {}) {})", for_ex.start, for_ex.cond, for_ex.step, for_ex.body)
             },
-            Expr::Function(call) => write!(
+            ExprKind::Function(call) => write!(
                 f,
                 "(@{} {})",
                 String::from_utf8_lossy(call.name),
                 call.params.iter().fold(String::new(), |acc, fnname| acc + &format!("{} ", fnname)).trim_end()
             ),
-            Expr::Group(group) => write!(
+            ExprKind::Group(group) => write!(
                 f,
                 "<{}>",
                 group
@@ -156,13 +184,13 @@ impl<'a> fmt::Display for Expr<'a> {
                     .fold(String::new(), |acc, expr| acc + &format!("{}, ", expr))
                     .trim_end_matches(|ch| ch == ' ' || ch == ',')
             ),
-            Expr::If(if_ex) => match if_ex.else_body {
+            ExprKind::If(if_ex) => match if_ex.else_body {
                 Some(ref els) => write!(f, "(if {} {} {})", if_ex.cond, if_ex.body, els),
                 None => write!(f, "(if {} {})", if_ex.cond, if_ex.body),
             },
-            Expr::Repeat(repeat) => write!(f, "(repeat {} {})", repeat.count, repeat.body),
-            Expr::Switch(switch) => write!(f, "(switch {} {})", switch.input, switch.body),
-            Expr::Var(var) => write!(
+            ExprKind::Repeat(repeat) => write!(f, "(repeat {} {})", repeat.count, repeat.body),
+            ExprKind::Switch(switch) => write!(f, "(switch {} {})", switch.input, switch.body),
+            ExprKind::Var(var) => write!(
                 f,
                 "(var {})",
                 var.vars
@@ -170,7 +198,7 @@ impl<'a> fmt::Display for Expr<'a> {
                     .fold(String::new(), |acc, varname| acc + &format!("{} ", String::from_utf8_lossy(varname)))
                     .trim_end()
             ),
-            Expr::GlobalVar(var) => write!(
+            ExprKind::GlobalVar(var) => write!(
                 f,
                 "(globalvar {})",
                 var.vars
@@ -178,16 +206,16 @@ impl<'a> fmt::Display for Expr<'a> {
                     .fold(String::new(), |acc, varname| acc + &format!("{} ", String::from_utf8_lossy(varname)))
                     .trim_end()
             ),
-            Expr::With(with) => write!(f, "(with {} {})", with.target, with.body),
-            Expr::While(while_ex) => write!(f, "(while {} {})", while_ex.cond, while_ex.body),
+            ExprKind::With(with) => write!(f, "(with {} {})", with.target, with.body),
+            ExprKind::While(while_ex) => write!(f, "(while {} {})", while_ex.cond, while_ex.body),
 
-            Expr::Case(e) => write!(f, "(case {})", e),
-            Expr::Default => write!(f, "(default)"),
+            ExprKind::Case(e) => write!(f, "(case {})", e),
+            ExprKind::Default => write!(f, "(default)"),
 
-            Expr::Continue => write!(f, "(continue)"),
-            Expr::Break => write!(f, "(break)"),
-            Expr::Exit => write!(f, "(exit)"),
-            Expr::Return(e) => write!(f, "(return {})", e),
+            ExprKind::Continue => write!(f, "(continue)"),
+            ExprKind::Break => write!(f, "(break)"),
+            ExprKind::Exit => write!(f, "(exit)"),
+            ExprKind::Return(e) => write!(f, "(return {})", e),
         }
     }
 }
@@ -195,23 +223,23 @@ impl<'a> fmt::Display for Expr<'a> {
 impl error::Error for Error {}
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.message)
+        write!(f, "{} at line {}, column {}", self.message, self.span.line, self.span.column)
     }
 }
 
 // TODO? This is not the prettiest.
 macro_rules! expect_token {
-    ( $token: expr, $($content: tt)* ) => ({
-        match $token {
+    ( $lex: expr, $($content: tt)* ) => ({
+        match $lex.next() {
             Some(Token::$($content)*) => {},
             Some(t) => {
-                return Err(Error::new(format!(
+                return Err($lex.error(format!(
                     "Unexpected token {:?}; `{}` expected",
                     t, Token::$($content)*,
                 )));
             }
             None => {
-                return Err(Error::new(format!(
+                return Err($lex.error(format!(
                     "Unexpected EOF; `{}` expected",
                     Token::$($content)*,
                 )));
@@ -220,6 +248,63 @@ macro_rules! expect_token {
     });
 }
 
+/// Tokens from the lexer with one token of lookahead, keeping track of where they all were.
+#[derive(Clone)]
+struct Tokens<'a> {
+    lexer: Lexer<'a>,
+    peeked: Option<Option<(Token<'a>, Span)>>,
+
+    /// Where the last token taken out was, or the end of the source code if there weren't any more.
+    last: Span,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(source: &'a [u8]) -> Self {
+        Tokens { lexer: Lexer::new(source), peeked: None, last: Span::default() }
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let next = match self.peeked.take() {
+            Some(next) => next,
+            None => self.lexer.next_spanned(),
+        };
+        match next {
+            Some((token, span)) => {
+                self.last = span;
+                Some(token)
+            },
+            None => {
+                self.last = self.lexer.end_span();
+                None
+            },
+        }
+    }
+
+    fn peek(&mut self) -> Option<&Token<'a>> {
+        let lexer = &mut self.lexer;
+        self.peeked.get_or_insert_with(|| lexer.next_spanned()).as_ref().map(|(token, _)| token)
+    }
+
+    /// Where the next token is, or the end of the source code if there isn't one.
+    fn peek_span(&mut self) -> Span {
+        self.peek();
+        match self.peeked {
+            Some(Some((_, span))) => span,
+            _ => self.lexer.end_span(),
+        }
+    }
+
+    /// Makes an expression going from the given span up to the last token taken out.
+    fn node(&self, start: Span, kind: ExprKind<'a>) -> Expr<'a> {
+        Expr { kind, span: start.to(self.last) }
+    }
+
+    /// Makes an error pointing at the last token taken out.
+    fn error(&self, message: String) -> Error {
+        Error::new(message, self.last)
+    }
+}
+
 impl<'a> Default for AST<'a> {
     fn default() -> Self {
         AST(Vec::new())
@@ -251,7 +336,7 @@ impl<'a> IntoIterator for AST<'a> {
 
 impl<'a> AST<'a> {
     pub fn new(source: &'a [u8]) -> Result<Self, Error> {
-        let mut lex = Lexer::new(source).peekable();
+        let mut lex = Tokens::new(source);
         let mut expressions = Vec::new();
 
         loop {
@@ -267,11 +352,15 @@ impl<'a> AST<'a> {
     }
 
     pub fn expression(source: &'a [u8]) -> Result<Expr<'a>, Error> {
-        let mut lex = Lexer::new(source).peekable();
-        if lex.peek().is_some() { AST::read_binary_tree(&mut lex, None, false) } else { Ok(Expr::LiteralReal(0.0)) }
+        let mut lex = Tokens::new(source);
+        if lex.peek().is_some() {
+            AST::read_binary_tree(&mut lex, None, false)
+        } else {
+            Ok(ExprKind::LiteralReal(0.0).into())
+        }
     }
 
-    fn read_line(lex: &mut Peekable<Lexer<'a>>) -> Result<Option<Expr<'a>>, Error> {
+    fn read_line(lex: &mut Tokens<'a>) -> Result<Option<Expr<'a>>, Error> {
         let token = loop {
             match lex.next() {
                 Some(Token::Separator(Separator::Semicolon)) => continue,
@@ -279,6 +368,7 @@ impl<'a> AST<'a> {
                 None => return Ok(None), // EOF
             }
         };
+        let start = lex.last;
 
         // Use token type to determine what logic we should apply here
         let ret = match token {
@@ -328,17 +418,21 @@ impl<'a> AST<'a> {
                             }
 
                             match key {
-                                Keyword::Var => Ok(Some(Expr::Var(Box::new(VarExpr { vars })))),
-                                Keyword::GlobalVar => Ok(Some(Expr::GlobalVar(Box::new(GlobalVarExpr { vars })))),
+                                Keyword::Var => Ok(Some(lex.node(start, ExprKind::Var(Box::new(VarExpr { vars }))))),
+                                Keyword::GlobalVar => {
+                                    Ok(Some(lex.node(start, ExprKind::GlobalVar(Box::new(GlobalVarExpr { vars })))))
+                                },
                                 _ => unreachable!(),
                             }
                         } else {
                             // This doesn't do anything in GML. We could probably make it a NOP.
                             match key {
-                                Keyword::Var => Ok(Some(Expr::Var(Box::new(VarExpr { vars: vec![] })))),
-                                Keyword::GlobalVar => {
-                                    Ok(Some(Expr::GlobalVar(Box::new(GlobalVarExpr { vars: vec![] }))))
+                                Keyword::Var => {
+                                    Ok(Some(lex.node(start, ExprKind::Var(Box::new(VarExpr { vars: vec![] })))))
                                 },
+                                Keyword::GlobalVar => Ok(Some(
+                                    lex.node(start, ExprKind::GlobalVar(Box::new(GlobalVarExpr { vars: vec![] }))),
+                                )),
                                 _ => unreachable!(),
                             }
                         }
@@ -346,10 +440,10 @@ impl<'a> AST<'a> {
 
                     Keyword::Do => {
                         let body = AST::read_group(lex)?
-                            .ok_or_else(|| Error::new("Unexpected EOF after 'do' keyword".to_string()))?;
-                        expect_token!(lex.next(), Keyword(Keyword::Until));
+                            .ok_or_else(|| lex.error("Unexpected EOF after 'do' keyword".to_string()))?;
+                        expect_token!(lex, Keyword(Keyword::Until));
                         let cond = AST::read_binary_tree(lex, None, false)?;
-                        Ok(Some(Expr::DoUntil(Box::new(DoUntilExpr { cond, body }))))
+                        Ok(Some(lex.node(start, ExprKind::DoUntil(Box::new(DoUntilExpr { cond, body })))))
                     },
 
                     Keyword::If => {
@@ -358,23 +452,23 @@ impl<'a> AST<'a> {
                             lex.next();
                         }
                         let body = AST::read_group(lex)?
-                            .ok_or_else(|| Error::new("Unexpected EOF after 'if' condition".to_string()))?;
+                            .ok_or_else(|| lex.error("Unexpected EOF after 'if' condition".to_string()))?;
                         let else_body = if lex.peek() == Some(&Token::Keyword(Keyword::Else)) {
                             lex.next(); // consume 'else'
                             Some(
                                 AST::read_group(lex)?
-                                    .ok_or_else(|| Error::new("Unexpected EOF after 'else' keyword".to_string()))?,
+                                    .ok_or_else(|| lex.error("Unexpected EOF after 'else' keyword".to_string()))?,
                             )
                         } else {
                             None
                         };
-                        Ok(Some(Expr::If(Box::new(IfExpr { cond, body, else_body }))))
+                        Ok(Some(lex.node(start, ExprKind::If(Box::new(IfExpr { cond, body, else_body })))))
                     },
 
                     Keyword::For => {
-                        expect_token!(lex.next(), Separator(Separator::ParenLeft));
-                        let start = AST::read_line(lex)?
-                            .ok_or_else(|| Error::new("Unexpected EOF during 'for' params".to_string()))?;
+                        expect_token!(lex, Separator(Separator::ParenLeft));
+                        let start_expr = AST::read_line(lex)?
+                            .ok_or_else(|| lex.error("Unexpected EOF during 'for' params".to_string()))?;
                         if lex.peek() == Some(&Token::Separator(Separator::Semicolon)) {
                             lex.next();
                         }
@@ -383,35 +477,36 @@ impl<'a> AST<'a> {
                             lex.next();
                         }
                         let step = AST::read_line(lex)?
-                            .ok_or_else(|| Error::new("Unexpected EOF during 'for' params".to_string()))?;
+                            .ok_or_else(|| lex.error("Unexpected EOF during 'for' params".to_string()))?;
                         while lex.peek() == Some(&Token::Separator(Separator::Semicolon)) {
                             lex.next();
                         }
-                        expect_token!(lex.next(), Separator(Separator::ParenRight));
+                        expect_token!(lex, Separator(Separator::ParenRight));
                         let body = AST::read_group(lex)?
-                            .ok_or_else(|| Error::new("Unexpected EOF after 'for' params".to_string()))?;
-                        Ok(Some(Expr::For(Box::new(ForExpr { start, cond, step, body }))))
+                            .ok_or_else(|| lex.error("Unexpected EOF after 'for' params".to_string()))?;
+                        let for_expr = ForExpr { start: start_expr, cond, step, body };
+                        Ok(Some(lex.node(start, ExprKind::For(Box::new(for_expr)))))
                     },
 
                     Keyword::Repeat => {
                         let count = AST::read_binary_tree(lex, None, false)?;
                         let body = AST::read_group(lex)?
-                            .ok_or_else(|| Error::new("Unexpected EOF after 'repeat' condition".to_string()))?;
-                        Ok(Some(Expr::Repeat(Box::new(RepeatExpr { count, body }))))
+                            .ok_or_else(|| lex.error("Unexpected EOF after 'repeat' condition".to_string()))?;
+                        Ok(Some(lex.node(start, ExprKind::Repeat(Box::new(RepeatExpr { count, body })))))
                     },
 
                     Keyword::Switch => {
                         let input = AST::read_binary_tree(lex, None, false)?;
                         let body = AST::read_line(lex)?
-                            .ok_or_else(|| Error::new("Unexpected EOF after 'switch' condition".to_string()))?;
-                        Ok(Some(Expr::Switch(Box::new(SwitchExpr { input, body }))))
+                            .ok_or_else(|| lex.error("Unexpected EOF after 'switch' condition".to_string()))?;
+                        Ok(Some(lex.node(start, ExprKind::Switch(Box::new(SwitchExpr { input, body })))))
                     },
 
                     Keyword::With => {
                         let target = AST::read_binary_tree(lex, None, false)?;
                         let body = AST::read_group(lex)?
-                            .ok_or_else(|| Error::new("Unexpected EOF after 'with' condition".to_string()))?;
-                        Ok(Some(Expr::With(Box::new(WithExpr { target, body }))))
+                            .ok_or_else(|| lex.error("Unexpected EOF after 'with' condition".to_string()))?;
+                        Ok(Some(lex.node(start, ExprKind::With(Box::new(WithExpr { target, body })))))
                     },
 
                     Keyword::While => {
@@ -420,33 +515,33 @@ impl<'a> AST<'a> {
                             lex.next();
                         }
                         let body = AST::read_group(lex)?
-                            .ok_or_else(|| Error::new("Unexpected EOF after 'while' condition".to_string()))?;
-                        Ok(Some(Expr::While(Box::new(WhileExpr { cond, body }))))
+                            .ok_or_else(|| lex.error("Unexpected EOF after 'while' condition".to_string()))?;
+                        Ok(Some(lex.node(start, ExprKind::While(Box::new(WhileExpr { cond, body })))))
                     },
 
                     Keyword::Case => {
                         let expr = AST::read_binary_tree(lex, None, false)?;
-                        expect_token!(lex.next(), Separator(Separator::Colon));
-                        Ok(Some(Expr::Case(Box::new(expr))))
+                        expect_token!(lex, Separator(Separator::Colon));
+                        Ok(Some(lex.node(start, ExprKind::Case(Box::new(expr)))))
                     },
 
                     Keyword::Default => {
-                        expect_token!(lex.next(), Separator(Separator::Colon));
-                        Ok(Some(Expr::Default))
+                        expect_token!(lex, Separator(Separator::Colon));
+                        Ok(Some(lex.node(start, ExprKind::Default)))
                     },
 
-                    Keyword::Break => Ok(Some(Expr::Break)),
+                    Keyword::Break => Ok(Some(lex.node(start, ExprKind::Break))),
 
-                    Keyword::Continue => Ok(Some(Expr::Continue)),
+                    Keyword::Continue => Ok(Some(lex.node(start, ExprKind::Continue))),
 
-                    Keyword::Exit => Ok(Some(Expr::Exit)),
+                    Keyword::Exit => Ok(Some(lex.node(start, ExprKind::Exit))),
 
                     Keyword::Return => {
                         let val = AST::read_binary_tree(lex, None, false)?;
-                        Ok(Some(Expr::Return(Box::new(val))))
+                        Ok(Some(lex.node(start, ExprKind::Return(Box::new(val)))))
                     },
 
-                    _ => return Err(Error::new(format!("Invalid Keyword at beginning of expression: {:?}", key))),
+                    _ => return Err(lex.error(format!("Invalid Keyword at beginning of expression: {:?}", key))),
                 }
             },
 
@@ -454,16 +549,16 @@ impl<'a> AST<'a> {
                 // An expression starting with an identifier may be either an assignment or script/function.
                 // This is determined by what type of token immediately follows it.
                 let next_token = match lex.peek() {
-                    Some(t) => t,
+                    Some(t) => *t,
                     None => {
-                        return Err(Error::new(format!("Stray identifier at EOF: {:?}", String::from_utf8_lossy(id))))
+                        return Err(lex.error(format!("Stray identifier at EOF: {:?}", String::from_utf8_lossy(id))))
                     },
                 };
                 match next_token {
                     Token::Separator(ref sep) if *sep == Separator::ParenLeft => {
-                        Ok(Some(AST::read_function_call(lex, id)?))
+                        Ok(Some(AST::read_function_call(lex, id, start)?))
                     },
-                    _ => Ok(Some(AST::read_binary_tree(lex, Some(token), true)?)),
+                    _ => Ok(Some(AST::read_binary_tree(lex, Some((token, start)), true)?)),
                 }
             },
 
@@ -476,11 +571,11 @@ impl<'a> AST<'a> {
                             match lex.peek() {
                                 Some(Token::Separator(Separator::BraceRight)) => {
                                     lex.next();
-                                    break Ok(Some(Expr::Group(inner_expressions)))
+                                    break Ok(Some(lex.node(start, ExprKind::Group(inner_expressions))))
                                 },
                                 _ => match AST::read_line(lex) {
                                     Ok(Some(e)) => inner_expressions.push(e),
-                                    Ok(None) => break Err(Error::new("Unclosed brace at EOF".to_string())),
+                                    Ok(None) => break Err(lex.error("Unclosed brace at EOF".to_string())),
                                     Err(e) => break Err(e),
                                 },
                             }
@@ -489,17 +584,18 @@ impl<'a> AST<'a> {
 
                     // An assignment may start with an open-parenthesis, eg: (1).x = 400;
                     Separator::ParenLeft => {
-                        let binary_tree =
-                            AST::read_binary_tree(lex, Some(Token::Separator(Separator::ParenLeft)), true)?;
+                        let binary_tree = AST::read_binary_tree(lex, Some((token, start)), true)?;
                         Ok(Some(binary_tree))
                     },
 
                     // Default
-                    _ => return Err(Error::new(format!("Invalid Separator at beginning of expression: {:?}", sep))),
+                    _ => {
+                        return Err(lex.error(format!("Invalid Separator at beginning of expression: {:?}", sep)))
+                    },
                 }
             },
 
-            _ => return Err(Error::new(format!("Invalid token at beginning of expression: {:?}", token))),
+            _ => return Err(lex.error(format!("Invalid token at beginning of expression: {:?}", token))),
         };
 
         // skip over trailing semicolons
@@ -510,13 +606,14 @@ impl<'a> AST<'a> {
         ret
     }
 
-    fn read_group(lex: &mut Peekable<Lexer<'a>>) -> Result<Option<Expr<'a>>, Error> {
+    fn read_group(lex: &mut Tokens<'a>) -> Result<Option<Expr<'a>>, Error> {
         match lex.peek() {
             Some(Token::Separator(Separator::Semicolon)) => {
+                let start = lex.peek_span();
                 while lex.peek() == Some(&Token::Separator(Separator::Semicolon)) {
                     lex.next();
                 }
-                Ok(Some(Expr::Group(vec![])))
+                Ok(Some(lex.node(start, ExprKind::Group(vec![]))))
             },
             Some(_) => Self::read_line(lex),
             None => Ok(None),
@@ -524,35 +621,35 @@ impl<'a> AST<'a> {
     }
 
     fn read_binary_tree(
-        lex: &mut Peekable<Lexer<'a>>,
-        first_token: Option<Token<'a>>, // Sometimes we've already parsed the first token, so it should be put here.
-        expect_assignment: bool,        // Do we expect the first op to be an assignment?
+        lex: &mut Tokens<'a>,
+        first_token: Option<(Token<'a>, Span)>, // Sometimes we've already parsed the first token, so it goes here.
+        expect_assignment: bool,                // Do we expect the first op to be an assignment?
     ) -> Result<Expr<'a>, Error> {
         let (val, op) = AST::read_binary_tree_recursive(lex, first_token, expect_assignment, 0)?;
         if let Some(stray_op) = op {
-            Err(Error::new(format!("read_binary_tree has stray operator: {:?}", stray_op)))
+            Err(lex.error(format!("read_binary_tree has stray operator: {:?}", stray_op)))
         } else {
             Ok(val)
         }
     }
 
     fn read_binary_tree_recursive(
-        lex: &mut Peekable<Lexer<'a>>,
-        first_token: Option<Token<'a>>, // Sometimes we've already parsed the first token, so it should be put here.
-        expect_assignment: bool,        // Do we expect the first op to be an assignment?
-        lowest_prec: u8,                // We are not allowed to go below this operator precedence in this tree.
-                                        // If we do, we'll return the next op.
+        lex: &mut Tokens<'a>,
+        first_token: Option<(Token<'a>, Span)>, // Sometimes we've already parsed the first token, so it goes here.
+        expect_assignment: bool,                // Do we expect the first op to be an assignment?
+        lowest_prec: u8,                        // We are not allowed to go below this operator precedence in this tree.
+                                                // If we do, we'll return the next op.
     ) -> Result<(Expr<'a>, Option<Operator>), Error> {
         // Get the first expression before any operators
         let mut lhs = AST::read_btree_expression(lex, first_token)?;
 
         // Check if the next token is an operator
-        let next_token = lex.peek();
+        let next_token = lex.peek().copied();
         match next_token {
             Some(Token::Operator(op)) => {
                 // '=' can be either an assignment or equality check (==) in GML.
                 // So if we're not expecting an assignment operator, it should be seen as a comparator instead.
-                let mut op = if (op == &Operator::Assign) && (!expect_assignment) { Operator::Equal } else { *op };
+                let mut op = if (op == Operator::Assign) && (!expect_assignment) { Operator::Equal } else { op };
 
                 // Consume operator
                 lex.next();
@@ -565,7 +662,7 @@ impl<'a> AST<'a> {
                     if let Some(precedence) = AST::get_op_precedence(&op) {
                         // this op is invalid if an assignment is expected
                         if expect_assignment {
-                            break Err(Error::new(format!("Invalid operator {:?} found, expected assignment", op)))
+                            break Err(lex.error(format!("Invalid operator {:?} found, expected assignment", op)))
                         }
                         // If this op has lower prec than we're allowed to read, we have to return it here.
                         if precedence < lowest_prec {
@@ -580,39 +677,36 @@ impl<'a> AST<'a> {
                             if let Some(next_prec) = AST::get_op_precedence(&next_op) {
                                 if next_prec < lowest_prec {
                                     // This next op is lower than we're allowed to go, so we must return it
-                                    break Ok((
-                                        Expr::Binary(Box::new(BinaryExpr { op, left: lhs, right: rhs })),
-                                        Some(next_op),
-                                    ))
+                                    break Ok((AST::binary(op, lhs, rhs), Some(next_op)))
                                 } else {
                                     // Update LHS by sticking RHS onto it,
                                     // set op to the new operator, and go round again.
-                                    lhs = Expr::Binary(Box::new(BinaryExpr { op, left: lhs, right: rhs }));
+                                    lhs = AST::binary(op, lhs, rhs);
                                     op = next_op;
                                 }
                             } else {
                                 // Precedence would already have been checked by the returning function.
-                                break Err(Error::new(format!(
+                                break Err(lex.error(format!(
                                     "read_binary_tree_recursive returned invalid operator: {}",
                                     next_op
                                 )))
                             }
                         } else {
                             // No more operators so let's put our lhs and rhs together.
-                            break Ok((Expr::Binary(Box::new(BinaryExpr { op, left: lhs, right: rhs })), None))
+                            break Ok((AST::binary(op, lhs, rhs), None))
                         }
                     } else {
                         // this op is invalid if assignment not expected, OR if it's a unary operator
                         // (those have no precedence so they pass the previous test.)
                         if !expect_assignment || op == Operator::Not || op == Operator::Complement {
-                            break Err(Error::new(format!("Invalid operator {:?} found, expected evaluable", op)))
+                            break Err(lex.error(format!("Invalid operator {:?} found, expected evaluable", op)))
                         } else {
                             // No need to do precedence on an assignment, so just grab RHS and return
                             let (rhs, stray_op) = AST::read_binary_tree_recursive(lex, None, false, lowest_prec)?;
                             break if let Some(op) = stray_op {
-                                Err(Error::new(format!("Stray operator {:?} in expression", op)))
+                                Err(lex.error(format!("Stray operator {:?} in expression", op)))
                             } else {
-                                Ok((Expr::Binary(Box::new(BinaryExpr { op, left: lhs, right: rhs })), None))
+                                Ok((AST::binary(op, lhs, rhs), None))
                             }
                         }
                     }
@@ -620,7 +714,8 @@ impl<'a> AST<'a> {
             },
             _ => {
                 if expect_assignment {
-                    Err(Error::new(format!("Invalid token {:?} when expecting assignment operator", next_token)))
+                    let message = format!("Invalid token {:?} when expecting assignment operator", next_token);
+                    Err(Error::new(message, lex.peek_span()))
                 } else {
                     Ok((lhs, None))
                 }
@@ -628,37 +723,42 @@ impl<'a> AST<'a> {
         }
     }
 
-    fn read_btree_expression(lex: &mut Peekable<Lexer<'a>>, first_token: Option<Token<'a>>) -> Result<Expr<'a>, Error> {
+    fn read_btree_expression(
+        lex: &mut Tokens<'a>,
+        first_token: Option<(Token<'a>, Span)>,
+    ) -> Result<Expr<'a>, Error> {
         // Get first token and match it
-        let mut lhs = match if first_token.is_some() { first_token } else { lex.next() } {
-            Some(Token::Separator(ref sep)) if *sep == Separator::ParenLeft => {
+        let first = if first_token.is_some() { first_token } else { lex.next().map(|t| (t, lex.last)) };
+        let mut lhs = match first {
+            Some((Token::Separator(ref sep), start)) if *sep == Separator::ParenLeft => {
                 let binary_tree = AST::read_binary_tree(lex, None, false)?;
                 if lex.next() != Some(Token::Separator(Separator::ParenRight)) {
-                    return Err(Error::new("Unclosed parenthesis in binary tree".to_string()))
+                    return Err(lex.error("Unclosed parenthesis in binary tree".to_string()))
                 } else {
-                    binary_tree
+                    lex.node(start, binary_tree.kind)
                 }
             },
-            Some(Token::Operator(op)) => {
+            Some((Token::Operator(op), start)) => {
                 if op == Operator::Add || op == Operator::Subtract || op == Operator::Not || op == Operator::Complement
                 {
-                    Expr::Unary(Box::new(UnaryExpr { op, child: AST::read_btree_expression(lex, None)? }))
+                    let child = AST::read_btree_expression(lex, None)?;
+                    lex.node(start, ExprKind::Unary(Box::new(UnaryExpr { op, child })))
                 } else {
-                    return Err(Error::new(format!("Invalid unary operator {:?} in expression", op)))
+                    return Err(lex.error(format!("Invalid unary operator {:?} in expression", op)))
                 }
             },
-            Some(Token::Identifier(t)) => {
+            Some((Token::Identifier(t), start)) => {
                 if lex.peek() == Some(&Token::Separator(Separator::ParenLeft)) {
-                    AST::read_function_call(lex, t)?
+                    AST::read_function_call(lex, t, start)?
                 } else {
-                    Expr::LiteralIdentifier(t)
+                    lex.node(start, ExprKind::LiteralIdentifier(t))
                 }
             },
 
-            Some(Token::Real(t)) => Expr::LiteralReal(t),
-            Some(Token::String(t)) => Expr::LiteralString(t),
-            Some(t) => return Err(Error::new(format!("Invalid token while scanning binary tree: {:?}", t))),
-            None => return Err(Error::new("Found EOF unexpectedly while reading binary tree".to_string())),
+            Some((Token::Real(t), start)) => lex.node(start, ExprKind::LiteralReal(t)),
+            Some((Token::String(t), start)) => lex.node(start, ExprKind::LiteralString(t)),
+            Some((t, _)) => return Err(lex.error(format!("Invalid token while scanning binary tree: {:?}", t))),
+            None => return Err(lex.error("Found EOF unexpectedly while reading binary tree".to_string())),
         };
 
         // Do we need to amend this LHS at all?
@@ -666,6 +766,7 @@ impl<'a> AST<'a> {
             match lex.peek() {
                 Some(Token::Separator(ref sep)) if *sep == Separator::BracketLeft => {
                     lex.next();
+                    let bracket = lex.last;
                     let mut dimensions = Vec::new();
                     if lex.peek() == Some(&Token::Separator(Separator::BracketRight)) {
                         lex.next();
@@ -682,33 +783,29 @@ impl<'a> AST<'a> {
                                     }
                                 },
                                 Some(t) => {
-                                    return Err(Error::new(format!("Invalid token {:?}, expected expression", t)))
+                                    return Err(lex.error(format!("Invalid token {:?}, expected expression", t)))
                                 },
                                 None => {
-                                    return Err(Error::new(
-                                        "Found EOF unexpectedly while reading array accessor".to_string(),
-                                    ))
+                                    return Err(
+                                        lex.error("Found EOF unexpectedly while reading array accessor".to_string())
+                                    )
                                 },
                             }
                         }
                     }
-                    lhs = Expr::Binary(Box::new(BinaryExpr {
-                        op: Operator::Index,
-                        left: lhs,
-                        right: Expr::Group(dimensions),
-                    }));
+                    let dimensions = lex.node(bracket, ExprKind::Group(dimensions));
+                    lhs = AST::binary(Operator::Index, lhs, dimensions);
                 },
 
                 Some(Token::Separator(ref sep)) if *sep == Separator::Period => {
                     lex.next();
                     lhs = match lex.next() {
-                        Some(Token::Identifier(id)) => Expr::Binary(Box::new(BinaryExpr {
-                            op: Operator::Deref,
-                            left: lhs,
-                            right: Expr::LiteralIdentifier(id),
-                        })),
-                        Some(t) => return Err(Error::new(format!("Unexpected token {:?} following deref", t))),
-                        None => return Err(Error::new("Found EOF unexpectedly while reading binary tree".to_string())),
+                        Some(Token::Identifier(id)) => {
+                            let field = lex.node(lex.last, ExprKind::LiteralIdentifier(id));
+                            AST::binary(Operator::Deref, lhs, field)
+                        },
+                        Some(t) => return Err(lex.error(format!("Unexpected token {:?} following deref", t))),
+                        None => return Err(lex.error("Found EOF unexpectedly while reading binary tree".to_string())),
                     }
                 },
                 _ => break,
@@ -718,8 +815,8 @@ impl<'a> AST<'a> {
         Ok(lhs)
     }
 
-    fn read_function_call(lex: &mut Peekable<Lexer<'a>>, function_name: &'a [u8]) -> Result<Expr<'a>, Error> {
-        expect_token!(lex.next(), Separator(Separator::ParenLeft));
+    fn read_function_call(lex: &mut Tokens<'a>, function_name: &'a [u8], start: Span) -> Result<Expr<'a>, Error> {
+        expect_token!(lex, Separator(Separator::ParenLeft));
 
         let mut params = Vec::new();
         if lex.peek() == Some(&Token::Separator(Separator::ParenRight)) {
@@ -736,12 +833,18 @@ impl<'a> AST<'a> {
                             break
                         }
                     },
-                    Some(t) => return Err(Error::new(format!("Invalid token {:?}, expected expression", t))),
-                    None => return Err(Error::new("Found EOF unexpectedly while reading function call".to_string())),
+                    Some(t) => return Err(lex.error(format!("Invalid token {:?}, expected expression", t))),
+                    None => return Err(lex.error("Found EOF unexpectedly while reading function call".to_string())),
                 }
             }
         }
-        Ok(Expr::Function(Box::new(FunctionExpr { name: function_name, params })))
+        Ok(lex.node(start, ExprKind::Function(Box::new(FunctionExpr { name: function_name, params }))))
+    }
+
+    /// Puts two expressions together with an operator, spanning from the start of one to the end of the other.
+    fn binary(op: Operator, left: Expr<'a>, right: Expr<'a>) -> Expr<'a> {
+        let span = left.span.to(right.span);
+        Expr { kind: ExprKind::Binary(Box::new(BinaryExpr { op, left, right })), span }
     }
 
     fn get_op_precedence(op: &Operator) -> Option<u8> {
@@ -809,11 +912,11 @@ mod tests {
         assert_ast(
             // Simple assignment - Assign
             "a = 1",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::Assign,
-                left: Expr::LiteralIdentifier(b"a"),
-                right: Expr::LiteralReal(1.0),
-            }))]),
+                left: ExprKind::LiteralIdentifier(b"a").into(),
+                right: ExprKind::LiteralReal(1.0).into(),
+            })).into()]),
         )
     }
 
@@ -822,11 +925,11 @@ mod tests {
         assert_ast(
             // Simple assignment - AssignAdd
             "b += 2",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::AssignAdd,
-                left: Expr::LiteralIdentifier(b"b"),
-                right: Expr::LiteralReal(2.0),
-            }))]),
+                left: ExprKind::LiteralIdentifier(b"b").into(),
+                right: ExprKind::LiteralReal(2.0).into(),
+            })).into()]),
         )
     }
 
@@ -835,11 +938,11 @@ mod tests {
         assert_ast(
             // Simple assignment - AssignSubtract
             "c -= 3",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::AssignSubtract,
-                left: Expr::LiteralIdentifier(b"c"),
-                right: Expr::LiteralReal(3.0),
-            }))]),
+                left: ExprKind::LiteralIdentifier(b"c").into(),
+                right: ExprKind::LiteralReal(3.0).into(),
+            })).into()]),
         )
     }
 
@@ -848,11 +951,11 @@ mod tests {
         assert_ast(
             // Simple assignment - AssignMultiply
             "d *= 4",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::AssignMultiply,
-                left: Expr::LiteralIdentifier(b"d"),
-                right: Expr::LiteralReal(4.0),
-            }))]),
+                left: ExprKind::LiteralIdentifier(b"d").into(),
+                right: ExprKind::LiteralReal(4.0).into(),
+            })).into()]),
         )
     }
 
@@ -861,11 +964,11 @@ mod tests {
         assert_ast(
             // Simple assignment - AssignDivide
             "e /= 5",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::AssignDivide,
-                left: Expr::LiteralIdentifier(b"e"),
-                right: Expr::LiteralReal(5.0),
-            }))]),
+                left: ExprKind::LiteralIdentifier(b"e").into(),
+                right: ExprKind::LiteralReal(5.0).into(),
+            })).into()]),
         )
     }
 
@@ -874,11 +977,11 @@ mod tests {
         assert_ast(
             // Simple assignment - AssignBinaryAnd
             "f &= 6",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::AssignBitwiseAnd,
-                left: Expr::LiteralIdentifier(b"f"),
-                right: Expr::LiteralReal(6.0),
-            }))]),
+                left: ExprKind::LiteralIdentifier(b"f").into(),
+                right: ExprKind::LiteralReal(6.0).into(),
+            })).into()]),
         )
     }
 
@@ -887,11 +990,11 @@ mod tests {
         assert_ast(
             // Simple assignment - AssignBinaryOr
             "g |= 7",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::AssignBitwiseOr,
-                left: Expr::LiteralIdentifier(b"g"),
-                right: Expr::LiteralReal(7.0),
-            }))]),
+                left: ExprKind::LiteralIdentifier(b"g").into(),
+                right: ExprKind::LiteralReal(7.0).into(),
+            })).into()]),
         )
     }
 
@@ -900,11 +1003,11 @@ mod tests {
         assert_ast(
             // Simple assignment - AssignBinaryXor
             "h ^= 8",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::AssignBitwiseXor,
-                left: Expr::LiteralIdentifier(b"h"),
-                right: Expr::LiteralReal(8.0),
-            }))]),
+                left: ExprKind::LiteralIdentifier(b"h").into(),
+                right: ExprKind::LiteralReal(8.0).into(),
+            })).into()]),
         )
     }
 
@@ -935,19 +1038,19 @@ mod tests {
         assert_ast(
             // Assignment with deref and index on lhs
             "a.b[c] += d;",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::AssignAdd,
-                left: Expr::Binary(Box::new(BinaryExpr {
+                left: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Index,
-                    left: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Deref,
-                        left: Expr::LiteralIdentifier(b"a"),
-                        right: Expr::LiteralIdentifier(b"b"),
-                    })),
-                    right: Expr::Group(vec![Expr::LiteralIdentifier(b"c")]),
-                })),
-                right: Expr::LiteralIdentifier(b"d"),
-            }))]),
+                        left: ExprKind::LiteralIdentifier(b"a").into(),
+                        right: ExprKind::LiteralIdentifier(b"b").into(),
+                    })).into(),
+                    right: ExprKind::Group(vec![ExprKind::LiteralIdentifier(b"c").into()]).into(),
+                })).into(),
+                right: ExprKind::LiteralIdentifier(b"d").into(),
+            })).into()]),
         );
     }
 
@@ -956,39 +1059,45 @@ mod tests {
         assert_ast(
             // Arbitrary chains of deref, 1- and 2-dimension index ops on both lhs and rhs
             "a.b[c].d.e[f,g]=h[i,j].k",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::Assign,
-                left: Expr::Binary(Box::new(BinaryExpr {
+                left: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Index,
-                    left: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Deref,
-                        left: Expr::Binary(Box::new(BinaryExpr {
+                        left: ExprKind::Binary(Box::new(BinaryExpr {
                             op: Operator::Deref,
-                            left: Expr::Binary(Box::new(BinaryExpr {
+                            left: ExprKind::Binary(Box::new(BinaryExpr {
                                 op: Operator::Index,
-                                left: Expr::Binary(Box::new(BinaryExpr {
+                                left: ExprKind::Binary(Box::new(BinaryExpr {
                                     op: Operator::Deref,
-                                    left: Expr::LiteralIdentifier(b"a"),
-                                    right: Expr::LiteralIdentifier(b"b"),
-                                })),
-                                right: Expr::Group(vec![Expr::LiteralIdentifier(b"c")]),
-                            })),
-                            right: Expr::LiteralIdentifier(b"d"),
-                        })),
-                        right: Expr::LiteralIdentifier(b"e"),
-                    })),
-                    right: Expr::Group(vec![Expr::LiteralIdentifier(b"f"), Expr::LiteralIdentifier(b"g")]),
-                })),
-                right: Expr::Binary(Box::new(BinaryExpr {
+                                    left: ExprKind::LiteralIdentifier(b"a").into(),
+                                    right: ExprKind::LiteralIdentifier(b"b").into(),
+                                })).into(),
+                                right: ExprKind::Group(vec![ExprKind::LiteralIdentifier(b"c").into()]).into(),
+                            })).into(),
+                            right: ExprKind::LiteralIdentifier(b"d").into(),
+                        })).into(),
+                        right: ExprKind::LiteralIdentifier(b"e").into(),
+                    })).into(),
+                    right: ExprKind::Group(vec![
+                        ExprKind::LiteralIdentifier(b"f").into(),
+                        ExprKind::LiteralIdentifier(b"g").into(),
+                    ]).into(),
+                })).into(),
+                right: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Deref,
-                    left: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Index,
-                        left: Expr::LiteralIdentifier(b"h"),
-                        right: Expr::Group(vec![Expr::LiteralIdentifier(b"i"), Expr::LiteralIdentifier(b"j")]),
-                    })),
-                    right: Expr::LiteralIdentifier(b"k"),
-                })),
-            }))]),
+                        left: ExprKind::LiteralIdentifier(b"h").into(),
+                        right: ExprKind::Group(vec![
+                            ExprKind::LiteralIdentifier(b"i").into(),
+                            ExprKind::LiteralIdentifier(b"j").into(),
+                        ]).into(),
+                    })).into(),
+                    right: ExprKind::LiteralIdentifier(b"k").into(),
+                })).into(),
+            })).into()]),
         );
     }
 
@@ -997,19 +1106,19 @@ mod tests {
         assert_ast(
             // Assignment whose LHS is an expression-deref
             "(a + 1).x = 400;",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::Assign,
-                left: Expr::Binary(Box::new(BinaryExpr {
+                left: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Deref,
-                    left: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Add,
-                        left: Expr::LiteralIdentifier(b"a"),
-                        right: Expr::LiteralReal(1.0),
-                    })),
-                    right: Expr::LiteralIdentifier(b"x"),
-                })),
-                right: Expr::LiteralReal(400.0),
-            }))]),
+                        left: ExprKind::LiteralIdentifier(b"a").into(),
+                        right: ExprKind::LiteralReal(1.0).into(),
+                    })).into(),
+                    right: ExprKind::LiteralIdentifier(b"x").into(),
+                })).into(),
+                right: ExprKind::LiteralReal(400.0).into(),
+            })).into()]),
         );
     }
 
@@ -1018,15 +1127,15 @@ mod tests {
         assert_ast(
             // Differentiation between usages of '=' - simple
             "a=b=c",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::Assign,
-                left: Expr::LiteralIdentifier(b"a"),
-                right: Expr::Binary(Box::new(BinaryExpr {
+                left: ExprKind::LiteralIdentifier(b"a").into(),
+                right: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Equal,
-                    left: Expr::LiteralIdentifier(b"b"),
-                    right: Expr::LiteralIdentifier(b"c"),
-                })),
-            }))]),
+                    left: ExprKind::LiteralIdentifier(b"b").into(),
+                    right: ExprKind::LiteralIdentifier(b"c").into(),
+                })).into(),
+            })).into()]),
         );
     }
 
@@ -1035,39 +1144,39 @@ mod tests {
         assert_ast(
             // Differentiation between usages of '=' - complex
             "(a=b).c[d=e]=f[g=h]=i",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::Assign,
-                left: Expr::Binary(Box::new(BinaryExpr {
+                left: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Index,
-                    left: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Deref,
-                        left: Expr::Binary(Box::new(BinaryExpr {
+                        left: ExprKind::Binary(Box::new(BinaryExpr {
                             op: Operator::Equal,
-                            left: Expr::LiteralIdentifier(b"a"),
-                            right: Expr::LiteralIdentifier(b"b"),
-                        })),
-                        right: Expr::LiteralIdentifier(b"c"),
-                    })),
-                    right: Expr::Group(vec![Expr::Binary(Box::new(BinaryExpr {
+                            left: ExprKind::LiteralIdentifier(b"a").into(),
+                            right: ExprKind::LiteralIdentifier(b"b").into(),
+                        })).into(),
+                        right: ExprKind::LiteralIdentifier(b"c").into(),
+                    })).into(),
+                    right: ExprKind::Group(vec![ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Equal,
-                        left: Expr::LiteralIdentifier(b"d"),
-                        right: Expr::LiteralIdentifier(b"e"),
-                    }))]),
-                })),
-                right: Expr::Binary(Box::new(BinaryExpr {
+                        left: ExprKind::LiteralIdentifier(b"d").into(),
+                        right: ExprKind::LiteralIdentifier(b"e").into(),
+                    })).into()]).into(),
+                })).into(),
+                right: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Equal,
-                    left: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Index,
-                        left: Expr::LiteralIdentifier(b"f"),
-                        right: Expr::Group(vec![Expr::Binary(Box::new(BinaryExpr {
+                        left: ExprKind::LiteralIdentifier(b"f").into(),
+                        right: ExprKind::Group(vec![ExprKind::Binary(Box::new(BinaryExpr {
                             op: Operator::Equal,
-                            left: Expr::LiteralIdentifier(b"g"),
-                            right: Expr::LiteralIdentifier(b"h"),
-                        }))]),
-                    })),
-                    right: Expr::LiteralIdentifier(b"i"),
-                })),
-            }))]),
+                            left: ExprKind::LiteralIdentifier(b"g").into(),
+                            right: ExprKind::LiteralIdentifier(b"h").into(),
+                        })).into()]).into(),
+                    })).into(),
+                    right: ExprKind::LiteralIdentifier(b"i").into(),
+                })).into(),
+            })).into()]),
         );
     }
 
@@ -1083,11 +1192,14 @@ mod tests {
         assert_ast(
             // Binary tree format - unary operator - positive
             "a=+1",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::Assign,
-                left: Expr::LiteralIdentifier(b"a"),
-                right: Expr::Unary(Box::new(UnaryExpr { op: Operator::Add, child: Expr::LiteralReal(1.0) })),
-            }))]),
+                left: ExprKind::LiteralIdentifier(b"a").into(),
+                right: ExprKind::Unary(Box::new(UnaryExpr {
+                    op: Operator::Add,
+                    child: ExprKind::LiteralReal(1.0).into(),
+                })).into(),
+            })).into()]),
         )
     }
 
@@ -1096,11 +1208,14 @@ mod tests {
         assert_ast(
             // Binary tree format - unary operator - negative
             "a=-1",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::Assign,
-                left: Expr::LiteralIdentifier(b"a"),
-                right: Expr::Unary(Box::new(UnaryExpr { op: Operator::Subtract, child: Expr::LiteralReal(1.0) })),
-            }))]),
+                left: ExprKind::LiteralIdentifier(b"a").into(),
+                right: ExprKind::Unary(Box::new(UnaryExpr {
+                    op: Operator::Subtract,
+                    child: ExprKind::LiteralReal(1.0).into(),
+                })).into(),
+            })).into()]),
         )
     }
 
@@ -1109,11 +1224,14 @@ mod tests {
         assert_ast(
             // Binary tree format - unary operator - complement
             "a=~1",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::Assign,
-                left: Expr::LiteralIdentifier(b"a"),
-                right: Expr::Unary(Box::new(UnaryExpr { op: Operator::Complement, child: Expr::LiteralReal(1.0) })),
-            }))]),
+                left: ExprKind::LiteralIdentifier(b"a").into(),
+                right: ExprKind::Unary(Box::new(UnaryExpr {
+                    op: Operator::Complement,
+                    child: ExprKind::LiteralReal(1.0).into(),
+                })).into(),
+            })).into()]),
         )
     }
 
@@ -1122,11 +1240,14 @@ mod tests {
         assert_ast(
             // Binary tree format - unary operator - negative
             "a=!1",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::Assign,
-                left: Expr::LiteralIdentifier(b"a"),
-                right: Expr::Unary(Box::new(UnaryExpr { op: Operator::Not, child: Expr::LiteralReal(1.0) })),
-            }))]),
+                left: ExprKind::LiteralIdentifier(b"a").into(),
+                right: ExprKind::Unary(Box::new(UnaryExpr {
+                    op: Operator::Not,
+                    child: ExprKind::LiteralReal(1.0).into(),
+                })).into(),
+            })).into()]),
         )
     }
 
@@ -1135,43 +1256,46 @@ mod tests {
         assert_ast(
             // Binary tree format - unary operators - syntax parse test
             "a = 1+!~-b.c[+d]-2--3", // (- (- (+ 1 2) 3) 4)
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::Assign,
-                left: Expr::LiteralIdentifier(b"a"),
-                right: Expr::Binary(Box::new(BinaryExpr {
+                left: ExprKind::LiteralIdentifier(b"a").into(),
+                right: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Subtract,
-                    left: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Subtract,
-                        left: Expr::Binary(Box::new(BinaryExpr {
+                        left: ExprKind::Binary(Box::new(BinaryExpr {
                             op: Operator::Add,
-                            left: Expr::LiteralReal(1.0),
-                            right: Expr::Unary(Box::new(UnaryExpr {
+                            left: ExprKind::LiteralReal(1.0).into(),
+                            right: ExprKind::Unary(Box::new(UnaryExpr {
                                 op: Operator::Not,
-                                child: Expr::Unary(Box::new(UnaryExpr {
+                                child: ExprKind::Unary(Box::new(UnaryExpr {
                                     op: Operator::Complement,
-                                    child: Expr::Unary(Box::new(UnaryExpr {
+                                    child: ExprKind::Unary(Box::new(UnaryExpr {
                                         op: Operator::Subtract,
-                                        child: Expr::Binary(Box::new(BinaryExpr {
+                                        child: ExprKind::Binary(Box::new(BinaryExpr {
                                             op: Operator::Index,
-                                            left: Expr::Binary(Box::new(BinaryExpr {
+                                            left: ExprKind::Binary(Box::new(BinaryExpr {
                                                 op: Operator::Deref,
-                                                left: Expr::LiteralIdentifier(b"b"),
-                                                right: Expr::LiteralIdentifier(b"c"),
-                                            })),
-                                            right: Expr::Group(vec![Expr::Unary(Box::new(UnaryExpr {
+                                                left: ExprKind::LiteralIdentifier(b"b").into(),
+                                                right: ExprKind::LiteralIdentifier(b"c").into(),
+                                            })).into(),
+                                            right: ExprKind::Group(vec![ExprKind::Unary(Box::new(UnaryExpr {
                                                 op: Operator::Add,
-                                                child: Expr::LiteralIdentifier(b"d"),
-                                            }))]),
-                                        })),
-                                    })),
-                                })),
-                            })),
-                        })),
-                        right: Expr::LiteralReal(2.0),
-                    })),
-                    right: Expr::Unary(Box::new(UnaryExpr { op: Operator::Subtract, child: Expr::LiteralReal(3.0) })),
-                })),
-            }))]),
+                                                child: ExprKind::LiteralIdentifier(b"d").into(),
+                                            })).into()]).into(),
+                                        })).into(),
+                                    })).into(),
+                                })).into(),
+                            })).into(),
+                        })).into(),
+                        right: ExprKind::LiteralReal(2.0).into(),
+                    })).into(),
+                    right: ExprKind::Unary(Box::new(UnaryExpr {
+                        op: Operator::Subtract,
+                        child: ExprKind::LiteralReal(3.0).into(),
+                    })).into(),
+                })).into(),
+            })).into()]),
         )
     }
 
@@ -1180,18 +1304,18 @@ mod tests {
         assert_ast(
             // Unary operator applied to sub-tree
             "a = ~(b + 1)",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::Assign,
-                left: Expr::LiteralIdentifier(b"a"),
-                right: Expr::Unary(Box::new(UnaryExpr {
+                left: ExprKind::LiteralIdentifier(b"a").into(),
+                right: ExprKind::Unary(Box::new(UnaryExpr {
                     op: Operator::Complement,
-                    child: Expr::Binary(Box::new(BinaryExpr {
+                    child: ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Add,
-                        left: Expr::LiteralIdentifier(b"b"),
-                        right: Expr::LiteralReal(1.0),
-                    })),
-                })),
-            }))]),
+                        left: ExprKind::LiteralIdentifier(b"b").into(),
+                        right: ExprKind::LiteralReal(1.0).into(),
+                    })).into(),
+                })).into(),
+            })).into()]),
         )
     }
 
@@ -1200,14 +1324,20 @@ mod tests {
         assert_ast(
             // Function call syntax
             "instance_create(random(800), random(608,), apple);",
-            Some(vec![Expr::Function(Box::new(FunctionExpr {
+            Some(vec![ExprKind::Function(Box::new(FunctionExpr {
                 name: b"instance_create",
                 params: vec![
-                    Expr::Function(Box::new(FunctionExpr { name: b"random", params: vec![Expr::LiteralReal(800.0)] })),
-                    Expr::Function(Box::new(FunctionExpr { name: b"random", params: vec![Expr::LiteralReal(608.0)] })),
-                    Expr::LiteralIdentifier(b"apple"),
+                    ExprKind::Function(Box::new(FunctionExpr {
+                        name: b"random",
+                        params: vec![ExprKind::LiteralReal(800.0).into()],
+                    })).into(),
+                    ExprKind::Function(Box::new(FunctionExpr {
+                        name: b"random",
+                        params: vec![ExprKind::LiteralReal(608.0).into()],
+                    })).into(),
+                    ExprKind::LiteralIdentifier(b"apple").into(),
                 ],
-            }))]),
+            })).into()]),
         )
     }
 
@@ -1216,35 +1346,35 @@ mod tests {
         assert_ast(
             // For-loop syntax - standard
             "for(i = 0; i < 10; i += 1) { a = 1; b = c;}",
-            Some(vec![Expr::For(Box::new(ForExpr {
-                start: Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::For(Box::new(ForExpr {
+                start: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Assign,
-                    left: Expr::LiteralIdentifier(b"i"),
-                    right: Expr::LiteralReal(0.0),
-                })),
-                cond: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::LiteralIdentifier(b"i").into(),
+                    right: ExprKind::LiteralReal(0.0).into(),
+                })).into(),
+                cond: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::LessThan,
-                    left: Expr::LiteralIdentifier(b"i"),
-                    right: Expr::LiteralReal(10.0),
-                })),
-                step: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::LiteralIdentifier(b"i").into(),
+                    right: ExprKind::LiteralReal(10.0).into(),
+                })).into(),
+                step: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::AssignAdd,
-                    left: Expr::LiteralIdentifier(b"i"),
-                    right: Expr::LiteralReal(1.0),
-                })),
-                body: Expr::Group(vec![
-                    Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::LiteralIdentifier(b"i").into(),
+                    right: ExprKind::LiteralReal(1.0).into(),
+                })).into(),
+                body: ExprKind::Group(vec![
+                    ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Assign,
-                        left: Expr::LiteralIdentifier(b"a"),
-                        right: Expr::LiteralReal(1.0),
-                    })),
-                    Expr::Binary(Box::new(BinaryExpr {
+                        left: ExprKind::LiteralIdentifier(b"a").into(),
+                        right: ExprKind::LiteralReal(1.0).into(),
+                    })).into(),
+                    ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Assign,
-                        left: Expr::LiteralIdentifier(b"b"),
-                        right: Expr::LiteralIdentifier(b"c"),
-                    })),
-                ]),
-            }))]),
+                        left: ExprKind::LiteralIdentifier(b"b").into(),
+                        right: ExprKind::LiteralIdentifier(b"c").into(),
+                    })).into(),
+                ]).into(),
+            })).into()]),
         )
     }
 
@@ -1253,28 +1383,28 @@ mod tests {
         assert_ast(
             // For-loop syntax - no separators
             "for(i=0 i<10 i+=1) c=3",
-            Some(vec![Expr::For(Box::new(ForExpr {
-                start: Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::For(Box::new(ForExpr {
+                start: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Assign,
-                    left: Expr::LiteralIdentifier(b"i"),
-                    right: Expr::LiteralReal(0.0),
-                })),
-                cond: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::LiteralIdentifier(b"i").into(),
+                    right: ExprKind::LiteralReal(0.0).into(),
+                })).into(),
+                cond: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::LessThan,
-                    left: Expr::LiteralIdentifier(b"i"),
-                    right: Expr::LiteralReal(10.0),
-                })),
-                step: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::LiteralIdentifier(b"i").into(),
+                    right: ExprKind::LiteralReal(10.0).into(),
+                })).into(),
+                step: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::AssignAdd,
-                    left: Expr::LiteralIdentifier(b"i"),
-                    right: Expr::LiteralReal(1.0),
-                })),
-                body: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::LiteralIdentifier(b"i").into(),
+                    right: ExprKind::LiteralReal(1.0).into(),
+                })).into(),
+                body: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Assign,
-                    left: Expr::LiteralIdentifier(b"c"),
-                    right: Expr::LiteralReal(3.0),
-                })),
-            }))]),
+                    left: ExprKind::LiteralIdentifier(b"c").into(),
+                    right: ExprKind::LiteralReal(3.0).into(),
+                })).into(),
+            })).into()]),
         )
     }
 
@@ -1283,28 +1413,28 @@ mod tests {
         assert_ast(
             // For-loop syntax - arbitrary semicolons
             "for(i=0; i<10 i+=1; ;) {d=4}",
-            Some(vec![Expr::For(Box::new(ForExpr {
-                start: Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::For(Box::new(ForExpr {
+                start: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Assign,
-                    left: Expr::LiteralIdentifier(b"i"),
-                    right: Expr::LiteralReal(0.0),
-                })),
-                cond: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::LiteralIdentifier(b"i").into(),
+                    right: ExprKind::LiteralReal(0.0).into(),
+                })).into(),
+                cond: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::LessThan,
-                    left: Expr::LiteralIdentifier(b"i"),
-                    right: Expr::LiteralReal(10.0),
-                })),
-                step: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::LiteralIdentifier(b"i").into(),
+                    right: ExprKind::LiteralReal(10.0).into(),
+                })).into(),
+                step: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::AssignAdd,
-                    left: Expr::LiteralIdentifier(b"i"),
-                    right: Expr::LiteralReal(1.0),
-                })),
-                body: Expr::Group(vec![Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::LiteralIdentifier(b"i").into(),
+                    right: ExprKind::LiteralReal(1.0).into(),
+                })).into(),
+                body: ExprKind::Group(vec![ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Assign,
-                    left: Expr::LiteralIdentifier(b"d"),
-                    right: Expr::LiteralReal(4.0),
-                }))]),
-            }))]),
+                    left: ExprKind::LiteralIdentifier(b"d").into(),
+                    right: ExprKind::LiteralReal(4.0).into(),
+                })).into()]).into(),
+            })).into()]),
         )
     }
 
@@ -1312,11 +1442,11 @@ mod tests {
     fn pascal_init_assign() {
         assert_ast(
             "a := 1",
-            Some(vec![Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::Assign,
-                left: Expr::LiteralIdentifier(b"a"),
-                right: Expr::LiteralReal(1.0),
-            }))]),
+                left: ExprKind::LiteralIdentifier(b"a").into(),
+                right: ExprKind::LiteralReal(1.0).into(),
+            })).into()]),
         );
     }
 
@@ -1333,31 +1463,31 @@ mod tests {
                 a = 4;
             end
             ",
-            Some(vec![Expr::If(Box::new(IfExpr {
-                cond: Expr::Binary(Box::new(BinaryExpr {
+            Some(vec![ExprKind::If(Box::new(IfExpr {
+                cond: ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Equal,
-                    left: Expr::LiteralIdentifier(b"a"),
-                    right: Expr::LiteralReal(1.0),
-                })),
-                body: Expr::Group(vec![Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::LiteralIdentifier(b"a").into(),
+                    right: ExprKind::LiteralReal(1.0).into(),
+                })).into(),
+                body: ExprKind::Group(vec![ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Assign,
-                    left: Expr::LiteralIdentifier(b"a"),
-                    right: Expr::LiteralReal(2.0),
-                }))]),
-                else_body: Some(Expr::If(Box::new(IfExpr {
-                    cond: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::LiteralIdentifier(b"a").into(),
+                    right: ExprKind::LiteralReal(2.0).into(),
+                })).into()]).into(),
+                else_body: Some(ExprKind::If(Box::new(IfExpr {
+                    cond: ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Equal,
-                        left: Expr::LiteralIdentifier(b"a"),
-                        right: Expr::LiteralReal(2.0),
-                    })),
-                    body: Expr::Group(vec![Expr::Binary(Box::new(BinaryExpr {
+                        left: ExprKind::LiteralIdentifier(b"a").into(),
+                        right: ExprKind::LiteralReal(2.0).into(),
+                    })).into(),
+                    body: ExprKind::Group(vec![ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Assign,
-                        left: Expr::LiteralIdentifier(b"a"),
-                        right: Expr::LiteralReal(4.0),
-                    }))]),
+                        left: ExprKind::LiteralIdentifier(b"a").into(),
+                        right: ExprKind::LiteralReal(4.0).into(),
+                    })).into()]).into(),
                     else_body: None,
-                }))),
-            }))]),
+                })).into()),
+            })).into()]),
         );
     }
 
@@ -1367,8 +1497,8 @@ mod tests {
             // var syntax - basic constructions
             "var a; var b, c",
             Some(vec![
-                Expr::Var(Box::new(VarExpr { vars: vec![b"a"] })),
-                Expr::Var(Box::new(VarExpr { vars: vec![b"b", b"c"] })),
+                ExprKind::Var(Box::new(VarExpr { vars: vec![b"a"] })).into(),
+                ExprKind::Var(Box::new(VarExpr { vars: vec![b"b", b"c"] })).into(),
             ]),
         )
     }
@@ -1379,10 +1509,10 @@ mod tests {
             // var syntax - unusual valid constructions
             "var; var a,b,; var c,var",
             Some(vec![
-                Expr::Var(Box::new(VarExpr { vars: vec![] })),
-                Expr::Var(Box::new(VarExpr { vars: vec![b"a", b"b"] })),
-                Expr::Var(Box::new(VarExpr { vars: vec![b"c"] })),
-                Expr::Var(Box::new(VarExpr { vars: vec![] })),
+                ExprKind::Var(Box::new(VarExpr { vars: vec![] })).into(),
+                ExprKind::Var(Box::new(VarExpr { vars: vec![b"a", b"b"] })).into(),
+                ExprKind::Var(Box::new(VarExpr { vars: vec![b"c"] })).into(),
+                ExprKind::Var(Box::new(VarExpr { vars: vec![] })).into(),
             ]),
         )
     }
@@ -1400,8 +1530,8 @@ mod tests {
         assert_ast(
             "var a instance_create instance_destroy ()",
             Some(vec![
-                Expr::Var(Box::new(VarExpr { vars: vec![b"a", b"instance_create"] })),
-                Expr::Function(Box::new(FunctionExpr { name: b"instance_destroy", params: vec![] })),
+                ExprKind::Var(Box::new(VarExpr { vars: vec![b"a", b"instance_create"] })).into(),
+                ExprKind::Function(Box::new(FunctionExpr { name: b"instance_destroy", params: vec![] })).into(),
             ]),
         )
     }
@@ -1412,16 +1542,16 @@ mod tests {
         assert_ast(
             "var a b global.g = 0",
             Some(vec![
-                Expr::Var(Box::new(VarExpr { vars: vec![b"a", b"b"] })),
-                Expr::Binary(Box::new(BinaryExpr {
+                ExprKind::Var(Box::new(VarExpr { vars: vec![b"a", b"b"] })).into(),
+                ExprKind::Binary(Box::new(BinaryExpr {
                     op: Operator::Assign,
-                    left: Expr::Binary(Box::new(BinaryExpr {
+                    left: ExprKind::Binary(Box::new(BinaryExpr {
                         op: Operator::Deref,
-                        left: Expr::LiteralIdentifier(b"global"),
-                        right: Expr::LiteralIdentifier(b"g"),
-                    })),
-                    right: Expr::LiteralReal(0.0),
-                })),
+                        left: ExprKind::LiteralIdentifier(b"global").into(),
+                        right: ExprKind::LiteralIdentifier(b"g").into(),
+                    })).into(),
+                    right: ExprKind::LiteralReal(0.0).into(),
+                })).into(),
             ]),
         )
     }
@@ -1429,13 +1559,13 @@ mod tests {
     #[test]
     fn expression_literal_real() {
         // expression - single literal real
-        assert_eq!(AST::expression(b"1").unwrap(), Expr::LiteralReal(1.0));
+        assert_eq!(AST::expression(b"1").unwrap(), ExprKind::LiteralReal(1.0).into());
     }
 
     #[test]
     fn expression_literal_identifier() {
         // expression - literal identifier
-        assert_eq!(AST::expression(b"a").unwrap(), Expr::LiteralIdentifier(b"a"));
+        assert_eq!(AST::expression(b"a").unwrap(), ExprKind::LiteralIdentifier(b"a").into());
     }
 
     #[test]
@@ -1443,17 +1573,52 @@ mod tests {
         // expression - unary and binary operators
         assert_eq!(
             AST::expression(b"1 * -2").unwrap(),
-            Expr::Binary(Box::new(BinaryExpr {
+            ExprKind::Binary(Box::new(BinaryExpr {
                 op: Operator::Multiply,
-                left: Expr::LiteralReal(1.0),
-                right: Expr::Unary(Box::new(UnaryExpr { op: Operator::Subtract, child: Expr::LiteralReal(2.0) })),
-            }))
+                left: ExprKind::LiteralReal(1.0).into(),
+                right: ExprKind::Unary(Box::new(UnaryExpr {
+                    op: Operator::Subtract,
+                    child: ExprKind::LiteralReal(2.0).into()
+                }))
+                .into(),
+            })).into()
         );
     }
 
     #[test]
     fn expression_with_overrun() {
         // expression with extra code after it - extra code should be dropped
-        assert_eq!(AST::expression(b"0; a=1; game_end()").unwrap(), Expr::LiteralReal(0.0));
+        assert_eq!(AST::expression(b"0; a=1; game_end()").unwrap(), ExprKind::LiteralReal(0.0).into());
+    }
+
+    #[test]
+    fn spans() {
+        let source = b"a = 1;\nif (b) {\n    c(2, d[3]);\n}";
+        let ast = AST::new(source).unwrap();
+        let text = |span: Span| std::str::from_utf8(&source[span.start..span.end]).unwrap();
+        assert_eq!(text(ast[0].span), "a = 1");
+        assert_eq!((ast[1].span.line, ast[1].span.column), (2, 1));
+        if let ExprKind::If(if_expr) = &ast[1].kind {
+            assert_eq!(text(if_expr.cond.span), "(b)");
+            assert_eq!(text(if_expr.body.span), "{\n    c(2, d[3]);\n}");
+            if let ExprKind::Group(group) = &if_expr.body.kind {
+                assert_eq!(text(group[0].span), "c(2, d[3])");
+                assert_eq!((group[0].span.line, group[0].span.column), (3, 5));
+                if let ExprKind::Function(call) = &group[0].kind {
+                    assert_eq!(text(call.params[1].span), "d[3]");
+                }
+            }
+        } else {
+            panic!("expected an if, got {}", ast[1]);
+        }
+    }
+
+    #[test]
+    fn error_spans() {
+        let error = AST::new(b"a = 1;\n  b = (2;").unwrap_err();
+        assert_eq!((error.span.line, error.span.column), (2, 9));
+        let error = AST::new(b"if (a) {\n  b = 1;\n").unwrap_err();
+        assert_eq!((error.span.line, error.span.column), (3, 1));
+        assert!(error.to_string().ends_with("at line 3, column 1"));
     }
 }
@@ -7,12 +7,41 @@ use std::{
     str, u64,
 };
 
+/// A range of GML source code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    /// Byte offset of the first byte.
+    pub start: usize,
+
+    /// Byte offset just past the last byte.
+    pub end: usize,
+
+    /// Line that the first byte is on, starting at 1.
+    pub line: usize,
+
+    /// Column of the first byte in bytes, starting at 1.
+    pub column: usize,
+}
+
+impl Span {
+    /// Creates a span going from the start of this one to the end of another.
+    pub fn to(self, other: Span) -> Self {
+        Span { end: other.end, ..self }
+    }
+}
+
 #[derive(Clone)]
 pub struct Lexer<'a> {
     /// GML source code to return references to.
     src: &'a [u8],
 
-    line_hint: usize,
+    /// Byte offset of the token that was last returned.
+    token_start: usize,
+
+    /// Line and byte offset of its line, counted up to `counted`.
+    line: usize,
+    line_start: usize,
+    counted: usize,
 
     /// Iterator over the source code as raw bytes.
     iter: Peekable<Enumerate<Copied<slice::Iter<'a, u8>>>>,
@@ -21,28 +50,55 @@ pub struct Lexer<'a> {
 impl<'a> Lexer<'a> {
     /// Creates a new Lexer over GML source code.
     pub fn new(src: &'a [u8]) -> Self {
-        Lexer { src, line_hint: 1, iter: src.iter().copied().enumerate().peekable() }
+        Lexer {
+            src,
+            token_start: 0,
+            line: 1,
+            line_start: 0,
+            counted: 0,
+            iter: src.iter().copied().enumerate().peekable(),
+        }
     }
 
-    /// Returns the current line number in the source code.
+    /// Returns the line number of the last token returned, starting at 1.
     pub fn line(&self) -> usize {
-        self.line_hint
+        self.line
+    }
+
+    /// Returns the next token along with where it is in the source code.
+    pub fn next_spanned(&mut self) -> Option<(Token<'a>, Span)> {
+        let token = self.next()?;
+        let end = self.iter.peek().map(|&(i, _)| i).unwrap_or(self.src.len());
+        let column = self.token_start - self.line_start + 1;
+        Some((token, Span { start: self.token_start, end, line: self.line, column }))
+    }
+
+    /// Returns an empty span at the end of the source code, for pointing out that something's missing.
+    pub fn end_span(&mut self) -> Span {
+        let end = self.src.len();
+        self.count_lines(end);
+        Span { start: end, end, line: self.line, column: end - self.line_start + 1 }
+    }
+
+    /// Counts lines up to the given byte offset. Tokens always come later than the last one, so this
+    /// only ever has to look at each byte once.
+    fn count_lines(&mut self, offset: usize) {
+        for (i, &ch) in self.src[self.counted..offset].iter().enumerate() {
+            if ch == b'\n' {
+                self.line += 1;
+                self.line_start = self.counted + i + 1;
+            }
+        }
+        self.counted = offset;
     }
 
     /// Fast-forwards the internal iterator to the next token, skipping over whitespace.
-    /// Returns how many lines (LF) were skipped in the process.
-    fn fast_forward(&mut self) -> usize {
-        let mut lines_skipped: usize = 0;
-        loop {
-            match self.iter.peek() {
-                Some(&(_, ch)) if ch <= b' ' => {
-                    if ch == b'\n' {
-                        lines_skipped += 1;
-                    }
-                    self.iter.next();
-                },
-                _ => break lines_skipped,
+    fn fast_forward(&mut self) {
+        while let Some(&(_, ch)) = self.iter.peek() {
+            if ch > b' ' {
+                break
             }
+            self.iter.next();
         }
     }
 }
@@ -52,7 +108,7 @@ impl<'a> Iterator for Lexer<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         // locate next token
-        self.line_hint = self.fast_forward();
+        self.fast_forward();
 
         /// Helper function to reconstruct our byte slices to a string easily.
         /// This is fine since we operate on something that is a &str in a first place,
@@ -63,6 +119,8 @@ impl<'a> Iterator for Lexer<'a> {
         }
 
         let head = *self.iter.peek()?;
+        self.token_start = head.0;
+        self.count_lines(head.0);
 
         #[allow(clippy::match_overlapping_arm)] // quotes overlap with the catch-all ASCII
         Some(match head.1 {
@@ -32,6 +32,18 @@ pub enum Message {
         mouse_buttons_requested: Vec<input::MouseButton>,
         instance_requested: Option<ID>,
     },
+
+    /// Replaces all of the debugger's breakpoints with these ones
+    SetBreakpoints { breakpoints: Vec<Breakpoint> },
+
+    /// Tells the debugger to pause the game at the next line of GML it runs
+    DebugPause,
+
+    /// Tells a paused game to carry on until it gets to a breakpoint
+    DebugContinue,
+
+    /// Tells a paused game to run until the next line, then pause again and send us where it is
+    DebugStep { kind: StepKind },
 }
 
 /// A message sent from the client to the controller.
@@ -64,6 +76,39 @@ pub enum Information {
 
     /// Sends the controller some general info which should be shown to the user
     General { message: String },
+
+    /// Tells the debugger that the game has paused, and what it was in the middle of.
+    /// Variables are (name, value) pairs, with strings in quotes and array elements named like a[2] or a[1,2].
+    Paused {
+        location: String,
+        call_stack: Vec<String>,
+        locals: Vec<(String, String)>,
+        instance: Vec<(String, String)>,
+        globals: Vec<(String, String)>,
+    },
+}
+
+/// Somewhere for the debugger to pause the game.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Breakpoint {
+    /// A line in a script, starting at 1
+    Line { script: String, line: usize },
+
+    /// The start of one of an object's events, such as (obj_player, 3, 0) for its Step Event
+    Event { object: String, event_type: usize, event_number: usize },
+}
+
+/// How far a paused game should run before pausing again.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StepKind {
+    /// To the very next line, even if that's inside a script being called
+    In,
+
+    /// To the next line of the same code, or whatever runs after it if it finishes
+    Over,
+
+    /// To the next line of whatever ran the current code
+    Out,
 }
 
 /// The details of an instance sent to the control panel for display.